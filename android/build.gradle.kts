@@ -0,0 +1,25 @@
+// Android library (AAR) wrapping the Rust engine. The native libraries are
+// built separately with cargo-ndk into src/main/jniLibs (see README.md).
+plugins {
+    id("com.android.library") version "8.5.0"
+    id("org.jetbrains.kotlin.android") version "1.9.24"
+}
+
+android {
+    namespace = "com.audiop2p"
+    compileSdk = 34
+
+    defaultConfig {
+        // AAudio needs 26; Oboe falls back to OpenSL ES below that.
+        minSdk = 26
+    }
+
+    compileOptions {
+        sourceCompatibility = JavaVersion.VERSION_17
+        targetCompatibility = JavaVersion.VERSION_17
+    }
+
+    kotlinOptions {
+        jvmTarget = "17"
+    }
+}
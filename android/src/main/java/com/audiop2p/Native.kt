@@ -0,0 +1,17 @@
+package com.audiop2p
+
+import android.content.Context
+
+/** JNI entry points implemented in `src/android.rs`. */
+internal object Native {
+    init {
+        System.loadLibrary("audio")
+    }
+
+    @JvmStatic external fun init(context: Context)
+
+    /** Returns a session handle; throws IllegalStateException on failure. */
+    @JvmStatic external fun start(localPort: Int, peer: String?): Long
+
+    @JvmStatic external fun stop(handle: Long)
+}
@@ -0,0 +1,98 @@
+package com.audiop2p
+
+import android.Manifest
+import android.app.Activity
+import android.content.Context
+import android.content.pm.PackageManager
+import android.media.AudioAttributes
+import android.media.AudioFocusRequest
+import android.media.AudioManager
+
+/**
+ * Runs a voice call through the Rust engine.
+ *
+ * Handles the parts that need the Android framework: the RECORD_AUDIO runtime
+ * permission, audio focus and switching the device into communication mode so
+ * the platform picks the voice-call audio path (earpiece/headset, hardware AEC).
+ */
+class VoiceChat(private val activity: Activity) {
+    private val audioManager = activity.getSystemService(Context.AUDIO_SERVICE) as AudioManager
+    private var handle = 0L
+    private var focusRequest: AudioFocusRequest? = null
+    private var pending: Pair<Int, String?>? = null
+
+    init {
+        Native.init(activity.applicationContext)
+    }
+
+    val isRunning: Boolean
+        get() = handle != 0L
+
+    /**
+     * Starts the call, asking for the microphone permission first if needed.
+     * When a prompt is shown, forward the activity's
+     * `onRequestPermissionsResult` to [onRequestPermissionsResult].
+     */
+    fun start(localPort: Int = 40000, peer: String? = null) {
+        if (activity.checkSelfPermission(Manifest.permission.RECORD_AUDIO)
+            != PackageManager.PERMISSION_GRANTED
+        ) {
+            pending = localPort to peer
+            activity.requestPermissions(arrayOf(Manifest.permission.RECORD_AUDIO), REQUEST_MIC)
+            return
+        }
+        if (isRunning) return
+
+        val attrs = AudioAttributes.Builder()
+            .setUsage(AudioAttributes.USAGE_VOICE_COMMUNICATION)
+            .setContentType(AudioAttributes.CONTENT_TYPE_SPEECH)
+            .build()
+        val request = AudioFocusRequest.Builder(AudioManager.AUDIOFOCUS_GAIN_TRANSIENT)
+            .setAudioAttributes(attrs)
+            .setOnAudioFocusChangeListener { change ->
+                // Another app took over (e.g. an incoming phone call).
+                if (change == AudioManager.AUDIOFOCUS_LOSS) stop()
+            }
+            .build()
+        if (audioManager.requestAudioFocus(request) != AudioManager.AUDIOFOCUS_REQUEST_GRANTED) {
+            throw IllegalStateException("audio focus denied")
+        }
+        focusRequest = request
+        audioManager.mode = AudioManager.MODE_IN_COMMUNICATION
+
+        try {
+            handle = Native.start(localPort, peer)
+        } catch (e: IllegalStateException) {
+            releaseAudio()
+            throw e
+        }
+    }
+
+    fun stop() {
+        if (handle != 0L) {
+            Native.stop(handle)
+            handle = 0L
+        }
+        releaseAudio()
+    }
+
+    /** Returns false if the user denied the microphone permission. */
+    fun onRequestPermissionsResult(requestCode: Int, grantResults: IntArray): Boolean {
+        if (requestCode != REQUEST_MIC) return true
+        val args = pending ?: return true
+        pending = null
+        if (grantResults.firstOrNull() != PackageManager.PERMISSION_GRANTED) return false
+        start(args.first, args.second)
+        return true
+    }
+
+    private fun releaseAudio() {
+        focusRequest?.let { audioManager.abandonAudioFocusRequest(it) }
+        focusRequest = null
+        audioManager.mode = AudioManager.MODE_NORMAL
+    }
+
+    companion object {
+        const val REQUEST_MIC = 0x7601
+    }
+}
@@ -0,0 +1,57 @@
+// ─── SIMD benchmark ────────────────────────────────────────────────────────────
+// `cargo bench --bench simd`: times each `audio::simd` kernel against its
+// scalar loop on a 20 ms stereo block at 48 kHz, the shape the capture chain
+// sees. Plain timing, so it runs on stable without extra dependencies.
+
+use audio::simd::{self, scalar};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const FRAMES: usize = 960;
+const ROUNDS: u32 = 20_000;
+
+fn time(mut f: impl FnMut()) -> Duration {
+    for _ in 0..ROUNDS / 10 {
+        f();
+    }
+    let started = Instant::now();
+    for _ in 0..ROUNDS {
+        f();
+    }
+    started.elapsed() / ROUNDS
+}
+
+fn report(name: &str, plain: Duration, vector: Duration) {
+    let speedup = plain.as_secs_f64() / vector.as_secs_f64().max(f64::EPSILON);
+    println!("{name:<12} scalar {plain:>10.2?}  simd {vector:>10.2?}  ×{speedup:.1}");
+}
+
+fn main() {
+    let ints: Vec<i16> = (0..2 * FRAMES).map(|i| (i * 331) as i16).collect();
+    let wide: Vec<i32> = (0..2 * FRAMES)
+        .map(|i| (i as i32).wrapping_mul(2_654_435))
+        .collect();
+    let stereo: Vec<f32> = ints.iter().map(|&s| s as f32 / 32768.0).collect();
+    let mut out = vec![0f32; 2 * FRAMES];
+
+    report(
+        "i16 → f32",
+        time(|| scalar::i16_to_f32(black_box(&ints), &mut out)),
+        time(|| simd::i16_to_f32(black_box(&ints), &mut out)),
+    );
+    report(
+        "i32 → f32",
+        time(|| scalar::i32_to_f32(black_box(&wide), &mut out)),
+        time(|| simd::i32_to_f32(black_box(&wide), &mut out)),
+    );
+    report(
+        "downmix",
+        time(|| scalar::downmix(black_box(&stereo), 2, &mut out[..FRAMES])),
+        time(|| simd::downmix(black_box(&stereo), 2, &mut out[..FRAMES])),
+    );
+    report(
+        "mix",
+        time(|| scalar::mix(&mut out, black_box(&stereo), 0.5)),
+        time(|| simd::mix(&mut out, black_box(&stereo), 0.5)),
+    );
+}
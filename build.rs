@@ -0,0 +1,60 @@
+// Generates the service code of the `grpc` feature (see src/grpc.rs) from a
+// description of it rather than from proto/control.proto, so the build needs
+// no protoc. Keep the three in step.
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "grpc")]
+    grpc();
+}
+
+#[cfg(feature = "grpc")]
+fn grpc() {
+    use tonic_build::manual::{Builder, Method, MethodBuilder, Service};
+
+    // `SetMuted` is `set_muted` in Rust.
+    fn method(route: &str, input: &str, output: &str) -> MethodBuilder {
+        let mut name = String::new();
+        for c in route.chars() {
+            if c.is_ascii_uppercase() && !name.is_empty() {
+                name.push('_');
+            }
+            name.push(c.to_ascii_lowercase());
+        }
+        Method::builder()
+            .name(name)
+            .route_name(route)
+            .input_type(format!("super::{input}"))
+            .output_type(format!("super::{output}"))
+            .codec_path("tonic::codec::ProstCodec")
+    }
+
+    let unary = [
+        ("SetMuted", "SetMutedRequest", "Empty"),
+        ("Hold", "Empty", "Empty"),
+        ("Resume", "Empty", "Empty"),
+        ("SetPeerVolume", "PeerVolumeRequest", "Empty"),
+        ("SetPeerMuted", "PeerMutedRequest", "Empty"),
+        ("SetOutputGain", "OutputGainRequest", "Empty"),
+        ("SetVad", "VadRequest", "Empty"),
+        ("GetStats", "Empty", "Stats"),
+        ("GetHealth", "Empty", "Health"),
+    ];
+    let streaming = [
+        ("WatchRoster", "Empty", "Roster"),
+        ("WatchHealth", "Empty", "Health"),
+        ("WatchStats", "WatchStatsRequest", "Stats"),
+    ];
+    let mut service = Service::builder()
+        .name("Control")
+        .package("voicechat.control.v1");
+    for (route, input, output) in unary {
+        service = service.method(method(route, input, output).build());
+    }
+    for (route, input, output) in streaming {
+        service = service.method(method(route, input, output).server_streaming().build());
+    }
+    Builder::new()
+        .build_client(false)
+        .compile(&[service.build()]);
+}
@@ -0,0 +1,30 @@
+/* C interface to the voice chat engine (src/ffi.rs). */
+#ifndef VOICE_CHAT_H
+#define VOICE_CHAT_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct VoiceChatSession VoiceChatSession;
+
+/* Starts a call bound to `local_port`, talking to `peer` ("ip:port", or NULL
+ * to only listen). Blocks until the audio streams run. Returns NULL on
+ * failure; see voice_chat_last_error(). On iOS, configure and activate the
+ * AVAudioSession before calling this. */
+VoiceChatSession *voice_chat_start(uint16_t local_port, const char *peer);
+
+/* Ends the call and frees the handle. NULL is ignored. */
+void voice_chat_stop(VoiceChatSession *session);
+
+/* Message for the last failure on this thread, or NULL. Valid until the next
+ * failing call on the same thread. */
+const char *voice_chat_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* VOICE_CHAT_H */
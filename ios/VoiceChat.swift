@@ -0,0 +1,111 @@
+import AVFoundation
+import VoiceChatFFI
+
+public enum VoiceChatError: Error {
+    case microphoneDenied
+    case engine(String)
+}
+
+/// Runs a voice call through the Rust engine.
+///
+/// Owns the AVAudioSession side of things: the play‑and‑record category in
+/// voice‑chat mode (which enables the platform echo canceller and routes to the
+/// receiver/headset) and interruptions such as incoming phone calls, after
+/// which the engine is restarted if iOS says we may resume.
+public final class VoiceChat {
+    private var session: OpaquePointer?
+    private var localPort: UInt16 = 40000
+    private var peer: String?
+    private var observers: [NSObjectProtocol] = []
+
+    public init() {
+        let center = NotificationCenter.default
+        let av = AVAudioSession.sharedInstance()
+        observers.append(center.addObserver(
+            forName: AVAudioSession.interruptionNotification, object: av, queue: .main
+        ) { [weak self] note in self?.handleInterruption(note) })
+        observers.append(center.addObserver(
+            forName: AVAudioSession.mediaServicesWereResetNotification, object: av, queue: .main
+        ) { [weak self] _ in
+            // Every audio object is invalid after a reset; start from scratch.
+            guard let self, self.session != nil else { return }
+            self.stopEngine()
+            try? self.startEngine()
+        })
+    }
+
+    deinit {
+        observers.forEach(NotificationCenter.default.removeObserver)
+        stop()
+    }
+
+    public var isRunning: Bool { session != nil }
+
+    /// Asks for the microphone permission (Info.plist needs
+    /// NSMicrophoneUsageDescription).
+    public static func requestPermission(_ done: @escaping (Bool) -> Void) {
+        AVAudioSession.sharedInstance().requestRecordPermission { granted in
+            DispatchQueue.main.async { done(granted) }
+        }
+    }
+
+    public func start(localPort: UInt16 = 40000, peer: String? = nil) throws {
+        guard AVAudioSession.sharedInstance().recordPermission == .granted else {
+            throw VoiceChatError.microphoneDenied
+        }
+        self.localPort = localPort
+        self.peer = peer
+        try startEngine()
+    }
+
+    public func stop() {
+        stopEngine()
+        try? AVAudioSession.sharedInstance().setActive(false, options: .notifyOthersOnDeactivation)
+    }
+
+    private func startEngine() throws {
+        guard session == nil else { return }
+        let av = AVAudioSession.sharedInstance()
+        try av.setCategory(.playAndRecord, mode: .voiceChat, options: [.allowBluetooth, .defaultToSpeaker])
+        try av.setPreferredSampleRate(48_000)
+        try av.setPreferredIOBufferDuration(0.01)
+        try av.setActive(true)
+
+        let handle: OpaquePointer?
+        if let peer {
+            handle = peer.withCString { voice_chat_start(localPort, $0) }
+        } else {
+            handle = voice_chat_start(localPort, nil)
+        }
+        guard let handle else {
+            let message = voice_chat_last_error().map { String(cString: $0) } ?? "unknown error"
+            throw VoiceChatError.engine(message)
+        }
+        session = handle
+    }
+
+    private func stopEngine() {
+        if let session {
+            voice_chat_stop(session)
+            self.session = nil
+        }
+    }
+
+    private func handleInterruption(_ note: Notification) {
+        guard let raw = note.userInfo?[AVAudioSessionInterruptionTypeKey] as? UInt,
+              let type = AVAudioSession.InterruptionType(rawValue: raw) else { return }
+        switch type {
+        case .began:
+            // The audio units are already stopped by the system.
+            stopEngine()
+        case .ended:
+            let opts = (note.userInfo?[AVAudioSessionInterruptionOptionKey] as? UInt)
+                .map(AVAudioSession.InterruptionOptions.init(rawValue:)) ?? []
+            if opts.contains(.shouldResume) {
+                try? startEngine()
+            }
+        @unknown default:
+            break
+        }
+    }
+}
@@ -0,0 +1,161 @@
+// ─── Access control ────────────────────────────────────────────────────────────
+// A relay on a public address forwards for whoever asks. Started with
+// `--acl-secret`, it only pairs callers that present a join token in their
+// Relay packets (`--relay-token`), and both ends of a pair must hold tokens for
+// the same room. Tokens name a room and a member and expire; `issue-token`
+// mints them with the same secret:
+//
+//   v1.<room>.<member>.<expires, Unix seconds>.<HMAC‑SHA256 of all that>
+//
+// room and member base64url‑encoded, the tag too. They are bearer credentials
+// and go in the clear like the pairing id, so keep them short‑lived.
+//
+// `--acl-members` limits rooms to the members a JSON file lists
+// (`{"team": ["alice", "bob"]}`); rooms it doesn't list take any member with
+// a valid token. A pairing ends within a second of either end's token
+// expiring, even mid‑call. Revoking a member (`revoke` on the relay's stdin,
+// or `Acl::revoke`) drops their pairings at once and refuses their tokens
+// until `allow` takes it back or the relay restarts.
+
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
+use parking_lot::Mutex;
+use ring::hmac;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const VERSION: &str = "v1";
+/// MACed along with the token, so the tag means nothing elsewhere.
+const CONTEXT: &[u8] = b"audio-p2p/v1 join";
+
+/// Who a valid token lets in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Grant {
+    pub room: String,
+    pub member: String,
+    /// Unix seconds.
+    pub expires: u64,
+}
+
+impl Grant {
+    pub fn expired(&self) -> bool {
+        self.expires <= now()
+    }
+}
+
+/// `Debug` shows the members and revocations but not the key.
+#[derive(Debug)]
+pub struct Acl {
+    key: hmac::Key,
+    /// Rooms limited to some members.
+    members: HashMap<String, HashSet<String>>,
+    revoked: Mutex<BTreeSet<String>>,
+    /// Bumped on every revocation, so the relay knows to look again.
+    generation: AtomicU64,
+}
+
+impl Acl {
+    pub fn new(secret: &str, members: HashMap<String, HashSet<String>>) -> Self {
+        Self {
+            key: hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()),
+            members,
+            revoked: Mutex::new(BTreeSet::new()),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    /// Reads a `--acl-members` file: room names to member lists.
+    pub fn load_members(path: &Path) -> Result<HashMap<String, HashSet<String>>> {
+        let json = std::fs::read(path).with_context(|| format!("can't read {}", path.display()))?;
+        serde_json::from_slice(&json).with_context(|| format!("can't parse {}", path.display()))
+    }
+
+    /// A token for `member` of `room`, valid for `ttl`.
+    pub fn issue(&self, room: &str, member: &str, ttl: Duration) -> String {
+        let expires = now() + ttl.as_secs();
+        let body = format!(
+            "{VERSION}.{}.{}.{expires}",
+            URL_SAFE_NO_PAD.encode(room),
+            URL_SAFE_NO_PAD.encode(member)
+        );
+        let tag = hmac::sign(&self.key, &message(&body));
+        format!("{body}.{}", URL_SAFE_NO_PAD.encode(tag.as_ref()))
+    }
+
+    /// Who `token` lets in, if it's ours, current, for a member the room
+    /// takes, and not revoked.
+    pub fn check(&self, token: &str) -> Result<Grant> {
+        let Some((body, tag)) = token.rsplit_once('.') else {
+            bail!("malformed token");
+        };
+        let tag = URL_SAFE_NO_PAD.decode(tag).context("malformed token")?;
+        if hmac::verify(&self.key, &message(body), &tag).is_err() {
+            bail!("token not issued with this secret");
+        }
+        let fields: Vec<_> = body.split('.').collect();
+        let [VERSION, room, member, expires] = fields[..] else {
+            bail!("unsupported token");
+        };
+        let text = |field: &str| -> Result<String> {
+            Ok(String::from_utf8(URL_SAFE_NO_PAD.decode(field)?)?)
+        };
+        let grant = Grant {
+            room: text(room)?,
+            member: text(member)?,
+            expires: expires.parse()?,
+        };
+        if grant.expired() {
+            bail!("token for {} expired", grant.member);
+        }
+        if let Some(members) = self.members.get(&grant.room) {
+            if !members.contains(&grant.member) {
+                bail!("{} isn't a member of {}", grant.member, grant.room);
+            }
+        }
+        if self.revoked.lock().contains(&grant.member) {
+            bail!("{} was revoked", grant.member);
+        }
+        Ok(grant)
+    }
+
+    /// Refuses `member` from now on and ends their pairings; false if they
+    /// already were.
+    pub fn revoke(&self, member: &str) -> bool {
+        let added = self.revoked.lock().insert(member.to_owned());
+        if added {
+            self.generation.fetch_add(1, Ordering::Relaxed);
+        }
+        added
+    }
+
+    /// Takes a revocation back; false if there was none.
+    pub fn allow(&self, member: &str) -> bool {
+        self.revoked.lock().remove(member)
+    }
+
+    pub fn revoked(&self) -> Vec<String> {
+        self.revoked.lock().iter().cloned().collect()
+    }
+
+    pub fn is_revoked(&self, member: &str) -> bool {
+        self.revoked.lock().contains(member)
+    }
+
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Relaxed)
+    }
+}
+
+fn message(body: &str) -> Vec<u8> {
+    [CONTEXT, b"\0", body.as_bytes()].concat()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
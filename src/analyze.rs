@@ -0,0 +1,666 @@
+// ─── Trace analysis ────────────────────────────────────────────────────────────
+// `voice-chat analyze <trace>` reads a packet capture – our own
+// `--capture-packets` pcapng, or any pcap/pcapng from tcpdump or Wireshark,
+// whatever the file is called – and reports on the call in it, one stream per
+// source and destination: the packet kinds, the sequence timeline with its
+// losses, duplicates and late arrivals, bitrate and packet sizes, and how
+// evenly the media arrived. Datagrams go through `packet::Packet::parse` like
+// a call's would; those it refuses are counted and otherwise ignored.
+//
+// Media and the control kinds share one sequence space per sender and key
+// epoch, so a gap in it is a packet lost whatever its kind. Jitter is the
+// RFC 3550 estimate, with each media packet expected one frame after the
+// last: the frame size the sender's Hello advertised, or the one its Opus
+// TOC says. Gaps of more than a few frames (DTX, mute, hold) aren't counted.
+//
+// The Opus frames are sealed, so their TOC bytes can only be read in a
+// capture that has the opened copies (`--capture-decrypted`, debug builds);
+// packet sizes still come out exactly, since sealing adds a fixed overhead.
+// For outgoing streams the times are when we sent, so their jitter is our own
+// pacing.
+
+use crate::codec::StreamParams;
+use crate::packet::{Packet, Sealed, MEDIA_OVERHEAD};
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+
+/// Media packets more than this many frames apart start a new talk spurt
+/// rather than count as jitter.
+const SPURT_FRAMES: f64 = 3.0;
+/// Gaps listed per stream; the rest are only counted.
+const MAX_GAPS: usize = 20;
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    /// Datagrams read, of any protocol.
+    pub datagrams: u64,
+    pub streams: Vec<StreamReport>,
+}
+
+/// One direction of one call leg.
+#[derive(Debug, Serialize)]
+pub struct StreamReport {
+    pub from: SocketAddr,
+    pub to: SocketAddr,
+    /// "sent" or "received", when the capture says.
+    pub direction: Option<&'static str>,
+    /// Seconds from the start of the capture.
+    pub start_secs: f64,
+    pub duration_secs: f64,
+    /// By kind, Hello and Relay included.
+    pub packets: BTreeMap<&'static str, u64>,
+    /// Datagrams `Packet::parse` refused.
+    pub unparsed: u64,
+    /// What the last Hello advertised.
+    pub frame_ms: Option<u8>,
+    pub channels: Option<u8>,
+    pub fec: Option<bool>,
+    pub epochs: usize,
+    /// Sealed packets the sequence numbers call for.
+    pub expected: u64,
+    pub lost: u64,
+    pub loss_percent: f64,
+    /// Copies of a sequence number already seen; NACKed media sent again
+    /// shows up here.
+    pub duplicates: u64,
+    /// Arrived after a later sequence number.
+    pub late: u64,
+    pub gaps: Vec<Gap>,
+    /// All gaps, including those past `gaps`.
+    pub gap_count: u64,
+    pub longest_gap: u32,
+    /// The Opus payloads alone.
+    pub opus_kbps: f64,
+    /// Whole datagrams, without IP and UDP headers.
+    pub wire_kbps: f64,
+    pub opus_bytes_min: usize,
+    pub opus_bytes_avg: f64,
+    pub opus_bytes_max: usize,
+    /// Median time between media packets.
+    pub interval_ms: f64,
+    pub jitter_ms: f64,
+    pub longest_pause_ms: f64,
+    /// Media packets whose TOC was readable.
+    pub decrypted: u64,
+    /// TOCs seen, as "CELT FB 20 ms stereo", by count.
+    pub toc: BTreeMap<String, u64>,
+}
+
+/// Sealed packets missing from a stream.
+#[derive(Clone, Debug, Serialize)]
+pub struct Gap {
+    pub epoch: u8,
+    /// The first missing sequence number.
+    pub seq: u32,
+    pub missing: u32,
+    /// When the packet before the gap was captured.
+    pub at_secs: f64,
+}
+
+/// Reads and analyses the capture at `path`.
+pub fn analyze(path: &Path) -> Result<Report> {
+    let data = std::fs::read(path).with_context(|| format!("can't read {}", path.display()))?;
+    let datagrams =
+        read_capture(&data).with_context(|| format!("can't read {}", path.display()))?;
+    let start = datagrams.first().map_or(0.0, |d| d.at);
+    let mut streams: Vec<Stream> = Vec::new();
+    let mut index: HashMap<(SocketAddr, SocketAddr), usize> = HashMap::new();
+    for d in &datagrams {
+        let i = *index.entry((d.src, d.dst)).or_insert_with(|| {
+            streams.push(Stream::new(d));
+            streams.len() - 1
+        });
+        streams[i].push(d);
+    }
+    Ok(Report {
+        datagrams: datagrams.iter().filter(|d| !d.decrypted).count() as u64,
+        streams: streams
+            .into_iter()
+            // Other traffic parses now and then, but never mostly.
+            .filter(|s| s.packets.values().sum::<u64>() > s.unparsed)
+            .map(|s| s.report(start))
+            .collect(),
+    })
+}
+
+// ─── Streams ───────────────────────────────────────────────────────────────────
+
+struct Stream {
+    src: SocketAddr,
+    dst: SocketAddr,
+    direction: Option<&'static str>,
+    first: f64,
+    last: f64,
+    packets: BTreeMap<&'static str, u64>,
+    unparsed: u64,
+    params: Option<StreamParams>,
+    /// Sequence numbers seen and when, by epoch, in arrival order.
+    seqs: BTreeMap<u8, Vec<(u32, f64)>>,
+    seen: HashSet<(u8, u32)>,
+    highest: HashMap<u8, u32>,
+    duplicates: u64,
+    late: u64,
+    /// Arrival time, Opus length and datagram length of each media packet,
+    /// first copies only.
+    media: Vec<(f64, usize, usize)>,
+    /// Frame duration of the last readable TOC, in ms.
+    toc_ms: Option<f64>,
+    decrypted: u64,
+    toc: BTreeMap<String, u64>,
+}
+
+impl Stream {
+    fn new(d: &Datagram) -> Self {
+        Self {
+            src: d.src,
+            dst: d.dst,
+            direction: d.direction,
+            first: d.at,
+            last: d.at,
+            packets: BTreeMap::new(),
+            unparsed: 0,
+            params: None,
+            seqs: BTreeMap::new(),
+            seen: HashSet::new(),
+            highest: HashMap::new(),
+            duplicates: 0,
+            late: 0,
+            media: Vec::new(),
+            toc_ms: None,
+            decrypted: 0,
+            toc: BTreeMap::new(),
+        }
+    }
+
+    fn push(&mut self, d: &Datagram) {
+        let Some(pkt) = Packet::parse(&d.payload) else {
+            if !d.decrypted {
+                self.unparsed += 1;
+            }
+            return;
+        };
+        if d.decrypted {
+            // The opened copy of the packet just before: only its TOC is new.
+            if let Packet::Sealed {
+                kind: Sealed::Media,
+                payload,
+                ..
+            } = pkt
+            {
+                if let Some(toc) = Toc::parse(payload) {
+                    self.decrypted += 1;
+                    self.toc_ms = Some(toc.frame_ms());
+                    *self.toc.entry(toc.to_string()).or_default() += 1;
+                }
+            }
+            return;
+        }
+        self.last = d.at;
+        let kind = match pkt {
+            Packet::Hello { params, .. } => {
+                self.params = Some(params);
+                "hello"
+            }
+            Packet::Relay { .. } => "relay",
+            Packet::Sealed {
+                kind, epoch, seq, ..
+            } => {
+                let first = self.seen.insert((epoch, seq));
+                if !first {
+                    self.duplicates += 1;
+                } else {
+                    let highest = self.highest.entry(epoch).or_insert(seq);
+                    if *highest > seq {
+                        self.late += 1;
+                    }
+                    *highest = seq.max(*highest);
+                    self.seqs.entry(epoch).or_default().push((seq, d.at));
+                }
+                if kind == Sealed::Media && first {
+                    let opus = d.payload.len().saturating_sub(MEDIA_OVERHEAD);
+                    self.media.push((d.at, opus, d.payload.len()));
+                }
+                sealed_name(kind)
+            }
+        };
+        *self.packets.entry(kind).or_default() += 1;
+    }
+
+    fn report(mut self, start: f64) -> StreamReport {
+        // The sequence timeline: whatever the numbers skip was lost.
+        let (mut expected, mut lost, mut gap_count, mut longest_gap) = (0u64, 0u64, 0u64, 0u32);
+        let mut gaps = Vec::new();
+        for (&epoch, seqs) in &mut self.seqs {
+            seqs.sort_by_key(|&(seq, _)| seq);
+            expected += (seqs[seqs.len() - 1].0 - seqs[0].0) as u64 + 1;
+            for pair in seqs.windows(2) {
+                let ((before, at), (after, _)) = (pair[0], pair[1]);
+                let missing = after - before - 1;
+                if missing == 0 {
+                    continue;
+                }
+                lost += missing as u64;
+                gap_count += 1;
+                longest_gap = longest_gap.max(missing);
+                if gaps.len() < MAX_GAPS {
+                    gaps.push(Gap {
+                        epoch,
+                        seq: before + 1,
+                        missing,
+                        at_secs: at - start,
+                    });
+                }
+            }
+        }
+
+        // Media timing.
+        let mut intervals: Vec<f64> = self
+            .media
+            .windows(2)
+            .map(|w| (w[1].0 - w[0].0) * 1000.0)
+            .collect();
+        let longest_pause_ms = intervals.iter().copied().fold(0.0, f64::max);
+        intervals.sort_by(f64::total_cmp);
+        let interval_ms = intervals.get(intervals.len() / 2).copied().unwrap_or(0.0);
+        let frame_ms = self
+            .params
+            .as_ref()
+            .map(|p| p.frame_ms as f64)
+            .or(self.toc_ms)
+            .unwrap_or(interval_ms);
+        let mut jitter_ms = 0.0;
+        for w in self.media.windows(2) {
+            let gap = (w[1].0 - w[0].0) * 1000.0;
+            if frame_ms > 0.0 && gap <= frame_ms * SPURT_FRAMES {
+                jitter_ms += ((gap - frame_ms).abs() - jitter_ms) / 16.0;
+            }
+        }
+
+        // Rates, over the time media flowed.
+        let span = match (self.media.first(), self.media.last()) {
+            (Some(a), Some(b)) if b.0 > a.0 => b.0 - a.0 + frame_ms / 1000.0,
+            _ => 0.0,
+        };
+        let opus: usize = self.media.iter().map(|m| m.1).sum();
+        let wire: usize = self.media.iter().map(|m| m.2).sum();
+        let kbps = |bytes: usize| match span > 0.0 {
+            true => bytes as f64 * 8.0 / span / 1000.0,
+            false => 0.0,
+        };
+
+        StreamReport {
+            from: self.src,
+            to: self.dst,
+            direction: self.direction,
+            start_secs: self.first - start,
+            duration_secs: self.last - self.first,
+            packets: self.packets,
+            unparsed: self.unparsed,
+            frame_ms: self.params.as_ref().map(|p| p.frame_ms),
+            channels: self.params.as_ref().map(|p| p.layout.channels),
+            fec: self.params.as_ref().map(|p| p.fec),
+            epochs: self.seqs.len(),
+            expected,
+            lost,
+            loss_percent: match expected {
+                0 => 0.0,
+                n => lost as f64 * 100.0 / n as f64,
+            },
+            duplicates: self.duplicates,
+            late: self.late,
+            gaps,
+            gap_count,
+            longest_gap,
+            opus_kbps: kbps(opus),
+            wire_kbps: kbps(wire),
+            opus_bytes_min: self.media.iter().map(|m| m.1).min().unwrap_or(0),
+            opus_bytes_avg: match self.media.len() {
+                0 => 0.0,
+                n => opus as f64 / n as f64,
+            },
+            opus_bytes_max: self.media.iter().map(|m| m.1).max().unwrap_or(0),
+            interval_ms,
+            jitter_ms,
+            longest_pause_ms,
+            decrypted: self.decrypted,
+            toc: self.toc,
+        }
+    }
+}
+
+fn sealed_name(kind: Sealed) -> &'static str {
+    match kind {
+        Sealed::Media => "media",
+        Sealed::Silence => "silence",
+        Sealed::Ping => "ping",
+        Sealed::Pong => "pong",
+        Sealed::Bye => "bye",
+        Sealed::Nack => "nack",
+        Sealed::Rate => "rate",
+        Sealed::Hold => "hold",
+        Sealed::Moderate => "moderate",
+        Sealed::Introduce => "introduce",
+    }
+}
+
+// ─── Opus TOC (RFC 6716 §3.1) ──────────────────────────────────────────────────
+
+struct Toc {
+    config: u8,
+    stereo: bool,
+    /// Frames in the packet.
+    frames: u8,
+}
+
+impl Toc {
+    fn parse(pkt: &[u8]) -> Option<Self> {
+        let (&toc, rest) = pkt.split_first()?;
+        let frames = match toc & 3 {
+            0 => 1,
+            1 | 2 => 2,
+            _ => rest.first()? & 0x3f,
+        };
+        Some(Self {
+            config: toc >> 3,
+            stereo: toc & 4 != 0,
+            frames,
+        })
+    }
+
+    fn mode(&self) -> &'static str {
+        match self.config {
+            0..=11 => "SILK",
+            12..=15 => "hybrid",
+            _ => "CELT",
+        }
+    }
+
+    fn bandwidth(&self) -> &'static str {
+        match self.config {
+            0..=3 => "NB",
+            4..=7 => "MB",
+            8..=11 => "WB",
+            12..=13 => "SWB",
+            14..=15 => "FB",
+            16..=19 => "NB",
+            20..=23 => "WB",
+            24..=27 => "SWB",
+            _ => "FB",
+        }
+    }
+
+    /// The whole packet's duration.
+    fn frame_ms(&self) -> f64 {
+        let one = match self.config {
+            0..=11 => [10.0, 20.0, 40.0, 60.0][self.config as usize % 4],
+            12..=15 => [10.0, 20.0][self.config as usize % 2],
+            _ => [2.5, 5.0, 10.0, 20.0][self.config as usize % 4],
+        };
+        one * self.frames as f64
+    }
+}
+
+impl std::fmt::Display for Toc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {} ms",
+            self.mode(),
+            self.bandwidth(),
+            self.frame_ms()
+        )?;
+        if self.frames > 1 {
+            write!(f, " ({} frames)", self.frames)?;
+        }
+        if self.stereo {
+            write!(f, " stereo")?;
+        }
+        Ok(())
+    }
+}
+
+// ─── Captures ──────────────────────────────────────────────────────────────────
+
+/// One UDP datagram out of a capture.
+struct Datagram {
+    /// Unix seconds.
+    at: f64,
+    src: SocketAddr,
+    dst: SocketAddr,
+    direction: Option<&'static str>,
+    /// The opened copy of the sealed packet before it (see `capture`).
+    decrypted: bool,
+    payload: Vec<u8>,
+}
+
+/// The UDP datagrams in a pcapng or pcap file, in file order.
+fn read_capture(data: &[u8]) -> Result<Vec<Datagram>> {
+    match data.get(..4) {
+        Some([0x0a, 0x0d, 0x0d, 0x0a]) => read_pcapng(data),
+        Some([0xa1, 0xb2, 0xc3, 0xd4] | [0xd4, 0xc3, 0xb2, 0xa1])
+        | Some([0xa1, 0xb2, 0x3c, 0x4d] | [0x4d, 0x3c, 0xb2, 0xa1]) => read_pcap(data),
+        _ => bail!("not a pcap or pcapng capture"),
+    }
+}
+
+/// Reads integers in a capture's byte order.
+#[derive(Clone, Copy)]
+struct Order {
+    big: bool,
+}
+
+impl Order {
+    fn u16(self, b: &[u8], at: usize) -> Option<u16> {
+        let b: [u8; 2] = b.get(at..at + 2)?.try_into().ok()?;
+        Some(if self.big {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        })
+    }
+
+    fn u32(self, b: &[u8], at: usize) -> Option<u32> {
+        let b: [u8; 4] = b.get(at..at + 4)?.try_into().ok()?;
+        Some(if self.big {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        })
+    }
+}
+
+fn read_pcapng(data: &[u8]) -> Result<Vec<Datagram>> {
+    let mut out = Vec::new();
+    let mut order = Order { big: false };
+    // Link type and ticks a second, by interface.
+    let mut interfaces: Vec<(u16, f64)> = Vec::new();
+    let mut at = 0;
+    while at + 12 <= data.len() {
+        if data[at..at + 4] == [0x0a, 0x0d, 0x0d, 0x0a] {
+            // A section header sets the byte order and starts over.
+            order.big = match data.get(at + 8..at + 12) {
+                Some([0x1a, 0x2b, 0x3c, 0x4d]) => true,
+                Some([0x4d, 0x3c, 0x2b, 0x1a]) => false,
+                _ => bail!("bad pcapng section header at byte {at}"),
+            };
+            interfaces.clear();
+        }
+        let (Some(kind), Some(len)) = (order.u32(data, at), order.u32(data, at + 4)) else {
+            break;
+        };
+        let len = len as usize;
+        if len < 12 || !len.is_multiple_of(4) || at + len > data.len() {
+            bail!("truncated pcapng block at byte {at}");
+        }
+        let body = &data[at + 8..at + len - 4];
+        match kind {
+            // Interface description.
+            1 => {
+                let link = order.u16(body, 0).unwrap_or_default();
+                let mut ticks = 1e6;
+                for (code, value) in options(order, body.get(8..).unwrap_or_default()) {
+                    // if_tsresol: a power of ten, or of two with the top bit.
+                    if let (9, &[res]) = (code, value) {
+                        ticks = match res & 0x80 {
+                            0 => 10f64.powi(res as i32),
+                            _ => 2f64.powi((res & 0x7f) as i32),
+                        };
+                    }
+                }
+                interfaces.push((link, ticks));
+            }
+            // Enhanced packet.
+            6 => {
+                let field = |i: usize| order.u32(body, i * 4).unwrap_or_default();
+                let Some(&(link, ticks)) = interfaces.get(field(0) as usize) else {
+                    bail!("pcapng packet for an undescribed interface at byte {at}");
+                };
+                let stamp = ((field(1) as u64) << 32) | field(2) as u64;
+                let caplen = field(3) as usize;
+                let Some(frame) = body.get(20..20 + caplen) else {
+                    bail!("truncated pcapng packet at byte {at}");
+                };
+                let mut direction = None;
+                let mut decrypted = false;
+                let rest = body.get((20 + caplen).next_multiple_of(4)..);
+                for (code, value) in options(order, rest.unwrap_or_default()) {
+                    match (code, value) {
+                        (1, b"decrypted") => decrypted = true,
+                        (2, flags) => {
+                            direction = match order.u32(flags, 0).map(|f| f & 3) {
+                                Some(1) => Some("received"),
+                                Some(2) => Some("sent"),
+                                _ => None,
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                if let Some((src, dst, payload)) = udp(link, frame) {
+                    out.push(Datagram {
+                        at: stamp as f64 / ticks,
+                        src,
+                        dst,
+                        direction,
+                        decrypted,
+                        payload: payload.to_vec(),
+                    });
+                }
+            }
+            _ => {}
+        }
+        at += len;
+    }
+    Ok(out)
+}
+
+/// A pcapng block's options, up to `opt_endofopt`.
+fn options(order: Order, mut body: &[u8]) -> impl Iterator<Item = (u16, &[u8])> + '_ {
+    std::iter::from_fn(move || {
+        let code = order.u16(body, 0)?;
+        let len = order.u16(body, 2)? as usize;
+        let value = body.get(4..4 + len)?;
+        if code == 0 {
+            return None;
+        }
+        body = body
+            .get((4 + len).next_multiple_of(4)..)
+            .unwrap_or_default();
+        Some((code, value))
+    })
+}
+
+fn read_pcap(data: &[u8]) -> Result<Vec<Datagram>> {
+    let order = Order {
+        big: data[0] == 0xa1,
+    };
+    let ticks = match data[2..4] == [0x3c, 0x4d] || data[..2] == [0x4d, 0x3c] {
+        true => 1e9,
+        false => 1e6,
+    };
+    let Some(link) = order.u32(data, 20) else {
+        bail!("truncated pcap header");
+    };
+    let mut out = Vec::new();
+    let mut at = 24;
+    while at + 16 <= data.len() {
+        let field = |i: usize| order.u32(data, at + i * 4).unwrap_or_default();
+        let caplen = field(2) as usize;
+        let Some(frame) = data.get(at + 16..at + 16 + caplen) else {
+            bail!("truncated pcap record at byte {at}");
+        };
+        if let Some((src, dst, payload)) = udp(link as u16, frame) {
+            out.push(Datagram {
+                at: field(0) as f64 + field(1) as f64 / ticks,
+                src,
+                dst,
+                direction: None,
+                decrypted: false,
+                payload: payload.to_vec(),
+            });
+        }
+        at += 16 + caplen;
+    }
+    Ok(out)
+}
+
+/// The UDP datagram in a captured frame, if it holds one, unfragmented.
+fn udp(link: u16, frame: &[u8]) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    let ip = match link {
+        // BSD loopback: the address family in host order.
+        0 | 108 => frame.get(4..)?,
+        // Ethernet, with at most one VLAN tag.
+        1 => match u16::from_be_bytes([*frame.get(12)?, *frame.get(13)?]) {
+            0x8100 => frame.get(18..)?,
+            _ => frame.get(14..)?,
+        },
+        // Raw IP; IPv4; IPv6.
+        101 | 228 | 229 => frame,
+        // Linux cooked capture.
+        113 => frame.get(16..)?,
+        _ => return None,
+    };
+    let (src, dst, udp) = match ip.first()? >> 4 {
+        4 => {
+            let header = (ip[0] & 0x0f) as usize * 4;
+            if header < 20 {
+                return None;
+            }
+            let fragment = u16::from_be_bytes([*ip.get(6)?, *ip.get(7)?]) & 0x3fff;
+            if *ip.get(9)? != 17 || fragment != 0 {
+                return None;
+            }
+            let addr = |at: usize| -> Option<IpAddr> {
+                let octets: [u8; 4] = ip.get(at..at + 4)?.try_into().ok()?;
+                Some(Ipv4Addr::from(octets).into())
+            };
+            (addr(12)?, addr(16)?, ip.get(header..)?)
+        }
+        6 => {
+            if *ip.get(6)? != 17 {
+                return None;
+            }
+            let addr = |at: usize| -> Option<IpAddr> {
+                let octets: [u8; 16] = ip.get(at..at + 16)?.try_into().ok()?;
+                Some(Ipv6Addr::from(octets).to_canonical())
+            };
+            (addr(8)?, addr(24)?, ip.get(40..)?)
+        }
+        _ => return None,
+    };
+    // A short snaplen can cut the UDP header itself.
+    if udp.len() < 8 {
+        return None;
+    }
+    let port = |at: usize| u16::from_be_bytes([udp[at], udp[at + 1]]);
+    let len = port(4) as usize;
+    let payload = &udp[8..len.clamp(8, udp.len())];
+    Some((
+        SocketAddr::new(src, port(0)),
+        SocketAddr::new(dst, port(2)),
+        payload,
+    ))
+}
@@ -0,0 +1,94 @@
+// ─── Android JNI glue ──────────────────────────────────────────────────────────
+// Native side of `com.audiop2p.Native` (see `android/`). The Kotlin wrapper
+// owns everything that needs an Activity: the RECORD_AUDIO permission prompt,
+// audio focus and MODE_IN_COMMUNICATION. By the time `start` is called the
+// permission has been granted, so opening the Oboe input stream succeeds.
+
+use crate::{SessionConfig, SessionThread};
+use jni::objects::{JClass, JObject, JString};
+use jni::sys::{jint, jlong};
+use jni::JNIEnv;
+use std::sync::Once;
+
+static CONTEXT_INIT: Once = Once::new();
+
+/// Hands the JavaVM and application Context to `ndk-context`, which cpal's
+/// Oboe backend uses to query the AudioManager. Safe to call repeatedly.
+#[no_mangle]
+pub extern "system" fn Java_com_audiop2p_Native_init<'local>(
+    env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    context: JObject<'local>,
+) {
+    CONTEXT_INIT.call_once(|| {
+        let vm = match env.get_java_vm() {
+            Ok(vm) => vm,
+            Err(e) => {
+                tracing::error!("JNI get_java_vm failed: {e}");
+                return;
+            }
+        };
+        let context = match env.new_global_ref(context) {
+            Ok(r) => r,
+            Err(e) => {
+                tracing::error!("JNI new_global_ref failed: {e}");
+                return;
+            }
+        };
+        // ndk-context keeps the raw pointer for the life of the process.
+        let context_ptr = context.as_obj().as_raw();
+        std::mem::forget(context);
+        unsafe {
+            ndk_context::initialize_android_context(
+                vm.get_java_vm_pointer().cast(),
+                context_ptr.cast(),
+            );
+        }
+    });
+}
+
+/// Returns an opaque handle for `stop`, or 0 after throwing
+/// `IllegalStateException` if the session could not start.
+#[no_mangle]
+pub extern "system" fn Java_com_audiop2p_Native_start<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    local_port: jint,
+    peer: JString<'local>,
+) -> jlong {
+    let peer = if peer.is_null() {
+        None
+    } else {
+        match env.get_string(&peer) {
+            Ok(s) => Some(String::from(s)),
+            Err(e) => {
+                let _ = env.throw_new("java/lang/IllegalArgumentException", e.to_string());
+                return 0;
+            }
+        }
+    };
+    let config = SessionConfig {
+        local_port: local_port as u16,
+        peer,
+        ..Default::default()
+    };
+    match SessionThread::spawn(config) {
+        Ok(session) => Box::into_raw(Box::new(session)) as jlong,
+        Err(e) => {
+            let _ = env.throw_new("java/lang/IllegalStateException", format!("{e:#}"));
+            0
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_audiop2p_Native_stop<'local>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) {
+    if handle != 0 {
+        // Dropping the SessionThread stops the streams and joins its runtime.
+        drop(unsafe { Box::from_raw(handle as *mut SessionThread) });
+    }
+}
@@ -0,0 +1,90 @@
+// ─── APM dump ──────────────────────────────────────────────────────────────────
+// For chasing echo cancellation and noise suppression problems:
+// `--dump-apm DIR` writes what the APM got from the microphone
+// (`DIR/capture-raw.wav`) and what it made of it (`DIR/capture-processed.wav`),
+// frame for frame, so the two line up sample for sample in any editor and a
+// processing artifact shows up as a difference between them. Both are 48 kHz
+// in the layout we send. Frames the APM never sees – muted, on hold, idle –
+// are left out of both.
+//
+// The capture chain only hands frames over; a thread of its own writes them.
+// If it falls behind, a frame is dropped from both files at once, so they stay
+// aligned.
+
+use crate::record::WavWriter;
+use crate::SAMPLE_RATE;
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread::JoinHandle;
+use tracing::{error, info, warn};
+
+/// Frames that may wait for the writer.
+const QUEUE: usize = 64;
+
+pub(crate) struct Dump {
+    /// Taken on drop, which ends the writer.
+    tx: Option<SyncSender<(Vec<f32>, Vec<f32>)>>,
+    writer: Option<JoinHandle<()>>,
+    dropped: AtomicU64,
+}
+
+impl Dump {
+    pub fn start(dir: &Path, channels: u16) -> Result<Self> {
+        std::fs::create_dir_all(dir).with_context(|| format!("can't create {}", dir.display()))?;
+        let raw = WavWriter::create(&dir.join("capture-raw.wav"), SAMPLE_RATE, channels)?;
+        let processed =
+            WavWriter::create(&dir.join("capture-processed.wav"), SAMPLE_RATE, channels)?;
+        let (tx, rx) = sync_channel(QUEUE);
+        let writer = std::thread::Builder::new()
+            .name("voice-apm-dump".into())
+            .spawn(move || write(rx, raw, processed))?;
+        info!("dumping the APM's input and output to {}", dir.display());
+        Ok(Self {
+            tx: Some(tx),
+            writer: Some(writer),
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    /// One frame as it went into the APM and as it came out. Never blocks.
+    pub fn push(&self, raw: &[f32], processed: &[f32]) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        if tx.try_send((raw.to_vec(), processed.to_vec())).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Drop for Dump {
+    fn drop(&mut self) {
+        self.tx = None;
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+        let dropped = self.dropped.load(Ordering::Relaxed);
+        if dropped > 0 {
+            warn!("APM dump fell behind; {dropped} frames left out of both files");
+        }
+    }
+}
+
+fn write(rx: Receiver<(Vec<f32>, Vec<f32>)>, mut raw: WavWriter, mut processed: WavWriter) {
+    for (a, b) in rx {
+        let written = raw
+            .write(a.into_iter())
+            .and_then(|()| processed.write(b.into_iter()));
+        if let Err(e) = written {
+            error!("APM dump: {e:#}");
+            break;
+        }
+    }
+    for file in [&mut raw, &mut processed] {
+        if let Err(e) = file.finish() {
+            error!("APM dump: {e:#}");
+        }
+    }
+}
@@ -0,0 +1,360 @@
+// ─── Broadcast ─────────────────────────────────────────────────────────────────
+// One sender, many listeners: each frame is encoded once and sealed separately
+// for every listener. Every listener gets its own X25519 handshake, keys and
+// SAS, so listeners are ordinary peers (`--peer <broadcaster>`) and none of them
+// can read another's copy. Anything listeners send besides Hellos and Pings is
+// ignored.
+//
+// Listeners come from a fixed address list and, with signaling, from the room's
+// subscriber list, which is polled for newcomers for as long as we broadcast.
+//
+// With `Tiers` the stream also goes out at lower bitrates (encoded by an
+// `encode_pool::Pool`). A listener starts on the main stream and is moved to
+// the best tier its bandwidth estimate (a Rate packet, see `bwe`) leaves room
+// for whenever that is less than the main stream uses, and back once the
+// estimate lifts. Silence and hold markers go to everyone.
+
+use crate::packet::{Packet, Sealed};
+use crate::{
+    capture, codec, crypto, flood, health, send_queue, signaling, socket, Outbound, HELLO_INTERVAL,
+};
+use anyhow::Result;
+use async_channel::{bounded, Receiver};
+use bytes::Bytes;
+use std::collections::hash_map::{Entry, HashMap};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::task;
+use tracing::{error, info, warn};
+
+const SUBSCRIBER_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+/// Weight of each new frame in the main stream's measured bitrate.
+const RATE_SMOOTHING: f64 = 0.05;
+
+/// Lower‑bitrate copies of the broadcast.
+pub(crate) struct Tiers {
+    /// Bits a second, by stream index.
+    pub bitrates: Vec<u32>,
+    pub frames: Receiver<(usize, Bytes)>,
+}
+
+impl Tiers {
+    /// The stream for a listener whose path carries `estimate` bits a
+    /// second (0 for no limit): `None` for the main one.
+    fn pick(&self, estimate: u32, main_bps: f64) -> Option<usize> {
+        if estimate == 0 || estimate as f64 >= main_bps {
+            return None;
+        }
+        let fits = |&(_, &bps): &(usize, &u32)| bps <= estimate;
+        let best = self
+            .bitrates
+            .iter()
+            .enumerate()
+            .filter(fits)
+            .max_by_key(|(_, &bps)| bps);
+        let lowest = self.bitrates.iter().enumerate().min_by_key(|(_, &bps)| bps);
+        best.or(lowest).map(|(i, _)| i)
+    }
+}
+
+struct Listener {
+    /// Key the signaling server relayed; Hellos must match it.
+    expected_key: Option<[u8; crypto::PUBLIC_KEY_LEN]>,
+    /// From the room passphrase, for subscribers of a room that keys media.
+    psk: Option<[u8; crypto::PSK_LEN]>,
+    /// Our Hello for this listener, carrying this listener's handshake key.
+    hello: Bytes,
+    handshake: Option<crypto::Handshake>,
+    session: Option<crypto::Session>,
+    /// The tier this listener gets; `None` for the main stream.
+    tier: Option<usize>,
+}
+
+impl Listener {
+    fn new(
+        expected_key: Option<[u8; crypto::PUBLIC_KEY_LEN]>,
+        psk: Option<[u8; crypto::PSK_LEN]>,
+        params: &codec::StreamParams,
+    ) -> Result<Self> {
+        let handshake = crypto::Handshake::new()?.with_psk(psk);
+        Ok(Self {
+            expected_key,
+            psk,
+            hello: hello(&handshake, params),
+            handshake: Some(handshake),
+            session: None,
+            tier: None,
+        })
+    }
+}
+
+fn hello(handshake: &crypto::Handshake, params: &codec::StreamParams) -> Bytes {
+    Packet::Hello {
+        pub_key: handshake.public_key(),
+        params: params.clone(),
+    }
+    .encode()
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn broadcast_task(
+    sock: UdpSocket,
+    listeners: Vec<String>,
+    signaling: Option<signaling::Signaling>,
+    rekey: crypto::RekeyPolicy,
+    params: codec::StreamParams,
+    mut outbound: send_queue::Outbox,
+    tiers: Option<Tiers>,
+    mut guard: flood::Guard,
+    health: Arc<health::Health>,
+) -> Result<()> {
+    let public_address = crate::get_public_address(&sock).await?;
+    info!("Reflexive addr {}", public_address);
+    health.set_socket(health::Status::Ok);
+
+    let mut audience = HashMap::new();
+    for addr in &listeners {
+        match tokio::net::lookup_host(addr).await?.next() {
+            Some(a) => {
+                audience.insert(a, Listener::new(None, None, &params)?);
+            }
+            None => warn!("listener {addr} did not resolve"),
+        }
+    }
+
+    // Subscribers registered through signaling, as they turn up.
+    let (sub_tx, subscribers) = bounded::<signaling::PeerInfo>(64);
+    let psk = signaling.as_ref().and_then(|s| s.media_psk());
+    if let Some(sig) = signaling {
+        let me = signaling::JoinPayload {
+            reflexive_addr: public_address.to_string(),
+            lan_addr: socket::lan_address(&sock)
+                .map(|a| a.to_string())
+                .unwrap_or_default(),
+            // Every listener gets a key of its own.
+            pub_key: String::new(),
+            relayed_addr: String::new(),
+        };
+        let health = Arc::clone(&health);
+        task::spawn(async move {
+            if let Err(e) = sig.register(&me).await {
+                error!("signaling: {e}");
+                health.set_signaling(health::Status::Failed);
+                return;
+            }
+            loop {
+                match sig.subscribers().await {
+                    Ok(list) => {
+                        health.set_signaling(health::Status::Ok);
+                        for peer in list {
+                            if sub_tx.send(peer).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        warn!("signaling: {e}");
+                        health.set_signaling(health::Status::Failed);
+                    }
+                }
+                tokio::time::sleep(SUBSCRIBER_POLL_INTERVAL).await;
+            }
+        });
+    }
+    info!("STATUS: broadcast_started listeners={}", audience.len());
+
+    let mut hello_tick = tokio::time::interval(HELLO_INTERVAL);
+    // One sealed copy per listener, reused frame after frame.
+    let mut batch = Vec::new();
+    let mut buf = [0u8; crate::MAX_SURROUND_PACKET_SIZE + crate::packet::MEDIA_OVERHEAD];
+    // What the main stream uses, measured, for choosing tiers.
+    let frames_per_sec = 1000.0 / params.frame_ms as f64;
+    let mut main_bps = 0.0;
+    loop {
+        tokio::select! {
+            msg = outbound.recv() => {
+                let Some(msg) = msg else {
+                    break;
+                };
+                let (kind, body) = match &msg {
+                    Outbound::Frame(frame) => {
+                        let bps = frame.len() as f64 * 8.0 * frames_per_sec;
+                        main_bps += RATE_SMOOTHING * (bps - main_bps);
+                        (Sealed::Media, &frame[..])
+                    }
+                    Outbound::Silence(reason) => (Sealed::Silence, &[*reason as u8][..]),
+                    Outbound::Hold(held) => (Sealed::Hold, &[*held as u8][..]),
+                };
+                // Media only to those on the main stream.
+                let everyone = kind != Sealed::Media;
+                seal_for(&mut audience, &mut batch, kind, body, |l| everyone || l.tier.is_none());
+                socket::send_batch(&sock, &batch).await;
+            }
+            Ok((tier, frame)) = async {
+                match &tiers {
+                    Some(t) => t.frames.recv().await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                seal_for(&mut audience, &mut batch, Sealed::Media, &frame, |l| l.tier == Some(tier));
+                socket::send_batch(&sock, &batch).await;
+            }
+            // Keep offering keys to listeners that haven't answered yet.
+            _ = hello_tick.tick() => {
+                for (addr, listener) in &audience {
+                    if listener.session.is_none() {
+                        capture::sent(&sock, *addr, &listener.hello);
+                        if let Err(e) = sock.send_to(&listener.hello, addr).await {
+                            error!("udp send error to {addr}: {e}");
+                        }
+                    }
+                }
+            }
+            Ok(peer) = subscribers.recv() => {
+                // Browsers have no address to send to (see `signaling`).
+                if peer.webrtc {
+                    continue;
+                }
+                let Ok(addr) = peer.reflexive_addr.parse::<SocketAddr>() else {
+                    warn!("signaling sent unusable address {}", peer.reflexive_addr);
+                    continue;
+                };
+                if let Entry::Vacant(slot) = audience.entry(addr) {
+                    info!("subscriber {addr} joined");
+                    slot.insert(Listener::new(crypto::key_from_hex(&peer.pub_key), psk, &params)?);
+                }
+            }
+            r = sock.recv_from(&mut buf) => {
+                let (n, src) = match r {
+                    Ok(r) => r,
+                    Err(e) => {
+                        error!("udp recv error: {e}");
+                        health.set_socket(health::Status::Failed);
+                        continue;
+                    }
+                };
+                health.set_socket(health::Status::Ok);
+                capture::received(&sock, src, &buf[..n]);
+                if !guard.admit(src, n) {
+                    continue;
+                }
+                let Some(listener) = audience.get_mut(&src) else {
+                    continue;
+                };
+                match Packet::parse(&buf[..n]) {
+                    Some(Packet::Hello { pub_key, .. }) => {
+                        let mut keyed = false;
+                        if listener.expected_key.is_some_and(|k| k != pub_key) {
+                            warn!("ignoring Hello from {src}: key differs from signaling");
+                            continue;
+                        }
+                        if listener.session.as_ref().is_some_and(|s| s.peer_key != pub_key) {
+                            // The listener restarted: key it afresh.
+                            info!("listener {src} rejoined");
+                            *listener = Listener::new(listener.expected_key, listener.psk, &params)?;
+                        }
+                        if let Some(hs) = listener.handshake.take() {
+                            match hs.complete(&pub_key, rekey) {
+                                Ok(s) => {
+                                    info!(
+                                        peer = %src,
+                                        sas = %s.sas,
+                                        "STATUS: listener_keyed {src} sas={}",
+                                        s.sas
+                                    );
+                                    listener.session = Some(s);
+                                    keyed = true;
+                                }
+                                Err(e) => error!("handshake with {src} failed: {e}"),
+                            }
+                        }
+                        capture::sent(&sock, src, &listener.hello);
+                        if let Err(e) = sock.send_to(&listener.hello, src).await {
+                            error!("udp send error to {src}: {e}");
+                        }
+                        if keyed {
+                            health.set_peers(audience.values().filter(|l| l.session.is_some()).count());
+                        }
+                    }
+                    Some(Packet::Sealed {
+                        kind: Sealed::Rate,
+                        epoch,
+                        seq,
+                        payload,
+                    }) => {
+                        let (Some(tiers), Some(session)) = (&tiers, listener.session.as_mut()) else {
+                            continue;
+                        };
+                        let Some(body) = session.opener.open(Sealed::Rate, epoch, seq, payload)
+                        else {
+                            continue;
+                        };
+                        let Some(&[a, b, c, d]) = body.get(..4) else {
+                            continue;
+                        };
+                        let tier = tiers.pick(u32::from_be_bytes([a, b, c, d]), main_bps);
+                        if tier != listener.tier {
+                            listener.tier = tier;
+                            match tier {
+                                Some(i) => info!(peer = %src, "listener {src} moves to the {} kbps tier", tiers.bitrates[i] / 1000),
+                                None => info!(peer = %src, "listener {src} moves back to the main stream"),
+                            }
+                        }
+                    }
+                    // Answer round‑trip probes; a listener's media goes nowhere.
+                    Some(Packet::Sealed {
+                        kind: Sealed::Ping,
+                        epoch,
+                        seq,
+                        payload,
+                    }) => {
+                        let Some(session) = listener.session.as_mut() else {
+                            continue;
+                        };
+                        let Some(body) = session.opener.open(Sealed::Ping, epoch, seq, payload)
+                        else {
+                            continue;
+                        };
+                        health.packet();
+                        capture::decrypted(&sock, src, capture::Direction::In, &buf[..n], &body);
+                        match session.sealer.seal(Sealed::Pong, &body) {
+                            Ok(pkt) => {
+                                capture::sent(&sock, src, &pkt);
+                                if let Err(e) = sock.send_to(&pkt, src).await {
+                                    error!("udp send error to {src}: {e}");
+                                }
+                            }
+                            Err(e) => error!("{e}"),
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Seals `body` for every keyed listener `wanted` picks, into `batch`.
+fn seal_for(
+    audience: &mut HashMap<SocketAddr, Listener>,
+    batch: &mut Vec<(SocketAddr, Bytes)>,
+    kind: Sealed,
+    body: &[u8],
+    wanted: impl Fn(&Listener) -> bool,
+) {
+    batch.clear();
+    for (addr, listener) in audience.iter_mut() {
+        if !wanted(listener) {
+            continue;
+        }
+        let Some(session) = listener.session.as_mut() else {
+            continue;
+        };
+        match session.sealer.seal(kind, body) {
+            Ok(pkt) => batch.push((*addr, pkt)),
+            Err(e) => error!("{e}"),
+        }
+    }
+}
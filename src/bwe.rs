@@ -0,0 +1,255 @@
+// ─── Bandwidth estimation ──────────────────────────────────────────────────────
+// Loss is a late sign of a congested uplink: by the time a router drops our
+// packets its queue – and the call's latency – has already grown. The
+// receiver watches for that growth instead, the way WebRTC's GCC does. Media
+// leaves the sender one frame apart, so whatever spacing between two
+// consecutive arrivals exceeds a frame was spent in a queue. The running sum
+// of those excesses tracks the queuing delay, and the slope of a line fitted
+// through its last `WINDOW` values says whether the queues are filling. A
+// slope above an adaptive threshold is overuse; below minus it, underuse.
+//
+// The receiver turns that into an estimate of what the path carries: on
+// overuse it drops to `DECREASE` times the rate actually arriving, while
+// nothing is queuing it grows by `INCREASE` a second, and while queues drain
+// it holds. There is no estimate until the first overuse, and it lapses once
+// it is well above what the sender uses. Estimates go back to the sender in a
+// sealed Rate packet, at once when they drop and once a second otherwise; a
+// sender with `--adaptive-bitrate` caps its encoder to fit (`Target`).
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+/// Delay samples the trend is fitted through.
+const WINDOW: usize = 20;
+/// Smoothing of the accumulated delay.
+const SMOOTHING: f64 = 0.9;
+const TREND_GAIN: f64 = 4.0;
+/// Starting threshold for the trend, in ms, and how fast it follows it.
+const INITIAL_THRESHOLD: f64 = 12.5;
+const THRESHOLD_UP: f64 = 0.0087;
+const THRESHOLD_DOWN: f64 = 0.039;
+/// How long the trend must stay over the threshold to count as overuse.
+const OVERUSE_FOR: Duration = Duration::from_millis(10);
+const DECREASE: f64 = 0.85;
+/// Growth a second while the path keeps up.
+const INCREASE: f64 = 1.08;
+/// How often the arriving rate is measured, and estimates are repeated.
+const INTERVAL: Duration = Duration::from_secs(1);
+/// An estimate this many times the arriving rate no longer limits anything.
+const LAPSE_ABOVE: f64 = 2.0;
+/// Opus' lowest useful bitrate.
+const MIN_BITRATE: i32 = 6_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Usage {
+    Normal,
+    Over,
+    Under,
+}
+
+/// One peer's stream, seen from the receiving side.
+pub(crate) struct Estimator {
+    frame_ms: f64,
+    started: Instant,
+    /// (epoch, seq) and arrival of the last media packet.
+    last: Option<(u8, u32, Instant)>,
+    delay: f64,
+    smoothed: f64,
+    /// (arrival, smoothed delay), both in ms.
+    samples: VecDeque<(f64, f64)>,
+    threshold: f64,
+    prev_trend: f64,
+    over_since: Option<Instant>,
+    usage: Usage,
+    /// Bytes arriving in the current interval, and the rate of the last one.
+    bytes: usize,
+    interval: Instant,
+    arriving: Option<f64>,
+    /// In bits a second.
+    estimate: Option<f64>,
+    updated: Instant,
+    decreased: Option<Instant>,
+    reported: Option<Instant>,
+}
+
+impl Estimator {
+    pub fn new(frame_ms: u8) -> Self {
+        let now = Instant::now();
+        Self {
+            frame_ms: frame_ms as f64,
+            started: now,
+            last: None,
+            delay: 0.0,
+            smoothed: 0.0,
+            samples: VecDeque::with_capacity(WINDOW),
+            threshold: INITIAL_THRESHOLD,
+            prev_trend: 0.0,
+            over_since: None,
+            usage: Usage::Normal,
+            bytes: 0,
+            interval: now,
+            arriving: None,
+            estimate: None,
+            updated: now,
+            decreased: None,
+            reported: None,
+        }
+    }
+
+    /// Accounts for a `len`‑byte media packet; returns an estimate in bits a
+    /// second to send back when one is due, 0 for no limit.
+    pub fn arrived(&mut self, epoch: u8, seq: u32, at: Instant, len: usize) -> Option<u32> {
+        self.bytes += len;
+        let elapsed = at.saturating_duration_since(self.interval);
+        if elapsed >= INTERVAL {
+            self.arriving = Some(self.bytes as f64 * 8.0 / elapsed.as_secs_f64());
+            self.bytes = 0;
+            self.interval = at;
+        }
+        let consecutive = self
+            .last
+            .is_some_and(|(e, s, _)| e == epoch && s.wrapping_add(1) == seq);
+        if let (true, Some((_, _, prev))) = (consecutive, self.last) {
+            let spacing = at.saturating_duration_since(prev).as_secs_f64() * 1000.0;
+            self.detect(at, spacing - self.frame_ms);
+        }
+        self.last = Some((epoch, seq, at));
+        let dropped = self.control(at);
+
+        let lapsed = self.estimate.is_none() && self.reported.is_some();
+        let due = self.reported.is_none_or(|r| at - r >= INTERVAL);
+        if !dropped && !(due && (self.estimate.is_some() || lapsed)) {
+            return None;
+        }
+        self.reported = self.estimate.map(|_| at);
+        Some(self.estimate.map_or(0, |e| e as u32))
+    }
+
+    fn detect(&mut self, at: Instant, excess_ms: f64) {
+        self.delay += excess_ms;
+        self.smoothed = SMOOTHING * self.smoothed + (1.0 - SMOOTHING) * self.delay;
+        if self.samples.len() == WINDOW {
+            self.samples.pop_front();
+        }
+        let t = (at - self.started).as_secs_f64() * 1000.0;
+        self.samples.push_back((t, self.smoothed));
+        if self.samples.len() < WINDOW / 2 {
+            return;
+        }
+        let trend = slope(&self.samples) * self.samples.len() as f64 * TREND_GAIN;
+
+        self.usage = if trend > self.threshold {
+            let since = *self.over_since.get_or_insert(at);
+            match at - since >= OVERUSE_FOR && trend >= self.prev_trend {
+                true => Usage::Over,
+                false => self.usage,
+            }
+        } else {
+            self.over_since = None;
+            match trend < -self.threshold {
+                true => Usage::Under,
+                false => Usage::Normal,
+            }
+        };
+        self.prev_trend = trend;
+
+        // The threshold follows the trend, quickly down and slowly up, but
+        // ignores spikes far above it.
+        if trend.abs() < self.threshold + 15.0 {
+            let k = match trend.abs() < self.threshold {
+                true => THRESHOLD_DOWN,
+                false => THRESHOLD_UP,
+            };
+            let spacing = (excess_ms + self.frame_ms).clamp(0.0, 100.0);
+            self.threshold += k * (trend.abs() - self.threshold) * spacing;
+            self.threshold = self.threshold.clamp(6.0, 600.0);
+        }
+    }
+
+    /// Updates the estimate; true if it just dropped.
+    fn control(&mut self, at: Instant) -> bool {
+        let dt = at.saturating_duration_since(self.updated).as_secs_f64();
+        self.updated = at;
+        let Some(arriving) = self.arriving else {
+            return false;
+        };
+        match self.usage {
+            Usage::Over => {
+                // The arriving rate lags a measuring interval behind.
+                if self.decreased.is_some_and(|d| at - d < INTERVAL) {
+                    return false;
+                }
+                let lower = DECREASE * arriving;
+                if self.estimate.is_some_and(|e| e <= lower) {
+                    return false;
+                }
+                self.estimate = Some(lower);
+                self.decreased = Some(at);
+                true
+            }
+            Usage::Normal => {
+                if let Some(e) = &mut self.estimate {
+                    *e *= INCREASE.powf(dt);
+                    if *e > LAPSE_ABOVE * arriving {
+                        self.estimate = None;
+                    }
+                }
+                false
+            }
+            Usage::Under => false,
+        }
+    }
+}
+
+/// Least‑squares slope of `y` over `x`.
+fn slope(samples: &VecDeque<(f64, f64)>) -> f64 {
+    let n = samples.len() as f64;
+    let (mx, my) = samples
+        .iter()
+        .fold((0.0, 0.0), |(sx, sy), (x, y)| (sx + x / n, sy + y / n));
+    let (num, den) = samples.iter().fold((0.0, 0.0), |(num, den), (x, y)| {
+        (num + (x - mx) * (y - my), den + (x - mx) * (x - mx))
+    });
+    match den > 0.0 {
+        true => num / den,
+        false => 0.0,
+    }
+}
+
+/// The latest estimate from the peer, from the network task to the encoder.
+#[derive(Default)]
+pub(crate) struct Target {
+    /// Bits a second; 0 for no limit.
+    bps: AtomicU32,
+    changed: AtomicBool,
+}
+
+impl Target {
+    /// Takes a Rate body; the new estimate, if it changed.
+    pub fn report(&self, body: &[u8]) -> Option<u32> {
+        let bps = u32::from_be_bytes(body.get(..4)?.try_into().ok()?);
+        if self.bps.swap(bps, Ordering::Relaxed) == bps {
+            return None;
+        }
+        self.changed.store(true, Ordering::Relaxed);
+        Some(bps)
+    }
+
+    /// A new encoder bitrate for `frame_ms` frames, if the estimate changed:
+    /// what it leaves after our per‑packet overhead, or `None` for Opus' own
+    /// choice.
+    pub fn take(&self, frame_ms: usize) -> Option<Option<i32>> {
+        if !self.changed.swap(false, Ordering::Relaxed) {
+            return None;
+        }
+        let bps = self.bps.load(Ordering::Relaxed);
+        let overhead = crate::packet::MEDIA_OVERHEAD * 8 * 1000 / frame_ms;
+        Some((bps > 0).then(|| (bps as i32 - overhead as i32).max(MIN_BITRATE)))
+    }
+}
+
+/// A Rate body.
+pub(crate) fn encode(bps: u32) -> [u8; 4] {
+    bps.to_be_bytes()
+}
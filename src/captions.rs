@@ -0,0 +1,196 @@
+// ─── Live captions ─────────────────────────────────────────────────────────────
+// Hands each sender's decoded audio to a speech‑to‑text backend and reports
+// what it recognises as `Caption` events (`VoiceSession::caption_events`).
+// Recognisers want 16 kHz mono, so the decode task folds each frame to mono
+// and queues it for a thread of its own, which brings it down to 16 kHz (see
+// `resample`); a backend that can't keep up loses audio rather than delaying
+// playout. Backends
+// implement `Transcriber`; with the `vosk` feature `vosk` builds one from a
+// Vosk model directory.
+
+use crate::resample::Resampler;
+use anyhow::Result;
+use async_channel::{bounded, Receiver, Sender};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::mpsc::{sync_channel, RecvTimeoutError, SyncSender};
+use std::time::Duration;
+use tracing::{error, warn};
+
+/// What transcribers are fed.
+pub const SAMPLE_RATE: u32 = 16_000;
+/// Caption events kept for a consumer that isn't reading.
+const CAPTION_EVENTS: usize = 256;
+
+/// Text recognised in one sender's speech.
+#[derive(Clone, Debug)]
+pub struct Caption {
+    pub peer: SocketAddr,
+    pub text: String,
+    /// The recogniser may still revise this; a final caption for the same
+    /// utterance follows.
+    pub partial: bool,
+}
+
+/// A speech‑to‑text engine, one per sender.
+pub trait Transcriber: Send {
+    /// Feeds 16 kHz mono samples; returns the text of the utterance so far,
+    /// if it changed, and whether the utterance ended.
+    fn feed(&mut self, pcm: &[f32]) -> Result<Option<(String, bool)>>;
+}
+
+/// Makes a transcriber for each new sender.
+pub type NewTranscriber = Box<dyn Fn() -> Result<Box<dyn Transcriber>> + Send>;
+
+/// Sending half, held by the decode task.
+#[derive(Clone)]
+pub(crate) struct Tap {
+    tx: SyncSender<(SocketAddr, Vec<f32>)>,
+}
+
+impl Tap {
+    /// `pcm` is interleaved 48 kHz with `channels` channels. Never blocks.
+    pub fn push(&self, peer: SocketAddr, pcm: &[f32], channels: usize) {
+        let pcm = pcm
+            .chunks(channels)
+            .map(|f| f.iter().sum::<f32>() / f.len() as f32)
+            .collect();
+        let _ = self.tx.try_send((peer, pcm));
+    }
+}
+
+/// Runs the transcribers until the session ends.
+pub(crate) struct Captioner {
+    rx: Receiver<Caption>,
+    _thread: std::thread::JoinHandle<()>,
+}
+
+impl Captioner {
+    pub fn start(new: NewTranscriber) -> Result<(Self, Tap)> {
+        let (tx, audio) = sync_channel::<(SocketAddr, Vec<f32>)>(256);
+        let (events, rx) = bounded(CAPTION_EVENTS);
+        let thread = std::thread::Builder::new()
+            .name("voice-captions".into())
+            .spawn(move || {
+                type Listener = (Resampler, Option<Box<dyn Transcriber>>);
+                let mut peers: HashMap<SocketAddr, Listener> = HashMap::new();
+                let mut down = Vec::new();
+                loop {
+                    let (peer, pcm) = match audio.recv_timeout(Duration::from_millis(250)) {
+                        Ok(chunk) => chunk,
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        // The decode task ended.
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    };
+                    // A sender whose transcriber failed stays uncaptioned.
+                    let (resampler, slot) = peers.entry(peer).or_insert_with(|| {
+                        let transcriber = match new() {
+                            Ok(t) => Some(t),
+                            Err(e) => {
+                                error!(%peer, "can't start captions: {e:#}");
+                                None
+                            }
+                        };
+                        let resampler = Resampler::new(crate::SAMPLE_RATE, SAMPLE_RATE, 1);
+                        (resampler, transcriber)
+                    });
+                    let Some(transcriber) = slot.as_mut() else {
+                        continue;
+                    };
+                    down.clear();
+                    for &sample in &pcm {
+                        resampler.push(&[sample], |s| down.push(s[0]));
+                    }
+                    match transcriber.feed(&down) {
+                        Ok(Some((text, done))) => emit(&events, peer, text, done),
+                        Ok(None) => {}
+                        Err(e) => {
+                            warn!(%peer, "captions stopped: {e:#}");
+                            *slot = None;
+                        }
+                    }
+                }
+            })?;
+        Ok((
+            Self {
+                rx,
+                _thread: thread,
+            },
+            Tap { tx },
+        ))
+    }
+
+    pub fn events(&self) -> Receiver<Caption> {
+        self.rx.clone()
+    }
+}
+
+fn emit(events: &Sender<Caption>, peer: SocketAddr, text: String, done: bool) {
+    if text.is_empty() {
+        return;
+    }
+    // Oldest first out if nobody is listening.
+    let _ = events.force_send(Caption {
+        peer,
+        text,
+        partial: !done,
+    });
+}
+
+/// Transcribes with the Vosk model in `model_dir`.
+#[cfg(feature = "vosk")]
+pub fn vosk(model_dir: &Path) -> Result<NewTranscriber> {
+    use anyhow::Context;
+    let path = model_dir.to_string_lossy().into_owned();
+    let model = vosk::Model::new(path)
+        .with_context(|| format!("no Vosk model in {}", model_dir.display()))?;
+    Ok(Box::new(move || {
+        let rec = vosk::Recognizer::new(&model, SAMPLE_RATE as f32)
+            .context("can't create a Vosk recognizer")?;
+        Ok(Box::new(Vosk {
+            rec,
+            pcm: Vec::new(),
+            last: String::new(),
+        }) as Box<dyn Transcriber>)
+    }))
+}
+
+#[cfg(not(feature = "vosk"))]
+pub fn vosk(_model_dir: &Path) -> Result<NewTranscriber> {
+    anyhow::bail!("captions need a build with the `vosk` feature")
+}
+
+#[cfg(feature = "vosk")]
+struct Vosk {
+    rec: vosk::Recognizer,
+    pcm: Vec<i16>,
+    /// The partial text last reported.
+    last: String,
+}
+
+#[cfg(feature = "vosk")]
+impl Transcriber for Vosk {
+    fn feed(&mut self, pcm: &[f32]) -> Result<Option<(String, bool)>> {
+        self.pcm.clear();
+        self.pcm.extend(
+            pcm.iter()
+                .map(|&s| (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16),
+        );
+        let state = self
+            .rec
+            .accept_waveform(&self.pcm)
+            .map_err(|e| anyhow::anyhow!("vosk: {e:?}"))?;
+        if matches!(state, vosk::DecodingState::Finalized) {
+            self.last.clear();
+            let text = self.rec.result().single().map(|r| r.text.to_string());
+            return Ok(text.map(|t| (t, true)));
+        }
+        let partial = self.rec.partial_result().partial;
+        if partial == self.last {
+            return Ok(None);
+        }
+        self.last = partial.to_string();
+        Ok(Some((self.last.clone(), false)))
+    }
+}
@@ -0,0 +1,296 @@
+// ─── Packet capture ────────────────────────────────────────────────────────────
+// `--capture-packets out.pcapng` records every datagram the call sends and
+// receives, wrapped in made‑up IP/UDP headers (raw‑IP link type) so Wireshark
+// shows addresses and ports and can be told to decode the port as anything.
+// Packets carry their direction in the EPB flags.
+//
+// Debug builds can also record the plaintext of sealed packets
+// (`CaptureOptions::decrypted`): a second copy right after the sealed one with
+// the same header, the opened body in place of the ciphertext, and the
+// comment "decrypted". Release builds never write plaintext.
+//
+// One capture per process at a time; it is flushed and closed when the
+// `Guard` returned by `start` (held by the session) is dropped.
+
+use crate::packet::MEDIA_HEADER_LEN;
+use anyhow::{Context, Result};
+use parking_lot::{const_mutex, Mutex};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+use tracing::{info, warn};
+
+/// LINKTYPE_RAW: packets start with an IPv4 or IPv6 header.
+const LINKTYPE_RAW: u16 = 101;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug)]
+pub struct CaptureOptions {
+    pub path: std::path::PathBuf,
+    /// Also record opened payloads (debug builds only).
+    pub decrypted: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Direction {
+    In,
+    Out,
+}
+
+struct Pcap {
+    out: BufWriter<File>,
+    decrypted: bool,
+    last_flush: Instant,
+    /// Stand‑ins for wildcard local addresses, by port.
+    local_ips: HashMap<u16, IpAddr>,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static CAPTURE: Mutex<Option<Pcap>> = const_mutex(None);
+
+/// Ends the capture when dropped.
+pub(crate) struct Guard;
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        ENABLED.store(false, Ordering::Relaxed);
+        if let Some(mut pcap) = CAPTURE.lock().take() {
+            if let Err(e) = pcap.out.flush() {
+                warn!("packet capture: {e}");
+            }
+        }
+    }
+}
+
+/// Starts writing `opts.path`, replacing any capture already running.
+pub(crate) fn start(opts: &CaptureOptions) -> Result<Guard> {
+    let file = File::create(&opts.path)
+        .with_context(|| format!("can't create {}", opts.path.display()))?;
+    let mut out = BufWriter::new(file);
+    write_header(&mut out)?;
+    let decrypted = opts.decrypted && cfg!(debug_assertions);
+    if opts.decrypted && !decrypted {
+        warn!("decrypted packet capture needs a debug build; recording sealed packets only");
+    }
+    info!("capturing packets to {}", opts.path.display());
+    *CAPTURE.lock() = Some(Pcap {
+        out,
+        decrypted,
+        last_flush: Instant::now(),
+        local_ips: HashMap::new(),
+    });
+    ENABLED.store(true, Ordering::Relaxed);
+    Ok(Guard)
+}
+
+/// Records a datagram sent from `sock` to `to`.
+pub(crate) fn sent(sock: &UdpSocket, to: SocketAddr, pkt: &[u8]) {
+    if ENABLED.load(Ordering::Relaxed) {
+        record(sock, to, Direction::Out, pkt, None);
+    }
+}
+
+/// Records a datagram `sock` received from `from`.
+pub(crate) fn received(sock: &UdpSocket, from: SocketAddr, pkt: &[u8]) {
+    if ENABLED.load(Ordering::Relaxed) {
+        record(sock, from, Direction::In, pkt, None);
+    }
+}
+
+/// Records `body`, the plaintext of the `sealed` packet, if the capture asked
+/// for it.
+pub(crate) fn decrypted(
+    sock: &UdpSocket,
+    remote: SocketAddr,
+    dir: Direction,
+    sealed: &[u8],
+    body: &[u8],
+) {
+    if !cfg!(debug_assertions) || !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let Some(header) = sealed.get(..MEDIA_HEADER_LEN) else {
+        return;
+    };
+    let pkt = [header, body].concat();
+    record(sock, remote, dir, &pkt, Some("decrypted"));
+}
+
+fn record(sock: &UdpSocket, remote: SocketAddr, dir: Direction, pkt: &[u8], comment: Option<&str>) {
+    let Ok(local) = sock.local_addr() else {
+        return;
+    };
+    let mut capture = CAPTURE.lock();
+    let Some(pcap) = capture.as_mut() else {
+        return;
+    };
+    if comment.is_some() && !pcap.decrypted {
+        return;
+    }
+    let local_ip = match local.ip().is_unspecified() {
+        true => *pcap
+            .local_ips
+            .entry(local.port())
+            .or_insert_with(|| crate::socket::lan_address(sock).map_or(local.ip(), |a| a.ip())),
+        false => local.ip(),
+    };
+    let local = SocketAddr::new(local_ip, local.port());
+    let (src, dst) = match dir {
+        Direction::In => (remote, local),
+        Direction::Out => (local, remote),
+    };
+    let r = write_packet(&mut pcap.out, src, dst, dir, pkt, comment).and_then(|()| {
+        if pcap.last_flush.elapsed() >= FLUSH_INTERVAL {
+            pcap.last_flush = Instant::now();
+            pcap.out.flush()?;
+        }
+        Ok(())
+    });
+    if let Err(e) = r {
+        warn!("packet capture stopped: {e}");
+        *capture = None;
+        ENABLED.store(false, Ordering::Relaxed);
+    }
+}
+
+// ─── pcapng ────────────────────────────────────────────────────────────────────
+
+fn block(out: &mut impl Write, kind: u32, body: &[u8]) -> std::io::Result<()> {
+    let pad = (4 - body.len() % 4) % 4;
+    let len = (12 + body.len() + pad) as u32;
+    out.write_all(&kind.to_le_bytes())?;
+    out.write_all(&len.to_le_bytes())?;
+    out.write_all(body)?;
+    out.write_all(&[0; 3][..pad])?;
+    out.write_all(&len.to_le_bytes())
+}
+
+/// Appends option `code` to `body`, padded to 32 bits.
+fn option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+    body.extend_from_slice(&code.to_le_bytes());
+    body.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    body.extend_from_slice(value);
+    body.resize(body.len().next_multiple_of(4), 0);
+}
+
+fn write_header(out: &mut impl Write) -> std::io::Result<()> {
+    // Section header: byte‑order magic, version 1.0, unknown section length.
+    let mut shb = Vec::new();
+    shb.extend_from_slice(&0x1a2b_3c4du32.to_le_bytes());
+    shb.extend_from_slice(&1u16.to_le_bytes());
+    shb.extend_from_slice(&0u16.to_le_bytes());
+    shb.extend_from_slice(&(-1i64).to_le_bytes());
+    block(out, 0x0a0d_0d0a, &shb)?;
+    // One interface; microsecond timestamps are the default.
+    let mut idb = Vec::new();
+    idb.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+    idb.extend_from_slice(&0u16.to_le_bytes());
+    idb.extend_from_slice(&0u32.to_le_bytes());
+    block(out, 1, &idb)
+}
+
+fn write_packet(
+    out: &mut impl Write,
+    src: SocketAddr,
+    dst: SocketAddr,
+    dir: Direction,
+    payload: &[u8],
+    comment: Option<&str>,
+) -> std::io::Result<()> {
+    let data = ip_udp(src, dst, payload);
+    let micros = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64;
+    let mut epb = Vec::with_capacity(data.len() + 48);
+    epb.extend_from_slice(&0u32.to_le_bytes());
+    epb.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+    epb.extend_from_slice(&(micros as u32).to_le_bytes());
+    epb.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    epb.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    epb.extend_from_slice(&data);
+    epb.resize(epb.len().next_multiple_of(4), 0);
+    // epb_flags bits 0–1: 1 inbound, 2 outbound.
+    let flags: u32 = match dir {
+        Direction::In => 1,
+        Direction::Out => 2,
+    };
+    option(&mut epb, 2, &flags.to_le_bytes());
+    if let Some(comment) = comment {
+        option(&mut epb, 1, comment.as_bytes());
+    }
+    option(&mut epb, 0, &[]);
+    block(out, 6, &epb)
+}
+
+/// `payload` as a UDP datagram from `src` to `dst`, with an IPv4 header when
+/// both ends are IPv4 and IPv6 otherwise.
+fn ip_udp(src: SocketAddr, dst: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let udp_len = (8 + payload.len()) as u16;
+    let mut udp = Vec::with_capacity(udp_len as usize);
+    udp.extend_from_slice(&src.port().to_be_bytes());
+    udp.extend_from_slice(&dst.port().to_be_bytes());
+    udp.extend_from_slice(&udp_len.to_be_bytes());
+    udp.extend_from_slice(&[0, 0]);
+    udp.extend_from_slice(payload);
+    match (src.ip(), dst.ip()) {
+        (IpAddr::V4(s), IpAddr::V4(d)) => {
+            let mut ip = vec![0x45, 0];
+            ip.extend_from_slice(&(20 + udp_len).to_be_bytes());
+            // No id; don't fragment; TTL 64; UDP.
+            ip.extend_from_slice(&[0, 0, 0x40, 0, 64, 17, 0, 0]);
+            ip.extend_from_slice(&s.octets());
+            ip.extend_from_slice(&d.octets());
+            let sum = checksum(&ip);
+            ip[10..12].copy_from_slice(&sum.to_be_bytes());
+            // A zero UDP checksum means "none" over IPv4.
+            ip.extend_from_slice(&udp);
+            ip
+        }
+        (s, d) => {
+            let (s, d) = (v6(s), v6(d));
+            let mut ip = vec![0x60, 0, 0, 0];
+            ip.extend_from_slice(&udp_len.to_be_bytes());
+            ip.extend_from_slice(&[17, 64]);
+            ip.extend_from_slice(&s.octets());
+            ip.extend_from_slice(&d.octets());
+            // IPv6 requires the UDP checksum, over a pseudo‑header.
+            let mut pseudo = Vec::with_capacity(40 + udp.len());
+            pseudo.extend_from_slice(&s.octets());
+            pseudo.extend_from_slice(&d.octets());
+            pseudo.extend_from_slice(&(udp_len as u32).to_be_bytes());
+            pseudo.extend_from_slice(&[0, 0, 0, 17]);
+            pseudo.extend_from_slice(&udp);
+            let sum = match checksum(&pseudo) {
+                0 => 0xffff,
+                sum => sum,
+            };
+            udp[6..8].copy_from_slice(&sum.to_be_bytes());
+            ip.extend_from_slice(&udp);
+            ip
+        }
+    }
+}
+
+fn v6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+/// The Internet checksum (RFC 1071).
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|c| u16::from_be_bytes([c[0], c.get(1).copied().unwrap_or(0)]) as u32)
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
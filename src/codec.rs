@@ -0,0 +1,417 @@
+// ─── Codec ─────────────────────────────────────────────────────────────────────
+// Thin wrapper over libopus' multistream API. With one channel it produces
+// plain Opus packets, so mono voice looks exactly like before on the wire;
+// more channels (e.g. 5.1 from a DAW loopback) use the Vorbis surround mapping
+// (family 1). The decoder can't infer the stream layout from the packets, so
+// each side advertises its `StreamParams` in its Hello, together with the
+// frame duration it would like (both sides then send the longer of the two)
+// and whether its packets carry in‑band FEC.
+
+use anyhow::{bail, Result};
+use audiopus_sys as sys;
+use std::ffi::{c_int, CStr};
+
+pub const MAX_CHANNELS: usize = 8;
+/// Frame durations we offer, in milliseconds.
+pub const FRAME_MS_OPTIONS: [u8; 4] = [10, 20, 40, 60];
+pub const DEFAULT_FRAME_MS: u8 = 20;
+/// Loss rate the encoder plans its FEC for.
+const FEC_LOSS_PERCENT: c_int = 10;
+/// `StreamParams` flag bits.
+const FLAG_FEC: u8 = 0x01;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StreamLayout {
+    pub channels: u8,
+    pub streams: u8,
+    pub coupled_streams: u8,
+    /// Output channel → coded channel, one entry per channel.
+    pub mapping: Vec<u8>,
+}
+
+impl StreamLayout {
+    pub fn mono() -> Self {
+        Self {
+            channels: 1,
+            streams: 1,
+            coupled_streams: 0,
+            mapping: vec![0],
+        }
+    }
+
+    /// `channels, streams, coupled, mapping…`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![self.channels, self.streams, self.coupled_streams];
+        out.extend_from_slice(&self.mapping);
+        out
+    }
+
+    /// Encoded length of a layout with `channels` channels.
+    fn encoded_len(channels: u8) -> usize {
+        3 + channels as usize
+    }
+
+    pub fn from_bytes(buf: &[u8]) -> Option<Self> {
+        let &[channels, streams, coupled_streams] = buf.get(..3)? else {
+            return None;
+        };
+        let mapping = buf.get(3..Self::encoded_len(channels))?.to_vec();
+        let valid = (1..=MAX_CHANNELS as u8).contains(&channels)
+            && (1..=MAX_CHANNELS as u8).contains(&streams)
+            && coupled_streams <= streams
+            && mapping
+                .iter()
+                .all(|&m| m == 255 || m < streams + coupled_streams);
+        valid.then_some(Self {
+            channels,
+            streams,
+            coupled_streams,
+            mapping,
+        })
+    }
+}
+
+/// What a sender advertises in its Hello.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StreamParams {
+    pub frame_ms: u8,
+    pub layout: StreamLayout,
+    /// Packets carry the previous frame again as in‑band FEC.
+    pub fec: bool,
+}
+
+impl Default for StreamParams {
+    fn default() -> Self {
+        Self {
+            frame_ms: DEFAULT_FRAME_MS,
+            layout: StreamLayout::mono(),
+            fec: false,
+        }
+    }
+}
+
+impl StreamParams {
+    /// `frame_ms, [layout, [flags]]`; empty for the defaults, which is also
+    /// what peers that predate the extension send. Older peers ignore the
+    /// flags.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        if *self == Self::default() {
+            return Vec::new();
+        }
+        let mut out = vec![self.frame_ms];
+        if self.layout != StreamLayout::mono() || self.fec {
+            out.extend_from_slice(&self.layout.to_bytes());
+        }
+        if self.fec {
+            out.push(FLAG_FEC);
+        }
+        out
+    }
+
+    pub fn from_bytes(buf: &[u8]) -> Option<Self> {
+        let Some((&frame_ms, rest)) = buf.split_first() else {
+            return Some(Self::default());
+        };
+        if !FRAME_MS_OPTIONS.contains(&frame_ms) {
+            return None;
+        }
+        let (layout, flags) = match rest {
+            [] => (StreamLayout::mono(), 0),
+            ext => {
+                let layout = StreamLayout::from_bytes(ext)?;
+                let flags = ext.get(StreamLayout::encoded_len(layout.channels));
+                (layout, flags.copied().unwrap_or(0))
+            }
+        };
+        Some(Self {
+            frame_ms,
+            layout,
+            fec: flags & FLAG_FEC != 0,
+        })
+    }
+}
+
+/// Rate control. VBR sounds best per bit; CBR makes every packet the same
+/// size, which hides speech activity from traffic analysis and makes
+/// bandwidth predictable; constrained VBR sits in between.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BitrateMode {
+    #[default]
+    Vbr,
+    ConstrainedVbr,
+    Cbr,
+}
+
+/// Upper limit on the coded audio bandwidth (passband).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bandwidth {
+    /// 4 kHz
+    Narrow,
+    /// 6 kHz
+    Medium,
+    /// 8 kHz
+    Wide,
+    /// 12 kHz
+    SuperWide,
+    /// 20 kHz
+    Full,
+}
+
+impl Bandwidth {
+    fn to_opus(self) -> c_int {
+        match self {
+            Bandwidth::Narrow => sys::OPUS_BANDWIDTH_NARROWBAND,
+            Bandwidth::Medium => sys::OPUS_BANDWIDTH_MEDIUMBAND,
+            Bandwidth::Wide => sys::OPUS_BANDWIDTH_WIDEBAND,
+            Bandwidth::SuperWide => sys::OPUS_BANDWIDTH_SUPERWIDEBAND,
+            Bandwidth::Full => sys::OPUS_BANDWIDTH_FULLBAND,
+        }
+    }
+}
+
+impl std::str::FromStr for Bandwidth {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "narrow" => Bandwidth::Narrow,
+            "medium" => Bandwidth::Medium,
+            "wide" => Bandwidth::Wide,
+            "superwide" => Bandwidth::SuperWide,
+            "full" => Bandwidth::Full,
+            _ => bail!("expected narrow, medium, wide, superwide or full"),
+        })
+    }
+}
+
+/// Encoder settings chosen by the user (as opposed to negotiated).
+#[derive(Clone, Debug, Default)]
+pub struct EncoderOptions {
+    pub bitrate_mode: BitrateMode,
+    /// `None` lets Opus pick from the bitrate.
+    pub max_bandwidth: Option<Bandwidth>,
+    /// Discontinuous transmission: during silence Opus emits empty frames,
+    /// which we replace with a silence marker.
+    pub dtx: bool,
+    /// In‑band FEC: each packet also carries a coarse copy of the previous
+    /// frame, so the receiver can rebuild a single lost one.
+    pub fec: bool,
+    /// Cap the bitrate to the peer's bandwidth estimate (see `bwe`).
+    pub adaptive_bitrate: bool,
+}
+
+fn check(what: &str, code: c_int) -> Result<c_int> {
+    if code < 0 {
+        let msg = unsafe { CStr::from_ptr(sys::opus_strerror(code)) };
+        bail!("opus {what} failed: {}", msg.to_string_lossy());
+    }
+    Ok(code)
+}
+
+pub struct Encoder {
+    ptr: *mut sys::OpusMSEncoder,
+    layout: StreamLayout,
+}
+
+// The state is only ever touched through `&mut self`.
+unsafe impl Send for Encoder {}
+
+impl Encoder {
+    /// Mono uses the VoIP application; surround is music/ambience, so it gets
+    /// the general audio mode.
+    pub fn new(sample_rate: u32, channels: u8) -> Result<Self> {
+        if !(1..=MAX_CHANNELS as u8).contains(&channels) {
+            bail!("opus supports 1..={MAX_CHANNELS} channels, not {channels}");
+        }
+        let (family, application) = match channels {
+            1 => (0, sys::OPUS_APPLICATION_VOIP),
+            _ => (1, sys::OPUS_APPLICATION_AUDIO),
+        };
+        let mut streams = 0;
+        let mut coupled = 0;
+        let mut mapping = vec![0u8; channels as usize];
+        let mut err = 0;
+        let ptr = unsafe {
+            sys::opus_multistream_surround_encoder_create(
+                sample_rate as i32,
+                channels as c_int,
+                family,
+                &mut streams,
+                &mut coupled,
+                mapping.as_mut_ptr(),
+                application,
+                &mut err,
+            )
+        };
+        check("encoder_create", err)?;
+        Ok(Self {
+            ptr,
+            layout: StreamLayout {
+                channels,
+                streams: streams as u8,
+                coupled_streams: coupled as u8,
+                mapping,
+            },
+        })
+    }
+
+    pub fn layout(&self) -> &StreamLayout {
+        &self.layout
+    }
+
+    pub fn apply(&mut self, opts: &EncoderOptions) -> Result<()> {
+        let (vbr, constrained) = match opts.bitrate_mode {
+            BitrateMode::Vbr => (1, 0),
+            BitrateMode::ConstrainedVbr => (1, 1),
+            BitrateMode::Cbr => (0, 0),
+        };
+        self.ctl("set_vbr", sys::OPUS_SET_VBR_REQUEST, vbr)?;
+        self.ctl(
+            "set_vbr_constraint",
+            sys::OPUS_SET_VBR_CONSTRAINT_REQUEST,
+            constrained,
+        )?;
+        if let Some(bw) = opts.max_bandwidth {
+            self.ctl(
+                "set_max_bandwidth",
+                sys::OPUS_SET_MAX_BANDWIDTH_REQUEST,
+                bw.to_opus(),
+            )?;
+        }
+        self.ctl("set_dtx", sys::OPUS_SET_DTX_REQUEST, opts.dtx as c_int)?;
+        self.ctl(
+            "set_inband_fec",
+            sys::OPUS_SET_INBAND_FEC_REQUEST,
+            opts.fec as c_int,
+        )?;
+        // Opus only spends bits on FEC when it expects loss.
+        let loss = if opts.fec { FEC_LOSS_PERCENT } else { 0 };
+        self.ctl(
+            "set_packet_loss_perc",
+            sys::OPUS_SET_PACKET_LOSS_PERC_REQUEST,
+            loss,
+        )?;
+        Ok(())
+    }
+
+    /// `None` lets Opus pick the bitrate.
+    pub fn set_bitrate(&mut self, bps: Option<i32>) -> Result<()> {
+        let bps = bps.unwrap_or(sys::OPUS_AUTO);
+        self.ctl("set_bitrate", sys::OPUS_SET_BITRATE_REQUEST, bps)
+    }
+
+    fn ctl(&mut self, what: &str, request: i32, value: c_int) -> Result<()> {
+        check(what, unsafe {
+            sys::opus_multistream_encoder_ctl(self.ptr, request, value)
+        })?;
+        Ok(())
+    }
+
+    /// `pcm` is interleaved in Vorbis channel order.
+    pub fn encode_float(&mut self, pcm: &[f32], out: &mut [u8]) -> Result<usize> {
+        let frame_size = pcm.len() / self.layout.channels as usize;
+        let len = unsafe {
+            sys::opus_multistream_encode_float(
+                self.ptr,
+                pcm.as_ptr(),
+                frame_size as c_int,
+                out.as_mut_ptr(),
+                out.len() as i32,
+            )
+        };
+        Ok(check("encode", len)? as usize)
+    }
+}
+
+impl Drop for Encoder {
+    fn drop(&mut self) {
+        unsafe { sys::opus_multistream_encoder_destroy(self.ptr) }
+    }
+}
+
+/// How much audio an encoded packet holds, read from the TOC of its first
+/// stream; `None` if it isn't an Opus packet.
+pub fn packet_duration(pkt: &[u8], sample_rate: u32) -> Option<std::time::Duration> {
+    if pkt.is_empty() {
+        return None;
+    }
+    let (per_frame, frames) = unsafe {
+        (
+            sys::opus_packet_get_samples_per_frame(pkt.as_ptr(), sample_rate as i32),
+            sys::opus_packet_get_nb_frames(pkt.as_ptr(), pkt.len() as i32),
+        )
+    };
+    let samples = u64::try_from(per_frame).ok()? * u64::try_from(frames).ok()?;
+    Some(std::time::Duration::from_micros(
+        samples * 1_000_000 / sample_rate as u64,
+    ))
+}
+
+pub struct Decoder {
+    ptr: *mut sys::OpusMSDecoder,
+    channels: usize,
+}
+
+unsafe impl Send for Decoder {}
+
+impl Decoder {
+    pub fn new(sample_rate: u32, layout: &StreamLayout) -> Result<Self> {
+        let mut err = 0;
+        let ptr = unsafe {
+            sys::opus_multistream_decoder_create(
+                sample_rate as i32,
+                layout.channels as c_int,
+                layout.streams as c_int,
+                layout.coupled_streams as c_int,
+                layout.mapping.as_ptr(),
+                &mut err,
+            )
+        };
+        check("decoder_create", err)?;
+        Ok(Self {
+            ptr,
+            channels: layout.channels as usize,
+        })
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Returns samples per channel written to `out` (interleaved, Vorbis
+    /// order).
+    pub fn decode_float(&mut self, packet: &[u8], out: &mut [f32], fec: bool) -> Result<usize> {
+        let n = unsafe {
+            sys::opus_multistream_decode_float(
+                self.ptr,
+                packet.as_ptr(),
+                packet.len() as i32,
+                out.as_mut_ptr(),
+                (out.len() / self.channels) as c_int,
+                fec as c_int,
+            )
+        };
+        Ok(check("decode", n)? as usize)
+    }
+
+    /// Packet loss concealment: fills `out` with a guess at the missing frame.
+    pub fn conceal(&mut self, out: &mut [f32]) -> Result<usize> {
+        let n = unsafe {
+            sys::opus_multistream_decode_float(
+                self.ptr,
+                std::ptr::null(),
+                0,
+                out.as_mut_ptr(),
+                (out.len() / self.channels) as c_int,
+                0,
+            )
+        };
+        Ok(check("conceal", n)? as usize)
+    }
+}
+
+impl Drop for Decoder {
+    fn drop(&mut self) {
+        unsafe { sys::opus_multistream_decoder_destroy(self.ptr) }
+    }
+}
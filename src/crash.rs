@@ -0,0 +1,155 @@
+// ─── Crash reports ─────────────────────────────────────────────────────────────
+// `install` replaces the panic hook for hosts that want a post‑mortem. A panic
+// on any thread or task then
+//   • tells keyed peers the call is over (a sealed Bye), so they don't sit
+//     waiting on a dead link,
+//   • writes `crash-<unix time>.txt` with the message, a backtrace, the
+//     session's configuration and audio devices, and the last minute of
+//     stats,
+//   • and exits, instead of running on without whatever panicked.
+//
+// The session feeds the report as it runs; none of this costs anything
+// until a panic.
+
+use crate::SessionConfig;
+use parking_lot::{const_mutex, Mutex};
+use std::backtrace::Backtrace;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::error;
+
+/// One stats line a second.
+const STATS_KEPT: usize = 60;
+/// The panicking thread may hold the state; don't wait on it for long.
+const LOCK_TIMEOUT: Duration = Duration::from_millis(200);
+const EXIT_CODE: i32 = 101;
+
+struct State {
+    config: Option<String>,
+    devices: Vec<String>,
+    stats: VecDeque<String>,
+    farewells: Vec<Box<dyn Fn() + Send>>,
+}
+
+static STATE: Mutex<State> = const_mutex(State {
+    config: None,
+    devices: Vec::new(),
+    stats: VecDeque::new(),
+    farewells: Vec::new(),
+});
+
+/// Writes crash reports to `dir` and exits the process on any panic.
+pub fn install(dir: impl Into<PathBuf>) {
+    let dir = dir.into();
+    std::panic::set_hook(Box::new(move |info| {
+        error!("panic occurred: {info}");
+        let panic = info.to_string();
+        let backtrace = Backtrace::force_capture();
+        let state = STATE.try_lock_for(LOCK_TIMEOUT);
+        if let Some(state) = &state {
+            for farewell in &state.farewells {
+                farewell();
+            }
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let path = dir.join(format!("crash-{now}.txt"));
+        let report = report(now, &panic, &backtrace, state.as_deref());
+        match write(&path, &report) {
+            Ok(()) => eprintln!("voice chat crashed; report in {}", path.display()),
+            Err(e) => eprintln!(
+                "voice chat crashed ({panic}); can't write {}: {e}",
+                path.display()
+            ),
+        }
+        std::process::exit(EXIT_CODE);
+    }));
+}
+
+fn write(path: &Path, report: &str) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, report)
+}
+
+fn report(now: u64, panic: &str, backtrace: &Backtrace, state: Option<&State>) -> String {
+    let mut out = String::new();
+    let thread = std::thread::current();
+    let _ = writeln!(out, "voice chat {} crash report", env!("CARGO_PKG_VERSION"));
+    let _ = writeln!(out, "time: {now} (unix)");
+    let _ = writeln!(
+        out,
+        "os: {} {}",
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    );
+    let _ = writeln!(out, "thread: {}", thread.name().unwrap_or("<unnamed>"));
+    let _ = writeln!(out, "panic: {panic}");
+    let _ = writeln!(out, "\n─── Backtrace ───\n{backtrace}");
+    let Some(state) = state else {
+        let _ = writeln!(out, "(session state was locked by the panicking thread)");
+        return out;
+    };
+    let config = state.config.as_deref().unwrap_or("(no session started)");
+    let _ = writeln!(out, "─── Configuration ───\n{config}");
+    let _ = writeln!(out, "─── Audio devices ───");
+    for device in &state.devices {
+        let _ = writeln!(out, "{device}");
+    }
+    let _ = writeln!(out, "\n─── Stats, oldest first ───");
+    for line in &state.stats {
+        let _ = writeln!(out, "{line}");
+    }
+    out
+}
+
+/// Records what a session was started with. Secrets (the signaling token,
+/// the group secret) are left out.
+pub(crate) fn session_started(config: &SessionConfig) {
+    let mut out = String::new();
+    let _ = writeln!(out, "local_port: {}", config.local_port);
+    let _ = writeln!(out, "peer: {:?}", config.peer);
+    let _ = writeln!(out, "signaling: {}", config.signaling.is_some());
+    let _ = writeln!(out, "dht: {}", config.dht.is_some());
+    let _ = writeln!(out, "manual: {}", config.manual.is_some());
+    let _ = writeln!(out, "rekey: {:?}", config.rekey);
+    let _ = writeln!(out, "audio: {:?}", config.audio);
+    let _ = writeln!(out, "encoder: {:?}", config.encoder);
+    let _ = writeln!(out, "jitter: {:?}", config.jitter);
+    let _ = writeln!(out, "record: {:?}", config.record);
+    let _ = writeln!(out, "replay_secs: {}", config.replay_secs);
+    let _ = writeln!(out, "source: {}", config.source.is_some());
+    let _ = writeln!(out, "sink: {}", config.sink.is_some());
+    let _ = writeln!(out, "broadcast: {:?}", config.broadcast);
+    let group = config.multicast.as_ref().map(|m| m.group);
+    let _ = writeln!(out, "multicast: {group:?}");
+    let _ = writeln!(out, "socket: {:?}", config.socket);
+    let mut state = STATE.lock();
+    state.config = Some(out);
+    state.stats.clear();
+}
+
+/// The audio devices now open, one line each.
+pub(crate) fn devices(devices: Vec<String>) {
+    STATE.lock().devices = devices;
+}
+
+/// Adds a line to the stats kept for the report.
+pub(crate) fn stats(line: String) {
+    let mut state = STATE.lock();
+    if state.stats.len() == STATS_KEPT {
+        state.stats.pop_front();
+    }
+    state.stats.push_back(line);
+}
+
+/// Runs `farewell` when the process panics. It must not block and must not
+/// lock anything the panicking thread could hold without a timeout.
+pub(crate) fn on_crash(farewell: impl Fn() + Send + 'static) {
+    STATE.lock().farewells.push(Box::new(farewell));
+}
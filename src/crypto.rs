@@ -0,0 +1,745 @@
+// ─── Key exchange ──────────────────────────────────────────────────────────────
+// Ephemeral X25519 handshake run directly over the media socket. Each side
+// sends its public key in a `Hello` packet; once both keys are known, both ends
+// derive the same secret and a short authentication string (SAS) from it.
+// Users read the SAS to each other: if someone on the signaling path swapped
+// the keys, the two strings will not match.
+//
+// Media is sealed with ChaCha20‑Poly1305. Each direction has its own hash
+// ratchet: epoch N+1's chain key is derived from epoch N's and the old one is
+// overwritten, so a key captured mid‑call cannot decrypt earlier epochs. The
+// epoch number travels in every media header as the rollover marker.
+//
+// A handshake can also mix in a pre‑shared key (`with_psk`, from a room
+// passphrase): then only someone who knows the passphrase derives the same
+// media keys, even if the signaling server handed out the wrong public key.
+
+use crate::packet::{Packet, Sealed, MEDIA_HEADER_LEN};
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305};
+use ring::agreement::{self, EphemeralPrivateKey, UnparsedPublicKey, X25519};
+use ring::hkdf;
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{hmac, pbkdf2, signature};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::num::NonZeroU32;
+use std::time::{Duration, Instant};
+
+pub const PUBLIC_KEY_LEN: usize = 32;
+const SAS_LEN: usize = 5; // 5 symbols × 6 bits = 30 bits
+const HKDF_SALT: &[u8] = b"audio-p2p/v1";
+const CHAIN_LEN: usize = 32;
+// How many epochs the receiver will ratchet forward in one go, e.g. when the
+// rollover packets themselves were lost.
+const MAX_EPOCH_SKIP: u8 = 4;
+// How long the previous epoch's key is kept for late/reordered packets.
+const PREVIOUS_KEY_GRACE: Duration = Duration::from_secs(2);
+/// PBKDF2 rounds for room passphrases; a guess has to pay this each time.
+const ROOM_KEY_ROUNDS: u32 = 100_000;
+
+pub struct Handshake {
+    private: EphemeralPrivateKey,
+    public: [u8; PUBLIC_KEY_LEN],
+    psk: Option<[u8; PSK_LEN]>,
+}
+
+impl Handshake {
+    pub fn new() -> Result<Self> {
+        let rng = SystemRandom::new();
+        let private = EphemeralPrivateKey::generate(&X25519, &rng)
+            .map_err(|_| anyhow!("failed to generate X25519 key"))?;
+        let mut public = [0u8; PUBLIC_KEY_LEN];
+        public.copy_from_slice(
+            private
+                .compute_public_key()
+                .map_err(|_| anyhow!("failed to compute X25519 public key"))?
+                .as_ref(),
+        );
+        Ok(Self {
+            private,
+            public,
+            psk: None,
+        })
+    }
+
+    /// Mixes `psk` into the session secrets; the peer must use the same one.
+    pub fn with_psk(mut self, psk: Option<[u8; PSK_LEN]>) -> Self {
+        self.psk = psk;
+        self
+    }
+
+    pub fn public_key(&self) -> [u8; PUBLIC_KEY_LEN] {
+        self.public
+    }
+
+    /// Consumes the ephemeral key and derives the session secrets.
+    pub fn complete(
+        self,
+        peer_public: &[u8; PUBLIC_KEY_LEN],
+        rekey: RekeyPolicy,
+    ) -> Result<Session> {
+        if *peer_public == self.public {
+            // Our own Hello reflected back at us.
+            return Err(anyhow!("peer sent our own public key"));
+        }
+        // Order the keys so both sides feed HKDF the same transcript.
+        let we_are_lo = self.public < *peer_public;
+        let (lo, hi) = if we_are_lo {
+            (self.public, *peer_public)
+        } else {
+            (*peer_public, self.public)
+        };
+        let peer = UnparsedPublicKey::new(&X25519, peer_public);
+        let psk = self.psk;
+        agreement::agree_ephemeral(self.private, &peer, |shared| {
+            let ikm = [shared, psk.as_ref().map_or(&[][..], |k| &k[..])].concat();
+            let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, HKDF_SALT).extract(&ikm);
+            let mut sas = [0u8; SAS_LEN];
+            expand(&prk, &[b"sas", &lo, &hi], &mut sas)?;
+            let mut lo_to_hi = [0u8; CHAIN_LEN];
+            expand(&prk, &[b"media lo->hi", &lo, &hi], &mut lo_to_hi)?;
+            let mut hi_to_lo = [0u8; CHAIN_LEN];
+            expand(&prk, &[b"media hi->lo", &lo, &hi], &mut hi_to_lo)?;
+            let (tx, rx) = if we_are_lo {
+                (lo_to_hi, hi_to_lo)
+            } else {
+                (hi_to_lo, lo_to_hi)
+            };
+            Ok(Session {
+                peer_key: *peer_public,
+                sas: Sas(sas.map(|b| b & 0x3f)),
+                sealer: Sealer::new(Ratchet::new(tx), rekey)?,
+                opener: Opener::new(Ratchet::new(rx))?,
+            })
+        })
+        .map_err(|_| anyhow!("X25519 key agreement failed"))?
+    }
+}
+
+/// Hex form used for keys in signaling payloads.
+pub fn key_to_hex(key: &[u8; PUBLIC_KEY_LEN]) -> String {
+    hex(key)
+}
+
+pub fn key_from_hex(s: &str) -> Option<[u8; PUBLIC_KEY_LEN]> {
+    if s.len() != PUBLIC_KEY_LEN * 2 {
+        return None;
+    }
+    let mut key = [0u8; PUBLIC_KEY_LEN];
+    for (i, b) in key.iter_mut().enumerate() {
+        *b = u8::from_str_radix(s.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(key)
+}
+
+pub struct Session {
+    pub peer_key: [u8; PUBLIC_KEY_LEN],
+    pub sas: Sas,
+    pub sealer: Sealer,
+    pub opener: Opener,
+}
+
+// ─── Group keys ────────────────────────────────────────────────────────────────
+// Multicast has no handshake: every member derives a sender's key from the
+// shared group secret and the random salt the sender announces in its Hello.
+// Anyone holding the secret can listen and send, and there is no SAS, so this
+// is only for trusted LANs. The ratchet doesn't roll on a schedule, so members
+// can join at any point in the stream.
+
+/// A fresh salt for a sender's group key.
+pub fn group_salt() -> Result<[u8; PUBLIC_KEY_LEN]> {
+    let mut salt = [0u8; PUBLIC_KEY_LEN];
+    ring::rand::SecureRandom::fill(&SystemRandom::new(), &mut salt)
+        .map_err(|_| anyhow!("no randomness for the group salt"))?;
+    Ok(salt)
+}
+
+pub fn group_sealer(secret: &[u8], salt: &[u8; PUBLIC_KEY_LEN]) -> Result<Sealer> {
+    let never = RekeyPolicy {
+        interval: None,
+        packets: None,
+    };
+    Sealer::new(Ratchet::new(group_chain(secret, salt)?), never)
+}
+
+pub fn group_opener(secret: &[u8], salt: &[u8; PUBLIC_KEY_LEN]) -> Result<Opener> {
+    Opener::new(Ratchet::new(group_chain(secret, salt)?))
+}
+
+fn group_chain(secret: &[u8], salt: &[u8; PUBLIC_KEY_LEN]) -> Result<[u8; CHAIN_LEN]> {
+    let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, HKDF_SALT).extract(secret);
+    let mut chain = [0u8; CHAIN_LEN];
+    expand(&prk, &[b"group", salt], &mut chain)?;
+    Ok(chain)
+}
+
+// ─── Room passphrases ──────────────────────────────────────────────────────────
+// A signaling room can require a passphrase. Everyone derives the same
+// `RoomKey` from it (PBKDF2, salted with the room name) and
+//   • sends the server a `tag`, so it can turn away joins whose tag differs
+//     from the room's first member. The tag is fixed for a room and
+//     passphrase, so whoever runs the server can guess passphrases against
+//     it offline, held back only by the PBKDF2 rounds: pick a long one, or
+//     use a server you trust,
+//   • proves to the other members that it knows the passphrase with an HMAC
+//     over its handshake key and address (`proof`),
+//   • optionally feeds `psk` into the media handshake.
+
+pub const PSK_LEN: usize = 32;
+
+#[derive(Clone)]
+pub struct RoomKey([u8; 32]);
+
+impl RoomKey {
+    pub fn derive(room: &str, passphrase: &str) -> Self {
+        let rounds = NonZeroU32::new(ROOM_KEY_ROUNDS).expect("non‑zero");
+        let salt = [HKDF_SALT, b"/room/", room.as_bytes()].concat();
+        let mut key = [0u8; 32];
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            rounds,
+            &salt,
+            passphrase.as_bytes(),
+            &mut key,
+        );
+        Self(key)
+    }
+
+    fn hmac_key(&self) -> hmac::Key {
+        hmac::Key::new(hmac::HMAC_SHA256, &self.0)
+    }
+
+    /// What the server compares between members. A fixed verifier of the
+    /// passphrase: whoever sees it can test guesses offline at PBKDF2's cost.
+    pub fn tag(&self) -> String {
+        hex(hmac::sign(&self.hmac_key(), b"tag").as_ref())
+    }
+
+    /// Proof that whoever joined with `pub_key` from `addr` knows the
+    /// passphrase.
+    pub fn proof(&self, pub_key: &str, addr: &str) -> String {
+        hex(hmac::sign(&self.hmac_key(), &join_message(pub_key, addr)).as_ref())
+    }
+
+    pub fn verify(&self, pub_key: &str, addr: &str, proof: &str) -> bool {
+        unhex(proof).is_some_and(|tag| {
+            hmac::verify(&self.hmac_key(), &join_message(pub_key, addr), &tag).is_ok()
+        })
+    }
+
+    /// Key material for `Handshake::with_psk`.
+    pub fn psk(&self) -> [u8; PSK_LEN] {
+        let prk = hkdf::Prk::new_less_safe(hkdf::HKDF_SHA256, &self.0);
+        let mut psk = [0u8; PSK_LEN];
+        expand(&prk, &[b"media psk"], &mut psk).expect("32 bytes fit HKDF‑SHA256");
+        psk
+    }
+}
+
+/// Length‑prefixed, so ("ab", "c") and ("a", "bc") differ.
+fn join_message(pub_key: &str, addr: &str) -> Vec<u8> {
+    let mut msg = b"join".to_vec();
+    for part in [pub_key.as_bytes(), addr.as_bytes()] {
+        msg.extend_from_slice(&(part.len() as u32).to_be_bytes());
+        msg.extend_from_slice(part);
+    }
+    msg
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// ─── Host keys ─────────────────────────────────────────────────────────────────
+// Whoever runs a multicast group can moderate it (see `moderation`). Their
+// Ed25519 `HostKey` is derived from a passphrase only they know, so it stays
+// the same from one session to the next; members are given the public half
+// and check every order against it.
+
+pub const SIGNATURE_LEN: usize = 64;
+
+pub struct HostKey(signature::Ed25519KeyPair);
+
+impl HostKey {
+    pub fn derive(passphrase: &str) -> Self {
+        let rounds = NonZeroU32::new(ROOM_KEY_ROUNDS).expect("non‑zero");
+        let salt = [HKDF_SALT, b"/host"].concat();
+        let mut seed = [0u8; 32];
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            rounds,
+            &salt,
+            passphrase.as_bytes(),
+            &mut seed,
+        );
+        Self(signature::Ed25519KeyPair::from_seed_unchecked(&seed).expect("32‑byte seed"))
+    }
+
+    /// What members pass as `--moderator`, in `key_to_hex` form.
+    pub fn public(&self) -> [u8; PUBLIC_KEY_LEN] {
+        use signature::KeyPair;
+        self.0
+            .public_key()
+            .as_ref()
+            .try_into()
+            .expect("32‑byte Ed25519 key")
+    }
+
+    pub fn sign(&self, msg: &[u8]) -> [u8; SIGNATURE_LEN] {
+        self.0
+            .sign(msg)
+            .as_ref()
+            .try_into()
+            .expect("64‑byte Ed25519 signature")
+    }
+}
+
+impl fmt::Debug for HostKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HostKey({})", hex(&self.public()))
+    }
+}
+
+/// Whether `sig` over `msg` was made with the host key whose public half is
+/// `host`.
+pub fn host_signed(host: &[u8; PUBLIC_KEY_LEN], msg: &[u8], sig: &[u8]) -> bool {
+    signature::UnparsedPublicKey::new(&signature::ED25519, host)
+        .verify(msg, sig)
+        .is_ok()
+}
+
+// ─── Media encryption ──────────────────────────────────────────────────────────
+#[derive(Clone, Copy, Debug)]
+pub struct RekeyPolicy {
+    /// Roll the send key after this long; `None` disables time‑based rekeying.
+    pub interval: Option<Duration>,
+    /// Roll the send key after this many packets; `None` disables it.
+    pub packets: Option<u32>,
+}
+
+impl Default for RekeyPolicy {
+    fn default() -> Self {
+        Self {
+            interval: Some(Duration::from_secs(300)),
+            packets: None,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Ratchet {
+    chain: [u8; CHAIN_LEN],
+    epoch: u8,
+}
+
+impl Ratchet {
+    fn new(chain: [u8; CHAIN_LEN]) -> Self {
+        Self { chain, epoch: 0 }
+    }
+
+    fn key(&self) -> Result<LessSafeKey> {
+        let prk = hkdf::Prk::new_less_safe(hkdf::HKDF_SHA256, &self.chain);
+        let okm = prk
+            .expand(&[b"key"], &CHACHA20_POLY1305)
+            .map_err(|_| anyhow!("HKDF expand failed"))?;
+        Ok(LessSafeKey::new(UnboundKey::from(okm)))
+    }
+
+    /// Steps to the next epoch, overwriting the old chain key.
+    fn advance(&mut self) -> Result<()> {
+        let prk = hkdf::Prk::new_less_safe(hkdf::HKDF_SHA256, &self.chain);
+        expand(&prk, &[b"chain"], &mut self.chain)?;
+        self.epoch = self.epoch.wrapping_add(1);
+        Ok(())
+    }
+}
+
+fn nonce(seq: u32) -> Nonce {
+    let mut n = [0u8; aead::NONCE_LEN];
+    n[aead::NONCE_LEN - 4..].copy_from_slice(&seq.to_be_bytes());
+    Nonce::assume_unique_for_key(n)
+}
+
+pub struct Sealer {
+    ratchet: Ratchet,
+    key: LessSafeKey,
+    seq: u32,
+    epoch_started: Instant,
+    rekey: RekeyPolicy,
+}
+
+impl Sealer {
+    fn new(ratchet: Ratchet, rekey: RekeyPolicy) -> Result<Self> {
+        Ok(Self {
+            key: ratchet.key()?,
+            ratchet,
+            seq: 0,
+            epoch_started: Instant::now(),
+            rekey,
+        })
+    }
+
+    fn due(&self) -> bool {
+        self.seq == u32::MAX
+            || self.rekey.packets.is_some_and(|n| self.seq >= n)
+            || self
+                .rekey
+                .interval
+                .is_some_and(|d| self.epoch_started.elapsed() >= d)
+    }
+
+    /// Encrypts one Opus frame (or silence marker) into a complete datagram.
+    pub fn seal(&mut self, kind: Sealed, body: &[u8]) -> Result<Bytes> {
+        if self.due() {
+            self.ratchet.advance()?;
+            self.key = self.ratchet.key()?;
+            self.seq = 0;
+            self.epoch_started = Instant::now();
+            tracing::info!("rekeyed send path to epoch {}", self.ratchet.epoch);
+        }
+        let (epoch, seq) = (self.ratchet.epoch, self.seq);
+        self.seq += 1;
+        let mut payload = body.to_vec();
+        self.key
+            .seal_in_place_append_tag(
+                nonce(seq),
+                Aad::from(Packet::sealed_header(kind, epoch, seq)),
+                &mut payload,
+            )
+            .map_err(|_| anyhow!("media encryption failed"))?;
+        Ok(Packet::Sealed {
+            kind,
+            epoch,
+            seq,
+            payload: &payload,
+        }
+        .encode())
+    }
+}
+
+pub struct Opener {
+    ratchet: Ratchet,
+    key: LessSafeKey,
+    previous: Option<(u8, LessSafeKey, Instant)>,
+}
+
+impl Opener {
+    fn new(ratchet: Ratchet) -> Result<Self> {
+        Ok(Self {
+            key: ratchet.key()?,
+            ratchet,
+            previous: None,
+        })
+    }
+
+    /// Authenticates and decrypts a sealed payload, following the sender's
+    /// rollover if `epoch` is ahead of ours. Returns `None` for anything that
+    /// does not authenticate.
+    pub fn open(&mut self, kind: Sealed, epoch: u8, seq: u32, payload: &[u8]) -> Option<Vec<u8>> {
+        if self
+            .previous
+            .as_ref()
+            .is_some_and(|(_, _, since)| since.elapsed() > PREVIOUS_KEY_GRACE)
+        {
+            self.previous = None;
+        }
+        let aad = Packet::sealed_header(kind, epoch, seq);
+        let buf = payload.to_vec();
+        let ahead = epoch.wrapping_sub(self.ratchet.epoch);
+        if ahead == 0 {
+            return open_with(&self.key, seq, aad, buf);
+        }
+        if let Some((prev_epoch, prev_key, _)) = &self.previous {
+            if *prev_epoch == epoch {
+                return open_with(prev_key, seq, aad, buf);
+            }
+        }
+        if ahead > MAX_EPOCH_SKIP {
+            return None;
+        }
+        // Only commit to the new epoch once a packet authenticates under it,
+        // so forged headers cannot push the ratchet forward.
+        let mut next = self.ratchet.clone();
+        for _ in 0..ahead {
+            next.advance().ok()?;
+        }
+        let next_key = next.key().ok()?;
+        let plain = open_with(&next_key, seq, aad, buf)?;
+        tracing::info!("peer rekeyed to epoch {epoch}");
+        let old_key = std::mem::replace(&mut self.key, next_key);
+        self.previous = Some((self.ratchet.epoch, old_key, Instant::now()));
+        self.ratchet = next;
+        Some(plain)
+    }
+}
+
+fn open_with(
+    key: &LessSafeKey,
+    seq: u32,
+    aad: [u8; MEDIA_HEADER_LEN],
+    mut buf: Vec<u8>,
+) -> Option<Vec<u8>> {
+    let len = key
+        .open_in_place(nonce(seq), Aad::from(aad), &mut buf)
+        .ok()?
+        .len();
+    buf.truncate(len);
+    Some(buf)
+}
+
+fn expand(prk: &hkdf::Prk, info: &[&[u8]], out: &mut [u8]) -> Result<()> {
+    prk.expand(info, Len(out.len()))
+        .and_then(|okm| okm.fill(out))
+        .map_err(|_| anyhow!("HKDF expand failed"))
+}
+
+struct Len(usize);
+
+impl hkdf::KeyType for Len {
+    fn len(&self) -> usize {
+        self.0
+    }
+}
+
+// ─── Recordings at rest ────────────────────────────────────────────────────────
+// A recording can be sealed with a passphrase so a copied archive is just
+// noise. The file starts with `FILE_MAGIC` and a random salt; PBKDF2 turns the
+// passphrase and salt into a ChaCha20‑Poly1305 key used for this file only.
+// The plaintext follows in `FILE_CHUNK` pieces, each sealed on its own with
+// its number in the nonce and a flag on the last one, so a file cut short or
+// with chunks swapped fails to open instead of decrypting to something else.
+
+const FILE_MAGIC: &[u8; 8] = b"VCREC1\n\0";
+const FILE_SALT_LEN: usize = 16;
+const FILE_CHUNK: usize = 64 * 1024;
+const FILE_TAG_LEN: usize = 16;
+
+fn file_key(passphrase: &str, salt: &[u8]) -> Result<LessSafeKey> {
+    let rounds = NonZeroU32::new(ROOM_KEY_ROUNDS).expect("non‑zero");
+    let salt = [HKDF_SALT, b"/file/", salt].concat();
+    let mut key = [0u8; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        rounds,
+        &salt,
+        passphrase.as_bytes(),
+        &mut key,
+    );
+    let key = UnboundKey::new(&CHACHA20_POLY1305, &key)
+        .map_err(|_| anyhow!("invalid ChaCha20‑Poly1305 key"))?;
+    Ok(LessSafeKey::new(key))
+}
+
+fn file_nonce(chunk: u32, last: bool) -> Nonce {
+    let mut n = [0u8; aead::NONCE_LEN];
+    n[aead::NONCE_LEN - 5..aead::NONCE_LEN - 1].copy_from_slice(&chunk.to_be_bytes());
+    n[aead::NONCE_LEN - 1] = last as u8;
+    Nonce::assume_unique_for_key(n)
+}
+
+/// Seals whatever is written to it into `out`. `finish` must be called, or
+/// the file won't open.
+pub struct FileSealer<W: Write> {
+    out: W,
+    key: LessSafeKey,
+    buf: Vec<u8>,
+    chunk: u32,
+}
+
+impl<W: Write> FileSealer<W> {
+    pub fn new(mut out: W, passphrase: &str) -> Result<Self> {
+        let mut salt = [0u8; FILE_SALT_LEN];
+        SystemRandom::new()
+            .fill(&mut salt)
+            .map_err(|_| anyhow!("no randomness for the file salt"))?;
+        out.write_all(FILE_MAGIC)?;
+        out.write_all(&salt)?;
+        Ok(Self {
+            out,
+            key: file_key(passphrase, &salt)?,
+            buf: Vec::with_capacity(FILE_CHUNK + FILE_TAG_LEN),
+            chunk: 0,
+        })
+    }
+
+    fn seal_chunk(&mut self, last: bool) -> io::Result<()> {
+        let nonce = file_nonce(self.chunk, last);
+        self.key
+            .seal_in_place_append_tag(nonce, Aad::empty(), &mut self.buf)
+            .map_err(|_| io::Error::other("sealing failed"))?;
+        self.out.write_all(&self.buf)?;
+        self.buf.clear();
+        self.chunk = self
+            .chunk
+            .checked_add(1)
+            .ok_or_else(|| io::Error::other("file too long to seal"))?;
+        Ok(())
+    }
+
+    /// Seals the last chunk and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.seal_chunk(true)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+impl<W: Write> Write for FileSealer<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        // A full chunk waits for more data, so the last one is never empty
+        // unless the file is.
+        if self.buf.len() == FILE_CHUNK {
+            self.seal_chunk(false)?;
+        }
+        let n = data.len().min(FILE_CHUNK - self.buf.len());
+        self.buf.extend_from_slice(&data[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Opens a file `FileSealer` wrote, writing the plaintext to `out`.
+pub fn open_file(passphrase: &str, mut input: impl Read, mut out: impl Write) -> Result<()> {
+    let mut head = [0u8; FILE_MAGIC.len() + FILE_SALT_LEN];
+    input
+        .read_exact(&mut head)
+        .map_err(|_| anyhow!("not a sealed recording"))?;
+    if &head[..FILE_MAGIC.len()] != FILE_MAGIC {
+        return Err(anyhow!("not a sealed recording"));
+    }
+    let key = file_key(passphrase, &head[FILE_MAGIC.len()..])?;
+    let mut cur = vec![0u8; FILE_CHUNK + FILE_TAG_LEN];
+    let mut next = vec![0u8; FILE_CHUNK + FILE_TAG_LEN];
+    let mut len = read_full(&mut input, &mut cur)?;
+    for chunk in 0u32.. {
+        // Only a full chunk can have another after it.
+        let next_len = match len == cur.len() {
+            true => read_full(&mut input, &mut next)?,
+            false => 0,
+        };
+        let last = next_len == 0;
+        let plain = key
+            .open_in_place(file_nonce(chunk, last), Aad::empty(), &mut cur[..len])
+            .map_err(|_| anyhow!("wrong passphrase, or the file is damaged"))?;
+        out.write_all(plain)?;
+        if last {
+            break;
+        }
+        std::mem::swap(&mut cur, &mut next);
+        len = next_len;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Reads until `buf` is full or the input ends.
+fn read_full(input: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match input.read(&mut buf[n..])? {
+            0 => break,
+            k => n += k,
+        }
+    }
+    Ok(n)
+}
+
+// ─── Short authentication string ───────────────────────────────────────────────
+// Same 64-symbol table as Matrix' emoji verification, so the words are easy to
+// read out over a bad line.
+pub struct Sas([u8; SAS_LEN]);
+
+const SAS_SYMBOLS: [(&str, &str); 64] = [
+    ("🐶", "Dog"),
+    ("🐱", "Cat"),
+    ("🦁", "Lion"),
+    ("🐎", "Horse"),
+    ("🦄", "Unicorn"),
+    ("🐷", "Pig"),
+    ("🐘", "Elephant"),
+    ("🐰", "Rabbit"),
+    ("🐼", "Panda"),
+    ("🐓", "Rooster"),
+    ("🐧", "Penguin"),
+    ("🐢", "Turtle"),
+    ("🐟", "Fish"),
+    ("🐙", "Octopus"),
+    ("🦋", "Butterfly"),
+    ("🌷", "Flower"),
+    ("🌳", "Tree"),
+    ("🌵", "Cactus"),
+    ("🍄", "Mushroom"),
+    ("🌏", "Globe"),
+    ("🌙", "Moon"),
+    ("☁️", "Cloud"),
+    ("🔥", "Fire"),
+    ("🍌", "Banana"),
+    ("🍎", "Apple"),
+    ("🍓", "Strawberry"),
+    ("🌽", "Corn"),
+    ("🍕", "Pizza"),
+    ("🎂", "Cake"),
+    ("❤️", "Heart"),
+    ("😀", "Smiley"),
+    ("🤖", "Robot"),
+    ("🎩", "Hat"),
+    ("👓", "Glasses"),
+    ("🔧", "Spanner"),
+    ("🎅", "Santa"),
+    ("👍", "Thumbs Up"),
+    ("☂️", "Umbrella"),
+    ("⌛", "Hourglass"),
+    ("⏰", "Clock"),
+    ("🎁", "Gift"),
+    ("💡", "Light Bulb"),
+    ("📕", "Book"),
+    ("✏️", "Pencil"),
+    ("📎", "Paperclip"),
+    ("✂️", "Scissors"),
+    ("🔒", "Lock"),
+    ("🔑", "Key"),
+    ("🔨", "Hammer"),
+    ("☎️", "Telephone"),
+    ("🏁", "Flag"),
+    ("🚂", "Train"),
+    ("🚲", "Bicycle"),
+    ("✈️", "Aeroplane"),
+    ("🚀", "Rocket"),
+    ("🏆", "Trophy"),
+    ("⚽", "Ball"),
+    ("🎸", "Guitar"),
+    ("🎺", "Trumpet"),
+    ("🔔", "Bell"),
+    ("⚓", "Anchor"),
+    ("🎧", "Headphones"),
+    ("📁", "Folder"),
+    ("📌", "Pin"),
+];
+
+impl fmt::Display for Sas {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, &idx) in self.0.iter().enumerate() {
+            let (emoji, word) = SAS_SYMBOLS[idx as usize];
+            if i > 0 {
+                f.write_str("  ")?;
+            }
+            write!(f, "{emoji} {word}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+// ─── Audio cues ────────────────────────────────────────────────────────────────
+// Short earcons for things that happen in a call without anyone saying so: a
+// peer joining or leaving, our own mute toggling, the call coming back after a
+// network change. They are built from `source::Tone` notes, played locally
+// only and mixed into playout at 48 kHz before the echo reference, so the
+// canceller keeps them out of what we send. Each kind can be turned on or off
+// on its own through `effects::Controls::cues`; all are off unless asked for.
+
+use crate::source::{AudioSource, Tone};
+use anyhow::{bail, Result};
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::Arc;
+
+/// Below the −12 dBFS tone source: cues shouldn't cover speech.
+const LEVEL: f32 = 0.5;
+/// Fade in and out of each note, in samples, so they don't click.
+const FADE: usize = 48 * 5;
+/// Cues waiting to play; anything beyond is dropped.
+const MAX_QUEUED: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cue {
+    Join,
+    Leave,
+    Mute,
+    Unmute,
+    Reconnect,
+}
+
+impl Cue {
+    pub const ALL: [Cue; 5] = [
+        Cue::Join,
+        Cue::Leave,
+        Cue::Mute,
+        Cue::Unmute,
+        Cue::Reconnect,
+    ];
+
+    /// (Hz, ms) per note; 0 Hz is a rest.
+    fn notes(self) -> &'static [(f32, usize)] {
+        match self {
+            Cue::Join => &[(660.0, 80), (880.0, 120)],
+            Cue::Leave => &[(880.0, 80), (660.0, 120)],
+            Cue::Mute => &[(440.0, 70)],
+            Cue::Unmute => &[(660.0, 70)],
+            Cue::Reconnect => &[(880.0, 50), (0.0, 40), (880.0, 50), (0.0, 40), (1175.0, 80)],
+        }
+    }
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+impl FromStr for Cue {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "join" => Cue::Join,
+            "leave" => Cue::Leave,
+            "mute" => Cue::Mute,
+            "unmute" => Cue::Unmute,
+            "reconnect" => Cue::Reconnect,
+            _ => bail!("expected join, leave, mute, unmute or reconnect"),
+        })
+    }
+}
+
+/// Which cues play, and the ones waiting to.
+#[derive(Debug, Default)]
+pub struct Cues {
+    enabled: AtomicU8,
+    pending: AtomicBool,
+    queue: Mutex<VecDeque<Cue>>,
+}
+
+impl Cues {
+    pub fn set_enabled(&self, cue: Cue, enabled: bool) {
+        match enabled {
+            true => self.enabled.fetch_or(cue.bit(), Ordering::Relaxed),
+            false => self.enabled.fetch_and(!cue.bit(), Ordering::Relaxed),
+        };
+    }
+
+    pub fn enabled(&self, cue: Cue) -> bool {
+        self.enabled.load(Ordering::Relaxed) & cue.bit() != 0
+    }
+
+    /// Queues `cue` unless it is turned off.
+    pub(crate) fn play(&self, cue: Cue) {
+        if !self.enabled(cue) {
+            return;
+        }
+        let mut queue = self.queue.lock();
+        if queue.len() < MAX_QUEUED {
+            queue.push_back(cue);
+            self.pending.store(true, Ordering::Release);
+        }
+    }
+
+    fn next(&self) -> Option<Cue> {
+        if !self.pending.load(Ordering::Acquire) {
+            return None;
+        }
+        // Never wait on the lock from the audio callback.
+        let mut queue = self.queue.try_lock()?;
+        let cue = queue.pop_front();
+        self.pending.store(!queue.is_empty(), Ordering::Release);
+        cue
+    }
+}
+
+/// Renders queued cues, one 48 kHz sample at a time.
+pub(crate) struct Player {
+    cues: Arc<Cues>,
+    notes: &'static [(f32, usize)],
+    tone: Option<Tone>,
+    /// Position in and length of the current note.
+    pos: usize,
+    len: usize,
+}
+
+impl Player {
+    pub fn new(cues: Arc<Cues>) -> Self {
+        Self {
+            cues,
+            notes: &[],
+            tone: None,
+            pos: 0,
+            len: 0,
+        }
+    }
+
+    pub fn next(&mut self) -> f32 {
+        if self.pos == self.len && !self.advance() {
+            return 0.0;
+        }
+        let mut s = 0.0;
+        if let Some(tone) = &mut self.tone {
+            tone.read(std::slice::from_mut(&mut s));
+        }
+        let edge = self.pos.min(self.len - self.pos).min(FADE);
+        self.pos += 1;
+        s * LEVEL * edge as f32 / FADE as f32
+    }
+
+    /// Starts the next note, of this cue or the next one; false if none.
+    fn advance(&mut self) -> bool {
+        if self.notes.is_empty() {
+            match self.cues.next() {
+                Some(cue) => self.notes = cue.notes(),
+                None => return false,
+            }
+        }
+        let (&(hz, ms), rest) = self.notes.split_first().expect("cue has notes");
+        self.notes = rest;
+        self.tone = (hz > 0.0).then(|| Tone::new(hz));
+        self.pos = 0;
+        self.len = ms * 48;
+        true
+    }
+}
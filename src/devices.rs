@@ -0,0 +1,463 @@
+// ─── Host & device selection ───────────────────────────────────────────────────
+// Picks the cpal host (platform default, or one named with `--host`), the
+// input/output devices on it (default, or chosen by name) and the stream
+// configs, optionally with a fixed hardware buffer size. ASIO drivers in
+// particular want an explicit driver and buffer choice to get low latency.
+//
+// JACK has no real devices: cpal creates one JACK client per direction. We
+// create them ourselves so they carry our client name (ports show up as
+// `voice-chat_in:in_N` / `voice-chat_out:out_N` in a patchbay) and so
+// auto‑connecting to `system:capture_*` / `system:playback_*` can be turned off
+// when the ports are wired up by a session manager instead.
+//
+// Windows keeps two defaults per direction: the plain ("console") one and the
+// default communications device, which is where headsets usually end up.
+// Calls use the communications endpoints unless told otherwise, found through
+// the MMDevice API (cpal only knows the console role) and matched to cpal's
+// devices by name. The audio thread reopens the streams when the user or the
+// OS moves the role to another device.
+//
+// PipeWire: `--host pipewire` is not a PipeWire client of our own. It opens
+// the `pipewire` PCM from pipewire‑alsa on the ALSA host; that plugin creates
+// the PipeWire streams (no pulse layer in between) and applies
+// `PIPEWIRE_PROPS` to them, which we use to tag the call with
+// media.role=Communication so volume controls and ducking treat it as one.
+// cpal only opens the ALSA devices it enumerates, so the properties can't go
+// in the device string; the variable is set instead. `select_host` sets it
+// when the host is picked, so every library path tags the call, but by then
+// other threads may be running, and C code reading the environment at that
+// moment races with the write; `prepare_host` sets it before there are any
+// and is what a binary should call first. A native client (pipewire‑rs)
+// would need libpipewire and bindgen at build time and a stream backend
+// beside cpal's, for the same tags.
+
+use anyhow::{Context, Result};
+use cpal::traits::*;
+
+#[derive(Clone, Debug)]
+pub struct AudioOptions {
+    /// cpal host name, e.g. "ALSA", "JACK", "WASAPI" (case‑insensitive).
+    pub host: Option<String>,
+    /// Device names (exact, else case‑insensitive substring); `None` uses the
+    /// host default.
+    pub input_device: Option<String>,
+    /// A second input device mixed into the first, e.g. a loopback of
+    /// desktop audio (see `mixer::Bridge`).
+    pub extra_input: Option<String>,
+    pub output_device: Option<String>,
+    /// A second output device that plays the same as the first (see
+    /// `mixer::Bridge`).
+    pub mirror_device: Option<String>,
+    /// Play into a virtual audio cable, found by name, as well as or
+    /// instead of the output device.
+    pub virtual_cable: Option<CableRouting>,
+    /// Fixed hardware buffer size in frames; `None` lets the host decide.
+    pub buffer_frames: Option<u32>,
+    /// Channels to send: 1 is voice (APM, effects); 2 is stereo (APM only);
+    /// more is surround.
+    pub send_channels: u8,
+    /// Preferred Opus frame duration; see `codec::FRAME_MS_OPTIONS`.
+    pub frame_ms: u8,
+    /// JACK client name.
+    pub jack_client_name: String,
+    /// Connect JACK ports to the system capture/playback ports.
+    pub jack_autoconnect: bool,
+    /// Open an output device; off for send‑only peers.
+    pub playback: bool,
+    /// On Windows, default to the communications devices rather than the
+    /// plain defaults.
+    pub communications: bool,
+    /// Stop processing and encoding the input after this long without
+    /// speech, until it gets louder; `None` processes every frame.
+    pub idle_after: Option<std::time::Duration>,
+}
+
+/// Where a virtual cable goes relative to the output device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CableRouting {
+    /// As the mirror device.
+    Also,
+    /// As the output device.
+    Instead,
+}
+
+/// Names virtual cables give their playback end: VB‑Cable and VoiceMeeter
+/// on Windows, BlackHole on macOS, snd‑aloop on Linux. A PulseAudio or
+/// PipeWire null sink isn't an ALSA device; load snd‑aloop instead.
+const VIRTUAL_CABLES: &[&str] = &["CABLE Input", "VoiceMeeter Input", "BlackHole", "Loopback"];
+
+impl AudioOptions {
+    /// Whether a second output device plays the call.
+    pub fn mirrors(&self) -> bool {
+        self.playback
+            && (self.mirror_device.is_some() || self.virtual_cable == Some(CableRouting::Also))
+    }
+}
+
+impl Default for AudioOptions {
+    fn default() -> Self {
+        Self {
+            host: None,
+            input_device: None,
+            extra_input: None,
+            output_device: None,
+            mirror_device: None,
+            virtual_cable: None,
+            buffer_frames: None,
+            send_channels: 1,
+            frame_ms: crate::codec::DEFAULT_FRAME_MS,
+            jack_client_name: "voice-chat".into(),
+            jack_autoconnect: true,
+            playback: true,
+            communications: true,
+            idle_after: Some(std::time::Duration::from_secs(3)),
+        }
+    }
+}
+
+/// Process‑wide setup the host `name` needs. It changes the environment, so
+/// call it before starting any threads (the Tokio runtime included);
+/// `select_host` does the same, later, for callers that don't.
+pub fn prepare_host(name: Option<&str>) {
+    #[cfg(target_os = "linux")]
+    if name.is_some_and(is_pipewire) {
+        tag_pipewire_streams();
+    }
+    let _ = name;
+}
+
+pub fn select_host(name: Option<&str>) -> Result<cpal::Host> {
+    #[cfg(target_os = "linux")]
+    if name.is_some_and(is_pipewire) {
+        tag_pipewire_streams();
+        return Ok(cpal::host_from_id(cpal::HostId::Alsa)?);
+    }
+    if let Some(name) = name {
+        let id = cpal::available_hosts()
+            .into_iter()
+            .find(|id| id.name().eq_ignore_ascii_case(name))
+            .with_context(|| {
+                format!("audio host {name} is not available (was it enabled at build time?)")
+            })?;
+        return Ok(cpal::host_from_id(id)?);
+    }
+    #[cfg(target_os = "windows")]
+    let host = cpal::host_from_id(cpal::HostId::Wasapi)?;
+    // Android's default host is Oboe (AAudio, falling back to OpenSL ES).
+    #[cfg(all(unix, not(any(target_os = "macos", target_os = "ios"))))]
+    let host = cpal::default_host();
+    // On iOS the app configures AVAudioSession first (see `ios/`).
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    let host = cpal::host_from_id(cpal::HostId::CoreAudio)?;
+    Ok(host)
+}
+
+/// Returns the (input, output) devices to use on `host`: no input device
+/// unless `capture`, no output device unless `opts.playback`.
+pub fn open_devices(
+    host: &cpal::Host,
+    opts: &AudioOptions,
+    capture: bool,
+) -> Result<(Option<cpal::Device>, Option<cpal::Device>)> {
+    #[cfg(all(feature = "jack", target_os = "linux"))]
+    if host.id() == cpal::HostId::Jack {
+        return jack_devices(opts, capture);
+    }
+    #[cfg(target_os = "linux")]
+    if opts.host.as_deref().is_some_and(is_pipewire) {
+        return pipewire_devices(host, capture, opts.playback);
+    }
+    let input = match &opts.input_device {
+        _ if !capture => None,
+        Some(name) => Some(find_device(host.input_devices()?, name, "input")?),
+        None => Some(default_device(host, opts, Direction::Input)?),
+    };
+    let output = match &opts.output_device {
+        _ if !opts.playback => None,
+        _ if opts.virtual_cable == Some(CableRouting::Instead) => Some(virtual_cable(host)?),
+        Some(name) => Some(find_device(host.output_devices()?, name, "output")?),
+        None => Some(default_device(host, opts, Direction::Output)?),
+    };
+    Ok((input, output))
+}
+
+/// Whether `open_devices` picks devices on `host` by name; on JACK and
+/// PipeWire it doesn't.
+pub fn by_name(host: &cpal::Host, opts: &AudioOptions) -> bool {
+    #[cfg(all(feature = "jack", target_os = "linux"))]
+    if host.id() == cpal::HostId::Jack {
+        return false;
+    }
+    #[cfg(target_os = "linux")]
+    if opts.host.as_deref().is_some_and(is_pipewire) {
+        return false;
+    }
+    let _ = (host, opts);
+    true
+}
+
+/// The input device to mix into the first, if one was asked for.
+pub fn extra_input(host: &cpal::Host, opts: &AudioOptions) -> Result<Option<cpal::Device>> {
+    match &opts.extra_input {
+        Some(name) => Ok(Some(find_device(
+            host.input_devices()?,
+            name,
+            "extra input",
+        )?)),
+        None => Ok(None),
+    }
+}
+
+/// The output device to mirror playback to, if one was asked for.
+pub fn mirror_device(host: &cpal::Host, opts: &AudioOptions) -> Result<Option<cpal::Device>> {
+    match &opts.mirror_device {
+        _ if !opts.mirrors() => Ok(None),
+        Some(name) => Ok(Some(find_device(host.output_devices()?, name, "mirror")?)),
+        None => virtual_cable(host).map(Some),
+    }
+}
+
+/// The first output device that looks like a virtual cable.
+fn virtual_cable(host: &cpal::Host) -> Result<cpal::Device> {
+    let outputs: Vec<_> = host.output_devices()?.collect();
+    for cable in VIRTUAL_CABLES {
+        if let Ok(device) = find_device(outputs.iter().cloned(), cable, "output") {
+            return Ok(device);
+        }
+    }
+    anyhow::bail!(
+        "no virtual cable among the output devices (looked for {})",
+        VIRTUAL_CABLES.join(", ")
+    )
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Input,
+    Output,
+}
+
+fn default_device(host: &cpal::Host, opts: &AudioOptions, dir: Direction) -> Result<cpal::Device> {
+    #[cfg(windows)]
+    if let Some(device) = communications_device(host, opts, dir) {
+        return Ok(device);
+    }
+    let _ = opts;
+    match dir {
+        Direction::Input => host
+            .default_input_device()
+            .context("No default input device found"),
+        Direction::Output => host
+            .default_output_device()
+            .context("No default output device found"),
+    }
+}
+
+/// If the default device we opened as `current` is no longer the default
+/// (Windows moved the communications role), the new default's name.
+/// Devices chosen by name never switch.
+pub fn default_switched(
+    host: &cpal::Host,
+    opts: &AudioOptions,
+    dir: Direction,
+    current: &str,
+) -> Option<String> {
+    let named = match dir {
+        Direction::Input => opts.input_device.is_some(),
+        Direction::Output => {
+            opts.output_device.is_some() || opts.virtual_cable == Some(CableRouting::Instead)
+        }
+    };
+    if named {
+        return None;
+    }
+    #[cfg(windows)]
+    if let Some(now) = communications_device(host, opts, dir).and_then(|d| d.name().ok()) {
+        return (now != current).then_some(now);
+    }
+    let _ = (host, current);
+    None
+}
+
+pub(crate) fn find_device(
+    devices: impl Iterator<Item = cpal::Device>,
+    name: &str,
+    kind: &str,
+) -> Result<cpal::Device> {
+    let needle = name.to_lowercase();
+    let mut partial = None;
+    for device in devices {
+        let Ok(device_name) = device.name() else {
+            continue;
+        };
+        if device_name == name {
+            return Ok(device);
+        }
+        if partial.is_none() && device_name.to_lowercase().contains(&needle) {
+            partial = Some(device);
+        }
+    }
+    partial.with_context(|| format!("no {kind} device matching {name:?}"))
+}
+
+/// Stream config for `supported`, with `buffer_frames` clamped to the range
+/// the device reports.
+pub fn stream_config(
+    supported: &cpal::SupportedStreamConfig,
+    buffer_frames: Option<u32>,
+) -> cpal::StreamConfig {
+    let mut cfg = supported.config();
+    if let Some(frames) = buffer_frames {
+        let frames = match *supported.buffer_size() {
+            cpal::SupportedBufferSize::Range { min, max } => {
+                let clamped = frames.clamp(min, max);
+                if clamped != frames {
+                    tracing::warn!(
+                        "buffer of {frames} frames unsupported, using {clamped} ({min}..={max})"
+                    );
+                }
+                clamped
+            }
+            cpal::SupportedBufferSize::Unknown => frames,
+        };
+        cfg.buffer_size = cpal::BufferSize::Fixed(frames);
+    }
+    cfg
+}
+
+/// The endpoint in the communications role, as a cpal device.
+#[cfg(windows)]
+fn communications_device(
+    host: &cpal::Host,
+    opts: &AudioOptions,
+    dir: Direction,
+) -> Option<cpal::Device> {
+    if !opts.communications || host.id() != cpal::HostId::Wasapi {
+        return None;
+    }
+    let name = communications_endpoint_name(dir)?;
+    let mut devices = match dir {
+        Direction::Input => host.input_devices().ok()?,
+        Direction::Output => host.output_devices().ok()?,
+    };
+    let device = devices.find(|d| d.name().is_ok_and(|n| n == name));
+    if device.is_none() {
+        tracing::warn!("communications device {name:?} not found; using the default");
+    }
+    device
+}
+
+/// Friendly name of the default communications endpoint, as cpal reports
+/// device names.
+#[cfg(windows)]
+fn communications_endpoint_name(dir: Direction) -> Option<String> {
+    use std::os::windows::ffi::OsStringExt;
+    use windows::Win32::Devices::Properties::DEVPKEY_Device_FriendlyName;
+    use windows::Win32::Foundation::RPC_E_CHANGED_MODE;
+    use windows::Win32::Media::Audio;
+    use windows::Win32::System::Com::{self, StructuredStorage, STGM_READ};
+    use windows::Win32::System::Variant::VT_LPWSTR;
+
+    unsafe {
+        // In the mode cpal uses; balanced below unless COM was already up in
+        // another mode.
+        let init = Com::CoInitializeEx(None, Com::COINIT_APARTMENTTHREADED);
+        if init.is_err() && init != RPC_E_CHANGED_MODE {
+            return None;
+        }
+        let name = (|| {
+            let enumerator: Audio::IMMDeviceEnumerator =
+                Com::CoCreateInstance(&Audio::MMDeviceEnumerator, None, Com::CLSCTX_ALL).ok()?;
+            let flow = match dir {
+                Direction::Input => Audio::eCapture,
+                Direction::Output => Audio::eRender,
+            };
+            let device = enumerator
+                .GetDefaultAudioEndpoint(flow, Audio::eCommunications)
+                .ok()?;
+            let store = device.OpenPropertyStore(STGM_READ).ok()?;
+            let mut value = store
+                .GetValue(&DEVPKEY_Device_FriendlyName as *const _ as *const _)
+                .ok()?;
+            let raw = &value.as_raw().Anonymous.Anonymous;
+            let name = (raw.vt == VT_LPWSTR.0).then(|| {
+                let ptr = *(&raw.Anonymous as *const _ as *const *const u16);
+                let len = (0..).take_while(|&i| *ptr.offset(i) != 0).count();
+                let wide = std::slice::from_raw_parts(ptr, len);
+                std::ffi::OsString::from_wide(wide)
+                    .to_string_lossy()
+                    .into_owned()
+            });
+            StructuredStorage::PropVariantClear(&mut value).ok();
+            name
+        })();
+        if init.is_ok() {
+            Com::CoUninitialize();
+        }
+        name
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn is_pipewire(host: &str) -> bool {
+    host.eq_ignore_ascii_case("pipewire")
+}
+
+/// Sets `PIPEWIRE_PROPS`, once.
+#[cfg(target_os = "linux")]
+fn tag_pipewire_streams() {
+    static TAGGED: std::sync::Once = std::sync::Once::new();
+    TAGGED.call_once(|| {
+        // Read by every pw_stream the plugin creates.
+        std::env::set_var(
+            "PIPEWIRE_PROPS",
+            "{ media.role = Communication media.category = Duplex \
+             application.name = \"voice-chat\" }",
+        );
+    });
+}
+
+#[cfg(target_os = "linux")]
+fn pipewire_devices(
+    host: &cpal::Host,
+    capture: bool,
+    playback: bool,
+) -> Result<(Option<cpal::Device>, Option<cpal::Device>)> {
+    const HINT: &str = "no `pipewire` ALSA device; is pipewire-alsa installed?";
+    let input = capture
+        .then(|| find_device(host.input_devices()?, "pipewire", "input").context(HINT))
+        .transpose()?;
+    let output = playback
+        .then(|| find_device(host.output_devices()?, "pipewire", "output").context(HINT))
+        .transpose()?;
+    Ok((input, output))
+}
+
+#[cfg(all(feature = "jack", target_os = "linux"))]
+fn jack_devices(
+    opts: &AudioOptions,
+    capture: bool,
+) -> Result<(Option<cpal::Device>, Option<cpal::Device>)> {
+    use cpal::platform::JackDevice;
+    let input = capture
+        .then(|| {
+            JackDevice::default_input_device(&opts.jack_client_name, opts.jack_autoconnect, false)
+                .map_err(|e| anyhow::anyhow!("JACK input client: {e}"))
+        })
+        .transpose()?;
+    let output = opts
+        .playback
+        .then(|| {
+            JackDevice::default_output_device(&opts.jack_client_name, opts.jack_autoconnect, false)
+                .map_err(|e| anyhow::anyhow!("JACK output client: {e}"))
+        })
+        .transpose()?;
+    if !opts.jack_autoconnect {
+        tracing::info!(
+            "JACK ports left unconnected: patch {}_in / {}_out manually",
+            opts.jack_client_name,
+            opts.jack_client_name
+        );
+    }
+    Ok((input.map(Into::into), output.map(Into::into)))
+}
@@ -0,0 +1,387 @@
+// ─── DHT rendezvous ────────────────────────────────────────────────────────────
+// Serverless alternative to `signaling` for casual calls: both sides publish
+// their address in the BitTorrent mainline DHT (a public Kademlia network,
+// BEP 5) under a key hashed from the room name, look each other up, and then
+// call like `--peer`. Everything runs on the media socket before the call
+// starts, so the port the DHT sees – and hands out – is the one NAT mapped
+// for media.
+//
+// The DHT remembers addresses for half an hour, so a lookup can return
+// callers that have since gone. Every address found is sent a small probe;
+// the first one we hear a probe (or anything else) from is the peer. A probe
+// from an address we haven't found yet counts too: they found us.
+//
+// The DHT only stores addresses, not keys: verify the call with the SAS. Pick
+// a room name nobody will guess; anyone who does can ring.
+
+use anyhow::{bail, Context, Result};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+
+pub const DEFAULT_BOOTSTRAP: [&str; 3] = [
+    "router.bittorrent.com:6881",
+    "dht.transmissionbt.com:6881",
+    "router.utorrent.com:6881",
+];
+const ROOM_SALT: &[u8] = b"audio-p2p/v1/dht/";
+/// Queries in flight per lookup round, and nodes we announce to.
+const ALPHA: usize = 8;
+const MAX_ROUNDS: usize = 8;
+const ROUND_TIMEOUT: Duration = Duration::from_secs(1);
+/// Between lookups (and announcements) while nobody has turned up.
+const LOOKUP_INTERVAL: Duration = Duration::from_secs(10);
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+const PROBE: &[u8] = b"audio-p2p dht probe";
+
+#[derive(Clone, Debug)]
+pub struct DhtOptions {
+    /// Shared by both callers; hashed into the DHT key.
+    pub room: String,
+    /// DHT nodes to start from, `host:port`.
+    pub bootstrap: Vec<String>,
+}
+
+type Id = [u8; 20];
+
+/// Announces `sock` under `opts.room` and waits for another caller.
+pub(crate) async fn rendezvous(
+    sock: &UdpSocket,
+    opts: &DhtOptions,
+    reflexive: SocketAddr,
+) -> Result<SocketAddr> {
+    let info_hash = room_hash(&opts.room);
+    let mut dht = Dht {
+        sock,
+        id: rand::random(),
+        info_hash,
+        port: reflexive.port(),
+        nodes: BTreeMap::new(),
+        tokens: HashMap::new(),
+        found: HashSet::new(),
+        myself: reflexive,
+        tid: 0,
+    };
+    for host in &opts.bootstrap {
+        match tokio::net::lookup_host(host.as_str()).await {
+            Ok(addrs) => {
+                for addr in addrs.filter_map(v4) {
+                    dht.get_peers(addr).await;
+                }
+            }
+            Err(e) => warn!("DHT bootstrap {host}: {e}"),
+        }
+    }
+    info!("STATUS: dht_lookup {}", hex(&info_hash));
+    loop {
+        if let Some(peer) = dht.lookup().await? {
+            return Ok(peer);
+        }
+        if dht.nodes.is_empty() {
+            bail!("no DHT node answered; check the bootstrap nodes and that UDP is allowed");
+        }
+        dht.announce().await;
+        // Wait for someone, probing whoever we found meanwhile.
+        let until = Instant::now() + LOOKUP_INTERVAL;
+        while Instant::now() < until {
+            dht.probe().await;
+            if let Some(peer) = dht.listen(Instant::now() + PROBE_INTERVAL).await? {
+                return Ok(peer);
+            }
+        }
+    }
+}
+
+fn room_hash(room: &str) -> Id {
+    let digest = ring::digest::digest(
+        &ring::digest::SHA1_FOR_LEGACY_USE_ONLY,
+        &[ROOM_SALT, room.as_bytes()].concat(),
+    );
+    let mut id = [0u8; 20];
+    id.copy_from_slice(digest.as_ref());
+    id
+}
+
+fn v4(addr: SocketAddr) -> Option<SocketAddrV4> {
+    match addr {
+        SocketAddr::V4(a) => Some(a),
+        SocketAddr::V6(_) => None,
+    }
+}
+
+fn distance(a: &Id, b: &Id) -> Id {
+    std::array::from_fn(|i| a[i] ^ b[i])
+}
+
+struct Dht<'a> {
+    sock: &'a UdpSocket,
+    id: Id,
+    info_hash: Id,
+    port: u16,
+    /// Nodes by distance to the room, with whether we asked them this lookup.
+    nodes: BTreeMap<Id, (SocketAddrV4, bool)>,
+    /// Announce tokens from the nodes that answered.
+    tokens: HashMap<SocketAddrV4, Vec<u8>>,
+    /// Callers stored under the room.
+    found: HashSet<SocketAddrV4>,
+    myself: SocketAddr,
+    tid: u16,
+}
+
+impl Dht<'_> {
+    async fn send(&mut self, to: SocketAddrV4, query: &[u8], args: Vec<(&[u8], Value)>) {
+        self.tid = self.tid.wrapping_add(1);
+        let mut a = BTreeMap::new();
+        a.insert(b"id".to_vec(), Value::Bytes(self.id.to_vec()));
+        for (k, v) in args {
+            a.insert(k.to_vec(), v);
+        }
+        let mut msg = BTreeMap::new();
+        msg.insert(b"t".to_vec(), Value::Bytes(self.tid.to_be_bytes().to_vec()));
+        msg.insert(b"y".to_vec(), Value::Bytes(b"q".to_vec()));
+        msg.insert(b"q".to_vec(), Value::Bytes(query.to_vec()));
+        msg.insert(b"a".to_vec(), Value::Dict(a));
+        let mut out = Vec::new();
+        Value::Dict(msg).encode(&mut out);
+        if let Err(e) = self.sock.send_to(&out, SocketAddr::V4(to)).await {
+            debug!("DHT send to {to}: {e}");
+        }
+    }
+
+    async fn get_peers(&mut self, to: SocketAddrV4) {
+        let hash = Value::Bytes(self.info_hash.to_vec());
+        self.send(to, b"get_peers", vec![(b"info_hash", hash)])
+            .await;
+    }
+
+    /// One iterative lookup towards the room; returns early if a caller
+    /// makes contact meanwhile.
+    async fn lookup(&mut self) -> Result<Option<SocketAddr>> {
+        for (_, asked) in self.nodes.values_mut() {
+            *asked = false;
+        }
+        // The bootstrap queries are already out on the first lookup.
+        let mut rounds = 0;
+        loop {
+            if let Some(peer) = self.listen(Instant::now() + ROUND_TIMEOUT).await? {
+                return Ok(Some(peer));
+            }
+            rounds += 1;
+            let next: Vec<SocketAddrV4> = self
+                .nodes
+                .values()
+                .take(ALPHA)
+                .filter(|(_, asked)| !asked)
+                .map(|(addr, _)| *addr)
+                .collect();
+            if next.is_empty() || rounds > MAX_ROUNDS {
+                return Ok(None);
+            }
+            for addr in next {
+                if let Some(node) = self.nodes.values_mut().find(|(a, _)| *a == addr) {
+                    node.1 = true;
+                }
+                self.get_peers(addr).await;
+            }
+            self.probe().await;
+        }
+    }
+
+    /// Stores our address with the closest nodes that gave us a token.
+    async fn announce(&mut self) {
+        let closest: Vec<(SocketAddrV4, Vec<u8>)> = self
+            .nodes
+            .values()
+            .filter_map(|(addr, _)| Some((*addr, self.tokens.get(addr)?.clone())))
+            .take(ALPHA)
+            .collect();
+        for (addr, token) in closest {
+            let args = vec![
+                (&b"info_hash"[..], Value::Bytes(self.info_hash.to_vec())),
+                // The DHT uses the source port, i.e. the NAT mapping.
+                (&b"implied_port"[..], Value::Int(1)),
+                (&b"port"[..], Value::Int(self.port as i64)),
+                (&b"token"[..], Value::Bytes(token)),
+            ];
+            self.send(addr, b"announce_peer", args).await;
+        }
+    }
+
+    async fn probe(&self) {
+        for addr in &self.found {
+            let _ = self.sock.send_to(PROBE, SocketAddr::V4(*addr)).await;
+        }
+    }
+
+    /// Handles DHT replies until `until`; returns a caller that made contact.
+    async fn listen(&mut self, until: Instant) -> Result<Option<SocketAddr>> {
+        let mut buf = [0u8; 2048];
+        loop {
+            let received = tokio::time::timeout_at(until, self.sock.recv_from(&mut buf)).await;
+            let Ok(received) = received else {
+                return Ok(None);
+            };
+            let (len, from) = received.context("DHT receive failed")?;
+            let data = &buf[..len];
+            if data == PROBE {
+                // Answer, so they hear from us even if our probes were lost.
+                let _ = self.sock.send_to(PROBE, from).await;
+                return Ok(Some(from));
+            }
+            let from4 = v4(from);
+            match Value::decode(data) {
+                Some(Value::Dict(msg)) => {
+                    if let (Some(from), Some(Value::Dict(r))) = (from4, msg.get(&b"r"[..])) {
+                        self.reply(from, r);
+                    }
+                }
+                // Not the DHT: the peer has moved on to the call already.
+                _ if from4.is_some_and(|a| self.found.contains(&a)) => return Ok(Some(from)),
+                _ => {}
+            }
+        }
+    }
+
+    fn reply(&mut self, from: SocketAddrV4, r: &BTreeMap<Vec<u8>, Value>) {
+        let bytes = |key: &[u8]| match r.get(key) {
+            Some(Value::Bytes(b)) => Some(b.as_slice()),
+            _ => None,
+        };
+        if let Some(id) = bytes(b"id").and_then(|id| Id::try_from(id).ok()) {
+            let d = distance(&id, &self.info_hash);
+            self.nodes.entry(d).or_insert((from, true));
+        }
+        if let Some(token) = bytes(b"token") {
+            self.tokens.insert(from, token.to_vec());
+        }
+        // Compact node info: 20‑byte id, IPv4, port.
+        for node in bytes(b"nodes").unwrap_or_default().chunks_exact(26) {
+            let id = Id::try_from(&node[..20]).expect("20 bytes");
+            let addr = compact(&node[20..]);
+            if addr.port() != 0 {
+                self.nodes
+                    .entry(distance(&id, &self.info_hash))
+                    .or_insert((addr, false));
+            }
+        }
+        if let Some(Value::List(values)) = r.get(&b"values"[..]) {
+            for v in values {
+                let Value::Bytes(peer) = v else {
+                    continue;
+                };
+                if peer.len() != 6 {
+                    continue;
+                }
+                let addr = compact(peer);
+                if SocketAddr::V4(addr) != self.myself && self.found.insert(addr) {
+                    info!("DHT: found caller {addr}");
+                }
+            }
+        }
+    }
+}
+
+fn compact(b: &[u8]) -> SocketAddrV4 {
+    SocketAddrV4::new(
+        Ipv4Addr::new(b[0], b[1], b[2], b[3]),
+        u16::from_be_bytes([b[4], b[5]]),
+    )
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+// ─── Bencode ───────────────────────────────────────────────────────────────────
+
+#[derive(Debug)]
+enum Value {
+    Int(i64),
+    Bytes(Vec<u8>),
+    List(Vec<Value>),
+    /// Keys sort as raw bytes, as bencode requires.
+    Dict(BTreeMap<Vec<u8>, Value>),
+}
+
+impl Value {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Value::Int(i) => out.extend_from_slice(format!("i{i}e").as_bytes()),
+            Value::Bytes(b) => {
+                out.extend_from_slice(format!("{}:", b.len()).as_bytes());
+                out.extend_from_slice(b);
+            }
+            Value::List(items) => {
+                out.push(b'l');
+                for item in items {
+                    item.encode(out);
+                }
+                out.push(b'e');
+            }
+            Value::Dict(entries) => {
+                out.push(b'd');
+                for (k, v) in entries {
+                    Value::Bytes(k.clone()).encode(out);
+                    v.encode(out);
+                }
+                out.push(b'e');
+            }
+        }
+    }
+
+    /// The whole of `data` as one value.
+    fn decode(data: &[u8]) -> Option<Value> {
+        match Self::parse(data, 0)? {
+            (value, []) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Nesting is capped so a hostile packet can't blow the stack.
+    fn parse(data: &[u8], depth: usize) -> Option<(Value, &[u8])> {
+        if depth > 16 {
+            return None;
+        }
+        match *data.first()? {
+            b'i' => {
+                let end = data.iter().position(|&b| b == b'e')?;
+                let i = std::str::from_utf8(&data[1..end]).ok()?.parse().ok()?;
+                Some((Value::Int(i), &data[end + 1..]))
+            }
+            b'l' => {
+                let mut rest = &data[1..];
+                let mut items = Vec::new();
+                while *rest.first()? != b'e' {
+                    let (item, r) = Self::parse(rest, depth + 1)?;
+                    items.push(item);
+                    rest = r;
+                }
+                Some((Value::List(items), &rest[1..]))
+            }
+            b'd' => {
+                let mut rest = &data[1..];
+                let mut entries = BTreeMap::new();
+                while *rest.first()? != b'e' {
+                    let (Value::Bytes(key), r) = Self::parse(rest, depth + 1)? else {
+                        return None;
+                    };
+                    let (value, r) = Self::parse(r, depth + 1)?;
+                    entries.insert(key, value);
+                    rest = r;
+                }
+                Some((Value::Dict(entries), &rest[1..]))
+            }
+            b'0'..=b'9' => {
+                let colon = data.iter().position(|&b| b == b':')?;
+                let len: usize = std::str::from_utf8(&data[..colon]).ok()?.parse().ok()?;
+                let end = (colon + 1).checked_add(len)?;
+                let body = data.get(colon + 1..end)?;
+                Some((Value::Bytes(body.to_vec()), &data[end..]))
+            }
+            _ => None,
+        }
+    }
+}
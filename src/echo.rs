@@ -0,0 +1,228 @@
+// ─── Echo server ───────────────────────────────────────────────────────────────
+// A test line: `echo-server` keys with whoever calls it, like any peer, and
+// sends their own audio back `delay` later, so one person can hear what their
+// mic, network and settings sound like at the far end, and how long the round
+// trip takes. Frames go back as they came unless `reencode` is set, in which
+// case they are decoded and encoded again, as a second voice‑chat would.
+// Pings are answered at once, so the caller's RTT readings stay honest.
+//
+// Each caller gets a handshake of its own and shows the usual SAS; callers
+// that send nothing for `IDLE` are forgotten.
+
+use crate::packet::{Packet, Sealed};
+use crate::{capture, codec, crypto, flood, socket, MAX_SURROUND_PACKET_SIZE, SAMPLE_RATE};
+use anyhow::Result;
+use bytes::Bytes;
+use std::collections::{HashMap, VecDeque};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{error, info, warn};
+
+/// A caller that has sent nothing for this long is dropped.
+const IDLE: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug)]
+pub struct EchoOptions {
+    pub port: u16,
+    pub socket: socket::SocketOptions,
+    /// How long after arriving a frame is sent back.
+    pub delay: Duration,
+    /// Decode and encode every frame again instead of returning it as is.
+    pub reencode: bool,
+    /// Callers served at once.
+    pub max_callers: usize,
+}
+
+struct Caller {
+    /// Our Hello to them, sent again whenever they send theirs.
+    hello: Bytes,
+    /// The key agreed from their first Hello.
+    peer_key: [u8; crypto::PUBLIC_KEY_LEN],
+    sealer: crypto::Sealer,
+    opener: crypto::Opener,
+    /// With `reencode`.
+    codec: Option<(codec::Decoder, codec::Encoder)>,
+    heard: Instant,
+}
+
+impl Caller {
+    fn new(
+        pub_key: &[u8; crypto::PUBLIC_KEY_LEN],
+        params: codec::StreamParams,
+        reencode: bool,
+    ) -> Result<Self> {
+        let codec = match reencode {
+            true => Some((
+                codec::Decoder::new(SAMPLE_RATE, &params.layout)?,
+                codec::Encoder::new(SAMPLE_RATE, params.layout.channels)?,
+            )),
+            false => None,
+        };
+        // Re‑encoded frames follow our encoder's layout, which may number
+        // the surround streams differently.
+        let ours = codec::StreamParams {
+            layout: codec
+                .as_ref()
+                .map_or(params.layout.clone(), |(_, enc)| enc.layout().clone()),
+            fec: params.fec && codec.is_none(),
+            ..params
+        };
+        let hs = crypto::Handshake::new()?;
+        let hello = Packet::Hello {
+            pub_key: hs.public_key(),
+            params: ours,
+        }
+        .encode();
+        let session = hs.complete(pub_key, crypto::RekeyPolicy::default())?;
+        Ok(Self {
+            hello,
+            peer_key: session.peer_key,
+            sealer: session.sealer,
+            opener: session.opener,
+            codec,
+            heard: Instant::now(),
+        })
+    }
+
+    /// The frame to send back for `frame`.
+    fn echo(&mut self, frame: &[u8]) -> Result<Vec<u8>> {
+        let Some((dec, enc)) = &mut self.codec else {
+            return Ok(frame.to_vec());
+        };
+        let mut pcm = vec![0.0f32; SAMPLE_RATE as usize / 1000 * 120 * dec.channels()];
+        let n = dec.decode_float(frame, &mut pcm, false)?;
+        pcm.truncate(n * dec.channels());
+        let mut out = vec![0u8; MAX_SURROUND_PACKET_SIZE];
+        let len = enc.encode_float(&pcm, &mut out)?;
+        out.truncate(len);
+        Ok(out)
+    }
+}
+
+/// Echoes for whoever calls, until the task is dropped.
+pub async fn run(opts: EchoOptions) -> Result<()> {
+    let ip = opts
+        .socket
+        .bind_addr
+        .unwrap_or(Ipv4Addr::UNSPECIFIED.into());
+    let sock = socket::bind(SocketAddr::new(ip, opts.port), &opts.socket)?;
+    let mut guard = flood::Guard::new(opts.socket.limits);
+    info!(
+        "echoing on {} after {} ms for up to {} callers",
+        sock.local_addr()?,
+        opts.delay.as_millis(),
+        opts.max_callers
+    );
+    crate::service::ready("echoing");
+    let mut callers: HashMap<SocketAddr, Caller> = HashMap::new();
+    // Frames waiting out the delay, oldest first.
+    let mut queue: VecDeque<(Instant, SocketAddr, Sealed, Vec<u8>)> = VecDeque::new();
+    let mut buf = [0u8; MAX_SURROUND_PACKET_SIZE + crate::packet::MEDIA_OVERHEAD];
+    loop {
+        let due = queue.front().map(|(at, ..)| *at);
+        tokio::select! {
+            () = tokio::time::sleep_until(due.unwrap_or_else(Instant::now)), if due.is_some() => {
+                let Some((_, to, kind, body)) = queue.pop_front() else {
+                    continue;
+                };
+                let Some(caller) = callers.get_mut(&to) else {
+                    continue;
+                };
+                let body = match kind {
+                    Sealed::Media => match caller.echo(&body) {
+                        Ok(frame) => frame,
+                        Err(e) => {
+                            warn!(peer = %to, "can't re‑encode: {e:#}");
+                            continue;
+                        }
+                    },
+                    _ => body,
+                };
+                match caller.sealer.seal(kind, &body) {
+                    Ok(pkt) => send(&sock, to, &pkt).await,
+                    Err(e) => error!("{e}"),
+                }
+            }
+            r = sock.recv_from(&mut buf) => {
+                let (n, src) = match r {
+                    Ok(r) => r,
+                    Err(e) => {
+                        error!("udp recv error: {e}");
+                        continue;
+                    }
+                };
+                capture::received(&sock, src, &buf[..n]);
+                if !guard.admit(src, n) {
+                    continue;
+                }
+                let now = Instant::now();
+                callers.retain(|addr, c| {
+                    let live = now - c.heard < IDLE;
+                    if !live {
+                        info!(peer = %addr, "echo: {addr} went idle");
+                    }
+                    live
+                });
+                match Packet::parse(&buf[..n]) {
+                    Some(Packet::Hello { pub_key, params }) => {
+                        if callers.get(&src).is_some_and(|c| c.peer_key != pub_key) {
+                            // They restarted; key again.
+                            callers.remove(&src);
+                        }
+                        if !callers.contains_key(&src) {
+                            if callers.len() >= opts.max_callers {
+                                warn!("echo: refusing {src}, already serving {} callers", callers.len());
+                                continue;
+                            }
+                            match Caller::new(&pub_key, params, opts.reencode) {
+                                Ok(caller) => {
+                                    info!(peer = %src, "echo: {src} called");
+                                    callers.insert(src, caller);
+                                }
+                                Err(e) => {
+                                    error!("handshake with {src} failed: {e:#}");
+                                    continue;
+                                }
+                            }
+                        }
+                        let caller = callers.get_mut(&src).expect("inserted above");
+                        caller.heard = now;
+                        send(&sock, src, &caller.hello).await;
+                    }
+                    Some(Packet::Sealed { kind, epoch, seq, payload }) => {
+                        let Some(caller) = callers.get_mut(&src) else {
+                            continue;
+                        };
+                        let Some(body) = caller.opener.open(kind, epoch, seq, payload) else {
+                            continue;
+                        };
+                        caller.heard = now;
+                        match kind {
+                            Sealed::Media | Sealed::Silence => {
+                                queue.push_back((now + opts.delay, src, kind, body));
+                            }
+                            Sealed::Ping => match caller.sealer.seal(Sealed::Pong, &body) {
+                                Ok(pkt) => send(&sock, src, &pkt).await,
+                                Err(e) => error!("{e}"),
+                            },
+                            Sealed::Bye => {
+                                info!(peer = %src, "echo: {src} hung up");
+                                callers.remove(&src);
+                            }
+                            _ => {}
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+async fn send(sock: &tokio::net::UdpSocket, to: SocketAddr, pkt: &[u8]) {
+    capture::sent(sock, to, pkt);
+    if let Err(e) = sock.send_to(pkt, to).await {
+        error!("udp send error to {to}: {e}");
+    }
+}
@@ -0,0 +1,1042 @@
+// ─── Effects ───────────────────────────────────────────────────────────────────
+// Optional DSP stages around the APM and codec. Their settings live in
+// `Controls`, which the embedder keeps an `Arc` of: the audio callbacks read it
+// on every frame, so effects can be changed or toggled mid‑call without
+// rebuilding any stream.
+
+use crate::cues::{Cue, Cues};
+use crate::{hold, loudness, vad};
+use anyhow::{bail, Result};
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+
+/// Runtime‑adjustable effect settings shared with the audio callbacks.
+#[derive(Debug)]
+pub struct Controls {
+    pitch_enabled: AtomicBool,
+    /// `f32` bits.
+    pitch_semitones: AtomicU32,
+    compressor: Mutex<Option<CompressorConfig>>,
+    eq: Mutex<Option<EqConfig>>,
+    /// EQ set for one peer instead of `eq`, by peer address; `None` bypasses
+    /// it for them.
+    peer_eq: Mutex<HashMap<SocketAddr, Option<EqConfig>>>,
+    ducking: Mutex<Option<DuckingConfig>>,
+    normalize: Mutex<Option<NormalizeConfig>>,
+    apm: Mutex<ApmStages>,
+    send_loudness: Mutex<Option<loudness::SendLoudness>>,
+    /// Gains set by hand, in dB, by peer address.
+    peer_gains: Mutex<HashMap<SocketAddr, f32>>,
+    /// Output trims and soft mutes, by peer address.
+    streams: Mutex<HashMap<SocketAddr, StreamOutput>>,
+    /// Trim for every stream of the call, `f32` bits in dB.
+    output_gain_db: AtomicU32,
+    /// Each output device's volume, `f32` bits in dB, by `Output`.
+    device_volume_db: [AtomicU32; 2],
+    /// Each input device's gain ahead of the APM, `f32` bits in dB, by
+    /// `Input`.
+    input_gain_db: [AtomicU32; 2],
+    /// The group member who cuts in on everyone else (see `multicast`).
+    priority_speaker: Mutex<Option<SocketAddr>>,
+    muted: AtomicBool,
+    held: AtomicBool,
+    hold_music: Mutex<Option<hold::Music>>,
+    vad: Mutex<vad::VadSettings>,
+    /// A noise floor measurement under way, and the last one's result.
+    vad_calibration: Mutex<Option<vad::Calibration>>,
+    noise_floor: Mutex<Option<vad::NoiseFloor>>,
+    /// Set by the capture side (see `vad`).
+    local_speech: AtomicBool,
+    cues: Arc<Cues>,
+}
+
+impl Default for Controls {
+    fn default() -> Self {
+        Self {
+            pitch_enabled: AtomicBool::new(false),
+            pitch_semitones: AtomicU32::new(0f32.to_bits()),
+            compressor: Mutex::new(None),
+            eq: Mutex::new(None),
+            peer_eq: Mutex::new(HashMap::new()),
+            ducking: Mutex::new(None),
+            normalize: Mutex::new(None),
+            apm: Mutex::new(ApmStages::default()),
+            send_loudness: Mutex::new(None),
+            peer_gains: Mutex::new(HashMap::new()),
+            streams: Mutex::new(HashMap::new()),
+            output_gain_db: AtomicU32::new(0f32.to_bits()),
+            device_volume_db: [0f32, 0f32].map(|db| AtomicU32::new(db.to_bits())),
+            input_gain_db: [0f32, 0f32].map(|db| AtomicU32::new(db.to_bits())),
+            priority_speaker: Mutex::new(None),
+            muted: AtomicBool::new(false),
+            held: AtomicBool::new(false),
+            hold_music: Mutex::new(None),
+            vad: Mutex::new(vad::VadSettings::default()),
+            vad_calibration: Mutex::new(None),
+            noise_floor: Mutex::new(None),
+            local_speech: AtomicBool::new(false),
+            cues: Arc::default(),
+        }
+    }
+}
+
+impl Controls {
+    /// Applies a voice preset; `Voice::Off` disables the pitch shifter.
+    pub fn set_voice(&self, voice: Voice) {
+        match voice.semitones() {
+            Some(semitones) => {
+                self.set_pitch_semitones(semitones);
+                self.set_pitch_enabled(true);
+            }
+            None => self.set_pitch_enabled(false),
+        }
+    }
+
+    pub fn set_pitch_semitones(&self, semitones: f32) {
+        let semitones = semitones.clamp(-MAX_SEMITONES, MAX_SEMITONES);
+        self.pitch_semitones
+            .store(semitones.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Toggles the pitch shifter without forgetting its setting.
+    pub fn set_pitch_enabled(&self, enabled: bool) {
+        self.pitch_enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Playback‑speed ratio for the pitch shifter, `None` when bypassed.
+    pub(crate) fn pitch_ratio(&self) -> Option<f32> {
+        if !self.pitch_enabled.load(Ordering::Relaxed) {
+            return None;
+        }
+        let semitones = f32::from_bits(self.pitch_semitones.load(Ordering::Relaxed));
+        Some(2f32.powf(semitones / 12.0))
+    }
+
+    /// `None` bypasses the compressor.
+    pub fn set_compressor(&self, config: Option<CompressorConfig>) {
+        *self.compressor.lock() = config;
+    }
+
+    pub(crate) fn compressor(&self) -> Option<CompressorConfig> {
+        *self.compressor.lock()
+    }
+
+    /// EQ for received audio from peers without one of their own; `None`
+    /// bypasses it.
+    pub fn set_eq(&self, config: Option<EqConfig>) {
+        *self.eq.lock() = config;
+    }
+
+    /// EQ for `peer` alone, whatever `set_eq` says; `None` bypasses it for
+    /// them.
+    pub fn set_peer_eq(&self, peer: SocketAddr, config: Option<EqConfig>) {
+        self.peer_eq.lock().insert(peer, config);
+    }
+
+    /// Gives `peer` the call's EQ again.
+    pub fn clear_peer_eq(&self, peer: SocketAddr) {
+        self.peer_eq.lock().remove(&peer);
+    }
+
+    pub(crate) fn eq(&self, peer: Option<SocketAddr>) -> Option<EqConfig> {
+        let own = peer.and_then(|peer| self.peer_eq.lock().get(&peer).copied());
+        own.unwrap_or_else(|| *self.eq.lock())
+    }
+
+    /// Stops sending media; the peer is told we're muted instead.
+    pub fn set_muted(&self, muted: bool) {
+        if self.muted.swap(muted, Ordering::Relaxed) != muted {
+            self.cues.play(if muted { Cue::Mute } else { Cue::Unmute });
+        }
+    }
+
+    pub fn muted(&self) -> bool {
+        self.muted.load(Ordering::Relaxed)
+    }
+
+    /// Cuts the microphone and tells the peer they're on hold; with `music`
+    /// they hear that, looped, until `resume` (see `hold`).
+    pub fn hold(&self, music: Option<hold::Music>) {
+        *self.hold_music.lock() = music;
+        self.held.store(true, Ordering::Relaxed);
+    }
+
+    pub fn resume(&self) {
+        self.held.store(false, Ordering::Relaxed);
+        *self.hold_music.lock() = None;
+    }
+
+    pub fn on_hold(&self) -> bool {
+        self.held.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn hold_music(&self) -> Option<hold::Music> {
+        self.hold_music.lock().clone()
+    }
+
+    /// Turns received audio down while we talk; `None` disables ducking.
+    pub fn set_ducking(&self, config: Option<DuckingConfig>) {
+        *self.ducking.lock() = config;
+    }
+
+    pub(crate) fn ducking(&self) -> Option<DuckingConfig> {
+        *self.ducking.lock()
+    }
+
+    /// Brings every peer toward the same loudness; `None` disables it.
+    pub fn set_normalize(&self, config: Option<NormalizeConfig>) {
+        *self.normalize.lock() = config;
+    }
+
+    pub(crate) fn normalize(&self) -> Option<NormalizeConfig> {
+        *self.normalize.lock()
+    }
+
+    /// Levels what we send to a LUFS target; `None` only meters it.
+    pub fn set_send_loudness(&self, target: Option<loudness::SendLoudness>) {
+        *self.send_loudness.lock() = target;
+    }
+
+    pub(crate) fn send_loudness(&self) -> Option<loudness::SendLoudness> {
+        *self.send_loudness.lock()
+    }
+
+    /// When what we send counts as speech, from the next frame.
+    pub fn set_vad(&self, settings: vad::VadSettings) {
+        *self.vad.lock() = settings;
+    }
+
+    pub fn vad(&self) -> vad::VadSettings {
+        *self.vad.lock()
+    }
+
+    /// Measures the noise floor of what we send over the next `over`, for
+    /// `noise_floor` to report; the user should keep quiet meanwhile.
+    pub fn calibrate_vad(&self, over: std::time::Duration) {
+        *self.noise_floor.lock() = None;
+        *self.vad_calibration.lock() = Some(vad::Calibration::new(over));
+    }
+
+    /// The last calibration's result; `None` while one runs.
+    pub fn noise_floor(&self) -> Option<vad::NoiseFloor> {
+        *self.noise_floor.lock()
+    }
+
+    pub(crate) fn calibrating_vad(&self) -> bool {
+        self.vad_calibration.lock().is_some()
+    }
+
+    /// Takes a processed frame's level while calibrating; the result when
+    /// this frame finished it.
+    pub(crate) fn calibrate_frame(
+        &self,
+        level_db: f32,
+        frame: std::time::Duration,
+    ) -> Option<vad::NoiseFloor> {
+        let mut calibration = self.vad_calibration.lock();
+        let floor = calibration.as_mut()?.push(level_db, frame)?;
+        *calibration = None;
+        *self.noise_floor.lock() = Some(floor);
+        Some(floor)
+    }
+
+    /// Which parts of the APM run on what we send, from the next frame.
+    pub fn set_apm(&self, stages: ApmStages) {
+        *self.apm.lock() = stages;
+    }
+
+    pub fn apm(&self) -> ApmStages {
+        *self.apm.lock()
+    }
+
+    /// Plays `peer` at a fixed gain instead of the normalized one; `None`
+    /// hands it back to normalization.
+    pub fn set_peer_gain(&self, peer: SocketAddr, gain_db: Option<f32>) {
+        let mut gains = self.peer_gains.lock();
+        match gain_db {
+            Some(db) => gains.insert(peer, db.clamp(-MAX_PEER_GAIN_DB, MAX_PEER_GAIN_DB)),
+            None => gains.remove(&peer),
+        };
+    }
+
+    pub(crate) fn peer_gain(&self, peer: SocketAddr) -> Option<f32> {
+        self.peer_gains.lock().get(&peer).copied()
+    }
+
+    /// Trims `peer`'s stream by `gain_db` on top of any normalization, as
+    /// heard here only (see `StreamGain`).
+    pub fn set_stream_gain(&self, peer: SocketAddr, gain_db: f32) {
+        let gain_db = gain_db.clamp(-MAX_STREAM_GAIN_DB, MAX_STREAM_GAIN_DB);
+        self.streams.lock().entry(peer).or_default().gain_db = gain_db;
+    }
+
+    /// Fades `peer` out (or back in) within a few milliseconds; unlike
+    /// `set_muted`, nothing is sent or signalled.
+    pub fn set_stream_muted(&self, peer: SocketAddr, muted: bool) {
+        self.streams.lock().entry(peer).or_default().muted = muted;
+    }
+
+    /// Trims every stream of the call by `gain_db`, e.g. to set one room's
+    /// volume against another's.
+    pub fn set_output_gain(&self, gain_db: f32) {
+        let gain_db = gain_db.clamp(-MAX_STREAM_GAIN_DB, MAX_STREAM_GAIN_DB);
+        self.output_gain_db
+            .store(gain_db.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Turns one output device up or down, leaving the other alone.
+    pub fn set_device_volume(&self, output: Output, volume_db: f32) {
+        let volume_db = volume_db.clamp(-MAX_STREAM_GAIN_DB, MAX_STREAM_GAIN_DB);
+        self.device_volume_db[output as usize].store(volume_db.to_bits(), Ordering::Relaxed);
+    }
+
+    pub(crate) fn device_volume(&self, output: Output) -> StreamOutput {
+        let bits = self.device_volume_db[output as usize].load(Ordering::Relaxed);
+        StreamOutput {
+            gain_db: f32::from_bits(bits),
+            muted: false,
+        }
+    }
+
+    /// Sets one input device's level in what we send, e.g. to sit shared
+    /// application audio under the voice.
+    pub fn set_input_gain(&self, input: Input, gain_db: f32) {
+        let gain_db = gain_db.clamp(-MAX_STREAM_GAIN_DB, MAX_STREAM_GAIN_DB);
+        self.input_gain_db[input as usize].store(gain_db.to_bits(), Ordering::Relaxed);
+    }
+
+    pub(crate) fn input_gain(&self, input: Input) -> StreamOutput {
+        let bits = self.input_gain_db[input as usize].load(Ordering::Relaxed);
+        StreamOutput {
+            gain_db: f32::from_bits(bits),
+            muted: false,
+        }
+    }
+
+    /// `peer`'s own setting, plus the call's output gain.
+    pub fn stream_output(&self, peer: SocketAddr) -> StreamOutput {
+        let mut output = self.streams.lock().get(&peer).copied().unwrap_or_default();
+        output.gain_db += f32::from_bits(self.output_gain_db.load(Ordering::Relaxed));
+        output
+    }
+
+    /// In a multicast group, plays `peer` the moment they talk, over
+    /// whoever else is playing, and holds everyone else off until they have
+    /// been quiet a while; `None` goes back to first come, first served.
+    pub fn set_priority_speaker(&self, peer: Option<SocketAddr>) {
+        *self.priority_speaker.lock() = peer;
+    }
+
+    pub fn priority_speaker(&self) -> Option<SocketAddr> {
+        *self.priority_speaker.lock()
+    }
+
+    /// Whether the local user is talking, as of the last captured frame.
+    pub fn local_speech(&self) -> bool {
+        self.local_speech.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_local_speech(&self, speaking: bool) {
+        self.local_speech.store(speaking, Ordering::Relaxed);
+    }
+
+    /// Earcons played into our own output; turn each on with
+    /// `cues().set_enabled`.
+    pub fn cues(&self) -> &Arc<Cues> {
+        &self.cues
+    }
+}
+
+// ─── APM stages ────────────────────────────────────────────────────────────────
+// Which parts of the WebRTC APM run on what we send, switchable mid‑call so the
+// user can hear at once whether they help: `bypass` skips it altogether (the
+// VAD falls back to a level check), otherwise each stage is on or off by
+// itself. The capture chain applies a change before its next frame, and
+// `VoiceSession::apm` reports what it is running. Echo cancellation alone is
+// the default.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct ApmStages {
+    pub bypass: bool,
+    pub echo_cancellation: bool,
+    pub noise_suppression: bool,
+    /// Adaptive digital gain, with its limiter.
+    pub gain_control: bool,
+    pub high_pass: bool,
+}
+
+impl Default for ApmStages {
+    fn default() -> Self {
+        Self {
+            bypass: false,
+            echo_cancellation: true,
+            noise_suppression: false,
+            gain_control: false,
+            high_pass: false,
+        }
+    }
+}
+
+impl ApmStages {
+    /// Turns `stage` on or off: `apm` (the whole thing), `aec`, `ns`, `agc`
+    /// or `hpf`.
+    pub fn set(&mut self, stage: &str, on: bool) -> Result<()> {
+        match stage {
+            "apm" => self.bypass = !on,
+            "aec" => self.echo_cancellation = on,
+            "ns" => self.noise_suppression = on,
+            "agc" => self.gain_control = on,
+            "hpf" => self.high_pass = on,
+            _ => bail!("unknown stage {stage:?} (apm, aec, ns, agc or hpf)"),
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for ApmStages {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.bypass {
+            return f.write_str("bypassed");
+        }
+        let stages = [
+            (self.echo_cancellation, "aec"),
+            (self.noise_suppression, "ns"),
+            (self.gain_control, "agc"),
+            (self.high_pass, "hpf"),
+        ];
+        let on: Vec<&str> = stages.iter().filter(|s| s.0).map(|s| s.1).collect();
+        match on.is_empty() {
+            true => f.write_str("on, every stage off"),
+            false => write!(f, "on ({})", on.join(", ")),
+        }
+    }
+}
+
+// ─── Voice changer ─────────────────────────────────────────────────────────────
+// A delay‑line pitch shifter: two read taps sweep through a short history at
+// `ratio` times the write speed, each faded in and out with a sin² window half
+// a cycle apart so the jumps back are inaudible. Formants move with the pitch,
+// which is what makes it work as voice masking.
+
+const MAX_SEMITONES: f32 = 12.0;
+/// ≈ 40 ms at 48 kHz; long enough for speech pitch periods, short enough not
+/// to smear consonants.
+const WINDOW: usize = 1920;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Voice {
+    Off,
+    Deep,
+    High,
+    Chipmunk,
+    Semitones(f32),
+}
+
+impl Voice {
+    fn semitones(self) -> Option<f32> {
+        match self {
+            Voice::Off => None,
+            Voice::Deep => Some(-5.0),
+            Voice::High => Some(4.0),
+            Voice::Chipmunk => Some(9.0),
+            Voice::Semitones(s) => Some(s),
+        }
+    }
+}
+
+impl FromStr for Voice {
+    type Err = anyhow::Error;
+
+    /// A preset name or a shift in semitones, e.g. `deep` or `-3.5`.
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "off" => Voice::Off,
+            "deep" => Voice::Deep,
+            "high" => Voice::High,
+            "chipmunk" => Voice::Chipmunk,
+            other => match other.parse::<f32>() {
+                Ok(semitones) if semitones.abs() <= MAX_SEMITONES => Voice::Semitones(semitones),
+                _ => bail!("expected off, deep, high, chipmunk or -12..=12 semitones"),
+            },
+        })
+    }
+}
+
+pub(crate) struct PitchShifter {
+    history: Vec<f32>,
+    write: usize,
+    /// Position of the first tap within the window, 0..1.
+    phase: f32,
+}
+
+impl PitchShifter {
+    pub(crate) fn new() -> Self {
+        Self {
+            history: vec![0.0; WINDOW],
+            write: 0,
+            phase: 0.0,
+        }
+    }
+
+    /// Shifts `frame` in place. With `ratio == None` the audio passes through
+    /// untouched but still fills the history, so re‑enabling doesn't replay
+    /// stale speech.
+    pub(crate) fn process(&mut self, frame: &mut [f32], ratio: Option<f32>) {
+        for sample in frame {
+            self.history[self.write] = *sample;
+            if let Some(ratio) = ratio {
+                let second = (self.phase + 0.5).fract();
+                let gain_first = (std::f32::consts::PI * self.phase).sin().powi(2);
+                *sample = self.tap(self.phase) * gain_first + self.tap(second) * (1.0 - gain_first);
+                self.phase = (self.phase + (1.0 - ratio) / WINDOW as f32).rem_euclid(1.0);
+            }
+            self.write = (self.write + 1) % WINDOW;
+        }
+    }
+
+    /// Reads `phase * WINDOW` samples behind the write head, interpolated.
+    fn tap(&self, phase: f32) -> f32 {
+        let delay = phase * (WINDOW - 1) as f32;
+        let pos = (self.write + WINDOW) as f32 - delay;
+        let i = pos.floor() as usize;
+        let frac = pos - pos.floor();
+        let a = self.history[i % WINDOW];
+        let b = self.history[(i + 1) % WINDOW];
+        a + (b - a) * frac
+    }
+}
+
+// ─── Compressor ────────────────────────────────────────────────────────────────
+// Feed‑forward compressor on the outgoing voice, after the APM's AGC, so a
+// laugh or a cough doesn't blast the other side. Hard knee, peak envelope in
+// the dB domain, and a final clamp that acts as a brick‑wall limiter after the
+// make‑up gain.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CompressorConfig {
+    pub threshold_db: f32,
+    /// Input dB over threshold per output dB over threshold.
+    pub ratio: f32,
+    pub attack_ms: f32,
+    pub release_ms: f32,
+    pub makeup_db: f32,
+}
+
+impl Default for CompressorConfig {
+    fn default() -> Self {
+        Self {
+            threshold_db: -18.0,
+            ratio: 4.0,
+            attack_ms: 5.0,
+            release_ms: 80.0,
+            makeup_db: 0.0,
+        }
+    }
+}
+
+pub(crate) struct Compressor {
+    sample_rate: f32,
+    /// Current gain reduction in dB (≥ 0).
+    reduction_db: f32,
+}
+
+impl Compressor {
+    pub(crate) fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate: sample_rate as f32,
+            reduction_db: 0.0,
+        }
+    }
+
+    pub(crate) fn process(&mut self, frame: &mut [f32], config: Option<CompressorConfig>) {
+        let Some(cfg) = config else {
+            self.reduction_db = 0.0;
+            return;
+        };
+        let attack = self.coefficient(cfg.attack_ms);
+        let release = self.coefficient(cfg.release_ms);
+        let slope = 1.0 - 1.0 / cfg.ratio.max(1.0);
+        for sample in frame {
+            let level_db = 20.0 * sample.abs().max(1e-6).log10();
+            let target = (level_db - cfg.threshold_db).max(0.0) * slope;
+            let coef = if target > self.reduction_db {
+                attack
+            } else {
+                release
+            };
+            self.reduction_db = target + (self.reduction_db - target) * coef;
+            let gain = db_to_gain(cfg.makeup_db - self.reduction_db);
+            *sample = (*sample * gain).clamp(-1.0, 1.0);
+        }
+    }
+
+    /// One‑pole smoothing coefficient for a time constant in milliseconds.
+    fn coefficient(&self, ms: f32) -> f32 {
+        (-1000.0 / (ms.max(0.1) * self.sample_rate)).exp()
+    }
+}
+
+fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+// ─── Ducking ───────────────────────────────────────────────────────────────────
+// For people without headphones: while the local user talks, received audio
+// is turned down by `attenuation_db`, so double‑talk is easier on the echo
+// canceller and on the ears. The gain glides down in `DUCK_ATTACK_MS` and
+// back up over `release_ms` once the voice detector's hangover ends (see
+// `vad`). Ducking happens as audio is decoded, so it lags the voice by the
+// jitter buffer's depth.
+
+const DUCK_ATTACK_MS: f32 = 20.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DuckingConfig {
+    pub attenuation_db: f32,
+    pub release_ms: f32,
+}
+
+impl Default for DuckingConfig {
+    fn default() -> Self {
+        Self {
+            attenuation_db: 12.0,
+            release_ms: 400.0,
+        }
+    }
+}
+
+pub(crate) struct Ducker {
+    sample_rate: f32,
+    /// Current attenuation in dB (≥ 0).
+    reduction_db: f32,
+}
+
+impl Ducker {
+    pub(crate) fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate: sample_rate as f32,
+            reduction_db: 0.0,
+        }
+    }
+
+    /// `pcm` is interleaved with `channels` channels.
+    pub(crate) fn process(
+        &mut self,
+        pcm: &mut [f32],
+        channels: usize,
+        speaking: bool,
+        config: Option<DuckingConfig>,
+    ) {
+        let Some(cfg) = config else {
+            self.reduction_db = 0.0;
+            return;
+        };
+        let target = if speaking {
+            cfg.attenuation_db.max(0.0)
+        } else {
+            0.0
+        };
+        if target == 0.0 && self.reduction_db < 0.01 {
+            self.reduction_db = 0.0;
+            return;
+        }
+        let ms = if target > self.reduction_db {
+            DUCK_ATTACK_MS
+        } else {
+            cfg.release_ms
+        };
+        let coef = (-1000.0 / (ms.max(0.1) * self.sample_rate)).exp();
+        for frame in pcm.chunks_mut(channels) {
+            self.reduction_db = target + (self.reduction_db - target) * coef;
+            let gain = db_to_gain(-self.reduction_db);
+            for sample in frame {
+                *sample *= gain;
+            }
+        }
+    }
+}
+
+// ─── Level normalization ───────────────────────────────────────────────────────
+// Peers arrive at very different loudness: a headset boom, a laptop across the
+// room, a phone with its own AGC. Each peer's speech level is tracked as the
+// RMS of frames above `LEVEL_GATE_DB`, averaged over `LEVEL_WINDOW_S`, so pauses
+// and background noise don't drag it down. With normalization on, the peer is
+// turned up or down toward `target_db` by at most `max_gain_db`; a gain set by
+// hand replaces that. Gain changes glide over `GAIN_GLIDE_MS`.
+
+const LEVEL_GATE_DB: f32 = -50.0;
+const LEVEL_WINDOW_S: f32 = 3.0;
+const GAIN_GLIDE_MS: f32 = 200.0;
+const MAX_PEER_GAIN_DB: f32 = 24.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NormalizeConfig {
+    /// Speech level to aim for, in dBFS RMS.
+    pub target_db: f32,
+    pub max_gain_db: f32,
+}
+
+impl Default for NormalizeConfig {
+    fn default() -> Self {
+        Self {
+            target_db: -20.0,
+            max_gain_db: 12.0,
+        }
+    }
+}
+
+/// One peer's level, as last measured.
+#[derive(Clone, Debug, Serialize)]
+pub struct PeerLevel {
+    pub peer: SocketAddr,
+    /// Speech level in dBFS RMS; `None` until they have spoken.
+    pub loudness_db: Option<f32>,
+    /// Gain applied to them.
+    pub gain_db: f32,
+    /// The gain was set by hand.
+    pub manual: bool,
+}
+
+pub(crate) struct Leveler {
+    sample_rate: f32,
+    loudness_db: Option<f32>,
+    gain_db: f32,
+    manual: bool,
+}
+
+impl Leveler {
+    pub(crate) fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate: sample_rate as f32,
+            loudness_db: None,
+            gain_db: 0.0,
+            manual: false,
+        }
+    }
+
+    /// `pcm` is interleaved with `channels` channels.
+    pub(crate) fn process(
+        &mut self,
+        pcm: &mut [f32],
+        channels: usize,
+        config: Option<NormalizeConfig>,
+        manual_db: Option<f32>,
+    ) {
+        let frames = pcm.len() / channels;
+        if frames == 0 {
+            return;
+        }
+        let power = pcm.iter().map(|s| s * s).sum::<f32>() / pcm.len() as f32;
+        let db = 10.0 * power.max(1e-12).log10();
+        if db > LEVEL_GATE_DB {
+            let k = 1.0 - (-(frames as f32) / (LEVEL_WINDOW_S * self.sample_rate)).exp();
+            let level = self.loudness_db.get_or_insert(db);
+            *level += k * (db - *level);
+        }
+        self.manual = manual_db.is_some();
+        let target = match (manual_db, config) {
+            (Some(db), _) => db,
+            (None, Some(cfg)) => self.loudness_db.map_or(0.0, |level| {
+                let max = cfg.max_gain_db.max(0.0);
+                (cfg.target_db - level).clamp(-max, max)
+            }),
+            (None, None) => 0.0,
+        };
+        if target == 0.0 && self.gain_db.abs() < 0.01 {
+            self.gain_db = 0.0;
+            return;
+        }
+        let coef = (-1000.0 / (GAIN_GLIDE_MS * self.sample_rate)).exp();
+        for frame in pcm.chunks_mut(channels) {
+            self.gain_db = target + (self.gain_db - target) * coef;
+            let gain = db_to_gain(self.gain_db);
+            for sample in frame {
+                *sample = (*sample * gain).clamp(-1.0, 1.0);
+            }
+        }
+    }
+
+    pub(crate) fn level(&self, peer: SocketAddr) -> PeerLevel {
+        PeerLevel {
+            peer,
+            loudness_db: self.loudness_db,
+            gain_db: self.gain_db,
+            manual: self.manual,
+        }
+    }
+}
+
+// ─── Stream gain ───────────────────────────────────────────────────────────────
+// A listener's own volume and mute for each stream, applied as it is decoded
+// and before it joins the recording and the playback ring – unlike the output
+// device's volume, which moves everyone. It comes after normalization, so the
+// leveler doesn't undo it. Changes ramp over `STREAM_RAMP_MS`, quick enough
+// for a mute to feel instant but slow enough not to click. A gain for the
+// whole call (`set_output_gain`) adds to each stream's. Each output device
+// has a volume of its own (`set_device_volume`), ramped the same way after the
+// mix – the main one and, with `--mirror-device`, the mirror – and so does
+// each input device ahead of the APM (`set_input_gain`).
+
+const STREAM_RAMP_MS: f32 = 10.0;
+const MAX_STREAM_GAIN_DB: f32 = 24.0;
+
+/// An output device, when playback is mirrored to a second one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Output {
+    Main,
+    /// `--mirror-device`.
+    Mirror,
+}
+
+impl FromStr for Output {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "main" => Ok(Output::Main),
+            "mirror" => Ok(Output::Mirror),
+            _ => bail!("unknown output {s:?} (main or mirror)"),
+        }
+    }
+}
+
+/// An input device, when a second one is mixed into what we send.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Input {
+    Main,
+    /// `--extra-input`.
+    Extra,
+}
+
+impl FromStr for Input {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "main" => Ok(Input::Main),
+            "extra" => Ok(Input::Extra),
+            _ => bail!("unknown input {s:?} (main or extra)"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct StreamOutput {
+    pub gain_db: f32,
+    pub muted: bool,
+}
+
+impl StreamOutput {
+    fn gain(self) -> f32 {
+        match self.muted {
+            true => 0.0,
+            false => db_to_gain(self.gain_db),
+        }
+    }
+}
+
+pub(crate) struct StreamGain {
+    coef: f32,
+    /// Linear, as of the last sample.
+    gain: f32,
+}
+
+impl StreamGain {
+    pub(crate) fn new(sample_rate: u32) -> Self {
+        Self {
+            coef: (-1000.0 / (STREAM_RAMP_MS * sample_rate as f32)).exp(),
+            gain: 1.0,
+        }
+    }
+
+    /// `pcm` is interleaved with `channels` channels.
+    pub(crate) fn process(&mut self, pcm: &mut [f32], channels: usize, output: StreamOutput) {
+        let target = output.gain();
+        if (self.gain - target).abs() < 1e-4 {
+            self.gain = target;
+            if target == 1.0 {
+                return;
+            }
+        }
+        for frame in pcm.chunks_mut(channels) {
+            self.gain = target + (self.gain - target) * self.coef;
+            for sample in frame {
+                *sample = (*sample * self.gain).clamp(-1.0, 1.0);
+            }
+        }
+    }
+}
+
+// ─── Playback EQ ───────────────────────────────────────────────────────────────
+// Three RBJ‑cookbook biquads on each peer's decoded audio: a low shelf against
+// boomy/muddy mics, a presence peak for intelligibility and an optional high
+// cut for harsh or hissy ones. Each decoder owns its own `Equalizer`, so the
+// filter state is per peer, and so can the settings be: `set_peer_eq`
+// overrides the call's `set_eq` for one peer.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EqConfig {
+    pub low_shelf_hz: f32,
+    pub low_shelf_db: f32,
+    pub presence_hz: f32,
+    pub presence_db: f32,
+    /// Low‑pass corner; `None` leaves the top end alone.
+    pub high_cut_hz: Option<f32>,
+}
+
+impl Default for EqConfig {
+    fn default() -> Self {
+        Self {
+            low_shelf_hz: 150.0,
+            low_shelf_db: 0.0,
+            presence_hz: 3000.0,
+            presence_db: 0.0,
+            high_cut_hz: None,
+        }
+    }
+}
+
+pub(crate) struct Equalizer {
+    sample_rate: f32,
+    config: Option<EqConfig>,
+    bands: [Biquad; 3],
+}
+
+impl Equalizer {
+    pub(crate) fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate: sample_rate as f32,
+            config: None,
+            bands: [Biquad::IDENTITY; 3],
+        }
+    }
+
+    pub(crate) fn process(&mut self, frame: &mut [f32], config: Option<EqConfig>) {
+        if config != self.config {
+            self.configure(config);
+        }
+        if self.config.is_none() {
+            return;
+        }
+        for sample in frame {
+            *sample = self.bands.iter_mut().fold(*sample, |s, band| band.run(s));
+        }
+    }
+
+    /// Swaps coefficients but keeps the filter state, so adjusting the EQ
+    /// mid‑call doesn't click.
+    fn configure(&mut self, config: Option<EqConfig>) {
+        self.config = config;
+        let Some(cfg) = config else {
+            self.bands = [Biquad::IDENTITY; 3];
+            return;
+        };
+        let fs = self.sample_rate;
+        let nyquist = fs / 2.0;
+        self.bands[0].set(Biquad::low_shelf(fs, cfg.low_shelf_hz, cfg.low_shelf_db));
+        self.bands[1].set(Biquad::peaking(fs, cfg.presence_hz, 1.0, cfg.presence_db));
+        self.bands[2].set(match cfg.high_cut_hz {
+            Some(hz) if hz < nyquist => Biquad::low_pass(fs, hz, std::f32::consts::FRAC_1_SQRT_2),
+            _ => Biquad::IDENTITY,
+        });
+    }
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    z1: f32,
+    z2: f32,
+}
+
+impl Biquad {
+    const IDENTITY: Self = Self {
+        b0: 1.0,
+        b1: 0.0,
+        b2: 0.0,
+        a1: 0.0,
+        a2: 0.0,
+        z1: 0.0,
+        z2: 0.0,
+    };
+
+    /// Normalises by `a0`; state starts at zero.
+    fn new(b0: f32, b1: f32, b2: f32, a0: f32, a1: f32, a2: f32) -> Self {
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    fn low_shelf(fs: f32, hz: f32, db: f32) -> Self {
+        let a = 10f32.powf(db / 40.0);
+        let (sin, cos) = (std::f32::consts::TAU * hz / fs).sin_cos();
+        // Shelf slope S = 1.
+        let k = 2.0 * a.sqrt() * sin / std::f32::consts::SQRT_2;
+        Self::new(
+            a * ((a + 1.0) - (a - 1.0) * cos + k),
+            2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+            a * ((a + 1.0) - (a - 1.0) * cos - k),
+            (a + 1.0) + (a - 1.0) * cos + k,
+            -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+            (a + 1.0) + (a - 1.0) * cos - k,
+        )
+    }
+
+    fn peaking(fs: f32, hz: f32, q: f32, db: f32) -> Self {
+        let a = 10f32.powf(db / 40.0);
+        let (sin, cos) = (std::f32::consts::TAU * hz / fs).sin_cos();
+        let alpha = sin / (2.0 * q);
+        Self::new(
+            1.0 + alpha * a,
+            -2.0 * cos,
+            1.0 - alpha * a,
+            1.0 + alpha / a,
+            -2.0 * cos,
+            1.0 - alpha / a,
+        )
+    }
+
+    pub(crate) fn low_pass(fs: f32, hz: f32, q: f32) -> Self {
+        let (sin, cos) = (std::f32::consts::TAU * hz / fs).sin_cos();
+        let alpha = sin / (2.0 * q);
+        Self::new(
+            (1.0 - cos) / 2.0,
+            1.0 - cos,
+            (1.0 - cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        )
+    }
+
+    fn set(&mut self, coefficients: Self) {
+        *self = Self {
+            z1: self.z1,
+            z2: self.z2,
+            ..coefficients
+        };
+    }
+
+    /// Transposed direct form II.
+    pub(crate) fn run(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y
+    }
+}
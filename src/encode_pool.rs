@@ -0,0 +1,120 @@
+// ─── Encoder pool ──────────────────────────────────────────────────────────────
+// Extra encodings of what we send, each at a bitrate of its own, for
+// broadcasts that serve listeners on slower links (`--broadcast-tier`). The
+// capture chain hands every processed frame to `Pool::submit` and moves on;
+// the encoding happens on worker threads, so a handful of tiers costs the
+// audio callback no more than one. Each stream's encoder stays on one worker
+// for good (stream i on worker i mod n), which keeps its state and its frames
+// in order without locking. Every worker has a bounded queue, and one that
+// falls behind loses frames rather than holding up capture. Encoded frames
+// come out on one channel, tagged with their stream.
+
+use crate::{codec, MAX_SURROUND_PACKET_SIZE, SAMPLE_RATE};
+use anyhow::Result;
+use async_channel::Sender;
+use bytes::Bytes;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use tracing::{debug, error, info};
+
+/// Frames a worker may have waiting.
+const QUEUE: usize = 4;
+
+pub(crate) struct Pool {
+    /// Dropped first, which ends the workers.
+    queues: Vec<SyncSender<Arc<[f32]>>>,
+    workers: Vec<JoinHandle<()>>,
+    /// Frames a full queue turned away, or encoded frames the channel had
+    /// no room for.
+    dropped: Arc<AtomicU64>,
+}
+
+impl Pool {
+    /// One stream per entry of `bitrates` (bits a second), encoded like
+    /// the main one otherwise; frames go to `out` as (index, frame).
+    pub fn new(
+        channels: u8,
+        opts: &codec::EncoderOptions,
+        bitrates: &[u32],
+        out: Sender<(usize, Bytes)>,
+    ) -> Result<Self> {
+        // Leave a core to the audio and network threads.
+        let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let n = cores.saturating_sub(1).clamp(1, bitrates.len().max(1));
+        let mut assigned: Vec<Vec<(usize, codec::Encoder)>> = (0..n).map(|_| Vec::new()).collect();
+        for (i, &bps) in bitrates.iter().enumerate() {
+            let mut enc = codec::Encoder::new(SAMPLE_RATE, channels)?;
+            enc.apply(opts)?;
+            enc.set_bitrate(Some(bps as i32))?;
+            assigned[i % n].push((i, enc));
+        }
+        let dropped = Arc::new(AtomicU64::new(0));
+        let mut queues = Vec::with_capacity(n);
+        let mut workers = Vec::with_capacity(n);
+        for (w, encoders) in assigned.into_iter().enumerate() {
+            let (tx, rx) = sync_channel::<Arc<[f32]>>(QUEUE);
+            let out = out.clone();
+            let dropped = Arc::clone(&dropped);
+            let worker = std::thread::Builder::new()
+                .name(format!("voice-encode-{w}"))
+                .spawn(move || encode(encoders, rx, out, dropped))?;
+            queues.push(tx);
+            workers.push(worker);
+        }
+        info!(
+            "encoding {} extra streams on {n} worker thread(s)",
+            bitrates.len()
+        );
+        Ok(Self {
+            queues,
+            workers,
+            dropped,
+        })
+    }
+
+    /// Queues one processed frame for every stream. Never blocks.
+    pub fn submit(&self, pcm: &[f32]) {
+        let pcm: Arc<[f32]> = Arc::from(pcm);
+        for queue in &self.queues {
+            if let Err(TrySendError::Full(_)) = queue.try_send(Arc::clone(&pcm)) {
+                let n = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                debug!("encoder pool behind; {n} frames dropped so far");
+            }
+        }
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        self.queues.clear();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn encode(
+    mut encoders: Vec<(usize, codec::Encoder)>,
+    frames: std::sync::mpsc::Receiver<Arc<[f32]>>,
+    out: Sender<(usize, Bytes)>,
+    dropped: Arc<AtomicU64>,
+) {
+    let mut buf = [0u8; MAX_SURROUND_PACKET_SIZE];
+    for pcm in frames {
+        for (i, enc) in &mut encoders {
+            match enc.encode_float(&pcm, &mut buf) {
+                // DTX: the main stream's silence marker covers it.
+                Ok(len) if len <= 2 => {}
+                Ok(len) => {
+                    let frame = Bytes::copy_from_slice(&buf[..len]);
+                    if out.try_send((*i, frame)).is_err() {
+                        dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                Err(e) => error!("opus encode error: {e}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,147 @@
+// ─── Connection fallback ───────────────────────────────────────────────────────
+// A caller that knows where the peer is tries the ways of reaching it in
+// order, each for a while, until the handshake completes:
+//   1. `Lan` – the LAN address the rendezvous reported, for a peer on the
+//      same network, even behind a NAT that won't hairpin,
+//   2. `Punch` – the reflexive address, both sides sending so that each NAT
+//      opens a way in for the other,
+//   3. `Turn` – through our TURN allocation, or straight to the peer's,
+//      whichever sides have one (see `turn`),
+//   4. `Relay` – through the `--relay` machine (see `relay`),
+//   5. `Tcp` – the same relay over TCP, for networks that block UDP.
+// Steps that don't apply (no LAN address, no TURN server on either side, no
+// relay) are left out. Each step starts with a `STATUS: connect_step` line;
+// when the last one runs out the call keeps trying it, and
+// `STATUS: connect_failed` says what was tried, so a call that can't
+// connect says why instead of staying silent.
+
+use bytes::Bytes;
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+const LAN_TIMEOUT: Duration = Duration::from_secs(2);
+const PUNCH_TIMEOUT: Duration = crate::relay::FALLBACK_AFTER;
+const TURN_TIMEOUT: Duration = Duration::from_secs(8);
+const RELAY_TIMEOUT: Duration = Duration::from_secs(10);
+const TCP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// In the order they're tried.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Step {
+    Lan,
+    Punch,
+    Turn,
+    Relay,
+    Tcp,
+}
+
+impl Step {
+    fn timeout(self) -> Duration {
+        match self {
+            Step::Lan => LAN_TIMEOUT,
+            Step::Punch => PUNCH_TIMEOUT,
+            Step::Turn => TURN_TIMEOUT,
+            Step::Relay => RELAY_TIMEOUT,
+            Step::Tcp => TCP_TIMEOUT,
+        }
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Step::Lan => "lan",
+            Step::Punch => "punch",
+            Step::Turn => "turn",
+            Step::Relay => "relay",
+            Step::Tcp => "tcp",
+        })
+    }
+}
+
+pub(crate) struct Attempt {
+    pub step: Step,
+    /// Where Hellos go during this step.
+    pub addr: SocketAddr,
+    /// Where they leave this host for: `addr`, unless that is our end of a
+    /// bridge (see `turn`, `relay::tunnel`). For status lines.
+    pub target: SocketAddr,
+    /// Sent ahead of each Hello: the relay's pairing request.
+    pub bind: Option<Bytes>,
+}
+
+pub(crate) struct Chain {
+    attempts: Vec<Attempt>,
+    current: usize,
+    started: Instant,
+    failed: bool,
+}
+
+impl Chain {
+    /// `attempts` must not be empty.
+    pub fn new(attempts: Vec<Attempt>) -> Self {
+        let first = &attempts[0];
+        info!("STATUS: connect_step {} {}", first.step, first.target);
+        Self {
+            attempts,
+            current: 0,
+            started: Instant::now(),
+            failed: false,
+        }
+    }
+
+    pub fn current(&self) -> &Attempt {
+        &self.attempts[self.current]
+    }
+
+    /// Moves on once the current step has had its time; returns the address
+    /// it leaves.
+    pub fn advance(&mut self) -> Option<SocketAddr> {
+        let step = self.current().step;
+        if self.started.elapsed() < step.timeout() {
+            return None;
+        }
+        if self.current + 1 == self.attempts.len() {
+            if !self.failed {
+                self.failed = true;
+                let reason = self.reason();
+                warn!(%reason, "STATUS: connect_failed {reason}");
+            }
+            return None;
+        }
+        let left = self.current().addr;
+        let from = self.current().target;
+        self.current += 1;
+        self.started = Instant::now();
+        let next = self.current();
+        info!("no answer over {step} from {from}; trying {}", next.step);
+        info!("STATUS: connect_step {} {}", next.step, next.target);
+        Some(left)
+    }
+
+    /// What was tried, for people.
+    fn reason(&self) -> String {
+        let tried: Vec<_> = self
+            .attempts
+            .iter()
+            .map(|a| {
+                format!(
+                    "{} {} for {} s",
+                    a.step,
+                    a.target,
+                    a.step.timeout().as_secs()
+                )
+            })
+            .collect();
+        let mut reason = format!("no answer after trying {}", tried.join(", then "));
+        if !self.attempts.iter().any(|a| a.step == Step::Turn) {
+            reason += "; neither side has a TURN server";
+        }
+        if !self.attempts.iter().any(|a| a.step == Step::Relay) {
+            reason += "; no relay is configured";
+        }
+        reason
+    }
+}
@@ -0,0 +1,75 @@
+// ─── C ABI ─────────────────────────────────────────────────────────────────────
+// Plain C entry points for embedding the engine in non‑Rust apps. The iOS
+// static library exposes these through `include/voice_chat.h`; the Swift
+// wrapper in `ios/` handles AVAudioSession around them.
+
+use crate::{SessionConfig, SessionThread};
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::ptr;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(msg: String) {
+    LAST_ERROR.with(|e| *e.borrow_mut() = CString::new(msg).ok());
+}
+
+/// Opaque handle returned by `voice_chat_start`.
+pub struct VoiceChatSession {
+    _session: SessionThread,
+}
+
+/// Starts a call; returns NULL on failure (see `voice_chat_last_error`).
+///
+/// # Safety
+/// `peer` must be NULL or point to a NUL‑terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn voice_chat_start(
+    local_port: u16,
+    peer: *const c_char,
+) -> *mut VoiceChatSession {
+    let peer = if peer.is_null() {
+        None
+    } else {
+        match CStr::from_ptr(peer).to_str() {
+            Ok(s) => Some(s.to_owned()),
+            Err(_) => {
+                set_last_error("peer is not valid UTF-8".into());
+                return ptr::null_mut();
+            }
+        }
+    };
+    let config = SessionConfig {
+        local_port,
+        peer,
+        ..Default::default()
+    };
+    match SessionThread::spawn(config) {
+        Ok(session) => Box::into_raw(Box::new(VoiceChatSession { _session: session })),
+        Err(e) => {
+            set_last_error(format!("{e:#}"));
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Ends the call and frees the handle.
+///
+/// # Safety
+/// `session` must be NULL or a handle from `voice_chat_start` that has not
+/// been stopped yet.
+#[no_mangle]
+pub unsafe extern "C" fn voice_chat_stop(session: *mut VoiceChatSession) {
+    if !session.is_null() {
+        drop(Box::from_raw(session));
+    }
+}
+
+/// Message for the last failed call on this thread, or NULL. Valid until the
+/// next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn voice_chat_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |s| s.as_ptr()))
+}
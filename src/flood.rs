@@ -0,0 +1,235 @@
+// ─── Inbound flood protection ──────────────────────────────────────────────────
+// Every receive loop checks each datagram against its source's packet and byte
+// budgets before parsing it or trying any crypto, so a flood costs us a hash
+// lookup per packet instead of an AEAD open and can't starve the real peers'
+// audio. Budgets are token buckets per source IP (rotating ports doesn't buy a
+// fresh one) holding half a second of burst. A source that stays over budget
+// for `STRIKES` seconds in a row is banned and everything it sends is dropped
+// unread until the ban runs out.
+//
+// A sealed packet from an address no peer uses may be a peer that moved, and
+// finding out costs an AEAD open per keyed peer (see `moved_from`). Those
+// trials have budgets of their own, per source and for all sources together,
+// so spoofed addresses can't turn one packet into many opens; a peer that
+// really moved needs only one to get through.
+//
+// The defaults are several times what a peer sends – 10 ms surround frames
+// with FEC, Pings and Hellos come to about 110 packets and 60 KB a second – so
+// only abuse hits them.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Seconds of budget a source may save up.
+const BURST_SECS: f64 = 0.5;
+/// Consecutive seconds over budget before a ban.
+const STRIKES: u32 = 3;
+/// Sources tracked at most; idle ones are forgotten first.
+const MAX_SOURCES: usize = 4096;
+/// A source quiet this long is forgotten (unless banned).
+const IDLE: Duration = Duration::from_secs(30);
+/// Packets a second tried against every peer's key, from one source and from
+/// all of them.
+const TRIALS_PER_SEC: u32 = 10;
+const TOTAL_TRIALS_PER_SEC: u32 = 100;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// Packets a second per source; 0 for no limit.
+    pub packets_per_sec: u32,
+    /// Bytes a second per source; 0 for no limit.
+    pub bytes_per_sec: u32,
+    /// How long a source that keeps flooding is ignored.
+    pub ban: Duration,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            packets_per_sec: 500,
+            bytes_per_sec: 256 * 1024,
+            ban: Duration::from_secs(60),
+        }
+    }
+}
+
+impl Limits {
+    fn enabled(&self) -> bool {
+        self.packets_per_sec > 0 || self.bytes_per_sec > 0
+    }
+}
+
+/// One receive loop's view of its sources.
+pub(crate) struct Guard {
+    limits: Limits,
+    sources: HashMap<IpAddr, Source>,
+    /// Packets dropped from sources we had no room to track.
+    untracked: u64,
+    trials: Bucket,
+}
+
+struct Source {
+    packets: Bucket,
+    bytes: Bucket,
+    trials: Bucket,
+    /// Start of the current one‑second window, and whether anything in it
+    /// went over budget.
+    window: Instant,
+    over: bool,
+    strikes: u32,
+    banned_until: Option<Instant>,
+    /// Dropped since the last report.
+    dropped: u64,
+    seen: Instant,
+}
+
+impl Guard {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            sources: HashMap::new(),
+            untracked: 0,
+            trials: Bucket::new(TOTAL_TRIALS_PER_SEC, Instant::now()),
+        }
+    }
+
+    /// Whether to look at a `len`‑byte datagram from `src` at all.
+    pub fn admit(&mut self, src: SocketAddr, len: usize) -> bool {
+        if !self.limits.enabled() {
+            return true;
+        }
+        let now = Instant::now();
+        let ip = src.ip();
+        if !self.sources.contains_key(&ip) && self.sources.len() >= MAX_SOURCES {
+            self.sweep(now);
+            if self.sources.len() >= MAX_SOURCES {
+                self.untracked += 1;
+                if self.untracked.is_power_of_two() {
+                    warn!(
+                        "flood: tracking {MAX_SOURCES} sources; dropped {} packets from new ones",
+                        self.untracked
+                    );
+                }
+                return false;
+            }
+        }
+        let limits = self.limits;
+        let source = self.sources.entry(ip).or_insert_with(|| Source {
+            packets: Bucket::new(limits.packets_per_sec, now),
+            bytes: Bucket::new(limits.bytes_per_sec, now),
+            trials: Bucket::new(TRIALS_PER_SEC, now),
+            window: now,
+            over: false,
+            strikes: 0,
+            banned_until: None,
+            dropped: 0,
+            seen: now,
+        });
+        source.seen = now;
+        match source.banned_until {
+            Some(until) if now < until => {
+                source.dropped += 1;
+                return false;
+            }
+            Some(_) => {
+                info!(
+                    "flood: ban on {ip} lifted ({} packets dropped)",
+                    source.dropped
+                );
+                source.banned_until = None;
+                source.strikes = 0;
+                source.dropped = 0;
+            }
+            None => {}
+        }
+        if now - source.window >= Duration::from_secs(1) {
+            source.strikes = if source.over { source.strikes + 1 } else { 0 };
+            source.over = false;
+            source.window = now;
+            if source.strikes >= STRIKES {
+                source.banned_until = Some(now + limits.ban);
+                warn!(
+                    "flood: banning {ip} for {} s after {} packets over budget",
+                    limits.ban.as_secs(),
+                    source.dropped
+                );
+                source.dropped = 0;
+                return false;
+            }
+        }
+        // Both budgets are charged only for packets that get through.
+        let admitted = source.packets.has(1.0, now) && source.bytes.has(len as f64, now);
+        match admitted {
+            true => {
+                source.packets.take(1.0);
+                source.bytes.take(len as f64);
+            }
+            false => {
+                source.over = true;
+                source.dropped += 1;
+            }
+        }
+        admitted
+    }
+
+    /// Whether an admitted packet from `src` may be tried against every
+    /// peer's key.
+    pub fn trial(&mut self, src: SocketAddr) -> bool {
+        let now = Instant::now();
+        let mut source = match self.sources.get_mut(&src.ip()) {
+            Some(source) => Some(&mut source.trials),
+            // Without limits `admit` tracks no sources.
+            None if !self.limits.enabled() => None,
+            None => return false,
+        };
+        let admitted = self.trials.has(1.0, now) && source.as_mut().is_none_or(|b| b.has(1.0, now));
+        if admitted {
+            self.trials.take(1.0);
+            if let Some(bucket) = source {
+                bucket.take(1.0);
+            }
+        }
+        admitted
+    }
+
+    fn sweep(&mut self, now: Instant) {
+        self.sources
+            .retain(|_, s| s.banned_until.is_some_and(|until| now < until) || now - s.seen < IDLE);
+    }
+}
+
+/// A token bucket; a rate of 0 is unlimited.
+struct Bucket {
+    rate: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    fn new(rate: u32, now: Instant) -> Self {
+        let rate = rate as f64;
+        Self {
+            rate,
+            tokens: rate * BURST_SECS,
+            refilled: now,
+        }
+    }
+
+    fn has(&mut self, amount: f64, now: Instant) -> bool {
+        if self.rate == 0.0 {
+            return true;
+        }
+        let elapsed = (now - self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate * BURST_SECS);
+        self.refilled = now;
+        self.tokens >= amount
+    }
+
+    fn take(&mut self, amount: f64) {
+        if self.rate > 0.0 {
+            self.tokens -= amount;
+        }
+    }
+}
@@ -0,0 +1,330 @@
+// ─── Frame streams ─────────────────────────────────────────────────────────────
+// For applications that route audio themselves instead of through cpal, or
+// want a copy of it for visualisation, analysis or another system.
+//
+// `VoiceSession::incoming_audio` hands out each sender's audio as
+// `AudioFrame`s on an async channel (a `futures::Stream` too), straight out of
+// the decoder: 48 kHz, one frame per packet, concealed and FEC frames
+// included, before our EQ, levelling, ducking and mixing. Set
+// `AudioOptions::playback` off, or a `sink`, to keep the output device closed
+// as well. Two more taps work the same way: `outgoing_packets`, every Opus
+// packet as it leaves the encoder, and `mixed_audio`, the mix as it goes to the
+// output, 48 kHz in the mixer's layout, before the device's volume. Every call
+// subscribes anew, nothing is copied while no one listens, and a subscriber
+// that falls behind loses items rather than holding up the audio.
+//
+// `outgoing` is the other direction: a `FrameSender` to push interleaved
+// samples into and the `AudioSource` it feeds, which goes in
+// `SessionConfig::source` and runs through the capture chain like a
+// microphone. The queue is short, so `send` paces the producer at the call's
+// own rate; while it's empty the call sends silence, and dropping the sender
+// ends the source.
+//
+// To send audio alongside the microphone instead – speech synthesis, game
+// sounds – push it to `VoiceSession::injector`. It takes any rate and layout,
+// converts it to what we send on the caller's thread and queues it; the
+// capture chain takes a frame's worth at a time, ahead of the APM, and mixes
+// it with the microphone as the clip's `MixMode` says. Injected audio counts
+// as input like any other: it wakes an idle chain, and muting or holding the
+// call silences it too.
+
+use crate::source::AudioSource;
+use crate::{resample, surround, SAMPLE_RATE};
+use anyhow::{bail, Result};
+use async_channel::{bounded, Receiver, Sender, TrySendError};
+use bytes::Bytes;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Items kept for a subscriber that isn't reading: about a second of 20 ms
+/// frames.
+const BACKLOG: usize = 50;
+/// Blocks `outgoing` queues before `send` waits.
+const OUTGOING_BLOCKS: usize = 8;
+/// Most the injector queues; pushes past it are refused.
+const MAX_INJECTED: Duration = Duration::from_secs(60);
+/// What `MixMode::Duck` does to the microphone.
+const DUCK_GAIN: f32 = 0.18;
+
+/// One decoded frame from one sender.
+#[derive(Clone, Debug)]
+pub struct AudioFrame {
+    pub peer: SocketAddr,
+    /// Always 48 kHz.
+    pub sample_rate: u32,
+    pub channels: usize,
+    /// Interleaved, in WAV/SMPTE order.
+    pub samples: Vec<f32>,
+}
+
+/// Opus as we send it, before encryption.
+#[derive(Clone, Debug)]
+pub struct EncodedPacket {
+    pub payload: Bytes,
+    pub frame_ms: u8,
+    pub channels: usize,
+}
+
+/// One block of what we play.
+#[derive(Clone, Debug)]
+pub struct MixedBlock {
+    /// Always 48 kHz.
+    pub sample_rate: u32,
+    pub channels: usize,
+    /// Interleaved, in Vorbis order like the mixer.
+    pub samples: Vec<f32>,
+}
+
+/// Who gets copies from one tap point.
+pub(crate) struct Subscribers<T> {
+    senders: Mutex<Vec<Sender<T>>>,
+    /// Spares the audio path the lock while no one listens.
+    any: AtomicBool,
+}
+
+impl<T> Default for Subscribers<T> {
+    fn default() -> Self {
+        Self {
+            senders: Mutex::new(Vec::new()),
+            any: AtomicBool::new(false),
+        }
+    }
+}
+
+impl<T: Clone> Subscribers<T> {
+    pub fn subscribe(&self) -> Receiver<T> {
+        let (tx, rx) = bounded(BACKLOG);
+        self.senders.lock().push(tx);
+        self.any.store(true, Ordering::Relaxed);
+        rx
+    }
+
+    /// Hands what `make` returns to every subscriber; `make` only runs if
+    /// there are any. Never blocks.
+    pub fn push(&self, make: impl FnOnce() -> T) {
+        if !self.any.load(Ordering::Relaxed) {
+            return;
+        }
+        let item = make();
+        let mut senders = self.senders.lock();
+        senders.retain(|tx| !matches!(tx.try_send(item.clone()), Err(TrySendError::Closed(_))));
+        self.any.store(!senders.is_empty(), Ordering::Relaxed);
+    }
+}
+
+/// A source fed from the application, and the handle that feeds it.
+pub fn outgoing(sample_rate: u32, channels: usize) -> (FrameSender, FrameSource) {
+    let (tx, rx) = bounded(OUTGOING_BLOCKS);
+    let source = FrameSource {
+        rate: sample_rate,
+        channels: channels.max(1),
+        rx,
+        pending: Vec::new(),
+        pos: 0,
+    };
+    (FrameSender { tx }, source)
+}
+
+/// Pushes audio into an `outgoing` source.
+#[derive(Clone)]
+pub struct FrameSender {
+    tx: Sender<Vec<f32>>,
+}
+
+impl FrameSender {
+    /// Queues interleaved samples of any length, waiting while the queue is
+    /// full; fails once the session has ended.
+    pub async fn send(&self, samples: Vec<f32>) -> Result<()> {
+        if self.tx.send(samples).await.is_err() {
+            bail!("the session has ended");
+        }
+        Ok(())
+    }
+
+    /// Like `send`, but gives the samples back instead of waiting.
+    pub fn try_send(&self, samples: Vec<f32>) -> Result<(), Vec<f32>> {
+        self.tx.try_send(samples).map_err(|e| e.into_inner())
+    }
+}
+
+/// The `AudioSource` half of `outgoing`.
+pub struct FrameSource {
+    rate: u32,
+    channels: usize,
+    rx: Receiver<Vec<f32>>,
+    /// The block being read, and how far.
+    pending: Vec<f32>,
+    pos: usize,
+}
+
+impl AudioSource for FrameSource {
+    fn sample_rate(&self) -> u32 {
+        self.rate
+    }
+
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn read(&mut self, buf: &mut [f32]) -> usize {
+        let want = buf.len() / self.channels * self.channels;
+        let mut n = 0;
+        while n < want {
+            if self.pos == self.pending.len() {
+                match self.rx.try_recv() {
+                    Ok(block) => {
+                        self.pending = block;
+                        self.pos = 0;
+                        continue;
+                    }
+                    // Gone and drained: whatever is left, then the end.
+                    Err(_) if self.rx.is_closed() => {
+                        let frames = n.div_ceil(self.channels);
+                        buf[n..frames * self.channels].fill(0.0);
+                        return frames;
+                    }
+                    // Nothing yet: silence, so the call keeps time.
+                    Err(_) => {
+                        buf[n..want].fill(0.0);
+                        return want / self.channels;
+                    }
+                }
+            }
+            let take = (want - n).min(self.pending.len() - self.pos);
+            buf[n..n + take].copy_from_slice(&self.pending[self.pos..self.pos + take]);
+            self.pos += take;
+            n += take;
+        }
+        want / self.channels
+    }
+}
+
+/// How injected audio meets the microphone.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MixMode {
+    /// Added to it.
+    #[default]
+    Mix,
+    /// Added to it turned down by about 15 dB, as on a radio.
+    Duck,
+    /// Sent instead of it.
+    Replace,
+}
+
+impl std::str::FromStr for MixMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "mix" => Ok(Self::Mix),
+            "duck" => Ok(Self::Duck),
+            "replace" => Ok(Self::Replace),
+            _ => bail!("unknown mix mode {s:?} (mix, duck, replace)"),
+        }
+    }
+}
+
+/// Audio queued to go out with the microphone; see `VoiceSession::injector`.
+pub struct Injector {
+    /// What we send: 48 kHz in Vorbis order with this many channels.
+    channels: usize,
+    clips: Mutex<VecDeque<Clip>>,
+}
+
+struct Clip {
+    samples: Vec<f32>,
+    pos: usize,
+    mode: MixMode,
+}
+
+impl Injector {
+    pub(crate) fn new(channels: usize) -> Self {
+        Self {
+            channels,
+            clips: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Queues `samples` (interleaved, WAV/SMPTE order) to go out after what
+    /// is queued already. Fails if that would queue more than a minute.
+    pub fn push(
+        &self,
+        samples: &[f32],
+        sample_rate: u32,
+        channels: usize,
+        mode: MixMode,
+    ) -> Result<()> {
+        if channels == 0 || sample_rate == 0 {
+            bail!("{channels} channels at {sample_rate} Hz");
+        }
+        let seconds = samples.len() as f64 / channels as f64 / sample_rate as f64;
+        if self.queued().as_secs_f64() + seconds > MAX_INJECTED.as_secs_f64() {
+            bail!("the injector already has {:?} queued", self.queued());
+        }
+        let (from, to) = (surround::wav(channels), surround::vorbis(self.channels));
+        let mut resampler = resample::Resampler::new(sample_rate, SAMPLE_RATE, self.channels);
+        let mut remixed = vec![0f32; self.channels];
+        let mut converted = Vec::with_capacity(
+            (seconds * SAMPLE_RATE as f64) as usize * self.channels + self.channels,
+        );
+        for frame in samples.chunks_exact(channels) {
+            surround::remix(frame, from, &mut remixed, to);
+            resampler.push(&remixed, |f| converted.extend_from_slice(f));
+        }
+        self.clips.lock().push_back(Clip {
+            samples: converted,
+            pos: 0,
+            mode,
+        });
+        Ok(())
+    }
+
+    /// Drops everything queued, including what is playing.
+    pub fn clear(&self) {
+        self.clips.lock().clear();
+    }
+
+    /// How much is still to go out.
+    pub fn queued(&self) -> Duration {
+        let samples: usize = self
+            .clips
+            .lock()
+            .iter()
+            .map(|c| c.samples.len() - c.pos)
+            .sum();
+        Duration::from_secs_f64(samples as f64 / self.channels as f64 / SAMPLE_RATE as f64)
+    }
+
+    pub(crate) fn pending(&self) -> bool {
+        !self.clips.lock().is_empty()
+    }
+
+    /// Mixes the next frame's worth into `frame`, 48 kHz in our layout.
+    pub(crate) fn apply(&self, frame: &mut [f32]) {
+        let mut clips = self.clips.lock();
+        let mut at = 0;
+        while at < frame.len() {
+            let Some(clip) = clips.front_mut() else {
+                return;
+            };
+            let take = (frame.len() - at).min(clip.samples.len() - clip.pos);
+            let from = &clip.samples[clip.pos..clip.pos + take];
+            for (d, s) in frame[at..at + take].iter_mut().zip(from) {
+                let mic = match clip.mode {
+                    MixMode::Mix => *d,
+                    MixMode::Duck => *d * DUCK_GAIN,
+                    MixMode::Replace => 0.0,
+                };
+                *d = (mic + s).clamp(-1.0, 1.0);
+            }
+            clip.pos += take;
+            at += take;
+            if clip.pos == clip.samples.len() {
+                clips.pop_front();
+            }
+        }
+    }
+}
@@ -0,0 +1,519 @@
+// ─── gRPC control ──────────────────────────────────────────────────────────────
+// With the `grpc` feature a call can be run from another process or machine:
+// `serve` exposes the `Control` service of proto/control.proto (package
+// `voicechat.control.v1`) on a TCP address, for backends in any language that
+// orchestrate headless clients. It does for the call what the stdin commands
+// do, and streams what changes:
+//
+//   SetMuted, Hold, Resume       our side of the call
+//   SetPeerVolume, SetPeerMuted  a sender's playback, by its ip:port
+//   SetOutputGain, SetVad        the mix, and what counts as speech
+//   GetStats, GetHealth          one snapshot
+//   WatchRoster                  the room's other members, when they change
+//   WatchHealth                  the `/healthz` report, when it changes
+//   WatchStats                   the stats, every `interval_ms`
+//
+//   • With a token, calls must carry `authorization: Bearer <token>`. Off
+//     loopback there must be one: `serve` refuses other addresses without.
+//   • Plain HTTP/2: keep it on a private network or put TLS in front.
+//   • `Hold` plays the hold music we were started with, if any; callers
+//     can't name files of their own.
+//   • Watches poll what `VoiceSession` reports, so any number of them can
+//     run without taking events from each other.
+//
+// The messages are written out below with prost's derive and the service
+// code is generated from a description of it in build.rs, so building needs
+// no protoc. proto/control.proto is the same contract for other languages;
+// change the three together.
+
+use crate::{effects, health, mixer, send_queue, signaling, Format};
+use anyhow::Result;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// How the service is offered.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// Refuse calls without this bearer token.
+    pub token: Option<String>,
+    /// Played while `Hold` holds the call; loaded when the service starts.
+    pub hold_music: Option<PathBuf>,
+}
+
+/// What the service reads and controls: the session's own handles.
+#[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+pub(crate) struct Call {
+    pub format: Arc<Format>,
+    pub mixer: Arc<mixer::Mixer>,
+    pub send: Arc<send_queue::Counters>,
+    pub health: Arc<health::Health>,
+    pub roster: Arc<signaling::Roster>,
+    pub effects: Arc<effects::Controls>,
+}
+
+/// Serves `Control` on `addr` until the session ends. Needs a Tokio runtime.
+#[cfg(feature = "grpc")]
+pub(crate) fn serve(addr: SocketAddr, options: Options, call: Call) -> Result<()> {
+    use anyhow::Context;
+    if options.token.is_none() && !addr.ip().is_loopback() {
+        anyhow::bail!("gRPC control on {addr} needs a token; only loopback can go without");
+    }
+    let listener =
+        crate::health::bind(addr).with_context(|| format!("can't serve gRPC control on {addr}"))?;
+    let incoming = tonic::transport::server::TcpIncoming::from_listener(listener, true, None)
+        .map_err(|e| anyhow::anyhow!("can't serve gRPC control on {addr}: {e}"))?;
+    let service = rpc::service(options, call)?;
+    tokio::spawn(async move {
+        let served = tonic::transport::Server::builder()
+            .add_service(service)
+            .serve_with_incoming(incoming)
+            .await;
+        if let Err(e) = served {
+            tracing::warn!("gRPC control stopped: {e}");
+        }
+    });
+    tracing::info!("gRPC control on {addr}");
+    Ok(())
+}
+
+#[cfg(not(feature = "grpc"))]
+pub(crate) fn serve(_addr: SocketAddr, _options: Options, _call: Call) -> Result<()> {
+    anyhow::bail!("gRPC control needs a build with the `grpc` feature")
+}
+
+#[cfg(feature = "grpc")]
+mod rpc {
+    use super::{Call, Options};
+    use crate::{health, hold, vad};
+    use anyhow::Context;
+    use async_channel::{bounded, Receiver, Sender};
+    use ring::hmac;
+    use ring::rand::SystemRandom;
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tonic::{Request, Response, Status};
+
+    include!(concat!(env!("OUT_DIR"), "/voicechat.control.v1.Control.rs"));
+
+    use control_server::{Control, ControlServer};
+
+    /// How often watches look for changes.
+    const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+    const MIN_STATS_INTERVAL: Duration = Duration::from_millis(100);
+
+    pub(super) type Service =
+        tonic::service::interceptor::InterceptedService<ControlServer<Controller>, Auth>;
+
+    pub(super) fn service(options: Options, call: Call) -> anyhow::Result<Service> {
+        let token = match options.token {
+            Some(token) => {
+                let key = hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new())
+                    .map_err(|_| anyhow::anyhow!("no randomness for the token key"))?;
+                let tag = hmac::sign(&key, token.as_bytes());
+                Some((key, tag))
+            }
+            None => None,
+        };
+        let hold_music = match &options.hold_music {
+            Some(path) => Some(
+                hold::Music::load(path)
+                    .with_context(|| format!("can't load hold music {}", path.display()))?,
+            ),
+            None => None,
+        };
+        let controller = Controller {
+            call: Arc::new(call),
+            hold_music,
+        };
+        Ok(ControlServer::with_interceptor(controller, Auth { token }))
+    }
+
+    #[derive(Clone)]
+    pub(super) struct Auth {
+        /// The token's MAC, and the key it was made with.
+        token: Option<(hmac::Key, hmac::Tag)>,
+    }
+
+    impl tonic::service::Interceptor for Auth {
+        /// Compares MACs of the tokens under a key of our own, which takes
+        /// the same time however much of the token is right.
+        fn call(&mut self, req: Request<()>) -> Result<Request<()>, Status> {
+            let Some((key, tag)) = &self.token else {
+                return Ok(req);
+            };
+            let given = req
+                .metadata()
+                .get("authorization")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.strip_prefix("Bearer "))
+                .unwrap_or_default();
+            match hmac::verify(key, given.as_bytes(), tag.as_ref()) {
+                Ok(()) => Ok(req),
+                Err(_) => Err(Status::unauthenticated("bad or missing bearer token")),
+            }
+        }
+    }
+
+    pub(super) struct Controller {
+        call: Arc<Call>,
+        hold_music: Option<hold::Music>,
+    }
+
+    #[tonic::async_trait]
+    impl Control for Controller {
+        async fn set_muted(
+            &self,
+            req: Request<SetMutedRequest>,
+        ) -> Result<Response<Empty>, Status> {
+            self.call.effects.set_muted(req.into_inner().muted);
+            Ok(Response::new(Empty {}))
+        }
+
+        async fn hold(&self, _: Request<Empty>) -> Result<Response<Empty>, Status> {
+            self.call.effects.hold(self.hold_music.clone());
+            Ok(Response::new(Empty {}))
+        }
+
+        async fn resume(&self, _: Request<Empty>) -> Result<Response<Empty>, Status> {
+            self.call.effects.resume();
+            Ok(Response::new(Empty {}))
+        }
+
+        async fn set_peer_volume(
+            &self,
+            req: Request<PeerVolumeRequest>,
+        ) -> Result<Response<Empty>, Status> {
+            let req = req.into_inner();
+            let peer = req.peer.parse().map_err(|_| bad_peer(&req.peer))?;
+            self.call.effects.set_stream_gain(peer, req.gain_db);
+            Ok(Response::new(Empty {}))
+        }
+
+        async fn set_peer_muted(
+            &self,
+            req: Request<PeerMutedRequest>,
+        ) -> Result<Response<Empty>, Status> {
+            let req = req.into_inner();
+            let peer = req.peer.parse().map_err(|_| bad_peer(&req.peer))?;
+            self.call.effects.set_stream_muted(peer, req.muted);
+            Ok(Response::new(Empty {}))
+        }
+
+        async fn set_output_gain(
+            &self,
+            req: Request<OutputGainRequest>,
+        ) -> Result<Response<Empty>, Status> {
+            self.call.effects.set_output_gain(req.into_inner().gain_db);
+            Ok(Response::new(Empty {}))
+        }
+
+        async fn set_vad(&self, req: Request<VadRequest>) -> Result<Response<Empty>, Status> {
+            let req = req.into_inner();
+            let mut settings: vad::VadSettings = self.call.effects.vad();
+            settings.threshold_db = req.threshold_db;
+            if req.hangover_ms > 0 {
+                settings.hangover = Duration::from_millis(req.hangover_ms as u64);
+            }
+            self.call.effects.set_vad(settings);
+            Ok(Response::new(Empty {}))
+        }
+
+        async fn get_stats(&self, _: Request<Empty>) -> Result<Response<Stats>, Status> {
+            Ok(Response::new(stats(&self.call)))
+        }
+
+        async fn get_health(&self, _: Request<Empty>) -> Result<Response<Health>, Status> {
+            Ok(Response::new(health(self.call.health.report())))
+        }
+
+        type WatchRosterStream = Receiver<Result<Roster, Status>>;
+
+        async fn watch_roster(
+            &self,
+            _: Request<Empty>,
+        ) -> Result<Response<Self::WatchRosterStream>, Status> {
+            let call = Arc::clone(&self.call);
+            Ok(Response::new(watch(WATCH_INTERVAL, true, move || {
+                roster(call.roster.members())
+            })))
+        }
+
+        type WatchHealthStream = Receiver<Result<Health, Status>>;
+
+        async fn watch_health(
+            &self,
+            _: Request<Empty>,
+        ) -> Result<Response<Self::WatchHealthStream>, Status> {
+            let call = Arc::clone(&self.call);
+            Ok(Response::new(watch(WATCH_INTERVAL, true, move || {
+                health(call.health.report())
+            })))
+        }
+
+        type WatchStatsStream = Receiver<Result<Stats, Status>>;
+
+        async fn watch_stats(
+            &self,
+            req: Request<WatchStatsRequest>,
+        ) -> Result<Response<Self::WatchStatsStream>, Status> {
+            let interval = match req.into_inner().interval_ms {
+                0 => WATCH_INTERVAL,
+                ms => Duration::from_millis(ms as u64).max(MIN_STATS_INTERVAL),
+            };
+            let call = Arc::clone(&self.call);
+            Ok(Response::new(watch(interval, false, move || stats(&call))))
+        }
+    }
+
+    /// Sends what `read` returns every `interval` (with `changes`, only when
+    /// it differs from what was sent last) until the client goes away.
+    fn watch<T>(
+        interval: Duration,
+        changes: bool,
+        mut read: impl FnMut() -> T + Send + 'static,
+    ) -> Receiver<Result<T, Status>>
+    where
+        T: PartialEq + Clone + Send + 'static,
+    {
+        let (tx, rx): (Sender<Result<T, Status>>, _) = bounded(1);
+        tokio::spawn(async move {
+            let mut tick = tokio::time::interval(interval);
+            let mut sent = None;
+            loop {
+                tick.tick().await;
+                let now = read();
+                if changes && sent.as_ref() == Some(&now) {
+                    continue;
+                }
+                if tx.send(Ok(now.clone())).await.is_err() {
+                    return;
+                }
+                sent = Some(now);
+            }
+        });
+        rx
+    }
+
+    fn bad_peer(addr: &str) -> Status {
+        Status::invalid_argument(format!("{addr:?} isn't an ip:port"))
+    }
+
+    fn stats(call: &Call) -> Stats {
+        let jitter = call.format.jitter_stats.lock().clone();
+        let quality = call.format.quality_stats.lock().clone();
+        let send = call.send.snapshot();
+        let peers = call
+            .mixer
+            .stats()
+            .into_iter()
+            .map(|lane| PeerStats {
+                peer: lane.peer.map(|p| p.to_string()).unwrap_or_default(),
+                label: lane.peer.map(|p| call.roster.label(p)).unwrap_or_default(),
+                buffer_ms: lane.depth_ms,
+                target_ms: lane.target_ms,
+                drift_ppm: lane.drift_ppm,
+                underruns: lane.underruns,
+                mos: lane.quality.mos,
+            })
+            .collect();
+        Stats {
+            jitter_buffer_ms: jitter.target_ms,
+            jitter_p95_ms: jitter.jitter_p95_ms,
+            received: jitter.received,
+            lost: jitter.lost,
+            late: jitter.late,
+            underruns: jitter.underruns,
+            sent: send.sent,
+            send_dropped: send.dropped,
+            mos: quality.mos,
+            rtt_ms: quality.rtt_ms,
+            loss_percent: quality.loss_percent,
+            concealed: quality.concealed,
+            fec_recovered: quality.fec_recovered,
+            peers,
+        }
+    }
+
+    fn health(report: health::HealthReport) -> Health {
+        let status = |s: health::Status| {
+            match s {
+                health::Status::Off => HealthStatus::Off,
+                health::Status::Starting => HealthStatus::Starting,
+                health::Status::Ok => HealthStatus::Ok,
+                health::Status::Failed => HealthStatus::Failed,
+            }
+            .into()
+        };
+        Health {
+            healthy: report.healthy,
+            ready: report.ready,
+            socket: status(report.socket),
+            signaling: status(report.signaling),
+            audio: status(report.audio),
+            peers: report.peers as u32,
+            // Whole seconds, so watching it doesn't send every tick.
+            last_packet_secs: report.last_packet_secs.map(|s| s.floor()),
+        }
+    }
+
+    fn roster(members: Vec<crate::signaling::Member>) -> Roster {
+        Roster {
+            members: members
+                .into_iter()
+                .map(|m| Member {
+                    id: m.id,
+                    nickname: m.nickname.unwrap_or_default(),
+                    metadata: m.metadata,
+                    muted: m.muted,
+                })
+                .collect(),
+        }
+    }
+
+    // ─── Messages (proto/control.proto) ──────────────────────────────────────
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Empty {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SetMutedRequest {
+        #[prost(bool, tag = "1")]
+        pub muted: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PeerVolumeRequest {
+        #[prost(string, tag = "1")]
+        pub peer: String,
+        #[prost(float, tag = "2")]
+        pub gain_db: f32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PeerMutedRequest {
+        #[prost(string, tag = "1")]
+        pub peer: String,
+        #[prost(bool, tag = "2")]
+        pub muted: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct OutputGainRequest {
+        #[prost(float, tag = "1")]
+        pub gain_db: f32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct VadRequest {
+        #[prost(float, tag = "1")]
+        pub threshold_db: f32,
+        /// 0 keeps the current hangover.
+        #[prost(uint32, tag = "2")]
+        pub hangover_ms: u32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct WatchStatsRequest {
+        /// 0 for every second.
+        #[prost(uint32, tag = "1")]
+        pub interval_ms: u32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Member {
+        #[prost(string, tag = "1")]
+        pub id: String,
+        #[prost(string, tag = "2")]
+        pub nickname: String,
+        #[prost(btree_map = "string, string", tag = "3")]
+        pub metadata: BTreeMap<String, String>,
+        #[prost(bool, tag = "4")]
+        pub muted: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Roster {
+        #[prost(message, repeated, tag = "1")]
+        pub members: Vec<Member>,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum HealthStatus {
+        Off = 0,
+        Starting = 1,
+        Ok = 2,
+        Failed = 3,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Health {
+        #[prost(bool, tag = "1")]
+        pub healthy: bool,
+        #[prost(bool, tag = "2")]
+        pub ready: bool,
+        #[prost(enumeration = "HealthStatus", tag = "3")]
+        pub socket: i32,
+        #[prost(enumeration = "HealthStatus", tag = "4")]
+        pub signaling: i32,
+        #[prost(enumeration = "HealthStatus", tag = "5")]
+        pub audio: i32,
+        #[prost(uint32, tag = "6")]
+        pub peers: u32,
+        #[prost(double, optional, tag = "7")]
+        pub last_packet_secs: Option<f64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PeerStats {
+        #[prost(string, tag = "1")]
+        pub peer: String,
+        /// With its nickname, if it sent one.
+        #[prost(string, tag = "2")]
+        pub label: String,
+        #[prost(float, tag = "3")]
+        pub buffer_ms: f32,
+        #[prost(uint32, tag = "4")]
+        pub target_ms: u32,
+        #[prost(float, tag = "5")]
+        pub drift_ppm: f32,
+        #[prost(uint64, tag = "6")]
+        pub underruns: u64,
+        #[prost(float, tag = "7")]
+        pub mos: f32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Stats {
+        #[prost(uint32, tag = "1")]
+        pub jitter_buffer_ms: u32,
+        #[prost(float, tag = "2")]
+        pub jitter_p95_ms: f32,
+        #[prost(uint64, tag = "3")]
+        pub received: u64,
+        #[prost(uint64, tag = "4")]
+        pub lost: u64,
+        #[prost(uint64, tag = "5")]
+        pub late: u64,
+        #[prost(uint64, tag = "6")]
+        pub underruns: u64,
+        #[prost(uint64, tag = "7")]
+        pub sent: u64,
+        #[prost(uint64, tag = "8")]
+        pub send_dropped: u64,
+        #[prost(float, tag = "9")]
+        pub mos: f32,
+        #[prost(float, optional, tag = "10")]
+        pub rtt_ms: Option<f32>,
+        #[prost(float, tag = "11")]
+        pub loss_percent: f32,
+        #[prost(uint64, tag = "12")]
+        pub concealed: u64,
+        #[prost(uint64, tag = "13")]
+        pub fec_recovered: u64,
+        #[prost(message, repeated, tag = "14")]
+        pub peers: Vec<PeerStats>,
+    }
+}
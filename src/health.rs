@@ -0,0 +1,217 @@
+// ─── Health ────────────────────────────────────────────────────────────────────
+// What an orchestrator needs to decide whether to restart a headless instance:
+// is the UDP socket working, did signaling succeed, how many peers are keyed,
+// and are the audio devices open. The network tasks and the audio thread keep
+// `Health` current; `serve` answers plain HTTP on a local address:
+//
+//   GET /healthz  200 unless something has failed (liveness)
+//   GET /readyz   200 once the call can carry audio (readiness)
+//
+// Both return the `HealthReport` as JSON.
+
+use anyhow::Result;
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{info, warn};
+
+/// Keyed peers send at least a silence marker every second and a Ping every
+/// two; this long without a packet means the path is gone.
+const STALE_AFTER: Duration = Duration::from_secs(15);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_REQUEST: usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// Not used in this mode.
+    Off,
+    Starting,
+    Ok,
+    Failed,
+}
+
+impl Status {
+    fn from_u8(v: u8) -> Self {
+        match v {
+            0 => Self::Off,
+            1 => Self::Starting,
+            2 => Self::Ok,
+            _ => Self::Failed,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct HealthReport {
+    /// Nothing has failed.
+    pub healthy: bool,
+    /// Healthy, set up, and hearing from the peers (if any are keyed).
+    pub ready: bool,
+    pub socket: Status,
+    pub signaling: Status,
+    pub audio: Status,
+    /// Keyed peers, listeners or multicast sources.
+    pub peers: usize,
+    /// Seconds since the last authenticated packet; `None` before the first.
+    pub last_packet_secs: Option<f64>,
+}
+
+pub(crate) struct Health {
+    socket: AtomicU8,
+    signaling: AtomicU8,
+    audio: AtomicU8,
+    peers: AtomicUsize,
+    /// Milliseconds after `started`, plus one; 0 until the first packet.
+    last_packet: AtomicU64,
+    started: Instant,
+}
+
+impl Health {
+    pub fn new(signaling: bool) -> Self {
+        let signaling = match signaling {
+            true => Status::Starting,
+            false => Status::Off,
+        };
+        Self {
+            socket: AtomicU8::new(Status::Starting as u8),
+            signaling: AtomicU8::new(signaling as u8),
+            audio: AtomicU8::new(Status::Starting as u8),
+            peers: AtomicUsize::new(0),
+            last_packet: AtomicU64::new(0),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn set_socket(&self, status: Status) {
+        self.socket.store(status as u8, Ordering::Relaxed);
+    }
+
+    pub fn set_signaling(&self, status: Status) {
+        self.signaling.store(status as u8, Ordering::Relaxed);
+    }
+
+    pub fn set_audio(&self, status: Status) {
+        self.audio.store(status as u8, Ordering::Relaxed);
+    }
+
+    pub fn set_peers(&self, peers: usize) {
+        self.peers.store(peers, Ordering::Relaxed);
+    }
+
+    /// An authenticated packet arrived.
+    pub fn packet(&self) {
+        let ms = self.started.elapsed().as_millis() as u64 + 1;
+        self.last_packet.store(ms, Ordering::Relaxed);
+    }
+
+    pub fn report(&self) -> HealthReport {
+        let socket = Status::from_u8(self.socket.load(Ordering::Relaxed));
+        let signaling = Status::from_u8(self.signaling.load(Ordering::Relaxed));
+        let audio = Status::from_u8(self.audio.load(Ordering::Relaxed));
+        let peers = self.peers.load(Ordering::Relaxed);
+        let last_packet_secs = match self.last_packet.load(Ordering::Relaxed) {
+            0 => None,
+            ms => Some(
+                self.started
+                    .elapsed()
+                    .saturating_sub(Duration::from_millis(ms - 1))
+                    .as_secs_f64(),
+            ),
+        };
+        let stale = peers > 0 && last_packet_secs.is_some_and(|s| s >= STALE_AFTER.as_secs_f64());
+        let healthy = [socket, signaling, audio]
+            .iter()
+            .all(|s| *s != Status::Failed);
+        let set_up = [socket, signaling, audio]
+            .iter()
+            .all(|s| matches!(s, Status::Ok | Status::Off));
+        HealthReport {
+            healthy,
+            ready: healthy && set_up && !stale,
+            socket,
+            signaling,
+            audio,
+            peers,
+            last_packet_secs,
+        }
+    }
+}
+
+/// Answers `/healthz` and `/readyz` until the session ends.
+pub(crate) async fn serve(listener: TcpListener, health: Arc<Health>) {
+    if let Ok(addr) = listener.local_addr() {
+        info!("health endpoint on http://{addr}/healthz");
+    }
+    loop {
+        let (stream, _) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                warn!("health endpoint: {e}");
+                continue;
+            }
+        };
+        let health = Arc::clone(&health);
+        tokio::spawn(async move {
+            // A client that stalls past the timeout just gets dropped.
+            let answered = tokio::time::timeout(REQUEST_TIMEOUT, respond(stream, &health)).await;
+            if let Ok(Err(e)) = answered {
+                warn!("health endpoint: {e}");
+            }
+        });
+    }
+}
+
+/// Binds the endpoint's listener. Needs a Tokio runtime.
+pub(crate) fn bind(addr: SocketAddr) -> Result<TcpListener> {
+    adopt(std::net::TcpListener::bind(addr)?)
+}
+
+/// Serves on a listener opened elsewhere (socket activation, see `service`).
+pub(crate) fn adopt(listener: std::net::TcpListener) -> Result<TcpListener> {
+    listener.set_nonblocking(true)?;
+    Ok(TcpListener::from_std(listener)?)
+}
+
+async fn respond(mut stream: TcpStream, health: &Health) -> Result<()> {
+    // Only the request line matters; read until the end of the headers.
+    let mut req = Vec::new();
+    let mut buf = [0u8; 512];
+    while !req.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 || req.len() + n > MAX_REQUEST {
+            return Ok(());
+        }
+        req.extend_from_slice(&buf[..n]);
+    }
+    let line = String::from_utf8_lossy(&req);
+    let mut words = line.split_whitespace();
+    let (status, body) = match (words.next(), words.next()) {
+        (Some("GET"), Some(path @ ("/healthz" | "/readyz"))) => {
+            let report = health.report();
+            let ok = match path {
+                "/healthz" => report.healthy,
+                _ => report.ready,
+            };
+            let status = match ok {
+                true => "200 OK",
+                false => "503 Service Unavailable",
+            };
+            (status, serde_json::to_string(&report)?)
+        }
+        (Some("GET"), _) => ("404 Not Found", String::new()),
+        _ => ("405 Method Not Allowed", String::new()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Cache-Control: no-store\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
@@ -0,0 +1,115 @@
+// ─── Hold ──────────────────────────────────────────────────────────────────────
+// Telephone‑style hold: the microphone is cut, the peer is told with a sealed
+// Hold packet, and, if there is hold music, hears that instead until we
+// resume. Without music no media is sent, as in DTX. Hold packets go out on
+// every change and then once a second – while held, and for a few seconds
+// after resuming – so a lost one doesn't leave the peer guessing.
+//
+// Music is decoded, folded to mono and resampled to 48 kHz when the hold
+// starts, so the capture callback only copies samples; it bypasses the APM
+// and voice effects, which are made for speech.
+
+use crate::resample::Resampler;
+use crate::source::{AudioSource, WavFile};
+use crate::SAMPLE_RATE;
+use anyhow::{bail, Result};
+use std::path::Path;
+use std::sync::Arc;
+
+/// How often Hold packets repeat, and for how long after resuming.
+const REPEAT_MS: usize = 1000;
+const RESUMED_REPEAT_MS: usize = 3000;
+
+/// A WAV file ready to loop to the peer.
+#[derive(Clone, Debug)]
+pub struct Music(Arc<[f32]>);
+
+impl Music {
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut wav = WavFile::open(path, false)?;
+        let channels = wav.channels();
+        let mut resampler = Resampler::new(wav.sample_rate(), SAMPLE_RATE, 1);
+        let mut samples = Vec::new();
+        let mut buf = vec![0f32; 4096 * channels];
+        loop {
+            let frames = wav.read(&mut buf);
+            if frames == 0 {
+                break;
+            }
+            for frame in buf[..frames * channels].chunks(channels) {
+                let mono = frame.iter().sum::<f32>() / channels as f32;
+                resampler.push(&[mono], |s| samples.push(s[0]));
+            }
+        }
+        if samples.is_empty() {
+            bail!("{} holds no audio", path.display());
+        }
+        Ok(Self(samples.into()))
+    }
+}
+
+/// Loops the music into outgoing frames.
+#[derive(Default)]
+pub(crate) struct Player {
+    music: Option<Music>,
+    pos: usize,
+}
+
+impl Player {
+    /// Overwrites `frame` (interleaved, `channels` wide) with the next of
+    /// `music`, from the top if it is new.
+    pub fn fill(&mut self, music: &Music, frame: &mut [f32], channels: usize) {
+        if !self
+            .music
+            .as_ref()
+            .is_some_and(|m| Arc::ptr_eq(&m.0, &music.0))
+        {
+            self.music = Some(music.clone());
+            self.pos = 0;
+        }
+        for f in frame.chunks_mut(channels) {
+            f.fill(music.0[self.pos]);
+            self.pos = (self.pos + 1) % music.0.len();
+        }
+    }
+}
+
+/// Decides when the capture side sends a Hold packet.
+pub(crate) struct Announcer {
+    held: bool,
+    /// Since the last packet, and since the last change.
+    since_sent_ms: usize,
+    since_change_ms: usize,
+}
+
+impl Default for Announcer {
+    /// Not held, and not just resumed either.
+    fn default() -> Self {
+        Self {
+            held: false,
+            since_sent_ms: 0,
+            since_change_ms: RESUMED_REPEAT_MS + 1,
+        }
+    }
+}
+
+impl Announcer {
+    /// Called once per frame; the state to send, if one is due.
+    pub fn frame(&mut self, held: bool, frame_ms: usize) -> Option<bool> {
+        self.since_sent_ms = self.since_sent_ms.saturating_add(frame_ms);
+        self.since_change_ms = self.since_change_ms.saturating_add(frame_ms);
+        let due = if held != self.held {
+            self.held = held;
+            self.since_change_ms = 0;
+            true
+        } else {
+            let repeating = held || self.since_change_ms <= RESUMED_REPEAT_MS;
+            repeating && self.since_sent_ms >= REPEAT_MS
+        };
+        if !due {
+            return None;
+        }
+        self.since_sent_ms = 0;
+        Some(held)
+    }
+}
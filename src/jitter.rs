@@ -0,0 +1,275 @@
+// ─── Jitter buffer ─────────────────────────────────────────────────────────────
+// Decoded audio waits in the playback ring until the output device takes it.
+// Playout starts – and restarts after the ring ran dry – only once `target_ms`
+// of audio is queued, which absorbs that much network jitter at the cost of
+// that much latency. Frames that would push the queue past `max_ms` are
+// dropped so latency can't creep up. LAN users want a small target, satellite
+// and mobile links a large one; by default the target adapts to the measured
+// jitter within `min_ms..=max_ms`.
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug)]
+pub struct JitterOptions {
+    /// Audio queued before playout starts; the starting point when adaptive.
+    pub target_ms: u32,
+    /// Bounds for `target_ms`; `max_ms` is also the most we ever queue.
+    pub min_ms: u32,
+    pub max_ms: u32,
+    /// Follow the measured network jitter.
+    pub adaptive: bool,
+    /// Ask the sender to resend isolated losses when the round trip allows
+    /// (see `nack`).
+    pub nack: bool,
+}
+
+impl Default for JitterOptions {
+    fn default() -> Self {
+        Self {
+            target_ms: 60,
+            min_ms: 20,
+            max_ms: 200,
+            adaptive: true,
+            nack: false,
+        }
+    }
+}
+
+impl JitterOptions {
+    pub fn validate(&self) -> Result<()> {
+        if !(self.min_ms <= self.target_ms && self.target_ms <= self.max_ms) {
+            bail!(
+                "jitter buffer needs min ≤ target ≤ max, got {} ≤ {} ≤ {} ms",
+                self.min_ms,
+                self.target_ms,
+                self.max_ms
+            );
+        }
+        Ok(())
+    }
+}
+
+// ─── Statistics and auto‑tuning ────────────────────────────────────────────────
+// Every sealed packet is accounted for by its (epoch, sequence) position:
+// gaps count as lost until the packet turns up late, repeats as duplicates,
+// and packets the full buffer had no room for as early. Inter‑arrival jitter
+// is how far consecutive media packets' spacing strays from the frame
+// duration. Once a second the target follows the 95th percentile of that
+// jitter plus a frame, growing at once (and on every underrun) and shrinking
+// a few ms at a time.
+
+const JITTER_WINDOW: usize = 500;
+const ADAPT_INTERVAL: Duration = Duration::from_secs(1);
+const SHRINK_STEP_MS: u32 = 5;
+
+/// Where a sealed packet sits in the sender's sequence, and when it arrived.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Position {
+    pub epoch: u8,
+    pub seq: u32,
+    pub at: Instant,
+}
+
+/// Counters since the call started, and the current jitter picture.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct JitterStats {
+    pub received: u64,
+    pub lost: u64,
+    /// Arrived after a newer packet; not played.
+    pub late: u64,
+    /// Late, but resent on request and played in time.
+    pub repaired: u64,
+    /// Arrived while the buffer was full; dropped.
+    pub early: u64,
+    pub duplicate: u64,
+    /// Times playout ran dry while the peer was talking.
+    pub underruns: u64,
+    pub jitter_p50_ms: f32,
+    pub jitter_p95_ms: f32,
+    pub jitter_p99_ms: f32,
+    /// Current playout target.
+    pub target_ms: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Arrival {
+    /// `missed` packets went missing right before this one (so far).
+    InOrder {
+        missed: u32,
+    },
+    Late,
+    Duplicate,
+}
+
+pub(crate) struct Tracker {
+    opts: JitterOptions,
+    frame_ms: u32,
+    epoch: Option<u8>,
+    highest: u32,
+    /// Bit n set: packet `highest - n` arrived.
+    seen: u64,
+    last_media: Option<(u32, Instant)>,
+    /// Recent |spacing − frame duration| in ms.
+    deltas: VecDeque<f32>,
+    stats: JitterStats,
+    last_adapt: Instant,
+}
+
+impl Tracker {
+    pub fn new(opts: JitterOptions, frame_ms: u32) -> Self {
+        Self {
+            opts,
+            frame_ms,
+            epoch: None,
+            highest: 0,
+            seen: 0,
+            last_media: None,
+            deltas: VecDeque::with_capacity(JITTER_WINDOW),
+            stats: JitterStats {
+                target_ms: opts.target_ms,
+                ..Default::default()
+            },
+            last_adapt: Instant::now(),
+        }
+    }
+
+    pub fn set_frame_ms(&mut self, frame_ms: u32) {
+        self.frame_ms = frame_ms;
+        self.last_media = None;
+    }
+
+    /// Another sender took over the stream: its sequence starts afresh.
+    pub fn new_sender(&mut self) {
+        self.epoch = None;
+        self.last_media = None;
+    }
+
+    /// Accounts for a sealed packet; only `InOrder` ones should be played.
+    pub fn arrived(&mut self, pos: Position, media: bool) -> Arrival {
+        if self.epoch != Some(pos.epoch) {
+            // First packet, or the sender rekeyed and restarted its sequence;
+            // packets from the epoch before are stragglers.
+            if let Some(epoch) = self.epoch {
+                if pos.epoch.wrapping_sub(epoch) > u8::MAX / 2 {
+                    self.stats.late += 1;
+                    return Arrival::Late;
+                }
+                self.stats.lost += pos.seq as u64;
+            }
+            self.epoch = Some(pos.epoch);
+            self.highest = pos.seq;
+            self.seen = 1;
+            self.last_media = None;
+            self.stats.received += 1;
+            self.spacing(pos, media);
+            return Arrival::InOrder { missed: 0 };
+        }
+        if pos.seq <= self.highest {
+            let back = self.highest - pos.seq;
+            let Some(bit) = 1u64.checked_shl(back) else {
+                // Too far back for `seen` to say whether it's a duplicate,
+                // so it can't be taken off `lost` either.
+                self.stats.late += 1;
+                return Arrival::Late;
+            };
+            if self.seen & bit != 0 {
+                self.stats.duplicate += 1;
+                return Arrival::Duplicate;
+            }
+            self.seen |= bit;
+            self.stats.received += 1;
+            self.stats.late += 1;
+            self.stats.lost = self.stats.lost.saturating_sub(1);
+            return Arrival::Late;
+        }
+        let gap = pos.seq - self.highest;
+        self.stats.lost += gap as u64 - 1;
+        self.seen = self.seen.checked_shl(gap).unwrap_or(0) | 1;
+        self.highest = pos.seq;
+        self.stats.received += 1;
+        self.spacing(pos, media);
+        Arrival::InOrder { missed: gap - 1 }
+    }
+
+    fn spacing(&mut self, pos: Position, media: bool) {
+        if !media {
+            self.last_media = None;
+            return;
+        }
+        if let Some((seq, at)) = self.last_media {
+            if pos.seq == seq.wrapping_add(1) {
+                let ms = pos.at.duration_since(at).as_secs_f32() * 1000.0;
+                if self.deltas.len() == JITTER_WINDOW {
+                    self.deltas.pop_front();
+                }
+                self.deltas.push_back((ms - self.frame_ms as f32).abs());
+            }
+        }
+        self.last_media = Some((pos.seq, pos.at));
+    }
+
+    /// A late packet was one we'd asked for, and got played after all.
+    pub fn repaired(&mut self, count: u64) {
+        self.stats.late = self.stats.late.saturating_sub(count);
+        self.stats.repaired += count;
+    }
+
+    /// The buffer had no room for a packet.
+    pub fn early(&mut self) {
+        self.stats.early += 1;
+    }
+
+    /// Refreshes the statistics and, at most once per `ADAPT_INTERVAL`,
+    /// returns a new playout target in ms, no higher than `cap` (though not
+    /// below `min_ms`).
+    pub fn tick(&mut self, underruns: u64, cap: Option<u32>) -> Option<u32> {
+        if self.last_adapt.elapsed() < ADAPT_INTERVAL {
+            return None;
+        }
+        self.last_adapt = Instant::now();
+        let mut sorted: Vec<f32> = self.deltas.iter().copied().collect();
+        sorted.sort_by(f32::total_cmp);
+        let pct = |p: f32| match sorted.len() {
+            0 => 0.0,
+            n => sorted[((n - 1) as f32 * p).round() as usize],
+        };
+        self.stats.jitter_p50_ms = pct(0.50);
+        self.stats.jitter_p95_ms = pct(0.95);
+        self.stats.jitter_p99_ms = pct(0.99);
+        let new_underruns = underruns - self.stats.underruns;
+        self.stats.underruns = underruns;
+
+        let target = self.stats.target_ms;
+        let max = cap.map_or(self.opts.max_ms, |cap| {
+            cap.clamp(self.opts.min_ms, self.opts.max_ms)
+        });
+        let next = if !self.opts.adaptive {
+            self.opts.target_ms.min(max)
+        } else if sorted.is_empty() {
+            target.min(max)
+        } else {
+            let mut want = self.stats.jitter_p95_ms.ceil() as u32 + self.frame_ms;
+            if new_underruns > 0 {
+                want = want.max(target + self.frame_ms);
+            }
+            // Grow at once; shrink gently so one calm spell doesn't undo it.
+            match want > target {
+                true => want,
+                false => target.saturating_sub(SHRINK_STEP_MS).max(want),
+            }
+            .clamp(self.opts.min_ms, max)
+        };
+        if next == target {
+            return None;
+        }
+        self.stats.target_ms = next;
+        Some(next)
+    }
+
+    pub fn stats(&self) -> &JitterStats {
+        &self.stats
+    }
+}
@@ -0,0 +1,200 @@
+// ─── Latency tracer ────────────────────────────────────────────────────────────
+// Where the mouth‑to‑ear delay goes. Each stage of the pipeline records how
+// long audio spent in it:
+//
+//   capture device   the input callback's capture → callback timestamps
+//   framing          waiting for a whole Opus frame to fill
+//   encode           APM, effects and the encoder
+//   send queue       capture side → network task
+//   network          half the measured round trip
+//   receive          socket → decode task
+//   decode           the decoder
+//   playout buffer   the ring's depth (jitter buffer) when the callback runs
+//   output device    the output callback's callback → playback timestamps
+//
+// The stages are measured on our side only; "network" is the one estimate.
+// Every `REPORT_INTERVAL` the session logs p50/p95/p99 per stage and an
+// end‑to‑end estimate (the stage p50s summed), and `VoiceSession::
+// latency_stats` returns the latest breakdown. Devices whose backend reports
+// no timestamps just leave their stage empty.
+//
+// Embedders that care more about delay than about dropouts (a game, say) can
+// set a target with `VoiceSession::set_target_latency`. The jitter buffer
+// then gets what the target leaves after a frame of framing and half the
+// round trip, however much jitter it measures; playout drops audio queued
+// past that; and send pacing holds frames back at most a quarter of it.
+
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Weak;
+use std::time::Duration;
+use tracing::info;
+
+pub(crate) const REPORT_INTERVAL: Duration = Duration::from_secs(30);
+/// Samples kept per stage between reports; the oldest are overwritten.
+const WINDOW: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Stage {
+    CaptureDevice,
+    Framing,
+    Encode,
+    SendQueue,
+    Network,
+    Receive,
+    Decode,
+    PlayoutBuffer,
+    OutputDevice,
+}
+
+impl Stage {
+    const ALL: [Stage; 9] = [
+        Stage::CaptureDevice,
+        Stage::Framing,
+        Stage::Encode,
+        Stage::SendQueue,
+        Stage::Network,
+        Stage::Receive,
+        Stage::Decode,
+        Stage::PlayoutBuffer,
+        Stage::OutputDevice,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Stage::CaptureDevice => "capture device",
+            Stage::Framing => "framing",
+            Stage::Encode => "encode",
+            Stage::SendQueue => "send queue",
+            Stage::Network => "network",
+            Stage::Receive => "receive",
+            Stage::Decode => "decode",
+            Stage::PlayoutBuffer => "playout buffer",
+            Stage::OutputDevice => "output device",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct StageLatency {
+    pub stage: &'static str,
+    /// Measurements behind the percentiles; 0 if the stage wasn't seen.
+    pub samples: usize,
+    pub p50_ms: f32,
+    pub p95_ms: f32,
+    pub p99_ms: f32,
+}
+
+/// Per‑stage latency over the last report interval.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct LatencyStats {
+    pub stages: Vec<StageLatency>,
+    /// The stage medians summed: a typical mouth‑to‑ear delay.
+    pub total_p50_ms: f32,
+    /// The stage p95s summed; an upper bound, as the stages rarely peak
+    /// together.
+    pub total_p95_ms: f32,
+}
+
+#[derive(Default)]
+struct Window {
+    ms: Vec<f32>,
+    next: usize,
+}
+
+#[derive(Default)]
+pub(crate) struct Tracer {
+    windows: Mutex<[Window; Stage::ALL.len()]>,
+    last: Mutex<LatencyStats>,
+    /// End‑to‑end target in ms; 0 for none.
+    target_ms: AtomicU32,
+}
+
+impl Tracer {
+    /// Called from the audio callbacks: a short lock, no allocation once
+    /// the window is full.
+    pub fn record(&self, stage: Stage, took: Duration) {
+        let mut windows = self.windows.lock();
+        let w = &mut windows[stage as usize];
+        let ms = took.as_secs_f32() * 1000.0;
+        if w.ms.len() < WINDOW {
+            w.ms.push(ms);
+        } else {
+            w.ms[w.next] = ms;
+            w.next = (w.next + 1) % WINDOW;
+        }
+    }
+
+    /// The breakdown since the previous report, which becomes `last`.
+    pub fn report(&self) -> LatencyStats {
+        let taken = std::mem::take(&mut *self.windows.lock());
+        let mut stats = LatencyStats::default();
+        for (stage, mut w) in Stage::ALL.into_iter().zip(taken) {
+            w.ms.sort_by(f32::total_cmp);
+            let pct = |p: f32| match w.ms.len() {
+                0 => 0.0,
+                n => w.ms[((n - 1) as f32 * p).round() as usize],
+            };
+            let s = StageLatency {
+                stage: stage.name(),
+                samples: w.ms.len(),
+                p50_ms: pct(0.50),
+                p95_ms: pct(0.95),
+                p99_ms: pct(0.99),
+            };
+            stats.total_p50_ms += s.p50_ms;
+            stats.total_p95_ms += s.p95_ms;
+            stats.stages.push(s);
+        }
+        *self.last.lock() = stats.clone();
+        stats
+    }
+
+    pub fn last(&self) -> LatencyStats {
+        self.last.lock().clone()
+    }
+
+    pub fn set_target_ms(&self, ms: Option<u32>) {
+        self.target_ms.store(ms.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    pub fn target_ms(&self) -> Option<u32> {
+        Some(self.target_ms.load(Ordering::Relaxed)).filter(|&ms| ms > 0)
+    }
+}
+
+/// Logs a breakdown every `REPORT_INTERVAL` until the session ends.
+pub(crate) async fn report_task(tracer: Weak<Tracer>) {
+    let mut tick = tokio::time::interval(REPORT_INTERVAL);
+    tick.tick().await;
+    loop {
+        tick.tick().await;
+        let Some(tracer) = tracer.upgrade() else {
+            return;
+        };
+        let stats = tracer.report();
+        if stats.stages.iter().all(|s| s.samples == 0) {
+            continue;
+        }
+        let breakdown: Vec<String> = stats
+            .stages
+            .iter()
+            .filter(|s| s.samples > 0)
+            .map(|s| {
+                format!(
+                    "{} {:.1}/{:.1}/{:.1}",
+                    s.stage, s.p50_ms, s.p95_ms, s.p99_ms
+                )
+            })
+            .collect();
+        info!(
+            total_p50_ms = stats.total_p50_ms,
+            total_p95_ms = stats.total_p95_ms,
+            "latency p50/p95/p99 ms: {}; about {:.0} ms end to end ({:.0} ms at p95)",
+            breakdown.join(", "),
+            stats.total_p50_ms,
+            stats.total_p95_ms
+        );
+    }
+}
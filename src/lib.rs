@@ -0,0 +1,3637 @@
+// A cross‑platform (Windows + Linux + Android + iOS) prototype for low‑latency voice chat.
+// ────────────────────────────────────────────────────────────────────────────────
+// Features implemented
+//   • Automatically selects the default input/output audio devices on the host
+//     (WASAPI on Windows, where calls take the communications devices and
+//     follow them when they change unless `--console-devices`; Pulse/ALSA/JACK
+//     on Linux – works fine on PipeWire through the `pipewire‑pulse`
+//     compatibility layer.)  `--host pipewire` goes through pipewire‑alsa
+//     instead and tags the call as Communication; `--host jack`
+//     (with the `jack` feature) registers named JACK ports instead; `--host
+//     asio` (with the `asio` feature) uses ASIO drivers on Windows. Devices and
+//     a fixed buffer size can be picked with `--input-device`,
+//     `--output-device` and `--buffer-frames`.
+//   • The devices picked by name are remembered per host in the config
+//     directory and used again next time; a remembered device that's gone
+//     falls back to the default with `STATUS: device_fallback`
+//     (`--forget-devices` clears them, see `prefs`).
+//   • Captures PCM audio, runs it through WebRTC’s echo‑canceller / AGC / noise
+//     suppression, then encodes it with Opus (mono @ 48 kHz, 20 ms frames).
+//     Sample conversion, the downmix and the recorder's mix use AVX2 or NEON
+//     where the CPU has them (see `simd`, `cargo bench --bench simd`).
+//   • Optional compressor/limiter on the outgoing voice (`--compressor`).
+//   • Optional voice changer (pitch shift with presets, `--voice`) after the
+//     APM, adjustable mid‑call through `effects::Controls`.
+//   • Optional surround: `--send-channels` up to 8 sends the capture device's
+//     channels through Opus multistream; the receiver plays them on as many
+//     speakers as it has and folds the rest down. Stereo (music) still gets
+//     echo cancellation, in stereo, against what we play.
+//   • Frame duration (`--frame-ms` 10/20/40/60) is agreed per call: both
+//     sides send the longer of the two preferences.
+//   • VBR by default; `--cbr` / `--constrained-vbr` for fixed‑size packets.
+//     `--bandwidth` caps the coded passband for very thin links.
+//   • While muted (or silent with `--dtx`) no media is sent at all, only a
+//     small sealed marker about once a second; the peer shows who is muted
+//     and plays comfort noise instead of dead air.
+//   • After a few seconds without speech (`--idle-after-ms`) the capture
+//     chain skips the APM and the encoder too, only checking the input level
+//     until it rises, to save CPU on laptops during long quiet stretches.
+//   • `--record call.wav` saves the call (both sides mixed) for archival,
+//     with a `call.json` timeline of who was speaking when (see `record`).
+//     `--record-voice-only` leaves out the silence between talk, keeping a
+//     little before and after each stretch of speech. `--record-passphrase`
+//     seals the files on disk; `decrypt` opens them again.
+//   • Instant replay: `--replay-secs` keeps the last seconds of the call in
+//     memory and the `save-clip` command writes the last 30 s to a WAV.
+//   • Bots and test peers can send a WAV file (`--input-file`) or a tone
+//     (`--input-tone`) instead of a microphone; embedders implement
+//     `source::AudioSource` for anything else. Likewise a `sink::AudioSink`
+//     can stand in for the speaker.
+//   • Send‑only mode (`--no-playback`) never opens an output device, for
+//     headless boxes and broadcast senders.
+//   • Broadcast mode (`--broadcast`) encodes once and sends the stream to a
+//     list of listeners (`--listener`) and/or the room's subscribers, each
+//     keyed separately (see `broadcast`). `--broadcast-tier` adds
+//     lower‑bitrate copies, encoded on worker threads (see `encode_pool`),
+//     for listeners whose bandwidth estimate is short of the main stream.
+//   • On trusted LANs, members of a multicast group (`--multicast`) send to
+//     and play from the group, keyed by a shared secret (see `multicast`).
+//     A priority speaker (`--priority-speaker`, or `priority` mid‑call) cuts
+//     in on whoever is playing and holds the others off while they talk.
+//     The group's host (`--host-passphrase`) can mute or remove members with
+//     signed orders that every member enforces (see `moderation`).
+//   • Sends encoded frames over UDP.  Each datagram carries a one‑byte packet
+//     kind; media frames add a key epoch + sequence number (see `packet`).
+//     If the network falls behind, the oldest queued frames are dropped and
+//     sustained backpressure is reported (see `send_queue`). Frames leave
+//     paced a frame apart even when the audio callback delivers several at
+//     once.
+//   • Calls survive network changes (Wi‑Fi → Ethernet, VPN up/down): a peer
+//     whose packets start arriving from a new address is sent to there too
+//     once they authenticate, and only there once the old one goes quiet.
+//     Multi‑homed machines can pin the call to one network instead
+//     (`--interface`, `--bind-addr`); the log names the one in use.
+//   • `--multipath <iface>` sends every packet over a second interface too
+//     (Wi‑Fi and LTE, say), so the call goes on while either network works;
+//     the receiver keeps whichever copy arrives first.
+//   • Inbound packets are budgeted per source address before any parsing or
+//     crypto, and sources that keep flooding are banned for a while
+//     (`--max-inbound-pps`, `--max-inbound-kib`, see `flood`).
+//   • Peers with no direct path can go through a third machine that runs
+//     `relay` (`--relay`); it forwards sealed packets it can't read, within a
+//     bandwidth cap, over UDP or, for networks that block it, over TCP.
+//     Callers try the peer's LAN address, then hole punching, then TURN
+//     (`--turn`, see `turn`), then the relay over UDP and over TCP, each for
+//     a few seconds, and say what they tried if nothing answers (see
+//     `fallback`).
+//   • A relay can require join tokens (`--acl-secret`, minted by
+//     `issue-token`, presented with `--relay-token`), limit rooms to listed
+//     members (`--acl-members`) and revoke members mid‑call from its stdin
+//     (see `acl`).
+//   • `echo-server` answers calls by sending the caller's own audio back a
+//     second later (`--delay-ms`), untouched or re‑encoded, so a setup can be
+//     checked without a second person (see `echo`).
+//   • Runs an ephemeral X25519 handshake with the peer and prints a short
+//     authentication string both users can compare out loud (see `crypto`).
+//   • Encrypts media with ChaCha20‑Poly1305 and periodically ratchets the
+//     keys forward (`--rekey-secs`, `--rekey-packets`).
+//   • Optional room rendezvous through an HTTPS signaling server, authenticated
+//     with a bearer token (`--room`, `--signal-url`, `--token`). Rooms can
+//     require a passphrase (`--room-passphrase`), which can also key the
+//     media (`--passphrase-keys-media`). Servers that stream the room's
+//     roster report members joining, leaving, renaming and muting
+//     (`--nickname`, `VoiceSession::roster_events`). The nickname and a
+//     little metadata (`--meta`) also go to each peer right after the
+//     handshake, so prompts and logs name peers instead of addresses. An `mqtt://` or
+//     `mqtts://` `--signal-url` uses an existing MQTT broker instead, with
+//     retained per‑member topics for candidates and presence; a `matrix://`
+//     one uses a Matrix room's state events, announces the call in the room
+//     and can invite users (`--invite`). Any of them can be reached through
+//     a SOCKS5 proxy such as Tor (`--proxy`); media still goes direct.
+//     `--also-room` listens to more rooms at once, each a session of its own
+//     on the same devices; `talk` picks the room the mic goes to and
+//     `room-volume` sets how loud each plays.
+//   • Serverless rendezvous through the BitTorrent mainline DHT
+//     (`--dht-room`): both callers announce under a hash of the room name
+//     and find each other without anyone running a server (see `dht`).
+//   • Or no network service at all: `offer` / `answer` print base64 blobs
+//     with addresses and keys to swap over any channel (see `manual`), or
+//     SDP descriptions with `--sdp` (see `sdp`).
+//   • `process` runs a WAV file through the same capture chain, codec and
+//     receive path offline, over a simulated lossy link (`--loss`,
+//     `--loss-burst`, `--seed`), into another WAV – deterministic, for
+//     comparing settings and regression tests (see `offline`).
+//   • `latency-test` plays chirps through the speaker and finds them on the
+//     microphone, for the device round trip the AEC must cover and the
+//     buffer size sets (see `roundtrip`).
+//   • `selftest` calls between two sessions in one process over loopback
+//     UDP with tone sources and analysing sinks, checks the tones arrive
+//     intact and reports the end‑to‑end latency (see `selftest`).
+//   • Resamples between the device rate/channel layout and the 48 kHz mono
+//     pipeline, and rebuilds the streams when a device disappears or switches
+//     rate mid‑call (Bluetooth headsets, AirPods on macOS).
+//   • Optional parametric EQ on received audio (low shelf, presence, high cut;
+//     `--eq-*`), one filter state per peer; the `eq` command sets it for one
+//     peer (`Controls::set_peer_eq`).
+//   • A ring‑buffer acts as a small jitter buffer on the playback side; its
+//     depth is set with `--jitter-ms` and bounded by `--jitter-min-ms` /
+//     `--jitter-max-ms` (see `jitter`). The depth adapts to the measured
+//     jitter unless `--jitter-fixed`; loss/reorder counts and jitter
+//     percentiles are available from `VoiceSession::jitter_stats` (and the
+//     `stats` command). `VoiceSession::set_target_latency` (or
+//     `--target-latency-ms`) holds it, playout and pacing to a latency
+//     budget instead (see `latency`).
+//   • Every sender plays from a lane of its own – jitter buffer, decoder
+//     and concealment state, and a drift compensator that drops or repeats
+//     the odd sample to hold its queue against that sender's clock – and
+//     lanes only meet in the mix (see `mixer`). `--multicast-mix` plays
+//     every group member at once this way instead of one at a time;
+//     `VoiceSession::peer_playout` reports each lane.
+//   • `--mirror-device` plays the call on a second output device as well,
+//     e.g. speakers beside a headset, held against the first device's clock
+//     the same way; each device has its own volume (`--output-volume-db`,
+//     `--mirror-volume-db`, or `device-volume` mid‑call). Only the first one
+//     feeds the echo canceller. `--virtual-cable also|instead` finds a
+//     virtual cable (VB‑Cable, BlackHole, snd‑aloop) and plays the call into
+//     it as well as or instead of the speakers, for streaming software.
+//   • The `apm` command bypasses the APM or turns its stages – echo
+//     cancellation, noise suppression, gain control, high‑pass – on and off
+//     mid‑call, for an A/B comparison; `stats` shows what runs
+//     (`Controls::set_apm`, `VoiceSession::apm`).
+//   • EBU R128 loudness (momentary, short‑term, integrated LUFS) is metered
+//     on what we send and on the mix (`VoiceSession::loudness`, `stats`);
+//     `--send-lufs` (or `send-lufs` mid‑call) levels the outgoing voice to a
+//     target so everyone arrives alike (see `loudness`).
+//   • `--dump-apm DIR` writes the microphone as the echo canceller got it
+//     and as it came out to two time‑aligned WAVs (see `apm_dump`).
+//   • `--extra-input` mixes a second input device into what we send – a
+//     loopback of desktop audio, say, to share an application's sound with
+//     the voice – bridged onto the first device's clock the same way, each
+//     at its own gain (`--input-gain-db`, `--extra-input-gain-db`, or
+//     `input-gain` mid‑call), ahead of the APM and encoder.
+//   • Lost frames are concealed; with `--fec` on the sending side a single
+//     lost frame is rebuilt from the FEC data in the next packet instead.
+//     On a LAN, `--nack` asks the sender to resend one or two lost packets
+//     and waits a round trip for them first (see `nack`).
+//   • `--duck <dB>` turns the peer down while the local user talks, for
+//     calls on speakers (see `effects`, `vad`).
+//   • What counts as the local user talking – a level threshold on top of
+//     the APM's detector, and how long speech holds – is set with
+//     `--vad-threshold-db`/`--vad-hangover-ms` or the `vad` command;
+//     `vad calibrate` measures the noise floor and suggests a threshold.
+//   • `--normalize` tracks each peer's speech level and brings them all to
+//     about the same loudness; `--peer-gain` (or `gain` mid‑call) sets one
+//     by hand instead (see `effects`). On top of that, `volume` and
+//     `mute-peer` trim a stream or fade it out as it is decoded
+//     (`Controls::set_stream_gain`, `Controls::set_stream_muted`).
+//   • The `hold` command cuts the microphone and tells the peer they're on
+//     hold, looping `--hold-music` (a WAV) to them until `resume` (see
+//     `hold`).
+//   • Live captions: each sender's audio goes to a speech‑to‑text backend
+//     (`SessionConfig::captions`; a Vosk model with `--captions` and the
+//     `vosk` feature) and comes back as `VoiceSession::caption_events` (see
+//     `captions`).
+//   • Talk time per participant (`VoiceSession::talk_stats`, see `talk`),
+//     printed when the call ends.
+//   • Every gap in the peer's sequence is logged; the end of the call prints
+//     a burst‑length histogram, the worst seconds and a heatmap of the call,
+//     and `--loss-csv` writes the gaps out for lining up with network logs
+//     (`VoiceSession::loss_stats`, see `loss`).
+//   • `--cues` plays short local tones when a peer joins or leaves, on mute
+//     and unmute, and when the call reconnects; `--no-cue` leaves single
+//     ones out (see `cues`).
+//   • Receivers watch the spacing of arriving media for queues building on
+//     the path and send back a bandwidth estimate; with `--adaptive-bitrate`
+//     the sender's encoder stays under it (see `bwe`).
+//   • Pings the peer for the round‑trip time and estimates call quality as an
+//     E‑model MOS from delay and effective loss (`VoiceSession::quality_stats`,
+//     see `quality`).
+//   • Traces where the delay goes – device buffers, framing, encode, send
+//     queue, network, decode, playout buffer – and logs per‑stage
+//     percentiles every 30 s (`VoiceSession::latency_stats`, see `latency`).
+//   • Callbacks that overrun their period, playout underruns and frames
+//     that take longer to encode than they last are counted and warned
+//     about (`VoiceSession::realtime_stats`, see `realtime`).
+//   • Optional OpenTelemetry export (`--otlp-endpoint`): spans and the key
+//     call metrics go to an OTLP/HTTP collector (see `telemetry`).
+//   • With the `webrtc` feature, browsers can join the call with nothing but
+//     their own RTCPeerConnection: `--webrtc-addr` answers SDP offers POSTed
+//     to it, and webrtc‑rs carries ICE, DTLS‑SRTP and Opus over RTP. Each
+//     browser plays from a lane of its own and hears what we send (see
+//     `browser`).
+//   • Headless instances can serve `/healthz` and `/readyz` (`--health-addr`)
+//     with socket, signaling, peer and audio‑device status (see `health`).
+//   • With the `grpc` feature, backends in any language can run headless
+//     clients across machines: `--grpc-addr` serves mute, hold, per‑peer
+//     volume and VAD control, and streams the roster, health and stats
+//     (see `grpc`, proto/control.proto).
+//   • Runs supervised under systemd: readiness and stopping are reported
+//     through sd_notify, a socket‑activated listener serves the health
+//     checks, and SIGTERM ends a call, relay or echo server like Ctrl‑C
+//     (see `service`).
+//   • A panic writes a crash report (backtrace, configuration, devices, the
+//     last minute of stats) and tells keyed peers the call is over before
+//     the process exits (see `crash`).
+//   • `--capture-packets` writes every datagram to a pcapng file for
+//     Wireshark (see `capture`); `analyze` reads it, or any pcap, back and
+//     reports each stream's loss, jitter, bitrate and Opus frame types.
+//   • Decodes Opus back to PCM and plays it on the default output device.
+//   • A browser client in `web/` (wasm, built with wasm‑pack) plays the
+//     call without the main thread: Opus is decoded with WebCodecs into a
+//     wait‑free SPSC ring in a SharedArrayBuffer, which an
+//     AudioWorkletProcessor plays from. It asks for the microphone up
+//     front, reports denied, missing or busy devices and the supported
+//     rates as a status, and captures to Opus with WebCodecs. It joins the
+//     same signaling rooms as native clients and calls the peer it is
+//     paired with through that peer's WebRTC endpoint (`--webrtc-url`).
+//   • Embeddable: `VoiceSession` runs a call on the caller's Tokio runtime,
+//     `SessionThread` on its own (used by the Android JNI glue in `android`
+//     and the C ABI in `ffi`, which the iOS static library exports).
+//   • Applications can take each sender's decoded audio as a stream of
+//     frames (`VoiceSession::incoming_audio`) and push their own audio in
+//     (`frames::outgoing` as the session's source) instead of using devices,
+//     and tap the packets we send (`outgoing_packets`) and the mix we play
+//     (`mixed_audio`) for visualisation or analysis. `VoiceSession::injector`
+//     takes audio of any rate and layout to mix with the microphone, duck it
+//     or replace it, processed and encoded like the rest (the `inject`
+//     command plays a WAV that way; see `frames`).
+//
+// Still TODO for production use
+//   • Ship a reference signalling server (the client side lives in `signaling`).
+
+pub mod acl;
+pub mod analyze;
+#[cfg(target_os = "android")]
+mod android;
+mod apm_dump;
+mod broadcast;
+pub mod browser;
+mod bwe;
+pub mod captions;
+pub mod capture;
+pub mod codec;
+pub mod crash;
+pub mod crypto;
+pub mod cues;
+pub mod devices;
+pub mod dht;
+pub mod echo;
+pub mod effects;
+mod encode_pool;
+mod fallback;
+pub mod ffi;
+pub mod flood;
+pub mod frames;
+pub mod grpc;
+pub mod health;
+pub mod hold;
+pub mod jitter;
+pub mod latency;
+pub mod logging;
+pub mod loss;
+pub mod loudness;
+pub mod manual;
+mod matrix;
+pub mod mixer;
+pub mod moderation;
+mod mqtt;
+pub mod multicast;
+mod nack;
+pub mod offline;
+pub mod packet;
+pub mod prefs;
+pub mod proxy;
+pub mod quality;
+pub mod realtime;
+pub mod record;
+pub mod relay;
+mod resample;
+pub mod roundtrip;
+pub mod sdp;
+pub mod selftest;
+pub mod send_queue;
+pub mod service;
+pub mod signaling;
+pub mod simd;
+pub mod sink;
+pub mod socket;
+pub mod source;
+mod surround;
+pub mod talk;
+pub mod telemetry;
+pub mod turn;
+pub mod vad;
+
+use anyhow::{bail, Context, Result};
+use async_channel::{bounded, Receiver, Sender};
+use bytes::Bytes;
+use cpal::traits::*;
+use cpal::Sample;
+use packet::{Packet, Sealed, SilenceReason};
+use parking_lot::Mutex as PLMutex;
+use ringbuf::ring_buffer::{RbRef, RbWrite};
+use std::any::TypeId;
+use std::collections::hash_map::{Entry, HashMap};
+use std::future::Future;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use stunclient::StunClient;
+use tokio::{net::UdpSocket, task};
+use tracing::{debug, error, info, info_span, warn, Instrument as _};
+use webrtc_audio_processing::*;
+
+// ─── Audio constants ────────────────────────────────────────────────────────────
+const SAMPLE_RATE: u32 = 48_000; // Opus best practice
+const MAX_FRAME_MS: usize = 60;
+const MAX_PACKET_SIZE: usize = 400; // plenty for mono 20 ms Opus
+const MAX_SURROUND_PACKET_SIZE: usize = 1200; // stays under a typical MTU
+/// Samples per channel in a frame of `ms` milliseconds.
+const fn frame_samples(ms: usize) -> usize {
+    SAMPLE_RATE as usize * ms / 1000
+}
+
+const HELLO_INTERVAL: Duration = Duration::from_millis(500);
+/// How often a muted or DTX‑silent sender repeats its silence marker.
+const SILENCE_REPEAT_MS: usize = 1000;
+/// How often to measure the round trip once keyed.
+const PING_INTERVAL: Duration = Duration::from_secs(2);
+/// Our introduction is resent every this many pings, in case it was lost.
+const INTRODUCE_EVERY: u32 = 5;
+/// A peer's second path unheard this long is dropped; its first, replaced
+/// by the second.
+const PATH_IDLE: Duration = Duration::from_secs(3);
+/// Addresses besides the first we send a peer's copies to.
+const MAX_PATHS: usize = 2;
+/// How often the receive stats are logged.
+const STATS_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+// ─── Session ───────────────────────────────────────────────────────────────────
+/// What a call starts with. The default has every option off: no peer, the
+/// default devices and an ephemeral port, so set what you need and leave
+/// the rest to `..Default::default()`.
+#[derive(Default)]
+pub struct SessionConfig {
+    pub local_port: u16,
+    pub peer: Option<String>,
+    /// Relay to fall back to if the direct path to the peer doesn't key
+    /// (see `relay`).
+    pub relay: Option<String>,
+    /// Join token for a relay that wants one (see `acl`).
+    pub relay_token: Option<String>,
+    /// TURN server to allocate an address on, for when no direct path keys
+    /// (see `turn`).
+    pub turn: Option<turn::TurnServer>,
+    pub signaling: Option<signaling::Signaling>,
+    /// Find `peer` through the mainline DHT instead of a signaling server.
+    pub dht: Option<dht::DhtOptions>,
+    /// Swap addresses and keys by hand (see `manual::exchange`).
+    pub manual: Option<manual::Exchange>,
+    pub rekey: crypto::RekeyPolicy,
+    pub audio: devices::AudioOptions,
+    pub encoder: codec::EncoderOptions,
+    pub jitter: jitter::JitterOptions,
+    /// Keep a clone to change effects while the call runs.
+    pub effects: Arc<effects::Controls>,
+    /// Record the call mix to this WAV, with a JSON speaker timeline beside it.
+    pub record: Option<PathBuf>,
+    /// Leave the silence out of the recording.
+    pub record_gate: Option<record::VoiceGate>,
+    /// Seal the recording, its timeline and saved clips with this (see
+    /// `record::decrypt`).
+    pub record_passphrase: Option<String>,
+    /// Seconds of the call kept for `VoiceSession::save_clip` (0 for none).
+    pub replay_secs: u32,
+    /// Write the APM's input and output to two WAVs in this directory (see
+    /// `apm_dump`).
+    pub dump_apm: Option<PathBuf>,
+    /// Send this instead of the capture device, which then stays closed.
+    pub source: Option<Box<dyn source::AudioSource>>,
+    /// Play to this instead of the output device, which then stays closed.
+    pub sink: Option<Box<dyn sink::AudioSink>>,
+    /// Broadcast to these listeners (and, with `signaling`, to the room's
+    /// subscribers) instead of calling `peer`. Nothing is received.
+    pub broadcast: Option<Vec<String>>,
+    /// Also encode a broadcast at these lower bitrates (bits a second), each
+    /// listener getting what its bandwidth estimate allows.
+    pub broadcast_tiers: Vec<u32>,
+    /// Send to and play from a LAN multicast group instead of calling `peer`.
+    pub multicast: Option<multicast::MulticastOptions>,
+    /// UDP buffer sizes, fragmentation and priority marking.
+    pub socket: socket::SocketOptions,
+    /// Export metrics (and spans from `telemetry::layer`) over OTLP.
+    pub telemetry: Option<telemetry::OtlpOptions>,
+    /// Serve `/healthz` and `/readyz` on this address.
+    pub health_addr: Option<SocketAddr>,
+    /// Or on this listener, e.g. `service::activated_listener`.
+    pub health_listener: Option<std::net::TcpListener>,
+    /// Write every packet sent and received to a pcapng file.
+    pub capture: Option<capture::CaptureOptions>,
+    /// Transcribe each sender's audio (see `captions`).
+    pub captions: Option<captions::NewTranscriber>,
+    /// Our nickname and metadata, told to each peer once keyed.
+    pub introduction: signaling::Introduction,
+}
+
+/// A running call. Audio stops when this is dropped.
+pub struct VoiceSession {
+    /// `None` when a source and a sink stand in for both devices.
+    _audio: Option<AudioThread>,
+    _source: Option<SourceThread>,
+    _sink: Option<SinkThread>,
+    /// Dropped after the audio, so the recording ends with the call.
+    recorder: Option<record::Recorder>,
+    format: Arc<Format>,
+    mixer: Arc<mixer::Mixer>,
+    mirror: Option<Arc<mixer::Bridge>>,
+    send: Arc<send_queue::Counters>,
+    health: Arc<health::Health>,
+    _capture: Option<capture::Guard>,
+    roster: Arc<signaling::Roster>,
+    captioner: Option<captions::Captioner>,
+    inject: Arc<frames::Injector>,
+    /// Set when we host a multicast group.
+    moderation: Option<Sender<(SocketAddr, moderation::Action)>>,
+    /// Opens lanes for senders from outside the network task (`browser`).
+    decoding: Decoding,
+}
+
+impl VoiceSession {
+    /// Opens the audio devices and starts the pipeline. The network and
+    /// decode tasks are spawned on the current Tokio runtime.
+    pub fn start(config: SessionConfig) -> Result<Self> {
+        config.jitter.validate()?;
+        crash::session_started(&config);
+        let capture = config.capture.as_ref().map(capture::start).transpose()?;
+        let exporter = config.telemetry.map(telemetry::Exporter::new).transpose()?;
+        // Multicast has no rendezvous.
+        let health = Arc::new(health::Health::new(
+            (config.signaling.is_some() || config.dht.is_some() || config.manual.is_some())
+                && config.multicast.is_none(),
+        ));
+        let listener = match (config.health_listener, config.health_addr) {
+            (Some(listener), _) => Some(health::adopt(listener)?),
+            (None, Some(addr)) => Some(
+                health::bind(addr)
+                    .with_context(|| format!("can't serve health checks on {addr}"))?,
+            ),
+            (None, None) => None,
+        };
+        if let Some(listener) = listener {
+            task::spawn(health::serve(listener, Arc::clone(&health)));
+        }
+
+        // Async channels between components.
+        // encoded frames to network
+        let latency = Arc::new(latency::Tracer::default());
+        let (net_tx, net_rx, send_counters) = send_queue::channel(Arc::clone(&latency));
+        // resend requests from the decoder
+        let (nack_tx, nack_rx) = bounded::<Vec<u8>>(16);
+        // the peer's bandwidth estimate, for the encoder
+        let bitrate = config
+            .encoder
+            .adaptive_bitrate
+            .then(|| Arc::new(bwe::Target::default()));
+
+        let (recorder, record) = if config.record.is_some() || config.replay_secs > 0 {
+            let path = config.record.as_deref();
+            let (recorder, tap) = record::Recorder::start(
+                path,
+                config.record_gate,
+                config.record_passphrase,
+                config.replay_secs,
+                SAMPLE_RATE,
+            )?;
+            if let Some(path) = path {
+                info!("recording to {}", path.display());
+            }
+            (Some(recorder), Some(tap))
+        } else {
+            (None, None)
+        };
+        let (captioner, captions) = config
+            .captions
+            .map(captions::Captioner::start)
+            .transpose()?
+            .unzip();
+
+        let jitter = config.jitter;
+        let local_frame_ms = config.audio.frame_ms;
+        let format = Arc::new(Format::new(local_frame_ms, Arc::clone(&latency)));
+        task::spawn(realtime::watch_task(Arc::downgrade(&format)));
+        task::spawn(latency::report_task(Arc::downgrade(&latency)));
+        if let Some(exporter) = exporter {
+            task::spawn(exporter.run(Arc::clone(&format), Arc::clone(&send_counters)));
+        }
+
+        // A lane per sender, each with a decode task of its own (network →
+        // jitter buffer), mixed by the playout chain.
+        let mixer = Arc::new(mixer::Mixer::default());
+        let decoding = Decoding {
+            format: Arc::clone(&format),
+            mixer: Arc::clone(&mixer),
+            local_frame_ms,
+            effects: config.effects.clone(),
+            record: record.clone(),
+            captions,
+            jitter,
+            nack: nack_tx,
+        };
+        let lanes = decoding.clone();
+
+        let local_ip = config
+            .socket
+            .bind_addr
+            .unwrap_or(Ipv4Addr::UNSPECIFIED.into());
+        let local_addr = SocketAddr::new(local_ip, config.local_port);
+        let remote_addr = config.peer;
+
+        let mut enc = codec::Encoder::new(SAMPLE_RATE, config.audio.send_channels)?;
+        enc.apply(&config.encoder)?;
+        let params = codec::StreamParams {
+            frame_ms: config.audio.frame_ms,
+            layout: enc.layout().clone(),
+            fec: config.encoder.fec,
+        };
+        let roster = Arc::new(signaling::Roster::new());
+        let introduction = match config.introduction.is_empty() {
+            true => None,
+            false => Some(Bytes::from(config.introduction.encode()?)),
+        };
+        if let (Some(sig), None) = (&config.signaling, &config.multicast) {
+            task::spawn(
+                sig.clone()
+                    .presence_task(config.effects.clone(), Arc::downgrade(&roster)),
+            );
+        }
+        let mut moderation = None;
+        let mut tier_pool = None;
+        match (config.multicast, config.broadcast) {
+            (Some(opts), _) => {
+                let sock = multicast::join(opts.group, &config.socket)?;
+                let orders = opts.host_key.is_some().then(|| {
+                    let (tx, rx) = bounded(16);
+                    moderation = Some(tx);
+                    rx
+                });
+                spawn_network(
+                    &health,
+                    multicast::multicast_task(
+                        sock,
+                        opts,
+                        params,
+                        net_rx,
+                        mixer::Demux::new(move || decoding.open()),
+                        Arc::clone(&config.effects),
+                        orders,
+                        introduction,
+                        Arc::clone(&roster),
+                        flood::Guard::new(config.socket.limits),
+                        Arc::clone(&health),
+                    ),
+                );
+            }
+            (None, Some(listeners)) => {
+                let tiers = match config.broadcast_tiers.is_empty() {
+                    true => None,
+                    false => {
+                        let (tx, frames) = bounded(64);
+                        let pool = encode_pool::Pool::new(
+                            enc.layout().channels,
+                            &config.encoder,
+                            &config.broadcast_tiers,
+                            tx,
+                        )?;
+                        tier_pool = Some(Arc::new(pool));
+                        Some(broadcast::Tiers {
+                            bitrates: config.broadcast_tiers,
+                            frames,
+                        })
+                    }
+                };
+                spawn_network(
+                    &health,
+                    broadcast::broadcast_task(
+                        socket::bind(local_addr, &config.socket)?,
+                        listeners,
+                        config.signaling,
+                        config.rekey,
+                        params,
+                        net_rx,
+                        tiers,
+                        flood::Guard::new(config.socket.limits),
+                        Arc::clone(&health),
+                    ),
+                );
+            }
+            (None, None) => {
+                let rendezvous = match (config.manual, config.dht, config.signaling) {
+                    (Some(exchange), _, _) => Rendezvous::Manual(exchange),
+                    (None, Some(dht), _) => Rendezvous::Dht(dht),
+                    (None, None, Some(sig)) => Rendezvous::Signaling(Box::new(sig)),
+                    (None, None, None) => Rendezvous::None,
+                };
+                let second = match &config.socket.multipath_interface {
+                    Some(name) => {
+                        let opts = socket::SocketOptions {
+                            interface: Some(name.clone()),
+                            bind_addr: None,
+                            ..config.socket.clone()
+                        };
+                        let any = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0);
+                        Some(socket::bind(any, &opts)?)
+                    }
+                    None => None,
+                };
+                spawn_network(
+                    &health,
+                    network_task(
+                        socket::bind(local_addr, &config.socket)?,
+                        second,
+                        remote_addr.clone(),
+                        config.relay,
+                        config.relay_token,
+                        config.turn.map(|server| (server, config.socket.clone())),
+                        rendezvous,
+                        config.rekey,
+                        params,
+                        net_rx,
+                        decoding.open(),
+                        nack_rx,
+                        bitrate.clone(),
+                        Arc::clone(config.effects.cues()),
+                        introduction,
+                        Arc::clone(&roster),
+                        flood::Guard::new(config.socket.limits),
+                        Arc::clone(&health),
+                    ),
+                );
+            }
+        }
+
+        let ap = voice_processor(enc.layout().channels as usize, config.effects.apm())?;
+        *format.apm.lock() = ap.is_some().then(|| config.effects.apm());
+        let apm_dump = match (&config.dump_apm, &ap) {
+            (Some(dir), Some(_)) => Some(Arc::new(apm_dump::Dump::start(
+                dir,
+                enc.layout().channels as u16,
+            )?)),
+            (Some(_), None) => {
+                warn!("surround goes out without the APM; nothing to dump");
+                None
+            }
+            (None, _) => None,
+        };
+        let inject = Arc::new(frames::Injector::new(enc.layout().channels as usize));
+        let enc = Arc::new(PLMutex::new(enc));
+        let mirror = (config.audio.mirrors() && config.sink.is_none())
+            .then(|| Arc::new(mixer::Bridge::new(2)));
+
+        let pipeline = Pipeline {
+            ap,
+            enc,
+            net_tx,
+            playback: Arc::clone(&mixer),
+            format: Arc::clone(&format),
+            effects: config.effects.clone(),
+            record,
+            health: Arc::clone(&health),
+            bitrate,
+            idle_after: config.audio.idle_after,
+            tiers: tier_pool,
+            mirror: mirror.clone(),
+            extra_input: (config.audio.extra_input.is_some() && config.source.is_none())
+                .then(|| Arc::new(mixer::Bridge::new(2))),
+            apm_dump,
+            inject: Arc::clone(&inject),
+        };
+        let source = config
+            .source
+            .map(|source| SourceThread::spawn(source, &pipeline))
+            .transpose()?;
+
+        let sink = config
+            .sink
+            .map(|sink| SinkThread::spawn(sink, &pipeline))
+            .transpose()?;
+
+        // Build and start CPAL streams.
+        let mut audio_opts = config.audio;
+        audio_opts.playback &= sink.is_none();
+        let audio = match (source.is_none(), audio_opts.playback) {
+            (false, false) => {
+                health.set_audio(health::Status::Ok);
+                None
+            }
+            (capture, _) => Some(AudioThread::spawn(audio_opts, capture, pipeline)?),
+        };
+
+        info!("Voice chat running, sending to {:?}", remote_addr);
+        Ok(Self {
+            _audio: audio,
+            _source: source,
+            _sink: sink,
+            recorder,
+            format,
+            mixer,
+            mirror,
+            send: send_counters,
+            health,
+            _capture: capture,
+            roster,
+            captioner,
+            inject,
+            moderation,
+            decoding: lanes,
+        })
+    }
+
+    /// Packet accounting and jitter of the received stream, refreshed about
+    /// once a second.
+    pub fn jitter_stats(&self) -> jitter::JitterStats {
+        self.format.jitter_stats.lock().clone()
+    }
+
+    /// Frames handed to the network, and frames dropped because it fell
+    /// behind.
+    pub fn send_stats(&self) -> send_queue::SendStats {
+        self.send.snapshot()
+    }
+
+    /// Concealment counters, round‑trip time and the estimated MOS of the
+    /// received audio, refreshed about once a second.
+    pub fn quality_stats(&self) -> quality::QualityStats {
+        self.format.quality_stats.lock().clone()
+    }
+
+    /// Each sender's speech level and the gain they are played at,
+    /// refreshed with every ping.
+    pub fn peer_levels(&self) -> Vec<effects::PeerLevel> {
+        self.format.peer_levels.lock().clone()
+    }
+
+    /// Trades robustness for delay: keeps the jitter buffer, playout and send
+    /// pacing within what `ms` of mouth‑to‑ear latency leaves them, however
+    /// much jitter the network has (see `latency`). `None` goes back to the
+    /// configured jitter buffer.
+    pub fn set_target_latency(&self, ms: Option<u32>) {
+        self.format.latency.set_target_ms(ms);
+    }
+
+    /// How long each participant has talked so far.
+    pub fn talk_stats(&self) -> talk::TalkStats {
+        self.format.talk.stats()
+    }
+
+    /// EBU R128 loudness of what we send and of what we play, and the gain
+    /// bringing what we send to its target (see `loudness`).
+    pub fn loudness(&self) -> loudness::Loudness {
+        *self.format.loudness.lock()
+    }
+
+    /// Which APM stages the capture chain is running, or whether it is
+    /// bypassed (see `effects::Controls::set_apm`); `None` when sending
+    /// surround, which goes out without it.
+    pub fn apm(&self) -> Option<effects::ApmStages> {
+        *self.format.apm.lock()
+    }
+
+    /// Each sender's playout: queue, target, clock drift, and its own
+    /// jitter and quality stats. The session‑wide ones are the worst
+    /// sender's.
+    pub fn peer_playout(&self) -> Vec<mixer::LaneStats> {
+        self.mixer.stats()
+    }
+
+    /// Times the `--mirror-device` ran dry; `None` without one.
+    pub fn mirror_underruns(&self) -> Option<u64> {
+        self.mirror.as_ref().map(|m| m.underruns())
+    }
+
+    /// Every gap in what the peer sent so far, for the loss report.
+    pub fn loss_stats(&self) -> loss::LossStats {
+        self.format.loss.stats()
+    }
+
+    /// Per‑stage percentiles of the pipeline's latency over the last report
+    /// interval (30 s).
+    pub fn latency_stats(&self) -> latency::LatencyStats {
+        self.format.latency.last()
+    }
+
+    /// Callback overruns, playout underruns and late encodes since the call
+    /// started.
+    pub fn realtime_stats(&self) -> realtime::RealtimeStats {
+        realtime::snapshot(&self.format)
+    }
+
+    /// The other members of the signaling room, with nicknames and mute
+    /// state; empty without signaling or if the server has no roster.
+    pub fn roster(&self) -> Vec<signaling::Member> {
+        self.roster.members()
+    }
+
+    /// Join, leave, rename and mute events from the room's roster. Meant for
+    /// one consumer: clones share the queue. The oldest events are dropped
+    /// if nobody reads them.
+    pub fn roster_events(&self) -> Receiver<signaling::RosterEvent> {
+        self.roster.events()
+    }
+
+    /// What `peer` told us about itself, if anything.
+    pub fn introduction(&self, peer: SocketAddr) -> Option<signaling::Introduction> {
+        self.roster.introduction(peer)
+    }
+
+    /// `peer` with its nickname, if it sent one: "alice (192.0.2.1:5000)".
+    pub fn peer_label(&self, peer: SocketAddr) -> String {
+        self.roster.label(peer)
+    }
+
+    /// Captions of what each sender says, as the backend recognises it;
+    /// closed without `SessionConfig::captions`. The oldest are dropped if
+    /// nobody reads them.
+    pub fn caption_events(&self) -> Receiver<captions::Caption> {
+        match &self.captioner {
+            Some(captioner) => captioner.events(),
+            None => bounded(1).1,
+        }
+    }
+
+    /// Each sender's decoded audio, frame by frame, for as long as the
+    /// receiver is kept (see `frames`).
+    pub fn incoming_audio(&self) -> Receiver<frames::AudioFrame> {
+        self.format.incoming.subscribe()
+    }
+
+    /// Where to push audio to send with the microphone's (see `frames`).
+    pub fn injector(&self) -> Arc<frames::Injector> {
+        Arc::clone(&self.inject)
+    }
+
+    /// Every packet the encoder produces, before it is sealed and sent.
+    pub fn outgoing_packets(&self) -> Receiver<frames::EncodedPacket> {
+        self.format.outgoing.subscribe()
+    }
+
+    /// The mix as it goes to the output device or sink.
+    pub fn mixed_audio(&self) -> Receiver<frames::MixedBlock> {
+        self.format.mixed.subscribe()
+    }
+
+    /// Socket, signaling, peer and audio‑device status, as `/healthz` reports
+    /// it.
+    pub fn health(&self) -> health::HealthReport {
+        self.health.report()
+    }
+
+    /// Takes browsers into the call: answers the WebRTC offers they POST to
+    /// `addr` (see `browser`). Needs the `webrtc` feature and a Tokio
+    /// runtime.
+    pub fn serve_browsers(&self, addr: SocketAddr, options: browser::Options) -> Result<()> {
+        browser::serve(addr, options, self.decoding.clone())
+    }
+
+    /// Lets another process or machine control the call and watch its
+    /// roster, health and stats over gRPC on `addr` (see `grpc`). Needs the
+    /// `grpc` feature and a Tokio runtime.
+    pub fn serve_control(&self, addr: SocketAddr, options: grpc::Options) -> Result<()> {
+        let call = grpc::Call {
+            format: Arc::clone(&self.format),
+            mixer: Arc::clone(&self.mixer),
+            send: Arc::clone(&self.send),
+            health: Arc::clone(&self.health),
+            roster: Arc::clone(&self.roster),
+            effects: self.decoding.effects.clone(),
+        };
+        grpc::serve(addr, options, call)
+    }
+
+    /// Writes up to the last `secs` seconds of the call (both sides mixed) to
+    /// a WAV file, and returns its path: `path`, with `.enc` added if
+    /// recordings are sealed. Needs a non‑zero `SessionConfig::replay_secs`.
+    pub fn save_clip(&self, path: &Path, secs: u32) -> Result<PathBuf> {
+        match &self.recorder {
+            Some(recorder) => recorder.save_clip(path, secs),
+            None => bail!("instant replay is off"),
+        }
+    }
+
+    /// Mutes, unmutes or removes the multicast group member sending from
+    /// `peer`. Needs `MulticastOptions::host_key`.
+    pub fn moderate(&self, peer: SocketAddr, action: moderation::Action) -> Result<()> {
+        match &self.moderation {
+            Some(orders) => orders
+                .try_send((peer, action))
+                .map_err(|e| anyhow::anyhow!("can't send the order: {e}")),
+            None => bail!("only the host of a multicast group can moderate it"),
+        }
+    }
+}
+
+/// A `VoiceSession` on a dedicated thread with its own Tokio runtime, for
+/// embedders that don't have one (mobile bindings). Dropping it ends the call
+/// and tears down every task the session spawned.
+pub struct SessionThread {
+    stop: Option<tokio::sync::oneshot::Sender<()>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl SessionThread {
+    /// Blocks until the audio streams are running (or failed to start).
+    pub fn spawn(config: SessionConfig) -> Result<Self> {
+        let (ready_tx, ready_rx) = std::sync::mpsc::channel::<Result<()>>();
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let thread = std::thread::Builder::new()
+            .name("voice-session".into())
+            .spawn(move || {
+                let rt = match tokio::runtime::Builder::new_multi_thread()
+                    .enable_all()
+                    .build()
+                {
+                    Ok(rt) => rt,
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(e.into()));
+                        return;
+                    }
+                };
+                rt.block_on(async move {
+                    match VoiceSession::start(config) {
+                        Ok(session) => {
+                            let _ = ready_tx.send(Ok(()));
+                            let _ = stop_rx.await;
+                            drop(session);
+                        }
+                        Err(e) => {
+                            let _ = ready_tx.send(Err(e));
+                        }
+                    }
+                });
+            })?;
+        ready_rx.recv().context("session thread exited early")??;
+        Ok(Self {
+            stop: Some(stop_tx),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for SessionThread {
+    fn drop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// ─── Audio thread ──────────────────────────────────────────────────────────────
+// cpal streams are not `Send`, so they live on a thread of their own. That
+// thread also watches for devices disappearing or switching format under us
+// (AirPods and many Bluetooth headsets drop to 16/24 kHz when the mic opens)
+// and rebuilds the streams; everything upstream of them runs at a fixed 48 kHz
+// and just keeps going.
+
+const DEVICE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Audio state that outlives any one pair of cpal streams.
+#[derive(Clone)]
+struct Pipeline {
+    /// `None` when sending surround.
+    ap: Option<Processor>,
+    enc: Arc<PLMutex<codec::Encoder>>,
+    net_tx: send_queue::SendQueue,
+    /// A lane per sender, mixed by the playout chain.
+    playback: Arc<mixer::Mixer>,
+    format: Arc<Format>,
+    effects: Arc<effects::Controls>,
+    record: Option<record::Tap>,
+    health: Arc<health::Health>,
+    /// Set with `--adaptive-bitrate`.
+    bitrate: Option<Arc<bwe::Target>>,
+    /// See `vad::Idle`.
+    idle_after: Option<Duration>,
+    /// Lower‑bitrate copies for a broadcast.
+    tiers: Option<Arc<encode_pool::Pool>>,
+    /// What the playout chain plays, for `--mirror-device`.
+    mirror: Option<Arc<mixer::Bridge>>,
+    /// What `--extra-input` captures, for the capture chain.
+    extra_input: Option<Arc<mixer::Bridge>>,
+    /// Set with `--dump-apm`.
+    apm_dump: Option<Arc<apm_dump::Dump>>,
+    /// Audio the application sends with the microphone.
+    inject: Arc<frames::Injector>,
+}
+
+/// Stream format settled with the peer once its Hello arrives.
+struct Format {
+    /// Frame duration both sides send.
+    frame_ms: AtomicUsize,
+    /// Playout ran dry, in any lane, while its sender wasn't silent.
+    underruns: AtomicU64,
+    jitter_stats: PLMutex<jitter::JitterStats>,
+    quality_stats: PLMutex<quality::QualityStats>,
+    peer_levels: PLMutex<Vec<effects::PeerLevel>>,
+    /// Samples per channel queued for playout in the deepest lane, as of the
+    /// last callback.
+    playout_depth: AtomicUsize,
+    /// Time spent in the encoder, and frames encoded.
+    encode_us: AtomicU64,
+    encodes: AtomicU64,
+    latency: Arc<latency::Tracer>,
+    realtime: realtime::Counters,
+    talk: Arc<talk::Tracker>,
+    loss: loss::Log,
+    /// What the capture chain's APM is running; `None` without one.
+    apm: PLMutex<Option<effects::ApmStages>>,
+    loudness: PLMutex<loudness::Loudness>,
+    /// Tap points (see `frames`).
+    incoming: Arc<frames::Subscribers<frames::AudioFrame>>,
+    outgoing: frames::Subscribers<frames::EncodedPacket>,
+    mixed: frames::Subscribers<frames::MixedBlock>,
+}
+
+impl Format {
+    /// Our own preferences, until the peer's Hello.
+    fn new(frame_ms: u8, latency: Arc<latency::Tracer>) -> Self {
+        Self {
+            frame_ms: AtomicUsize::new(frame_ms as usize),
+            underruns: AtomicU64::new(0),
+            jitter_stats: PLMutex::new(jitter::JitterStats::default()),
+            quality_stats: PLMutex::new(quality::QualityStats::default()),
+            peer_levels: PLMutex::new(Vec::new()),
+            playout_depth: AtomicUsize::new(0),
+            encode_us: AtomicU64::new(0),
+            encodes: AtomicU64::new(0),
+            latency,
+            realtime: realtime::Counters::default(),
+            talk: Arc::default(),
+            loss: loss::Log::default(),
+            apm: PLMutex::new(None),
+            loudness: PLMutex::default(),
+            incoming: Arc::default(),
+            outgoing: Default::default(),
+            mixed: Default::default(),
+        }
+    }
+}
+
+/// WebRTC's APM as the capture chain uses it: echo cancellation on 10 ms at
+/// a time of what we send (mono voice, or stereo music) against what we play,
+/// folded to the same layout so a stereo peer on stereo speakers gets stereo
+/// AEC, plus whichever other `stages` are on. Wider layouts go out as captured
+/// and get none.
+fn voice_processor(channels: usize, stages: effects::ApmStages) -> Result<Option<Processor>> {
+    if channels > 2 {
+        return Ok(None);
+    }
+    let init = InitializationConfig {
+        num_capture_channels: channels as i32,
+        num_render_channels: channels as i32,
+        ..InitializationConfig::default()
+    };
+
+    let mut ap = Processor::new(&init)?;
+    ap.set_config(apm_config(stages));
+    Ok(Some(ap))
+}
+
+/// The VAD always runs, even with every stage off; a bypassed APM isn't
+/// called at all.
+fn apm_config(stages: effects::ApmStages) -> Config {
+    Config {
+        echo_cancellation: stages.echo_cancellation.then_some(EchoCancellation {
+            suppression_level: EchoCancellationSuppressionLevel::High,
+            enable_delay_agnostic: false,
+            enable_extended_filter: false,
+            stream_delay_ms: None,
+        }),
+        noise_suppression: stages.noise_suppression.then_some(NoiseSuppression {
+            suppression_level: NoiseSuppressionLevel::High,
+        }),
+        gain_control: stages.gain_control.then_some(GainControl {
+            mode: GainControlMode::AdaptiveDigital,
+            target_level_dbfs: 3,
+            compression_gain_db: 9,
+            enable_limiter: true,
+        }),
+        voice_detection: Some(VoiceDetection {
+            detection_likelihood: VoiceDetectionLikelihood::Moderate,
+        }),
+        enable_high_pass_filter: stages.high_pass,
+        ..Config::default()
+    }
+}
+
+/// The APM's render side: what we play, folded to its layout and handed over
+/// 10 ms at a time as the echo canceller's reference.
+struct EchoReference {
+    ap: Processor,
+    layout: &'static [surround::Speaker],
+    frame: Vec<f32>,
+    block: Vec<f32>,
+}
+
+impl EchoReference {
+    fn new(ap: Processor, channels: usize) -> Self {
+        Self {
+            ap,
+            layout: surround::vorbis(channels),
+            frame: vec![0f32; channels],
+            block: Vec::with_capacity(NUM_SAMPLES_PER_FRAME as usize * channels),
+        }
+    }
+
+    /// Takes one 48 kHz frame in the peer's layout.
+    fn push(&mut self, src: &[f32]) {
+        surround::remix(
+            src,
+            surround::vorbis(src.len()),
+            &mut self.frame,
+            self.layout,
+        );
+        self.block.extend_from_slice(&self.frame);
+        if self.block.len() == NUM_SAMPLES_PER_FRAME as usize * self.frame.len() {
+            let _ = self.ap.process_render_frame(&mut self.block);
+            self.block.clear();
+        }
+    }
+}
+
+struct AudioThread {
+    stop: Option<std::sync::mpsc::Sender<()>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl AudioThread {
+    /// Blocks until the first pair of streams is running (or failed to start).
+    /// Without `capture` the input device is left closed.
+    fn spawn(opts: devices::AudioOptions, capture: bool, pipeline: Pipeline) -> Result<Self> {
+        let (ready_tx, ready_rx) = std::sync::mpsc::channel::<Result<()>>();
+        let (stop_tx, stop_rx) = std::sync::mpsc::channel::<()>();
+        let thread = std::thread::Builder::new()
+            .name("voice-audio".into())
+            .spawn(move || {
+                let opened = devices::select_host(opts.host.as_deref())
+                    .and_then(|host| Ok((Streams::open(&host, &opts, capture, &pipeline)?, host)));
+                let (streams, host) = match opened {
+                    Ok(opened) => {
+                        pipeline.health.set_audio(health::Status::Ok);
+                        let _ = ready_tx.send(Ok(()));
+                        opened
+                    }
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(e));
+                        return;
+                    }
+                };
+                let mut streams = Some(streams);
+                // Runs until `stop` is signalled or dropped.
+                while let Err(RecvTimeoutError::Timeout) =
+                    stop_rx.recv_timeout(DEVICE_POLL_INTERVAL)
+                {
+                    if let Some(current) = &streams {
+                        match current.needs_rebuild(&host, &opts) {
+                            Some(reason) => warn!("{reason}; reopening audio devices"),
+                            None => continue,
+                        }
+                        pipeline.health.set_audio(health::Status::Starting);
+                    }
+                    streams = None;
+                    match Streams::open(&host, &opts, capture, &pipeline) {
+                        Ok(s) => {
+                            streams = Some(s);
+                            pipeline.health.set_audio(health::Status::Ok);
+                        }
+                        Err(e) => {
+                            warn!("reopening audio devices failed: {e:#}");
+                            pipeline.health.set_audio(health::Status::Failed);
+                        }
+                    }
+                }
+            })?;
+        ready_rx.recv().context("audio thread exited early")??;
+        Ok(Self {
+            stop: Some(stop_tx),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for AudioThread {
+    fn drop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+struct Streams {
+    /// `None` when an `AudioSource` feeds the pipeline instead.
+    input: Option<Side>,
+    /// `None` without `--extra-input`, or while that device can't be opened.
+    extra_input: Option<Side>,
+    /// `None` in send‑only mode.
+    output: Option<Side>,
+    /// `None` without `--mirror-device`, or while that device can't be
+    /// opened.
+    mirror: Option<Side>,
+    /// Set from the error callbacks when a device goes away.
+    lost: Arc<AtomicBool>,
+}
+
+/// One direction's device and running stream.
+struct Side {
+    device: cpal::Device,
+    cfg: cpal::StreamConfig,
+    _stream: cpal::Stream,
+}
+
+impl Streams {
+    fn open(
+        host: &cpal::Host,
+        opts: &devices::AudioOptions,
+        capture: bool,
+        pipeline: &Pipeline,
+    ) -> Result<Self> {
+        let _span = info_span!("audio.open").entered();
+        let (input, output) = devices::open_devices(host, opts, capture)?;
+        let lost = Arc::new(AtomicBool::new(false));
+
+        let input = match input {
+            Some(device) => {
+                let cfg =
+                    devices::stream_config(&device.default_input_config()?, opts.buffer_frames);
+                info!(
+                    "Using input device: {}",
+                    device.name().unwrap_or("Unknown".into())
+                );
+                info!("Using input config: {:?}", cfg);
+                let stream = build_input_stream(&device, &cfg, pipeline, &lost, false)?;
+                stream.play()?;
+                Some(Side {
+                    device,
+                    cfg,
+                    _stream: stream,
+                })
+            }
+            None => None,
+        };
+
+        // Like the mirror, the call goes on without it.
+        let extra_input = match &input {
+            Some(_) => Self::open_extra_input(host, opts, pipeline, &lost).unwrap_or_else(|e| {
+                warn!("extra input device unavailable: {e:#}");
+                None
+            }),
+            None => None,
+        };
+
+        let output = match output {
+            Some(device) => {
+                let cfg =
+                    devices::stream_config(&device.default_output_config()?, opts.buffer_frames);
+                info!(
+                    "Using output device: {}",
+                    device.name().unwrap_or("Unknown".into())
+                );
+                info!("Using output config: {:?}", cfg);
+                let stream = build_output_stream(&device, &cfg, pipeline, &lost, false)?;
+                stream.play()?;
+                Some(Side {
+                    device,
+                    cfg,
+                    _stream: stream,
+                })
+            }
+            None => None,
+        };
+
+        // The call goes on without it.
+        let mirror = match &output {
+            Some(_) => Self::open_mirror(host, opts, pipeline, &lost).unwrap_or_else(|e| {
+                warn!("mirror device unavailable: {e:#}");
+                None
+            }),
+            None => None,
+        };
+
+        let sides = [
+            ("input", &input),
+            ("extra input", &extra_input),
+            ("output", &output),
+            ("mirror", &mirror),
+        ];
+        crash::devices(
+            sides
+                .into_iter()
+                .filter_map(|(dir, side)| {
+                    let side = side.as_ref()?;
+                    let name = side.device.name().unwrap_or("Unknown".into());
+                    Some(format!("{dir}: {name} {:?}", side.cfg))
+                })
+                .collect(),
+        );
+        Ok(Self {
+            input,
+            extra_input,
+            output,
+            mirror,
+            lost,
+        })
+    }
+
+    fn open_extra_input(
+        host: &cpal::Host,
+        opts: &devices::AudioOptions,
+        pipeline: &Pipeline,
+        lost: &Arc<AtomicBool>,
+    ) -> Result<Option<Side>> {
+        if pipeline.extra_input.is_none() {
+            return Ok(None);
+        }
+        let Some(device) = devices::extra_input(host, opts)? else {
+            return Ok(None);
+        };
+        let cfg = devices::stream_config(&device.default_input_config()?, opts.buffer_frames);
+        info!(
+            "Mixing in input device: {}",
+            device.name().unwrap_or("Unknown".into())
+        );
+        info!("Using extra input config: {:?}", cfg);
+        let stream = build_input_stream(&device, &cfg, pipeline, lost, true)?;
+        stream.play()?;
+        Ok(Some(Side {
+            device,
+            cfg,
+            _stream: stream,
+        }))
+    }
+
+    fn open_mirror(
+        host: &cpal::Host,
+        opts: &devices::AudioOptions,
+        pipeline: &Pipeline,
+        lost: &Arc<AtomicBool>,
+    ) -> Result<Option<Side>> {
+        if pipeline.mirror.is_none() {
+            return Ok(None);
+        }
+        let Some(device) = devices::mirror_device(host, opts)? else {
+            return Ok(None);
+        };
+        let cfg = devices::stream_config(&device.default_output_config()?, opts.buffer_frames);
+        info!(
+            "Mirroring output to: {}",
+            device.name().unwrap_or("Unknown".into())
+        );
+        info!("Using mirror config: {:?}", cfg);
+        let stream = build_output_stream(&device, &cfg, pipeline, lost, true)?;
+        stream.play()?;
+        Ok(Some(Side {
+            device,
+            cfg,
+            _stream: stream,
+        }))
+    }
+
+    fn needs_rebuild(&self, host: &cpal::Host, opts: &devices::AudioOptions) -> Option<String> {
+        if self.lost.load(Ordering::Relaxed) {
+            return Some("audio device lost".into());
+        }
+        let opened = [
+            (devices::Direction::Input, &self.input),
+            (devices::Direction::Output, &self.output),
+        ];
+        for (dir, side) in opened {
+            let Some(current) = side.as_ref().and_then(|s| s.device.name().ok()) else {
+                continue;
+            };
+            if let Some(now) = devices::default_switched(host, opts, dir, &current) {
+                let dir = match dir {
+                    devices::Direction::Input => "input",
+                    devices::Direction::Output => "output",
+                };
+                return Some(format!("default {dir} device is now {now}"));
+            }
+        }
+        // Errors here are usually "device busy" on ALSA hw devices we are
+        // holding open ourselves; a device that really vanished reports
+        // through the stream error callback instead.
+        let sides = [
+            (
+                "input",
+                self.input
+                    .as_ref()
+                    .map(|s| (s, s.device.default_input_config())),
+            ),
+            (
+                "extra input",
+                self.extra_input
+                    .as_ref()
+                    .map(|s| (s, s.device.default_input_config())),
+            ),
+            (
+                "output",
+                self.output
+                    .as_ref()
+                    .map(|s| (s, s.device.default_output_config())),
+            ),
+            (
+                "mirror",
+                self.mirror
+                    .as_ref()
+                    .map(|s| (s, s.device.default_output_config())),
+            ),
+        ];
+        for (dir, side) in sides {
+            let Some((side, Ok(now))) = side else {
+                continue;
+            };
+            if now.sample_rate() != side.cfg.sample_rate || now.channels() != side.cfg.channels {
+                return Some(format!(
+                    "{dir} switched to {} Hz × {}",
+                    now.sample_rate().0,
+                    now.channels()
+                ));
+            }
+        }
+        None
+    }
+}
+
+fn stream_error_fn(dir: &'static str, lost: &Arc<AtomicBool>) -> impl FnMut(cpal::StreamError) {
+    let lost = Arc::clone(lost);
+    move |e| {
+        error!("{dir} stream error: {e}");
+        if matches!(e, cpal::StreamError::DeviceNotAvailable) {
+            lost.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+// ─── CPAL input stream ─────────────────────────────────────────────────────────
+/// The main input, or with `extra` the second device mixed into it.
+fn build_input_stream(
+    device: &cpal::Device,
+    cfg: &cpal::StreamConfig,
+    pipeline: &Pipeline,
+    lost: &Arc<AtomicBool>,
+    extra: bool,
+) -> Result<cpal::Stream> {
+    match device.default_input_config()?.sample_format() {
+        cpal::SampleFormat::F32 => build_input::<f32>(device, cfg, pipeline, lost, extra),
+        cpal::SampleFormat::I16 => build_input::<i16>(device, cfg, pipeline, lost, extra),
+        cpal::SampleFormat::U16 => build_input::<u16>(device, cfg, pipeline, lost, extra),
+        // ASIO drivers commonly only offer 32‑bit integer samples.
+        cpal::SampleFormat::I32 => build_input::<i32>(device, cfg, pipeline, lost, extra),
+        _ => Err(anyhow::anyhow!("Unsupported sample format")),
+    }
+}
+
+/// What a device callback runs on each buffer, as f32.
+type Chain = Box<dyn FnMut(&mut [f32]) + Send>;
+
+fn build_input<T>(
+    device: &cpal::Device,
+    cfg: &cpal::StreamConfig,
+    pipeline: &Pipeline,
+    lost: &Arc<AtomicBool>,
+    extra: bool,
+) -> Result<cpal::Stream>
+where
+    T: Sample + cpal::SizedSample + 'static,
+{
+    let (rate, channels) = (cfg.sample_rate.0, cfg.channels as usize);
+    let mut capture: Chain = match &pipeline.extra_input {
+        Some(bridge) if extra => Box::new(extra_input_chain(bridge, rate, channels)),
+        _ => {
+            let mut mix = input_mix(pipeline, rate, channels);
+            let mut chain = capture_chain(pipeline, rate, channels);
+            Box::new(move |data: &mut [f32]| {
+                mix(data);
+                chain(data);
+            })
+        }
+    };
+    let format = Arc::clone(&pipeline.format);
+    let mut block = Vec::new();
+    let stream = device.build_input_stream(
+        cfg,
+        move |data: &[T], info: &cpal::InputCallbackInfo| {
+            let started = Instant::now();
+            let ts = info.timestamp();
+            // Backends without timestamps report zero.
+            if let Some(waited) = ts.callback.duration_since(&ts.capture) {
+                if !waited.is_zero() && !extra {
+                    format.latency.record(latency::Stage::CaptureDevice, waited);
+                }
+            }
+            samples_to_f32(data, &mut block);
+            capture(&mut block);
+            if !extra {
+                format
+                    .realtime
+                    .capture_done(started, data.len() / channels, rate);
+            }
+        },
+        stream_error_fn(if extra { "extra input" } else { "input" }, lost),
+        None,
+    )?;
+    Ok(stream)
+}
+
+/// The main input at its gain, plus the `--extra-input` device's queued
+/// samples, resampled to this device's rate and layout, at theirs.
+fn input_mix(
+    pipeline: &Pipeline,
+    rate: u32,
+    dev_channels: usize,
+) -> impl FnMut(&mut [f32]) + Send + 'static {
+    let controls = Arc::clone(&pipeline.effects);
+    let extra = pipeline.extra_input.clone();
+    if let Some(extra) = &extra {
+        extra.restart();
+    }
+    let dev_layout = surround::wav(dev_channels);
+    let mut gains = [rate, rate].map(effects::StreamGain::new);
+    let mut resampler = resample::Resampler::new(SAMPLE_RATE, rate, 2);
+    let mut mixed = vec![0f32; dev_channels];
+    let (mut block, mut remixed) = (Vec::new(), Vec::new());
+    move |data: &mut [f32]| {
+        let main = controls.input_gain(effects::Input::Main);
+        gains[0].process(data, dev_channels, main);
+        let Some(extra) = &extra else {
+            return;
+        };
+        let frames = data.len() / dev_channels;
+        block.resize(resampler.input_frames(frames) * 2, 0.0);
+        extra.play(&mut block);
+        let mut next = block.chunks_exact(2);
+        remixed.clear();
+        for _ in 0..frames {
+            let src = resampler.pull(|f| {
+                if let Some(from) = next.next() {
+                    f.copy_from_slice(from);
+                }
+            });
+            surround::remix(src, surround::vorbis(2), &mut mixed, dev_layout);
+            remixed.extend_from_slice(&mixed);
+        }
+        let gain = controls.input_gain(effects::Input::Extra);
+        gains[1].process(&mut remixed, dev_channels, gain);
+        for (d, s) in data.iter_mut().zip(&remixed) {
+            *d = (*d + s).clamp(-1.0, 1.0);
+        }
+    }
+}
+
+/// The extra input's side of its `mixer::Bridge`: device samples folded to
+/// stereo and resampled to 48 kHz.
+fn extra_input_chain(
+    bridge: &Arc<mixer::Bridge>,
+    rate: u32,
+    dev_channels: usize,
+) -> impl FnMut(&mut [f32]) + Send + 'static {
+    let bridge = Arc::clone(bridge);
+    let dev_layout = surround::wav(dev_channels);
+    let mut resampler = resample::Resampler::new(rate, SAMPLE_RATE, 2);
+    let mut pair = vec![0f32; 2];
+    let mut block = Vec::new();
+    move |data: &mut [f32]| {
+        block.clear();
+        for frame in data.chunks_exact(dev_channels) {
+            surround::remix(frame, dev_layout, &mut pair, surround::vorbis(2));
+            resampler.push(&pair, |f| block.extend_from_slice(f));
+        }
+        bridge.feed(&block);
+    }
+}
+
+/// Everything from interleaved device samples to sent packets. Fed by the
+/// capture stream, or by the source thread when an `AudioSource` stands in
+/// for the microphone.
+fn capture_chain(
+    pipeline: &Pipeline,
+    rate: u32,
+    dev_channels: usize,
+) -> impl FnMut(&[f32]) + Send + 'static {
+    let Pipeline {
+        mut ap,
+        enc,
+        net_tx,
+        format,
+        effects,
+        record,
+        bitrate,
+        idle_after,
+        tiers,
+        apm_dump,
+        inject,
+        ..
+    } = pipeline.clone();
+
+    // Down‑mix to mono (or remix to the surround layout we send) and
+    // resample to the codec rate first.
+    let send_channels = enc.lock().layout().channels as usize;
+    if send_channels > dev_channels {
+        warn!("sending {send_channels} channels from a {dev_channels}‑channel input");
+    }
+    let (dev_layout, send_layout) = (surround::wav(dev_channels), surround::vorbis(send_channels));
+    let mut send_frame = vec![0f32; send_channels];
+    let mut mono = Vec::new();
+    let mut resampler = resample::Resampler::new(rate, SAMPLE_RATE, send_channels);
+    let mut compressor = effects::Compressor::new(SAMPLE_RATE);
+    let mut normalizer = loudness::Normalizer::new(send_channels);
+    let mut pitch = effects::PitchShifter::new();
+    // Buffer to accumulate exactly one Opus frame before encoding; the
+    // duration can change once the peer's preference is known.
+    let max_frame_len = frame_samples(MAX_FRAME_MS) * send_channels;
+    let mut frame_buf = Vec::<f32>::with_capacity(max_frame_len * 2);
+    let mut tmp = vec![0f32; max_frame_len];
+    // `tmp` before the APM, for `--dump-apm`.
+    let mut raw = Vec::with_capacity(max_frame_len);
+    // When the oldest sample in `frame_buf` came in.
+    let mut frame_started: Option<Instant> = None;
+    // Silence markers replace media while muted or in DTX; `quiet_frames`
+    // paces the repeats.
+    let mut quiet = None;
+    let mut quiet_frames = 0;
+    let mut speech = vad::Detector::default();
+    // While idle, input is only measured; `idle_samples` paces the markers.
+    let mut idle = vad::Idle::new(idle_after);
+    let mut idle_samples = 0;
+    let mut hold_announcer = hold::Announcer::default();
+    let mut hold_music = hold::Player::default();
+    move |data: &[f32]| {
+        if idle.idle() {
+            let busy = effects.calibrating_vad() || inject.pending();
+            if effects.muted() || effects.on_hold() || busy {
+                idle.interrupt();
+            } else if !idle.wake(data) {
+                idle_samples += data.len() / dev_channels;
+                if idle_samples * 1000 >= rate as usize * SILENCE_REPEAT_MS {
+                    net_tx.push(Outbound::Silence(SilenceReason::Silent));
+                    idle_samples = 0;
+                }
+                return;
+            }
+            debug!("input woke; processing again");
+        }
+        let frame_ms = format.frame_ms.load(Ordering::Relaxed);
+        let frame_len = frame_samples(frame_ms) * send_channels;
+        // Sized for 20 ms and scaled, so longer frames don't cost bitrate.
+        let packet_limit = match send_channels {
+            1 => (MAX_PACKET_SIZE * frame_ms / 20).min(MAX_SURROUND_PACKET_SIZE),
+            _ => MAX_SURROUND_PACKET_SIZE,
+        };
+        let mut on_frame = |f: &[f32]| {
+            let mut went_idle = false;
+            if frame_buf.is_empty() {
+                frame_started = Some(Instant::now());
+            }
+            frame_buf.extend_from_slice(f);
+            if frame_buf.len() >= frame_len {
+                let processing = Instant::now();
+                if let Some(started) = frame_started {
+                    let framing = processing - started;
+                    format.latency.record(latency::Stage::Framing, framing);
+                }
+                let tmp = &mut tmp[..frame_len];
+                tmp.copy_from_slice(&frame_buf[..frame_len]);
+                inject.apply(tmp);
+                let held = effects.on_hold();
+                if let Some(held) = hold_announcer.frame(held, frame_ms) {
+                    net_tx.push(Outbound::Hold(held));
+                }
+                // Hold music goes out even while muted.
+                let music = held.then(|| effects.hold_music()).flatten();
+                let silenced = match () {
+                    _ if music.is_some() => None,
+                    _ if effects.muted() => Some(SilenceReason::Muted),
+                    _ if held => Some(SilenceReason::Silent),
+                    _ => None,
+                };
+                let reason = if let Some(reason) = silenced {
+                    effects.set_local_speech(false);
+                    Some(reason)
+                } else {
+                    if let Some(music) = &music {
+                        effects.set_local_speech(false);
+                        hold_music.fill(music, tmp, send_channels);
+                    } else {
+                        // The APM takes mono or stereo, 10 ms at a time; the
+                        // voice effects are mono. Surround goes out as
+                        // captured.
+                        let stages = effects.apm();
+                        let vad_settings = effects.vad();
+                        if let Some(ap) = &mut ap {
+                            let mut running = format.apm.lock();
+                            if running.is_some_and(|r| r != stages) {
+                                info!("APM now {stages}");
+                                ap.set_config(apm_config(stages));
+                                *running = Some(stages);
+                            }
+                        }
+                        let voice = match &mut ap {
+                            Some(ap) if !stages.bypass => {
+                                if apm_dump.is_some() {
+                                    raw.clear();
+                                    raw.extend_from_slice(tmp);
+                                }
+                                let block = NUM_SAMPLES_PER_FRAME as usize * send_channels;
+                                let mut voice = false;
+                                for chunk in tmp.chunks_mut(block) {
+                                    let _ = ap.process_capture_frame(chunk);
+                                    voice |= ap.get_stats().has_voice.unwrap_or(false);
+                                }
+                                if let Some(dump) = &apm_dump {
+                                    dump.push(&raw, tmp);
+                                }
+                                voice
+                            }
+                            _ => true,
+                        };
+                        let level = vad::level_db(tmp);
+                        let took = Duration::from_millis(frame_ms as u64);
+                        if let Some(floor) = effects.calibrate_frame(level, took) {
+                            info!(
+                                "STATUS: vad_calibrated floor={:.1} suggested={:.1}",
+                                floor.floor_db, floor.suggested_db
+                            );
+                        }
+                        speech.set_hangover(vad_settings.hangover);
+                        let talking = speech.update(voice && level >= vad_settings.threshold_db);
+                        effects.set_local_speech(talking);
+                        let busy = effects.calibrating_vad() || inject.pending();
+                        went_idle |= idle.update(talking || busy);
+                        if talking {
+                            format.talk.local(took);
+                        }
+                        if send_channels == 1 {
+                            compressor.process(tmp, effects.compressor());
+                            pitch.process(tmp, effects.pitch_ratio());
+                        }
+                        normalizer.process(tmp, send_channels, effects.send_loudness());
+                        {
+                            let mut loudness = format.loudness.lock();
+                            loudness.capture = normalizer.stats();
+                            loudness.send_gain_db = normalizer.gain_db();
+                        }
+                        if let Some(tap) = &record {
+                            tap.push(record::Speaker::Local, tmp, send_channels);
+                        }
+                    }
+
+                    let mut enc = enc.lock();
+                    if let Some(bps) = bitrate.as_ref().and_then(|t| t.take(frame_ms)) {
+                        if let Err(e) = enc.set_bitrate(bps) {
+                            error!("{e}");
+                        }
+                    }
+                    let mut pkt_buf = [0u8; MAX_SURROUND_PACKET_SIZE];
+                    let started = Instant::now();
+                    let encoded = enc.encode_float(tmp, &mut pkt_buf[..packet_limit]);
+                    let took = started.elapsed().as_micros() as u64;
+                    format.encode_us.fetch_add(took, Ordering::Relaxed);
+                    format.encodes.fetch_add(1, Ordering::Relaxed);
+                    let processed = processing.elapsed();
+                    format.latency.record(latency::Stage::Encode, processed);
+                    format.realtime.encoded(processed, frame_ms);
+                    match encoded {
+                        // A DTX frame is just the TOC byte (or two).
+                        Ok(len) if len <= 2 => Some(SilenceReason::Silent),
+                        Ok(len) => {
+                            let pkt = Bytes::copy_from_slice(&pkt_buf[..len]);
+                            format.outgoing.push(|| frames::EncodedPacket {
+                                payload: pkt.clone(),
+                                frame_ms: frame_ms as u8,
+                                channels: send_channels,
+                            });
+                            net_tx.push(Outbound::Frame(pkt));
+                            if let Some(pool) = &tiers {
+                                pool.submit(tmp);
+                            }
+                            None
+                        }
+                        Err(e) => {
+                            error!("opus encode error: {e}");
+                            None
+                        }
+                    }
+                };
+                // Announce every change, then repeat once a second in
+                // case a marker got lost.
+                if let Some(reason) = reason {
+                    if quiet != Some(reason) || quiet_frames * frame_ms >= SILENCE_REPEAT_MS {
+                        net_tx.push(Outbound::Silence(reason));
+                        quiet_frames = 0;
+                    }
+                    quiet_frames += 1;
+                }
+                quiet = reason;
+                frame_buf.drain(..frame_len);
+                frame_started = (!frame_buf.is_empty()).then(Instant::now);
+                if went_idle {
+                    debug!("no speech for a while; idling the capture chain");
+                    // Nothing buffered survives the gap; the peer hears
+                    // silence from here.
+                    net_tx.push(Outbound::Silence(SilenceReason::Silent));
+                    quiet = Some(SilenceReason::Silent);
+                    quiet_frames = 1;
+                    idle_samples = 0;
+                    frame_buf.clear();
+                    frame_started = None;
+                }
+            }
+        };
+        if send_channels == 1 {
+            mono.resize(data.len() / dev_channels, 0.0);
+            simd::downmix(data, dev_channels, &mut mono);
+            for s in &mono {
+                resampler.push(std::slice::from_ref(s), &mut on_frame);
+            }
+        } else {
+            for frame in data.chunks(dev_channels) {
+                surround::remix(frame, dev_layout, &mut send_frame, send_layout);
+                resampler.push(&send_frame, &mut on_frame);
+            }
+        }
+    }
+}
+
+// ─── Source thread ─────────────────────────────────────────────────────────────
+/// Feeds an `AudioSource` through the capture chain in real time.
+struct SourceThread {
+    stop: Option<std::sync::mpsc::Sender<()>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl SourceThread {
+    const BLOCK: Duration = Duration::from_millis(10);
+
+    fn spawn(mut source: Box<dyn source::AudioSource>, pipeline: &Pipeline) -> Result<Self> {
+        let (stop_tx, stop_rx) = std::sync::mpsc::channel::<()>();
+        let channels = source.channels();
+        let mut capture = capture_chain(pipeline, source.sample_rate(), channels);
+        let format = Arc::clone(&pipeline.format);
+        let mut buf = vec![0f32; source.sample_rate() as usize / 100 * channels];
+        let thread = std::thread::Builder::new()
+            .name("voice-source".into())
+            .spawn(move || {
+                let mut next = std::time::Instant::now();
+                // Behind by a whole block already; counted once per lapse.
+                let mut late = false;
+                loop {
+                    let frames = source.read(&mut buf);
+                    if frames == 0 {
+                        info!("STATUS: source_finished");
+                        let _ = stop_rx.recv();
+                        return;
+                    }
+                    capture(&buf[..frames * channels]);
+                    // Pace by deadline, so sleep overshoot doesn't add up.
+                    next += Self::BLOCK;
+                    let now = std::time::Instant::now();
+                    let behind = now.saturating_duration_since(next) >= Self::BLOCK;
+                    if behind && !late {
+                        format.realtime.capture_late();
+                    }
+                    late = behind;
+                    let wait = next.saturating_duration_since(now);
+                    if !matches!(stop_rx.recv_timeout(wait), Err(RecvTimeoutError::Timeout)) {
+                        return;
+                    }
+                }
+            })?;
+        Ok(Self {
+            stop: Some(stop_tx),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for SourceThread {
+    fn drop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// ─── CPAL output stream ─────────────────────────────────────────────────────────
+/// The main output, or with `mirror` the second device that copies it.
+fn build_output_stream(
+    device: &cpal::Device,
+    cfg: &cpal::StreamConfig,
+    pipeline: &Pipeline,
+    lost: &Arc<AtomicBool>,
+    mirror: bool,
+) -> Result<cpal::Stream> {
+    match device.default_output_config()?.sample_format() {
+        cpal::SampleFormat::F32 => build_output::<f32>(device, cfg, pipeline, lost, mirror),
+        cpal::SampleFormat::I16 => build_output::<i16>(device, cfg, pipeline, lost, mirror),
+        cpal::SampleFormat::U16 => build_output::<u16>(device, cfg, pipeline, lost, mirror),
+        cpal::SampleFormat::I32 => build_output::<i32>(device, cfg, pipeline, lost, mirror),
+        _ => Err(anyhow::anyhow!("Unsupported sample format")),
+    }
+}
+
+fn build_output<T>(
+    device: &cpal::Device,
+    cfg: &cpal::StreamConfig,
+    pipeline: &Pipeline,
+    lost: &Arc<AtomicBool>,
+    mirror: bool,
+) -> Result<cpal::Stream>
+where
+    T: Sample + cpal::SizedSample + cpal::FromSample<f32> + 'static,
+{
+    let format = Arc::clone(&pipeline.format);
+    let rate = cfg.sample_rate.0;
+    let dev_channels = cfg.channels as usize;
+    let mut playout: Chain = match &pipeline.mirror {
+        Some(copy) if mirror => Box::new(mirror_chain(pipeline, copy, rate, dev_channels)),
+        _ => Box::new(playout_chain(pipeline, rate, dev_channels)),
+    };
+    let mut block = Vec::new();
+
+    let stream = device.build_output_stream(
+        cfg,
+        move |out: &mut [T], info: &cpal::OutputCallbackInfo| {
+            let started = Instant::now();
+            let ts = info.timestamp();
+            if let Some(ahead) = ts.playback.duration_since(&ts.callback) {
+                if !ahead.is_zero() && !mirror {
+                    format.latency.record(latency::Stage::OutputDevice, ahead);
+                }
+            }
+            block.clear();
+            block.resize(out.len(), 0.0);
+            playout(&mut block);
+            for (o, &s) in out.iter_mut().zip(&block) {
+                *o = T::from_sample(s);
+            }
+            if !mirror {
+                format
+                    .realtime
+                    .playback_done(started, out.len() / dev_channels, rate);
+            }
+        },
+        stream_error_fn(if mirror { "mirror" } else { "output" }, lost),
+        None,
+    )?;
+    Ok(stream)
+}
+
+/// Everything from the senders' lanes to interleaved device samples: each
+/// lane's playout and comfort noise, the mix, the echo canceller's reference,
+/// the mix tap, the loudness meter and the mirror's feed, resampling, the speaker layout
+/// and the device's volume. Drives the output stream, or the sink thread when an `AudioSink`
+/// stands in for the speaker.
+fn playout_chain(
+    pipeline: &Pipeline,
+    rate: u32,
+    dev_channels: usize,
+) -> impl FnMut(&mut [f32]) + Send + 'static {
+    let mixer = Arc::clone(&pipeline.playback);
+    let format = Arc::clone(&pipeline.format);
+    let dev_layout = surround::wav(dev_channels);
+    let mut resampler = resample::Resampler::new(SAMPLE_RATE, rate, 1);
+    let mut mixed = vec![0f32; dev_channels];
+    let mut block = Vec::new();
+    let mut cues = cues::Player::new(Arc::clone(pipeline.effects.cues()));
+    let send_channels = pipeline.enc.lock().layout().channels as usize;
+    let mut echo = pipeline
+        .ap
+        .clone()
+        .map(|ap| EchoReference::new(ap, send_channels));
+    let mirror = pipeline.mirror.clone();
+    let (mut pair, mut stereo) = (vec![0f32; 2], Vec::new());
+    let mut meter = loudness::Meter::new(2);
+    let controls = Arc::clone(&pipeline.effects);
+    let mut volume = effects::StreamGain::new(rate);
+    move |out: &mut [f32]| {
+        // Play surround as is when the device has the speakers, fold it
+        // down otherwise.
+        let channels = mixer.channels();
+        if channels != resampler.channels() {
+            resampler = resample::Resampler::new(SAMPLE_RATE, rate, channels);
+        }
+        let frames = resampler.input_frames(out.len() / dev_channels);
+        block.resize(frames * channels, 0.0);
+        mixer.mix(&mut block, channels, Some(&format));
+        format.mixed.push(|| frames::MixedBlock {
+            sample_rate: SAMPLE_RATE,
+            channels,
+            samples: block.clone(),
+        });
+        stereo.clear();
+        for f in block.chunks_exact_mut(channels) {
+            let cue = cues.next();
+            if cue != 0.0 {
+                f.iter_mut().for_each(|s| *s += cue);
+            }
+            if let Some(echo) = &mut echo {
+                echo.push(f);
+            }
+            let (from, to) = (surround::vorbis(channels), surround::vorbis(2));
+            surround::remix(f, from, &mut pair, to);
+            stereo.extend_from_slice(&pair);
+        }
+        if meter.process(&stereo) {
+            format.loudness.lock().mix = meter.stats();
+        }
+        if let Some(mirror) = &mirror {
+            mirror.feed(&stereo);
+        }
+        let mut next = block.chunks_exact(channels);
+        for frame in out.chunks_mut(dev_channels) {
+            let src = resampler.pull(|f| {
+                if let Some(from) = next.next() {
+                    f.copy_from_slice(from);
+                }
+            });
+            surround::remix(src, surround::vorbis(channels), &mut mixed, dev_layout);
+            frame.copy_from_slice(&mixed[..frame.len()]);
+        }
+        let output = controls.device_volume(effects::Output::Main);
+        volume.process(out, dev_channels, output);
+    }
+}
+
+/// The mirror device's side of `mixer::Bridge`: the main output's mix, queued,
+/// resampled to this device's rate and layout, at this device's volume.
+fn mirror_chain(
+    pipeline: &Pipeline,
+    mirror: &Arc<mixer::Bridge>,
+    rate: u32,
+    dev_channels: usize,
+) -> impl FnMut(&mut [f32]) + Send + 'static {
+    mirror.restart();
+    let mirror = Arc::clone(mirror);
+    let controls = Arc::clone(&pipeline.effects);
+    let dev_layout = surround::wav(dev_channels);
+    let mut resampler = resample::Resampler::new(SAMPLE_RATE, rate, 2);
+    let mut volume = effects::StreamGain::new(rate);
+    let mut mixed = vec![0f32; dev_channels];
+    let mut block = Vec::new();
+    move |out: &mut [f32]| {
+        let frames = resampler.input_frames(out.len() / dev_channels);
+        block.resize(frames * 2, 0.0);
+        mirror.play(&mut block);
+        let mut next = block.chunks_exact(2);
+        for frame in out.chunks_mut(dev_channels) {
+            let src = resampler.pull(|f| {
+                if let Some(from) = next.next() {
+                    f.copy_from_slice(from);
+                }
+            });
+            surround::remix(src, surround::vorbis(2), &mut mixed, dev_layout);
+            frame.copy_from_slice(&mixed[..frame.len()]);
+        }
+        let output = controls.device_volume(effects::Output::Mirror);
+        volume.process(out, dev_channels, output);
+    }
+}
+
+// ─── Sink thread ───────────────────────────────────────────────────────────────
+/// Feeds an `AudioSink` from the playout chain in real time.
+struct SinkThread {
+    stop: Option<std::sync::mpsc::Sender<()>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl SinkThread {
+    const BLOCK: Duration = Duration::from_millis(10);
+
+    fn spawn(mut sink: Box<dyn sink::AudioSink>, pipeline: &Pipeline) -> Result<Self> {
+        let (stop_tx, stop_rx) = std::sync::mpsc::channel::<()>();
+        let channels = sink.channels();
+        let mut playout = playout_chain(pipeline, sink.sample_rate(), channels);
+        let mut buf = vec![0f32; sink.sample_rate() as usize / 100 * channels];
+        let thread = std::thread::Builder::new()
+            .name("voice-sink".into())
+            .spawn(move || {
+                let mut next = std::time::Instant::now();
+                loop {
+                    playout(&mut buf);
+                    sink.write(&buf);
+                    // Pace by deadline, so sleep overshoot doesn't add up.
+                    next += Self::BLOCK;
+                    let wait = next.saturating_duration_since(std::time::Instant::now());
+                    if !matches!(stop_rx.recv_timeout(wait), Err(RecvTimeoutError::Timeout)) {
+                        return;
+                    }
+                }
+            })?;
+        Ok(Self {
+            stop: Some(stop_tx),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for SinkThread {
+    fn drop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// ─── Network task (UDP) ────────────────────────────────────────────────────────
+/// Capture → network messages.
+enum Outbound {
+    /// An encoded Opus frame.
+    Frame(Bytes),
+    /// Sent instead of media while we're muted or DTX‑silent.
+    Silence(SilenceReason),
+    /// We put the peer on hold, or resumed.
+    Hold(bool),
+}
+
+/// One remote on the shared socket, and where its stream goes.
+struct Peer {
+    /// Key the signaling server relayed for this peer; Hellos must match it.
+    expected_key: Option<[u8; crypto::PUBLIC_KEY_LEN]>,
+    /// Consumed when the peer's Hello completes it.
+    handshake: Option<crypto::Handshake>,
+    /// Set once keyed.
+    session: Option<crypto::Session>,
+    /// The decode pipeline for this peer's media.
+    inbound: Sender<Inbound>,
+    /// (epoch, seq) of the newest packet that authenticated.
+    newest: Option<(u8, u32)>,
+    /// Which of the 64 sequence numbers up to `newest` arrived already.
+    seen: u64,
+    /// When a packet from the table address last authenticated.
+    heard: Instant,
+    /// Other addresses the peer's packets authenticate from at the same
+    /// time (it sends over two networks), and when each was last heard.
+    paths: Vec<(SocketAddr, Instant)>,
+    /// Media we sealed for this peer, should it ask for some again.
+    history: nack::History,
+    /// Watches the peer's media for queues building; set once keyed.
+    bwe: Option<bwe::Estimator>,
+    /// The peer has us on hold.
+    holding: bool,
+}
+
+impl Peer {
+    fn new(
+        expected_key: Option<[u8; crypto::PUBLIC_KEY_LEN]>,
+        handshake: Option<crypto::Handshake>,
+        inbound: Sender<Inbound>,
+    ) -> Self {
+        Self {
+            expected_key,
+            handshake,
+            session: None,
+            inbound,
+            newest: None,
+            seen: 0,
+            heard: Instant::now(),
+            paths: Vec::new(),
+            history: nack::History::default(),
+            bwe: None,
+            holding: false,
+        }
+    }
+
+    /// Records an authenticated packet; false for a copy already received
+    /// over another path.
+    fn first_copy(&mut self, at: (u8, u32)) -> bool {
+        let Some(newest) = self.newest else {
+            self.newest = Some(at);
+            self.seen = 1;
+            return true;
+        };
+        if is_newer(at, newest) {
+            self.seen = match at.0 == newest.0 {
+                true => self.seen.checked_shl(at.1 - newest.1).unwrap_or(0) | 1,
+                false => 1,
+            };
+            self.newest = Some(at);
+            return true;
+        }
+        // Older than the window, or from the previous key epoch: too late to
+        // tell, and the jitter buffer drops what it has already played.
+        let back = newest.1.wrapping_sub(at.1);
+        if at.0 != newest.0 || back >= u64::BITS {
+            return true;
+        }
+        let first = self.seen & 1 << back == 0;
+        self.seen |= 1 << back;
+        first
+    }
+}
+
+/// Remotes by address. Packets from addresses not in the table are ignored,
+/// unless they authenticate as a known peer whose address changed.
+type PeerTable = Arc<PLMutex<HashMap<SocketAddr, Peer>>>;
+
+/// Whether `a` comes after `b` in a sender's sequence; epochs wrap.
+fn is_newer(a: (u8, u32), b: (u8, u32)) -> bool {
+    match a.0 == b.0 {
+        true => a.1 > b.1,
+        false => a.0.wrapping_sub(b.0) <= u8::MAX / 2,
+    }
+}
+
+/// The address of the keyed peer that sealed `pkt`, if it sent it: the peer
+/// switched networks, its NAT mapping changed or it sends over a second
+/// network too. Only a packet newer than
+/// anything the peer sent before counts, so replaying a captured one from
+/// elsewhere can't redirect the call, and only as many as `guard` allows
+/// `src` are tried at all.
+fn moved_from(
+    peers: &mut HashMap<SocketAddr, Peer>,
+    pkt: &Packet,
+    src: SocketAddr,
+    guard: &mut flood::Guard,
+) -> Option<SocketAddr> {
+    let Packet::Sealed {
+        kind,
+        epoch,
+        seq,
+        payload,
+    } = pkt
+    else {
+        return None;
+    };
+    if !guard.trial(src) {
+        return None;
+    }
+    peers.iter_mut().find_map(|(addr, peer)| {
+        let newer = peer.newest.is_none_or(|n| is_newer((*epoch, *seq), n));
+        let session = peer.session.as_mut()?;
+        (newer && session.opener.open(*kind, *epoch, *seq, payload).is_some()).then_some(*addr)
+    })
+}
+
+/// Sends a copy of `pkt` over the `--multipath` socket, if there is one.
+/// Failures are expected while that network is down and only logged quietly.
+async fn send_second(second: Option<&UdpSocket>, pkt: &[u8], addr: SocketAddr) {
+    let Some(second) = second else {
+        return;
+    };
+    capture::sent(second, addr, pkt);
+    if let Err(e) = second.send_to(pkt, addr).await {
+        debug!("udp send error on the second path: {e}");
+    }
+}
+
+/// How a call finds its peer when no address is given.
+enum Rendezvous {
+    /// Wait for whoever sends the first Hello.
+    None,
+    Signaling(Box<signaling::Signaling>),
+    Dht(dht::DhtOptions),
+    Manual(manual::Exchange),
+}
+
+/// Runs a network task; if it gives up, the socket is reported failed.
+fn spawn_network(
+    health: &Arc<health::Health>,
+    net: impl Future<Output = Result<()>> + Send + 'static,
+) {
+    let health = Arc::clone(health);
+    task::spawn(async move {
+        if let Err(e) = net.await {
+            error!("network: {e:#}");
+            health.set_socket(health::Status::Failed);
+        }
+    });
+}
+
+/// Files the not yet keyed peer at `from` under `to` instead.
+fn move_peer(peers: &PeerTable, from: SocketAddr, to: SocketAddr) {
+    let mut peers = peers.lock();
+    if let Some(peer) = peers.remove(&from) {
+        peers.insert(to, peer);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn network_task(
+    sock: UdpSocket,
+    second: Option<UdpSocket>,
+    remote_addr: Option<String>,
+    relay: Option<String>,
+    relay_token: Option<String>,
+    // With the options its socket is tuned with.
+    turn: Option<(turn::TurnServer, socket::SocketOptions)>,
+    rendezvous: Rendezvous,
+    rekey: crypto::RekeyPolicy,
+    params: codec::StreamParams,
+    mut outbound: send_queue::Outbox,
+    inbound_tx: Sender<Inbound>,
+    nack: Receiver<Vec<u8>>,
+    bitrate: Option<Arc<bwe::Target>>,
+    cues: Arc<cues::Cues>,
+    introduction: Option<Bytes>,
+    roster: Arc<signaling::Roster>,
+    mut guard: flood::Guard,
+    health: Arc<health::Health>,
+) -> Result<()> {
+    let sock = Arc::new(sock);
+
+    // A peer on this host (the self‑test) is reached without STUN, which
+    // may not even be reachable.
+    let loopback = remote_addr
+        .as_deref()
+        .and_then(|a| a.parse::<SocketAddr>().ok())
+        .filter(|a| a.ip().is_loopback());
+    let public_address = match loopback {
+        Some(peer) => SocketAddr::new(peer.ip(), sock.local_addr()?.port()),
+        None => get_public_address(&sock).await?,
+    };
+    info!("Reflexive addr {}", public_address);
+    let lan = socket::lan_address(&sock);
+    match lan {
+        Some(addr) => info!("sending from {}", socket::describe(addr)),
+        None => warn!("no route to the internet from this socket"),
+    }
+    let second = second.map(Arc::new);
+    if let Some(second) = &second {
+        match socket::lan_address(second) {
+            Some(addr) => info!("also sending from {}", socket::describe(addr)),
+            None => warn!("no route to the internet over the second path yet"),
+        }
+    }
+    health.set_socket(health::Status::Ok);
+
+    let psk = match &rendezvous {
+        Rendezvous::Signaling(sig) => sig.media_psk(),
+        _ => None,
+    };
+    let handshake = crypto::Handshake::new()?.with_psk(psk);
+
+    // Allocated before the rendezvous, so the peer learns the address.
+    let turn = match turn {
+        Some((server, opts)) => match turn::Allocation::new(&server, &sock, &opts).await {
+            Ok(allocation) => Some(allocation),
+            Err(e) => {
+                warn!("no TURN allocation, going on without: {e:#}");
+                None
+            }
+        },
+        None => None,
+    };
+
+    // Key the signaling server relayed for the peer; Hellos must match it.
+    let mut expected_key = None;
+    let me = signaling::JoinPayload {
+        reflexive_addr: public_address.to_string(),
+        lan_addr: lan.map(|a| a.to_string()).unwrap_or_default(),
+        pub_key: crypto::key_to_hex(&handshake.public_key()),
+        relayed_addr: turn
+            .as_ref()
+            .map(|t| t.relayed().to_string())
+            .unwrap_or_default(),
+    };
+    let joined = match rendezvous {
+        // An address given up front wins.
+        _ if remote_addr.is_some() => None,
+        Rendezvous::None => None,
+        Rendezvous::Signaling(sig) => Some(
+            sig.register_and_wait(&me)
+                .instrument(info_span!("signaling.join"))
+                .await,
+        ),
+        Rendezvous::Dht(dht) => Some(
+            dht::rendezvous(&sock, &dht, public_address)
+                .instrument(info_span!("dht.rendezvous"))
+                .await
+                .map(|addr| signaling::PeerInfo {
+                    reflexive_addr: addr.to_string(),
+                    lan_addr: String::new(),
+                    pub_key: String::new(),
+                    relayed_addr: String::new(),
+                    room_proof: None,
+                    webrtc: false,
+                }),
+        ),
+        Rendezvous::Manual(exchange) => Some(exchange.run(&me, &params).await),
+    };
+    // The peer's address on its own network and on its TURN server, if the
+    // rendezvous told us.
+    let mut peer_lan = None;
+    let mut peer_relayed = None;
+    let remote_addr = match joined {
+        None => remote_addr,
+        Some(Err(e)) => {
+            health.set_signaling(health::Status::Failed);
+            return Err(e);
+        }
+        // It calls the WebRTC endpoint (see `browser`), not our socket.
+        Some(Ok(peer)) if peer.webrtc => {
+            health.set_signaling(health::Status::Ok);
+            info!("matched a browser; it comes in through the WebRTC endpoint");
+            None
+        }
+        Some(Ok(peer)) => {
+            health.set_signaling(health::Status::Ok);
+            info!(
+                "matched peer {} (lan {})",
+                peer.reflexive_addr, peer.lan_addr
+            );
+            expected_key = crypto::key_from_hex(&peer.pub_key);
+            if expected_key.is_none() {
+                warn!("peer advertised no usable key; relying on SAS only");
+            }
+            peer_lan = peer.lan_addr.parse::<SocketAddr>().ok();
+            peer_relayed = peer.relayed_addr.parse::<SocketAddr>().ok();
+            Some(peer.reflexive_addr)
+        }
+    };
+
+    let frame_ms = params.frame_ms;
+    let hello = Packet::Hello {
+        pub_key: handshake.public_key(),
+        params,
+    }
+    .encode();
+    let peers: PeerTable = Arc::default();
+    // If we crash, tell keyed peers the call is over.
+    {
+        let sock = Arc::downgrade(&sock);
+        let peers = Arc::downgrade(&peers);
+        crash::on_crash(move || {
+            let (Some(sock), Some(peers)) = (sock.upgrade(), peers.upgrade()) else {
+                return;
+            };
+            let Some(mut peers) = peers.try_lock_for(Duration::from_millis(50)) else {
+                return;
+            };
+            for (addr, peer) in peers.iter_mut() {
+                if let Some(Ok(pkt)) = peer
+                    .session
+                    .as_mut()
+                    .map(|s| s.sealer.seal(Sealed::Bye, &[]))
+                {
+                    let _ = sock.try_send_to(&pkt, *addr);
+                }
+            }
+        });
+    }
+    // Without a remote we wait for whoever sends the first Hello.
+    let mut pending = Some(handshake);
+    let remote = match &remote_addr {
+        Some(peer) => {
+            let addr = tokio::net::lookup_host(peer)
+                .await?
+                .next()
+                .with_context(|| format!("{peer} did not resolve"))?;
+            peers.lock().insert(
+                addr,
+                Peer::new(expected_key, pending.take(), inbound_tx.clone()),
+            );
+            info!(peer = %addr, "STATUS: punch_attempt {addr}");
+            Some(addr)
+        }
+        None => {
+            info!("STATUS: listen_only");
+            None
+        }
+    };
+    let keyed = Arc::new(AtomicBool::new(false));
+    // From the first Hello to the first key.
+    let mut connecting = Some(info_span!("call.connect", peer = tracing::field::Empty));
+
+    // Keep offering our key until the peer's Hello arrives; the peer answers
+    // every Hello it sees, so one of ours getting through is enough. Each
+    // way of reaching the peer gets a while before the next is tried (see
+    // `fallback`), and the peer moves to the address being tried.
+    let relay = match (relay, remote) {
+        (Some(relay), Some(remote)) => {
+            let addr = tokio::net::lookup_host(&relay)
+                .await?
+                .next()
+                .with_context(|| format!("relay {relay} did not resolve"))?;
+            let id = relay::pairing_id(public_address, remote);
+            let token = relay_token.as_deref().unwrap_or_default().as_bytes();
+            Some((addr, Packet::Relay { id, token }.encode()))
+        }
+        _ => None,
+    };
+    // The relay again over TCP; like `turn`, kept for the whole call.
+    let tunnel = match &relay {
+        Some((addr, _)) => relay::tunnel(*addr, &sock)
+            .map_err(|e| warn!("no relay over TCP: {e:#}"))
+            .ok(),
+        None => None,
+    };
+    if let Some(remote) = remote {
+        let lan = peer_lan
+            .filter(|lan| *lan != remote && !lan.ip().is_unspecified())
+            .map(|addr| fallback::Attempt {
+                step: fallback::Step::Lan,
+                addr,
+                target: addr,
+                bind: None,
+            });
+        let punch = fallback::Attempt {
+            step: fallback::Step::Punch,
+            addr: remote,
+            target: remote,
+            bind: None,
+        };
+        // Out through our allocation if we have one, else straight to the
+        // peer's; either way to the peer's relayed address if it has one.
+        let turned = match (&turn, peer_relayed) {
+            (Some(turn), _) => {
+                turn.open(peer_relayed.unwrap_or(remote));
+                Some(fallback::Attempt {
+                    step: fallback::Step::Turn,
+                    addr: turn.local(),
+                    target: turn.server(),
+                    bind: None,
+                })
+            }
+            (None, Some(addr)) => Some(fallback::Attempt {
+                step: fallback::Step::Turn,
+                addr,
+                target: addr,
+                bind: None,
+            }),
+            (None, None) => None,
+        };
+        let relayed = relay.iter().map(|(addr, bind)| fallback::Attempt {
+            step: fallback::Step::Relay,
+            addr: *addr,
+            target: *addr,
+            bind: Some(bind.clone()),
+        });
+        let tcp = relay
+            .iter()
+            .zip(&tunnel)
+            .map(|((addr, bind), tunnel)| fallback::Attempt {
+                step: fallback::Step::Tcp,
+                addr: tunnel.local(),
+                target: *addr,
+                bind: Some(bind.clone()),
+            });
+        let attempts = lan
+            .into_iter()
+            .chain([punch])
+            .chain(turned)
+            .chain(relayed)
+            .chain(tcp);
+        let mut chain = fallback::Chain::new(attempts.collect());
+        move_peer(&peers, remote, chain.current().addr);
+        let sock = Arc::clone(&sock);
+        let hello = hello.clone();
+        let keyed = Arc::clone(&keyed);
+        let peers = Arc::clone(&peers);
+        task::spawn(async move {
+            while !keyed.load(Ordering::Relaxed) {
+                if let Some(left) = chain.advance() {
+                    let attempt = chain.current();
+                    if attempt.step > fallback::Step::Punch {
+                        let (step, through) = (attempt.step, attempt.target);
+                        warn!("no direct path to {remote}; relaying through {through} ({step})");
+                        info!(peer = %remote, %step, "STATUS: relaying {through}");
+                    }
+                    move_peer(&peers, left, attempt.addr);
+                }
+                let attempt = chain.current();
+                if let Some(bind) = &attempt.bind {
+                    capture::sent(&sock, attempt.addr, bind);
+                    if let Err(e) = sock.send_to(bind, attempt.addr).await {
+                        error!("udp send error: {e}");
+                    }
+                }
+                capture::sent(&sock, attempt.addr, &hello);
+                if let Err(e) = sock.send_to(&hello, attempt.addr).await {
+                    error!("udp send error: {e}");
+                }
+                tokio::time::sleep(HELLO_INTERVAL).await;
+            }
+        });
+    }
+
+    // Seals `body` for every keyed peer, once, and lists it for each of the
+    // peer's addresses; sending happens after the table lock is released.
+    let seal_all = |peers: &PeerTable, kind: Sealed, body: &[u8]| -> Vec<(SocketAddr, Bytes)> {
+        let mut peers = peers.lock();
+        let mut out = Vec::with_capacity(peers.len());
+        for (addr, peer) in peers.iter_mut() {
+            match peer.session.as_mut().map(|s| s.sealer.seal(kind, body)) {
+                Some(Ok(pkt)) => {
+                    peer.history.push(&pkt);
+                    out.extend(peer.paths.iter().map(|(path, _)| (*path, pkt.clone())));
+                    out.push((*addr, pkt));
+                }
+                Some(Err(e)) => error!("{e}"),
+                // Media is never sent in the clear; drop frames until keyed.
+                None => {}
+            }
+        }
+        out
+    };
+
+    // Round‑trip probes: the peer echoes our timestamp back in a Pong. They
+    // also keep the path open when we change networks: unless `--interface`
+    // or `--bind-addr` ties it down, the socket isn't tied to an interface,
+    // and the peer follows the first packet that authenticates from our new
+    // address.
+    let start = Instant::now();
+    if remote.is_some() {
+        let sock = Arc::clone(&sock);
+        let second = second.clone();
+        let peers = Arc::clone(&peers);
+        let cues = Arc::clone(&cues);
+        let introduction = introduction.clone();
+        task::spawn(async move {
+            let mut interval = tokio::time::interval(PING_INTERVAL);
+            let mut local = socket::lan_address(&sock);
+            let mut pings = 0u32;
+            loop {
+                interval.tick().await;
+                pings += 1;
+                let now = socket::lan_address(&sock);
+                if now != local {
+                    match now {
+                        Some(addr) => {
+                            info!("local address changed to {}", socket::describe(addr));
+                            if local.is_none() {
+                                cues.play(cues::Cue::Reconnect);
+                            }
+                        }
+                        None => warn!("lost the network; waiting for it to return"),
+                    }
+                    info!("STATUS: local_address_changed");
+                    local = now;
+                }
+                let stamp = (start.elapsed().as_micros() as u64).to_be_bytes();
+                let mut pkts: Vec<_> = seal_all(&peers, Sealed::Ping, &stamp)
+                    .into_iter()
+                    .map(|(addr, pkt)| (addr, pkt, &stamp[..]))
+                    .collect();
+                if let Some(intro) = introduction
+                    .as_ref()
+                    .filter(|_| pings.is_multiple_of(INTRODUCE_EVERY))
+                {
+                    let intros = seal_all(&peers, Sealed::Introduce, intro);
+                    pkts.extend(
+                        intros
+                            .into_iter()
+                            .map(|(addr, pkt)| (addr, pkt, &intro[..])),
+                    );
+                }
+                for (addr, pkt, body) in pkts {
+                    capture::sent(&sock, addr, &pkt);
+                    capture::decrypted(&sock, addr, capture::Direction::Out, &pkt, body);
+                    if let Err(e) = sock.send_to(&pkt, addr).await {
+                        error!("udp send error: {e}");
+                    }
+                    send_second(second.as_deref(), &pkt, addr).await;
+                }
+            }
+        });
+    }
+
+    // The decoder's resend requests.
+    {
+        let sock = Arc::clone(&sock);
+        let second = second.clone();
+        let peers = Arc::clone(&peers);
+        task::spawn(async move {
+            while let Ok(body) = nack.recv().await {
+                for (addr, pkt) in seal_all(&peers, Sealed::Nack, &body) {
+                    capture::sent(&sock, addr, &pkt);
+                    capture::decrypted(&sock, addr, capture::Direction::Out, &pkt, &body);
+                    if let Err(e) = sock.send_to(&pkt, addr).await {
+                        error!("udp send error: {e}");
+                    }
+                    send_second(second.as_deref(), &pkt, addr).await;
+                }
+            }
+        });
+    }
+
+    let sock_recv = Arc::clone(&sock);
+
+    // The second path's datagrams join the first's in the receive loop.
+    let (second_tx, second_rx) = bounded::<(Bytes, SocketAddr)>(256);
+    if let Some(second) = &second {
+        let second = Arc::clone(second);
+        task::spawn(async move {
+            let mut buf = [0u8; MAX_SURROUND_PACKET_SIZE + packet::MEDIA_OVERHEAD];
+            loop {
+                match second.recv_from(&mut buf).await {
+                    Ok((n, src)) => {
+                        capture::received(&second, src, &buf[..n]);
+                        let pkt = Bytes::copy_from_slice(&buf[..n]);
+                        if second_tx.send((pkt, src)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => warn!("udp recv error on the second path: {e}"),
+                }
+            }
+        });
+    } else {
+        drop(second_tx);
+    }
+
+    // Sender task
+    let send = {
+        let sock = Arc::clone(&sock);
+        let second = second.clone();
+        let has_peer = remote.is_some();
+        let peers = Arc::clone(&peers);
+
+        task::spawn(async move {
+            while let Some(msg) = outbound.recv().await {
+                if !has_peer {
+                    continue;
+                }
+                let (kind, body) = match &msg {
+                    Outbound::Frame(frame) => (Sealed::Media, &frame[..]),
+                    Outbound::Silence(reason) => (Sealed::Silence, &[*reason as u8][..]),
+                    Outbound::Hold(held) => (Sealed::Hold, &[*held as u8][..]),
+                };
+                for (addr, pkt) in seal_all(&peers, kind, body) {
+                    capture::sent(&sock, addr, &pkt);
+                    capture::decrypted(&sock, addr, capture::Direction::Out, &pkt, body);
+                    if let Err(e) = sock.send_to(&pkt, addr).await {
+                        error!("udp send error: {e}");
+                    }
+                    send_second(second.as_deref(), &pkt, addr).await;
+                }
+            }
+        })
+    };
+
+    // Receiver task
+    let recv = task::spawn(async move {
+        let mut buf = [0u8; MAX_SURROUND_PACKET_SIZE + packet::MEDIA_OVERHEAD];
+        loop {
+            // Replies go back out the socket the request came in on.
+            let (n, src, via) = tokio::select! {
+                r = sock_recv.recv_from(&mut buf) => match r {
+                    Ok((n, src)) => {
+                        health.set_socket(health::Status::Ok);
+                        capture::received(&sock_recv, src, &buf[..n]);
+                        (n, src, &sock_recv)
+                    }
+                    Err(e) => {
+                        error!("udp recv error: {e}");
+                        health.set_socket(health::Status::Failed);
+                        continue;
+                    }
+                },
+                Ok((pkt, src)) = second_rx.recv() => {
+                    let n = pkt.len().min(buf.len());
+                    buf[..n].copy_from_slice(&pkt[..n]);
+                    // Only received on when it exists.
+                    (n, src, second.as_ref().unwrap())
+                }
+            };
+            if !guard.admit(src, n) {
+                continue;
+            }
+            let Some(pkt) = Packet::parse(&buf[..n]) else {
+                continue;
+            };
+            // Replies to send and messages to route once the table is
+            // unlocked.
+            let mut replies = Vec::new();
+            let mut route = None;
+            let mut promote = None;
+            {
+                let mut peers = peers.lock();
+                // A peer's other path is filed under its first.
+                let alias = match peers.contains_key(&src) {
+                    true => None,
+                    false => peers
+                        .iter()
+                        .find(|(_, p)| p.paths.iter().any(|(path, _)| *path == src))
+                        .map(|(addr, _)| *addr),
+                };
+                let moved = match alias.is_some() || peers.contains_key(&src) {
+                    true => None,
+                    false => moved_from(&mut peers, &pkt, src, &mut guard),
+                };
+                // Taken as a second path until the first goes quiet: a peer
+                // that moved stops sending from its old address, one that
+                // sends over two networks doesn't.
+                if let Some(old) = moved {
+                    if let Some(peer) = peers.get_mut(&old) {
+                        info!(peer = %old, path = %src, "peer {old} also sending from {src}");
+                        info!(peer = %old, "STATUS: peer_path_added {src}");
+                        if peer.paths.len() >= MAX_PATHS {
+                            peer.paths.remove(0);
+                        }
+                        peer.paths.push((src, Instant::now()));
+                    }
+                }
+                let key = alias.or(moved).unwrap_or(src);
+                let peer = match peers.entry(key) {
+                    Entry::Occupied(known) => known.into_mut(),
+                    // Listening: the first peer to say Hello gets our key.
+                    Entry::Vacant(slot) => match (&pkt, pending.take()) {
+                        (Packet::Hello { .. }, Some(hs)) => {
+                            slot.insert(Peer::new(None, Some(hs), inbound_tx.clone()))
+                        }
+                        (_, hs) => {
+                            pending = hs;
+                            continue;
+                        }
+                    },
+                };
+                match pkt {
+                    // For relays only.
+                    Packet::Relay { .. } => continue,
+                    Packet::Hello { pub_key, params } => {
+                        if peer.expected_key.is_some_and(|k| k != pub_key) {
+                            warn!("ignoring Hello from {src}: key differs from signaling");
+                            continue;
+                        }
+                        let mut introduce = false;
+                        if let Some(hs) = peer.handshake.take() {
+                            match hs.complete(&pub_key, rekey) {
+                                Ok(s) => {
+                                    info!(peer = %src, sas = %s.sas, "STATUS: keyed {src} sas={}", s.sas);
+                                    cues.play(cues::Cue::Join);
+                                    peer.session = Some(s);
+                                    // Both sides send the longer frames.
+                                    let frame_ms = frame_ms.max(params.frame_ms);
+                                    peer.bwe = Some(bwe::Estimator::new(frame_ms));
+                                    keyed.store(true, Ordering::Relaxed);
+                                    if let Some(span) = connecting.take() {
+                                        span.record("peer", tracing::field::display(src));
+                                    }
+                                    let msg = Inbound::Params(src, params);
+                                    route = Some((peer.inbound.clone(), msg));
+                                    introduce = true;
+                                }
+                                Err(e) => error!("handshake with {src} failed: {e}"),
+                            }
+                        } else if peer.session.as_ref().is_some_and(|s| s.peer_key != pub_key) {
+                            warn!("{src} restarted the handshake; restart to verify again");
+                        }
+                        replies.push(hello.clone());
+                        // After the Hello, which the peer needs to open it.
+                        let session = peer.session.as_mut().filter(|_| introduce);
+                        if let (Some(intro), Some(s)) = (&introduction, session) {
+                            match s.sealer.seal(Sealed::Introduce, intro) {
+                                Ok(pkt) => replies.push(pkt),
+                                Err(e) => error!("{e}"),
+                            }
+                        }
+                    }
+                    Packet::Sealed {
+                        kind,
+                        epoch,
+                        seq,
+                        payload,
+                    } => {
+                        let pos = jitter::Position {
+                            epoch,
+                            seq,
+                            at: Instant::now(),
+                        };
+                        let Some(session) = peer.session.as_mut() else {
+                            continue;
+                        };
+                        let Some(body) = session.opener.open(kind, epoch, seq, payload) else {
+                            warn!(peer = %src, epoch, seq, "dropping unauthenticated media");
+                            continue;
+                        };
+                        capture::decrypted(via, src, capture::Direction::In, &buf[..n], &body);
+                        match peer.paths.iter_mut().find(|(path, _)| *path == src) {
+                            Some((_, heard)) => *heard = pos.at,
+                            None => peer.heard = pos.at,
+                        }
+                        peer.paths.retain(|(_, heard)| pos.at - *heard < PATH_IDLE);
+                        if key != src && pos.at - peer.heard >= PATH_IDLE {
+                            promote = Some(key);
+                        }
+                        if !peer.first_copy((epoch, seq)) {
+                            continue;
+                        }
+                        health.packet();
+                        let estimate = match (kind, &mut peer.bwe) {
+                            (Sealed::Media, Some(bwe)) => bwe.arrived(epoch, seq, pos.at, n),
+                            _ => None,
+                        };
+                        if let Some(bps) = estimate {
+                            let rate = bwe::encode(bps);
+                            match peer
+                                .session
+                                .as_mut()
+                                .map(|s| s.sealer.seal(Sealed::Rate, &rate))
+                            {
+                                Some(Ok(pkt)) => replies.push(pkt),
+                                Some(Err(e)) => error!("{e}"),
+                                None => {}
+                            }
+                        }
+                        let msg = match kind {
+                            Sealed::Media => Inbound::Frame(pos, body),
+                            Sealed::Silence => {
+                                match body.first().copied().and_then(SilenceReason::from_byte) {
+                                    Some(reason) => Inbound::Silence(pos, reason),
+                                    None => continue,
+                                }
+                            }
+                            Sealed::Ping => {
+                                let session = peer.session.as_mut();
+                                match session.map(|s| s.sealer.seal(Sealed::Pong, &body)) {
+                                    Some(Ok(pkt)) => replies.push(pkt),
+                                    Some(Err(e)) => error!("{e}"),
+                                    None => {}
+                                }
+                                Inbound::Probe(pos, None)
+                            }
+                            Sealed::Pong => {
+                                let Ok(stamp) = <[u8; 8]>::try_from(&body[..]) else {
+                                    continue;
+                                };
+                                let sent = Duration::from_micros(u64::from_be_bytes(stamp));
+                                Inbound::Probe(pos, start.elapsed().checked_sub(sent))
+                            }
+                            Sealed::Nack => {
+                                let resend = peer.history.resend(&body);
+                                debug!(peer = %src, "resending {} packets", resend.len());
+                                replies.extend(resend);
+                                Inbound::Probe(pos, None)
+                            }
+                            Sealed::Rate => {
+                                match bitrate.as_ref().and_then(|t| t.report(&body)) {
+                                    Some(0) => {
+                                        info!(peer = %key, "peer lifted its bandwidth estimate")
+                                    }
+                                    Some(bps) => info!(
+                                        peer = %key,
+                                        "peer estimates the path carries {} kbps",
+                                        bps / 1000
+                                    ),
+                                    None => {}
+                                }
+                                Inbound::Probe(pos, None)
+                            }
+                            Sealed::Hold => {
+                                let holding = body.first() == Some(&1);
+                                if peer.holding != holding {
+                                    peer.holding = holding;
+                                    let label = roster.label(key);
+                                    match holding {
+                                        true => {
+                                            info!(peer = %key, %label, "STATUS: on_hold {key}")
+                                        }
+                                        false => {
+                                            info!(peer = %key, %label, "STATUS: hold_resumed {key}")
+                                        }
+                                    }
+                                }
+                                Inbound::Probe(pos, None)
+                            }
+                            // Only multicast groups have a host.
+                            Sealed::Moderate => continue,
+                            Sealed::Introduce => {
+                                match signaling::Introduction::decode(&body) {
+                                    Some(intro) => roster.introduce(key, intro),
+                                    None => debug!(peer = %key, "unreadable introduction"),
+                                }
+                                Inbound::Probe(pos, None)
+                            }
+                            Sealed::Bye => {
+                                let label = roster.label(key);
+                                info!(peer = %key, %label, "STATUS: peer_left {key}");
+                                cues.play(cues::Cue::Leave);
+                                continue;
+                            }
+                        };
+                        let _ = peer.inbound.try_send(msg);
+                    }
+                }
+                if let Some(old) = promote {
+                    if let Some(mut peer) = peers.remove(&old) {
+                        info!(peer = %src, from = %old, "peer moved from {old} to {src}");
+                        info!(peer = %src, "STATUS: peer_migrated {src}");
+                        cues.play(cues::Cue::Reconnect);
+                        peer.paths.retain(|(path, _)| *path != src);
+                        peer.heard = Instant::now();
+                        peers.insert(src, peer);
+                    }
+                }
+            }
+            // Only set when a peer was just keyed.
+            if let Some((inbound, msg)) = route {
+                let keyed = peers
+                    .lock()
+                    .values()
+                    .filter(|p| p.session.is_some())
+                    .count();
+                health.set_peers(keyed);
+                let _ = inbound.send(msg).await;
+            }
+            for pkt in replies {
+                capture::sent(via, src, &pkt);
+                if let Err(e) = via.send_to(&pkt, src).await {
+                    error!("udp send error: {e}");
+                }
+            }
+        }
+    });
+
+    let _ = tokio::join!(send, recv);
+    Ok(())
+}
+
+// ─── Decode task ───────────────────────────────────────────────────────────────
+// When the peer sends in‑band FEC, each frame is held back until its successor
+// arrives: if exactly the frames in between went missing, the successor
+// carries a copy of the last of them. That costs one frame of latency. Other
+// short gaps are filled by Opus' packet loss concealment. With `--nack`, a
+// short gap on a fast link is first asked for again (see `nack`).
+
+/// Gaps longer than this many frames are left to the jitter buffer instead
+/// of being concealed.
+const MAX_CONCEALED_FRAMES: u32 = 3;
+
+/// Network → decoder messages, in arrival order.
+enum Inbound {
+    /// Who sends from now on and their stream parameters, from the Hello
+    /// that completed the handshake.
+    Params(SocketAddr, codec::StreamParams),
+    Frame(jitter::Position, Vec<u8>),
+    /// The peer stopped sending media, and why.
+    Silence(jitter::Position, SilenceReason),
+    /// A Ping or Nack, or a Pong with the round‑trip time it measured.
+    Probe(jitter::Position, Option<Duration>),
+}
+
+/// The decoder and everything its output passes through on the way to the
+/// playback ring.
+struct Playout<S>
+where
+    S: RbRef,
+    <S as RbRef>::Rb: RbWrite<f32>,
+{
+    dec: codec::Decoder,
+    pcm_buf: Vec<f32>,
+    eq: effects::Equalizer,
+    /// The current sender, and each sender's level so far.
+    peer: Option<SocketAddr>,
+    levels: HashMap<SocketAddr, effects::Leveler>,
+    outputs: HashMap<SocketAddr, effects::StreamGain>,
+    listener: talk::Listener,
+    talk: Arc<talk::Tracker>,
+    duck: effects::Ducker,
+    effects: Arc<effects::Controls>,
+    record: Option<record::Tap>,
+    captions: Option<captions::Tap>,
+    incoming: Arc<frames::Subscribers<frames::AudioFrame>>,
+    producer: ringbuf::Producer<f32, S>,
+    /// Samples per channel the ring may hold.
+    backlog: usize,
+    latency: Arc<latency::Tracer>,
+}
+
+enum Decode<'a> {
+    Packet(&'a [u8]),
+    /// The FEC copy of the previous frame inside this packet.
+    Fec(&'a [u8]),
+    Conceal,
+}
+
+impl<S> Playout<S>
+where
+    S: RbRef,
+    <S as RbRef>::Rb: RbWrite<f32>,
+{
+    /// Decodes one frame and queues it. FEC and concealment produce
+    /// `frame_len` samples per channel. Returns false if the ring was full.
+    fn play(&mut self, what: Decode, frame_len: usize) -> bool {
+        let channels = self.dec.channels();
+        let exact = &mut self.pcm_buf[..frame_len * channels];
+        let started = Instant::now();
+        let decoded = match what {
+            Decode::Packet(pkt) => self.dec.decode_float(pkt, &mut self.pcm_buf, false),
+            Decode::Fec(pkt) => self.dec.decode_float(pkt, exact, true),
+            Decode::Conceal => self.dec.conceal(exact),
+        };
+        self.latency
+            .record(latency::Stage::Decode, started.elapsed());
+        let sz = match decoded {
+            Ok(sz) => sz,
+            Err(e) => {
+                warn!("opus decode error: {e}");
+                return true;
+            }
+        };
+        info!("Decoded {} samples", sz);
+        let pcm = &mut self.pcm_buf[..sz * channels];
+        if let Some(peer) = self.peer {
+            self.incoming.push(|| frames::AudioFrame {
+                peer,
+                sample_rate: SAMPLE_RATE,
+                channels,
+                samples: pcm.to_vec(),
+            });
+        }
+        if channels == 1 {
+            self.eq.process(pcm, self.effects.eq(self.peer));
+        }
+        if let Some(peer) = self.peer {
+            if let Some(tap) = &self.captions {
+                tap.push(peer, pcm, channels);
+            }
+            if self.listener.talking(peer, pcm) {
+                let took = Duration::from_secs_f64(sz as f64 / SAMPLE_RATE as f64);
+                self.talk.peer(peer, took);
+            }
+            let level = self
+                .levels
+                .entry(peer)
+                .or_insert_with(|| effects::Leveler::new(SAMPLE_RATE));
+            let manual = self.effects.peer_gain(peer);
+            level.process(pcm, channels, self.effects.normalize(), manual);
+            self.outputs
+                .entry(peer)
+                .or_insert_with(|| effects::StreamGain::new(SAMPLE_RATE))
+                .process(pcm, channels, self.effects.stream_output(peer));
+        }
+        if let Some(tap) = &self.record {
+            tap.push(record::Speaker::Peer, pcm, channels);
+        }
+        let speaking = self.effects.local_speech();
+        self.duck
+            .process(pcm, channels, speaking, self.effects.ducking());
+        // Whole frames only, so channels stay aligned in the ring.
+        if self.producer.len() + pcm.len() > self.backlog * channels {
+            return false;
+        }
+        self.producer.push_slice(pcm);
+        true
+    }
+
+    /// Plays packets that arrived in order, each after the number of lost
+    /// ones before it (see `play_in_order`).
+    fn play_all(
+        &mut self,
+        held: &mut Option<Vec<u8>>,
+        frames: Vec<(Vec<u8>, u32)>,
+        peer_fec: bool,
+        frame_len: usize,
+        meter: &mut quality::Meter,
+        tracker: &mut jitter::Tracker,
+    ) {
+        for (pkt, missed) in frames {
+            play_in_order(held, pkt, missed, peer_fec, |what| {
+                match what {
+                    Decode::Conceal => meter.concealed(),
+                    Decode::Fec(_) => meter.fec_recovered(),
+                    Decode::Packet(_) => {}
+                }
+                if !self.play(what, frame_len) {
+                    tracker.early();
+                }
+            });
+        }
+    }
+}
+
+/// What a latency target leaves the jitter buffer, once the sender's framing
+/// and the network have had their share.
+fn buffer_cap(latency: &latency::Tracer, frame_ms: usize, rtt: Option<Duration>) -> Option<u32> {
+    let network = rtt.map_or(0, |rtt| rtt.as_millis() as u32 / 2);
+    let target = latency.target_ms()?;
+    Some(target.saturating_sub(frame_ms as u32 + network))
+}
+
+/// Samples per channel the playback ring may hold: the jitter buffer's
+/// maximum, or a frame past what a latency target leaves it, but always at
+/// least three of the peer's frames.
+fn backlog(max_ms: u32, cap: Option<u32>, frame_ms: usize) -> usize {
+    let ms = cap.map_or(max_ms, |cap| (cap + frame_ms as u32).min(max_ms));
+    frame_samples((ms as usize).max(3 * frame_ms))
+}
+
+/// What every lane's decode task shares.
+#[derive(Clone)]
+struct Decoding {
+    format: Arc<Format>,
+    mixer: Arc<mixer::Mixer>,
+    local_frame_ms: u8,
+    effects: Arc<effects::Controls>,
+    record: Option<record::Tap>,
+    captions: Option<captions::Tap>,
+    jitter: jitter::JitterOptions,
+    nack: Sender<Vec<u8>>,
+}
+
+impl Decoding {
+    /// A lane for one sender, and a decode task feeding it until the
+    /// returned channel closes.
+    fn open(&self) -> Sender<Inbound> {
+        let (tx, rx) = bounded::<Inbound>(1024);
+        // Sized for the deepest queue, widest layout and longest frames the
+        // sender can send.
+        let capacity = frame_samples((self.jitter.max_ms as usize).max(3 * MAX_FRAME_MS))
+            * codec::MAX_CHANNELS;
+        let target = frame_samples(self.jitter.target_ms as usize);
+        let (lane, producer) = self.mixer.add(capacity, target);
+        task::spawn(decode_task(self.clone(), lane, rx, producer));
+        tx
+    }
+}
+
+async fn decode_task<S>(
+    ctx: Decoding,
+    lane: Arc<mixer::Lane>,
+    inbound: Receiver<Inbound>,
+    producer: ringbuf::Producer<f32, S>,
+) -> Result<()>
+where
+    S: RbRef,
+    <S as RbRef>::Rb: RbWrite<f32>,
+{
+    let Decoding {
+        format,
+        mixer,
+        local_frame_ms,
+        effects,
+        record,
+        captions,
+        jitter,
+        nack,
+    } = ctx;
+    let mut playout = Playout {
+        dec: codec::Decoder::new(SAMPLE_RATE, &codec::StreamLayout::mono())?,
+        pcm_buf: vec![0f32; frame_samples(MAX_FRAME_MS) * codec::MAX_CHANNELS],
+        eq: effects::Equalizer::new(SAMPLE_RATE),
+        peer: None,
+        levels: HashMap::new(),
+        outputs: HashMap::new(),
+        listener: talk::Listener::default(),
+        talk: Arc::clone(&format.talk),
+        duck: effects::Ducker::new(SAMPLE_RATE),
+        effects,
+        record: record.map(|tap| tap.lane(lane.id)),
+        captions,
+        incoming: Arc::clone(&format.incoming),
+        producer,
+        backlog: backlog(jitter.max_ms, None, local_frame_ms as usize),
+        latency: Arc::clone(&format.latency),
+    };
+    let mut frame_ms = local_frame_ms as usize;
+    let mut peer_fec = false;
+    // With FEC: the newest frame, waiting for the next packet.
+    let mut held: Option<Vec<u8>> = None;
+    // With NACK: a gap we asked to have resent, and what arrived after it.
+    let mut repair: Option<nack::Repair> = None;
+    let mut last_rtt = None;
+    let mut peer_quiet = None;
+    let mut tracker = jitter::Tracker::new(jitter, local_frame_ms as u32);
+    let mut meter = quality::Meter::default();
+    let mut stats_logged = Instant::now();
+    let mut update_stats =
+        |tracker: &mut jitter::Tracker, meter: &mut quality::Meter, peer_fec: bool, cap| {
+            let underruns = lane.underruns.load(Ordering::Relaxed);
+            if let Some(target_ms) = tracker.tick(underruns, cap) {
+                let s = tracker.stats();
+                info!(
+                    "jitter target now {target_ms} ms (p95 jitter {:.1} ms, {} underruns)",
+                    s.jitter_p95_ms, s.underruns
+                );
+                lane.target
+                    .store(frame_samples(target_ms as usize), Ordering::Relaxed);
+            }
+            *lane.jitter_stats.lock() = tracker.stats().clone();
+            // The session reports the sender heard worst.
+            let worst = mixer.worst(&lane);
+            if worst {
+                *format.jitter_stats.lock() = tracker.stats().clone();
+            }
+            // Frame, playout target and, with FEC, the frame held back.
+            let frame_ms = format.frame_ms.load(Ordering::Relaxed) as f32;
+            let target_ms = tracker.stats().target_ms as f32;
+            let held_ms = if peer_fec { frame_ms } else { 0.0 };
+            if let Some(q) = meter.tick(tracker.stats(), frame_ms + target_ms + held_ms) {
+                *lane.quality_stats.lock() = q.clone();
+                if worst {
+                    *format.quality_stats.lock() = q.clone();
+                }
+                let now = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default();
+                let line = serde_json::json!({ "jitter": tracker.stats(), "quality": q });
+                crash::stats(format!("{} {line}", now.as_secs()));
+                if stats_logged.elapsed() >= STATS_LOG_INTERVAL {
+                    stats_logged = Instant::now();
+                    let s = tracker.stats();
+                    info!(
+                        received = s.received,
+                        lost = s.lost,
+                        late = s.late,
+                        underruns = s.underruns,
+                        jitter_p95_ms = s.jitter_p95_ms,
+                        target_ms = s.target_ms,
+                        rtt_ms = q.rtt_ms,
+                        mos = q.mos,
+                        "call stats"
+                    );
+                }
+            }
+        };
+    loop {
+        let msg = match (&repair, &held) {
+            (Some(r), _) => {
+                match tokio::time::timeout_at(r.deadline().into(), inbound.recv()).await {
+                    Ok(msg) => msg,
+                    // Whatever didn't come back in time is concealed.
+                    Err(_) => {
+                        let r = repair.take().unwrap();
+                        tracker.repaired(r.repaired() as u64);
+                        let frames = r.into_frames();
+                        playout.play_all(
+                            &mut held,
+                            frames,
+                            peer_fec,
+                            frame_samples(frame_ms),
+                            &mut meter,
+                            &mut tracker,
+                        );
+                        continue;
+                    }
+                }
+            }
+            // Don't wait for a successor that isn't coming (DTX, hang‑up).
+            (None, Some(pkt)) => {
+                let wait = Duration::from_millis(2 * frame_ms as u64);
+                match tokio::time::timeout(wait, inbound.recv()).await {
+                    Ok(msg) => msg,
+                    Err(_) => {
+                        if !playout.play(Decode::Packet(pkt), frame_samples(frame_ms)) {
+                            tracker.early();
+                        }
+                        held = None;
+                        continue;
+                    }
+                }
+            }
+            (None, None) => inbound.recv().await,
+        };
+        let Ok(msg) = msg else {
+            break;
+        };
+        let frames = match msg {
+            Inbound::Params(from, params) => {
+                playout.peer = Some(from);
+                *lane.peer.lock() = Some(from);
+                match codec::Decoder::new(SAMPLE_RATE, &params.layout) {
+                    Ok(d) => {
+                        playout.dec = d;
+                        lane.channels
+                            .store(playout.dec.channels(), Ordering::Relaxed);
+                    }
+                    Err(e) => error!("unusable stream layout from peer: {e}"),
+                }
+                lane.frame
+                    .store(frame_samples(params.frame_ms as usize), Ordering::Relaxed);
+                frame_ms = local_frame_ms.max(params.frame_ms) as usize;
+                format.frame_ms.store(frame_ms, Ordering::Relaxed);
+                tracker.set_frame_ms(frame_ms as u32);
+                tracker.new_sender();
+                let cap = buffer_cap(&format.latency, frame_ms, last_rtt);
+                playout.backlog = backlog(jitter.max_ms, cap, frame_ms);
+                peer_fec = params.fec;
+                info!(
+                    "peer sends {} channel(s){}; using {frame_ms} ms frames",
+                    params.layout.channels,
+                    if peer_fec { " with FEC" } else { "" }
+                );
+                continue;
+            }
+            Inbound::Silence(pos, reason) => {
+                let jitter::Arrival::InOrder { missed } = tracker.arrived(pos, false) else {
+                    continue;
+                };
+                if missed > 0 {
+                    format.loss.gap(&pos, missed);
+                }
+                if let Some(pkt) = held.take() {
+                    if !playout.play(Decode::Packet(&pkt), frame_samples(frame_ms)) {
+                        tracker.early();
+                    }
+                }
+                if peer_quiet != Some(reason) {
+                    match reason {
+                        SilenceReason::Muted => info!("STATUS: peer_muted"),
+                        SilenceReason::Silent => info!("STATUS: peer_silent"),
+                    }
+                    peer_quiet = Some(reason);
+                    lane.silent.store(true, Ordering::Relaxed);
+                }
+                continue;
+            }
+            Inbound::Probe(pos, rtt) => {
+                tracker.arrived(pos, false);
+                if let Some(rtt) = rtt {
+                    format.latency.record(latency::Stage::Network, rtt / 2);
+                    meter.rtt(rtt);
+                    last_rtt = Some(rtt);
+                }
+                let cap = buffer_cap(&format.latency, frame_ms, last_rtt);
+                playout.backlog = backlog(jitter.max_ms, cap, frame_ms);
+                update_stats(&mut tracker, &mut meter, peer_fec, cap);
+                // Other lanes keep theirs.
+                let mut levels = format.peer_levels.lock();
+                levels.retain(|l| !playout.levels.contains_key(&l.peer));
+                levels.extend(
+                    playout
+                        .levels
+                        .iter()
+                        .map(|(&peer, level)| level.level(peer)),
+                );
+                continue;
+            }
+            Inbound::Frame(pos, pkt) => {
+                format
+                    .latency
+                    .record(latency::Stage::Receive, pos.at.elapsed());
+                let arrival = tracker.arrived(pos, true);
+                if let jitter::Arrival::InOrder {
+                    missed: missed @ 1..,
+                } = arrival
+                {
+                    format.loss.gap(&pos, missed);
+                }
+                let cap = buffer_cap(&format.latency, frame_ms, last_rtt);
+                update_stats(&mut tracker, &mut meter, peer_fec, cap);
+                let pkt = match &mut repair {
+                    Some(r) if arrival != jitter::Arrival::Duplicate => match r.fill(pos, pkt) {
+                        Ok(()) if !r.complete() => continue,
+                        Ok(()) => None,
+                        Err(pkt) => Some(pkt),
+                    },
+                    _ => Some(pkt),
+                };
+                let arrived = match (pkt, arrival) {
+                    (Some(pkt), jitter::Arrival::InOrder { missed }) => Some((pkt, missed)),
+                    // Playing it now would only garble what came after it.
+                    (Some(_), _) => continue,
+                    (None, _) => None,
+                };
+                // Complete, or overtaken by a packet past it.
+                let mut frames = match repair.take() {
+                    Some(r) => {
+                        tracker.repaired(r.repaired() as u64);
+                        r.into_frames()
+                    }
+                    None => Vec::new(),
+                };
+                if let Some((pkt, missed)) = arrived {
+                    let wait = nack::wait(missed, last_rtt, frame_ms).filter(|_| jitter.nack);
+                    match wait {
+                        Some(wait) => {
+                            let r = nack::Repair::new(pos, missed, pkt, wait);
+                            let _ = nack.try_send(r.request());
+                            repair = Some(r);
+                        }
+                        None => frames.push((pkt, missed)),
+                    }
+                }
+                frames
+            }
+        };
+        if let Some(reason) = peer_quiet.take() {
+            if reason == SilenceReason::Muted {
+                info!("STATUS: peer_unmuted");
+            }
+            info!("STATUS: peer_speaking");
+            lane.silent.store(false, Ordering::Relaxed);
+        }
+
+        let frame_len = frame_samples(frame_ms);
+        playout.play_all(
+            &mut held,
+            frames,
+            peer_fec,
+            frame_len,
+            &mut meter,
+            &mut tracker,
+        );
+    }
+    mixer.remove(&lane);
+    Ok(())
+}
+
+/// Hands `queue` what to play for a packet that arrived in order after
+/// `missed` lost ones: the frame held back for FEC, then the gap (concealed,
+/// or recovered from this packet's FEC), then this one – unless it is held
+/// back in turn.
+fn play_in_order(
+    held: &mut Option<Vec<u8>>,
+    pkt: Vec<u8>,
+    missed: u32,
+    peer_fec: bool,
+    mut queue: impl FnMut(Decode<'_>),
+) {
+    if let Some(prev) = held.take() {
+        queue(Decode::Packet(&prev));
+    }
+    if missed <= MAX_CONCEALED_FRAMES {
+        // The last missing frame rides along in this packet's FEC.
+        let fec = peer_fec && missed > 0;
+        for _ in 0..missed - fec as u32 {
+            queue(Decode::Conceal);
+        }
+        if fec {
+            queue(Decode::Fec(&pkt));
+        }
+    }
+    if peer_fec {
+        *held = Some(pkt);
+    } else {
+        queue(Decode::Packet(&pkt));
+    }
+}
+
+/// Very quiet white noise (about −66 dBFS) for gaps while the peer is muted or
+/// silent, so the call doesn't sound dropped.
+struct ComfortNoise(u32);
+
+impl Default for ComfortNoise {
+    fn default() -> Self {
+        Self(0x9E37_79B9)
+    }
+}
+
+impl ComfortNoise {
+    fn next(&mut self) -> f32 {
+        // xorshift32
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 as f32 / u32::MAX as f32 * 2.0 - 1.0) * 0.0005
+    }
+}
+
+async fn get_public_address(sock: &UdpSocket) -> Result<SocketAddr> {
+    // Google’s anycast STUN
+    let address: SocketAddr = "74.125.194.127:19302".parse()?;
+    let client = StunClient::new(address);
+    let public = client
+        .query_external_address_async(sock)
+        .await
+        .context("STUN failed")?;
+    Ok(public)
+}
+
+/// A whole callback's worth of `sample_to_f32`, vectorised for the integer
+/// formats (see `simd`).
+fn samples_to_f32<T: Sample + 'static>(data: &[T], out: &mut Vec<f32>) {
+    out.resize(data.len(), 0.0);
+    // SAFETY: `T` is checked to be the type each slice is read as.
+    if TypeId::of::<T>() == TypeId::of::<i16>() {
+        let data = unsafe { std::slice::from_raw_parts(data.as_ptr().cast(), data.len()) };
+        simd::i16_to_f32(data, out);
+    } else if TypeId::of::<T>() == TypeId::of::<i32>() {
+        let data = unsafe { std::slice::from_raw_parts(data.as_ptr().cast(), data.len()) };
+        simd::i32_to_f32(data, out);
+    } else {
+        for (o, &s) in out.iter_mut().zip(data) {
+            *o = sample_to_f32(s);
+        }
+    }
+}
+
+fn sample_to_f32<T: Sample + 'static>(s: T) -> f32 {
+    if TypeId::of::<T>() == TypeId::of::<i16>() {
+        let s: i16 = unsafe { std::mem::transmute_copy(&s) };
+        s as f32 / i16::MAX as f32
+    } else if TypeId::of::<T>() == TypeId::of::<u16>() {
+        let s: u16 = unsafe { std::mem::transmute_copy(&s) };
+        s as f32 / u16::MAX as f32 * 2.0 - 1.0
+    } else if TypeId::of::<T>() == TypeId::of::<i32>() {
+        let s: i32 = unsafe { std::mem::transmute_copy(&s) };
+        s as f32 / i32::MAX as f32
+    } else if TypeId::of::<T>() == TypeId::of::<f32>() {
+        let s: f32 = unsafe { std::mem::transmute_copy(&s) };
+        s
+    } else {
+        panic!("Unsupported sample type");
+    }
+}
@@ -0,0 +1,248 @@
+// ─── Logging ───────────────────────────────────────────────────────────────────
+// `Json` formats each event as one JSON object per line for log pipelines:
+//
+//   {"fields":{"peer":"203.0.113.7:40000"},"level":"INFO","message":"STATUS: keyed …",
+//    "spans":["call.connect"],"status":"keyed","target":"audio","timestamp":"…"}
+//
+// Typed fields (peer addresses, epoch/seq, stats) are kept as JSON values, and
+// `STATUS:` lines get their event name in `status` so they can be filtered
+// without parsing the message.
+//
+// Both the text and the JSON log go through `RollingFile` (see Rotation).
+
+use serde_json::{Map, Number, Value};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::FormatTime;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::registry::LookupSpan;
+
+/// JSON‑lines event format for `tracing_subscriber::fmt::layer().event_format`.
+pub struct Json<T> {
+    timer: T,
+}
+
+impl<T: FormatTime> Json<T> {
+    pub fn new(timer: T) -> Self {
+        Self { timer }
+    }
+}
+
+struct Fields<'a>(&'a mut Map<String, Value>);
+
+impl Fields<'_> {
+    fn put(&mut self, field: &Field, value: Value) {
+        self.0.insert(field.name().to_owned(), value);
+    }
+}
+
+impl Visit for Fields<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        // NaN and infinities have no JSON number.
+        let value = Number::from_f64(value).map_or(Value::Null, Value::Number);
+        self.put(field, value);
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.put(field, value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.put(field, value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.put(field, value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.put(field, value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.put(field, format!("{value:?}").into());
+    }
+}
+
+impl<S, N, T> FormatEvent<S, N> for Json<T>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+    T: FormatTime,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let mut timestamp = String::new();
+        self.timer.format_time(&mut Writer::new(&mut timestamp))?;
+        let mut fields = Map::new();
+        event.record(&mut Fields(&mut fields));
+        let meta = event.metadata();
+
+        let mut line = Map::new();
+        line.insert("timestamp".into(), timestamp.into());
+        line.insert("level".into(), meta.level().as_str().into());
+        line.insert("target".into(), meta.target().into());
+        if let Some(message) = fields.remove("message") {
+            let status = message
+                .as_str()
+                .and_then(|m| m.strip_prefix("STATUS: "))
+                .and_then(|m| m.split_whitespace().next())
+                .map(Value::from);
+            line.insert("message".into(), message);
+            if let Some(status) = status {
+                line.insert("status".into(), status);
+            }
+        }
+        if !fields.is_empty() {
+            line.insert("fields".into(), fields.into());
+        }
+        if let Some(scope) = ctx.event_scope() {
+            let spans: Vec<Value> = scope.from_root().map(|s| s.name().into()).collect();
+            line.insert("spans".into(), spans.into());
+        }
+        writeln!(writer, "{}", Value::Object(line))
+    }
+}
+
+// ─── Rotation ──────────────────────────────────────────────────────────────────
+// `RollingFile` caps a log by size rather than by day: once `<name>` would
+// grow past `max_bytes` it becomes `<name>.1` (gzipped to `<name>.1.gz` with
+// `compress`), older files move up one, and anything past `keep` is deleted.
+// Rotation runs on whichever thread writes – the `tracing_appender`
+// non‑blocking worker in the CLI – so compressing never stalls the call.
+
+#[derive(Clone, Debug)]
+pub struct RotationOptions {
+    /// Size at which the current file is rotated.
+    pub max_bytes: u64,
+    /// Rotated files kept besides the current one.
+    pub keep: usize,
+    /// Gzip rotated files.
+    pub compress: bool,
+}
+
+impl Default for RotationOptions {
+    fn default() -> Self {
+        Self {
+            max_bytes: 10 * 1024 * 1024,
+            keep: 5,
+            compress: true,
+        }
+    }
+}
+
+pub struct RollingFile {
+    path: PathBuf,
+    opts: RotationOptions,
+    file: File,
+    written: u64,
+    /// The last byte written ended a line.
+    line_end: bool,
+}
+
+impl RollingFile {
+    /// Appends to `dir/name`, creating the directory if needed.
+    pub fn open(dir: impl AsRef<Path>, name: &str, opts: RotationOptions) -> io::Result<Self> {
+        std::fs::create_dir_all(dir.as_ref())?;
+        let path = dir.as_ref().join(name);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path,
+            opts,
+            file,
+            written,
+            line_end: true,
+        })
+    }
+
+    /// `<name>.<n>`, plus `.gz` when compressed.
+    fn rotated(&self, n: usize, gz: bool) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{n}"));
+        if gz {
+            name.push(".gz");
+        }
+        name.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        for gz in [false, true] {
+            let _ = std::fs::remove_file(self.rotated(self.opts.keep, gz));
+            for n in (1..self.opts.keep).rev() {
+                let _ = std::fs::rename(self.rotated(n, gz), self.rotated(n + 1, gz));
+            }
+        }
+        match self.opts.keep {
+            0 => std::fs::remove_file(&self.path)?,
+            _ => {
+                let first = self.rotated(1, false);
+                std::fs::rename(&self.path, &first)?;
+                if self.opts.compress {
+                    let data = std::fs::read(&first)?;
+                    std::fs::write(self.rotated(1, true), gzip(&data))?;
+                    std::fs::remove_file(&first)?;
+                }
+            }
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for RollingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Only between lines, so no event is split across files.
+        if self.line_end
+            && self.written > 0
+            && self.written + buf.len() as u64 > self.opts.max_bytes
+        {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        if n > 0 {
+            self.line_end = buf[n - 1] == b'\n';
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// A single‑member gzip file (RFC 1952).
+fn gzip(data: &[u8]) -> Vec<u8> {
+    // No mtime, default compression, unknown OS.
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+    out.extend_from_slice(&miniz_oxide::deflate::compress_to_vec(data, 6));
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
@@ -0,0 +1,173 @@
+// ─── Loss pattern ──────────────────────────────────────────────────────────────
+// Where in the call packets went missing, and how many at a time. Every gap
+// in the sender's sequence is logged with when it showed up and how many
+// packets it swallowed, so a dropout someone heard can be lined up with what
+// the network was doing then. At the end of the call `LossStats::summary`
+// gives a histogram of burst lengths, the worst seconds, and a heatmap of the
+// whole call; `write_csv` (`--loss-csv`) dumps the events themselves.
+//
+// Gaps are logged as first seen: a packet that turns up afterwards, late or
+// resent on request (see `nack`), doesn't take its gap back.
+
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::path::Path;
+use std::time::Instant;
+
+/// Enough for hours of a bad link; later gaps are only counted.
+const MAX_GAPS: usize = 100_000;
+/// Upper bounds of the burst‑length histogram's bins.
+const BURSTS: [u32; 6] = [1, 2, 3, 5, 10, u32::MAX];
+/// Seconds listed as the worst.
+const WORST: usize = 5;
+/// Cells across the heatmap, at most.
+const HEAT_CELLS: u64 = 60;
+const HEAT: [char; 5] = ['·', '░', '▒', '▓', '█'];
+
+/// One run of missing packets.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Gap {
+    /// Since the call started, when the packet after the gap arrived.
+    pub at_ms: u64,
+    pub epoch: u8,
+    /// The first sequence number missing.
+    pub seq: u32,
+    pub missed: u32,
+}
+
+/// Every gap since the call started.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct LossStats {
+    pub call_secs: f32,
+    pub gaps: Vec<Gap>,
+    /// Gaps past `MAX_GAPS`, and the packets they missed.
+    pub unlogged_gaps: u64,
+    pub unlogged_missed: u64,
+}
+
+impl LossStats {
+    pub fn missed(&self) -> u64 {
+        self.gaps.iter().map(|g| g.missed as u64).sum::<u64>() + self.unlogged_missed
+    }
+
+    /// The end‑of‑call report, a line at a time.
+    pub fn summary(&self) -> Vec<String> {
+        let count = self.gaps.len() as u64 + self.unlogged_gaps;
+        if count == 0 {
+            return vec!["no packets lost".into()];
+        }
+        let mut lines = vec![format!("{} packets lost in {count} gaps", self.missed())];
+
+        let mut bins = [0u64; BURSTS.len()];
+        for gap in &self.gaps {
+            let bin = BURSTS.iter().position(|&max| gap.missed <= max);
+            bins[bin.unwrap_or(BURSTS.len() - 1)] += 1;
+        }
+        let mut histogram = Vec::new();
+        let mut low = 1;
+        for (&max, &n) in BURSTS.iter().zip(&bins) {
+            let label = match max {
+                u32::MAX => format!(">{}", low - 1),
+                max if max == low => format!("{max}"),
+                max => format!("{low}–{max}"),
+            };
+            histogram.push(format!("{label}: {n}"));
+            low = max.saturating_add(1);
+        }
+        lines.push(format!("burst lengths: {}", histogram.join(", ")));
+
+        let mut seconds = std::collections::BTreeMap::<u64, u64>::new();
+        for gap in &self.gaps {
+            *seconds.entry(gap.at_ms / 1000).or_default() += gap.missed as u64;
+        }
+        let mut worst: Vec<_> = seconds.into_iter().collect();
+        worst.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let worst: Vec<String> = worst
+            .iter()
+            .take(WORST)
+            .map(|&(sec, n)| format!("{} ({n})", crate::talk::clock(sec as f32)))
+            .collect();
+        lines.push(format!("worst seconds: {}", worst.join(", ")));
+
+        let (cell_secs, heat) = self.heatmap();
+        lines.push(format!("heatmap, {cell_secs} s a cell: {heat}"));
+        if self.unlogged_gaps > 0 {
+            lines.push(format!(
+                "{} later gaps ({} packets) weren't logged",
+                self.unlogged_gaps, self.unlogged_missed
+            ));
+        }
+        lines
+    }
+
+    /// The call as a row of cells, each shaded by the packets lost in it
+    /// relative to the worst one; and how many seconds each cell covers.
+    fn heatmap(&self) -> (u64, String) {
+        let call_secs = (self.call_secs.ceil() as u64).max(1);
+        let cell_secs = call_secs.div_ceil(HEAT_CELLS);
+        let mut cells = vec![0u64; call_secs.div_ceil(cell_secs) as usize];
+        for gap in &self.gaps {
+            let cell = (gap.at_ms / 1000 / cell_secs) as usize;
+            if let Some(c) = cells.get_mut(cell) {
+                *c += gap.missed as u64;
+            }
+        }
+        let most = cells.iter().copied().max().unwrap_or(0).max(1);
+        let shades = HEAT.len() as u64 - 1;
+        let heat = cells
+            .iter()
+            .map(|&n| HEAT[(n * shades).div_ceil(most) as usize])
+            .collect();
+        (cell_secs, heat)
+    }
+
+    /// One row per gap: `at_ms,epoch,seq,missed`.
+    pub fn write_csv(&self, path: &Path) -> Result<()> {
+        let mut csv = String::from("at_ms,epoch,seq,missed\n");
+        for g in &self.gaps {
+            let _ = writeln!(csv, "{},{},{},{}", g.at_ms, g.epoch, g.seq, g.missed);
+        }
+        std::fs::write(path, csv).with_context(|| format!("can't write {}", path.display()))
+    }
+}
+
+pub(crate) struct Log {
+    started: Instant,
+    stats: Mutex<LossStats>,
+}
+
+impl Default for Log {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            stats: Mutex::default(),
+        }
+    }
+}
+
+impl Log {
+    /// `missed` packets went missing right before `pos`.
+    pub fn gap(&self, pos: &crate::jitter::Position, missed: u32) {
+        let mut stats = self.stats.lock();
+        if stats.gaps.len() == MAX_GAPS {
+            stats.unlogged_gaps += 1;
+            stats.unlogged_missed += missed as u64;
+            return;
+        }
+        stats.gaps.push(Gap {
+            at_ms: pos.at.saturating_duration_since(self.started).as_millis() as u64,
+            epoch: pos.epoch,
+            seq: pos.seq.wrapping_sub(missed),
+            missed,
+        });
+    }
+
+    pub fn stats(&self) -> LossStats {
+        LossStats {
+            call_secs: self.started.elapsed().as_secs_f32(),
+            ..self.stats.lock().clone()
+        }
+    }
+}
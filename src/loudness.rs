@@ -0,0 +1,251 @@
+// ─── Loudness ──────────────────────────────────────────────────────────────────
+// EBU R128 / ITU‑R BS.1770 loudness, metered on what we send and on the mix we
+// play: K‑weighting (a high shelf for the head, a high‑pass below 60 Hz), mean
+// square over 400 ms blocks every 100 ms, and from those the momentary (400 ms)
+// and short‑term (3 s) loudness and the integrated loudness of the whole call,
+// gated at −70 LUFS and then 10 LU under the ungated mean. Everything runs at
+// 48 kHz; channels are summed unweighted, which is exact for mono and stereo.
+//
+// With `SendLoudness` the outgoing voice is also brought to a LUFS target, so
+// every participant arrives at about the same level however close they sit to
+// their mic. The gain follows the short‑term loudness, only while it is over
+// `SPEECH_LUFS` so pauses don't pump it up, and glides over `GLIDE_MS`.
+
+use crate::SAMPLE_RATE;
+use serde::Serialize;
+use std::collections::VecDeque;
+
+/// 100 ms, in samples per channel.
+const HOP: usize = SAMPLE_RATE as usize / 10;
+const MOMENTARY_HOPS: usize = 4;
+const SHORT_TERM_HOPS: usize = 30;
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+/// Integrated loudness keeps gating blocks as a histogram of this many LU a
+/// bin, from the absolute gate up to +5 LUFS: hours of call in a few KiB.
+const BIN_LU: f64 = 0.1;
+const BINS: usize = 750;
+/// Short‑term loudness above which the normalizer trusts it.
+const SPEECH_LUFS: f64 = -45.0;
+const GLIDE_MS: f32 = 500.0;
+
+/// Loudness so far, in LUFS; `None` until there is a block over the gate.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct LoudnessStats {
+    pub momentary: Option<f32>,
+    pub short_term: Option<f32>,
+    pub integrated: Option<f32>,
+}
+
+/// What `VoiceSession::loudness` reports.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct Loudness {
+    /// What we send, before it is levelled.
+    pub capture: LoudnessStats,
+    /// The gain `SendLoudness` applies now.
+    pub send_gain_db: f32,
+    /// What we play, folded to stereo.
+    pub mix: LoudnessStats,
+}
+
+/// Bring what we send to `target_lufs`, by at most `max_gain_db` either way.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SendLoudness {
+    pub target_lufs: f32,
+    pub max_gain_db: f32,
+}
+
+impl Default for SendLoudness {
+    fn default() -> Self {
+        Self {
+            target_lufs: -18.0,
+            max_gain_db: 12.0,
+        }
+    }
+}
+
+/// Direct form I, in `f64`: the high‑pass pole sits too close to 1 for `f32`.
+#[derive(Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    const fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Self {
+            b,
+            a,
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+/// BS.1770's two stages, with its coefficients for 48 kHz.
+const K_WEIGHTING: [Biquad; 2] = [
+    Biquad::new(
+        [1.53512485958697, -2.69169618940638, 1.19839281085285],
+        [-1.69065929318241, 0.73248077421585],
+    ),
+    Biquad::new([1.0, -2.0, 1.0], [-1.99004745483398, 0.99007225036621]),
+];
+
+fn lufs(mean_square: f64) -> f64 {
+    -0.691 + 10.0 * mean_square.log10()
+}
+
+pub(crate) struct Meter {
+    filters: Vec<[Biquad; 2]>,
+    /// Sum of squares in the current hop, and frames in it.
+    sum: f64,
+    frames: usize,
+    /// Mean square of each of the last `SHORT_TERM_HOPS` hops, newest last.
+    hops: VecDeque<f64>,
+    /// Gating blocks over the absolute gate, by loudness.
+    histogram: Vec<u64>,
+    stats: LoudnessStats,
+}
+
+impl Meter {
+    pub fn new(channels: usize) -> Self {
+        Self {
+            filters: vec![K_WEIGHTING; channels],
+            sum: 0.0,
+            frames: 0,
+            hops: VecDeque::with_capacity(SHORT_TERM_HOPS),
+            histogram: vec![0; BINS],
+            stats: LoudnessStats::default(),
+        }
+    }
+
+    /// Feeds interleaved 48 kHz audio; returns whether a hop ended, and so
+    /// the stats moved.
+    pub fn process(&mut self, pcm: &[f32]) -> bool {
+        let channels = self.filters.len();
+        let mut hopped = false;
+        for frame in pcm.chunks_exact(channels) {
+            for (s, [shelf, high_pass]) in frame.iter().zip(&mut self.filters) {
+                let k = high_pass.process(shelf.process(*s as f64));
+                self.sum += k * k;
+            }
+            self.frames += 1;
+            if self.frames == HOP {
+                self.hop();
+                hopped = true;
+            }
+        }
+        hopped
+    }
+
+    fn hop(&mut self) {
+        if self.hops.len() == SHORT_TERM_HOPS {
+            self.hops.pop_front();
+        }
+        self.hops.push_back(self.sum / HOP as f64);
+        self.sum = 0.0;
+        self.frames = 0;
+        // Digital silence has no loudness at all.
+        let window = |hops: usize| {
+            let sum: f64 = self.hops.iter().rev().take(hops).sum();
+            let l = lufs(sum / hops as f64);
+            (self.hops.len() >= hops && l.is_finite()).then_some(l)
+        };
+        let momentary = window(MOMENTARY_HOPS);
+        if let Some(l) = momentary.filter(|&l| l > ABSOLUTE_GATE) {
+            let bin = ((l - ABSOLUTE_GATE) / BIN_LU) as usize;
+            self.histogram[bin.min(BINS - 1)] += 1;
+        }
+        self.stats = LoudnessStats {
+            momentary: momentary.map(|l| l as f32),
+            short_term: window(SHORT_TERM_HOPS).map(|l| l as f32),
+            integrated: self.integrated().map(|l| l as f32),
+        };
+    }
+
+    /// The two‑pass gate over the histogram, each bin taken at its middle.
+    fn integrated(&self) -> Option<f64> {
+        let bin_lufs = |i: usize| ABSOLUTE_GATE + (i as f64 + 0.5) * BIN_LU;
+        let mean = |from: usize| {
+            let (mut n, mut energy) = (0u64, 0.0);
+            for (i, &count) in self.histogram.iter().enumerate().skip(from) {
+                n += count;
+                energy += count as f64 * 10f64.powf((bin_lufs(i) + 0.691) / 10.0);
+            }
+            (n > 0).then(|| lufs(energy / n as f64))
+        };
+        let gate = mean(0)? + RELATIVE_GATE;
+        let from = ((gate - ABSOLUTE_GATE) / BIN_LU).max(0.0) as usize;
+        mean(from)
+    }
+
+    pub fn stats(&self) -> LoudnessStats {
+        self.stats
+    }
+}
+
+/// Meters what we send and, given a target, levels it there.
+pub(crate) struct Normalizer {
+    meter: Meter,
+    coef: f32,
+    /// Applied now, and aimed for.
+    gain_db: f32,
+    target_db: f32,
+}
+
+impl Normalizer {
+    pub fn new(channels: usize) -> Self {
+        Self {
+            meter: Meter::new(channels),
+            coef: (-1000.0 / (GLIDE_MS * SAMPLE_RATE as f32)).exp(),
+            gain_db: 0.0,
+            target_db: 0.0,
+        }
+    }
+
+    /// Meters `pcm` (interleaved, 48 kHz) as captured, then levels it.
+    pub fn process(&mut self, pcm: &mut [f32], channels: usize, target: Option<SendLoudness>) {
+        if self.meter.process(pcm) {
+            let short_term = self.meter.stats.short_term;
+            self.target_db = match (target, short_term) {
+                (None, _) => 0.0,
+                (Some(t), Some(l)) if l as f64 > SPEECH_LUFS => {
+                    let max = t.max_gain_db.max(0.0);
+                    (t.target_lufs - l).clamp(-max, max)
+                }
+                // Quiet: hold the gain where it is.
+                (Some(_), _) => self.target_db,
+            };
+        }
+        if self.target_db == 0.0 && self.gain_db.abs() < 0.01 {
+            self.gain_db = 0.0;
+            return;
+        }
+        for frame in pcm.chunks_mut(channels) {
+            self.gain_db = self.target_db + (self.gain_db - self.target_db) * self.coef;
+            let gain = 10f32.powf(self.gain_db / 20.0);
+            for s in frame {
+                *s = (*s * gain).clamp(-1.0, 1.0);
+            }
+        }
+    }
+
+    pub fn stats(&self) -> LoudnessStats {
+        self.meter.stats()
+    }
+
+    pub fn gain_db(&self) -> f32 {
+        self.gain_db
+    }
+}
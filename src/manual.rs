@@ -0,0 +1,211 @@
+// ─── Manual offer/answer ───────────────────────────────────────────────────────
+// Rendezvous with no server at all: the caller prints an offer – our
+// reflexive, LAN and TURN addresses and handshake key, packed and
+// base64'd – and sends it to the other side over anything (email, chat).
+// The other side starts with that offer and prints an answer of the same
+// shape; once the caller pastes the answer, both call each other as with
+// `--peer`.
+//
+// The blobs carry the keys each side expects in the other's Hello, so as
+// long as the channel they went through wasn't tampered with, the call
+// can't be intercepted. The SAS is still shown.
+//
+// `exchange` splits the job: the network task fills in our half (it owns the
+// socket and the key) and waits for the peer's, which the host supplies.
+//
+// Either side can use SDP instead (see `sdp`), for tools that speak it; a
+// pasted description is recognised by its `v=0` line.
+
+use crate::codec::StreamParams;
+use crate::crypto::{self, PUBLIC_KEY_LEN};
+use crate::sdp;
+use crate::signaling::{JoinPayload, PeerInfo};
+use anyhow::{anyhow, bail, Context, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
+use std::net::{IpAddr, SocketAddr};
+use tokio::sync::oneshot;
+
+const VERSION: u8 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    /// Goes first; pastes the answer.
+    Offer,
+    /// Starts from the offer.
+    Answer,
+}
+
+/// How we show our half; the peer's is taken in either form.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// The compact base64 blob.
+    #[default]
+    Blob,
+    /// An SDP description.
+    Sdp,
+}
+
+/// The network task's end.
+pub struct Exchange {
+    role: Role,
+    encoding: Encoding,
+    ours: oneshot::Sender<String>,
+    theirs: oneshot::Receiver<PeerInfo>,
+}
+
+/// The host's end: shows our blob and takes the peer's.
+pub struct Host {
+    role: Role,
+    ours: oneshot::Receiver<String>,
+    theirs: Option<oneshot::Sender<PeerInfo>>,
+}
+
+pub fn exchange(role: Role, encoding: Encoding) -> (Exchange, Host) {
+    let (ours_tx, ours_rx) = oneshot::channel();
+    let (theirs_tx, theirs_rx) = oneshot::channel();
+    let exchange = Exchange {
+        role,
+        encoding,
+        ours: ours_tx,
+        theirs: theirs_rx,
+    };
+    let host = Host {
+        role,
+        ours: ours_rx,
+        theirs: Some(theirs_tx),
+    };
+    (exchange, host)
+}
+
+impl Exchange {
+    /// Hands our half to the host and waits for the peer's.
+    pub(crate) async fn run(self, me: &JoinPayload, params: &StreamParams) -> Result<PeerInfo> {
+        let blob = match self.encoding {
+            Encoding::Blob => encode(self.role, me)?,
+            Encoding::Sdp => sdp::describe(self.role, me, params)?.to_string(),
+        };
+        let _ = self.ours.send(blob);
+        self.theirs
+            .await
+            .map_err(|_| anyhow!("the offer/answer exchange was abandoned"))
+    }
+}
+
+impl Host {
+    /// Our offer or answer, once the session has its addresses (STUN takes a
+    /// moment).
+    pub async fn ours(&mut self) -> Result<String> {
+        (&mut self.ours)
+            .await
+            .context("the session ended before it had an address")
+    }
+
+    /// Takes the peer's offer or answer. Errors leave the exchange open, so
+    /// the user can paste again.
+    pub fn accept(&mut self, blob: &str) -> Result<()> {
+        let (role, peer) = match blob.trim_start().starts_with("v=") {
+            true => sdp::peer_info(&blob.parse()?)?,
+            false => decode(blob)?,
+        };
+        match (self.role, role) {
+            (Role::Offer, Role::Offer) => bail!("that's an offer; paste the other side's answer"),
+            (Role::Answer, Role::Answer) => bail!("that's an answer; start from an offer"),
+            _ => {}
+        }
+        let theirs = self.theirs.take().context("already have the peer's blob")?;
+        let _ = theirs.send(peer);
+        Ok(())
+    }
+}
+
+fn encode(role: Role, me: &JoinPayload) -> Result<String> {
+    let key = crypto::key_from_hex(&me.pub_key).context("no handshake key to offer")?;
+    let reflexive: SocketAddr = me.reflexive_addr.parse()?;
+    let mut out = vec![VERSION, role as u8];
+    out.extend_from_slice(&key);
+    put_addr(&mut out, Some(reflexive));
+    put_addr(&mut out, me.lan_addr.parse().ok());
+    put_addr(&mut out, me.relayed_addr.parse().ok());
+    Ok(URL_SAFE_NO_PAD.encode(out))
+}
+
+fn decode(blob: &str) -> Result<(Role, PeerInfo)> {
+    let bytes = URL_SAFE_NO_PAD
+        .decode(blob.trim())
+        .context("not an offer or answer (bad base64)")?;
+    let mut rest = bytes.as_slice();
+    let [version, role, tail @ ..] = rest else {
+        bail!("offer/answer too short");
+    };
+    if *version != VERSION {
+        bail!("offer/answer from an incompatible version ({version})");
+    }
+    let role = match role {
+        0 => Role::Offer,
+        1 => Role::Answer,
+        _ => bail!("not an offer or answer"),
+    };
+    rest = tail;
+    let key: [u8; PUBLIC_KEY_LEN] = rest
+        .get(..PUBLIC_KEY_LEN)
+        .and_then(|k| k.try_into().ok())
+        .context("offer/answer too short")?;
+    rest = &rest[PUBLIC_KEY_LEN..];
+    let reflexive = take_addr(&mut rest)?.context("offer/answer has no address")?;
+    let lan = take_addr(&mut rest)?;
+    // Blobs from before TURN end here.
+    let relayed = match rest.is_empty() {
+        true => None,
+        false => take_addr(&mut rest)?,
+    };
+    Ok((
+        role,
+        PeerInfo {
+            reflexive_addr: reflexive.to_string(),
+            lan_addr: lan.map(|a| a.to_string()).unwrap_or_default(),
+            pub_key: crypto::key_to_hex(&key),
+            relayed_addr: relayed.map(|a| a.to_string()).unwrap_or_default(),
+            room_proof: None,
+            webrtc: false,
+        },
+    ))
+}
+
+/// Family (0 none, 4, 6), address, port.
+fn put_addr(out: &mut Vec<u8>, addr: Option<SocketAddr>) {
+    match addr.map(|a| (a.ip(), a.port())) {
+        None => out.push(0),
+        Some((IpAddr::V4(ip), port)) => {
+            out.push(4);
+            out.extend_from_slice(&ip.octets());
+            out.extend_from_slice(&port.to_be_bytes());
+        }
+        Some((IpAddr::V6(ip), port)) => {
+            out.push(6);
+            out.extend_from_slice(&ip.octets());
+            out.extend_from_slice(&port.to_be_bytes());
+        }
+    }
+}
+
+fn take_addr(rest: &mut &[u8]) -> Result<Option<SocketAddr>> {
+    let (&family, tail) = rest.split_first().context("offer/answer too short")?;
+    let len = match family {
+        0 => {
+            *rest = tail;
+            return Ok(None);
+        }
+        4 => 4,
+        6 => 16,
+        _ => bail!("bad address in offer/answer"),
+    };
+    let b = tail.get(..len + 2).context("offer/answer too short")?;
+    let ip = match len {
+        4 => IpAddr::from(<[u8; 4]>::try_from(&b[..4])?),
+        _ => IpAddr::from(<[u8; 16]>::try_from(&b[..16])?),
+    };
+    let port = u16::from_be_bytes([b[len], b[len + 1]]);
+    *rest = &tail[len + 2..];
+    Ok(Some(SocketAddr::new(ip, port)))
+}
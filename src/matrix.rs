@@ -0,0 +1,178 @@
+// ─── Matrix client ─────────────────────────────────────────────────────────────
+// The few client‑server API calls signaling over a Matrix room needs (see
+// `signaling`): join, set a state event, long‑poll `/sync` for one state
+// event type, invite a user and post a message. Authenticated with an
+// access token; errors carry the homeserver's `errcode`.
+
+use anyhow::{bail, Context, Result};
+use reqwest::{Method, StatusCode, Url};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// On top of the long‑poll timeout, for the homeserver to answer.
+const REQUEST_SLACK: Duration = Duration::from_secs(15);
+
+pub(crate) struct Client {
+    http: reqwest::Client,
+    base: Url,
+    token: String,
+    /// Transaction ids for messages, unique per access token and session.
+    txn: AtomicU64,
+}
+
+/// A state event as `/sync` delivered it.
+pub(crate) struct StateEvent {
+    pub state_key: String,
+    pub sender: String,
+    pub content: Value,
+}
+
+impl Client {
+    pub fn new(http: reqwest::Client, base: Url, token: String) -> Self {
+        Self {
+            http,
+            base,
+            token,
+            txn: AtomicU64::new(rand::random::<u32>() as u64),
+        }
+    }
+
+    /// Joins a room by id or alias (a no‑op if we're in it) and returns its id.
+    pub async fn join(&self, room: &str) -> Result<String> {
+        let resp: Value = self
+            .call(Method::POST, &["join", room], Some(&json!({})))
+            .await?;
+        resp["room_id"]
+            .as_str()
+            .map(String::from)
+            .context("homeserver returned no room id")
+    }
+
+    pub async fn put_state(
+        &self,
+        room_id: &str,
+        event_type: &str,
+        state_key: &str,
+        content: &impl Serialize,
+    ) -> Result<()> {
+        let path = ["rooms", room_id, "state", event_type, state_key];
+        self.call(Method::PUT, &path, Some(content)).await?;
+        Ok(())
+    }
+
+    pub async fn invite(&self, room_id: &str, user_id: &str) -> Result<()> {
+        let body = json!({ "user_id": user_id });
+        self.call(Method::POST, &["rooms", room_id, "invite"], Some(&body))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn send_message(&self, room_id: &str, content: &Value) -> Result<()> {
+        let txn = self.txn.fetch_add(1, Ordering::Relaxed).to_string();
+        let path = ["rooms", room_id, "send", "m.room.message", &txn];
+        self.call(Method::PUT, &path, Some(content)).await?;
+        Ok(())
+    }
+
+    /// The `event_type` state events of `room_id` since `since` (all of them
+    /// on the first call), waiting up to `timeout` for one. Returns them in
+    /// order, with the token for the next call.
+    pub async fn sync(
+        &self,
+        room_id: &str,
+        event_type: &str,
+        since: Option<&str>,
+        timeout: Duration,
+    ) -> Result<(String, Vec<StateEvent>)> {
+        let only = json!({ "types": [event_type] });
+        let none = json!({ "not_types": ["*"] });
+        let filter = json!({
+            "presence": none,
+            "account_data": none,
+            "room": {
+                "rooms": [room_id],
+                "state": only,
+                "timeline": { "types": [event_type], "limit": 100 },
+                "ephemeral": none,
+                "account_data": none,
+            },
+        });
+        let mut url = self.url(&["sync"])?;
+        url.query_pairs_mut()
+            .append_pair("filter", &filter.to_string())
+            .append_pair("timeout", &timeout.as_millis().to_string());
+        if let Some(since) = since {
+            url.query_pairs_mut().append_pair("since", since);
+        }
+        let resp = self
+            .http
+            .get(url)
+            .bearer_auth(&self.token)
+            .timeout(timeout + REQUEST_SLACK)
+            .send()
+            .await?;
+        let resp = check(resp).await?;
+        let next = resp["next_batch"]
+            .as_str()
+            .context("sync response without next_batch")?
+            .to_owned();
+        let room = &resp["rooms"]["join"][room_id];
+        let events = [&room["state"]["events"], &room["timeline"]["events"]]
+            .into_iter()
+            .filter_map(Value::as_array)
+            .flatten()
+            .filter(|e| e["type"] == event_type)
+            .filter_map(|e| {
+                Some(StateEvent {
+                    state_key: e["state_key"].as_str()?.to_owned(),
+                    sender: e["sender"].as_str().unwrap_or_default().to_owned(),
+                    content: e["content"].clone(),
+                })
+            })
+            .collect();
+        Ok((next, events))
+    }
+
+    async fn call(
+        &self,
+        method: Method,
+        path: &[&str],
+        body: Option<&impl Serialize>,
+    ) -> Result<Value> {
+        let mut req = self
+            .http
+            .request(method, self.url(path)?)
+            .bearer_auth(&self.token)
+            .timeout(REQUEST_SLACK);
+        if let Some(body) = body {
+            req = req.json(body);
+        }
+        check(req.send().await?).await
+    }
+
+    fn url(&self, path: &[&str]) -> Result<Url> {
+        let mut url = self.base.clone();
+        url.path_segments_mut()
+            .map_err(|()| anyhow::anyhow!("invalid homeserver URL"))?
+            .pop_if_empty()
+            .extend(["_matrix", "client", "v3"])
+            .extend(path);
+        Ok(url)
+    }
+}
+
+async fn check(resp: reqwest::Response) -> Result<Value> {
+    let status = resp.status();
+    let body: Value = resp.json().await.unwrap_or(Value::Null);
+    if status.is_success() {
+        return Ok(body);
+    }
+    let errcode = body["errcode"].as_str().unwrap_or("no errcode");
+    let error = body["error"].as_str().unwrap_or_default();
+    match status {
+        StatusCode::UNAUTHORIZED => bail!("homeserver rejected the access token ({errcode})"),
+        _ => bail!("homeserver answered {status}: {errcode} {error}"),
+    }
+}
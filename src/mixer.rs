@@ -0,0 +1,394 @@
+// ─── Mixer ─────────────────────────────────────────────────────────────────────
+// Every sender gets a lane of its own: a ring fed by a decode task with its
+// own jitter tracker, decoder (and so concealment state) and playout target,
+// plus a drift compensator. Lanes only meet in `Mixer::mix`, which the output
+// callback calls once a block: each lane plays out on its own terms –
+// buffering, underruns and comfort noise are per lane – and the results are
+// summed at 48 kHz in the widest layout any lane has. A call has one lane; a
+// multicast group played with `MulticastOptions::mix` has one per source.
+//
+// No sender's clock runs at exactly our rate, and each is off by a different
+// amount, so one shared queue can't be held steady for all of them. Each
+// lane's `Drift` follows its smoothed depth and, once that strays more than
+// `MARGIN_MS` from where the target puts it, drops or repeats a single sample
+// frame, at most one in `CORRECT_EVERY` – inaudible for the tens to hundreds
+// of ppm real clocks are apart. The same correction eases the queue to a new
+// target when the jitter buffer adapts.
+
+use crate::{codec, frame_samples, jitter, latency, quality, surround};
+use crate::{ComfortNoise, Format, Inbound, SAMPLE_RATE};
+use async_channel::Sender;
+use parking_lot::Mutex;
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How far the smoothed depth may stray before it's corrected, at most.
+const MARGIN_MS: usize = 20;
+/// Sample frames played per correction, at least (1000 ppm).
+const CORRECT_EVERY: u32 = 1000;
+/// Time constant of the smoothed depth.
+const SMOOTHING_SECS: f64 = 2.0;
+
+/// One sender's playout, as `VoiceSession::peer_playout` reports it.
+#[derive(Clone, Debug, Serialize)]
+pub struct LaneStats {
+    /// `None` until its stream parameters arrive.
+    pub peer: Option<SocketAddr>,
+    pub channels: usize,
+    pub depth_ms: f32,
+    pub target_ms: u32,
+    pub underruns: u64,
+    /// Sample frames dropped (positive) or repeated per million played:
+    /// how far the sender's clock runs from ours, plus any easing to a new
+    /// target.
+    pub drift_ppm: f32,
+    pub jitter: jitter::JitterStats,
+    pub quality: quality::QualityStats,
+}
+
+pub(crate) struct Lane {
+    /// Tells its recording apart from other senders'.
+    pub id: u32,
+    pub peer: Mutex<Option<SocketAddr>>,
+    /// Channels the ring holds, in Vorbis order.
+    pub channels: AtomicUsize,
+    /// Samples per channel in one of the sender's frames.
+    pub frame: AtomicUsize,
+    /// The sender is muted or DTX‑silent: fill gaps with comfort noise.
+    pub silent: AtomicBool,
+    /// Samples per channel to queue before playout (re)starts.
+    pub target: AtomicUsize,
+    /// Playout ran dry while the sender wasn't silent.
+    pub underruns: AtomicU64,
+    /// Samples per channel queued, as of the last callback.
+    depth: AtomicUsize,
+    pub jitter_stats: Mutex<jitter::JitterStats>,
+    pub quality_stats: Mutex<quality::QualityStats>,
+    reader: Mutex<Reader>,
+}
+
+/// The output side of a lane; only the mixer touches it.
+struct Reader {
+    consumer: HeapConsumer<f32>,
+    /// Set when the ring runs dry; playout waits for the target.
+    buffering: bool,
+    noise: ComfortNoise,
+    drift: Drift,
+    /// The frame last played, which a repeat plays again.
+    frame: Vec<f32>,
+    remixed: Vec<f32>,
+}
+
+impl Lane {
+    /// Samples (all channels) still queued.
+    pub fn queued(&self) -> usize {
+        self.reader.lock().consumer.len()
+    }
+
+    /// Adds `out.len() / channels` frames of this lane to `out`.
+    fn play(&self, out: &mut [f32], channels: usize, format: Option<&Format>) {
+        let mut reader = self.reader.lock();
+        let r = &mut *reader;
+        let n = self.channels.load(Ordering::Relaxed);
+        if r.frame.len() != n {
+            r.frame = vec![0.0; n];
+        }
+        r.remixed.resize(channels, 0.0);
+        let silent = self.silent.load(Ordering::Relaxed);
+        let target = self.target.load(Ordering::Relaxed);
+        let depth = r.consumer.len() / n;
+        if r.consumer.is_empty() {
+            if !r.buffering && !silent {
+                self.underruns.fetch_add(1, Ordering::Relaxed);
+                if let Some(format) = format {
+                    format.underruns.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            r.buffering = true;
+        } else if r.buffering && depth >= target {
+            r.buffering = false;
+            r.drift.depth = depth as f64;
+        }
+        self.depth.store(depth, Ordering::Relaxed);
+        if !r.buffering {
+            if let Some(format) = format {
+                let queued = Duration::from_secs_f64(depth as f64 / SAMPLE_RATE as f64);
+                format.latency.record(latency::Stage::PlayoutBuffer, queued);
+            }
+            r.drift.measure(depth, out.len() / channels);
+        }
+        // Frames arrive whole, so the queue averages half a frame over the
+        // target.
+        let center = target + self.frame.load(Ordering::Relaxed) / 2;
+        let (from, to) = (surround::vorbis(n), surround::vorbis(channels));
+        for dst in out.chunks_exact_mut(channels) {
+            let take = match r.buffering {
+                true => 1,
+                false => r.drift.next(target, center),
+            };
+            for _ in 0..take {
+                for s in r.frame.iter_mut() {
+                    let queued = if r.buffering { None } else { r.consumer.pop() };
+                    *s = match queued {
+                        Some(s) => s,
+                        None if silent => r.noise.next(),
+                        None => 0.0,
+                    };
+                }
+            }
+            let src = match n == channels {
+                true => &r.frame,
+                false => {
+                    surround::remix(&r.frame, from, &mut r.remixed, to);
+                    &r.remixed
+                }
+            };
+            dst.iter_mut().zip(src).for_each(|(d, s)| *d += s);
+        }
+    }
+
+    fn stats(&self) -> LaneStats {
+        let ms = |samples: usize| samples as f32 * 1000.0 / SAMPLE_RATE as f32;
+        LaneStats {
+            peer: *self.peer.lock(),
+            channels: self.channels.load(Ordering::Relaxed),
+            depth_ms: ms(self.depth.load(Ordering::Relaxed)),
+            target_ms: ms(self.target.load(Ordering::Relaxed)) as u32,
+            underruns: self.underruns.load(Ordering::Relaxed),
+            drift_ppm: self.reader.lock().drift.ppm(),
+            jitter: self.jitter_stats.lock().clone(),
+            quality: self.quality_stats.lock().clone(),
+        }
+    }
+}
+
+/// Steers one lane's queue towards its target a sample frame at a time.
+#[derive(Default)]
+struct Drift {
+    /// Smoothed depth, samples per channel.
+    depth: f64,
+    /// Sample frames since the last correction.
+    since: u32,
+    /// Frames dropped less frames repeated, and frames played.
+    adjusted: i64,
+    played: u64,
+}
+
+impl Drift {
+    /// Folds in the depth at the start of a block of `frames`.
+    fn measure(&mut self, depth: usize, frames: usize) {
+        let a = (frames as f64 / (SMOOTHING_SECS * SAMPLE_RATE as f64)).min(1.0);
+        self.depth += (depth as f64 - self.depth) * a;
+    }
+
+    /// Frames to take from the ring for the next one played: two to catch
+    /// up, none to repeat the last, one otherwise.
+    fn next(&mut self, target: usize, center: usize) -> usize {
+        self.played += 1;
+        self.since = self.since.saturating_add(1);
+        // Less below a shallow target, or it would run dry first.
+        let margin = frame_samples(MARGIN_MS).min(target / 2) as f64;
+        let take = match self.depth - center as f64 {
+            _ if self.since < CORRECT_EVERY => return 1,
+            off if off > margin => 2,
+            off if off < -margin => 0,
+            _ => return 1,
+        };
+        self.since = 0;
+        self.adjusted += take as i64 - 1;
+        self.depth -= take as f64 - 1.0;
+        take
+    }
+
+    fn ppm(&self) -> f32 {
+        match self.played {
+            0 => 0.0,
+            n => (self.adjusted as f64 * 1e6 / n as f64) as f32,
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Mixer {
+    lanes: Mutex<Vec<Arc<Lane>>>,
+    next_id: AtomicU32,
+}
+
+impl Mixer {
+    /// A new lane whose ring holds `capacity` samples, waiting for `target`
+    /// per channel; and the decode task's end of the ring.
+    pub fn add(&self, capacity: usize, target: usize) -> (Arc<Lane>, HeapProducer<f32>) {
+        let (producer, consumer) = HeapRb::<f32>::new(capacity).split();
+        let lane = Arc::new(Lane {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            peer: Mutex::new(None),
+            channels: AtomicUsize::new(1),
+            frame: AtomicUsize::new(0),
+            silent: AtomicBool::new(false),
+            target: AtomicUsize::new(target),
+            underruns: AtomicU64::new(0),
+            depth: AtomicUsize::new(0),
+            jitter_stats: Mutex::default(),
+            quality_stats: Mutex::default(),
+            reader: Mutex::new(Reader {
+                consumer,
+                buffering: true,
+                noise: ComfortNoise::default(),
+                drift: Drift::default(),
+                frame: vec![0.0],
+                remixed: Vec::new(),
+            }),
+        });
+        self.lanes.lock().push(Arc::clone(&lane));
+        (lane, producer)
+    }
+
+    pub fn remove(&self, lane: &Arc<Lane>) {
+        self.lanes.lock().retain(|l| !Arc::ptr_eq(l, lane));
+    }
+
+    /// Channels to mix in: the widest lane's.
+    pub fn channels(&self) -> usize {
+        let lanes = self.lanes.lock();
+        let widest = lanes.iter().map(|l| l.channels.load(Ordering::Relaxed));
+        widest.max().unwrap_or(1)
+    }
+
+    /// Fills `out` (interleaved, Vorbis order, 48 kHz) with every lane's next
+    /// `out.len() / channels` frames, summed. Underruns, depth and latency
+    /// are reported to `format`, if given.
+    pub fn mix(&self, out: &mut [f32], channels: usize, format: Option<&Format>) {
+        out.fill(0.0);
+        let mut deepest = 0;
+        for lane in self.lanes.lock().iter() {
+            lane.play(out, channels, format);
+            deepest = deepest.max(lane.depth.load(Ordering::Relaxed));
+        }
+        if let Some(format) = format {
+            format.playout_depth.store(deepest, Ordering::Relaxed);
+        }
+    }
+
+    /// Whether `lane` is heard worst (lowest MOS), so that its stats stand
+    /// for the session's.
+    pub fn worst(&self, lane: &Arc<Lane>) -> bool {
+        let lanes = self.lanes.lock();
+        let mos = |l: &Arc<Lane>| l.quality_stats.lock().mos;
+        let worst = lanes.iter().min_by(|a, b| mos(a).total_cmp(&mos(b)));
+        worst.is_none_or(|w| Arc::ptr_eq(w, lane))
+    }
+
+    pub fn stats(&self) -> Vec<LaneStats> {
+        self.lanes.lock().iter().map(|l| l.stats()).collect()
+    }
+}
+
+/// Routes each sender's messages to a lane of its own. Closing a sender's
+/// lane ends its decode task, which takes the lane out of the mix.
+pub(crate) struct Demux {
+    /// A new lane and decode task, and the way in to it.
+    open: Box<dyn Fn() -> Sender<Inbound> + Send>,
+    lanes: HashMap<SocketAddr, Sender<Inbound>>,
+}
+
+impl Demux {
+    pub fn new(open: impl Fn() -> Sender<Inbound> + Send + 'static) -> Self {
+        Self {
+            open: Box::new(open),
+            lanes: HashMap::new(),
+        }
+    }
+
+    pub fn has(&self, from: &SocketAddr) -> bool {
+        self.lanes.contains_key(from)
+    }
+
+    /// Starts a lane for `from`, which sends with `params`.
+    pub fn open(&mut self, from: SocketAddr, params: codec::StreamParams) {
+        let lane = (self.open)();
+        let _ = lane.try_send(Inbound::Params(from, params));
+        self.lanes.insert(from, lane);
+    }
+
+    /// Drops the message if `from` has no lane, or its lane is behind.
+    pub fn send(&self, from: &SocketAddr, msg: Inbound) {
+        if let Some(lane) = self.lanes.get(from) {
+            let _ = lane.try_send(msg);
+        }
+    }
+
+    pub fn close(&mut self, from: &SocketAddr) {
+        self.lanes.remove(from);
+    }
+}
+
+// ─── Bridges ───────────────────────────────────────────────────────────────────
+// Audio handed from one device's callback to another's. Two devices run off
+// clocks no closer to each other than a sender's is to ours, so a `Bridge` is
+// a lane of its own: one side feeds it whatever blocks its device delivers,
+// the other plays it out with the same buffering and drift correction as any
+// sender's. The main output feeds one to a second output device
+// (`--mirror-device`), and a second input device feeds one to the main input
+// (`--extra-input`).
+
+/// Queued before the far side plays: room for both devices' buffers to tick
+/// at different times.
+const BRIDGE_MS: usize = 40;
+/// Past this the far side isn't playing; blocks are dropped.
+const BRIDGE_CAPACITY_MS: usize = 500;
+
+pub(crate) struct Bridge {
+    channels: usize,
+    mixer: Mixer,
+    lane: Arc<Lane>,
+    feed: Mutex<HeapProducer<f32>>,
+}
+
+impl Bridge {
+    /// Carries `channels` channels (Vorbis order) at 48 kHz.
+    pub fn new(channels: usize) -> Self {
+        let mixer = Mixer::default();
+        let capacity = frame_samples(BRIDGE_CAPACITY_MS) * channels;
+        let (lane, feed) = mixer.add(capacity, frame_samples(BRIDGE_MS));
+        lane.channels.store(channels, Ordering::Relaxed);
+        Self {
+            channels,
+            mixer,
+            lane,
+            feed: Mutex::new(feed),
+        }
+    }
+
+    /// Queues a block the feeding device delivered.
+    pub fn feed(&self, block: &[f32]) {
+        self.lane
+            .frame
+            .store(block.len() / self.channels, Ordering::Relaxed);
+        let mut feed = self.feed.lock();
+        if feed.free_len() >= block.len() {
+            feed.push_slice(block);
+        }
+    }
+
+    /// Forgets what was queued while nothing played it.
+    pub fn restart(&self) {
+        let mut reader = self.lane.reader.lock();
+        reader.consumer.clear();
+        reader.buffering = true;
+    }
+
+    /// Fills `out` with the next `out.len() / channels` frames.
+    pub fn play(&self, out: &mut [f32]) {
+        self.mixer.mix(out, self.channels, None);
+    }
+
+    /// Times the playing side ran dry.
+    pub fn underruns(&self) -> u64 {
+        self.lane.underruns.load(Ordering::Relaxed)
+    }
+}
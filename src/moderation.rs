@@ -0,0 +1,144 @@
+// ─── Moderation ────────────────────────────────────────────────────────────────
+// The host of a multicast group (`--host-passphrase`) can mute, unmute or
+// remove members mid‑call. Each `Order` names the member by the salt it
+// announces in its Hello, carries the host's clock and is signed with the
+// `crypto::HostKey`; it goes to the group sealed like any other packet
+// (`Sealed::Moderate`). Members started with the host's public key
+// (`--moderator`) check the signature and enforce the order themselves:
+//   • the member named stops sending (mute) or leaves the group (remove),
+//   • everyone else stops playing them, so a member whose client ignores the
+//     order still isn't heard, and ignores a removed member's address for the
+//     rest of the session.
+// Orders older than `MAX_AGE`, or not newer than the last one applied, are
+// dropped so a recorded order can't be replayed. Groups have no forwarding
+// server; a relay only ever forwards for two peers, so there is nothing for
+// one to enforce.
+
+use crate::crypto::{self, PUBLIC_KEY_LEN, SIGNATURE_LEN};
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How far an order's clock may be from ours.
+const MAX_AGE: Duration = Duration::from_secs(30);
+/// Orders are sent this many times; members apply the first that arrives.
+pub(crate) const REPEATS: usize = 3;
+const ORDER_LEN: usize = 1 + PUBLIC_KEY_LEN + 8;
+/// Signed along with the order, so the signature means nothing elsewhere.
+const CONTEXT: &[u8] = b"audio-p2p/v1 moderation";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Mute = 0,
+    Unmute = 1,
+    /// Out of the group for the rest of the session.
+    Remove = 2,
+}
+
+impl Action {
+    fn from_byte(b: u8) -> Option<Self> {
+        match b {
+            0 => Some(Action::Mute),
+            1 => Some(Action::Unmute),
+            2 => Some(Action::Remove),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Order {
+    pub action: Action,
+    /// The member's announced salt.
+    pub target: [u8; PUBLIC_KEY_LEN],
+    /// Host clock, ms since the Unix epoch.
+    issued_ms: u64,
+}
+
+impl Order {
+    pub fn new(action: Action, target: [u8; PUBLIC_KEY_LEN]) -> Self {
+        Self {
+            action,
+            target,
+            issued_ms: now_ms(),
+        }
+    }
+
+    fn bytes(&self) -> [u8; ORDER_LEN] {
+        let mut out = [0u8; ORDER_LEN];
+        out[0] = self.action as u8;
+        out[1..1 + PUBLIC_KEY_LEN].copy_from_slice(&self.target);
+        out[1 + PUBLIC_KEY_LEN..].copy_from_slice(&self.issued_ms.to_be_bytes());
+        out
+    }
+
+    /// The order followed by the host's signature.
+    pub fn sign(&self, key: &crypto::HostKey) -> Vec<u8> {
+        let body = self.bytes();
+        let mut out = body.to_vec();
+        out.extend_from_slice(&key.sign(&[CONTEXT, &body].concat()));
+        out
+    }
+
+    /// `None` unless `host` signed `msg` and it is recent.
+    pub fn verify(msg: &[u8], host: &[u8; PUBLIC_KEY_LEN]) -> Option<Self> {
+        if msg.len() != ORDER_LEN + SIGNATURE_LEN {
+            return None;
+        }
+        let (body, sig) = msg.split_at(ORDER_LEN);
+        if !crypto::host_signed(host, &[CONTEXT, body].concat(), sig) {
+            return None;
+        }
+        let issued_ms = u64::from_be_bytes(body[1 + PUBLIC_KEY_LEN..].try_into().ok()?);
+        if now_ms().abs_diff(issued_ms) > MAX_AGE.as_millis() as u64 {
+            return None;
+        }
+        Some(Self {
+            action: Action::from_byte(body[0])?,
+            target: body[1..1 + PUBLIC_KEY_LEN].try_into().ok()?,
+            issued_ms,
+        })
+    }
+}
+
+/// The orders a member has applied.
+#[derive(Default)]
+pub(crate) struct Ledger {
+    /// The newest order applied, by the host's clock.
+    last_ms: u64,
+    muted: HashSet<[u8; PUBLIC_KEY_LEN]>,
+    removed: HashSet<[u8; PUBLIC_KEY_LEN]>,
+}
+
+impl Ledger {
+    /// Records `order`; false if it is a copy or older than the last one.
+    pub fn apply(&mut self, order: &Order) -> bool {
+        if order.issued_ms <= self.last_ms {
+            return false;
+        }
+        self.last_ms = order.issued_ms;
+        match order.action {
+            Action::Mute => {
+                self.muted.insert(order.target);
+            }
+            Action::Unmute => {
+                self.muted.remove(&order.target);
+            }
+            Action::Remove => {
+                self.removed.insert(order.target);
+            }
+        }
+        true
+    }
+
+    /// Whether the member announcing `salt` must not be played.
+    pub fn silenced(&self, salt: &[u8; PUBLIC_KEY_LEN]) -> bool {
+        self.muted.contains(salt) || self.removed.contains(salt)
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
@@ -0,0 +1,300 @@
+// ─── MQTT client ───────────────────────────────────────────────────────────────
+// Just enough MQTT 3.1.1 for signaling over a broker (see `signaling`):
+// connect (optionally over TLS, with credentials and a last will), subscribe
+// to one filter, publish at QoS 0, keep the connection alive. Incoming
+// messages are whatever the broker delivers at the QoS 0 we ask for, so
+// there is nothing to acknowledge.
+
+use anyhow::{bail, Context, Result};
+use bytes::{Buf, BufMut, BytesMut};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::{self, pki_types::ServerName};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Brokers may send bigger packets; ours never are, so anything past this
+/// is a broken stream.
+const MAX_PACKET: usize = 256 * 1024;
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+const DISCONNECT: u8 = 14;
+
+#[derive(Clone, Debug)]
+pub(crate) struct Options {
+    pub host: String,
+    pub port: u16,
+    pub tls: bool,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Published by the broker if we vanish without disconnecting.
+    pub will: Option<Message>,
+    pub keep_alive: Duration,
+    pub proxy: Option<crate::proxy::Proxy>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Message {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub retain: bool,
+}
+
+trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
+
+pub(crate) struct Connection {
+    stream: Box<dyn Io>,
+    buf: BytesMut,
+    /// Messages that arrived while we waited for something else.
+    pending: VecDeque<Message>,
+    next_id: u16,
+    heard: Instant,
+}
+
+impl Connection {
+    /// Connects and logs in; fails if the broker refuses us.
+    pub async fn connect(options: &Options) -> Result<Self> {
+        let tcp = match &options.proxy {
+            Some(proxy) => proxy.connect(&options.host, options.port).await?,
+            None => {
+                let tcp = tokio::time::timeout(
+                    CONNECT_TIMEOUT,
+                    TcpStream::connect((options.host.as_str(), options.port)),
+                )
+                .await
+                .context("timed out connecting to the broker")??;
+                tcp.set_nodelay(true)?;
+                tcp
+            }
+        };
+        let stream: Box<dyn Io> = match options.tls {
+            false => Box::new(tcp),
+            true => {
+                let roots = rustls::RootCertStore {
+                    roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+                };
+                let config = rustls::ClientConfig::builder_with_provider(Arc::new(
+                    rustls::crypto::ring::default_provider(),
+                ))
+                .with_safe_default_protocol_versions()?
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+                let name = ServerName::try_from(options.host.clone())
+                    .context("invalid broker host name")?;
+                let tls = tokio_rustls::TlsConnector::from(Arc::new(config))
+                    .connect(name, tcp)
+                    .await
+                    .context("TLS handshake with the broker failed")?;
+                Box::new(tls)
+            }
+        };
+        let mut conn = Self {
+            stream,
+            buf: BytesMut::with_capacity(4096),
+            pending: VecDeque::new(),
+            next_id: 1,
+            heard: Instant::now(),
+        };
+        conn.write(CONNECT << 4, &connect_body(options)).await?;
+        let (header, body) = tokio::time::timeout(CONNECT_TIMEOUT, conn.read_packet())
+            .await
+            .context("the broker didn't answer our CONNECT")??;
+        if header >> 4 != CONNACK || body.len() < 2 {
+            bail!("the broker sent something other than CONNACK");
+        }
+        match body[1] {
+            0 => Ok(conn),
+            1 => bail!("the broker doesn't speak MQTT 3.1.1"),
+            2 => bail!("the broker rejected our client id"),
+            3 => bail!("the broker is unavailable"),
+            4 | 5 => bail!("the broker rejected our credentials"),
+            code => bail!("the broker refused the connection ({code})"),
+        }
+    }
+
+    /// Subscribes to `filter` at QoS 0.
+    pub async fn subscribe(&mut self, filter: &str) -> Result<()> {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        let mut body = BytesMut::new();
+        body.put_u16(id);
+        put_str(&mut body, filter.as_bytes());
+        body.put_u8(0);
+        self.write(SUBSCRIBE << 4 | 0b0010, &body).await?;
+        loop {
+            let (header, body) = self.read_packet().await?;
+            if header >> 4 == PUBLISH {
+                self.pending.push_back(parse_publish(header, body)?);
+                continue;
+            }
+            if header >> 4 != SUBACK || body.len() < 3 || body[..2] != id.to_be_bytes() {
+                continue;
+            }
+            if body[2] == 0x80 {
+                bail!("the broker refused our subscription to {filter}");
+            }
+            return Ok(());
+        }
+    }
+
+    pub async fn publish(&mut self, message: &Message) -> Result<()> {
+        let mut body = BytesMut::new();
+        put_str(&mut body, message.topic.as_bytes());
+        body.put_slice(&message.payload);
+        self.write(PUBLISH << 4 | message.retain as u8, &body).await
+    }
+
+    pub async fn ping(&mut self) -> Result<()> {
+        self.write(PINGREQ << 4, &[]).await
+    }
+
+    /// How long since the broker last sent anything.
+    pub fn idle(&self) -> Duration {
+        self.heard.elapsed()
+    }
+
+    /// Sends DISCONNECT, so the broker drops the will.
+    pub async fn disconnect(mut self) {
+        let _ = self.write(DISCONNECT << 4, &[]).await;
+        let _ = self.stream.shutdown().await;
+    }
+
+    /// The next message on our subscription. Cancel‑safe: a partly read
+    /// packet stays buffered.
+    pub async fn next(&mut self) -> Result<Message> {
+        if let Some(message) = self.pending.pop_front() {
+            return Ok(message);
+        }
+        loop {
+            let (header, body) = self.read_packet().await?;
+            match header >> 4 {
+                PUBLISH => return parse_publish(header, body),
+                PINGRESP | SUBACK => {}
+                kind => bail!("unexpected packet {kind} from the broker"),
+            }
+        }
+    }
+
+    async fn write(&mut self, header: u8, body: &[u8]) -> Result<()> {
+        let mut packet = BytesMut::with_capacity(body.len() + 5);
+        packet.put_u8(header);
+        let mut len = body.len();
+        loop {
+            let byte = (len % 128) as u8;
+            len /= 128;
+            packet.put_u8(if len > 0 { byte | 0x80 } else { byte });
+            if len == 0 {
+                break;
+            }
+        }
+        packet.put_slice(body);
+        self.stream.write_all(&packet).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    /// One whole packet: its first header byte and its body.
+    async fn read_packet(&mut self) -> Result<(u8, BytesMut)> {
+        loop {
+            if let Some(packet) = take_packet(&mut self.buf)? {
+                self.heard = Instant::now();
+                return Ok(packet);
+            }
+            if self.stream.read_buf(&mut self.buf).await? == 0 {
+                bail!("the broker closed the connection");
+            }
+        }
+    }
+}
+
+fn connect_body(options: &Options) -> BytesMut {
+    let mut body = BytesMut::new();
+    put_str(&mut body, b"MQTT");
+    body.put_u8(4);
+    let mut flags = 0b10; // clean session
+    if let Some(will) = &options.will {
+        flags |= 0b100 | (will.retain as u8) << 5;
+    }
+    if options.username.is_some() {
+        flags |= 0x80;
+    }
+    if options.password.is_some() {
+        flags |= 0x40;
+    }
+    body.put_u8(flags);
+    body.put_u16(options.keep_alive.as_secs().clamp(1, u16::MAX as u64) as u16);
+    put_str(&mut body, options.client_id.as_bytes());
+    if let Some(will) = &options.will {
+        put_str(&mut body, will.topic.as_bytes());
+        put_str(&mut body, &will.payload);
+    }
+    if let Some(username) = &options.username {
+        put_str(&mut body, username.as_bytes());
+    }
+    if let Some(password) = &options.password {
+        put_str(&mut body, password.as_bytes());
+    }
+    body
+}
+
+fn parse_publish(header: u8, mut body: BytesMut) -> Result<Message> {
+    if body.len() < 2 {
+        bail!("short PUBLISH from the broker");
+    }
+    let len = body.get_u16() as usize;
+    // Then a packet id, which QoS 0 doesn't have.
+    let skip = len + if (header >> 1) & 3 > 0 { 2 } else { 0 };
+    if body.len() < skip {
+        bail!("short PUBLISH from the broker");
+    }
+    let topic = String::from_utf8_lossy(&body[..len]).into_owned();
+    body.advance(skip);
+    Ok(Message {
+        topic,
+        payload: body.to_vec(),
+        retain: header & 1 == 1,
+    })
+}
+
+fn put_str(out: &mut BytesMut, s: &[u8]) {
+    out.put_u16(s.len() as u16);
+    out.put_slice(s);
+}
+
+/// Splits a complete packet off the front of `buf`, if there is one.
+fn take_packet(buf: &mut BytesMut) -> Result<Option<(u8, BytesMut)>> {
+    let mut len = 0usize;
+    let mut at = 1;
+    loop {
+        let Some(&byte) = buf.get(at) else {
+            return Ok(None);
+        };
+        len |= ((byte & 0x7f) as usize) << (7 * (at - 1));
+        at += 1;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        if at > 4 {
+            bail!("bad packet length from the broker");
+        }
+    }
+    if len > MAX_PACKET {
+        bail!("{len}‑byte packet from the broker");
+    }
+    if buf.len() < at + len {
+        return Ok(None);
+    }
+    let header = buf[0];
+    buf.advance(at);
+    Ok(Some((header, buf.split_to(len))))
+}
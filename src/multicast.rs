@@ -0,0 +1,375 @@
+// ─── LAN multicast ─────────────────────────────────────────────────────────────
+// For trusted LANs: every member sends to one multicast group and listens to
+// it, so a room of machines can hear a source without per‑peer connections.
+// Senders announce a random salt and their stream parameters in a Hello every
+// `HELLO_INTERVAL`; members derive each sender's key from that salt and the
+// shared group secret (see `crypto::group_sealer`).
+//
+// One source plays at a time: whoever sent media last, once the current source
+// has been quiet for `SWITCH_AFTER`. A priority speaker (set by whoever runs
+// the event, `effects::Controls::set_priority_speaker`) doesn't wait: their
+// speech replaces the current source at once, and the usual wait keeps the
+// others out until they pause. With `mix` every source plays at once, each in
+// a lane of its own (see `mixer`), and a talking priority speaker holds the
+// others off instead. Members that only listen can join `--muted`.
+// The host can also mute or remove members outright (see `moderation`).
+// Members with a nickname send it, sealed, along with each Hello.
+
+use crate::jitter::Position;
+use crate::packet::{Packet, Sealed, SilenceReason};
+use crate::{
+    capture, codec, crypto, effects, flood, health, mixer, moderation, send_queue, signaling,
+    socket, Inbound, Outbound, HELLO_INTERVAL,
+};
+use anyhow::{bail, Result};
+use async_channel::Receiver;
+use bytes::Bytes;
+use std::collections::{HashMap, HashSet};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tracing::{error, info, warn};
+
+/// How long the playing source must be quiet before another takes over.
+const SWITCH_AFTER: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug)]
+pub struct MulticastOptions {
+    /// Group address and port, e.g. `239.255.42.1:40000`.
+    pub group: SocketAddr,
+    /// Shared by every member of the group.
+    pub secret: String,
+    /// Public key of the host whose orders we follow.
+    pub moderator: Option<[u8; crypto::PUBLIC_KEY_LEN]>,
+    /// Set when we are the host; we follow our own orders too.
+    pub host_key: Option<Arc<crypto::HostKey>>,
+    /// Play every source at once instead of one at a time.
+    pub mix: bool,
+}
+
+struct Source {
+    salt: [u8; crypto::PUBLIC_KEY_LEN],
+    params: codec::StreamParams,
+    opener: crypto::Opener,
+}
+
+/// A socket on the group's port that other members on this host can share.
+/// Needs a Tokio runtime.
+pub(crate) fn join(group: SocketAddr, opts: &socket::SocketOptions) -> Result<UdpSocket> {
+    let sock = socket::new(group, opts)?;
+    sock.set_reuse_address(true)?;
+    let any: SocketAddr = match group {
+        SocketAddr::V4(g) => {
+            if !g.ip().is_multicast() {
+                bail!("{} is not a multicast address", g.ip());
+            }
+            sock.join_multicast_v4(g.ip(), &Ipv4Addr::UNSPECIFIED)?;
+            (Ipv4Addr::UNSPECIFIED, g.port()).into()
+        }
+        SocketAddr::V6(g) => {
+            if !g.ip().is_multicast() {
+                bail!("{} is not a multicast address", g.ip());
+            }
+            sock.join_multicast_v6(g.ip(), 0)?;
+            (Ipv6Addr::UNSPECIFIED, g.port()).into()
+        }
+    };
+    sock.bind(&any.into())?;
+    Ok(UdpSocket::from_std(sock.into())?)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn multicast_task(
+    sock: UdpSocket,
+    opts: MulticastOptions,
+    params: codec::StreamParams,
+    mut outbound: send_queue::Outbox,
+    mut lanes: mixer::Demux,
+    effects: Arc<effects::Controls>,
+    mut orders: Option<Receiver<(SocketAddr, moderation::Action)>>,
+    introduction: Option<Bytes>,
+    roster: Arc<signaling::Roster>,
+    mut guard: flood::Guard,
+    health: Arc<health::Health>,
+) -> Result<()> {
+    let group = opts.group;
+    let secret = opts.secret.into_bytes();
+    let salt = crypto::group_salt()?;
+    let mut sealer = crypto::group_sealer(&secret, &salt)?;
+    let hello = Packet::Hello {
+        pub_key: salt,
+        params,
+    }
+    .encode();
+    let moderator = opts
+        .host_key
+        .as_ref()
+        .map(|k| k.public())
+        .or(opts.moderator);
+    let mut ledger = moderation::Ledger::default();
+    // Removed members, by address, so they can't come back with a new salt.
+    let mut banned: HashSet<SocketAddr> = HashSet::new();
+    info!("STATUS: multicast_joined {group}");
+    health.set_socket(health::Status::Ok);
+
+    let mut sources: HashMap<SocketAddr, Source> = HashMap::new();
+    // The source being played, and when it last sent media. With `mix`, the
+    // priority speaker while they talk.
+    let mut playing: Option<(SocketAddr, Instant)> = None;
+    let mut hello_tick = tokio::time::interval(HELLO_INTERVAL);
+    let mut buf = [0u8; crate::MAX_SURROUND_PACKET_SIZE + crate::packet::MEDIA_OVERHEAD];
+    loop {
+        tokio::select! {
+            msg = outbound.recv() => {
+                let Some(msg) = msg else {
+                    break;
+                };
+                let (kind, body) = match &msg {
+                    Outbound::Frame(frame) => (Sealed::Media, &frame[..]),
+                    Outbound::Silence(reason) => (Sealed::Silence, &[*reason as u8][..]),
+                    Outbound::Hold(held) => (Sealed::Hold, &[*held as u8][..]),
+                };
+                match sealer.seal(kind, body) {
+                    Ok(pkt) => {
+                        capture::sent(&sock, group, &pkt);
+                        capture::decrypted(&sock, group, capture::Direction::Out, &pkt, body);
+                        if let Err(e) = sock.send_to(&pkt, group).await {
+                            error!("udp send error: {e}");
+                        }
+                    }
+                    Err(e) => error!("{e}"),
+                }
+            }
+            order = async { orders.as_ref()?.recv().await.ok() }, if orders.is_some() => {
+                let (Some((peer, action)), Some(key)) = (order, &opts.host_key) else {
+                    orders = None;
+                    continue;
+                };
+                let Some(target) = sources.get(&peer).map(|s| s.salt) else {
+                    warn!(%peer, "STATUS: moderation_unknown {peer}");
+                    continue;
+                };
+                let order = moderation::Order::new(action, target);
+                ledger.apply(&order);
+                enforce(&order, &mut sources, &mut playing, &mut lanes, &mut banned, &health);
+                let body = order.sign(key);
+                for _ in 0..moderation::REPEATS {
+                    match sealer.seal(Sealed::Moderate, &body) {
+                        Ok(pkt) => {
+                            capture::sent(&sock, group, &pkt);
+                            if let Err(e) = sock.send_to(&pkt, group).await {
+                                error!("udp send error: {e}");
+                            }
+                        }
+                        Err(e) => error!("{e}"),
+                    }
+                }
+                info!(%peer, ?action, "STATUS: moderation_sent {action:?} {peer}");
+            }
+            _ = hello_tick.tick() => {
+                capture::sent(&sock, group, &hello);
+                if let Err(e) = sock.send_to(&hello, group).await {
+                    error!("udp send error: {e}");
+                }
+                let Some(intro) = &introduction else {
+                    continue;
+                };
+                match sealer.seal(Sealed::Introduce, intro) {
+                    Ok(pkt) => {
+                        capture::sent(&sock, group, &pkt);
+                        if let Err(e) = sock.send_to(&pkt, group).await {
+                            error!("udp send error: {e}");
+                        }
+                    }
+                    Err(e) => error!("{e}"),
+                }
+            }
+            r = sock.recv_from(&mut buf) => {
+                let (n, src) = match r {
+                    Ok(r) => r,
+                    Err(e) => {
+                        error!("udp recv error: {e}");
+                        health.set_socket(health::Status::Failed);
+                        continue;
+                    }
+                };
+                health.set_socket(health::Status::Ok);
+                capture::received(&sock, src, &buf[..n]);
+                if !guard.admit(src, n) {
+                    continue;
+                }
+                match Packet::parse(&buf[..n]) {
+                    Some(Packet::Hello { pub_key, params }) => {
+                        // Our own Hello, looped back.
+                        if pub_key == salt || banned.contains(&src) {
+                            continue;
+                        }
+                        if sources.get(&src).is_some_and(|s| s.salt == pub_key) {
+                            continue;
+                        }
+                        // New sender, or one that restarted.
+                        match crypto::group_opener(&secret, &pub_key) {
+                            Ok(opener) => {
+                                info!("multicast source {src} announced");
+                                let source = Source {
+                                    salt: pub_key,
+                                    params,
+                                    opener,
+                                };
+                                sources.insert(src, source);
+                                health.set_peers(sources.len());
+                                if playing.is_some_and(|(addr, _)| addr == src) {
+                                    playing = None;
+                                }
+                                // Its lane decodes the old stream.
+                                lanes.close(&src);
+                            }
+                            Err(e) => error!("{e}"),
+                        }
+                    }
+                    Some(Packet::Sealed {
+                        kind,
+                        epoch,
+                        seq,
+                        payload,
+                    }) => {
+                        // Nobody pings a group.
+                        if !matches!(
+                            kind,
+                            Sealed::Media | Sealed::Silence | Sealed::Moderate | Sealed::Introduce
+                        ) {
+                            continue;
+                        }
+                        let Some(source) = sources.get_mut(&src) else {
+                            continue;
+                        };
+                        let Some(body) = source.opener.open(kind, epoch, seq, payload) else {
+                            warn!(peer = %src, epoch, seq, "dropping multicast packet that fails the group key");
+                            continue;
+                        };
+                        health.packet();
+                        capture::decrypted(&sock, src, capture::Direction::In, &buf[..n], &body);
+                        if kind == Sealed::Introduce {
+                            if let Some(intro) = signaling::Introduction::decode(&body) {
+                                roster.introduce(src, intro);
+                            }
+                            continue;
+                        }
+                        if kind == Sealed::Moderate {
+                            let Some(order) =
+                                moderator.and_then(|host| moderation::Order::verify(&body, &host))
+                            else {
+                                warn!(peer = %src, "ignoring an order not signed by our moderator");
+                                continue;
+                            };
+                            if !ledger.apply(&order) {
+                                continue;
+                            }
+                            if order.target != salt {
+                                enforce(
+                                    &order,
+                                    &mut sources,
+                                    &mut playing,
+                                    &mut lanes,
+                                    &mut banned,
+                                    &health,
+                                );
+                                continue;
+                            }
+                            match order.action {
+                                moderation::Action::Mute => effects.set_muted(true),
+                                moderation::Action::Unmute => effects.set_muted(false),
+                                moderation::Action::Remove => {
+                                    effects.set_muted(true);
+                                    info!("STATUS: multicast_removed {group}");
+                                    return Ok(());
+                                }
+                            }
+                            info!("STATUS: moderated {:?}", order.action);
+                            continue;
+                        }
+                        // Muted or removed by the host, whatever their client does.
+                        if ledger.silenced(&source.salt) {
+                            continue;
+                        }
+                        let pos = Position {
+                            epoch,
+                            seq,
+                            at: Instant::now(),
+                        };
+                        let priority = effects.priority_speaker() == Some(src);
+                        let current = playing.map(|(addr, _)| addr);
+                        let talking = playing.is_some_and(|(_, at)| at.elapsed() < SWITCH_AFTER);
+                        if opts.mix {
+                            if current != Some(src) && talking {
+                                continue;
+                            }
+                        } else if current != Some(src) {
+                            if kind != Sealed::Media || (talking && !priority) {
+                                continue;
+                            }
+                            if let Some(old) = current {
+                                lanes.close(&old);
+                            }
+                        }
+                        if !lanes.has(&src) {
+                            if kind != Sealed::Media {
+                                continue;
+                            }
+                            let label = roster.label(src);
+                            info!(%label, "STATUS: multicast_source {src}");
+                            lanes.open(src, source.params.clone());
+                        }
+                        let msg = match kind {
+                            Sealed::Media => {
+                                if !opts.mix || priority {
+                                    playing = Some((src, pos.at));
+                                }
+                                Inbound::Frame(pos, body)
+                            }
+                            _ => match body.first().copied().and_then(SilenceReason::from_byte) {
+                                Some(reason) => Inbound::Silence(pos, reason),
+                                None => continue,
+                            },
+                        };
+                        lanes.send(&src, msg);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// What every member does about another member named in `order`.
+fn enforce(
+    order: &moderation::Order,
+    sources: &mut HashMap<SocketAddr, Source>,
+    playing: &mut Option<(SocketAddr, Instant)>,
+    lanes: &mut mixer::Demux,
+    banned: &mut HashSet<SocketAddr>,
+    health: &health::Health,
+) {
+    let Some(addr) = sources
+        .iter()
+        .find(|(_, s)| s.salt == order.target)
+        .map(|(addr, _)| *addr)
+    else {
+        return;
+    };
+    if order.action == moderation::Action::Unmute {
+        return;
+    }
+    if playing.is_some_and(|(p, _)| p == addr) {
+        *playing = None;
+    }
+    lanes.close(&addr);
+    if order.action == moderation::Action::Remove {
+        sources.remove(&addr);
+        banned.insert(addr);
+        health.set_peers(sources.len());
+    }
+    info!("STATUS: moderated {:?} {addr}", order.action);
+}
@@ -0,0 +1,156 @@
+// ─── Retransmission (NACK) ─────────────────────────────────────────────────────
+// On a LAN a lost packet can be sent again faster than FEC or concealment can
+// hide it. With `--nack`, a receiver that sees one or two packets go missing –
+// an isolated loss, not a dead link – and measured a round trip of at most
+// two frames holds back what arrived after the gap, asks the sender for the
+// missing ones in a sealed Nack, and waits a round trip (plus half a frame of
+// slack) for them. Whatever is still missing then is concealed as usual, so
+// the cost of a loss that can't be repaired is that wait on top of the gap.
+//
+// Senders always keep their last `HISTORY` media packets, sealed, and resend
+// them byte for byte; the receiver treats the copy like any other late packet.
+// Calls have one sender at a time, so a Nack goes to every keyed peer and the
+// ones that didn't send those sequence numbers find nothing to resend.
+
+use crate::jitter::Position;
+use crate::packet::{Packet, Sealed};
+use bytes::Bytes;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Most packets missing in a row that we ask for.
+const MAX_MISSING: u32 = 2;
+/// Media packets a sender can resend: a second of 20 ms frames.
+const HISTORY: usize = 50;
+
+/// The sender's recently sealed media.
+#[derive(Default)]
+pub(crate) struct History(VecDeque<(u8, u32, Bytes)>);
+
+impl History {
+    pub fn push(&mut self, pkt: &Bytes) {
+        let Some(Packet::Sealed {
+            kind: Sealed::Media,
+            epoch,
+            seq,
+            ..
+        }) = Packet::parse(pkt)
+        else {
+            return;
+        };
+        if self.0.len() == HISTORY {
+            self.0.pop_front();
+        }
+        self.0.push_back((epoch, seq, pkt.clone()));
+    }
+
+    /// The packets a Nack body asks for that we still have.
+    pub fn resend(&self, body: &[u8]) -> Vec<Bytes> {
+        let Some((&epoch, seqs)) = body.split_first() else {
+            return Vec::new();
+        };
+        seqs.chunks_exact(4)
+            .map(|s| u32::from_be_bytes([s[0], s[1], s[2], s[3]]))
+            .filter_map(|seq| {
+                self.0
+                    .iter()
+                    .find(|(e, s, _)| (*e, *s) == (epoch, seq))
+                    .map(|(_, _, pkt)| pkt.clone())
+            })
+            .collect()
+    }
+}
+
+/// How long to wait for a gap of `missed` packets to be resent at round
+/// trip `rtt`, if it is worth asking at all.
+pub(crate) fn wait(missed: u32, rtt: Option<Duration>, frame_ms: usize) -> Option<Duration> {
+    let frame = Duration::from_millis(frame_ms as u64);
+    let rtt = rtt.filter(|&rtt| rtt <= 2 * frame)?;
+    (1..=MAX_MISSING).contains(&missed).then(|| rtt + frame / 2)
+}
+
+/// A gap the decoder holds playout back for.
+pub(crate) struct Repair {
+    epoch: u8,
+    /// Sequence number of the first missing packet.
+    first: u32,
+    missed: u32,
+    /// The missing packets as they come back, then everything that arrived
+    /// in order after the gap.
+    slots: Vec<Option<Vec<u8>>>,
+    deadline: Instant,
+}
+
+impl Repair {
+    /// `pkt` at `pos` arrived right after `missed` missing packets.
+    pub fn new(pos: Position, missed: u32, pkt: Vec<u8>, wait: Duration) -> Self {
+        let mut slots = vec![None; missed as usize];
+        slots.push(Some(pkt));
+        Self {
+            epoch: pos.epoch,
+            first: pos.seq - missed,
+            missed,
+            slots,
+            deadline: pos.at + wait,
+        }
+    }
+
+    /// The Nack body: the epoch, then each missing sequence number.
+    pub fn request(&self) -> Vec<u8> {
+        let mut body = vec![self.epoch];
+        for seq in self.first..self.first + self.missed {
+            body.extend_from_slice(&seq.to_be_bytes());
+        }
+        body
+    }
+
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Takes `pkt` if it is a missing one or the next in order; otherwise
+    /// hands it back.
+    pub fn fill(&mut self, pos: Position, pkt: Vec<u8>) -> Result<(), Vec<u8>> {
+        if pos.epoch != self.epoch || pos.seq < self.first {
+            return Err(pkt);
+        }
+        let at = (pos.seq - self.first) as usize;
+        let next = self.slots.len();
+        match self.slots.get_mut(at) {
+            Some(slot) if slot.is_none() => *slot = Some(pkt),
+            None if at == next => self.slots.push(Some(pkt)),
+            _ => return Err(pkt),
+        }
+        Ok(())
+    }
+
+    /// Everything that was asked for came back.
+    pub fn complete(&self) -> bool {
+        self.slots.iter().all(Option::is_some)
+    }
+
+    /// Packets that did come back.
+    pub fn repaired(&self) -> usize {
+        self.slots[..self.missed as usize]
+            .iter()
+            .filter(|s| s.is_some())
+            .count()
+    }
+
+    /// What to play, in order, each with the number of packets still missing
+    /// right before it.
+    pub fn into_frames(self) -> Vec<(Vec<u8>, u32)> {
+        let mut missed = 0;
+        let mut out = Vec::with_capacity(self.slots.len());
+        for slot in self.slots {
+            match slot {
+                Some(pkt) => {
+                    out.push((pkt, missed));
+                    missed = 0;
+                }
+                None => missed += 1,
+            }
+        }
+        out
+    }
+}
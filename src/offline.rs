@@ -0,0 +1,320 @@
+// ─── Offline processing ────────────────────────────────────────────────────────
+// Runs a WAV file through a call's audio path with no devices and no network:
+// the capture chain exactly as a call runs it (down‑mix, resampling, APM,
+// effects, encoder), a simulated lossy link, then the receive side (FEC,
+// concealment, EQ, jitter buffer) into an output WAV. Nothing is paced by a
+// clock and the losses come from a seeded generator, so the same file and
+// settings always give the same output – for comparing DSP and codec
+// settings, and for regression tests.
+//
+// The playout side stands in for the output callback: one frame is mixed
+// per frame sent, from the same kind of lane a call plays from (see
+// `mixer`), starting once the jitter target is queued. A loss longer than
+// concealment covers drains the buffer and it rebuffers, as in a call; the
+// output is therefore delayed by the jitter target and any rebuffering.
+
+use crate::packet::SilenceReason;
+use crate::{
+    capture_chain, codec, effects, frame_samples, frames, health, jitter, latency, mixer,
+    play_in_order, record, send_queue, source, surround, voice_processor, Decode, Format, Outbound,
+    Pipeline, Playout, MAX_FRAME_MS, SAMPLE_RATE,
+};
+use anyhow::{Context, Result};
+use parking_lot::Mutex as PLMutex;
+use serde::Serialize;
+use source::AudioSource;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::info;
+
+pub struct ProcessOptions {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    /// As `devices::AudioOptions::send_channels`; the output has as many.
+    pub send_channels: u8,
+    pub frame_ms: u8,
+    pub encoder: codec::EncoderOptions,
+    /// The playout target; adaptation needs real time and is off.
+    pub jitter: jitter::JitterOptions,
+    pub effects: Arc<effects::Controls>,
+    pub loss: LossModel,
+}
+
+/// Packet loss on the simulated link. With `burst` above 1 losses come in
+/// runs (a two‑state Gilbert model) averaging that many packets.
+#[derive(Clone, Copy, Debug)]
+pub struct LossModel {
+    pub percent: f32,
+    pub burst: f32,
+    pub seed: u64,
+}
+
+impl Default for LossModel {
+    fn default() -> Self {
+        Self {
+            percent: 0.0,
+            burst: 1.0,
+            seed: 1,
+        }
+    }
+}
+
+/// What happened to the file on the way through.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ProcessReport {
+    /// Frames encoded.
+    pub frames: u64,
+    /// Media packets and silence markers sent.
+    pub packets: u64,
+    pub lost: u64,
+    pub concealed: u64,
+    pub fec_recovered: u64,
+    /// Times the jitter buffer ran dry mid‑speech and rebuffered.
+    pub underruns: u64,
+    /// Frames sent as DTX silence markers instead of media.
+    pub silent_frames: u64,
+    /// Average size of the media packets, as a bitrate.
+    pub bitrate_kbps: f32,
+    pub duration_secs: f32,
+}
+
+/// Processes `opts.input` into `opts.output`; see the module comment.
+pub fn process(opts: &ProcessOptions) -> Result<ProcessReport> {
+    opts.jitter.validate()?;
+    let mut input = source::WavFile::open(&opts.input, false)?;
+    let in_channels = input.channels();
+    let mut enc = codec::Encoder::new(SAMPLE_RATE, opts.send_channels)?;
+    enc.apply(&opts.encoder)?;
+    let layout = enc.layout().clone();
+    let channels = layout.channels as usize;
+
+    let latency = Arc::new(latency::Tracer::default());
+    let (net_tx, mut outbox, _) = send_queue::channel(Arc::clone(&latency));
+    let format = Arc::new(Format::new(opts.frame_ms, latency));
+    let frame_ms = opts.frame_ms as usize;
+    let backlog = frame_samples((opts.jitter.max_ms as usize).max(3 * MAX_FRAME_MS));
+    let mixer = Arc::new(mixer::Mixer::default());
+    let target = frame_samples(opts.jitter.target_ms as usize);
+    let (lane, producer) = mixer.add(backlog * channels, target);
+    lane.channels.store(channels, Ordering::Relaxed);
+    lane.frame.store(frame_samples(frame_ms), Ordering::Relaxed);
+    let pipeline = Pipeline {
+        ap: voice_processor(channels, opts.effects.apm())?,
+        enc: Arc::new(PLMutex::new(enc)),
+        net_tx,
+        playback: Arc::clone(&mixer),
+        format: Arc::clone(&format),
+        effects: Arc::clone(&opts.effects),
+        record: None,
+        health: Arc::new(health::Health::new(false)),
+        bitrate: None,
+        idle_after: None,
+        tiers: None,
+        mirror: None,
+        extra_input: None,
+        apm_dump: None,
+        inject: Arc::new(frames::Injector::new(channels)),
+    };
+    *format.apm.lock() = pipeline.ap.is_some().then(|| opts.effects.apm());
+    let mut capture = capture_chain(&pipeline, input.sample_rate(), in_channels);
+    let mut playout = Playout {
+        dec: codec::Decoder::new(SAMPLE_RATE, &layout)?,
+        pcm_buf: vec![0f32; frame_samples(MAX_FRAME_MS) * codec::MAX_CHANNELS],
+        eq: effects::Equalizer::new(SAMPLE_RATE),
+        peer: None,
+        levels: Default::default(),
+        outputs: Default::default(),
+        listener: Default::default(),
+        talk: Arc::clone(&format.talk),
+        duck: effects::Ducker::new(SAMPLE_RATE),
+        effects: Arc::clone(&opts.effects),
+        record: None,
+        captions: None,
+        incoming: Arc::clone(&format.incoming),
+        producer,
+        backlog,
+        latency: Arc::clone(&format.latency),
+    };
+    let mut speaker = Speaker {
+        out: record::WavWriter::create(&opts.output, SAMPLE_RATE, channels as u16)
+            .with_context(|| format!("creating {}", opts.output.display()))?,
+        channels,
+        block: Vec::new(),
+        mixed: vec![0f32; channels],
+    };
+    info!(
+        "processing {} → {} ({} ms frames, {:.1}% loss)",
+        opts.input.display(),
+        opts.output.display(),
+        frame_ms,
+        opts.loss.percent
+    );
+
+    let frame_len = frame_samples(frame_ms);
+    let mut link = Link::new(opts.loss);
+    let mut tracker = jitter::Tracker::new(opts.jitter, frame_ms as u32);
+    let mut report = ProcessReport::default();
+    let (mut seq, mut media_bytes, mut media) = (0u32, 0u64, 0u64);
+    let mut held: Option<Vec<u8>> = None;
+    // Frames played since the last packet, for giving up on a held frame's
+    // successor as the decode task does.
+    let mut idle = 0;
+    let mut buf = vec![0f32; input.sample_rate() as usize / 100 * in_channels];
+    loop {
+        let frames = input.read(&mut buf);
+        if frames == 0 {
+            break;
+        }
+        let before = format.encodes.load(Ordering::Relaxed);
+        capture(&buf[..frames * in_channels]);
+        while let Some(msg) = outbox.try_recv() {
+            seq += 1;
+            report.packets += 1;
+            if let Outbound::Silence(SilenceReason::Silent) = msg {
+                report.silent_frames += 1;
+            }
+            if link.drops() {
+                report.lost += 1;
+                continue;
+            }
+            idle = 0;
+            let pos = jitter::Position {
+                epoch: 0,
+                seq,
+                at: Instant::now(),
+            };
+            match msg {
+                Outbound::Hold(_) => {
+                    tracker.arrived(pos, false);
+                }
+                Outbound::Silence(_) => {
+                    tracker.arrived(pos, false);
+                    if let Some(pkt) = held.take() {
+                        playout.play(Decode::Packet(&pkt), frame_len);
+                    }
+                    lane.silent.store(true, Ordering::Relaxed);
+                }
+                Outbound::Frame(pkt) => {
+                    media += 1;
+                    media_bytes += pkt.len() as u64;
+                    let jitter::Arrival::InOrder { missed } = tracker.arrived(pos, true) else {
+                        continue;
+                    };
+                    lane.silent.store(false, Ordering::Relaxed);
+                    play_in_order(&mut held, pkt.to_vec(), missed, opts.encoder.fec, |what| {
+                        match what {
+                            Decode::Conceal => report.concealed += 1,
+                            Decode::Fec(_) => report.fec_recovered += 1,
+                            Decode::Packet(_) => {}
+                        }
+                        playout.play(what, frame_len);
+                    });
+                }
+            }
+        }
+        let encoded = format.encodes.load(Ordering::Relaxed) - before;
+        for _ in 0..encoded {
+            idle += 1;
+            if idle > 2 {
+                if let Some(pkt) = held.take() {
+                    playout.play(Decode::Packet(&pkt), frame_len);
+                }
+            }
+            speaker.play(&mixer, &format, frame_len)?;
+        }
+        report.frames += encoded;
+    }
+    // Play out whatever is still queued.
+    if let Some(pkt) = held.take() {
+        playout.play(Decode::Packet(&pkt), frame_len);
+    }
+    lane.target.store(0, Ordering::Relaxed);
+    while lane.queued() > 0 {
+        speaker.play(&mixer, &format, frame_len)?;
+    }
+    speaker.out.finish()?;
+
+    report.underruns = lane.underruns.load(Ordering::Relaxed);
+    report.duration_secs = (report.frames * frame_ms as u64) as f32 / 1000.0;
+    if media > 0 {
+        report.bitrate_kbps = (media_bytes * 8) as f32 / (media * frame_ms as u64) as f32;
+    }
+    info!(
+        frames = report.frames,
+        lost = report.lost,
+        concealed = report.concealed,
+        fec_recovered = report.fec_recovered,
+        underruns = report.underruns,
+        "processed {:.1} s of audio",
+        report.duration_secs
+    );
+    Ok(report)
+}
+
+/// The output callback, writing to a file.
+struct Speaker {
+    out: record::WavWriter,
+    channels: usize,
+    block: Vec<f32>,
+    mixed: Vec<f32>,
+}
+
+impl Speaker {
+    fn play(&mut self, mixer: &mixer::Mixer, format: &Format, frame_len: usize) -> Result<()> {
+        self.block.resize(frame_len * self.channels, 0.0);
+        mixer.mix(&mut self.block, self.channels, Some(format));
+        let (vorbis, wav) = (
+            surround::vorbis(self.channels),
+            surround::wav(self.channels),
+        );
+        for frame in self.block.chunks_exact(self.channels) {
+            surround::remix(frame, vorbis, &mut self.mixed, wav);
+            self.out.write(self.mixed.iter().copied())?;
+        }
+        Ok(())
+    }
+}
+
+/// Decides which packets the simulated link loses.
+struct Link {
+    model: LossModel,
+    /// In a loss burst.
+    bad: bool,
+    rng: u64,
+}
+
+impl Link {
+    fn new(model: LossModel) -> Self {
+        Self {
+            model,
+            bad: false,
+            // xorshift64* can't start from zero.
+            rng: model.seed | 1,
+        }
+    }
+
+    fn drops(&mut self) -> bool {
+        let p = (self.model.percent / 100.0).clamp(0.0, 1.0) as f64;
+        if self.model.burst <= 1.0 || p >= 1.0 {
+            return self.uniform() < p;
+        }
+        // Leave a burst with 1/burst per packet; enter one often enough that
+        // the long‑run loss is `p`.
+        let leave = 1.0 / self.model.burst as f64;
+        let enter = p * leave / (1.0 - p);
+        self.bad = match self.bad {
+            true => self.uniform() >= leave,
+            false => self.uniform() < enter,
+        };
+        self.bad
+    }
+
+    fn uniform(&mut self) -> f64 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        (self.rng.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
@@ -0,0 +1,208 @@
+// ─── Wire format ───────────────────────────────────────────────────────────────
+// Every datagram starts with a one-byte packet kind:
+//   0x01 Hello  – 32-byte X25519 public key (see `crypto`), optionally
+//                 followed by the sender's stream parameters (frame size,
+//                 Opus layout, FEC; see `codec`); without them it's 20 ms mono
+//   0x02 Media  – key epoch (u8) + sequence (u32 BE) + sealed Opus frame
+//   0x03 Silence – same header as Media + sealed one-byte `SilenceReason`;
+//                 sent instead of media while muted or DTX-silent
+//   0x04 Ping    – same header + sealed sender timestamp (opaque, 8 bytes)
+//   0x05 Pong    – same header + the sealed timestamp of the Ping it answers
+//   0x06 Bye     – same header + nothing; the sender is going away
+//   0x07 Relay   – 16-byte pairing id, in the clear; asks a relay to forward
+//                 our packets to whoever else sends the same id (see `relay`),
+//                 optionally followed by a join token (see `acl`)
+//   0x08 Nack    – same header + sealed epoch (u8) and the sequence numbers
+//                 (u32 BE each) of media to send again (see `nack`)
+//   0x09 Rate    – same header + sealed u32 BE: the bits a second the receiver
+//                 estimates the path carries, 0 for no limit (see `bwe`)
+//   0x0A Hold    – same header + sealed one byte: 1 while the sender has us on
+//                 hold, 0 once it resumes (see `hold`)
+//   0x0B Moderate – same header + a sealed order from a multicast group's
+//                 host, signed with its host key (see `moderation`)
+//   0x0C Introduce – same header + the sender's sealed nickname and metadata
+//                 as JSON (see `signaling::Introduction`)
+//
+// The header of sealed packets doubles as the AEAD associated data, so it
+// cannot be altered in transit. A change in epoch marks a key rollover. Media
+// and the control kinds share one sequence space, so nonces never repeat
+// across kinds.
+
+use crate::codec::StreamParams;
+use crate::crypto::PUBLIC_KEY_LEN;
+use bytes::{BufMut, Bytes, BytesMut};
+
+const KIND_HELLO: u8 = 0x01;
+const KIND_MEDIA: u8 = 0x02;
+const KIND_SILENCE: u8 = 0x03;
+const KIND_PING: u8 = 0x04;
+const KIND_PONG: u8 = 0x05;
+const KIND_BYE: u8 = 0x06;
+const KIND_RELAY: u8 = 0x07;
+const KIND_NACK: u8 = 0x08;
+const KIND_RATE: u8 = 0x09;
+const KIND_HOLD: u8 = 0x0A;
+const KIND_MODERATE: u8 = 0x0B;
+const KIND_INTRODUCE: u8 = 0x0C;
+
+pub const RELAY_ID_LEN: usize = 16;
+
+pub const MEDIA_HEADER_LEN: usize = 6;
+/// Header plus AEAD tag on top of the Opus frame.
+pub const MEDIA_OVERHEAD: usize = MEDIA_HEADER_LEN + 16;
+
+/// Packets sealed under the media keys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sealed {
+    Media,
+    Silence,
+    /// Round‑trip time probes.
+    Ping,
+    Pong,
+    /// The sender is shutting down (sent when it crashes).
+    Bye,
+    /// Media to send again.
+    Nack,
+    /// The receiver's bandwidth estimate.
+    Rate,
+    /// The sender put us on hold, or resumed.
+    Hold,
+    /// A group host's order to mute or remove a member.
+    Moderate,
+    /// The sender's nickname and metadata.
+    Introduce,
+}
+
+impl Sealed {
+    fn kind(self) -> u8 {
+        match self {
+            Sealed::Media => KIND_MEDIA,
+            Sealed::Silence => KIND_SILENCE,
+            Sealed::Ping => KIND_PING,
+            Sealed::Pong => KIND_PONG,
+            Sealed::Bye => KIND_BYE,
+            Sealed::Nack => KIND_NACK,
+            Sealed::Rate => KIND_RATE,
+            Sealed::Hold => KIND_HOLD,
+            Sealed::Moderate => KIND_MODERATE,
+            Sealed::Introduce => KIND_INTRODUCE,
+        }
+    }
+}
+
+/// Why the sender stopped sending media.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SilenceReason {
+    Muted = 0,
+    /// DTX: the encoder found nothing worth sending.
+    Silent = 1,
+}
+
+impl SilenceReason {
+    pub fn from_byte(b: u8) -> Option<Self> {
+        match b {
+            0 => Some(SilenceReason::Muted),
+            1 => Some(SilenceReason::Silent),
+            _ => None,
+        }
+    }
+}
+
+pub enum Packet<'a> {
+    Hello {
+        pub_key: [u8; PUBLIC_KEY_LEN],
+        params: StreamParams,
+    },
+    Sealed {
+        kind: Sealed,
+        epoch: u8,
+        seq: u32,
+        payload: &'a [u8],
+    },
+    /// Only ever sent to a relay; a peer ignores one. `token` is empty
+    /// unless the relay wants one.
+    Relay {
+        id: [u8; RELAY_ID_LEN],
+        token: &'a [u8],
+    },
+}
+
+impl<'a> Packet<'a> {
+    pub fn sealed_header(kind: Sealed, epoch: u8, seq: u32) -> [u8; MEDIA_HEADER_LEN] {
+        let mut hdr = [0u8; MEDIA_HEADER_LEN];
+        hdr[0] = kind.kind();
+        hdr[1] = epoch;
+        hdr[2..6].copy_from_slice(&seq.to_be_bytes());
+        hdr
+    }
+
+    pub fn encode(&self) -> Bytes {
+        match self {
+            Packet::Hello { pub_key, params } => {
+                let mut out = BytesMut::with_capacity(1 + PUBLIC_KEY_LEN);
+                out.put_u8(KIND_HELLO);
+                out.extend_from_slice(pub_key);
+                out.extend_from_slice(&params.to_bytes());
+                out.freeze()
+            }
+            Packet::Sealed {
+                kind,
+                epoch,
+                seq,
+                payload,
+            } => {
+                let mut out = BytesMut::with_capacity(MEDIA_HEADER_LEN + payload.len());
+                out.extend_from_slice(&Self::sealed_header(*kind, *epoch, *seq));
+                out.extend_from_slice(payload);
+                out.freeze()
+            }
+            Packet::Relay { id, token } => {
+                let mut out = BytesMut::with_capacity(1 + RELAY_ID_LEN + token.len());
+                out.put_u8(KIND_RELAY);
+                out.extend_from_slice(id);
+                out.extend_from_slice(token);
+                out.freeze()
+            }
+        }
+    }
+
+    /// Returns `None` for truncated or unknown packets.
+    pub fn parse(buf: &'a [u8]) -> Option<Self> {
+        let (&kind, body) = buf.split_first()?;
+        match kind {
+            KIND_HELLO => {
+                let pub_key = body.get(..PUBLIC_KEY_LEN)?.try_into().ok()?;
+                let params = StreamParams::from_bytes(&body[PUBLIC_KEY_LEN..])?;
+                Some(Packet::Hello { pub_key, params })
+            }
+            KIND_MEDIA | KIND_SILENCE | KIND_PING | KIND_PONG | KIND_BYE | KIND_NACK
+            | KIND_RATE | KIND_HOLD | KIND_MODERATE | KIND_INTRODUCE => {
+                if body.len() < MEDIA_HEADER_LEN - 1 {
+                    return None;
+                }
+                Some(Packet::Sealed {
+                    kind: match kind {
+                        KIND_MEDIA => Sealed::Media,
+                        KIND_SILENCE => Sealed::Silence,
+                        KIND_PING => Sealed::Ping,
+                        KIND_PONG => Sealed::Pong,
+                        KIND_BYE => Sealed::Bye,
+                        KIND_NACK => Sealed::Nack,
+                        KIND_RATE => Sealed::Rate,
+                        KIND_HOLD => Sealed::Hold,
+                        KIND_MODERATE => Sealed::Moderate,
+                        _ => Sealed::Introduce,
+                    },
+                    epoch: body[0],
+                    seq: u32::from_be_bytes([body[1], body[2], body[3], body[4]]),
+                    payload: &body[MEDIA_HEADER_LEN - 1..],
+                })
+            }
+            KIND_RELAY => Some(Packet::Relay {
+                id: body.get(..RELAY_ID_LEN)?.try_into().ok()?,
+                token: &body[RELAY_ID_LEN..],
+            }),
+            _ => None,
+        }
+    }
+}
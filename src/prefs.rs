@@ -0,0 +1,153 @@
+// ─── Device preferences ────────────────────────────────────────────────────────
+// The input and output devices chosen with `--input-device`/`--output-device`
+// are remembered in `prefs.json` in the platform config directory
+// (`$XDG_CONFIG_HOME/voice-chat`, `~/Library/Application Support/voice-chat`,
+// `%APPDATA%\voice-chat`) and used again when the next call names none.
+//
+// cpal has no persistent device IDs, so a device is remembered by the audio
+// host and its exact name, which is what stays put across reboots and
+// replugging; a substring given on the command line is stored as the name it
+// matched. If the remembered device isn't there next time, the call uses the
+// default instead and says so with a `STATUS: device_fallback` line; the
+// preference is kept for when the device comes back.
+
+use crate::devices::{self, AudioOptions, Direction};
+use anyhow::{Context, Result};
+use cpal::traits::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Remembered devices, by cpal host name.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DevicePrefs {
+    #[serde(default)]
+    hosts: BTreeMap<String, HostDevices>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+struct HostDevices {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    input: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    output: Option<String>,
+}
+
+/// `prefs.json` in the platform config directory, if there is a home to
+/// put it in.
+pub fn default_path() -> Option<PathBuf> {
+    let dir = if cfg!(windows) {
+        PathBuf::from(std::env::var_os("APPDATA")?)
+    } else if cfg!(target_os = "macos") {
+        PathBuf::from(std::env::var_os("HOME")?).join("Library/Application Support")
+    } else {
+        match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        }
+    };
+    Some(dir.join("voice-chat").join("prefs.json"))
+}
+
+impl DevicePrefs {
+    /// Reads `path`; a missing file is no preferences.
+    pub fn load(path: &Path) -> Result<Self> {
+        match std::fs::read(path) {
+            Ok(json) => serde_json::from_slice(&json)
+                .with_context(|| format!("can't parse {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("can't read {}", path.display())),
+        }
+    }
+
+    /// Writes to a temporary file beside `path` and renames it over, so a
+    /// crash never leaves half a file.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("can't create {}", dir.display()))?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("can't write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path).with_context(|| format!("can't write {}", path.display()))
+    }
+
+    /// Forgets the devices remembered for `host`.
+    pub fn forget(&mut self, host: &cpal::Host) {
+        self.hosts.remove(host.id().name());
+    }
+
+    /// Remembers the devices `opts` names, resolved to their exact names, and
+    /// fills in the remembered ones where it names none. Leaves `opts` alone
+    /// for the directions not in use: no `capture`, no `opts.playback`, or a
+    /// virtual cable instead of the output, and hosts without named devices.
+    /// Returns whether anything new was remembered.
+    pub fn apply(&mut self, host: &cpal::Host, opts: &mut AudioOptions, capture: bool) -> bool {
+        if !devices::by_name(host, opts) {
+            return false;
+        }
+        let host_name = host.id().name();
+        let mut remembered = self.hosts.get(host_name).cloned().unwrap_or_default();
+        if capture {
+            resolve(
+                host,
+                Direction::Input,
+                &mut opts.input_device,
+                &mut remembered.input,
+            );
+        }
+        if opts.playback && opts.virtual_cable != Some(devices::CableRouting::Instead) {
+            resolve(
+                host,
+                Direction::Output,
+                &mut opts.output_device,
+                &mut remembered.output,
+            );
+        }
+        let changed = self.hosts.get(host_name) != Some(&remembered);
+        if changed {
+            self.hosts.insert(host_name.into(), remembered);
+        }
+        changed
+    }
+}
+
+fn resolve(
+    host: &cpal::Host,
+    dir: Direction,
+    chosen: &mut Option<String>,
+    remembered: &mut Option<String>,
+) {
+    let kind = match dir {
+        Direction::Input => "input",
+        Direction::Output => "output",
+    };
+    let devices = || -> Vec<cpal::Device> {
+        let devices = match dir {
+            Direction::Input => host.input_devices().map(|d| d.collect()),
+            Direction::Output => host.output_devices().map(|d| d.collect()),
+        };
+        devices.unwrap_or_default()
+    };
+    match (chosen.as_deref(), remembered.as_deref()) {
+        // Opening it reports a name that matches nothing; remember only
+        // what was found.
+        (Some(name), _) => {
+            let found = devices::find_device(devices().into_iter(), name, kind);
+            if let Some(exact) = found.ok().and_then(|d| d.name().ok()) {
+                *remembered = Some(exact);
+            }
+        }
+        (None, Some(name)) if devices().iter().any(|d| d.name().is_ok_and(|n| n == name)) => {
+            info!("using the remembered {kind} device {name:?}");
+            *chosen = Some(name.to_owned());
+        }
+        (None, Some(name)) => {
+            warn!("the remembered {kind} device {name:?} isn't there; using the default");
+            info!("STATUS: device_fallback {kind} {name}");
+        }
+        (None, None) => {}
+    }
+}
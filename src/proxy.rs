@@ -0,0 +1,202 @@
+// ─── SOCKS5 proxy ──────────────────────────────────────────────────────────────
+// `--proxy socks5h://[user:pass@]host:port` sends signaling through a SOCKS5
+// proxy – Tor's is socks5h://127.0.0.1:9050 – so the signaling server sees the
+// proxy's address instead of ours. HTTP signaling and Matrix go through
+// reqwest's own SOCKS support; the MQTT client connects with `connect` below
+// (RFC 1928, with RFC 1929 username/password). `socks5h` has the proxy resolve
+// host names, which Tor needs; plain `socks5` resolves them locally.
+//
+// Media is UDP, which Tor can't carry, and still goes direct: the peer sees
+// our address, and so does anyone who reads our join record, since the
+// addresses we announce are the ones the media uses. There is no TCP media
+// path to proxy.
+
+use anyhow::{bail, Context, Result};
+use reqwest::Url;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_PORT: u16 = 1080;
+
+const VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+const USER_PASS: u8 = 2;
+const CMD_CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+#[derive(Clone, Debug)]
+pub struct Proxy {
+    url: Url,
+    host: String,
+    port: u16,
+    auth: Option<(String, String)>,
+    /// Let the proxy resolve host names (`socks5h`).
+    remote_dns: bool,
+}
+
+impl FromStr for Proxy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let url = Url::parse(s).context("invalid proxy URL")?;
+        let remote_dns = match url.scheme() {
+            "socks5h" => true,
+            "socks5" => false,
+            other => bail!("unsupported proxy scheme {other} (use socks5h:// or socks5://)"),
+        };
+        let host = url
+            .host_str()
+            .context("no host in the proxy URL")?
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_owned();
+        let auth = match (url.username(), url.password()) {
+            ("", None) => None,
+            (user, pass) => Some((user.to_owned(), pass.unwrap_or_default().to_owned())),
+        };
+        if auth
+            .as_ref()
+            .is_some_and(|(user, pass)| user.len() > 255 || pass.len() > 255)
+        {
+            bail!("SOCKS5 usernames and passwords are at most 255 bytes");
+        }
+        Ok(Self {
+            port: url.port().unwrap_or(DEFAULT_PORT),
+            url,
+            host,
+            auth,
+            remote_dns,
+        })
+    }
+}
+
+impl std::fmt::Display for Proxy {
+    /// Without the credentials, for logs.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}://{}:{}", self.url.scheme(), self.host, self.port)
+    }
+}
+
+impl Proxy {
+    /// A TCP stream to `host:port` through the proxy.
+    pub(crate) async fn connect(&self, host: &str, port: u16) -> Result<TcpStream> {
+        let mut tcp = tokio::time::timeout(
+            CONNECT_TIMEOUT,
+            TcpStream::connect((self.host.as_str(), self.port)),
+        )
+        .await
+        .context("timed out connecting to the proxy")?
+        .with_context(|| format!("connecting to the proxy at {self}"))?;
+        tokio::time::timeout(CONNECT_TIMEOUT, self.handshake(&mut tcp, host, port))
+            .await
+            .context("the proxy didn't answer in time")??;
+        tcp.set_nodelay(true)?;
+        Ok(tcp)
+    }
+
+    async fn handshake(&self, tcp: &mut TcpStream, host: &str, port: u16) -> Result<()> {
+        let method = if self.auth.is_some() {
+            USER_PASS
+        } else {
+            NO_AUTH
+        };
+        tcp.write_all(&[VERSION, 1, method]).await?;
+        let mut reply = [0u8; 2];
+        tcp.read_exact(&mut reply).await?;
+        if reply[0] != VERSION {
+            bail!("{self} is not a SOCKS5 proxy");
+        }
+        if reply[1] != method {
+            bail!("the proxy accepts none of our authentication methods");
+        }
+        if let Some((user, pass)) = &self.auth {
+            let mut req = vec![1, user.len() as u8];
+            req.extend_from_slice(user.as_bytes());
+            req.push(pass.len() as u8);
+            req.extend_from_slice(pass.as_bytes());
+            tcp.write_all(&req).await?;
+            tcp.read_exact(&mut reply).await?;
+            if reply[1] != 0 {
+                bail!("the proxy rejected our username and password");
+            }
+        }
+
+        let mut req = vec![VERSION, CMD_CONNECT, 0];
+        let ip = match host.parse::<IpAddr>() {
+            Ok(ip) => Some(ip),
+            Err(_) if self.remote_dns => None,
+            Err(_) => {
+                let addr = tokio::net::lookup_host((host, port))
+                    .await?
+                    .next()
+                    .with_context(|| format!("{host} has no address"))?;
+                Some(addr.ip())
+            }
+        };
+        match ip {
+            Some(IpAddr::V4(ip)) => {
+                req.push(ATYP_IPV4);
+                req.extend_from_slice(&ip.octets());
+            }
+            Some(IpAddr::V6(ip)) => {
+                req.push(ATYP_IPV6);
+                req.extend_from_slice(&ip.octets());
+            }
+            None => {
+                let name = u8::try_from(host.len()).context("host name too long")?;
+                req.extend_from_slice(&[ATYP_DOMAIN, name]);
+                req.extend_from_slice(host.as_bytes());
+            }
+        }
+        req.extend_from_slice(&port.to_be_bytes());
+        tcp.write_all(&req).await?;
+
+        // VER REP RSV ATYP, then the address the proxy bound, which we skip.
+        let mut head = [0u8; 4];
+        tcp.read_exact(&mut head).await?;
+        if head[1] != 0 {
+            bail!(
+                "the proxy couldn't reach {host}:{port}: {}",
+                reply_error(head[1])
+            );
+        }
+        let bound = match head[3] {
+            ATYP_IPV4 => 4,
+            ATYP_IPV6 => 16,
+            ATYP_DOMAIN => tcp.read_u8().await? as usize,
+            other => bail!("the proxy answered with address type {other}"),
+        };
+        let mut skip = vec![0u8; bound + 2];
+        tcp.read_exact(&mut skip).await?;
+        Ok(())
+    }
+}
+
+/// An HTTP client going through `proxy`, if any.
+pub(crate) fn http_client(proxy: Option<&Proxy>) -> Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder();
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy.url.as_str())?);
+    }
+    Ok(builder.build()?)
+}
+
+fn reply_error(code: u8) -> &'static str {
+    match code {
+        1 => "general failure",
+        2 => "not allowed by its rules",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
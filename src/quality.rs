@@ -0,0 +1,120 @@
+// ─── Call quality ──────────────────────────────────────────────────────────────
+// Turns what the receive side sees into one number: an ITU‑T G.107 (E‑model)
+// style R factor and the MOS it maps to. Only the two impairments we can
+// measure are modelled – mouth‑to‑ear delay (half the round trip plus
+// packetization and the jitter buffer) and effective packet loss (lost, late or
+// dropped frames, minus those FEC rebuilt) – so it's an estimate, good for
+// comparing calls and spotting trouble rather than an absolute rating.
+//
+// Loss is averaged over the last few seconds, so the score follows the call
+// instead of its whole history.
+
+use crate::jitter::JitterStats;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+const INTERVAL: Duration = Duration::from_secs(1);
+/// Weight of the newest second in the loss average (≈ 5 s window).
+const LOSS_SMOOTHING: f32 = 0.2;
+/// Opus' equipment impairment is close to zero at voice bitrates; its
+/// robustness to random loss is in the range G.113 gives codecs with PLC.
+const IE: f32 = 0.0;
+const BPL: f32 = 20.0;
+/// Encoder lookahead.
+const CODEC_DELAY_MS: f32 = 6.5;
+
+/// Receive‑side quality, refreshed about once a second.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct QualityStats {
+    /// Frames filled in by loss concealment.
+    pub concealed: u64,
+    /// Lost frames rebuilt from the next packet's FEC.
+    pub fec_recovered: u64,
+    /// Frames not played: late, or no room in the jitter buffer.
+    pub dropped: u64,
+    /// `None` until the first Pong.
+    pub rtt_ms: Option<f32>,
+    /// Effective loss over the last few seconds.
+    pub loss_percent: f32,
+    pub one_way_delay_ms: f32,
+    pub r_factor: f32,
+    /// 1 (bad) … 4.5 (as good as narrow‑to‑wideband VoIP gets in this model).
+    pub mos: f32,
+}
+
+pub(crate) struct Meter {
+    stats: QualityStats,
+    last: Instant,
+    /// Counters at the last tick: (expected, bad).
+    prev: (u64, u64),
+}
+
+impl Default for Meter {
+    fn default() -> Self {
+        Self {
+            stats: QualityStats {
+                r_factor: r_factor(0.0, 0.0),
+                mos: mos(r_factor(0.0, 0.0)),
+                ..Default::default()
+            },
+            last: Instant::now(),
+            prev: (0, 0),
+        }
+    }
+}
+
+impl Meter {
+    pub fn concealed(&mut self) {
+        self.stats.concealed += 1;
+    }
+
+    pub fn fec_recovered(&mut self) {
+        self.stats.fec_recovered += 1;
+    }
+
+    pub fn rtt(&mut self, rtt: Duration) {
+        self.stats.rtt_ms = Some(rtt.as_secs_f32() * 1000.0);
+    }
+
+    /// Recomputes the score at most once per `INTERVAL`; `buffer_ms` is
+    /// everything the receive side adds on top of the network (frame,
+    /// jitter buffer, FEC hold‑back).
+    pub fn tick(&mut self, jitter: &JitterStats, buffer_ms: f32) -> Option<&QualityStats> {
+        if self.last.elapsed() < INTERVAL {
+            return None;
+        }
+        self.last = Instant::now();
+        self.stats.dropped = jitter.late + jitter.early;
+        let expected = jitter.received + jitter.lost;
+        let bad =
+            (jitter.lost + jitter.late + jitter.early).saturating_sub(self.stats.fec_recovered);
+        let (d_expected, d_bad) = (expected - self.prev.0, bad.saturating_sub(self.prev.1));
+        self.prev = (expected, bad);
+        if d_expected > 0 {
+            let loss = (d_bad as f32 / d_expected as f32 * 100.0).min(100.0);
+            self.stats.loss_percent += LOSS_SMOOTHING * (loss - self.stats.loss_percent);
+        }
+        let network = self.stats.rtt_ms.unwrap_or(0.0) / 2.0;
+        self.stats.one_way_delay_ms = network + buffer_ms + CODEC_DELAY_MS;
+        self.stats.r_factor = r_factor(self.stats.one_way_delay_ms, self.stats.loss_percent);
+        self.stats.mos = mos(self.stats.r_factor);
+        Some(&self.stats)
+    }
+}
+
+/// Simplified E‑model: default basic signal‑to‑noise (93.2) less delay and
+/// loss impairments.
+fn r_factor(delay_ms: f32, loss_percent: f32) -> f32 {
+    let id = 0.024 * delay_ms + 0.11 * (delay_ms - 177.3).max(0.0);
+    let ie_eff = IE + (95.0 - IE) * loss_percent / (loss_percent + BPL);
+    (93.2 - id - ie_eff).clamp(0.0, 100.0)
+}
+
+/// G.107 R → MOS mapping.
+fn mos(r: f32) -> f32 {
+    match r {
+        r if r <= 0.0 => 1.0,
+        r if r >= 100.0 => 4.5,
+        r => 1.0 + 0.035 * r + r * (r - 60.0) * (100.0 - r) * 7e-6,
+    }
+}
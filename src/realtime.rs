@@ -0,0 +1,120 @@
+// ─── Realtime deadlines ────────────────────────────────────────────────────────
+// Glitches the user would otherwise only hear:
+//   • a capture or playback callback that ran longer than the audio it
+//     handled (the device's buffer period), so the device over‑ or underran
+//     behind our back,
+//   • the playout ring running dry mid‑speech (`Format::underruns`),
+//   • a frame whose APM + effects + encode took longer than the frame lasts,
+//     so capture falls further behind with every one.
+//
+// The audio threads only bump counters; `watch_task` turns new counts into
+// rate‑limited warnings, and `VoiceSession::realtime_stats` returns the
+// totals.
+
+use crate::Format;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Weak;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// One warning per kind per this long; the counts in between are summed.
+const WARN_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Deadline misses since the call started.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct RealtimeStats {
+    /// Capture callbacks (or source‑thread blocks) that overran their
+    /// period.
+    pub capture_overruns: u64,
+    /// Playback callbacks that overran their period.
+    pub playback_overruns: u64,
+    /// The playout ring ran dry while the peer was talking.
+    pub underruns: u64,
+    /// Frames that took longer to process and encode than they last.
+    pub encoder_late: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct Counters {
+    capture_overruns: AtomicU64,
+    playback_overruns: AtomicU64,
+    encoder_late: AtomicU64,
+}
+
+impl Counters {
+    /// A capture callback for `frames` frames at `rate` began at `started`.
+    pub fn capture_done(&self, started: Instant, frames: usize, rate: u32) {
+        if overran(started, frames, rate) {
+            self.capture_overruns.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Like `capture_done`, for the playback callback.
+    pub fn playback_done(&self, started: Instant, frames: usize, rate: u32) {
+        if overran(started, frames, rate) {
+            self.playback_overruns.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// The source thread woke up a whole block late.
+    pub fn capture_late(&self) {
+        self.capture_overruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Processing and encoding one frame took `took`.
+    pub fn encoded(&self, took: Duration, frame_ms: usize) {
+        if took > Duration::from_millis(frame_ms as u64) {
+            self.encoder_late.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn overran(started: Instant, frames: usize, rate: u32) -> bool {
+    rate > 0 && started.elapsed().as_secs_f64() > frames as f64 / rate as f64
+}
+
+pub(crate) fn snapshot(format: &Format) -> RealtimeStats {
+    let c = &format.realtime;
+    RealtimeStats {
+        capture_overruns: c.capture_overruns.load(Ordering::Relaxed),
+        playback_overruns: c.playback_overruns.load(Ordering::Relaxed),
+        underruns: format.underruns.load(Ordering::Relaxed),
+        encoder_late: c.encoder_late.load(Ordering::Relaxed),
+    }
+}
+
+/// Warns about new deadline misses until the session ends.
+pub(crate) async fn watch_task(format: Weak<Format>) {
+    const KINDS: [&str; 4] = [
+        "capture callback overran its period",
+        "playback callback overran its period",
+        "playout ran dry mid‑speech",
+        "encoding took longer than the frame",
+    ];
+    let mut tick = tokio::time::interval(CHECK_INTERVAL);
+    // Per kind: the count at the last warning, and when that was.
+    let mut warned = [(0u64, None::<Instant>); 4];
+    loop {
+        tick.tick().await;
+        let Some(format) = format.upgrade() else {
+            return;
+        };
+        let s = snapshot(&format);
+        let counts = [
+            s.capture_overruns,
+            s.playback_overruns,
+            s.underruns,
+            s.encoder_late,
+        ];
+        for ((count, what), (seen, at)) in counts.into_iter().zip(KINDS).zip(&mut warned) {
+            if count == *seen || at.is_some_and(|t| t.elapsed() < WARN_INTERVAL) {
+                continue;
+            }
+            warn!("{what} {} time(s); audio may glitch", count - *seen);
+            *seen = count;
+            *at = Some(Instant::now());
+        }
+    }
+}
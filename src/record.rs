@@ -0,0 +1,683 @@
+// ─── Call recording ────────────────────────────────────────────────────────────
+// Writes the call as one mono 48 kHz WAV – what we sent mixed with what we
+// heard – plus a JSON sidecar listing who was speaking when. The same mix
+// also feeds the instant‑replay buffer, from which `save_clip` dumps the last
+// few seconds on request. The audio callbacks only hand frames to a `Tap`;
+// placing, mixing and disk I/O happen on a thread of their own.
+//
+// Capture and playback run on different clocks and both go quiet for long
+// stretches (mute, DTX), so frames are placed by arrival time: a source that
+// keeps up is appended back to back, one that fell more than `RESYNC_MS`
+// behind the wall clock jumps forward and leaves silence in between.
+//
+// With a `VoiceGate` only the stretches around speech reach the file, so a
+// long open‑mic session records as its conversations. The mix is already
+// held back `HOLD_MS` for late frames, which is what lets the pre‑roll reach
+// back before the first loud frame. The timeline then also lists the clips
+// kept, each with where it sits in the call and in the file.
+//
+// With a passphrase every file – recording, timeline and clips – is sealed
+// (see `crypto::FileSealer`) and gets a `.enc` suffix; `decrypt` opens them.
+// A sealed WAV can't be patched once written, so its header's sizes are
+// filled in when it is decrypted.
+
+use crate::crypto;
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+const RESYNC_MS: u64 = 100;
+/// Mixed audio is held back this long for late frames before it's written.
+const HOLD_MS: u64 = 1000;
+/// Frames louder than this (RMS, about −40 dBFS) count as speech.
+const SPEECH_RMS: f32 = 0.01;
+/// Quiet time that ends a speech segment.
+const HANGOVER_MS: u64 = 500;
+
+/// Record only while someone speaks.
+#[derive(Clone, Copy, Debug)]
+pub struct VoiceGate {
+    /// Kept before speech starts; at most `HOLD_MS`.
+    pub pre_roll_ms: u32,
+    /// Kept after it ends.
+    pub post_roll_ms: u32,
+}
+
+impl Default for VoiceGate {
+    fn default() -> Self {
+        Self {
+            pre_roll_ms: 300,
+            post_roll_ms: 1000,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Speaker {
+    Local,
+    Peer,
+}
+
+impl Speaker {
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Sending half, cloned into the audio callbacks and the decode task.
+#[derive(Clone)]
+pub(crate) struct Tap {
+    tx: SyncSender<Msg>,
+    /// Which of the speaker's streams this is: senders each play in a lane
+    /// of their own (see `mixer`), and each keeps its own place in the mix.
+    track: u32,
+}
+
+enum Msg {
+    Chunk(Chunk),
+    SaveClip {
+        path: PathBuf,
+        secs: u32,
+        done: SyncSender<Result<PathBuf>>,
+    },
+}
+
+struct Chunk {
+    speaker: Speaker,
+    track: u32,
+    at: Instant,
+    /// Mono.
+    pcm: Vec<f32>,
+}
+
+impl Tap {
+    /// The same tap, for another stream.
+    pub fn lane(&self, track: u32) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            track,
+        }
+    }
+
+    /// `pcm` is interleaved with `channels` channels; it's folded to mono.
+    /// Never blocks: frames are dropped if the writer falls behind.
+    pub fn push(&self, speaker: Speaker, pcm: &[f32], channels: usize) {
+        let pcm = pcm
+            .chunks(channels)
+            .map(|f| f.iter().sum::<f32>() / channels as f32)
+            .collect();
+        let _ = self.tx.try_send(Msg::Chunk(Chunk {
+            speaker,
+            track: self.track,
+            at: Instant::now(),
+            pcm,
+        }));
+    }
+}
+
+/// Finishes the files when dropped.
+pub(crate) struct Recorder {
+    tx: SyncSender<Msg>,
+    stop: Arc<AtomicBool>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Recorder {
+    /// With a `path`, records the whole call there, and on finish writes the
+    /// timeline to `path` with a `.json` extension. `replay_secs` sizes the
+    /// instant‑replay buffer (0 for none); `gate` leaves silence out of the
+    /// file, not the replay buffer. With a `passphrase` every file is sealed.
+    pub fn start(
+        path: Option<&Path>,
+        gate: Option<VoiceGate>,
+        passphrase: Option<String>,
+        replay_secs: u32,
+        sample_rate: u32,
+    ) -> Result<(Self, Tap)> {
+        if let Some(gate) = gate {
+            if gate.pre_roll_ms as u64 > HOLD_MS {
+                bail!("pre-roll can't be longer than {HOLD_MS} ms");
+            }
+        }
+        let sealing = passphrase.as_deref();
+        let wav = path
+            .map(|p| {
+                WavWriter::create_sealed(p, sample_rate, 1, sealing)
+                    .with_context(|| format!("creating {}", p.display()))
+            })
+            .transpose()?;
+        let timeline = path.map(|p| sealed_path(&p.with_extension("json"), sealing));
+        let (tx, rx) = sync_channel(256);
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = std::thread::Builder::new()
+            .name("voice-record".into())
+            .spawn({
+                let stop = Arc::clone(&stop);
+                move || {
+                    let mut mix = Mix::new(wav, gate, passphrase, replay_secs, sample_rate);
+                    run(&mut mix, &rx, &stop);
+                    match (mix.finish(timeline.as_deref()), timeline) {
+                        (Err(e), _) => error!("finishing recording: {e:#}"),
+                        (Ok(()), Some(t)) => info!("recording saved with timeline {}", t.display()),
+                        (Ok(()), None) => {}
+                    }
+                }
+            })?;
+        Ok((
+            Self {
+                tx: tx.clone(),
+                stop,
+                thread: Some(thread),
+            },
+            Tap { tx, track: 0 },
+        ))
+    }
+
+    /// Writes up to the last `secs` seconds of the call to `path`, or sealed
+    /// to `path.enc`; returns which.
+    pub fn save_clip(&self, path: &Path, secs: u32) -> Result<PathBuf> {
+        let (done, result) = sync_channel(1);
+        self.tx
+            .send(Msg::SaveClip {
+                path: path.to_path_buf(),
+                secs,
+                done,
+            })
+            .map_err(|_| anyhow!("recorder stopped"))?;
+        result.recv().map_err(|_| anyhow!("recorder stopped"))?
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+    }
+}
+
+fn run(mix: &mut Mix, rx: &Receiver<Msg>, stop: &AtomicBool) {
+    while !stop.load(Ordering::Relaxed) {
+        match rx.recv_timeout(Duration::from_millis(250)) {
+            Ok(Msg::Chunk(chunk)) => mix.add(chunk),
+            Ok(Msg::SaveClip { path, secs, done }) => {
+                let _ = done.send(mix.save_clip(&path, secs));
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        if let Err(e) = mix.flush(false) {
+            error!("writing recording: {e:#}");
+            return;
+        }
+    }
+    // Whatever was already queued still belongs in the file.
+    while let Ok(msg) = rx.try_recv() {
+        if let Msg::Chunk(chunk) = msg {
+            mix.add(chunk);
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Segment {
+    speaker: Speaker,
+    start_ms: u64,
+    end_ms: u64,
+}
+
+/// A stretch of the call the voice gate kept.
+#[derive(Serialize)]
+struct Clip {
+    start_ms: u64,
+    /// Where it starts in the file.
+    file_ms: u64,
+    len_ms: u64,
+}
+
+#[derive(Serialize)]
+struct Timeline {
+    audio: String,
+    sample_rate: u32,
+    /// In call time, like the clips.
+    segments: Vec<Segment>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clips: Option<Vec<Clip>>,
+}
+
+/// Which samples a voice‑gated recording keeps.
+struct Gate {
+    pre_roll: u64,
+    post_roll: u64,
+    /// Loud stretches not yet written, by start; merged where they overlap.
+    voiced: VecDeque<(u64, u64)>,
+    /// Samples written to the file so far.
+    file_len: u64,
+    /// In samples: call start, file start, length.
+    clips: Vec<(u64, u64, u64)>,
+    open: Option<(u64, u64, u64)>,
+}
+
+impl Gate {
+    fn new(gate: VoiceGate, rate: u64) -> Self {
+        Self {
+            pre_roll: rate * gate.pre_roll_ms as u64 / 1000,
+            post_roll: rate * gate.post_roll_ms as u64 / 1000,
+            voiced: VecDeque::new(),
+            file_len: 0,
+            clips: Vec::new(),
+            open: None,
+        }
+    }
+
+    fn voiced(&mut self, start: u64, end: u64) {
+        let mut i = self.voiced.partition_point(|&(a, _)| a <= start);
+        self.voiced.insert(i, (start, end));
+        // Merge with its neighbours, keeping the queue sorted and disjoint.
+        if i > 0 && self.voiced[i - 1].1 >= start {
+            i -= 1;
+        }
+        while i + 1 < self.voiced.len() && self.voiced[i + 1].0 <= self.voiced[i].1 {
+            let (_, d) = self.voiced.remove(i + 1).unwrap();
+            self.voiced[i].1 = self.voiced[i].1.max(d);
+        }
+    }
+
+    /// Whether sample `at` of the call goes in the file. Asked in order.
+    fn keeps(&mut self, at: u64) -> bool {
+        while let Some(&(_, end)) = self.voiced.front() {
+            if end + self.post_roll > at {
+                break;
+            }
+            self.voiced.pop_front();
+        }
+        let keep = matches!(self.voiced.front(), Some(&(start, _)) if start <= at + self.pre_roll);
+        match (&mut self.open, keep) {
+            (Some((_, _, len)), true) => *len += 1,
+            (None, true) => self.open = Some((at, self.file_len, 1)),
+            (Some(_), false) => self.close(),
+            (None, false) => {}
+        }
+        self.file_len += keep as u64;
+        keep
+    }
+
+    fn close(&mut self) {
+        self.clips.extend(self.open.take());
+    }
+}
+
+struct Mix {
+    wav: Option<WavWriter>,
+    gate: Option<Gate>,
+    /// Seals clips and the timeline too.
+    passphrase: Option<String>,
+    /// The most recent written samples, for instant replay.
+    replay: VecDeque<f32>,
+    replay_len: usize,
+    rate: u64,
+    start: Instant,
+    /// Samples already written; `pending[0]` is sample number `written`.
+    written: u64,
+    pending: VecDeque<f32>,
+    /// Where each stream's next frame goes.
+    cursors: HashMap<(Speaker, u32), u64>,
+    /// Open speech segment per speaker: first and last loud sample.
+    talking: [Option<(u64, u64)>; 2],
+    segments: Vec<Segment>,
+}
+
+impl Mix {
+    fn new(
+        wav: Option<WavWriter>,
+        gate: Option<VoiceGate>,
+        passphrase: Option<String>,
+        replay_secs: u32,
+        sample_rate: u32,
+    ) -> Self {
+        let replay_len = (replay_secs * sample_rate) as usize;
+        let gate = gate
+            .filter(|_| wav.is_some())
+            .map(|g| Gate::new(g, sample_rate as u64));
+        Self {
+            wav,
+            gate,
+            passphrase,
+            replay: VecDeque::with_capacity(replay_len),
+            replay_len,
+            rate: sample_rate as u64,
+            start: Instant::now(),
+            written: 0,
+            pending: VecDeque::new(),
+            cursors: HashMap::new(),
+            talking: [None; 2],
+            segments: Vec::new(),
+        }
+    }
+
+    fn samples(&self, ms: u64) -> u64 {
+        self.rate * ms / 1000
+    }
+
+    fn ms(&self, samples: u64) -> u64 {
+        samples * 1000 / self.rate
+    }
+
+    fn add(&mut self, chunk: Chunk) {
+        let i = chunk.speaker.index();
+        let len = chunk.pcm.len() as u64;
+        // The frame ended when it arrived.
+        let arrived = self.samples(chunk.at.duration_since(self.start).as_millis() as u64);
+        let at = arrived.saturating_sub(len);
+        let resync = self.samples(RESYNC_MS);
+        let cursor = self
+            .cursors
+            .entry((chunk.speaker, chunk.track))
+            .or_default();
+        if at > *cursor + resync {
+            *cursor = at;
+        }
+        let pos = *cursor;
+        *cursor += len;
+
+        // Too late for the part of the file already written.
+        let late = self.written.saturating_sub(pos);
+        if late < len {
+            let pcm = &chunk.pcm[late as usize..];
+            let off = (pos + late - self.written) as usize;
+            if off + pcm.len() > self.pending.len() {
+                self.pending.resize(off + pcm.len(), 0.0);
+            }
+            let pending = self.pending.make_contiguous();
+            crate::simd::mix(&mut pending[off..], pcm, 1.0);
+        }
+
+        let rms = (chunk.pcm.iter().map(|s| s * s).sum::<f32>() / len.max(1) as f32).sqrt();
+        if rms >= SPEECH_RMS {
+            if let Some(gate) = &mut self.gate {
+                gate.voiced(pos, pos + len);
+            }
+            let (end, hangover) = (pos + len, self.samples(HANGOVER_MS));
+            let done = match &mut self.talking[i] {
+                Some((_, last)) if pos <= *last + hangover => {
+                    *last = end.max(*last);
+                    None
+                }
+                open => open.replace((pos, end)),
+            };
+            if let Some(done) = done {
+                self.close(chunk.speaker, done);
+            }
+        }
+    }
+
+    fn close(&mut self, speaker: Speaker, (first, last): (u64, u64)) {
+        self.segments.push(Segment {
+            speaker,
+            start_ms: self.ms(first),
+            end_ms: self.ms(last),
+        });
+    }
+
+    /// Writes out everything older than `HOLD_MS`, or everything with `all`.
+    fn flush(&mut self, all: bool) -> Result<()> {
+        let now = self.samples(self.start.elapsed().as_millis() as u64);
+        let upto = match all {
+            true => self.written + self.pending.len() as u64,
+            false => now.saturating_sub(self.samples(HOLD_MS)),
+        };
+        if upto <= self.written {
+            return Ok(());
+        }
+        let n = (upto - self.written) as usize;
+        let have = n.min(self.pending.len());
+        let samples: Vec<f32> = self
+            .pending
+            .drain(..have)
+            .chain(std::iter::repeat(0.0))
+            .take(n)
+            .collect();
+        if let Some(wav) = &mut self.wav {
+            match &mut self.gate {
+                Some(gate) => {
+                    let from = self.written;
+                    let kept = samples.iter().enumerate();
+                    let kept = kept.filter(|&(n, _)| gate.keeps(from + n as u64));
+                    wav.write(kept.map(|(_, &s)| s))?;
+                }
+                None => wav.write(samples.iter().copied())?,
+            }
+        }
+        if self.replay_len > 0 {
+            let keep = samples.len().min(self.replay_len);
+            let excess = (self.replay.len() + keep).saturating_sub(self.replay_len);
+            self.replay.drain(..excess);
+            self.replay.extend(&samples[samples.len() - keep..]);
+        }
+        self.written = upto;
+        Ok(())
+    }
+
+    /// The replay buffer plus what's still held back for late frames.
+    fn save_clip(&self, path: &Path, secs: u32) -> Result<PathBuf> {
+        if self.replay_len == 0 {
+            return Err(anyhow!("instant replay is off"));
+        }
+        let n = (secs as u64 * self.rate) as usize;
+        let all = self.replay.iter().chain(&self.pending);
+        let skip = (self.replay.len() + self.pending.len()).saturating_sub(n);
+        let mut wav =
+            WavWriter::create_sealed(path, self.rate as u32, 1, self.passphrase.as_deref())
+                .with_context(|| format!("creating {}", path.display()))?;
+        wav.write(all.skip(skip).copied())?;
+        wav.finish()?;
+        Ok(wav.path)
+    }
+
+    fn finish(mut self, timeline: Option<&Path>) -> Result<()> {
+        let Some(timeline) = timeline else {
+            return Ok(());
+        };
+        self.flush(true)?;
+        let Some(mut wav) = self.wav.take() else {
+            return Ok(());
+        };
+        wav.finish()?;
+        for speaker in [Speaker::Local, Speaker::Peer] {
+            if let Some(open) = self.talking[speaker.index()].take() {
+                self.close(speaker, open);
+            }
+        }
+        self.segments.sort_by_key(|s| s.start_ms);
+        let rate = self.rate;
+        let clips = self.gate.take().map(|mut gate| {
+            gate.close();
+            let ms = |samples: u64| samples * 1000 / rate;
+            gate.clips
+                .into_iter()
+                .map(|(start, file, len)| Clip {
+                    start_ms: ms(start),
+                    file_ms: ms(file),
+                    len_ms: ms(len),
+                })
+                .collect()
+        });
+        let timeline_json = Timeline {
+            audio: wav.path.display().to_string(),
+            sample_rate: self.rate as u32,
+            segments: self.segments,
+            clips,
+        };
+        let file = BufWriter::new(
+            File::create(timeline).with_context(|| format!("creating {}", timeline.display()))?,
+        );
+        match &self.passphrase {
+            Some(passphrase) => {
+                let mut sealer = crypto::FileSealer::new(file, passphrase)?;
+                serde_json::to_writer_pretty(&mut sealer, &timeline_json)?;
+                sealer.finish()?;
+            }
+            None => {
+                let mut file = file;
+                serde_json::to_writer_pretty(&mut file, &timeline_json)?;
+                file.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where a file sealed with a passphrase goes instead of `path`.
+fn sealed_path(path: &Path, passphrase: Option<&str>) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    if passphrase.is_some() {
+        path.push(".enc");
+    }
+    path.into()
+}
+
+/// Opens a sealed recording, timeline or clip into `output`.
+pub fn decrypt(input: &Path, output: &Path, passphrase: &str) -> Result<()> {
+    let sealed = File::open(input).with_context(|| format!("opening {}", input.display()))?;
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(output)
+        .with_context(|| format!("creating {}", output.display()))?;
+    let mut out = BufWriter::new(file);
+    if let Err(e) = crypto::open_file(passphrase, BufReader::new(sealed), &mut out) {
+        drop(out);
+        let _ = std::fs::remove_file(output);
+        return Err(e);
+    }
+    let mut file = out.into_inner().map_err(|e| e.into_error())?;
+    patch_wav_sizes(&mut file)
+}
+
+/// Fills in the sizes of a WAV whose header was sealed before they were
+/// known; leaves anything else alone.
+fn patch_wav_sizes(file: &mut File) -> Result<()> {
+    let len = file.seek(SeekFrom::End(0))?;
+    let mut head = [0u8; 12];
+    file.seek(SeekFrom::Start(0))?;
+    if len < 44 || file.read_exact(&mut head).is_err() {
+        return Ok(());
+    }
+    if &head[..4] != b"RIFF" || &head[8..] != b"WAVE" {
+        return Ok(());
+    }
+    file.seek(SeekFrom::Start(4))?;
+    file.write_all(&((len - 8) as u32).to_le_bytes())?;
+    file.seek(SeekFrom::Start(40))?;
+    file.write_all(&((len - 44) as u32).to_le_bytes())?;
+    Ok(())
+}
+
+/// 16‑bit PCM WAV; the sizes in the header are patched on finish.
+pub(crate) struct WavWriter {
+    path: PathBuf,
+    out: Out,
+    data_len: u32,
+}
+
+enum Out {
+    Plain(BufWriter<File>),
+    /// `None` once finished. Boxed: the sealer keeps a whole chunk.
+    Sealed(Option<Box<crypto::FileSealer<BufWriter<File>>>>),
+}
+
+impl Write for Out {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Out::Plain(out) => out.write(buf),
+            Out::Sealed(Some(out)) => out.write(buf),
+            Out::Sealed(None) => Err(io::Error::other("recording already finished")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Out::Plain(out) => out.flush(),
+            Out::Sealed(Some(out)) => out.flush(),
+            Out::Sealed(None) => Ok(()),
+        }
+    }
+}
+
+impl WavWriter {
+    /// Samples are then written interleaved, in WAV channel order.
+    pub fn create(path: &Path, sample_rate: u32, channels: u16) -> Result<Self> {
+        Self::create_sealed(path, sample_rate, channels, None)
+    }
+
+    /// With a `passphrase`, sealed to `path.enc` instead.
+    pub fn create_sealed(
+        path: &Path,
+        sample_rate: u32,
+        channels: u16,
+        passphrase: Option<&str>,
+    ) -> Result<Self> {
+        let path = sealed_path(path, passphrase);
+        let file = BufWriter::new(File::create(&path)?);
+        let mut out = match passphrase {
+            Some(passphrase) => {
+                Out::Sealed(Some(Box::new(crypto::FileSealer::new(file, passphrase)?)))
+            }
+            None => Out::Plain(file),
+        };
+        let block = 2 * channels;
+        out.write_all(b"RIFF\0\0\0\0WAVEfmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?; // PCM
+        out.write_all(&channels.to_le_bytes())?;
+        out.write_all(&sample_rate.to_le_bytes())?;
+        out.write_all(&(sample_rate * block as u32).to_le_bytes())?; // bytes per second
+        out.write_all(&block.to_le_bytes())?; // block align
+        out.write_all(&16u16.to_le_bytes())?; // bits per sample
+        out.write_all(b"data\0\0\0\0")?;
+        Ok(Self {
+            path,
+            out,
+            data_len: 0,
+        })
+    }
+
+    pub fn write(&mut self, samples: impl Iterator<Item = f32>) -> Result<()> {
+        for s in samples {
+            let s = (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.out.write_all(&s.to_le_bytes())?;
+            self.data_len += 2;
+        }
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<()> {
+        match &mut self.out {
+            Out::Plain(out) => {
+                out.seek(SeekFrom::Start(4))?;
+                out.write_all(&(36 + self.data_len).to_le_bytes())?;
+                out.seek(SeekFrom::Start(40))?;
+                out.write_all(&self.data_len.to_le_bytes())?;
+                out.flush()?;
+            }
+            Out::Sealed(out) => {
+                if let Some(out) = out.take() {
+                    out.finish()?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
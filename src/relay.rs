@@ -0,0 +1,483 @@
+// ─── Relay ─────────────────────────────────────────────────────────────────────
+// When two peers can't reach each other directly (both behind symmetric NATs,
+// say) but both can reach a third machine, that machine can forward their
+// packets. Running `relay` is the consent: it binds a UDP port and forwards
+// for at most `max_pairs` pairs, each capped at `max_kbps`.
+//
+// Callers started with `--relay <addr>` first try the direct paths (see
+// `fallback`); if no key is agreed over those, they send Relay packets
+// carrying a pairing id to the relay and from then on treat it as the peer's
+// address.
+// The relay pairs the two senders of an id and forwards everything else from
+// one to the other untouched. Media stays sealed end to end – the relay holds
+// no keys, and a relay that meddles with the handshake changes the SAS.
+//
+// The pairing id is a hash of both sides' reflexive addresses, which each
+// side learns from the rendezvous (or `--peer`), so nothing new is exchanged;
+// a side that only listens for a caller knows no peer address and can't relay.
+//
+// Callers whose network blocks UDP reach the relay over TCP on the same
+// port instead (see `tunnel`), each packet behind its two-byte length as in
+// RFC 4571. Either end of a pair can be on either transport; pairing,
+// tokens and the bandwidth cap are the same.
+//
+// A relay with an `acl::Acl` only pairs callers whose Relay packets carry a
+// valid join token, both for the same room, and drops the pairings of
+// revoked members and of those whose token has expired (see `acl`).
+
+use crate::acl::{Acl, Grant};
+use crate::packet::{Packet, RELAY_ID_LEN};
+use crate::{capture, flood, socket};
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+
+/// How long a caller tries hole punching before relaying.
+pub(crate) const FALLBACK_AFTER: Duration = Duration::from_secs(5);
+/// A pairing nobody has sent anything on for this long is dropped.
+const IDLE: Duration = Duration::from_secs(30);
+/// How often the ends' grants are checked for expiry.
+const EXPIRY_SWEEP: Duration = Duration::from_secs(1);
+/// Largest packet either transport carries.
+const MAX_PACKET: usize = 2048;
+/// Packets queued for a TCP end before more are dropped.
+const TCP_QUEUE: usize = 64;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// A tunnel that couldn't connect waits this long before trying again.
+const RECONNECT_AFTER: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Debug)]
+pub struct RelayOptions {
+    pub port: u16,
+    pub socket: socket::SocketOptions,
+    /// Pairs forwarded at once.
+    pub max_pairs: usize,
+    /// Per pair, both directions together.
+    pub max_kbps: u32,
+    /// Pair only callers with a join token; `None` pairs anyone.
+    pub acl: Option<Arc<Acl>>,
+}
+
+/// The id both sides of a call send to the relay: the same whichever side
+/// computes it.
+pub(crate) fn pairing_id(a: SocketAddr, b: SocketAddr) -> [u8; RELAY_ID_LEN] {
+    let (lo, hi) = if a.to_string() <= b.to_string() {
+        (a, b)
+    } else {
+        (b, a)
+    };
+    let text = format!("audio-p2p relay {lo} {hi}");
+    let digest = ring::digest::digest(&ring::digest::SHA256, text.as_bytes());
+    digest.as_ref()[..RELAY_ID_LEN].try_into().unwrap()
+}
+
+/// One side of a pairing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum End {
+    Udp(SocketAddr),
+    Tcp(SocketAddr),
+}
+
+impl End {
+    fn addr(self) -> SocketAddr {
+        match self {
+            End::Udp(addr) | End::Tcp(addr) => addr,
+        }
+    }
+}
+
+impl fmt::Display for End {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            End::Udp(addr) => write!(f, "{addr}"),
+            End::Tcp(addr) => write!(f, "{addr} (tcp)"),
+        }
+    }
+}
+
+/// The TCP ends' outgoing queues.
+type Streams = Arc<Mutex<HashMap<SocketAddr, mpsc::Sender<Bytes>>>>;
+
+struct Pair {
+    ends: Vec<End>,
+    /// Bytes still allowed in the current second, and when it started.
+    budget: usize,
+    window: Instant,
+    dropped: u64,
+    seen: Instant,
+}
+
+/// Forwards for whoever asks, until the task is dropped.
+pub async fn run(opts: RelayOptions) -> Result<()> {
+    let ip = opts
+        .socket
+        .bind_addr
+        .unwrap_or(Ipv4Addr::UNSPECIFIED.into());
+    let local = SocketAddr::new(ip, opts.port);
+    let sock = socket::bind(local, &opts.socket)?;
+    let streams = Streams::default();
+    let (tcp_tx, mut tcp_rx) = mpsc::channel(TCP_QUEUE);
+    match TcpListener::bind(sock.local_addr()?).await {
+        Ok(listener) => {
+            tokio::spawn(accept(
+                listener,
+                Arc::clone(&streams),
+                tcp_tx,
+                opts.max_pairs * 2,
+            ));
+        }
+        Err(e) => warn!("relay: no TCP on {}: {e}; UDP only", sock.local_addr()?),
+    }
+    let mut guard = flood::Guard::new(opts.socket.limits);
+    let per_second = opts.max_kbps as usize * 1000 / 8;
+    info!(
+        "relaying on {} for up to {} pairs at {} kbps each",
+        sock.local_addr()?,
+        opts.max_pairs,
+        opts.max_kbps
+    );
+    crate::service::ready("relaying");
+    let mut pairs: HashMap<[u8; RELAY_ID_LEN], Pair> = HashMap::new();
+    let mut ends: HashMap<End, [u8; RELAY_ID_LEN]> = HashMap::new();
+    // With an ACL: who each end is, the revocations last looked at, and
+    // when the grants were last checked.
+    let mut grants: HashMap<End, Grant> = HashMap::new();
+    let mut generation = 0;
+    let mut swept = Instant::now();
+    let mut buf = [0u8; MAX_PACKET];
+    loop {
+        let (n, src) = tokio::select! {
+            r = sock.recv_from(&mut buf) => match r {
+                Ok((n, src)) => {
+                    capture::received(&sock, src, &buf[..n]);
+                    (n, End::Udp(src))
+                }
+                Err(e) => {
+                    error!("udp recv error: {e}");
+                    continue;
+                }
+            },
+            Some((src, pkt)) = tcp_rx.recv() => {
+                let pkt: Bytes = pkt;
+                buf[..pkt.len()].copy_from_slice(&pkt);
+                (pkt.len(), End::Tcp(src))
+            }
+        };
+        if !guard.admit(src.addr(), n) {
+            continue;
+        }
+        let now = Instant::now();
+        if let Some(acl) = opts
+            .acl
+            .as_deref()
+            .filter(|acl| acl.generation() != generation || now - swept >= EXPIRY_SWEEP)
+        {
+            generation = acl.generation();
+            swept = now;
+            grants.retain(|addr, grant| {
+                let why = if acl.is_revoked(&grant.member) {
+                    " was revoked"
+                } else if grant.expired() {
+                    "'s token expired"
+                } else {
+                    return true;
+                };
+                info!("relay: dropping {addr} ({}{why})", grant.member);
+                if let Some(pair) = ends.remove(addr).and_then(|id| pairs.get_mut(&id)) {
+                    pair.ends.retain(|e| e != addr);
+                }
+                false
+            });
+        }
+        if let Some(Packet::Relay { id, token }) = Packet::parse(&buf[..n]) {
+            pairs.retain(|id, pair| {
+                let live = now - pair.seen < IDLE;
+                if !live {
+                    info!("relay: pair {} went idle", hex(id));
+                    ends.retain(|_, end| end != id);
+                }
+                live
+            });
+            grants.retain(|addr, _| ends.contains_key(addr));
+            let grant = match &opts.acl {
+                Some(acl) => {
+                    let checked = std::str::from_utf8(token)
+                        .map_err(anyhow::Error::from)
+                        .and_then(|token| acl.check(token));
+                    match checked {
+                        Ok(grant) => Some(grant),
+                        Err(e) => {
+                            debug!("relay: refusing {src}: {e:#}");
+                            continue;
+                        }
+                    }
+                }
+                None => None,
+            };
+            let other_room = pairs.get(&id).and_then(|pair| {
+                let grant = grant.as_ref()?;
+                let other = pair.ends.iter().find(|&&e| e != src)?;
+                grants.get(other).filter(|g| g.room != grant.room)
+            });
+            if let Some(other) = other_room {
+                warn!("relay: refusing {src}, its peer joined room {}", other.room);
+                continue;
+            }
+            if ends.get(&src).is_some_and(|bound| *bound != id) {
+                // The same address asking for another pair leaves the old one.
+                let old = ends.remove(&src).unwrap();
+                if let Some(pair) = pairs.get_mut(&old) {
+                    pair.ends.retain(|&e| e != src);
+                }
+            }
+            if !pairs.contains_key(&id) && pairs.len() >= opts.max_pairs {
+                warn!(
+                    "relay: refusing {src}, already relaying {} pairs",
+                    pairs.len()
+                );
+                continue;
+            }
+            let pair = pairs.entry(id).or_insert_with(|| Pair {
+                ends: Vec::with_capacity(2),
+                budget: per_second,
+                window: now,
+                dropped: 0,
+                seen: now,
+            });
+            pair.seen = now;
+            if !pair.ends.contains(&src) {
+                if pair.ends.len() == 2 {
+                    // Someone else's NAT mapping changed; the newest wins.
+                    let old = pair.ends.remove(0);
+                    ends.remove(&old);
+                }
+                pair.ends.push(src);
+                ends.insert(src, id);
+                if let Some(grant) = grant {
+                    info!("relay: {src} is {} of {}", grant.member, grant.room);
+                    grants.insert(src, grant);
+                }
+                match pair.ends.as_slice() {
+                    [a, b] => info!("relay: pairing {a} with {b}"),
+                    _ => info!("relay: {src} waiting for its peer"),
+                }
+            }
+            continue;
+        }
+        let Some(pair) = ends.get(&src).and_then(|id| pairs.get_mut(id)) else {
+            continue;
+        };
+        let Some(&to) = pair.ends.iter().find(|&&e| e != src) else {
+            continue;
+        };
+        pair.seen = now;
+        if now - pair.window >= Duration::from_secs(1) {
+            if pair.dropped > 0 {
+                warn!(
+                    "relay: {src} ⇄ {to} over {} kbps, dropped {} packets",
+                    opts.max_kbps, pair.dropped
+                );
+            }
+            pair.budget = per_second;
+            pair.window = now;
+            pair.dropped = 0;
+        }
+        if n > pair.budget {
+            pair.dropped += 1;
+            continue;
+        }
+        pair.budget -= n;
+        match to {
+            End::Udp(to) => {
+                capture::sent(&sock, to, &buf[..n]);
+                if let Err(e) = sock.send_to(&buf[..n], to).await {
+                    error!("udp send error to {to}: {e}");
+                }
+            }
+            // A full queue drops the packet, as a full socket buffer would.
+            End::Tcp(to) => {
+                if let Some(queue) = streams.lock().get(&to) {
+                    let _ = queue.try_send(Bytes::copy_from_slice(&buf[..n]));
+                }
+            }
+        }
+    }
+}
+
+/// Takes callers over TCP, at most `max` at once.
+async fn accept(
+    listener: TcpListener,
+    streams: Streams,
+    received: mpsc::Sender<(SocketAddr, Bytes)>,
+    max: usize,
+) {
+    loop {
+        let (tcp, src) = match listener.accept().await {
+            Ok(r) => r,
+            Err(e) => {
+                error!("relay: tcp accept error: {e}");
+                continue;
+            }
+        };
+        let (queue, mut outgoing) = mpsc::channel::<Bytes>(TCP_QUEUE);
+        {
+            let mut streams = streams.lock();
+            if streams.len() >= max {
+                warn!(
+                    "relay: refusing {src} over TCP, already {} connected",
+                    streams.len()
+                );
+                continue;
+            }
+            streams.insert(src, queue);
+        }
+        let _ = tcp.set_nodelay(true);
+        let streams = Arc::clone(&streams);
+        let received = received.clone();
+        tokio::spawn(async move {
+            let (mut rd, mut wr) = tcp.into_split();
+            let reading = async {
+                let mut buf = [0u8; MAX_PACKET];
+                loop {
+                    let n = read_frame(&mut rd, &mut buf).await?;
+                    let pkt = Bytes::copy_from_slice(&buf[..n]);
+                    if received.send((src, pkt)).await.is_err() {
+                        return anyhow::Ok(());
+                    }
+                }
+            };
+            let writing = async {
+                while let Some(pkt) = outgoing.recv().await {
+                    write_frame(&mut wr, &pkt).await?;
+                }
+                anyhow::Ok(())
+            };
+            if let Err(e) = tokio::try_join!(reading, writing) {
+                debug!("relay: {src} over TCP went away: {e:#}");
+            }
+            streams.lock().remove(&src);
+        });
+    }
+}
+
+/// The call's end of a relay reached over TCP, open until dropped.
+pub(crate) struct Tunnel {
+    local: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl Tunnel {
+    /// Where the call socket sends what should go to the relay.
+    pub fn local(&self) -> SocketAddr {
+        self.local
+    }
+}
+
+impl Drop for Tunnel {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Carries what `call` sends to the tunnel's address to the relay at
+/// `relay` over TCP, and hands what the relay forwards back to `call` from
+/// there. Connects when the first packet comes, and again after the
+/// connection drops.
+pub(crate) fn tunnel(relay: SocketAddr, call: &UdpSocket) -> Result<Tunnel> {
+    let (bridge, from) = socket::beside(call)?;
+    let local = bridge.local_addr()?;
+    let task = tokio::spawn(async move {
+        let mut buf = [0u8; MAX_PACKET];
+        loop {
+            let first = match bridge.recv_from(&mut buf).await {
+                Ok((n, src)) if src == from => n,
+                Ok(_) => continue,
+                Err(e) => {
+                    error!("udp recv error: {e}");
+                    continue;
+                }
+            };
+            let tcp = match tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(relay)).await {
+                Ok(Ok(tcp)) => tcp,
+                Ok(Err(e)) => {
+                    warn!("can't reach the relay at {relay} over TCP: {e}");
+                    tokio::time::sleep(RECONNECT_AFTER).await;
+                    continue;
+                }
+                Err(_) => {
+                    warn!("timed out reaching the relay at {relay} over TCP");
+                    continue;
+                }
+            };
+            let _ = tcp.set_nodelay(true);
+            info!("connected to the relay at {relay} over TCP");
+            let (rd, wr) = tcp.into_split();
+            let sending = to_relay(&bridge, from, &buf[..first], wr);
+            if let Err(e) = tokio::try_join!(sending, from_relay(rd, &bridge, from)) {
+                warn!("lost the relay at {relay} over TCP: {e:#}");
+            }
+        }
+    });
+    Ok(Tunnel { local, task })
+}
+
+/// What `from` sends to `bridge`, after `first`, framed into `wr`.
+async fn to_relay(
+    bridge: &UdpSocket,
+    from: SocketAddr,
+    first: &[u8],
+    mut wr: impl AsyncWrite + Unpin,
+) -> Result<()> {
+    write_frame(&mut wr, first).await?;
+    let mut buf = [0u8; MAX_PACKET];
+    loop {
+        let (n, src) = bridge.recv_from(&mut buf).await?;
+        if src == from {
+            write_frame(&mut wr, &buf[..n]).await?;
+        }
+    }
+}
+
+/// The packets framed in `rd`, from `bridge` to `to`.
+async fn from_relay(
+    mut rd: impl AsyncRead + Unpin,
+    bridge: &UdpSocket,
+    to: SocketAddr,
+) -> Result<()> {
+    let mut buf = [0u8; MAX_PACKET];
+    loop {
+        let n = read_frame(&mut rd, &mut buf).await?;
+        bridge.send_to(&buf[..n], to).await?;
+    }
+}
+
+/// One packet behind its length (RFC 4571).
+async fn write_frame(wr: &mut (impl AsyncWrite + Unpin), pkt: &[u8]) -> Result<()> {
+    wr.write_u16(pkt.len() as u16).await?;
+    wr.write_all(pkt).await?;
+    Ok(())
+}
+
+/// The next packet into `buf`; returns its length.
+async fn read_frame(rd: &mut (impl AsyncRead + Unpin), buf: &mut [u8]) -> Result<usize> {
+    let n = rd.read_u16().await? as usize;
+    if n > buf.len() {
+        bail!("{n}-byte packet over TCP, more than {} allowed", buf.len());
+    }
+    rd.read_exact(&mut buf[..n]).await?;
+    Ok(n)
+}
+
+fn hex(id: &[u8]) -> String {
+    id.iter().map(|b| format!("{b:02x}")).collect()
+}
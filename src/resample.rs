@@ -0,0 +1,107 @@
+// ─── Resampling ────────────────────────────────────────────────────────────────
+// The codec always runs at 48 kHz, but devices run at whatever rate they
+// like – and Bluetooth headsets switch between 16/24/48 kHz when their mic
+// opens. A linear interpolator is plenty for narrow‑band voice and costs next
+// to nothing in the audio callback. Going down in rate, the input first goes
+// through a 4th‑order Butterworth low‑pass just under the new Nyquist, so
+// what's above it doesn't fold back into the band. Works on interleaved
+// frames so surround channels stay in lock‑step.
+
+use crate::effects::Biquad;
+
+/// Where the low‑pass cuts off, as a fraction of the output rate.
+const CUTOFF: f32 = 0.45;
+/// The Qs of the two sections of a 4th‑order Butterworth.
+const BUTTERWORTH_Q: [f32; 2] = [0.541_196_1, 1.306_563];
+
+pub struct Resampler {
+    /// Input frames per output frame.
+    step: f64,
+    /// Position of the next output frame between `x0` and `x1`.
+    frac: f64,
+    x0: Vec<f32>,
+    x1: Vec<f32>,
+    out: Vec<f32>,
+    /// Per channel; empty unless the rate goes down.
+    anti_alias: Vec<[Biquad; 2]>,
+}
+
+impl Resampler {
+    pub fn new(from_rate: u32, to_rate: u32, channels: usize) -> Self {
+        let anti_alias = match to_rate < from_rate {
+            true => {
+                let cutoff = CUTOFF * to_rate as f32;
+                let sections = BUTTERWORTH_Q.map(|q| Biquad::low_pass(from_rate as f32, cutoff, q));
+                vec![sections; channels]
+            }
+            false => Vec::new(),
+        };
+        Self {
+            step: from_rate as f64 / to_rate as f64,
+            frac: 0.0,
+            x0: vec![0.0; channels],
+            x1: vec![0.0; channels],
+            out: vec![0.0; channels],
+            anti_alias,
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.x0.len()
+    }
+
+    /// Feeds one input frame, calling `emit` for every output frame it
+    /// completes (capture side).
+    pub fn push(&mut self, frame: &[f32], mut emit: impl FnMut(&[f32])) {
+        std::mem::swap(&mut self.x0, &mut self.x1);
+        self.x1.copy_from_slice(frame);
+        self.filter();
+        while self.frac < 1.0 {
+            self.interpolate();
+            emit(&self.out);
+            self.frac += self.step;
+        }
+        self.frac -= 1.0;
+    }
+
+    /// Produces one output frame, pulling input frames from `next` as needed
+    /// (playback side).
+    pub fn pull(&mut self, mut next: impl FnMut(&mut [f32])) -> &[f32] {
+        while self.frac >= 1.0 {
+            std::mem::swap(&mut self.x0, &mut self.x1);
+            next(&mut self.x1);
+            self.filter();
+            self.frac -= 1.0;
+        }
+        self.interpolate();
+        self.frac += self.step;
+        &self.out
+    }
+
+    /// Input frames `pull` will ask for over the next `frames` output frames.
+    pub fn input_frames(&self, frames: usize) -> usize {
+        let mut frac = self.frac;
+        let mut n = 0;
+        for _ in 0..frames {
+            while frac >= 1.0 {
+                frac -= 1.0;
+                n += 1;
+            }
+            frac += self.step;
+        }
+        n
+    }
+
+    fn filter(&mut self) {
+        for (x, sections) in self.x1.iter_mut().zip(&mut self.anti_alias) {
+            *x = sections.iter_mut().fold(*x, |s, section| section.run(s));
+        }
+    }
+
+    fn interpolate(&mut self) {
+        let t = self.frac as f32;
+        for ((out, &a), &b) in self.out.iter_mut().zip(&self.x0).zip(&self.x1) {
+            *out = a + (b - a) * t;
+        }
+    }
+}
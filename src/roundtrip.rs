@@ -0,0 +1,268 @@
+// ─── Acoustic round trip ───────────────────────────────────────────────────────
+// `latency-test`: plays a short chirp through the speaker once a second, finds
+// it again in the microphone signal with a matched filter and reports how long
+// it took from our output callback to our input callback. That is the device
+// round trip – driver and hardware buffers both ways plus the air gap – which
+// is the echo delay the AEC has to cover and the part of a call's delay that
+// `--buffer-frames` controls. It needs speakers (not headphones) and a fairly
+// quiet room.
+//
+// Times come from `Instant::now()` in the callbacks, not stream timestamps,
+// which several backends don't provide: a sample is "written" when its
+// buffer is handed over (plus its offset in the buffer) and "heard" when its
+// block is delivered (less the samples after it).
+
+use crate::{devices, sample_to_f32};
+use anyhow::{bail, Context, Result};
+use cpal::traits::*;
+use cpal::Sample;
+use parking_lot::Mutex as PLMutex;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// One chirp per interval, after one interval for the devices to settle.
+const INTERVAL: Duration = Duration::from_secs(1);
+const CHIRP: Duration = Duration::from_millis(40);
+const CHIRP_HZ: (f32, f32) = (1000.0, 5000.0);
+/// Normalised correlation below which the chirp counts as not heard.
+const MIN_MATCH: f32 = 0.25;
+
+pub struct RoundTripOptions {
+    /// Host, devices and buffer size, as for a call.
+    pub audio: devices::AudioOptions,
+    pub chirps: u32,
+    /// The call's playout target, for the pipeline's share of the delay.
+    pub jitter_target_ms: u32,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RoundTripReport {
+    pub input: String,
+    pub output: String,
+    pub chirps_sent: u32,
+    /// Device round trip for each chirp heard, in order.
+    pub device_ms: Vec<f32>,
+    /// What a call adds at this end on top of the devices: a frame of
+    /// framing on the way out, the jitter target on the way in.
+    pub pipeline_ms: f32,
+}
+
+impl RoundTripReport {
+    pub fn median_ms(&self) -> Option<f32> {
+        let mut sorted = self.device_ms.clone();
+        sorted.sort_by(f32::total_cmp);
+        sorted.get(sorted.len() / 2).copied()
+    }
+}
+
+/// Runs the test on the calling thread (cpal streams aren't `Send`) and
+/// blocks for about `chirps + 2` seconds.
+pub fn measure(opts: &RoundTripOptions) -> Result<RoundTripReport> {
+    let host = devices::select_host(opts.audio.host.as_deref())?;
+    let audio = devices::AudioOptions {
+        playback: true,
+        ..opts.audio.clone()
+    };
+    let (Some(input), Some(output)) = devices::open_devices(&host, &audio, true)? else {
+        bail!("the latency test needs an input and an output device");
+    };
+    let in_supported = input.default_input_config()?;
+    let out_supported = output.default_output_config()?;
+    let in_cfg = devices::stream_config(&in_supported, audio.buffer_frames);
+    let out_cfg = devices::stream_config(&out_supported, audio.buffer_frames);
+    let names = (
+        input.name().unwrap_or("Unknown".into()),
+        output.name().unwrap_or("Unknown".into()),
+    );
+    info!(
+        "latency test: {} {in_cfg:?} → {} {out_cfg:?}",
+        names.0, names.1
+    );
+
+    let heard = Arc::new(PLMutex::new(Heard::default()));
+    let written = Arc::new(PLMutex::new(Vec::new()));
+    let in_stream = match in_supported.sample_format() {
+        cpal::SampleFormat::F32 => listen::<f32>(&input, &in_cfg, &heard)?,
+        cpal::SampleFormat::I16 => listen::<i16>(&input, &in_cfg, &heard)?,
+        cpal::SampleFormat::U16 => listen::<u16>(&input, &in_cfg, &heard)?,
+        cpal::SampleFormat::I32 => listen::<i32>(&input, &in_cfg, &heard)?,
+        f => bail!("unsupported input sample format {f}"),
+    };
+    let chirps = opts.chirps;
+    let out_stream = match out_supported.sample_format() {
+        cpal::SampleFormat::F32 => play::<f32>(&output, &out_cfg, chirps, &written)?,
+        cpal::SampleFormat::I16 => play::<i16>(&output, &out_cfg, chirps, &written)?,
+        cpal::SampleFormat::U16 => play::<u16>(&output, &out_cfg, chirps, &written)?,
+        cpal::SampleFormat::I32 => play::<i32>(&output, &out_cfg, chirps, &written)?,
+        f => bail!("unsupported output sample format {f}"),
+    };
+    in_stream.play()?;
+    out_stream.play()?;
+    std::thread::sleep(INTERVAL * (chirps + 2));
+    drop(out_stream);
+    drop(in_stream);
+
+    let heard = heard.lock();
+    let rate = in_cfg.sample_rate.0;
+    let reference = chirp(rate);
+    let device_ms = written
+        .lock()
+        .iter()
+        .filter_map(|&at| {
+            let ms = heard.find(&reference, at, rate)?.as_secs_f32() * 1000.0;
+            info!("chirp heard after {ms:.1} ms");
+            Some(ms)
+        })
+        .collect();
+    Ok(RoundTripReport {
+        input: names.0,
+        output: names.1,
+        chirps_sent: chirps,
+        device_ms,
+        pipeline_ms: (audio.frame_ms as u32 + opts.jitter_target_ms) as f32,
+    })
+}
+
+/// The microphone signal, down‑mixed, with when each block was delivered.
+#[derive(Default)]
+struct Heard {
+    samples: Vec<f32>,
+    /// End of each block in `samples`, and when it arrived.
+    blocks: Vec<(usize, Instant)>,
+}
+
+impl Heard {
+    /// How long after `written` the chirp was heard, if it was.
+    fn find(&self, reference: &[f32], written: Instant, rate: u32) -> Option<Duration> {
+        // Nothing delivered before it was written can hold it.
+        let start = self
+            .blocks
+            .iter()
+            .take_while(|(_, at)| *at < written)
+            .last()
+            .map_or(0, |&(end, _)| end);
+        let window = (INTERVAL.as_secs_f64() * rate as f64) as usize;
+        let end = (start + window).min(self.samples.len());
+        let signal = self.samples.get(start..end)?;
+        let lag = best_match(reference, signal)?;
+        let at = start + lag;
+        let &(block_end, delivered) = self.blocks.iter().find(|(end, _)| *end > at)?;
+        let captured = delivered.checked_sub(samples_to_duration(block_end - 1 - at, rate))?;
+        captured.checked_duration_since(written)
+    }
+}
+
+/// Where `reference` matches `signal` best, if well enough.
+fn best_match(reference: &[f32], signal: &[f32]) -> Option<usize> {
+    let n = reference.len();
+    if signal.len() < n {
+        return None;
+    }
+    let ref_energy: f32 = reference.iter().map(|s| s * s).sum();
+    // Running, in f64 so the sliding sum doesn't drift.
+    let mut energy: f64 = signal[..n].iter().map(|&s| s as f64 * s as f64).sum();
+    let mut best = (0.0, 0);
+    for lag in 0..=signal.len() - n {
+        if lag > 0 {
+            energy += (signal[lag + n - 1] as f64).powi(2) - (signal[lag - 1] as f64).powi(2);
+        }
+        let dot: f32 = reference
+            .iter()
+            .zip(&signal[lag..])
+            .map(|(r, s)| r * s)
+            .sum();
+        let score = dot.abs() / (ref_energy * (energy as f32).max(f32::MIN_POSITIVE)).sqrt();
+        if score > best.0 {
+            best = (score, lag);
+        }
+    }
+    (best.0 >= MIN_MATCH).then_some(best.1)
+}
+
+/// A Hann‑windowed linear sweep.
+fn chirp(rate: u32) -> Vec<f32> {
+    let len = (CHIRP.as_secs_f32() * rate as f32) as usize;
+    let (f0, f1) = CHIRP_HZ;
+    let secs = CHIRP.as_secs_f32();
+    (0..len)
+        .map(|i| {
+            let t = i as f32 / rate as f32;
+            let phase = std::f32::consts::TAU * (f0 * t + (f1 - f0) * t * t / (2.0 * secs));
+            let window = 0.5 - 0.5 * (std::f32::consts::TAU * i as f32 / len as f32).cos();
+            0.5 * window * phase.sin()
+        })
+        .collect()
+}
+
+fn samples_to_duration(samples: usize, rate: u32) -> Duration {
+    Duration::from_secs_f64(samples as f64 / rate as f64)
+}
+
+fn listen<T>(
+    device: &cpal::Device,
+    cfg: &cpal::StreamConfig,
+    heard: &Arc<PLMutex<Heard>>,
+) -> Result<cpal::Stream>
+where
+    T: Sample + cpal::SizedSample + 'static,
+{
+    let channels = cfg.channels as usize;
+    let heard = Arc::clone(heard);
+    let stream = device.build_input_stream(
+        cfg,
+        move |data: &[T], _: &cpal::InputCallbackInfo| {
+            let now = Instant::now();
+            let mut heard = heard.lock();
+            for frame in data.chunks(channels) {
+                let sum: f32 = frame.iter().map(|&s| sample_to_f32(s)).sum();
+                heard.samples.push(sum / channels as f32);
+            }
+            let end = heard.samples.len();
+            heard.blocks.push((end, now));
+        },
+        |e| tracing::error!("input stream error: {e}"),
+        None,
+    )?;
+    Ok(stream)
+}
+
+fn play<T>(
+    device: &cpal::Device,
+    cfg: &cpal::StreamConfig,
+    chirps: u32,
+    written: &Arc<PLMutex<Vec<Instant>>>,
+) -> Result<cpal::Stream>
+where
+    T: Sample + cpal::SizedSample + cpal::FromSample<f32> + 'static,
+{
+    let channels = cfg.channels as usize;
+    let rate = cfg.sample_rate.0;
+    let sweep = chirp(rate);
+    let every = (INTERVAL.as_secs_f64() * rate as f64) as u64;
+    let written = Arc::clone(written);
+    // Frames played so far.
+    let mut pos = 0u64;
+    let stream = device
+        .build_output_stream(
+            cfg,
+            move |out: &mut [T], _: &cpal::OutputCallbackInfo| {
+                let now = Instant::now();
+                for (i, frame) in out.chunks_mut(channels).enumerate() {
+                    let (n, at) = (pos / every, (pos % every) as usize);
+                    let chirping = (1..=chirps as u64).contains(&n);
+                    if chirping && at == 0 {
+                        written.lock().push(now + samples_to_duration(i, rate));
+                    }
+                    let s = sweep.get(at).filter(|_| chirping).copied();
+                    frame.fill(T::from_sample(s.unwrap_or(0.0)));
+                    pos += 1;
+                }
+            },
+            |e| tracing::error!("output stream error: {e}"),
+            None,
+        )
+        .context("opening the output stream")?;
+    Ok(stream)
+}
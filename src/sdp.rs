@@ -0,0 +1,559 @@
+// ─── SDP ───────────────────────────────────────────────────────────────────────
+// Session descriptions (RFC 8866) in the offer/answer style of RFC 3264 – what
+// SIP and WebRTC peers exchange, and a form of `manual`'s offers and answers
+// that other tools can read. `describe` writes our side: Opus with its fmtp
+// parameters (Chrome's `multiopus` for surround), our handshake key, and ICE
+// candidates (RFC 8839) for our reflexive and LAN addresses. `FromStr` reads
+// any SDP, keeping every attribute as text; `peer_info` pulls out what we
+// need to call.
+//
+// Our media is not DTLS‑SRTP, so the transport is `UDP/AUDIO-P2P` and the
+// X25519 key goes in an `a=x-audio-p2p-key` attribute. `a=setup` still tells
+// an offer (`actpass`) from an answer (`active`), as in WebRTC.
+
+use crate::codec::{self, StreamParams};
+use crate::manual::Role;
+use crate::signaling::{JoinPayload, PeerInfo};
+use anyhow::{anyhow, bail, Context, Result};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+/// Dynamic payload type for Opus, the one browsers use.
+pub const OPUS_PAYLOAD_TYPE: u8 = 111;
+pub const PROTO: &str = "UDP/AUDIO-P2P";
+pub const KEY_ATTRIBUTE: &str = "x-audio-p2p-key";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SessionDescription {
+    pub origin: Origin,
+    /// `s=`
+    pub name: String,
+    /// Session‑level `c=`, the default for every media section.
+    pub connection: Option<IpAddr>,
+    /// Session‑level attributes.
+    pub attributes: Vec<Attribute>,
+    pub media: Vec<Media>,
+}
+
+/// `o=`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Origin {
+    pub username: String,
+    pub session_id: u64,
+    pub version: u64,
+    pub addr: IpAddr,
+}
+
+/// `a=name` or `a=name:value`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Attribute {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+/// An `m=` section.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Media {
+    /// `audio`, `video`, …
+    pub kind: String,
+    pub port: u16,
+    pub proto: String,
+    /// Payload types for RTP protocols.
+    pub formats: Vec<String>,
+    pub connection: Option<IpAddr>,
+    pub attributes: Vec<Attribute>,
+}
+
+/// `a=rtpmap:<pt> <encoding>/<clock rate>[/<channels>]`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RtpMap {
+    pub payload_type: u8,
+    pub encoding: String,
+    pub clock_rate: u32,
+    pub channels: Option<u8>,
+}
+
+/// `a=candidate:` (RFC 8839).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Candidate {
+    pub foundation: String,
+    pub component: u8,
+    /// `udp` (case‑insensitive) is the only one we can use.
+    pub transport: String,
+    pub priority: u32,
+    pub addr: SocketAddr,
+    pub kind: CandidateKind,
+    /// `raddr`/`rport` of reflexive and relayed candidates.
+    pub related: Option<SocketAddr>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CandidateKind {
+    Host,
+    ServerReflexive,
+    PeerReflexive,
+    Relay,
+}
+
+impl CandidateKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Host => "host",
+            Self::ServerReflexive => "srflx",
+            Self::PeerReflexive => "prflx",
+            Self::Relay => "relay",
+        }
+    }
+
+    /// RFC 8445 type preference.
+    fn preference(self) -> u32 {
+        match self {
+            Self::Host => 126,
+            Self::PeerReflexive => 110,
+            Self::ServerReflexive => 100,
+            Self::Relay => 0,
+        }
+    }
+}
+
+impl Candidate {
+    fn new(foundation: &str, addr: SocketAddr, kind: CandidateKind) -> Self {
+        // RFC 8445 §5.1.2.1, one component, one local address per kind.
+        let priority = (kind.preference() << 24) | (65535 << 8) | (256 - 1);
+        Self {
+            foundation: foundation.into(),
+            component: 1,
+            transport: "udp".into(),
+            priority,
+            addr,
+            kind,
+            related: None,
+        }
+    }
+}
+
+impl Media {
+    /// The value of the first `name` attribute (empty for a flag).
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|a| a.name == name)
+            .map(|a| a.value.as_deref().unwrap_or_default())
+    }
+
+    pub fn attributes<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.attributes
+            .iter()
+            .filter(move |a| a.name == name)
+            .map(|a| a.value.as_deref().unwrap_or_default())
+    }
+
+    pub fn rtpmaps(&self) -> Vec<RtpMap> {
+        self.attributes("rtpmap")
+            .filter_map(|v| v.parse().ok())
+            .collect()
+    }
+
+    /// The `a=fmtp` parameters of `payload_type`, as key/value pairs.
+    pub fn fmtp(&self, payload_type: u8) -> Vec<(String, String)> {
+        let pt = payload_type.to_string();
+        self.attributes("fmtp")
+            .filter_map(|v| v.split_once(' ').filter(|(p, _)| *p == pt))
+            .flat_map(|(_, params)| params.split(';'))
+            .filter_map(|p| {
+                let (k, v) = p.split_once('=').unwrap_or((p, ""));
+                Some((k.trim().to_owned(), v.trim().to_owned())).filter(|(k, _)| !k.is_empty())
+            })
+            .collect()
+    }
+
+    /// The candidates we could parse; others (e.g. TCP) are skipped.
+    pub fn candidates(&self) -> Vec<Candidate> {
+        self.attributes("candidate")
+            .filter_map(|v| v.parse().ok())
+            .collect()
+    }
+
+    fn push(&mut self, name: &str, value: impl Into<Option<String>>) {
+        self.attributes.push(Attribute {
+            name: name.into(),
+            value: value.into(),
+        });
+    }
+}
+
+/// Our side of a call: Opus as `params` sends it, `me`'s key and addresses.
+pub fn describe(role: Role, me: &JoinPayload, params: &StreamParams) -> Result<SessionDescription> {
+    let reflexive: SocketAddr = me
+        .reflexive_addr
+        .parse()
+        .context("no address to describe")?;
+    let layout = &params.layout;
+    let pt = OPUS_PAYLOAD_TYPE;
+    let mut media = Media {
+        kind: "audio".into(),
+        port: reflexive.port(),
+        proto: PROTO.into(),
+        formats: vec![pt.to_string()],
+        connection: None,
+        attributes: Vec::new(),
+    };
+    // RFC 7587: always opus/48000/2; stereo is a format parameter.
+    let mut fmtp = vec!["minptime=10".to_owned()];
+    if layout.channels > 2 {
+        media.push(
+            "rtpmap",
+            format!("{pt} multiopus/48000/{}", layout.channels),
+        );
+        let mapping: Vec<String> = layout.mapping.iter().map(u8::to_string).collect();
+        fmtp.push(format!("channel_mapping={}", mapping.join(",")));
+        fmtp.push(format!("num_streams={}", layout.streams));
+        fmtp.push(format!("coupled_streams={}", layout.coupled_streams));
+    } else {
+        media.push("rtpmap", format!("{pt} opus/48000/2"));
+        let stereo = (layout.channels == 2) as u8;
+        fmtp.push(format!("stereo={stereo};sprop-stereo={stereo}"));
+    }
+    fmtp.push(format!("useinbandfec={}", params.fec as u8));
+    media.push("fmtp", format!("{pt} {}", fmtp.join(";")));
+    media.push("ptime", params.frame_ms.to_string());
+    let max_ms = codec::FRAME_MS_OPTIONS
+        .iter()
+        .max()
+        .copied()
+        .unwrap_or_default();
+    media.push("maxptime", max_ms.to_string());
+    media.push("sendrecv", None);
+    let setup = match role {
+        Role::Offer => "actpass",
+        Role::Answer => "active",
+    };
+    media.push("setup", setup.to_owned());
+    media.push(KEY_ATTRIBUTE, me.pub_key.clone());
+    media.push(
+        "candidate",
+        Candidate::new("1", reflexive, CandidateKind::ServerReflexive).to_string(),
+    );
+    if let Ok(lan) = me.lan_addr.parse::<SocketAddr>() {
+        media.push(
+            "candidate",
+            Candidate::new("2", lan, CandidateKind::Host).to_string(),
+        );
+    }
+    if let Ok(relayed) = me.relayed_addr.parse::<SocketAddr>() {
+        media.push(
+            "candidate",
+            Candidate::new("3", relayed, CandidateKind::Relay).to_string(),
+        );
+    }
+    Ok(SessionDescription {
+        origin: Origin {
+            username: "-".into(),
+            session_id: rand::random::<u32>() as u64,
+            version: 1,
+            addr: reflexive.ip(),
+        },
+        name: "audio-p2p".into(),
+        connection: Some(reflexive.ip()),
+        attributes: Vec::new(),
+        media: vec![media],
+    })
+}
+
+/// Whether `sdp` is an offer or an answer, and how to reach its sender.
+/// Only our own descriptions carry a handshake key, so that's required.
+pub fn peer_info(sdp: &SessionDescription) -> Result<(Role, PeerInfo)> {
+    let media = sdp
+        .media
+        .iter()
+        .find(|m| m.kind == "audio" && m.port != 0)
+        .context("no audio in the description")?;
+    let key = media.attribute(KEY_ATTRIBUTE).with_context(|| {
+        format!("no {KEY_ATTRIBUTE} attribute; only this app's descriptions can be called")
+    })?;
+    let role = match media.attribute("setup") {
+        Some("actpass") | None => Role::Offer,
+        Some(_) => Role::Answer,
+    };
+    let candidates: Vec<Candidate> = media
+        .candidates()
+        .into_iter()
+        .filter(|c| c.component == 1 && c.transport.eq_ignore_ascii_case("udp"))
+        .collect();
+    let of = |kind| candidates.iter().find(|c| c.kind == kind).map(|c| c.addr);
+    let fallback = media
+        .connection
+        .or(sdp.connection)
+        .map(|ip| SocketAddr::new(ip, media.port));
+    let lan = of(CandidateKind::Host);
+    let reflexive = of(CandidateKind::ServerReflexive)
+        .or(fallback)
+        .or(lan)
+        .context("no address in the description")?;
+    Ok((
+        role,
+        PeerInfo {
+            reflexive_addr: reflexive.to_string(),
+            lan_addr: lan.map(|a| a.to_string()).unwrap_or_default(),
+            pub_key: key.to_owned(),
+            relayed_addr: of(CandidateKind::Relay)
+                .map(|a| a.to_string())
+                .unwrap_or_default(),
+            room_proof: None,
+            webrtc: false,
+        },
+    ))
+}
+
+// ─── Text form ────────────────────────────────────────────────────────────────
+impl fmt::Display for SessionDescription {
+    /// With CRLF line ends, as the RFC wants.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let o = &self.origin;
+        write!(f, "v=0\r\n")?;
+        write!(
+            f,
+            "o={} {} {} IN {} {}\r\n",
+            o.username,
+            o.session_id,
+            o.version,
+            addr_type(o.addr),
+            o.addr
+        )?;
+        write!(
+            f,
+            "s={}\r\n",
+            if self.name.is_empty() {
+                "-"
+            } else {
+                &self.name
+            }
+        )?;
+        if let Some(ip) = self.connection {
+            write!(f, "c=IN {} {ip}\r\n", addr_type(ip))?;
+        }
+        write!(f, "t=0 0\r\n")?;
+        write_attributes(f, &self.attributes)?;
+        for m in &self.media {
+            write!(
+                f,
+                "m={} {} {} {}\r\n",
+                m.kind,
+                m.port,
+                m.proto,
+                m.formats.join(" ")
+            )?;
+            if let Some(ip) = m.connection {
+                write!(f, "c=IN {} {ip}\r\n", addr_type(ip))?;
+            }
+            write_attributes(f, &m.attributes)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_attributes(f: &mut fmt::Formatter<'_>, attributes: &[Attribute]) -> fmt::Result {
+    for a in attributes {
+        match &a.value {
+            Some(value) => write!(f, "a={}:{value}\r\n", a.name)?,
+            None => write!(f, "a={}\r\n", a.name)?,
+        }
+    }
+    Ok(())
+}
+
+fn addr_type(ip: IpAddr) -> &'static str {
+    match ip {
+        IpAddr::V4(_) => "IP4",
+        IpAddr::V6(_) => "IP6",
+    }
+}
+
+impl FromStr for SessionDescription {
+    type Err = anyhow::Error;
+
+    /// Lenient: LF or CRLF, unknown lines and attributes are kept or skipped
+    /// rather than rejected.
+    fn from_str(text: &str) -> Result<Self> {
+        let mut lines = text
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .enumerate();
+        match lines.next() {
+            Some((_, "v=0")) => {}
+            _ => bail!("not an SDP description (no v=0)"),
+        }
+        let mut origin = None;
+        let mut name = String::new();
+        let mut connection = None;
+        let mut attributes = Vec::new();
+        let mut media: Vec<Media> = Vec::new();
+        for (n, line) in lines {
+            let (kind, value) = line
+                .split_once('=')
+                .filter(|(k, _)| k.len() == 1)
+                .ok_or_else(|| anyhow!("SDP line {}: {line:?}", n + 1))?;
+            let context = || format!("SDP line {}: {line:?}", n + 1);
+            match kind {
+                "o" => origin = Some(parse_origin(value).with_context(context)?),
+                "s" => name = value.to_owned(),
+                "c" => {
+                    let ip = parse_connection(value).with_context(context)?;
+                    match media.last_mut() {
+                        Some(m) => m.connection = Some(ip),
+                        None => connection = Some(ip),
+                    }
+                }
+                "m" => media.push(parse_media(value).with_context(context)?),
+                "a" => {
+                    let (name, value) = match value.split_once(':') {
+                        Some((name, value)) => (name, Some(value.to_owned())),
+                        None => (value, None),
+                    };
+                    let attribute = Attribute {
+                        name: name.to_owned(),
+                        value,
+                    };
+                    match media.last_mut() {
+                        Some(m) => m.attributes.push(attribute),
+                        None => attributes.push(attribute),
+                    }
+                }
+                // Timing, bandwidth, info and the like don't concern us.
+                _ => {}
+            }
+        }
+        Ok(Self {
+            origin: origin.context("SDP without an o= line")?,
+            name,
+            connection,
+            attributes,
+            media,
+        })
+    }
+}
+
+fn parse_origin(value: &str) -> Result<Origin> {
+    let f: Vec<&str> = value.split_whitespace().collect();
+    let [username, id, version, "IN", _, addr] = f[..] else {
+        bail!("bad o= line");
+    };
+    Ok(Origin {
+        username: username.to_owned(),
+        session_id: id.parse()?,
+        version: version.parse()?,
+        // Origins may name a host; only the address form is of any use.
+        addr: addr.parse().unwrap_or(IpAddr::from([0, 0, 0, 0])),
+    })
+}
+
+fn parse_connection(value: &str) -> Result<IpAddr> {
+    let f: Vec<&str> = value.split_whitespace().collect();
+    let ["IN", _, addr] = f[..] else {
+        bail!("bad c= line");
+    };
+    // Multicast addresses carry a /ttl.
+    let addr = addr.split('/').next().unwrap_or_default();
+    Ok(addr.parse()?)
+}
+
+fn parse_media(value: &str) -> Result<Media> {
+    let mut f = value.split_whitespace();
+    let (Some(kind), Some(port), Some(proto)) = (f.next(), f.next(), f.next()) else {
+        bail!("bad m= line");
+    };
+    // A port range "port/count" only ever matters for RTP over many ports.
+    let port = port.split('/').next().unwrap_or_default().parse()?;
+    Ok(Media {
+        kind: kind.to_owned(),
+        port,
+        proto: proto.to_owned(),
+        formats: f.map(String::from).collect(),
+        connection: None,
+        attributes: Vec::new(),
+    })
+}
+
+impl FromStr for RtpMap {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (pt, format) = value.split_once(' ').context("bad rtpmap")?;
+        let mut parts = format.trim().split('/');
+        let encoding = parts.next().context("bad rtpmap")?.to_owned();
+        let clock_rate = parts
+            .next()
+            .context("rtpmap without a clock rate")?
+            .parse()?;
+        let channels = parts.next().map(str::parse).transpose()?;
+        Ok(Self {
+            payload_type: pt.parse()?,
+            encoding,
+            clock_rate,
+            channels,
+        })
+    }
+}
+
+impl fmt::Display for Candidate {
+    /// The attribute value, without `a=candidate:`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {} {} typ {}",
+            self.foundation,
+            self.component,
+            self.transport,
+            self.priority,
+            self.addr.ip(),
+            self.addr.port(),
+            self.kind.as_str()
+        )?;
+        if let Some(related) = self.related {
+            write!(f, " raddr {} rport {}", related.ip(), related.port())?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Candidate {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let f: Vec<&str> = value.split_whitespace().collect();
+        let [foundation, component, transport, priority, ip, port, "typ", kind, ref rest @ ..] =
+            f[..]
+        else {
+            bail!("bad candidate {value:?}");
+        };
+        let kind = match kind {
+            "host" => CandidateKind::Host,
+            "srflx" => CandidateKind::ServerReflexive,
+            "prflx" => CandidateKind::PeerReflexive,
+            "relay" => CandidateKind::Relay,
+            _ => bail!("unknown candidate type {kind}"),
+        };
+        // Then name/value extensions, of which we want the related address.
+        let ext = |name: &str| {
+            rest.chunks(2)
+                .find(|kv| kv.first() == Some(&name))
+                .and_then(|kv| kv.get(1).copied())
+        };
+        let related = match (ext("raddr"), ext("rport")) {
+            (Some(ip), Some(port)) => Some(SocketAddr::new(ip.parse()?, port.parse()?)),
+            _ => None,
+        };
+        Ok(Self {
+            foundation: foundation.to_owned(),
+            component: component.parse()?,
+            transport: transport.to_owned(),
+            priority: priority.parse()?,
+            // mDNS (.local) host candidates don't parse and are skipped.
+            addr: SocketAddr::new(ip.parse()?, port.parse()?),
+            kind,
+            related,
+        })
+    }
+}
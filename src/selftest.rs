@@ -0,0 +1,312 @@
+// ─── Self‑test ─────────────────────────────────────────────────────────────────
+// Two sessions in one process calling each other over loopback UDP, with no
+// audio devices: each sends tone bursts from an `AudioSource` and listens
+// through an `AudioSink`. Every burst sent once the call is up must arrive at
+// the right pitch and level; the time from the sender's capture chain to the
+// receiver's playout is the call's end‑to‑end latency minus the device
+// buffers. The `selftest` command and the loopback integration test run it.
+
+use crate::{codec, devices, jitter, sink, source};
+use crate::{SessionConfig, VoiceSession, SAMPLE_RATE};
+use anyhow::{bail, Result};
+use parking_lot::Mutex as PLMutex;
+use serde::Serialize;
+use source::AudioSource;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Bursts start every `PERIOD_BLOCKS` 10 ms blocks and last `ON_BLOCKS`.
+const PERIOD_BLOCKS: u64 = 50;
+const ON_BLOCKS: u64 = 20;
+const PERIOD: Duration = Duration::from_millis(PERIOD_BLOCKS * 10);
+/// One per direction; whole cycles per 10 ms block, so blocks analyse
+/// cleanly.
+const TONES: [f32; 2] = [400.0, 700.0];
+/// RMS of `source::Tone`.
+const TONE_RMS: f32 = 0.25 * std::f32::consts::FRAC_1_SQRT_2;
+/// A block louder than this is part of a burst.
+const ON_RMS: f32 = 0.05;
+/// Least share of a burst's power at the tone's frequency.
+const MIN_PURITY: f32 = 0.8;
+/// Most the received level may differ from the sent one.
+const MAX_GAIN_DB: f32 = 6.0;
+
+pub struct SelftestOptions {
+    /// How long the tones run; the first bursts go before the call is up.
+    pub duration: Duration,
+    pub frame_ms: u8,
+    pub encoder: codec::EncoderOptions,
+    pub jitter: jitter::JitterOptions,
+}
+
+impl Default for SelftestOptions {
+    fn default() -> Self {
+        Self {
+            duration: Duration::from_secs(5),
+            frame_ms: codec::DEFAULT_FRAME_MS,
+            encoder: codec::EncoderOptions::default(),
+            jitter: jitter::JitterOptions::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SelftestReport {
+    pub directions: Vec<Direction>,
+}
+
+/// One side's tone, as the other side heard it.
+#[derive(Clone, Debug, Serialize)]
+pub struct Direction {
+    /// "A → B" or "B → A".
+    pub name: &'static str,
+    pub hz: f32,
+    /// Bursts sent from the first one that arrived until a period before
+    /// the end.
+    pub bursts_sent: usize,
+    pub bursts_heard: usize,
+    /// Share of the received bursts' power at the tone's frequency.
+    pub purity: f32,
+    /// Received level relative to the sent one.
+    pub gain_db: f32,
+    /// From the sender's capture chain to the receiver's playout.
+    pub latency_ms: Option<LatencySummary>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct LatencySummary {
+    pub min: f32,
+    pub median: f32,
+    pub max: f32,
+}
+
+impl SelftestReport {
+    /// Fails unless every burst arrived, at the right pitch and level.
+    pub fn check(&self) -> Result<()> {
+        for d in &self.directions {
+            if d.bursts_heard == 0 {
+                bail!("{}: no audio arrived", d.name);
+            }
+            if d.bursts_heard < d.bursts_sent {
+                bail!(
+                    "{}: heard {} of {} bursts",
+                    d.name,
+                    d.bursts_heard,
+                    d.bursts_sent
+                );
+            }
+            if d.purity < MIN_PURITY {
+                bail!("{}: tone distorted ({:.0}% pure)", d.name, d.purity * 100.0);
+            }
+            if d.gain_db.abs() > MAX_GAIN_DB {
+                bail!("{}: tone arrived at {:+.1} dB", d.name, d.gain_db);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs both sessions for `opts.duration` on the current Tokio runtime.
+pub async fn run(opts: &SelftestOptions) -> Result<SelftestReport> {
+    let ports = [free_port()?, free_port()?];
+    let mut sent = Vec::new();
+    let mut heard = Vec::new();
+    let mut sessions = Vec::new();
+    for i in 0..2 {
+        let onsets = Arc::new(PLMutex::new(Vec::new()));
+        let ears = Arc::new(PLMutex::new(Heard::default()));
+        let source = Bursts {
+            tone: source::Tone::new(TONES[i]),
+            block: 0,
+            onsets: Arc::clone(&onsets),
+        };
+        let sink = Listener {
+            hz: TONES[1 - i],
+            on: false,
+            heard: Arc::clone(&ears),
+        };
+        sessions.push(VoiceSession::start(SessionConfig {
+            local_port: ports[i],
+            peer: Some(format!("127.0.0.1:{}", ports[1 - i])),
+            audio: devices::AudioOptions {
+                frame_ms: opts.frame_ms,
+                ..Default::default()
+            },
+            encoder: opts.encoder.clone(),
+            jitter: opts.jitter,
+            source: Some(Box::new(source)),
+            sink: Some(Box::new(sink)),
+            ..Default::default()
+        })?);
+        sent.push(onsets);
+        heard.push(ears);
+    }
+    info!(
+        "self‑test: calling between ports {} and {}",
+        ports[0], ports[1]
+    );
+    tokio::time::sleep(opts.duration).await;
+    let end = Instant::now();
+    drop(sessions);
+
+    let directions = [("A → B", 0, 1), ("B → A", 1, 0)]
+        .into_iter()
+        .map(|(name, from, to)| {
+            let sent = sent[from].lock().clone();
+            direction(name, TONES[from], &sent, &heard[to].lock(), end)
+        })
+        .collect();
+    Ok(SelftestReport { directions })
+}
+
+fn direction(
+    name: &'static str,
+    hz: f32,
+    sent: &[Instant],
+    heard: &Heard,
+    end: Instant,
+) -> Direction {
+    // Each onset heard belongs to the last burst sent before it, if that
+    // was less than a period ago.
+    let mut matched = Vec::new();
+    let mut latencies = Vec::new();
+    for &h in &heard.onsets {
+        let Some(&s) = sent.iter().rev().find(|&&s| s <= h) else {
+            continue;
+        };
+        if h - s < PERIOD && matched.last() != Some(&s) {
+            matched.push(s);
+            latencies.push((h - s).as_secs_f32() * 1000.0);
+        }
+    }
+    // Earlier bursts raced the handshake; later ones may still be in flight.
+    let first = matched.first().copied();
+    let counted = |s: &&Instant| first.is_some_and(|f| **s >= f) && **s + PERIOD <= end;
+    let bursts_sent = sent.iter().filter(counted).count();
+    let bursts_heard = matched.iter().filter(counted).count();
+    latencies.sort_by(f32::total_cmp);
+    let latency_ms = (!latencies.is_empty()).then(|| LatencySummary {
+        min: latencies[0],
+        median: latencies[latencies.len() / 2],
+        max: latencies[latencies.len() - 1],
+    });
+    let (purity, gain_db) = match heard.blocks {
+        0 => (0.0, f32::NEG_INFINITY),
+        n => {
+            let rms = (heard.power / (n * heard.block_len) as f64).sqrt() as f32;
+            let purity = (heard.tone_power / heard.power.max(f64::MIN_POSITIVE)) as f32;
+            (purity, 20.0 * (rms / TONE_RMS).log10())
+        }
+    };
+    Direction {
+        name,
+        hz,
+        bursts_sent,
+        bursts_heard,
+        purity,
+        gain_db,
+        latency_ms,
+    }
+}
+
+/// A port that was free a moment ago.
+fn free_port() -> Result<u16> {
+    Ok(std::net::UdpSocket::bind("127.0.0.1:0")?
+        .local_addr()?
+        .port())
+}
+
+/// Tone bursts, noting when each one started into the capture chain. The
+/// session reads 10 ms blocks.
+struct Bursts {
+    tone: source::Tone,
+    block: u64,
+    onsets: Arc<PLMutex<Vec<Instant>>>,
+}
+
+impl AudioSource for Bursts {
+    fn sample_rate(&self) -> u32 {
+        self.tone.sample_rate()
+    }
+
+    fn channels(&self) -> usize {
+        1
+    }
+
+    fn read(&mut self, buf: &mut [f32]) -> usize {
+        let at = self.block % PERIOD_BLOCKS;
+        self.block += 1;
+        if at == 0 {
+            self.onsets.lock().push(Instant::now());
+        }
+        match at < ON_BLOCKS {
+            true => self.tone.read(buf),
+            false => {
+                buf.fill(0.0);
+                buf.len()
+            }
+        }
+    }
+}
+
+/// What a `Listener` heard.
+#[derive(Default)]
+struct Heard {
+    /// When each burst started playing.
+    onsets: Vec<Instant>,
+    /// Over the blocks inside bursts (not their first, which is partial):
+    /// total power, and power at the tone's frequency.
+    power: f64,
+    tone_power: f64,
+    blocks: usize,
+    block_len: usize,
+}
+
+/// Listens for the other side's bursts. Mono at 48 kHz.
+struct Listener {
+    hz: f32,
+    /// The last block was inside a burst.
+    on: bool,
+    heard: Arc<PLMutex<Heard>>,
+}
+
+impl sink::AudioSink for Listener {
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn channels(&self) -> usize {
+        1
+    }
+
+    fn write(&mut self, buf: &[f32]) {
+        let power: f64 = buf.iter().map(|&s| s as f64 * s as f64).sum();
+        let on = (power / buf.len() as f64).sqrt() > ON_RMS as f64;
+        let mut heard = self.heard.lock();
+        match (self.on, on) {
+            (false, true) => heard.onsets.push(Instant::now()),
+            (true, true) => {
+                heard.power += power;
+                heard.tone_power += goertzel(buf, self.hz);
+                heard.blocks += 1;
+                heard.block_len = buf.len();
+            }
+            _ => {}
+        }
+        self.on = on;
+    }
+}
+
+/// Power of `buf` at `hz`, on the same scale as the sum of squares (so a pure
+/// tone of whole cycles gives exactly that sum).
+fn goertzel(buf: &[f32], hz: f32) -> f64 {
+    let coeff = 2.0 * (std::f64::consts::TAU * hz as f64 / SAMPLE_RATE as f64).cos();
+    let (mut s1, mut s2) = (0f64, 0f64);
+    for &x in buf {
+        let s0 = x as f64 + coeff * s1 - s2;
+        s2 = s1;
+        s1 = s0;
+    }
+    (s1 * s1 + s2 * s2 - coeff * s1 * s2) * 2.0 / buf.len() as f64
+}
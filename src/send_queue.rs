@@ -0,0 +1,167 @@
+// ─── Send queue ────────────────────────────────────────────────────────────────
+// Capture → network. When the network can't keep up, the oldest queued message
+// is dropped to make room: audio that has already waited is worth less than
+// what was just spoken, and dropping the newest would add latency on top of
+// the gap. A queue that keeps overflowing for several seconds is reported as
+// backpressure; so is its recovery. How long messages wait goes to the
+// latency tracer.
+//
+// Frames also leave paced, one frame duration apart (a little less, so a
+// capture clock running fast can't build a backlog): audio callbacks often
+// deliver several frames at once, and a burst of them is what a constrained
+// uplink drops. Pacing never holds a frame back more than `MAX_LAG_FRAMES`,
+// nor more than a `MAX_LAG_SHARE` of a latency target; when it would, the
+// schedule starts over from the frame at hand.
+
+use crate::latency::{Stage, Tracer};
+use crate::{codec, Outbound};
+use async_channel::{bounded, Receiver, Sender};
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// About a second of 20 ms frames; more would only be stale.
+const CAPACITY: usize = 50;
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Consecutive seconds with drops before we call it backpressure.
+const SUSTAINED_SECS: u32 = 3;
+/// Paced frames go out this fraction of their duration apart.
+const PACE: f64 = 15.0 / 16.0;
+/// How far pacing may hold a frame back, in frames, and at most this
+/// fraction of a latency target.
+const MAX_LAG_FRAMES: u32 = 2;
+const MAX_LAG_SHARE: u32 = 4;
+
+/// Send‑side counters since the call started.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct SendStats {
+    /// Taken off the queue by the network task.
+    pub sent: u64,
+    /// Dropped, oldest first, because the queue was full.
+    pub dropped: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct Counters {
+    sent: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl Counters {
+    pub fn snapshot(&self) -> SendStats {
+        SendStats {
+            sent: self.sent.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+pub(crate) fn channel(latency: Arc<Tracer>) -> (SendQueue, Outbox, Arc<Counters>) {
+    let (tx, rx) = bounded(CAPACITY);
+    let counters = Arc::new(Counters::default());
+    let queue = SendQueue {
+        tx,
+        counters: Arc::clone(&counters),
+    };
+    let outbox = Outbox {
+        rx,
+        counters: Arc::clone(&counters),
+        latency,
+        next_frame: None,
+        last_check: Instant::now(),
+        last_dropped: 0,
+        congested_secs: 0,
+    };
+    (queue, outbox, counters)
+}
+
+/// The capture side. Never blocks.
+#[derive(Clone)]
+pub(crate) struct SendQueue {
+    tx: Sender<(Instant, Outbound)>,
+    counters: Arc<Counters>,
+}
+
+impl SendQueue {
+    pub fn push(&self, msg: Outbound) {
+        if let Ok(Some(_)) = self.tx.force_send((Instant::now(), msg)) {
+            self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The network side.
+pub(crate) struct Outbox {
+    rx: Receiver<(Instant, Outbound)>,
+    counters: Arc<Counters>,
+    latency: Arc<Tracer>,
+    /// When pacing lets the next frame go.
+    next_frame: Option<Instant>,
+    last_check: Instant,
+    last_dropped: u64,
+    congested_secs: u32,
+}
+
+impl Outbox {
+    /// The next message, paced, or `None` once the capture side is gone.
+    pub async fn recv(&mut self) -> Option<Outbound> {
+        let (queued, msg) = self.rx.recv().await.ok()?;
+        if let Outbound::Frame(frame) = &msg {
+            self.pace(frame).await;
+        }
+        self.latency.record(Stage::SendQueue, queued.elapsed());
+        self.counters.sent.fetch_add(1, Ordering::Relaxed);
+        if self.last_check.elapsed() >= CHECK_INTERVAL {
+            self.last_check = Instant::now();
+            self.check();
+        }
+        Some(msg)
+    }
+
+    /// The next message if one is queued; for callers that run the capture
+    /// side themselves.
+    pub fn try_recv(&mut self) -> Option<Outbound> {
+        let (queued, msg) = self.rx.try_recv().ok()?;
+        self.latency.record(Stage::SendQueue, queued.elapsed());
+        self.counters.sent.fetch_add(1, Ordering::Relaxed);
+        Some(msg)
+    }
+
+    async fn pace(&mut self, frame: &[u8]) {
+        let Some(duration) = codec::packet_duration(frame, crate::SAMPLE_RATE) else {
+            return;
+        };
+        let mut max_lag = duration * MAX_LAG_FRAMES;
+        if let Some(ms) = self.latency.target_ms() {
+            max_lag = max_lag.min(Duration::from_millis((ms / MAX_LAG_SHARE) as u64));
+        }
+        let now = Instant::now();
+        let at = match self.next_frame {
+            Some(next) if next > now && next - now <= max_lag => next,
+            _ => now,
+        };
+        tokio::time::sleep_until(at.into()).await;
+        self.next_frame = Some(at + duration.mul_f64(PACE));
+    }
+
+    fn check(&mut self) {
+        let dropped = self.counters.dropped.load(Ordering::Relaxed);
+        let new = dropped - self.last_dropped;
+        self.last_dropped = dropped;
+        if new > 0 {
+            self.congested_secs += 1;
+            if self.congested_secs == SUSTAINED_SECS {
+                warn!("network can't keep up; dropping the oldest queued audio ({new} frames in the last second)");
+                info!("STATUS: send_backpressure");
+            }
+        } else {
+            if self.congested_secs >= SUSTAINED_SECS {
+                info!("send queue keeping up again ({dropped} frames dropped in total)");
+                info!("STATUS: send_recovered");
+            }
+            self.congested_secs = 0;
+        }
+    }
+}
@@ -0,0 +1,154 @@
+// ─── Service integration ───────────────────────────────────────────────────────
+// For running under systemd with `Type=notify`: `ready` and `stopping` tell
+// the service manager how far we got through `$NOTIFY_SOCKET`, and
+// `activated_listener` takes over a TCP socket the manager opened for us
+// (socket activation, `$LISTEN_FDS`), once it has checked that it is one and
+// is listening, which then serves the health checks in place of
+// `--health-addr`. Both do nothing when not started that way, and on other
+// platforms. `shutdown` resolves on Ctrl‑C or SIGTERM, so
+// `systemctl stop` ends a call, a relay or an echo server the same way Ctrl‑C
+// does.
+
+use tracing::debug;
+
+/// Startup is done; `status` shows in `systemctl status`.
+pub fn ready(status: &str) {
+    notify(&format!("READY=1\nSTATUS={status}"));
+}
+
+/// We were asked to stop and are on our way out.
+pub fn stopping() {
+    notify("STOPPING=1");
+}
+
+#[cfg(unix)]
+fn notify(state: &str) {
+    use std::os::unix::net::UnixDatagram;
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let sent = UnixDatagram::unbound().and_then(|sock| {
+        match path.as_encoded_bytes().strip_prefix(b"@") {
+            // An abstract socket.
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+                sock.send_to_addr(state.as_bytes(), &addr)
+            }
+            _ => sock.send_to(state.as_bytes(), &path),
+        }
+    });
+    if let Err(e) = sent {
+        debug!("sd_notify: {e}");
+    }
+}
+
+#[cfg(not(unix))]
+fn notify(_state: &str) {}
+
+/// The first socket the service manager passed us, if it passed any and it
+/// is a listening TCP socket. Only the first call gets it.
+#[cfg(unix)]
+pub fn activated_listener() -> Option<std::net::TcpListener> {
+    use std::os::fd::FromRawFd;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    const LISTEN_FDS_START: i32 = 3;
+    static TAKEN: AtomicBool = AtomicBool::new(false);
+    // The variables are left set: the environment can't safely be changed
+    // once the runtime's threads are running, and `LISTEN_PID` already tells
+    // any child we start that the sockets aren't theirs.
+    let pid: u32 = std::env::var("LISTEN_PID").ok()?.parse().ok()?;
+    let fds: i32 = std::env::var("LISTEN_FDS").ok()?.parse().ok()?;
+    if pid != std::process::id() || fds < 1 || TAKEN.swap(true, Ordering::Relaxed) {
+        return None;
+    }
+    if fds > 1 {
+        tracing::warn!("socket activation passed {fds} sockets; using the first");
+    }
+    match is_tcp_listener(LISTEN_FDS_START) {
+        Ok(true) => {}
+        Ok(false) => {
+            tracing::warn!("socket activation passed something other than a listening TCP socket");
+            return None;
+        }
+        Err(e) => {
+            tracing::warn!("socket activation: can't inspect the passed socket: {e}");
+            return None;
+        }
+    }
+    // SAFETY: the service manager opened descriptor 3 for this process, it
+    // was just checked to be a listening TCP socket, and `TAKEN` makes this
+    // the only place that takes ownership of it.
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(LISTEN_FDS_START) };
+    // Passed to us without close‑on‑exec; children shouldn't keep it open.
+    if let Err(e) = socket2::SockRef::from(&listener).set_cloexec(true) {
+        debug!("socket activation: can't set close-on-exec: {e}");
+    }
+    Some(listener)
+}
+
+/// Whether `fd` is a TCP socket in the listening state.
+#[cfg(unix)]
+fn is_tcp_listener(fd: i32) -> std::io::Result<bool> {
+    let option = |name| {
+        let mut value: libc::c_int = 0;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        // SAFETY: `value` and `len` are valid for the int the option holds.
+        let r = unsafe {
+            libc::getsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                name,
+                (&mut value as *mut libc::c_int).cast(),
+                &mut len,
+            )
+        };
+        match r {
+            0 => Ok(value),
+            _ => Err(std::io::Error::last_os_error()),
+        }
+    };
+    if option(libc::SO_TYPE)? != libc::SOCK_STREAM || option(libc::SO_ACCEPTCONN)? == 0 {
+        return Ok(false);
+    }
+    // A Unix stream socket passes the above too.
+    // SAFETY: all zeroes is a valid `sockaddr_storage`.
+    let mut addr: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    // SAFETY: `addr` and `len` describe a buffer large enough for any address.
+    let r = unsafe {
+        libc::getsockname(
+            fd,
+            (&mut addr as *mut libc::sockaddr_storage).cast(),
+            &mut len,
+        )
+    };
+    if r != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(matches!(
+        addr.ss_family as libc::c_int,
+        libc::AF_INET | libc::AF_INET6
+    ))
+}
+
+#[cfg(not(unix))]
+pub fn activated_listener() -> Option<std::net::TcpListener> {
+    None
+}
+
+/// Resolves on Ctrl‑C or, on Unix, SIGTERM.
+pub async fn shutdown() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut term = signal(SignalKind::terminate())?;
+        tokio::select! {
+            r = tokio::signal::ctrl_c() => r,
+            _ = term.recv() => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
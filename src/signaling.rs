@@ -0,0 +1,1297 @@
+// ─── Signaling ─────────────────────────────────────────────────────────────────
+// Minimal HTTP rendezvous: POST our candidates to `<server>/join/<room>`, then
+// poll the same URL until the server hands back the other participant.
+//
+// Every request carries the room token as `Authorization: Bearer …`; the server
+// is expected to answer 401/403 for joins without a valid one. Tokens are only
+// ever sent over HTTPS (plain HTTP is allowed for loopback test servers).
+//
+// A broadcaster registers the same way but doesn't wait for a match; it polls
+// `<server>/subscribers/<room>` for the JSON list of everyone who joined since.
+// Servers can also stream the room's roster (see Presence).
+//
+// Rooms can require a passphrase (`with_passphrase`). Joins then carry a
+// `room_tag` the server should compare with the room's first member (403 on a
+// mismatch) and a `room_proof` binding our key and address to the
+// passphrase. We check the proof of whoever the server matches us with, so a
+// server that doesn't check tags still can't pair us with a stranger. The
+// tag lets the server guess the passphrase offline, so weak passphrases are
+// only as safe as the server is trusted. See `crypto::RoomKey`.
+//
+// An `mqtt://` or `mqtts://` URL puts the room on an MQTT broker instead, and
+// a `matrix://` URL in a Matrix room (see Record rooms below). All three can
+// go through a SOCKS5 proxy (see `proxy`).
+//
+// Members can carry a nickname and a little metadata (`Introduction`). Both
+// go into the join, the presence and the records, and the network task also
+// seals them to each peer right after the handshake, so peers found any other
+// way are known by name too.
+//
+// Browsers join HTTP rooms as well (see `web/`), but have no UDP address to
+// offer: their join says `"webrtc": true` and leaves the addresses and key
+// empty. Members that answer WebRTC offers (`with_webrtc`) put the URL of
+// their endpoint in their join as `webrtc_url`, and a browser matched with
+// one POSTs its offer there (see `browser`) instead of punching. Browsers
+// can't prove a passphrase, so they only get into rooms without one. Servers
+// can also give browsers the room as a WebSocket at `<server>/ws/<room>`,
+// carrying the same JSON (see `web/src/room.rs`); it's the same room either
+// way.
+
+use crate::crypto::{RoomKey, PSK_LEN};
+use crate::effects::Controls;
+use crate::{matrix, mqtt, proxy};
+use anyhow::{bail, Context, Result};
+use async_channel::{bounded, Receiver, Sender};
+use parking_lot::Mutex;
+use reqwest::{StatusCode, Url};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{debug, info, warn};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Encoded `Introduction`s must fit one datagram.
+pub const MAX_INTRODUCTION_LEN: usize = 512;
+
+/// What a member tells the others about itself.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Introduction {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nickname: Option<String>,
+    /// Small application data, e.g. an avatar URL or a role.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl Introduction {
+    /// JSON, at most `MAX_INTRODUCTION_LEN` bytes.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let json = serde_json::to_vec(self)?;
+        if json.len() > MAX_INTRODUCTION_LEN {
+            bail!(
+                "nickname and metadata take {} bytes; at most {MAX_INTRODUCTION_LEN} fit",
+                json.len()
+            );
+        }
+        Ok(json)
+    }
+
+    pub fn decode(json: &[u8]) -> Option<Self> {
+        match json.len() {
+            0..=MAX_INTRODUCTION_LEN => serde_json::from_slice(json).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nickname.is_none() && self.metadata.is_empty()
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct JoinPayload {
+    pub reflexive_addr: String,
+    pub lan_addr: String,
+    pub pub_key: String,
+    /// Our address on a TURN server, if we have one (see `turn`).
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub relayed_addr: String,
+}
+
+/// What is actually posted: the payload plus the passphrase fields.
+#[derive(serde::Serialize)]
+struct Join<'a> {
+    #[serde(flatten)]
+    me: &'a JoinPayload,
+    member_id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nickname: Option<&'a str>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    metadata: &'a BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    room_tag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    room_proof: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    webrtc_url: Option<&'a str>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PeerInfo {
+    pub reflexive_addr: String,
+    pub lan_addr: String,
+    pub pub_key: String,
+    #[serde(default)]
+    pub relayed_addr: String,
+    #[serde(default)]
+    pub room_proof: Option<String>,
+    /// A browser, which reaches us through our WebRTC endpoint.
+    #[serde(default)]
+    pub webrtc: bool,
+}
+
+#[derive(Clone)]
+pub struct Signaling {
+    transport: Transport,
+    /// Ours in the roster.
+    member_id: String,
+    nickname: Option<String>,
+    metadata: BTreeMap<String, String>,
+    room: String,
+    token: Option<String>,
+    room_key: Option<RoomKey>,
+    /// Mix the room key into the media handshake too.
+    keys_media: bool,
+    /// Where browsers in the room send their offers.
+    webrtc_url: Option<String>,
+}
+
+#[derive(Clone)]
+enum Transport {
+    Http(Box<Http>),
+    Records(Arc<RecordRoom>),
+}
+
+#[derive(Clone)]
+struct Http {
+    client: reqwest::Client,
+    join_url: Url,
+    subscribers_url: Url,
+    roster_url: Url,
+    presence_url: Url,
+}
+
+impl Signaling {
+    pub fn new(
+        server: &str,
+        room: &str,
+        token: Option<String>,
+        proxy: Option<&proxy::Proxy>,
+    ) -> Result<Self> {
+        let base = Url::parse(server).context("invalid signaling server URL")?;
+        let member_id = format!("{:016x}", rand::random::<u64>());
+        let records = match base.scheme() {
+            "mqtt" | "mqtts" => Some(RecordRoom::mqtt(&base, room, &member_id, &token, proxy)?),
+            "matrix" => Some(RecordRoom::matrix(
+                &base, server, room, &member_id, &token, proxy,
+            )?),
+            _ => None,
+        };
+        if let Some(records) = records {
+            return Ok(Self {
+                transport: Transport::Records(Arc::new(records)),
+                member_id,
+                nickname: None,
+                metadata: BTreeMap::new(),
+                room: room.to_owned(),
+                token,
+                room_key: None,
+                keys_media: false,
+                webrtc_url: None,
+            });
+        }
+        match base.scheme() {
+            "https" => {}
+            "http" if token.is_none() || is_loopback(&base) => {}
+            "http" => bail!("refusing to send the room token over plain HTTP; use https://"),
+            other => bail!("unsupported signaling scheme {other}"),
+        }
+        let join_url = room_url(&base, "join", room)?;
+        let subscribers_url = room_url(&base, "subscribers", room)?;
+        let roster_url = room_url(&base, "roster", room)?;
+        let presence_url = room_url(&base, "presence", room)?;
+        Ok(Self {
+            transport: Transport::Http(Box::new(Http {
+                client: proxy::http_client(proxy)?,
+                join_url,
+                subscribers_url,
+                roster_url,
+                presence_url,
+            })),
+            member_id,
+            nickname: None,
+            metadata: BTreeMap::new(),
+            room: room.to_owned(),
+            token,
+            room_key: None,
+            keys_media: false,
+            webrtc_url: None,
+        })
+    }
+
+    /// Requires everyone in the room to know `passphrase`; with `keys_media`
+    /// it also keys the call, so the passphrase is needed to hear it.
+    pub fn with_passphrase(mut self, passphrase: &str, keys_media: bool) -> Self {
+        self.room_key = Some(RoomKey::derive(&self.room, passphrase));
+        self.keys_media = keys_media;
+        self
+    }
+
+    /// Lets browsers in the room call us through the WebRTC endpoint at
+    /// `url` (see `browser`), which must be reachable from their pages.
+    pub fn with_webrtc(mut self, url: &str) -> Self {
+        self.webrtc_url = Some(url.to_owned());
+        self
+    }
+
+    /// Invites `users` into the room when we start calling; Matrix rooms
+    /// only.
+    pub fn with_invites(self, users: &[String]) -> Result<Self> {
+        if users.is_empty() {
+            return Ok(self);
+        }
+        match &self.transport {
+            Transport::Records(room) => match &room.store {
+                Store::Matrix(store) => store.invites.lock().extend_from_slice(users),
+                Store::Mqtt(_) => bail!("only Matrix rooms can invite users"),
+            },
+            Transport::Http(_) => bail!("only Matrix rooms can invite users"),
+        }
+        Ok(self)
+    }
+
+    /// Pre‑shared key for the media handshake, if the room keys media.
+    pub fn media_psk(&self) -> Option<[u8; PSK_LEN]> {
+        self.room_key
+            .as_ref()
+            .filter(|_| self.keys_media)
+            .map(RoomKey::psk)
+    }
+
+    /// Whether `peer` may be talked to: always without a passphrase.
+    fn admits(&self, peer: &PeerInfo) -> bool {
+        let Some(key) = &self.room_key else {
+            return true;
+        };
+        peer.room_proof
+            .as_deref()
+            .is_some_and(|proof| key.verify(&peer.pub_key, &peer.reflexive_addr, proof))
+    }
+
+    fn request_to(
+        &self,
+        http: &Http,
+        method: reqwest::Method,
+        url: &Url,
+    ) -> reqwest::RequestBuilder {
+        let req = http.client.request(method, url.clone());
+        match &self.token {
+            Some(token) => req.bearer_auth(token),
+            None => req,
+        }
+    }
+
+    fn check(&self, resp: reqwest::Response) -> Result<reqwest::Response> {
+        match resp.status() {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => bail!(
+                "signaling server rejected our credentials for room {}",
+                self.room
+            ),
+            _ => Ok(resp.error_for_status()?),
+        }
+    }
+
+    pub async fn register(&self, me: &JoinPayload) -> Result<()> {
+        let join = Join {
+            me,
+            member_id: &self.member_id,
+            nickname: self.nickname.as_deref(),
+            metadata: &self.metadata,
+            room_tag: self.room_key.as_ref().map(RoomKey::tag),
+            room_proof: self
+                .room_key
+                .as_ref()
+                .map(|key| key.proof(&me.pub_key, &me.reflexive_addr)),
+            webrtc_url: self.webrtc_url.as_deref(),
+        };
+        let http = match &self.transport {
+            Transport::Http(http) => http,
+            Transport::Records(room) => {
+                room.announce(&join);
+                return Ok(());
+            }
+        };
+        let resp = self
+            .request_to(http, reqwest::Method::POST, &http.join_url)
+            .json(&join)
+            .send()
+            .await?;
+        self.check(resp)?;
+        Ok(())
+    }
+
+    pub async fn register_and_wait(&self, me: &JoinPayload) -> Result<PeerInfo> {
+        self.register(me).await?;
+        let http = match &self.transport {
+            Transport::Http(http) => http,
+            Transport::Records(room) => return Ok(room.wait_for_peer(|p| self.admits(p)).await),
+        };
+
+        loop {
+            let resp = self
+                .request_to(http, reqwest::Method::GET, &http.join_url)
+                .send()
+                .await?;
+            if let Some(p) = self.check(resp)?.json::<Option<PeerInfo>>().await? {
+                if !self.admits(&p) {
+                    bail!(
+                        "the peer in room {} doesn't know the room passphrase",
+                        self.room
+                    );
+                }
+                if p.webrtc && self.webrtc_url.is_none() {
+                    bail!(
+                        "a browser joined room {}, but we don't answer WebRTC offers",
+                        self.room
+                    );
+                }
+                return Ok(p);
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Everyone who joined the room as a listener of our broadcast.
+    pub async fn subscribers(&self) -> Result<Vec<PeerInfo>> {
+        let mut list: Vec<PeerInfo> = match &self.transport {
+            Transport::Http(http) => {
+                let resp = self
+                    .request_to(http, reqwest::Method::GET, &http.subscribers_url)
+                    .send()
+                    .await?;
+                self.check(resp)?.json().await?
+            }
+            Transport::Records(room) => room.subscribers(),
+        };
+        list.retain(|p| {
+            let admitted = self.admits(p);
+            if !admitted {
+                warn!(
+                    "ignoring subscriber {} without the room passphrase",
+                    p.reflexive_addr
+                );
+            }
+            admitted
+        });
+        Ok(list)
+    }
+}
+
+/// `<base>/<kind>/<room>`, with the room one percent‑encoded path segment
+/// whatever characters it holds.
+fn room_url(base: &Url, kind: &str, room: &str) -> Result<Url> {
+    // `push` skips these rather than encoding them.
+    if matches!(room, "" | "." | "..") {
+        bail!("invalid room name {room:?}");
+    }
+    let mut url = base.clone();
+    url.set_query(None);
+    url.set_fragment(None);
+    url.path_segments_mut()
+        .map_err(|()| anyhow::anyhow!("invalid signaling server URL"))?
+        .pop_if_empty()
+        .push(kind)
+        .push(room);
+    Ok(url)
+}
+
+fn is_loopback(url: &Url) -> bool {
+    match url.host_str() {
+        Some("localhost") => true,
+        Some(host) => host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback()),
+        None => false,
+    }
+}
+
+// ─── Presence ──────────────────────────────────────────────────────────────────
+// Servers that support it stream the room's roster from `<server>/roster/
+// <room>` as server‑sent events: `event: roster` with the whole member list as
+// JSON on connect and whenever it changes, or `event: join` / `leave` /
+// `update` with a single member. We diff against what we knew and hand out
+// join, leave, rename and mute events, reconnecting if the stream drops.
+// Our own nickname, metadata and mute state go to `<server>/presence/<room>`; the
+// server is expected to drop members whose roster stream disconnects.
+
+/// Sent when nothing else is, by well‑behaved servers; we give up waiting
+/// after this long and reconnect.
+const ROSTER_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+const ROSTER_RETRY_MAX: Duration = Duration::from_secs(30);
+const ROSTER_EVENTS: usize = 256;
+const PRESENCE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Member {
+    /// Picked at random by the member for this session.
+    pub id: String,
+    #[serde(default)]
+    pub nickname: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+    #[serde(default)]
+    pub muted: bool,
+}
+
+impl Member {
+    /// The nickname, or the id for members without one.
+    pub fn name(&self) -> &str {
+        self.nickname.as_deref().unwrap_or(&self.id)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum RosterEvent {
+    Joined(Member),
+    Left(Member),
+    Renamed {
+        member: Member,
+        old: Option<String>,
+    },
+    MuteChanged(Member),
+    MetadataChanged(Member),
+    /// A peer sent its nickname and metadata over the media path, or
+    /// changed them.
+    Introduced {
+        peer: SocketAddr,
+        introduction: Introduction,
+    },
+}
+
+/// The room's other members, as the signaling server last told us, and the
+/// peers that introduced themselves.
+pub(crate) struct Roster {
+    members: Mutex<BTreeMap<String, Member>>,
+    peers: Mutex<HashMap<SocketAddr, Introduction>>,
+    tx: Sender<RosterEvent>,
+    rx: Receiver<RosterEvent>,
+}
+
+impl Roster {
+    pub fn new() -> Self {
+        let (tx, rx) = bounded(ROSTER_EVENTS);
+        Self {
+            members: Mutex::default(),
+            peers: Mutex::default(),
+            tx,
+            rx,
+        }
+    }
+
+    pub fn members(&self) -> Vec<Member> {
+        self.members.lock().values().cloned().collect()
+    }
+
+    pub fn events(&self) -> Receiver<RosterEvent> {
+        self.rx.clone()
+    }
+
+    pub fn introduction(&self, peer: SocketAddr) -> Option<Introduction> {
+        self.peers.lock().get(&peer).cloned()
+    }
+
+    /// `peer` with its nickname, for logs and prompts.
+    pub fn label(&self, peer: SocketAddr) -> String {
+        match self
+            .peers
+            .lock()
+            .get(&peer)
+            .and_then(|i| i.nickname.clone())
+        {
+            Some(name) => format!("{name} ({peer})"),
+            None => peer.to_string(),
+        }
+    }
+
+    /// Records what `peer` says about itself.
+    pub fn introduce(&self, peer: SocketAddr, introduction: Introduction) {
+        let old = self.peers.lock().insert(peer, introduction.clone());
+        if old.as_ref() == Some(&introduction) {
+            return;
+        }
+        let name = introduction.nickname.as_deref().unwrap_or("");
+        info!(%peer, "STATUS: peer_introduced {peer} {name}");
+        let _ = self
+            .tx
+            .force_send(RosterEvent::Introduced { peer, introduction });
+    }
+
+    /// Replaces the roster with `now` and reports the differences.
+    fn update(&self, now: BTreeMap<String, Member>) {
+        let before = std::mem::replace(&mut *self.members.lock(), now.clone());
+        let mut events = Vec::new();
+        for (id, old) in &before {
+            match now.get(id) {
+                None => events.push(RosterEvent::Left(old.clone())),
+                Some(new) => {
+                    if new.nickname != old.nickname {
+                        events.push(RosterEvent::Renamed {
+                            member: new.clone(),
+                            old: old.nickname.clone(),
+                        });
+                    }
+                    if new.muted != old.muted {
+                        events.push(RosterEvent::MuteChanged(new.clone()));
+                    }
+                    if new.metadata != old.metadata {
+                        events.push(RosterEvent::MetadataChanged(new.clone()));
+                    }
+                }
+            }
+        }
+        for (id, new) in &now {
+            if !before.contains_key(id) {
+                events.push(RosterEvent::Joined(new.clone()));
+            }
+        }
+        for event in events {
+            match &event {
+                RosterEvent::Joined(m) => {
+                    info!(member = %m.id, "STATUS: member_joined {}", m.name())
+                }
+                RosterEvent::Left(m) => info!(member = %m.id, "STATUS: member_left {}", m.name()),
+                RosterEvent::Renamed { member: m, .. } => {
+                    info!(member = %m.id, "STATUS: member_renamed {}", m.name())
+                }
+                RosterEvent::MuteChanged(m) => {
+                    info!(member = %m.id, muted = m.muted, "STATUS: member_muted {} {}", m.name(), m.muted)
+                }
+                RosterEvent::MetadataChanged(m) => {
+                    info!(member = %m.id, "STATUS: member_metadata {}", m.name())
+                }
+                RosterEvent::Introduced { .. } => {}
+            }
+            // Oldest first out if nobody is listening.
+            let _ = self.tx.force_send(event);
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+struct Presence<'a> {
+    id: &'a str,
+    nickname: Option<&'a str>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    metadata: &'a BTreeMap<String, String>,
+    muted: bool,
+}
+
+impl Signaling {
+    /// Shown to the other members of the room.
+    pub fn with_nickname(mut self, nickname: &str) -> Self {
+        self.nickname = Some(nickname.to_owned());
+        self
+    }
+
+    /// Key/value pairs shown with our nickname; keep them small.
+    pub fn with_metadata(mut self, metadata: BTreeMap<String, String>) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Keeps `roster` current and publishes our mute state until the roster
+    /// is dropped.
+    pub(crate) async fn presence_task(self, effects: Arc<Controls>, roster: Weak<Roster>) {
+        let http = match &self.transport {
+            Transport::Http(http) => http,
+            Transport::Records(room) => return room.presence_task(effects, roster).await,
+        };
+        let publish = async {
+            let mut tick = tokio::time::interval(PRESENCE_CHECK_INTERVAL);
+            let mut published = None;
+            loop {
+                tick.tick().await;
+                if roster.strong_count() == 0 {
+                    return;
+                }
+                let muted = effects.muted();
+                if published == Some(muted) {
+                    continue;
+                }
+                match self.publish(http, muted).await {
+                    Ok(()) => published = Some(muted),
+                    Err(e) => debug!("presence: {e}"),
+                }
+            }
+        };
+        tokio::select! {
+            () = publish => {}
+            () = self.watch_roster(http, &roster) => {}
+        }
+    }
+
+    async fn publish(&self, http: &Http, muted: bool) -> Result<()> {
+        let presence = Presence {
+            id: &self.member_id,
+            nickname: self.nickname.as_deref(),
+            metadata: &self.metadata,
+            muted,
+        };
+        let resp = self
+            .request_to(http, reqwest::Method::POST, &http.presence_url)
+            .json(&presence)
+            .send()
+            .await?;
+        // Not supported; the roster stream will say so.
+        if resp.status() != StatusCode::NOT_FOUND {
+            self.check(resp)?;
+        }
+        Ok(())
+    }
+
+    async fn watch_roster(&self, http: &Http, roster: &Weak<Roster>) {
+        let mut retry = Duration::from_secs(1);
+        loop {
+            match self.stream_roster(http, roster).await {
+                Ok(Stream::Gone) => return,
+                Ok(Stream::Unsupported) => {
+                    info!("signaling server has no roster; members show up as they connect");
+                    return;
+                }
+                Ok(Stream::Ended) => retry = Duration::from_secs(1),
+                Err(e) => warn!("roster stream: {e}"),
+            }
+            tokio::time::sleep(retry).await;
+            retry = (retry * 2).min(ROSTER_RETRY_MAX);
+        }
+    }
+
+    async fn stream_roster(&self, http: &Http, roster: &Weak<Roster>) -> Result<Stream> {
+        let resp = self
+            .request_to(http, reqwest::Method::GET, &http.roster_url)
+            .header(reqwest::header::ACCEPT, "text/event-stream")
+            .send()
+            .await?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Ok(Stream::Unsupported);
+        }
+        let mut resp = self.check(resp)?;
+        let mut buf = Vec::new();
+        let mut event = SseEvent::default();
+        loop {
+            let chunk = tokio::time::timeout(ROSTER_IDLE_TIMEOUT, resp.chunk())
+                .await
+                .context("roster stream went quiet")??;
+            let Some(chunk) = chunk else {
+                return Ok(Stream::Ended);
+            };
+            let Some(roster) = roster.upgrade() else {
+                return Ok(Stream::Gone);
+            };
+            buf.extend_from_slice(&chunk);
+            while let Some(end) = buf.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = buf.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line);
+                let line = line.trim_end_matches(['\r', '\n']);
+                if line.is_empty() {
+                    let done = std::mem::take(&mut event);
+                    self.apply(&roster, &done.name, &done.data);
+                } else if let Some(name) = line.strip_prefix("event:") {
+                    event.name = name.trim_start().to_owned();
+                } else if let Some(data) = line.strip_prefix("data:") {
+                    if !event.data.is_empty() {
+                        event.data.push('\n');
+                    }
+                    event.data.push_str(data.strip_prefix(' ').unwrap_or(data));
+                }
+                // Comments (": keep‑alive") and other fields are ignored.
+            }
+        }
+    }
+
+    fn apply(&self, roster: &Roster, event: &str, data: &str) {
+        let mut members = roster.members.lock().clone();
+        let parsed = match event {
+            "roster" => serde_json::from_str::<Vec<Member>>(data).map(|list| {
+                members = list.into_iter().map(|m| (m.id.clone(), m)).collect();
+            }),
+            "join" | "update" => serde_json::from_str::<Member>(data).map(|m| {
+                members.insert(m.id.clone(), m);
+            }),
+            "leave" => serde_json::from_str::<Member>(data).map(|m| {
+                members.remove(&m.id);
+            }),
+            _ => return,
+        };
+        if let Err(e) = parsed {
+            warn!("roster stream: bad {event} event: {e}");
+            return;
+        }
+        members.remove(&self.member_id);
+        roster.update(members);
+    }
+}
+
+enum Stream {
+    /// The server closed it; reconnect.
+    Ended,
+    /// The session is over.
+    Gone,
+    Unsupported,
+}
+
+#[derive(Default)]
+struct SseEvent {
+    name: String,
+    data: String,
+}
+
+// ─── Record rooms ──────────────────────────────────────────────────────────────
+// MQTT brokers and Matrix rooms have no join endpoint to pair us, but both
+// keep one record per member that everyone can read: a retained message, a
+// state event. Each member writes its own – candidates, key, passphrase
+// proof, nickname, metadata, mute state – and watches everyone else's. A caller takes
+// the room's broadcaster if it has one, else the other member with the
+// lowest id; a broadcaster serves everyone else. The records double as the
+// roster. Anyone who can write to the room can claim to be a member, so use
+// the store's access control or a room passphrase.
+
+/// A member's record.
+#[derive(Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+struct Announcement {
+    reflexive_addr: String,
+    lan_addr: String,
+    pub_key: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    relayed_addr: String,
+    #[serde(default)]
+    nickname: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    metadata: BTreeMap<String, String>,
+    #[serde(default)]
+    muted: bool,
+    #[serde(default)]
+    room_proof: Option<String>,
+    /// Unix ms after which a record that wasn't refreshed is stale; for
+    /// stores that can't remove it when we vanish.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_ts: Option<u64>,
+}
+
+impl Announcement {
+    fn peer(&self) -> PeerInfo {
+        PeerInfo {
+            reflexive_addr: self.reflexive_addr.clone(),
+            lan_addr: self.lan_addr.clone(),
+            pub_key: self.pub_key.clone(),
+            relayed_addr: self.relayed_addr.clone(),
+            room_proof: self.room_proof.clone(),
+            webrtc: false,
+        }
+    }
+
+    /// Broadcasters key every listener separately, so they announce no key.
+    fn is_broadcaster(&self) -> bool {
+        self.pub_key.is_empty()
+    }
+}
+
+enum Store {
+    Mqtt(Arc<MqttStore>),
+    Matrix(Arc<MatrixStore>),
+}
+
+struct RecordRoom {
+    store: Store,
+    member_id: String,
+    started: AtomicBool,
+    /// Our record once registered; the store task writes every change.
+    ours: watch::Sender<Option<Announcement>>,
+    /// Everyone else's, by member id.
+    members: watch::Sender<BTreeMap<String, Announcement>>,
+}
+
+impl RecordRoom {
+    fn new(store: Store, member_id: &str) -> Self {
+        Self {
+            store,
+            member_id: member_id.to_owned(),
+            started: AtomicBool::new(false),
+            ours: watch::Sender::new(None),
+            members: watch::Sender::new(BTreeMap::new()),
+        }
+    }
+
+    /// Connects, the first time anyone needs the room.
+    fn start(self: &Arc<Self>) {
+        if self.started.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        let (room, ours) = (Arc::downgrade(self), self.ours.subscribe());
+        match &self.store {
+            Store::Mqtt(store) => tokio::spawn(mqtt_task(room, Arc::clone(store), ours)),
+            Store::Matrix(store) => tokio::spawn(matrix_task(room, Arc::clone(store), ours)),
+        };
+    }
+
+    fn announce(self: &Arc<Self>, join: &Join) {
+        let record = Announcement {
+            reflexive_addr: join.me.reflexive_addr.clone(),
+            lan_addr: join.me.lan_addr.clone(),
+            pub_key: join.me.pub_key.clone(),
+            relayed_addr: join.me.relayed_addr.clone(),
+            nickname: join.nickname.map(String::from),
+            metadata: join.metadata.clone(),
+            muted: self.ours.borrow().as_ref().is_some_and(|a| a.muted),
+            room_proof: join.room_proof.clone(),
+            expires_ts: None,
+        };
+        self.ours.send_replace(Some(record));
+        self.start();
+    }
+
+    async fn wait_for_peer(self: &Arc<Self>, admits: impl Fn(&PeerInfo) -> bool) -> PeerInfo {
+        self.start();
+        let mut members = self.members.subscribe();
+        let mut refused = HashSet::new();
+        loop {
+            {
+                let members = members.borrow_and_update();
+                let admitted: Vec<_> = members
+                    .iter()
+                    .filter(|(id, a)| {
+                        let ok = admits(&a.peer());
+                        if !ok && refused.insert((*id).clone()) {
+                            warn!("ignoring member {id} of the room without the room passphrase");
+                        }
+                        ok
+                    })
+                    .collect();
+                let pick = admitted.iter().find(|(_, a)| a.is_broadcaster());
+                if let Some((id, a)) = pick.or(admitted.first()) {
+                    if admitted.len() > 1 {
+                        info!("room has {} other members; calling {id}", admitted.len());
+                    }
+                    return a.peer();
+                }
+            }
+            // Our own sender is alive, so this can't fail.
+            let _ = members.changed().await;
+        }
+    }
+
+    fn subscribers(&self) -> Vec<PeerInfo> {
+        self.members
+            .borrow()
+            .values()
+            .filter(|a| !a.is_broadcaster())
+            .map(Announcement::peer)
+            .collect()
+    }
+
+    async fn presence_task(self: &Arc<Self>, effects: Arc<Controls>, roster: Weak<Roster>) {
+        self.start();
+        let mut members = self.members.subscribe();
+        let mut tick = tokio::time::interval(PRESENCE_CHECK_INTERVAL);
+        loop {
+            let Some(roster) = roster.upgrade() else {
+                return;
+            };
+            let muted = effects.muted();
+            self.ours.send_if_modified(|ours| match ours {
+                Some(a) if a.muted != muted => {
+                    a.muted = muted;
+                    true
+                }
+                _ => false,
+            });
+            let now = members
+                .borrow_and_update()
+                .iter()
+                .map(|(id, a)| {
+                    let member = Member {
+                        id: id.clone(),
+                        nickname: a.nickname.clone(),
+                        metadata: a.metadata.clone(),
+                        muted: a.muted,
+                    };
+                    (id.clone(), member)
+                })
+                .collect();
+            roster.update(now);
+            drop(roster);
+            tokio::select! {
+                _ = tick.tick() => {}
+                _ = members.changed() => {}
+            }
+        }
+    }
+
+    /// Someone else's record changed; `None` removes it.
+    fn record(&self, id: &str, record: Option<Announcement>) {
+        if id == self.member_id {
+            return;
+        }
+        self.members.send_if_modified(|m| match record {
+            None => m.remove(id).is_some(),
+            Some(a) => m.insert(id.to_owned(), a.clone()) != Some(a),
+        });
+    }
+}
+
+// ─── MQTT ──────────────────────────────────────────────────────────────────────
+// For self‑hosters who already run a broker (Mosquitto and friends). Records
+// are retained JSON messages at `<prefix>/<room>/members/<member id>`,
+// where the prefix is the URL's path (`audio-p2p` if empty), and a last will
+// clears ours if we drop off without saying goodbye.
+//
+// The token, if any, is the MQTT password (the username comes from the URL,
+// `audio-p2p` if it has none); like HTTP, plain `mqtt://` only carries one
+// to a loopback broker.
+
+const MQTT_KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+struct MqttStore {
+    options: mqtt::Options,
+    /// `<prefix>/<room>/members/`
+    topic: String,
+    /// Where our record goes.
+    own_topic: String,
+}
+
+impl RecordRoom {
+    fn mqtt(
+        url: &Url,
+        room: &str,
+        member_id: &str,
+        token: &Option<String>,
+        proxy: Option<&proxy::Proxy>,
+    ) -> Result<Self> {
+        let tls = url.scheme() == "mqtts";
+        if !tls && token.is_some() && !is_loopback(url) {
+            bail!("refusing to send the room token over plain MQTT; use mqtts://");
+        }
+        if room.is_empty() || room.contains(['/', '+', '#']) {
+            bail!("invalid room name");
+        }
+        let host = url
+            .host_str()
+            .context("no broker host in the signaling URL")?
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_owned();
+        let prefix = match url.path().trim_matches('/') {
+            "" => "audio-p2p",
+            path => path,
+        };
+        let topic = format!("{prefix}/{room}/members/");
+        let own_topic = format!("{topic}{member_id}");
+        let password = token.clone().or(url.password().map(String::from));
+        // MQTT 3.1.1 allows no password without a username.
+        let username = match url.username() {
+            "" => password.as_ref().map(|_| "audio-p2p".to_owned()),
+            user => Some(user.to_owned()),
+        };
+        let options = mqtt::Options {
+            host,
+            port: url.port().unwrap_or(if tls { 8883 } else { 1883 }),
+            tls,
+            client_id: format!("ap2p-{member_id}"),
+            username,
+            password,
+            will: Some(mqtt::Message {
+                topic: own_topic.clone(),
+                payload: Vec::new(),
+                retain: true,
+            }),
+            keep_alive: MQTT_KEEP_ALIVE,
+            proxy: proxy.cloned(),
+        };
+        let store = MqttStore {
+            options,
+            topic,
+            own_topic,
+        };
+        Ok(Self::new(Store::Mqtt(Arc::new(store)), member_id))
+    }
+}
+
+/// Keeps the broker connection up until the room is dropped, then withdraws
+/// our record.
+async fn mqtt_task(
+    room: Weak<RecordRoom>,
+    store: Arc<MqttStore>,
+    mut ours: watch::Receiver<Option<Announcement>>,
+) {
+    let mut retry = Duration::from_secs(1);
+    loop {
+        match mqtt_session(&room, &store, &mut ours, &mut retry).await {
+            Ok(()) => return,
+            Err(e) => warn!("mqtt signaling: {e:#}"),
+        }
+        if room.strong_count() == 0 {
+            return;
+        }
+        tokio::time::sleep(retry).await;
+        retry = (retry * 2).min(ROSTER_RETRY_MAX);
+    }
+}
+
+async fn mqtt_session(
+    room: &Weak<RecordRoom>,
+    store: &MqttStore,
+    ours: &mut watch::Receiver<Option<Announcement>>,
+    retry: &mut Duration,
+) -> Result<()> {
+    let MqttStore {
+        options,
+        topic,
+        own_topic,
+    } = store;
+    let record = |payload: Vec<u8>| mqtt::Message {
+        topic: own_topic.clone(),
+        payload,
+        retain: true,
+    };
+    let mut conn = mqtt::Connection::connect(options).await?;
+    conn.subscribe(&format!("{topic}+")).await?;
+    *retry = Duration::from_secs(1);
+    info!(
+        "mqtt signaling: connected to {}:{}",
+        options.host, options.port
+    );
+    // Anything registered before (or while we were disconnected).
+    ours.mark_changed();
+    let mut ping = tokio::time::interval(options.keep_alive);
+    loop {
+        tokio::select! {
+            changed = ours.changed() => {
+                if changed.is_err() {
+                    // The session is over: take our record down and leave.
+                    conn.publish(&record(Vec::new())).await?;
+                    conn.disconnect().await;
+                    return Ok(());
+                }
+                let current = ours.borrow_and_update().clone();
+                if let Some(a) = current {
+                    conn.publish(&record(serde_json::to_vec(&a)?)).await?;
+                }
+            }
+            message = conn.next() => {
+                let message = message?;
+                let (Some(id), Some(room)) = (message.topic.strip_prefix(topic.as_str()), room.upgrade()) else {
+                    continue;
+                };
+                let a = match message.payload.is_empty() {
+                    true => None,
+                    false => match serde_json::from_slice::<Announcement>(&message.payload) {
+                        Ok(a) => Some(a),
+                        Err(e) => {
+                            debug!("mqtt signaling: bad record for {id}: {e}");
+                            continue;
+                        }
+                    },
+                };
+                room.record(id, a);
+            }
+            _ = ping.tick() => {
+                if conn.idle() > options.keep_alive * 2 {
+                    bail!("the broker stopped answering");
+                }
+                conn.ping().await?;
+            }
+        }
+    }
+}
+
+// ─── Matrix ────────────────────────────────────────────────────────────────────
+// For communities that already meet in Matrix: `--signal-url matrix://
+// <homeserver>` with `--room '#calls:example.org'` (or a room id) and a
+// Matrix access token as the token. Records are `org.audio_p2p.member`
+// state events keyed by member id, read through `/sync`; the room's power
+// levels must let members send that event type (by default only moderators
+// may send state). Matrix can't clear a record when we vanish, so records
+// carry an expiry we keep pushing back.
+//
+// The first member to register posts a message saying how to join, so the
+// call shows up in everyone's client; `with_invites` also invites users into
+// the room first.
+
+const MATRIX_EVENT: &str = "org.audio_p2p.member";
+const MATRIX_SYNC_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long a record stays valid; we rewrite ours at half this.
+const MATRIX_RECORD_TTL: Duration = Duration::from_secs(600);
+
+struct MatrixStore {
+    client: matrix::Client,
+    /// As given: id or alias.
+    room: String,
+    /// The signaling URL, for the join instructions.
+    url: String,
+    /// Taken by the first session to register.
+    invites: Mutex<Vec<String>>,
+}
+
+impl RecordRoom {
+    fn matrix(
+        url: &Url,
+        server: &str,
+        room: &str,
+        member_id: &str,
+        token: &Option<String>,
+        proxy: Option<&proxy::Proxy>,
+    ) -> Result<Self> {
+        let token = token
+            .clone()
+            .context("signaling over Matrix needs an access token")?;
+        if !room.starts_with(['!', '#']) || !room.contains(':') {
+            bail!("Matrix rooms look like #alias:server or !id:server");
+        }
+        let host = url
+            .host_str()
+            .context("no homeserver in the signaling URL")?;
+        // Like HTTP signaling: TLS except to a loopback test server.
+        let scheme = if is_loopback(url) { "http" } else { "https" };
+        let port = url.port().map(|p| format!(":{p}")).unwrap_or_default();
+        let base = Url::parse(&format!("{scheme}://{host}{port}{}", url.path()))
+            .context("invalid homeserver URL")?;
+        let store = MatrixStore {
+            client: matrix::Client::new(proxy::http_client(proxy)?, base, token),
+            room: room.to_owned(),
+            url: server.to_owned(),
+            invites: Mutex::default(),
+        };
+        Ok(Self::new(Store::Matrix(Arc::new(store)), member_id))
+    }
+}
+
+/// Follows the room until it is dropped, then clears our record.
+async fn matrix_task(
+    room: Weak<RecordRoom>,
+    store: Arc<MatrixStore>,
+    mut ours: watch::Receiver<Option<Announcement>>,
+) {
+    let mut retry = Duration::from_secs(1);
+    loop {
+        match matrix_session(&room, &store, &mut ours, &mut retry).await {
+            Ok(()) => return,
+            Err(e) => warn!("matrix signaling: {e:#}"),
+        }
+        if room.strong_count() == 0 {
+            return;
+        }
+        tokio::time::sleep(retry).await;
+        retry = (retry * 2).min(ROSTER_RETRY_MAX);
+    }
+}
+
+async fn matrix_session(
+    room: &Weak<RecordRoom>,
+    store: &MatrixStore,
+    ours: &mut watch::Receiver<Option<Announcement>>,
+    retry: &mut Duration,
+) -> Result<()> {
+    let client = &store.client;
+    let Some(member_id) = room.upgrade().map(|r| r.member_id.clone()) else {
+        return Ok(());
+    };
+    let room_id = client.join(&store.room).await?;
+    let (mut since, events) = client
+        .sync(&room_id, MATRIX_EVENT, None, Duration::ZERO)
+        .await?;
+    matrix_records(room, events);
+    *retry = Duration::from_secs(1);
+    info!("matrix signaling: following {}", store.room);
+    ours.mark_changed();
+    let ttl = MATRIX_RECORD_TTL;
+    let mut refresh = tokio::time::interval_at(tokio::time::Instant::now() + ttl / 2, ttl / 2);
+    let mut written: Option<Announcement> = None;
+    loop {
+        let write = tokio::select! {
+            changed = ours.changed() => {
+                if changed.is_err() {
+                    // The session is over: clear our record.
+                    let gone = serde_json::json!({});
+                    client.put_state(&room_id, MATRIX_EVENT, &member_id, &gone).await?;
+                    return Ok(());
+                }
+                ours.borrow_and_update().clone()
+            }
+            _ = refresh.tick() => written.clone(),
+            synced = client.sync(&room_id, MATRIX_EVENT, Some(&since), MATRIX_SYNC_TIMEOUT) => {
+                let (next, events) = synced?;
+                since = next;
+                matrix_records(room, events);
+                None
+            }
+        };
+        if let Some(mut a) = write {
+            a.expires_ts = Some(unix_ms() + ttl.as_millis() as u64);
+            client
+                .put_state(&room_id, MATRIX_EVENT, &member_id, &a)
+                .await?;
+            if written.replace(a).is_none() {
+                let alone = room
+                    .upgrade()
+                    .is_some_and(|r| r.members.borrow().is_empty());
+                announce_call(store, &room_id, alone).await;
+            }
+        }
+        // Members that vanished without clearing their records.
+        if let Some(room) = room.upgrade() {
+            let now = unix_ms();
+            room.members.send_if_modified(|m| {
+                let before = m.len();
+                m.retain(|_, a| a.expires_ts.is_none_or(|t| t > now));
+                m.len() != before
+            });
+        }
+    }
+}
+
+fn matrix_records(room: &Weak<RecordRoom>, events: Vec<matrix::StateEvent>) {
+    let Some(room) = room.upgrade() else {
+        return;
+    };
+    let now = unix_ms();
+    for event in events {
+        // Cleared records are `{}`.
+        let a = serde_json::from_value::<Announcement>(event.content)
+            .ok()
+            .filter(|a| a.expires_ts.is_none_or(|t| t > now))
+            .map(|mut a| {
+                a.nickname.get_or_insert(event.sender);
+                a
+            });
+        room.record(&event.state_key, a);
+    }
+}
+
+/// Invites anyone we were asked to and, if we're first, tells the room how
+/// to join.
+async fn announce_call(store: &MatrixStore, room_id: &str, first: bool) {
+    let invites = std::mem::take(&mut *store.invites.lock());
+    for user in invites {
+        match store.client.invite(room_id, &user).await {
+            Ok(()) => info!("matrix signaling: invited {user}"),
+            Err(e) => warn!("matrix signaling: couldn't invite {user}: {e:#}"),
+        }
+    }
+    if !first {
+        return;
+    }
+    let body = format!(
+        "Started a voice call. Join with: voice-chat --signal-url {} --room '{}'",
+        store.url, store.room
+    );
+    let content = serde_json::json!({
+        "msgtype": "m.text",
+        "body": body,
+        "org.audio_p2p.call": { "room": store.room },
+    });
+    if let Err(e) = store.client.send_message(room_id, &content).await {
+        warn!("matrix signaling: couldn't announce the call: {e:#}");
+    }
+}
+
+fn unix_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
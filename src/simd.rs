@@ -0,0 +1,222 @@
+// ─── SIMD kernels ──────────────────────────────────────────────────────────────
+// The per‑sample loops that run on every audio callback: device samples to
+// f32, interleaved input folded down to mono, and one signal added into
+// another (the recorder's mix). On x86‑64 each takes an AVX2 version when the
+// CPU has one (checked at run time, the answer cached by std); on aarch64 NEON
+// is always there. Other targets, channel counts other than stereo, and the
+// tail shorter than a vector go through `scalar`, which is also what the
+// vector versions must match and what `benches/simd.rs` measures them
+// against.
+
+const I16_SCALE: f32 = 1.0 / i16::MAX as f32;
+const I32_SCALE: f32 = 1.0 / i32::MAX as f32;
+
+/// `src` to ±1.0 into `dst`, as far as the shorter of the two goes.
+pub fn i16_to_f32(src: &[i16], dst: &mut [f32]) {
+    #[cfg(target_arch = "x86_64")]
+    if avx2() {
+        // SAFETY: the CPU has AVX2.
+        return unsafe { x86::i16_to_f32(src, dst) };
+    }
+    #[cfg(target_arch = "aarch64")]
+    neon::i16_to_f32(src, dst);
+    #[cfg(not(target_arch = "aarch64"))]
+    scalar::i16_to_f32(src, dst);
+}
+
+/// Like `i16_to_f32`.
+pub fn i32_to_f32(src: &[i32], dst: &mut [f32]) {
+    #[cfg(target_arch = "x86_64")]
+    if avx2() {
+        // SAFETY: the CPU has AVX2.
+        return unsafe { x86::i32_to_f32(src, dst) };
+    }
+    #[cfg(target_arch = "aarch64")]
+    neon::i32_to_f32(src, dst);
+    #[cfg(not(target_arch = "aarch64"))]
+    scalar::i32_to_f32(src, dst);
+}
+
+/// The mean of each frame of interleaved `src` into `dst`, one sample per
+/// frame.
+pub fn downmix(src: &[f32], channels: usize, dst: &mut [f32]) {
+    if channels != 2 {
+        return scalar::downmix(src, channels, dst);
+    }
+    #[cfg(target_arch = "x86_64")]
+    if avx2() {
+        // SAFETY: the CPU has AVX2.
+        return unsafe { x86::stereo_to_mono(src, dst) };
+    }
+    #[cfg(target_arch = "aarch64")]
+    neon::stereo_to_mono(src, dst);
+    #[cfg(not(target_arch = "aarch64"))]
+    scalar::downmix(src, channels, dst);
+}
+
+/// Adds `src` times `gain` into `dst`.
+pub fn mix(dst: &mut [f32], src: &[f32], gain: f32) {
+    #[cfg(target_arch = "x86_64")]
+    if avx2() {
+        // SAFETY: the CPU has AVX2.
+        return unsafe { x86::mix(dst, src, gain) };
+    }
+    #[cfg(target_arch = "aarch64")]
+    neon::mix(dst, src, gain);
+    #[cfg(not(target_arch = "aarch64"))]
+    scalar::mix(dst, src, gain);
+}
+
+#[cfg(target_arch = "x86_64")]
+fn avx2() -> bool {
+    std::is_x86_feature_detected!("avx2")
+}
+
+/// The plain loops.
+pub mod scalar {
+    pub fn i16_to_f32(src: &[i16], dst: &mut [f32]) {
+        for (d, &s) in dst.iter_mut().zip(src) {
+            *d = s as f32 * super::I16_SCALE;
+        }
+    }
+
+    pub fn i32_to_f32(src: &[i32], dst: &mut [f32]) {
+        for (d, &s) in dst.iter_mut().zip(src) {
+            *d = s as f32 * super::I32_SCALE;
+        }
+    }
+
+    pub fn downmix(src: &[f32], channels: usize, dst: &mut [f32]) {
+        for (d, frame) in dst.iter_mut().zip(src.chunks_exact(channels)) {
+            *d = frame.iter().sum::<f32>() / channels as f32;
+        }
+    }
+
+    pub fn mix(dst: &mut [f32], src: &[f32], gain: f32) {
+        for (d, &s) in dst.iter_mut().zip(src) {
+            *d += s * gain;
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use super::scalar;
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn i16_to_f32(src: &[i16], dst: &mut [f32]) {
+        let n = src.len().min(dst.len()) / 8 * 8;
+        let scale = _mm256_set1_ps(super::I16_SCALE);
+        for i in (0..n).step_by(8) {
+            let s = _mm_loadu_si128(src.as_ptr().add(i).cast());
+            let f = _mm256_cvtepi32_ps(_mm256_cvtepi16_epi32(s));
+            _mm256_storeu_ps(dst.as_mut_ptr().add(i), _mm256_mul_ps(f, scale));
+        }
+        scalar::i16_to_f32(&src[n..], &mut dst[n..]);
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn i32_to_f32(src: &[i32], dst: &mut [f32]) {
+        let n = src.len().min(dst.len()) / 8 * 8;
+        let scale = _mm256_set1_ps(super::I32_SCALE);
+        for i in (0..n).step_by(8) {
+            let s = _mm256_loadu_si256(src.as_ptr().add(i).cast());
+            let f = _mm256_cvtepi32_ps(s);
+            _mm256_storeu_ps(dst.as_mut_ptr().add(i), _mm256_mul_ps(f, scale));
+        }
+        scalar::i32_to_f32(&src[n..], &mut dst[n..]);
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn stereo_to_mono(src: &[f32], dst: &mut [f32]) {
+        // Eight frames at a time.
+        let n = (src.len() / 2).min(dst.len()) / 8 * 8;
+        let half = _mm256_set1_ps(0.5);
+        for i in (0..n).step_by(8) {
+            let a = _mm256_loadu_ps(src.as_ptr().add(2 * i));
+            let b = _mm256_loadu_ps(src.as_ptr().add(2 * i + 8));
+            // The pair sums come out as frames 0 1 4 5 2 3 6 7; swap the
+            // middle 64‑bit lanes back.
+            let sums = _mm256_castps_pd(_mm256_hadd_ps(a, b));
+            let sums = _mm256_castpd_ps(_mm256_permute4x64_pd(sums, 0b11_01_10_00));
+            _mm256_storeu_ps(dst.as_mut_ptr().add(i), _mm256_mul_ps(sums, half));
+        }
+        scalar::downmix(&src[2 * n..], 2, &mut dst[n..]);
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn mix(dst: &mut [f32], src: &[f32], gain: f32) {
+        let n = dst.len().min(src.len()) / 8 * 8;
+        let gain_v = _mm256_set1_ps(gain);
+        for i in (0..n).step_by(8) {
+            let d = _mm256_loadu_ps(dst.as_ptr().add(i));
+            let s = _mm256_loadu_ps(src.as_ptr().add(i));
+            let sum = _mm256_add_ps(d, _mm256_mul_ps(s, gain_v));
+            _mm256_storeu_ps(dst.as_mut_ptr().add(i), sum);
+        }
+        scalar::mix(&mut dst[n..], &src[n..], gain);
+    }
+}
+
+// SAFETY (throughout): NEON is part of the aarch64 baseline, and every
+// load and store stays below `n`, which fits both slices.
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use super::scalar;
+    use std::arch::aarch64::*;
+
+    pub fn i16_to_f32(src: &[i16], dst: &mut [f32]) {
+        let n = src.len().min(dst.len()) / 8 * 8;
+        unsafe {
+            let scale = vdupq_n_f32(super::I16_SCALE);
+            for i in (0..n).step_by(8) {
+                let s = vld1q_s16(src.as_ptr().add(i));
+                let lo = vcvtq_f32_s32(vmovl_s16(vget_low_s16(s)));
+                let hi = vcvtq_f32_s32(vmovl_high_s16(s));
+                vst1q_f32(dst.as_mut_ptr().add(i), vmulq_f32(lo, scale));
+                vst1q_f32(dst.as_mut_ptr().add(i + 4), vmulq_f32(hi, scale));
+            }
+        }
+        scalar::i16_to_f32(&src[n..], &mut dst[n..]);
+    }
+
+    pub fn i32_to_f32(src: &[i32], dst: &mut [f32]) {
+        let n = src.len().min(dst.len()) / 4 * 4;
+        unsafe {
+            let scale = vdupq_n_f32(super::I32_SCALE);
+            for i in (0..n).step_by(4) {
+                let f = vcvtq_f32_s32(vld1q_s32(src.as_ptr().add(i)));
+                vst1q_f32(dst.as_mut_ptr().add(i), vmulq_f32(f, scale));
+            }
+        }
+        scalar::i32_to_f32(&src[n..], &mut dst[n..]);
+    }
+
+    pub fn stereo_to_mono(src: &[f32], dst: &mut [f32]) {
+        // Four frames at a time; `vld2q` splits left from right.
+        let n = (src.len() / 2).min(dst.len()) / 4 * 4;
+        unsafe {
+            let half = vdupq_n_f32(0.5);
+            for i in (0..n).step_by(4) {
+                let lr = vld2q_f32(src.as_ptr().add(2 * i));
+                let mono = vmulq_f32(vaddq_f32(lr.0, lr.1), half);
+                vst1q_f32(dst.as_mut_ptr().add(i), mono);
+            }
+        }
+        scalar::downmix(&src[2 * n..], 2, &mut dst[n..]);
+    }
+
+    pub fn mix(dst: &mut [f32], src: &[f32], gain: f32) {
+        let n = dst.len().min(src.len()) / 4 * 4;
+        unsafe {
+            let gain_v = vdupq_n_f32(gain);
+            for i in (0..n).step_by(4) {
+                let d = vld1q_f32(dst.as_ptr().add(i));
+                let s = vld1q_f32(src.as_ptr().add(i));
+                vst1q_f32(dst.as_mut_ptr().add(i), vaddq_f32(d, vmulq_f32(s, gain_v)));
+            }
+        }
+        scalar::mix(&mut dst[n..], &src[n..], gain);
+    }
+}
@@ -0,0 +1,15 @@
+// ─── Output sinks ──────────────────────────────────────────────────────────────
+// Audio that doesn't go to a playback device – the other half of `source`.
+// Test harnesses and bots that check what they hear use these in place of a
+// speaker. The session hands over a block every 10 ms of wall‑clock time from
+// the same playout path as the output callback (jitter buffer, comfort noise,
+// resampling, speaker layout), so the sink gets exactly what a device would.
+
+/// Something to play to instead of the output device.
+pub trait AudioSink: Send + 'static {
+    fn sample_rate(&self) -> u32;
+    /// Interleaved in WAV/SMPTE order, like an output device.
+    fn channels(&self) -> usize;
+    /// Takes one block of whole frames.
+    fn write(&mut self, buf: &[f32]);
+}
@@ -0,0 +1,500 @@
+// ─── UDP socket tuning ─────────────────────────────────────────────────────────
+// Voice is many small packets that must not queue: the defaults below give the
+// kernel room for bursts (some distros ship receive buffers small enough to
+// drop packets while the decoder is busy), keep packets from being fragmented
+// – our largest fits a 1280‑byte path, and a lost fragment loses the whole
+// packet – and mark them for low‑latency queueing (DSCP EF, Linux
+// SO_PRIORITY).
+//
+// Every option is best effort: one the platform or our privileges don't allow
+// is logged and the call goes on with the OS default – except the interface
+// and local address, which the user picked on purpose (see Interfaces).
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::net::UdpSocket;
+use tracing::{error, info, warn};
+
+#[derive(Clone, Debug)]
+pub struct SocketOptions {
+    /// SO_RCVBUF in bytes; `None` keeps the OS default.
+    pub recv_buffer: Option<usize>,
+    /// SO_SNDBUF in bytes; `None` keeps the OS default.
+    pub send_buffer: Option<usize>,
+    /// Set the don't‑fragment bit (where the platform can) instead of letting
+    /// routers split packets.
+    pub dont_fragment: bool,
+    /// DiffServ code point for outgoing packets; 46 is Expedited Forwarding.
+    pub dscp: Option<u8>,
+    /// Linux SO_PRIORITY for the egress queue (0–6 without CAP_NET_ADMIN).
+    pub priority: Option<u32>,
+    /// Local address for the call; `None` binds every address.
+    pub bind_addr: Option<IpAddr>,
+    /// Network interface to carry the call (Linux, Android and Apple).
+    pub interface: Option<String>,
+    /// A second interface every call packet is also sent over (`--multipath`).
+    pub multipath_interface: Option<String>,
+    /// Per‑source budgets for everything received (see `flood`).
+    pub limits: crate::flood::Limits,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self {
+            recv_buffer: Some(256 * 1024),
+            send_buffer: Some(128 * 1024),
+            dont_fragment: true,
+            dscp: Some(46),
+            priority: Some(6),
+            bind_addr: None,
+            interface: None,
+            multipath_interface: None,
+            limits: Default::default(),
+        }
+    }
+}
+
+/// A non‑blocking, tuned UDP socket for `addr`, not yet bound.
+pub(crate) fn new(addr: SocketAddr, opts: &SocketOptions) -> Result<Socket> {
+    let sock = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    sock.set_nonblocking(true)?;
+    tune(&sock, addr.is_ipv6(), opts);
+    if let Some(name) = &opts.interface {
+        bind_interface(&sock, name, addr.is_ipv6())
+            .with_context(|| format!("can't bind to interface {name}"))?;
+    }
+    Ok(sock)
+}
+
+/// Binds a tuned socket to `addr`. Needs a Tokio runtime.
+pub(crate) fn bind(addr: SocketAddr, opts: &SocketOptions) -> Result<UdpSocket> {
+    let sock = new(addr, opts)?;
+    sock.bind(&addr.into())?;
+    Ok(UdpSocket::from_std(sock.into())?)
+}
+
+/// What the kernel granted of a buffer size it reports as `reported`.
+fn granted(reported: usize) -> usize {
+    // Linux doubles the request for bookkeeping (after capping it at
+    // net.core.[rw]mem_max) and reports the doubled value.
+    match cfg!(any(target_os = "linux", target_os = "android")) {
+        true => reported / 2,
+        false => reported,
+    }
+}
+
+fn tune(sock: &Socket, v6: bool, opts: &SocketOptions) {
+    if let Some(size) = opts.recv_buffer {
+        match sock
+            .set_recv_buffer_size(size)
+            .and_then(|_| sock.recv_buffer_size())
+        {
+            Ok(got) if granted(got) < size => {
+                let got = granted(got);
+                warn!("receive buffer is {got} bytes, asked for {size}; raise net.core.rmem_max")
+            }
+            Ok(got) => info!("receive buffer {} bytes", granted(got)),
+            Err(e) => warn!("can't set receive buffer: {e}"),
+        }
+    }
+    if let Some(size) = opts.send_buffer {
+        match sock
+            .set_send_buffer_size(size)
+            .and_then(|_| sock.send_buffer_size())
+        {
+            Ok(got) if granted(got) < size => {
+                let got = granted(got);
+                warn!("send buffer is {got} bytes, asked for {size}; raise net.core.wmem_max")
+            }
+            Ok(got) => info!("send buffer {} bytes", granted(got)),
+            Err(e) => warn!("can't set send buffer: {e}"),
+        }
+    }
+    if let Some(dscp) = opts.dscp {
+        // DSCP is the top six bits of the TOS / traffic class byte.
+        let tos = (dscp as u32) << 2;
+        let r = match v6 {
+            true => set_tclass_v6(sock, tos),
+            false => set_tos(sock, tos),
+        };
+        report(&format!("DSCP {dscp}"), r);
+    }
+    if opts.dont_fragment {
+        report("don't‑fragment", set_dont_fragment(sock, v6));
+    }
+    if let Some(priority) = opts.priority {
+        report(
+            &format!("socket priority {priority}"),
+            set_priority(sock, priority),
+        );
+    }
+}
+
+fn report(what: &str, r: std::io::Result<()>) {
+    match r {
+        Ok(()) => {}
+        // The defaults ask for everything; not every platform has it.
+        Err(e) if e.kind() == std::io::ErrorKind::Unsupported => {
+            info!("{what} not supported on this platform")
+        }
+        Err(e) => warn!("can't set {what}: {e}"),
+    }
+}
+
+#[cfg(not(any(
+    target_os = "fuchsia",
+    target_os = "redox",
+    target_os = "solaris",
+    target_os = "illumos",
+    target_os = "haiku"
+)))]
+fn set_tos(sock: &Socket, tos: u32) -> std::io::Result<()> {
+    sock.set_tos(tos)
+}
+
+#[cfg(any(
+    target_os = "fuchsia",
+    target_os = "redox",
+    target_os = "solaris",
+    target_os = "illumos",
+    target_os = "haiku"
+))]
+fn set_tos(_: &Socket, _: u32) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(any(
+    target_os = "android",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "freebsd"
+))]
+fn set_tclass_v6(sock: &Socket, tclass: u32) -> std::io::Result<()> {
+    sock.set_tclass_v6(tclass)
+}
+
+#[cfg(not(any(
+    target_os = "android",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "freebsd"
+)))]
+fn set_tclass_v6(_: &Socket, _: u32) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn set_dont_fragment(sock: &Socket, v6: bool) -> std::io::Result<()> {
+    let (level, name, value) = match v6 {
+        true => (
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MTU_DISCOVER,
+            libc::IPV6_PMTUDISC_DO,
+        ),
+        false => (
+            libc::IPPROTO_IP,
+            libc::IP_MTU_DISCOVER,
+            libc::IP_PMTUDISC_DO,
+        ),
+    };
+    setsockopt(sock, level, name, value)
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn set_dont_fragment(sock: &Socket, v6: bool) -> std::io::Result<()> {
+    // IPV6_DONTFRAG from <netinet6/in6.h>; libc doesn't export it for Apple.
+    let (level, name) = match v6 {
+        true => (libc::IPPROTO_IPV6, 62),
+        false => (libc::IPPROTO_IP, libc::IP_DONTFRAG),
+    };
+    setsockopt(sock, level, name, 1)
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios"
+)))]
+fn set_dont_fragment(_: &Socket, _: bool) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn set_priority(sock: &Socket, priority: u32) -> std::io::Result<()> {
+    setsockopt(
+        sock,
+        libc::SOL_SOCKET,
+        libc::SO_PRIORITY,
+        priority as libc::c_int,
+    )
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn set_priority(_: &Socket, _: u32) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios"
+))]
+fn setsockopt(
+    sock: &Socket,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: libc::c_int,
+) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+    // SAFETY: a valid fd and a pointer to a live c_int of the size passed.
+    let r = unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            level,
+            name,
+            (&value as *const libc::c_int).cast(),
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    match r {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+// ─── Interfaces ────────────────────────────────────────────────────────────────
+// On a multi‑homed machine (VPN and LAN, Wi‑Fi and Ethernet) the OS picks the
+// route per packet. `--interface` ties the call socket to one interface
+// (SO_BINDTODEVICE on Linux and Android, IP_BOUND_IF on Apple) and
+// `--bind-addr` to one local address, which works everywhere but only steers
+// the route on hosts with source‑based routing. Either fails the call rather
+// than quietly using another path.
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn bind_interface(sock: &Socket, name: &str, _v6: bool) -> std::io::Result<()> {
+    sock.bind_device(Some(name.as_bytes()))
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn bind_interface(sock: &Socket, name: &str, v6: bool) -> std::io::Result<()> {
+    let name = std::ffi::CString::new(name)?;
+    // SAFETY: a valid NUL‑terminated string.
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    let index = std::num::NonZeroU32::new(index).ok_or(std::io::ErrorKind::NotFound)?;
+    match v6 {
+        true => sock.bind_device_by_index_v6(Some(index)),
+        false => sock.bind_device_by_index_v4(Some(index)),
+    }
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios"
+)))]
+fn bind_interface(_: &Socket, _: &str, _: bool) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "not supported on this platform; use --bind-addr with its address",
+    ))
+}
+
+/// Puts `probe` on the same interface as `sock`, if that is bound to one.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn same_interface(probe: &Socket, sock: SockRef) -> std::io::Result<()> {
+    match sock.device()? {
+        Some(name) => probe.bind_device(Some(&name)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn same_interface(probe: &Socket, sock: SockRef) -> std::io::Result<()> {
+    match sock.device_index_v4()? {
+        Some(index) => probe.bind_device_by_index_v4(Some(index)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios"
+)))]
+fn same_interface(_: &Socket, _: SockRef) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// The address our packets leave `sock` from: the one it is bound to, else
+/// the source address the OS would pick to reach the internet through its
+/// interface. No packet is sent.
+pub(crate) fn lan_address(sock: &UdpSocket) -> Option<SocketAddr> {
+    let local = sock.local_addr().ok()?;
+    if !local.ip().is_unspecified() {
+        return Some(local);
+    }
+    let probe = Socket::new(Domain::for_address(local), Type::DGRAM, None).ok()?;
+    same_interface(&probe, SockRef::from(sock)).ok()?;
+    let internet: SocketAddr = match local {
+        SocketAddr::V4(_) => ([8, 8, 8, 8], 80).into(),
+        SocketAddr::V6(_) => ([0x2001, 0x4860, 0x4860, 0, 0, 0, 0, 0x8888], 80).into(),
+    };
+    probe.connect(&internet.into()).ok()?;
+    let ip = probe.local_addr().ok()?.as_socket()?.ip();
+    Some(SocketAddr::new(ip, local.port()))
+}
+
+/// A socket on this host that `sock` can send to, for bridging the call to
+/// a transport it doesn't speak (see `turn`, `relay::tunnel`): on `sock`'s
+/// own address if it is bound to one, on loopback if not. Also returns the
+/// address `sock`'s packets arrive from. Needs a Tokio runtime.
+pub(crate) fn beside(sock: &UdpSocket) -> Result<(UdpSocket, SocketAddr)> {
+    let local = sock.local_addr()?;
+    let ip = match local.ip() {
+        ip if !ip.is_unspecified() => ip,
+        IpAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+        IpAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+    };
+    let bridge = std::net::UdpSocket::bind((ip, 0))?;
+    bridge.set_nonblocking(true)?;
+    Ok((
+        UdpSocket::from_std(bridge)?,
+        SocketAddr::new(ip, local.port()),
+    ))
+}
+
+/// `addr` with the name of the interface that has it, where we can tell.
+pub(crate) fn describe(addr: SocketAddr) -> String {
+    match interface_name(addr.ip()) {
+        Some(name) => format!("{addr} on {name}"),
+        None => addr.to_string(),
+    }
+}
+
+#[cfg(unix)]
+fn interface_name(ip: IpAddr) -> Option<String> {
+    let mut addrs = std::ptr::null_mut();
+    // SAFETY: getifaddrs fills in a list we walk read‑only and then free.
+    unsafe {
+        if libc::getifaddrs(&mut addrs) != 0 {
+            return None;
+        }
+        let mut found = None;
+        let mut ifa = addrs;
+        while let Some(entry) = ifa.as_ref() {
+            if sockaddr_ip(entry.ifa_addr) == Some(ip) {
+                let name = std::ffi::CStr::from_ptr(entry.ifa_name);
+                found = Some(name.to_string_lossy().into_owned());
+                break;
+            }
+            ifa = entry.ifa_next;
+        }
+        libc::freeifaddrs(addrs);
+        found
+    }
+}
+
+/// SAFETY: `sa` is null or points to a sockaddr of its family's size.
+#[cfg(unix)]
+unsafe fn sockaddr_ip(sa: *const libc::sockaddr) -> Option<IpAddr> {
+    match sa.as_ref()?.sa_family as libc::c_int {
+        libc::AF_INET => {
+            let sin = &*sa.cast::<libc::sockaddr_in>();
+            Some(IpAddr::from(sin.sin_addr.s_addr.to_ne_bytes()))
+        }
+        libc::AF_INET6 => {
+            let sin6 = &*sa.cast::<libc::sockaddr_in6>();
+            Some(IpAddr::from(sin6.sin6_addr.s6_addr))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(not(unix))]
+fn interface_name(_: IpAddr) -> Option<String> {
+    None
+}
+
+// ─── Batched sends ─────────────────────────────────────────────────────────────
+// Fanning one frame out to many listeners costs a syscall per copy. On Linux
+// and Android `sendmmsg` hands the kernel up to `BATCH_MAX` datagrams at once;
+// elsewhere this falls back to one `send_to` each.
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const BATCH_MAX: usize = 64;
+
+/// Sends each packet to its address. A destination that fails is logged and
+/// skipped; the rest still go out.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) async fn send_batch(sock: &UdpSocket, batch: &[(SocketAddr, Bytes)]) {
+    use std::os::fd::AsRawFd;
+    for (addr, pkt) in batch {
+        crate::capture::sent(sock, *addr, pkt);
+    }
+    let mut sent = 0;
+    while sent < batch.len() {
+        let rest = &batch[sent..(sent + BATCH_MAX).min(batch.len())];
+        let r = sock
+            .async_io(tokio::io::Interest::WRITABLE, || {
+                sendmmsg(sock.as_raw_fd(), rest)
+            })
+            .await;
+        match r {
+            Ok(n) => sent += n,
+            // The kernel stops at the first datagram that fails.
+            Err(e) => {
+                error!("udp send error to {}: {e}", batch[sent].0);
+                sent += 1;
+            }
+        }
+    }
+}
+
+/// One `sendmmsg` call; returns how many datagrams went out.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn sendmmsg(fd: libc::c_int, batch: &[(SocketAddr, Bytes)]) -> std::io::Result<usize> {
+    let addrs: Vec<socket2::SockAddr> = batch.iter().map(|(a, _)| (*a).into()).collect();
+    let mut iovs: Vec<libc::iovec> = batch
+        .iter()
+        .map(|(_, pkt)| libc::iovec {
+            iov_base: pkt.as_ptr() as *mut libc::c_void,
+            iov_len: pkt.len(),
+        })
+        .collect();
+    let mut msgs: Vec<libc::mmsghdr> = addrs
+        .iter()
+        .zip(iovs.iter_mut())
+        .map(|(addr, iov)| {
+            // SAFETY: all‑zero is a valid (empty) mmsghdr.
+            let mut m: libc::mmsghdr = unsafe { std::mem::zeroed() };
+            m.msg_hdr.msg_name = addr.as_ptr() as *mut libc::c_void;
+            m.msg_hdr.msg_namelen = addr.len();
+            m.msg_hdr.msg_iov = iov;
+            m.msg_hdr.msg_iovlen = 1;
+            m
+        })
+        .collect();
+    // SAFETY: every header points into `addrs`, `iovs` and the packets, all
+    // of which outlive the call; the kernel only reads them.
+    let n = unsafe { libc::sendmmsg(fd, msgs.as_mut_ptr(), msgs.len() as _, 0) };
+    match n {
+        n if n < 0 => Err(std::io::Error::last_os_error()),
+        n => Ok(n as usize),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) async fn send_batch(sock: &UdpSocket, batch: &[(SocketAddr, Bytes)]) {
+    for (addr, pkt) in batch {
+        crate::capture::sent(sock, *addr, pkt);
+        if let Err(e) = sock.send_to(pkt, addr).await {
+            error!("udp send error to {addr}: {e}");
+        }
+    }
+}
@@ -0,0 +1,168 @@
+// ─── Input sources ─────────────────────────────────────────────────────────────
+// Audio that doesn't come from a capture device: a WAV file or a test tone.
+// Automated test peers and announcement bots use these in place of a
+// microphone. The session pulls a block every 10 ms of wall‑clock time and runs
+// it through the same capture chain as device input, so resampling, effects
+// and the encoder behave exactly as with a real microphone.
+
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+
+/// Something to send instead of the capture device.
+pub trait AudioSource: Send + 'static {
+    fn sample_rate(&self) -> u32;
+    /// Interleaved in WAV/SMPTE order, like a capture device.
+    fn channels(&self) -> usize;
+    /// Fills `buf` with whole frames and returns how many it wrote; `0` once
+    /// the source is exhausted.
+    fn read(&mut self, buf: &mut [f32]) -> usize;
+}
+
+/// A WAV file, decoded into memory up front.
+pub struct WavFile {
+    rate: u32,
+    channels: usize,
+    samples: Vec<f32>,
+    pos: usize,
+    looping: bool,
+}
+
+impl WavFile {
+    /// Reads 8/16/24/32‑bit PCM or 32‑bit float WAV. With `looping` the file
+    /// starts over when it ends.
+    pub fn open(path: &Path, looping: bool) -> Result<Self> {
+        let data = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        Self::parse(&data, looping)
+            .with_context(|| format!("{} is not a usable WAV", path.display()))
+    }
+
+    fn parse(data: &[u8], looping: bool) -> Result<Self> {
+        if data.get(..4) != Some(b"RIFF") || data.get(8..12) != Some(b"WAVE") {
+            bail!("missing RIFF/WAVE header");
+        }
+        let u16_at = |b: &[u8], i: usize| u16::from_le_bytes([b[i], b[i + 1]]);
+        let u32_at = |b: &[u8], i: usize| u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]]);
+        let mut fmt = None;
+        let mut pcm = None;
+        let mut rest = &data[12..];
+        while rest.len() >= 8 {
+            let id = &rest[..4];
+            let len = u32_at(rest, 4) as usize;
+            let body = rest.get(8..8 + len).unwrap_or(&rest[8..]);
+            match id {
+                b"fmt " if body.len() >= 16 => {
+                    let mut tag = u16_at(body, 0);
+                    // WAVE_FORMAT_EXTENSIBLE: the real tag opens the subformat GUID.
+                    if tag == 0xFFFE && body.len() >= 26 {
+                        tag = u16_at(body, 24);
+                    }
+                    fmt = Some((tag, u16_at(body, 2), u32_at(body, 4), u16_at(body, 14)));
+                }
+                b"data" => pcm = Some(body),
+                _ => {}
+            }
+            // Chunks are padded to even length.
+            rest = rest.get(8 + len + (len & 1)..).unwrap_or_default();
+        }
+        let (tag, channels, rate, bits) = fmt.context("no fmt chunk")?;
+        let pcm = pcm.context("no data chunk")?;
+        if channels == 0 || rate == 0 {
+            bail!("{channels} channels at {rate} Hz");
+        }
+        let mut samples: Vec<f32> = match (tag, bits) {
+            (1, 8) => pcm.iter().map(|&b| (b as f32 - 128.0) / 128.0).collect(),
+            (1, 16) => pcm
+                .chunks_exact(2)
+                .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0)
+                .collect(),
+            (1, 24) => pcm
+                .chunks_exact(3)
+                .map(|b| i32::from_le_bytes([0, b[0], b[1], b[2]]) as f32 / 2_147_483_648.0)
+                .collect(),
+            (1, 32) => pcm
+                .chunks_exact(4)
+                .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2_147_483_648.0)
+                .collect(),
+            (3, 32) => pcm
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect(),
+            _ => bail!("unsupported format {tag} with {bits} bits"),
+        };
+        let channels = channels as usize;
+        samples.truncate(samples.len() / channels * channels);
+        if samples.is_empty() {
+            bail!("no audio");
+        }
+        Ok(Self {
+            rate,
+            channels,
+            samples,
+            pos: 0,
+            looping,
+        })
+    }
+}
+
+impl AudioSource for WavFile {
+    fn sample_rate(&self) -> u32 {
+        self.rate
+    }
+
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn read(&mut self, buf: &mut [f32]) -> usize {
+        let want = buf.len() / self.channels * self.channels;
+        let mut n = 0;
+        while n < want {
+            if self.pos == self.samples.len() {
+                if !self.looping {
+                    break;
+                }
+                self.pos = 0;
+            }
+            let take = (want - n).min(self.samples.len() - self.pos);
+            buf[n..n + take].copy_from_slice(&self.samples[self.pos..self.pos + take]);
+            self.pos += take;
+            n += take;
+        }
+        n / self.channels
+    }
+}
+
+/// An endless sine tone at −12 dBFS, mono 48 kHz.
+pub struct Tone {
+    step: f32,
+    phase: f32,
+}
+
+impl Tone {
+    const RATE: u32 = 48_000;
+
+    pub fn new(hz: f32) -> Self {
+        Self {
+            step: std::f32::consts::TAU * hz / Self::RATE as f32,
+            phase: 0.0,
+        }
+    }
+}
+
+impl AudioSource for Tone {
+    fn sample_rate(&self) -> u32 {
+        Self::RATE
+    }
+
+    fn channels(&self) -> usize {
+        1
+    }
+
+    fn read(&mut self, buf: &mut [f32]) -> usize {
+        for s in buf.iter_mut() {
+            *s = 0.25 * self.phase.sin();
+            self.phase = (self.phase + self.step) % std::f32::consts::TAU;
+        }
+        buf.len()
+    }
+}
@@ -0,0 +1,127 @@
+// ─── Channel layouts ───────────────────────────────────────────────────────────
+// Opus surround (mapping family 1) interleaves channels in Vorbis order, while
+// sound cards use the WAV/SMPTE order. `remix` moves every source channel to
+// the same speaker in the destination layout, and folds speakers the
+// destination doesn't have into front left/right (or centre for mono) with the
+// usual −3 dB downmix gains. LFE is dropped when folded.
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Speaker {
+    FrontLeft,
+    FrontRight,
+    FrontCenter,
+    Lfe,
+    BackLeft,
+    BackRight,
+    SideLeft,
+    SideRight,
+    BackCenter,
+}
+
+use Speaker::*;
+
+const VORBIS: [&[Speaker]; 8] = [
+    &[FrontCenter],
+    &[FrontLeft, FrontRight],
+    &[FrontLeft, FrontCenter, FrontRight],
+    &[FrontLeft, FrontRight, BackLeft, BackRight],
+    &[FrontLeft, FrontCenter, FrontRight, BackLeft, BackRight],
+    &[FrontLeft, FrontCenter, FrontRight, BackLeft, BackRight, Lfe],
+    &[
+        FrontLeft,
+        FrontCenter,
+        FrontRight,
+        SideLeft,
+        SideRight,
+        BackCenter,
+        Lfe,
+    ],
+    &[
+        FrontLeft,
+        FrontCenter,
+        FrontRight,
+        SideLeft,
+        SideRight,
+        BackLeft,
+        BackRight,
+        Lfe,
+    ],
+];
+
+const WAV: [&[Speaker]; 8] = [
+    &[FrontCenter],
+    &[FrontLeft, FrontRight],
+    &[FrontLeft, FrontRight, FrontCenter],
+    &[FrontLeft, FrontRight, BackLeft, BackRight],
+    &[FrontLeft, FrontRight, FrontCenter, BackLeft, BackRight],
+    &[FrontLeft, FrontRight, FrontCenter, Lfe, BackLeft, BackRight],
+    &[
+        FrontLeft,
+        FrontRight,
+        FrontCenter,
+        Lfe,
+        BackCenter,
+        SideLeft,
+        SideRight,
+    ],
+    &[
+        FrontLeft,
+        FrontRight,
+        FrontCenter,
+        Lfe,
+        BackLeft,
+        BackRight,
+        SideLeft,
+        SideRight,
+    ],
+];
+
+/// Speaker layout of an Opus surround frame with `channels` channels.
+pub fn vorbis(channels: usize) -> &'static [Speaker] {
+    VORBIS[channels.clamp(1, 8) - 1]
+}
+
+/// Speaker layout of a device frame; channels past the 8th stay silent.
+pub fn wav(channels: usize) -> &'static [Speaker] {
+    WAV[channels.clamp(1, 8) - 1]
+}
+
+impl Speaker {
+    fn stereo_gains(self) -> (f32, f32) {
+        const H: f32 = std::f32::consts::FRAC_1_SQRT_2;
+        match self {
+            FrontLeft => (1.0, 0.0),
+            FrontRight => (0.0, 1.0),
+            FrontCenter | BackCenter => (H, H),
+            BackLeft | SideLeft => (H, 0.0),
+            BackRight | SideRight => (0.0, H),
+            Lfe => (0.0, 0.0),
+        }
+    }
+}
+
+/// Writes one `src` frame into one `dst` frame.
+pub fn remix(src: &[f32], src_layout: &[Speaker], dst: &mut [f32], dst_layout: &[Speaker]) {
+    dst.fill(0.0);
+    // Mono goes to every speaker at full level, as before surround existed.
+    if src.len() == 1 {
+        dst.fill(src[0]);
+        return;
+    }
+    let find = |speaker| dst_layout.iter().position(|&d| d == speaker);
+    let fold = (find(FrontLeft), find(FrontRight));
+    for (&s, &speaker) in src.iter().zip(src_layout) {
+        if let Some(i) = find(speaker) {
+            dst[i] += s;
+            continue;
+        }
+        let (l, r) = speaker.stereo_gains();
+        match fold {
+            (Some(li), Some(ri)) => {
+                dst[li] += s * l;
+                dst[ri] += s * r;
+            }
+            _ => dst[0] += s * (l + r) / 2.0,
+        }
+    }
+}
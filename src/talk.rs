@@ -0,0 +1,116 @@
+// ─── Talk time ─────────────────────────────────────────────────────────────────
+// How long each participant spoke, for facilitators who want to know whether
+// a meeting was a conversation or a monologue. Our own frames count while the
+// voice detector says we're talking (see `vad`); each sender's decoded frames
+// count while they are loud enough, with the same hangover, so the pauses
+// between words are included but long silences aren't. Frames are counted at
+// their audio duration, so bursts and gaps in delivery don't skew the totals.
+
+use crate::vad;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// Talk time since the call started.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct TalkStats {
+    pub call_secs: f32,
+    pub local_secs: f32,
+    /// By sender, most talkative first.
+    pub peers: Vec<PeerTalk>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PeerTalk {
+    pub peer: SocketAddr,
+    pub secs: f32,
+}
+
+impl TalkStats {
+    /// One line per participant, with their share of the call so far.
+    pub fn summary(&self) -> Vec<String> {
+        let share = |secs: f32| match self.call_secs > 0.0 {
+            true => 100.0 * secs / self.call_secs,
+            false => 0.0,
+        };
+        let mut lines = vec![format!(
+            "you: {} ({:.0}%)",
+            clock(self.local_secs),
+            share(self.local_secs)
+        )];
+        for p in &self.peers {
+            lines.push(format!(
+                "{}: {} ({:.0}%)",
+                p.peer,
+                clock(p.secs),
+                share(p.secs)
+            ));
+        }
+        lines
+    }
+}
+
+/// `m:ss`.
+pub(crate) fn clock(secs: f32) -> String {
+    let secs = secs.round() as u64;
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+pub(crate) struct Tracker {
+    started: Instant,
+    local: Mutex<Duration>,
+    peers: Mutex<HashMap<SocketAddr, Duration>>,
+}
+
+impl Default for Tracker {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            local: Mutex::new(Duration::ZERO),
+            peers: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl Tracker {
+    /// A frame of `took` we sent while talking.
+    pub fn local(&self, took: Duration) {
+        *self.local.lock() += took;
+    }
+
+    /// A frame of `took` `peer` spoke in.
+    pub fn peer(&self, peer: SocketAddr, took: Duration) {
+        *self.peers.lock().entry(peer).or_default() += took;
+    }
+
+    pub fn stats(&self) -> TalkStats {
+        let mut peers: Vec<PeerTalk> = self
+            .peers
+            .lock()
+            .iter()
+            .map(|(&peer, took)| PeerTalk {
+                peer,
+                secs: took.as_secs_f32(),
+            })
+            .collect();
+        peers.sort_by(|a, b| b.secs.total_cmp(&a.secs));
+        TalkStats {
+            call_secs: self.started.elapsed().as_secs_f32(),
+            local_secs: self.local.lock().as_secs_f32(),
+            peers,
+        }
+    }
+}
+
+/// Speech detection for each sender's decoded audio.
+#[derive(Default)]
+pub(crate) struct Listener(HashMap<SocketAddr, vad::Detector>);
+
+impl Listener {
+    /// Whether `peer` is talking, as of this frame.
+    pub fn talking(&mut self, peer: SocketAddr, pcm: &[f32]) -> bool {
+        self.0.entry(peer).or_default().update(vad::loud(pcm))
+    }
+}
@@ -0,0 +1,300 @@
+// ─── OpenTelemetry export ──────────────────────────────────────────────────────
+// Optional OTLP/HTTP export (JSON encoding) for deployments that watch
+// headless instances in their observability stack. Every `interval` the
+// exporter posts the call's key metrics – encode time, round trip, jitter
+// buffer target and depth, quality, loss, send drops and deadline misses – to
+// `<endpoint>/v1/metrics`, and the spans finished since to
+// `<endpoint>/v1/traces`.
+//
+// Spans are collected by `layer()`, which the host adds to its `tracing`
+// subscriber; without it only metrics are exported.
+
+use crate::{realtime, send_queue, Format, SAMPLE_RATE};
+use anyhow::{bail, Context as _, Result};
+use async_channel::{bounded, Receiver, Sender};
+use reqwest::Url;
+use serde_json::{json, Value};
+use std::fmt::Debug;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::field::{Field, Visit};
+use tracing::{span, warn, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Finished spans waiting for the exporter; older ones are dropped first
+/// when nobody exports.
+const SPAN_QUEUE: usize = 1024;
+const SCOPE: &str = "audio";
+
+#[derive(Clone, Debug)]
+pub struct OtlpOptions {
+    /// Base URL of an OTLP/HTTP collector, e.g. `http://localhost:4318`.
+    pub endpoint: String,
+    /// `service.name` on everything exported.
+    pub service_name: String,
+    pub interval: Duration,
+}
+
+// ─── Spans ─────────────────────────────────────────────────────────────────────
+
+struct FinishedSpan {
+    name: &'static str,
+    open: OpenSpan,
+    end: SystemTime,
+}
+
+struct OpenSpan {
+    trace_id: u128,
+    span_id: u64,
+    parent_id: Option<u64>,
+    start: SystemTime,
+    attributes: Vec<(&'static str, String)>,
+}
+
+fn spans() -> &'static (Sender<FinishedSpan>, Receiver<FinishedSpan>) {
+    static SPANS: OnceLock<(Sender<FinishedSpan>, Receiver<FinishedSpan>)> = OnceLock::new();
+    SPANS.get_or_init(|| bounded(SPAN_QUEUE))
+}
+
+/// A `tracing` layer that hands finished spans to the OTLP exporter.
+pub fn layer() -> SpanLayer {
+    SpanLayer
+}
+
+pub struct SpanLayer;
+
+struct Fields<'a>(&'a mut Vec<(&'static str, String)>);
+
+impl Visit for Fields<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.push((field.name(), value.to_owned()));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0.push((field.name(), format!("{value:?}")));
+    }
+}
+
+impl<S> Layer<S> for SpanLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let parent = span.parent().and_then(|p| {
+            p.extensions()
+                .get::<OpenSpan>()
+                .map(|o| (o.trace_id, o.span_id))
+        });
+        let mut attributes = Vec::new();
+        attrs.record(&mut Fields(&mut attributes));
+        span.extensions_mut().insert(OpenSpan {
+            trace_id: parent.map_or_else(rand::random, |(trace, _)| trace),
+            span_id: rand::random(),
+            parent_id: parent.map(|(_, id)| id),
+            start: SystemTime::now(),
+            attributes,
+        });
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(open) = span.extensions_mut().get_mut::<OpenSpan>() {
+                values.record(&mut Fields(&mut open.attributes));
+            }
+        }
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(open) = span.extensions_mut().remove::<OpenSpan>() else {
+            return;
+        };
+        let finished = FinishedSpan {
+            name: span.name(),
+            open,
+            end: SystemTime::now(),
+        };
+        let _ = spans().0.force_send(finished);
+    }
+}
+
+// ─── Exporter ──────────────────────────────────────────────────────────────────
+
+pub(crate) struct Exporter {
+    opts: OtlpOptions,
+    metrics_url: Url,
+    traces_url: Url,
+    client: reqwest::Client,
+    started: SystemTime,
+}
+
+impl Exporter {
+    pub fn new(opts: OtlpOptions) -> Result<Self> {
+        let mut base = Url::parse(&opts.endpoint).context("invalid OTLP endpoint")?;
+        if !matches!(base.scheme(), "http" | "https") {
+            bail!("OTLP endpoint must be http:// or https://");
+        }
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+        Ok(Self {
+            metrics_url: base.join("v1/metrics")?,
+            traces_url: base.join("v1/traces")?,
+            client: reqwest::Client::new(),
+            started: SystemTime::now(),
+            opts,
+        })
+    }
+
+    pub async fn run(self, format: Arc<Format>, send: Arc<send_queue::Counters>) {
+        let mut tick = tokio::time::interval(self.opts.interval);
+        // Encode time and count at the previous export.
+        let mut last_encode = (0, 0);
+        loop {
+            tick.tick().await;
+            let finished: Vec<_> = std::iter::from_fn(|| spans().1.try_recv().ok()).collect();
+            if !finished.is_empty() {
+                self.post(&self.traces_url, self.traces(&finished)).await;
+            }
+
+            let encode = (
+                format.encode_us.load(Ordering::Relaxed),
+                format.encodes.load(Ordering::Relaxed),
+            );
+            let encode_ms = match encode.1 - last_encode.1 {
+                0 => None,
+                n => Some((encode.0 - last_encode.0) as f64 / n as f64 / 1000.0),
+            };
+            last_encode = encode;
+            let jitter = format.jitter_stats.lock().clone();
+            let quality = format.quality_stats.lock().clone();
+            let depth = format.playout_depth.load(Ordering::Relaxed);
+            let send = send.snapshot();
+            let rt = realtime::snapshot(&format);
+
+            let now = nanos(SystemTime::now());
+            let mut metrics = vec![
+                gauge("voice.jitter.target", "ms", jitter.target_ms as f64, &now),
+                gauge("voice.jitter.p95", "ms", jitter.jitter_p95_ms as f64, &now),
+                gauge(
+                    "voice.playout.depth",
+                    "ms",
+                    depth as f64 * 1000.0 / SAMPLE_RATE as f64,
+                    &now,
+                ),
+                gauge("voice.quality.mos", "1", quality.mos as f64, &now),
+                gauge("voice.quality.loss", "%", quality.loss_percent as f64, &now),
+            ];
+            if let Some(ms) = encode_ms {
+                metrics.push(gauge("voice.encode.latency", "ms", ms, &now));
+            }
+            if let Some(ms) = quality.rtt_ms {
+                metrics.push(gauge("voice.network.rtt", "ms", ms as f64, &now));
+            }
+            let start = nanos(self.started);
+            for (name, value) in [
+                ("voice.packets.received", jitter.received),
+                ("voice.packets.lost", jitter.lost),
+                ("voice.playout.underruns", jitter.underruns),
+                ("voice.frames.concealed", quality.concealed),
+                ("voice.send.dropped", send.dropped),
+                ("voice.capture.overruns", rt.capture_overruns),
+                ("voice.playback.overruns", rt.playback_overruns),
+                ("voice.encode.late", rt.encoder_late),
+            ] {
+                metrics.push(counter(name, value, &start, &now));
+            }
+            self.post(&self.metrics_url, self.metrics(metrics)).await;
+        }
+    }
+
+    async fn post(&self, url: &Url, body: Value) {
+        let sent = self.client.post(url.clone()).json(&body).send().await;
+        if let Err(e) = sent.and_then(|r| r.error_for_status()) {
+            warn!("OTLP export to {url} failed: {e}");
+        }
+    }
+
+    fn resource(&self) -> Value {
+        json!({ "attributes": [attribute("service.name", &self.opts.service_name)] })
+    }
+
+    fn metrics(&self, metrics: Vec<Value>) -> Value {
+        json!({
+            "resourceMetrics": [{
+                "resource": self.resource(),
+                "scopeMetrics": [{ "scope": { "name": SCOPE }, "metrics": metrics }],
+            }]
+        })
+    }
+
+    fn traces(&self, finished: &[FinishedSpan]) -> Value {
+        let spans: Vec<Value> = finished
+            .iter()
+            .map(|s| {
+                json!({
+                    "traceId": format!("{:032x}", s.open.trace_id),
+                    "spanId": format!("{:016x}", s.open.span_id),
+                    "parentSpanId": s.open.parent_id.map(|p| format!("{p:016x}")).unwrap_or_default(),
+                    "name": s.name,
+                    // SPAN_KIND_INTERNAL
+                    "kind": 1,
+                    "startTimeUnixNano": nanos(s.open.start),
+                    "endTimeUnixNano": nanos(s.end),
+                    "attributes": s.open.attributes.iter().map(|(k, v)| attribute(k, v)).collect::<Vec<_>>(),
+                })
+            })
+            .collect();
+        json!({
+            "resourceSpans": [{
+                "resource": self.resource(),
+                "scopeSpans": [{ "scope": { "name": SCOPE }, "spans": spans }],
+            }]
+        })
+    }
+}
+
+/// OTLP/JSON carries 64‑bit integers as strings.
+fn nanos(t: SystemTime) -> String {
+    t.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+fn gauge(name: &str, unit: &str, value: f64, now: &str) -> Value {
+    json!({
+        "name": name,
+        "unit": unit,
+        "gauge": { "dataPoints": [{ "timeUnixNano": now, "asDouble": value }] },
+    })
+}
+
+fn counter(name: &str, value: u64, start: &str, now: &str) -> Value {
+    json!({
+        "name": name,
+        "unit": "1",
+        "sum": {
+            // AGGREGATION_TEMPORALITY_CUMULATIVE
+            "aggregationTemporality": 2,
+            "isMonotonic": true,
+            "dataPoints": [{
+                "startTimeUnixNano": start,
+                "timeUnixNano": now,
+                "asInt": value.to_string(),
+            }],
+        },
+    })
+}
@@ -0,0 +1,187 @@
+// ─── Voice activity ────────────────────────────────────────────────────────────
+// Whether the local user is talking, judged on what the capture chain sends
+// (and, for `talk`, whether a peer is, judged on their level alone).
+// Mono and stereo go through the APM, whose voice detector decides; surround
+// skips the APM, so there level alone does. Either way a frame also has to be
+// louder than the threshold in `VadSettings` (−40 dBFS unless changed), and
+// speech holds for its hangover after the last such frame, so the pauses
+// between words don't count as silence. Both can be changed mid‑call.
+//
+// `Calibration` helps pick the threshold: it measures the processed input for
+// a few seconds while the user keeps quiet and suggests a threshold a margin
+// over the 90th percentile of that noise's frame levels.
+//
+// After a long enough stretch without speech, `Idle` lets the capture chain
+// stop running the APM and the encoder altogether: until the raw input gets
+// louder than `WAKE_RMS` it only measures each callback's level and sends a
+// silence marker now and then. The wake threshold sits well under
+// `SPEECH_DB`, since the raw input hasn't been through the AGC yet.
+
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+const SPEECH_DB: f32 = -40.0;
+const HANGOVER: Duration = Duration::from_millis(300);
+/// About −50 dBFS.
+const WAKE_RMS: f32 = 0.003;
+/// How far over the 90th percentile of the noise `Calibration` puts the
+/// threshold, and the range it stays in.
+const CALIBRATION_MARGIN_DB: f32 = 6.0;
+const MIN_THRESHOLD_DB: f32 = -70.0;
+const MAX_THRESHOLD_DB: f32 = -10.0;
+/// A level for digital silence, which has none.
+const FLOOR_DB: f32 = -120.0;
+
+/// When a frame counts as speech.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct VadSettings {
+    /// Frames quieter than this (RMS, dBFS, after the APM) are never speech.
+    pub threshold_db: f32,
+    /// How long speech holds after its last frame.
+    pub hangover: Duration,
+}
+
+impl Default for VadSettings {
+    fn default() -> Self {
+        Self {
+            threshold_db: SPEECH_DB,
+            hangover: HANGOVER,
+        }
+    }
+}
+
+/// What `Calibration` measured.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct NoiseFloor {
+    /// Median frame level, dBFS.
+    pub floor_db: f32,
+    /// A threshold that keeps all but the loudest tenth of it out, plus a
+    /// margin.
+    pub suggested_db: f32,
+}
+
+pub(crate) struct Detector {
+    /// When the current stretch of speech ends unless more comes.
+    until: Option<Instant>,
+    hangover: Duration,
+}
+
+impl Default for Detector {
+    fn default() -> Self {
+        Self {
+            until: None,
+            hangover: HANGOVER,
+        }
+    }
+}
+
+impl Detector {
+    pub fn set_hangover(&mut self, hangover: Duration) {
+        self.hangover = hangover;
+    }
+
+    /// Takes the verdict on the latest frame; whether the user is talking.
+    pub fn update(&mut self, voice: bool) -> bool {
+        let now = Instant::now();
+        if voice {
+            self.until = Some(now + self.hangover);
+        }
+        self.until.is_some_and(|until| now < until)
+    }
+}
+
+/// RMS level of `pcm` in dBFS.
+pub(crate) fn level_db(pcm: &[f32]) -> f32 {
+    let energy = pcm.iter().map(|s| s * s).sum::<f32>() / pcm.len().max(1) as f32;
+    (10.0 * energy.log10()).max(FLOOR_DB)
+}
+
+/// The fallback verdict for audio the APM doesn't see.
+pub(crate) fn loud(pcm: &[f32]) -> bool {
+    level_db(pcm) >= SPEECH_DB
+}
+
+/// Frame levels gathered for a while, to suggest a threshold from.
+#[derive(Debug)]
+pub(crate) struct Calibration {
+    levels: Vec<f32>,
+    left: Duration,
+}
+
+impl Calibration {
+    pub fn new(over: Duration) -> Self {
+        Self {
+            levels: Vec::new(),
+            left: over,
+        }
+    }
+
+    /// Takes one processed frame's level; the result once `over` has been
+    /// measured.
+    pub fn push(&mut self, level_db: f32, frame: Duration) -> Option<NoiseFloor> {
+        self.levels.push(level_db);
+        self.left = self.left.saturating_sub(frame);
+        if !self.left.is_zero() {
+            return None;
+        }
+        self.levels.sort_by(f32::total_cmp);
+        let at = |q: f32| self.levels[((self.levels.len() - 1) as f32 * q) as usize];
+        Some(NoiseFloor {
+            floor_db: at(0.5),
+            suggested_db: (at(0.9) + CALIBRATION_MARGIN_DB)
+                .clamp(MIN_THRESHOLD_DB, MAX_THRESHOLD_DB),
+        })
+    }
+}
+
+/// Whether the capture chain can skip its work for now.
+pub(crate) struct Idle {
+    /// `None` never idles.
+    after: Option<Duration>,
+    /// When the user last talked, or the chain last woke.
+    heard: Instant,
+    idle: bool,
+}
+
+impl Idle {
+    pub fn new(after: Option<Duration>) -> Self {
+        Self {
+            after,
+            heard: Instant::now(),
+            idle: false,
+        }
+    }
+
+    pub fn idle(&self) -> bool {
+        self.idle
+    }
+
+    /// Takes whether the user is talking after a processed frame; true when
+    /// the chain has just gone idle.
+    pub fn update(&mut self, talking: bool) -> bool {
+        let now = Instant::now();
+        if talking {
+            self.heard = now;
+        }
+        let was = self.idle;
+        self.idle = self.after.is_some_and(|after| now - self.heard >= after);
+        self.idle && !was
+    }
+
+    /// While idle, takes raw interleaved input; true when it's loud enough
+    /// to process again.
+    pub fn wake(&mut self, pcm: &[f32]) -> bool {
+        let energy = pcm.iter().map(|s| s * s).sum::<f32>() / pcm.len().max(1) as f32;
+        if energy.sqrt() >= WAKE_RMS {
+            self.idle = false;
+            self.heard = Instant::now();
+        }
+        !self.idle
+    }
+
+    /// Muting or holding needs the full chain.
+    pub fn interrupt(&mut self) {
+        self.idle = false;
+        self.heard = Instant::now();
+    }
+}
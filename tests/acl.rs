@@ -0,0 +1,121 @@
+// Join tokens from `acl::Acl`: what one issues, it lets in until the token
+// expires, the member is revoked or the room's list leaves them out;
+// tokens that were altered, cut or minted with another secret never get in.
+
+use audio::acl::Acl;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SECRET: &str = "relay secret";
+const HOUR: Duration = Duration::from_secs(3600);
+
+fn open() -> Acl {
+    Acl::new(SECRET, HashMap::new())
+}
+
+#[test]
+fn issued_tokens_are_granted() {
+    let acl = open();
+    let token = acl.issue("team.blue", "alice", HOUR);
+    let grant = acl.check(&token).unwrap();
+    assert_eq!(grant.room, "team.blue");
+    assert_eq!(grant.member, "alice");
+    assert!(!grant.expired());
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    assert!(grant.expires.abs_diff(now.as_secs() + HOUR.as_secs()) <= 1);
+    // Another relay with the same secret takes it too.
+    assert_eq!(open().check(&token).unwrap(), grant);
+}
+
+#[test]
+fn expired_tokens_are_refused() {
+    let acl = open();
+    let err = acl.check(&acl.issue("room", "alice", Duration::ZERO));
+    assert!(err.unwrap_err().to_string().contains("expired"));
+}
+
+#[test]
+fn other_secrets_are_refused() {
+    let token = Acl::new("another secret", HashMap::new()).issue("room", "alice", HOUR);
+    assert!(open().check(&token).is_err());
+}
+
+#[test]
+fn altered_and_malformed_tokens_are_refused() {
+    let acl = open();
+    let token = acl.issue("room", "alice", HOUR);
+    let fields: Vec<&str> = token.split('.').collect();
+    let with = |i: usize, value: &str| {
+        let mut fields = fields.clone();
+        fields[i] = value;
+        fields.join(".")
+    };
+    let mallory = URL_SAFE_NO_PAD.encode("mallory");
+    let later = (fields[3].parse::<u64>().unwrap() + 3600).to_string();
+    let cases = [
+        ("empty", String::new()),
+        ("no tag", fields[..4].join(".")),
+        ("other member", with(2, &mallory)),
+        ("other room", with(1, &URL_SAFE_NO_PAD.encode("lobby"))),
+        ("extended", with(3, &later)),
+        ("other version", with(0, "v2")),
+        ("tag not base64", with(4, "not*base64")),
+        ("cut tag", token[..token.len() - 4].to_owned()),
+        ("extra field", format!("x.{token}")),
+    ];
+    for (name, token) in cases {
+        assert!(acl.check(&token).is_err(), "{name}");
+    }
+}
+
+#[test]
+fn member_lists_limit_their_rooms() {
+    let members = HashMap::from([(
+        "team".to_owned(),
+        HashSet::from(["alice".to_owned(), "bob".to_owned()]),
+    )]);
+    let acl = Acl::new(SECRET, members);
+    assert!(acl.check(&acl.issue("team", "alice", HOUR)).is_ok());
+    assert!(acl.check(&acl.issue("team", "mallory", HOUR)).is_err());
+    // Rooms the list doesn't name take anyone with a token.
+    assert!(acl.check(&acl.issue("lobby", "mallory", HOUR)).is_ok());
+}
+
+#[test]
+fn revocation_refuses_until_allowed() {
+    let acl = open();
+    let token = acl.issue("room", "alice", HOUR);
+    assert!(acl.revoke("alice"));
+    assert!(!acl.revoke("alice"));
+    assert!(acl.is_revoked("alice"));
+    assert_eq!(acl.revoked(), ["alice"]);
+    assert!(acl.check(&token).is_err());
+    assert!(acl.check(&acl.issue("room", "alice", HOUR)).is_err());
+    assert!(acl.check(&acl.issue("room", "bob", HOUR)).is_ok());
+
+    assert!(acl.allow("alice"));
+    assert!(!acl.allow("alice"));
+    assert!(acl.revoked().is_empty());
+    assert!(acl.check(&token).is_ok());
+}
+
+#[test]
+fn member_files_are_read() {
+    let path = std::env::temp_dir().join(format!("acl-{}.json", std::process::id()));
+    std::fs::write(&path, r#"{"team": ["alice", "bob"], "empty": []}"#).unwrap();
+    let members = Acl::load_members(&path);
+    std::fs::write(&path, r#"{"team": "alice"}"#).unwrap();
+    let malformed = Acl::load_members(&path);
+    std::fs::remove_file(&path).ok();
+
+    let members = members.unwrap();
+    assert_eq!(
+        members["team"],
+        HashSet::from(["alice".into(), "bob".into()])
+    );
+    assert!(members["empty"].is_empty());
+    assert!(malformed.is_err());
+    assert!(Acl::load_members(&path).is_err());
+}
@@ -0,0 +1,106 @@
+// `analyze` on hand‑built captures whose records are cut short or malformed:
+// they are skipped or refused, never a panic.
+
+use audio::analyze::{self, Report};
+use std::path::PathBuf;
+
+/// Raw IPv4, no link header.
+const LINK_IPV4: u32 = 228;
+
+fn udp(payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&4000u16.to_be_bytes());
+    out.extend_from_slice(&5000u16.to_be_bytes());
+    out.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+    out.extend_from_slice(&[0, 0]);
+    out.extend_from_slice(payload);
+    out
+}
+
+/// A 20‑byte IPv4 header claiming `ihl` words, then `body`.
+fn ipv4(ihl: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x40 | ihl, 0];
+    out.extend_from_slice(&(20 + body.len() as u16).to_be_bytes());
+    out.extend_from_slice(&[0, 0, 0, 0, 64, 17, 0, 0]);
+    out.extend_from_slice(&[127, 0, 0, 1, 127, 0, 0, 1]);
+    out.extend_from_slice(body);
+    out
+}
+
+fn pcap(frames: &[&[u8]]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for word in [0xa1b2_c3d4, 0x0004_0002, 0, 0, 65535, LINK_IPV4] {
+        out.extend_from_slice(&u32::to_le_bytes(word));
+    }
+    for (i, frame) in frames.iter().enumerate() {
+        for word in [i as u32, 0, frame.len() as u32, frame.len() as u32] {
+            out.extend_from_slice(&word.to_le_bytes());
+        }
+        out.extend_from_slice(frame);
+    }
+    out
+}
+
+fn pcapng(frames: &[&[u8]]) -> Vec<u8> {
+    let block = |out: &mut Vec<u8>, kind: u32, body: &[u8]| {
+        let len = 12 + body.len().next_multiple_of(4) as u32;
+        out.extend_from_slice(&kind.to_le_bytes());
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(body);
+        out.resize(out.len().next_multiple_of(4), 0);
+        out.extend_from_slice(&len.to_le_bytes());
+    };
+    let mut out = Vec::new();
+    block(
+        &mut out,
+        0x0a0d_0d0a,
+        &[
+            0x4d, 0x3c, 0x2b, 0x1a, 1, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        ],
+    );
+    let mut idb = (LINK_IPV4 as u16).to_le_bytes().to_vec();
+    idb.extend_from_slice(&[0, 0, 0xff, 0xff, 0, 0]);
+    block(&mut out, 1, &idb);
+    for (i, frame) in frames.iter().enumerate() {
+        let mut epb = Vec::new();
+        for word in [0, 0, i as u32, frame.len() as u32, frame.len() as u32] {
+            epb.extend_from_slice(&word.to_le_bytes());
+        }
+        epb.extend_from_slice(frame);
+        block(&mut out, 6, &epb);
+    }
+    out
+}
+
+fn run(name: &str, data: &[u8]) -> anyhow::Result<Report> {
+    let path: PathBuf = std::env::temp_dir().join(format!("analyze-{}-{name}", std::process::id()));
+    std::fs::write(&path, data).unwrap();
+    let report = analyze::analyze(&path);
+    std::fs::remove_file(&path).ok();
+    report
+}
+
+#[test]
+fn short_snaplen_and_bad_ihl_are_skipped() {
+    let whole = ipv4(5, &udp(b"payload"));
+    // Cut inside the UDP header, just after the ports and at the length.
+    let cut6 = &whole[..26];
+    let cut7 = &whole[..27];
+    // An IHL under five would put the UDP header inside the IP header.
+    let bad_ihl = ipv4(2, &udp(b"payload"));
+    let frames: [&[u8]; 4] = [&whole, cut6, cut7, &bad_ihl];
+    for (name, data) in [("pcap", pcap(&frames)), ("pcapng", pcapng(&frames))] {
+        let report = run(name, &data).unwrap();
+        assert_eq!(report.datagrams, 1, "{name}");
+    }
+}
+
+#[test]
+fn truncated_records_are_refused() {
+    let whole = ipv4(5, &udp(b"payload"));
+    for (name, mut data) in [("pcap", pcap(&[&whole])), ("pcapng", pcapng(&[&whole]))] {
+        data.truncate(data.len() - 5);
+        assert!(run(name, &data).is_err(), "{name}");
+    }
+    assert!(run("empty", &[]).is_err());
+}
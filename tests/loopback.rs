@@ -0,0 +1,29 @@
+// Two sessions in one process, calling each other over loopback UDP with tone
+// sources and analysing sinks instead of audio devices (see `selftest`).
+
+use audio::selftest::{self, SelftestOptions};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tones_cross_loopback_intact() {
+    let report = selftest::run(&SelftestOptions::default()).await.unwrap();
+    report.check().unwrap();
+    for d in &report.directions {
+        let latency = d.latency_ms.expect("no latency measured");
+        // Frame, jitter target and playout granularity; nothing like a
+        // device buffer or a network.
+        assert!(latency.median < 250.0, "{}: {latency:?}", d.name);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tones_cross_loopback_with_short_frames_and_fec() {
+    let opts = SelftestOptions {
+        frame_ms: 10,
+        encoder: audio::codec::EncoderOptions {
+            fec: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    selftest::run(&opts).await.unwrap().check().unwrap();
+}
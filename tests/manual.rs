@@ -0,0 +1,123 @@
+// `manual` offers and answers pasted by hand: blobs and SDP of the right
+// role are taken once, anything else is refused and leaves the exchange
+// open for another try.
+
+use audio::codec::StreamParams;
+use audio::manual::{self, Encoding, Role};
+use audio::sdp;
+use audio::signaling::JoinPayload;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
+use std::net::Ipv6Addr;
+
+const KEY: [u8; 32] = [7; 32];
+
+/// Version, role, key, then the addresses as `manual` packs them.
+fn blob(version: u8, role: Role, addrs: &[&[u8]]) -> String {
+    let mut out = vec![version, role as u8];
+    out.extend_from_slice(&KEY);
+    for addr in addrs {
+        out.extend_from_slice(addr);
+    }
+    URL_SAFE_NO_PAD.encode(out)
+}
+
+fn v4(ip: [u8; 4], port: u16) -> Vec<u8> {
+    let mut out = vec![4];
+    out.extend_from_slice(&ip);
+    out.extend_from_slice(&port.to_be_bytes());
+    out
+}
+
+fn v6(ip: [u8; 16], port: u16) -> Vec<u8> {
+    let mut out = vec![6];
+    out.extend_from_slice(&ip);
+    out.extend_from_slice(&port.to_be_bytes());
+    out
+}
+
+fn answer() -> String {
+    let reflexive = v4([203, 0, 113, 7], 40000);
+    let lan = v4([192, 168, 1, 20], 40000);
+    let relayed = v4([198, 51, 100, 9], 50000);
+    blob(1, Role::Answer, &[&reflexive, &lan, &relayed])
+}
+
+#[test]
+fn answer_is_taken_once() {
+    let (_exchange, mut host) = manual::exchange(Role::Offer, Encoding::Blob);
+    host.accept(&answer()).unwrap();
+    assert!(host.accept(&answer()).is_err());
+}
+
+#[test]
+fn blobs_without_lan_or_turn_addresses_are_taken() {
+    // Before TURN, blobs ended after the LAN address.
+    let reflexive = v6(
+        Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).octets(),
+        40000,
+    );
+    for addrs in [&[&reflexive[..], &[0]][..], &[&reflexive[..], &[0], &[0]]] {
+        let (_exchange, mut host) = manual::exchange(Role::Answer, Encoding::Blob);
+        host.accept(&blob(1, Role::Offer, addrs)).unwrap();
+    }
+}
+
+#[test]
+fn same_role_is_refused() {
+    let (_exchange, mut host) = manual::exchange(Role::Answer, Encoding::Blob);
+    let err = host.accept(&answer()).unwrap_err();
+    assert!(err.to_string().contains("start from an offer"), "{err}");
+
+    let (_exchange, mut host) = manual::exchange(Role::Offer, Encoding::Blob);
+    let offer = blob(1, Role::Offer, &[&v4([203, 0, 113, 7], 40000), &[0]]);
+    assert!(host.accept(&offer).is_err());
+    // Still open.
+    host.accept(&answer()).unwrap();
+}
+
+#[test]
+fn malformed_blobs_are_refused() {
+    let reflexive = v4([203, 0, 113, 7], 40000);
+    let whole = URL_SAFE_NO_PAD.decode(answer()).unwrap();
+    let cut = |n: usize| URL_SAFE_NO_PAD.encode(&whole[..n]);
+    let cases = [
+        ("empty", String::new()),
+        ("not base64", "not a blob!".to_owned()),
+        ("role only", cut(2)),
+        ("short key", cut(20)),
+        ("no addresses", cut(34)),
+        ("cut address", cut(38)),
+        ("cut LAN address", cut(42)),
+        ("newer version", blob(2, Role::Answer, &[&reflexive, &[0]])),
+        ("no reflexive", blob(1, Role::Answer, &[&[0], &[0]])),
+        (
+            "bad family",
+            blob(1, Role::Answer, &[&[5, 1, 2, 3, 4, 0, 1], &[0]]),
+        ),
+        ("bad role", {
+            let mut bytes = whole.clone();
+            bytes[1] = 9;
+            URL_SAFE_NO_PAD.encode(bytes)
+        }),
+    ];
+    let (_exchange, mut host) = manual::exchange(Role::Offer, Encoding::Blob);
+    for (name, blob) in cases {
+        assert!(host.accept(&blob).is_err(), "{name}");
+    }
+    host.accept(&answer()).unwrap();
+}
+
+#[test]
+fn sdp_is_taken_in_place_of_a_blob() {
+    let me = JoinPayload {
+        reflexive_addr: "203.0.113.7:40000".into(),
+        lan_addr: String::new(),
+        pub_key: audio::crypto::key_to_hex(&KEY),
+        relayed_addr: String::new(),
+    };
+    let answer = sdp::describe(Role::Answer, &me, &StreamParams::default()).unwrap();
+    let (_exchange, mut host) = manual::exchange(Role::Offer, Encoding::Blob);
+    assert!(host.accept("v=0\r\ns=-\r\n").is_err());
+    host.accept(&answer.to_string()).unwrap();
+}
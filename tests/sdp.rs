@@ -0,0 +1,207 @@
+// `sdp` descriptions from `describe` read back to the same session and the
+// same peer; descriptions from elsewhere are read leniently, but text that
+// isn't SDP, or can't be called, is refused.
+
+use audio::codec::{StreamLayout, StreamParams};
+use audio::manual::Role;
+use audio::sdp::{self, Candidate, CandidateKind, SessionDescription};
+use audio::signaling::JoinPayload;
+use std::net::SocketAddr;
+
+const KEY: &str = "0707070707070707070707070707070707070707070707070707070707070707";
+
+fn me() -> JoinPayload {
+    JoinPayload {
+        reflexive_addr: "203.0.113.7:40000".into(),
+        lan_addr: "192.168.1.20:40001".into(),
+        pub_key: KEY.into(),
+        relayed_addr: "198.51.100.9:50000".into(),
+    }
+}
+
+#[test]
+fn description_round_trips() {
+    for role in [Role::Offer, Role::Answer] {
+        let ours = sdp::describe(role, &me(), &StreamParams::default()).unwrap();
+        let text = ours.to_string();
+        assert!(text.starts_with("v=0\r\n"), "{text}");
+        let parsed: SessionDescription = text.parse().unwrap();
+        assert_eq!(parsed, ours);
+
+        let (parsed_role, peer) = sdp::peer_info(&parsed).unwrap();
+        assert_eq!(parsed_role, role);
+        assert_eq!(peer.reflexive_addr, "203.0.113.7:40000");
+        assert_eq!(peer.lan_addr, "192.168.1.20:40001");
+        assert_eq!(peer.relayed_addr, "198.51.100.9:50000");
+        assert_eq!(peer.pub_key, KEY);
+    }
+}
+
+#[test]
+fn opus_parameters_are_described() {
+    let params = StreamParams {
+        frame_ms: 10,
+        fec: true,
+        ..Default::default()
+    };
+    let ours = sdp::describe(Role::Offer, &me(), &params).unwrap();
+    let media = &ours.media[0];
+    let rtpmap = &media.rtpmaps()[0];
+    assert_eq!(rtpmap.payload_type, sdp::OPUS_PAYLOAD_TYPE);
+    assert_eq!(
+        (rtpmap.encoding.as_str(), rtpmap.clock_rate, rtpmap.channels),
+        ("opus", 48000, Some(2))
+    );
+    let fmtp = media.fmtp(sdp::OPUS_PAYLOAD_TYPE);
+    assert!(
+        fmtp.contains(&("useinbandfec".into(), "1".into())),
+        "{fmtp:?}"
+    );
+    assert!(fmtp.contains(&("stereo".into(), "0".into())), "{fmtp:?}");
+    assert_eq!(media.attribute("ptime"), Some("10"));
+
+    let params = StreamParams {
+        layout: StreamLayout {
+            channels: 6,
+            streams: 4,
+            coupled_streams: 2,
+            mapping: vec![0, 4, 1, 2, 3, 5],
+        },
+        ..Default::default()
+    };
+    let ours = sdp::describe(Role::Offer, &me(), &params).unwrap();
+    let media = &ours.media[0];
+    assert_eq!(media.rtpmaps()[0].encoding, "multiopus");
+    assert_eq!(media.rtpmaps()[0].channels, Some(6));
+    let fmtp = media.fmtp(sdp::OPUS_PAYLOAD_TYPE);
+    assert!(fmtp.contains(&("channel_mapping".into(), "0,4,1,2,3,5".into())));
+    assert!(fmtp.contains(&("num_streams".into(), "4".into())));
+}
+
+#[test]
+fn candidates_round_trip() {
+    let candidate = Candidate {
+        foundation: "4".into(),
+        component: 1,
+        transport: "udp".into(),
+        priority: 1686052607,
+        addr: "[2001:db8::7]:40000".parse().unwrap(),
+        kind: CandidateKind::ServerReflexive,
+        related: Some("[fd00::7]:40000".parse().unwrap()),
+    };
+    assert_eq!(
+        candidate.to_string().parse::<Candidate>().unwrap(),
+        candidate
+    );
+
+    for bad in [
+        "",
+        "1 1 udp 2130706431 192.0.2.1",
+        "1 1 udp 2130706431 192.0.2.1 5000 host",
+        "1 1 udp 2130706431 192.0.2.1 5000 typ bogus",
+        "1 1 udp many 192.0.2.1 5000 typ host",
+        "1 1 udp 2130706431 192.0.2.1 99999 typ host",
+        "1 1 udp 2130706431 2e1b7a2c.local 5000 typ host",
+    ] {
+        assert!(bad.parse::<Candidate>().is_err(), "{bad:?}");
+    }
+}
+
+/// Much as a browser writes it, plus our key.
+const FOREIGN: &str = "v=0
+o=- 4611731400430051336 2 IN IP4 127.0.0.1
+s=-
+t=0 0
+a=group:BUNDLE 0
+m=audio 9 UDP/TLS/RTP/SAVPF 111 0
+c=IN IP4 0.0.0.0
+b=AS:64
+a=rtpmap:111 opus/48000/2
+a=fmtp:111 minptime=10;useinbandfec=1
+a=rtpmap:0 PCMU/8000
+a=candidate:1 1 udp 2113937151 2e1b7a2c.local 51000 typ host
+a=candidate:2 1 tcp 1518280447 192.0.2.5 9 typ host tcptype active
+a=candidate:3 1 udp 1677729535 203.0.113.50 51000 typ srflx raddr 0.0.0.0 rport 0
+a=x-audio-p2p-key:0707070707070707070707070707070707070707070707070707070707070707
+";
+
+#[test]
+fn foreign_descriptions_are_read_leniently() {
+    for text in [FOREIGN.to_owned(), FOREIGN.replace('\n', "\r\n")] {
+        let parsed: SessionDescription = text.parse().unwrap();
+        let media = &parsed.media[0];
+        assert_eq!(media.port, 9);
+        assert_eq!(media.formats, ["111", "0"]);
+        assert_eq!(media.rtpmaps().len(), 2);
+        // The mDNS candidate doesn't parse, and the TCP one isn't called.
+        assert_eq!(media.candidates().len(), 2);
+        let (role, peer) = sdp::peer_info(&parsed).unwrap();
+        assert_eq!(role, Role::Offer);
+        assert_eq!(peer.reflexive_addr, "203.0.113.50:51000");
+        assert_eq!(peer.lan_addr, "");
+    }
+
+    // Without candidates, the connection address and media port.
+    let bare: String = FOREIGN
+        .lines()
+        .filter(|l| !l.starts_with("a=candidate"))
+        .map(|l| format!("{l}\n"))
+        .collect();
+    let (_, peer) = sdp::peer_info(&bare.parse().unwrap()).unwrap();
+    let addr: SocketAddr = peer.reflexive_addr.parse().unwrap();
+    assert_eq!(addr, "0.0.0.0:9".parse().unwrap());
+}
+
+#[test]
+fn malformed_descriptions_are_refused() {
+    let cases = [
+        ("empty", String::new()),
+        ("no version", FOREIGN.replacen("v=0\n", "", 1)),
+        ("wrong version", FOREIGN.replacen("v=0", "v=1", 1)),
+        (
+            "no origin",
+            FOREIGN.replacen("o=- 4611731400430051336 2 IN IP4 127.0.0.1\n", "", 1),
+        ),
+        (
+            "bad origin",
+            FOREIGN.replacen("IN IP4 127.0.0.1", "IP4 127.0.0.1", 1),
+        ),
+        (
+            "bad connection",
+            FOREIGN.replacen("c=IN IP4 0.0.0.0", "c=IN IP4 nowhere", 1),
+        ),
+        (
+            "bad media",
+            FOREIGN.replacen("m=audio 9 UDP/TLS/RTP/SAVPF 111 0", "m=audio", 1),
+        ),
+        ("bad port", FOREIGN.replacen("m=audio 9", "m=audio port", 1)),
+        ("not a line", FOREIGN.replacen("s=-", "s -", 1)),
+        ("long type", FOREIGN.replacen("s=-", "ss=-", 1)),
+    ];
+    for (name, text) in cases {
+        assert!(text.parse::<SessionDescription>().is_err(), "{name}");
+    }
+}
+
+#[test]
+fn uncallable_descriptions_are_refused() {
+    let cases = [
+        (
+            "no key",
+            FOREIGN.replacen("a=x-audio-p2p-key", "a=x-other-key", 1),
+        ),
+        ("no audio", FOREIGN.replacen("m=audio", "m=video", 1)),
+        (
+            "audio refused",
+            FOREIGN.replacen("m=audio 9", "m=audio 0", 1),
+        ),
+    ];
+    for (name, text) in cases {
+        let parsed: SessionDescription = text.parse().unwrap();
+        assert!(sdp::peer_info(&parsed).is_err(), "{name}");
+    }
+
+    let mut nowhere = me();
+    nowhere.reflexive_addr.clear();
+    assert!(sdp::describe(Role::Offer, &nowhere, &StreamParams::default()).is_err());
+}
@@ -0,0 +1,100 @@
+// Recordings sealed with `crypto::FileSealer` open to what was written, at
+// any length around the chunk size; anything cut, reordered, altered or
+// opened with the wrong passphrase is refused.
+
+use audio::crypto::{self, FileSealer};
+use std::io::Write;
+
+const PASSPHRASE: &str = "correct horse battery staple";
+/// `crypto`'s chunk size.
+const CHUNK: usize = 64 * 1024;
+/// Magic and salt.
+const HEADER: usize = 24;
+const TAG: usize = 16;
+
+fn plaintext(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 31 % 251) as u8).collect()
+}
+
+fn seal(data: &[u8]) -> Vec<u8> {
+    let mut sealer = FileSealer::new(Vec::new(), PASSPHRASE).unwrap();
+    // Uneven writes, so chunks don't line up with them.
+    for piece in data.chunks(10_000) {
+        sealer.write_all(piece).unwrap();
+    }
+    sealer.finish().unwrap()
+}
+
+fn open(passphrase: &str, sealed: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    crypto::open_file(passphrase, sealed, &mut out)?;
+    Ok(out)
+}
+
+#[test]
+fn round_trips_around_the_chunk_size() {
+    for len in [0, 1, CHUNK - 1, CHUNK, CHUNK + 1, 2 * CHUNK, 2 * CHUNK + 5] {
+        let data = plaintext(len);
+        let sealed = seal(&data);
+        let chunks = len.div_ceil(CHUNK).max(1);
+        assert_eq!(sealed.len(), HEADER + len + chunks * TAG, "{len}");
+        assert_eq!(open(PASSPHRASE, &sealed).unwrap(), data, "{len}");
+    }
+}
+
+#[test]
+fn same_plaintext_seals_differently() {
+    let data = plaintext(100);
+    assert_ne!(seal(&data), seal(&data));
+}
+
+#[test]
+fn wrong_passphrase_is_refused() {
+    let sealed = seal(&plaintext(100));
+    assert!(open("correct horse battery stapler", &sealed).is_err());
+}
+
+#[test]
+fn damaged_files_are_refused() {
+    let data = plaintext(2 * CHUNK + 5);
+    let sealed = seal(&data);
+    let sealed_chunk = CHUNK + TAG;
+
+    let mut flipped = sealed.clone();
+    flipped[HEADER + CHUNK + 7] ^= 1;
+    let mut salted = sealed.clone();
+    salted[10] ^= 1;
+    let mut swapped = sealed.clone();
+    let (first, rest) = swapped[HEADER..].split_at_mut(sealed_chunk);
+    first.swap_with_slice(&mut rest[..sealed_chunk]);
+    let cases = [
+        ("flipped bit", flipped),
+        ("altered salt", salted),
+        ("swapped chunks", swapped),
+        (
+            "last chunk dropped",
+            sealed[..HEADER + 2 * sealed_chunk].to_vec(),
+        ),
+        ("cut mid-chunk", sealed[..sealed.len() - 3].to_vec()),
+        ("cut tag", sealed[..HEADER + TAG - 1].to_vec()),
+        ("header only", sealed[..HEADER].to_vec()),
+        ("cut header", sealed[..HEADER - 1].to_vec()),
+        ("not sealed", data[..1000].to_vec()),
+        ("empty", Vec::new()),
+    ];
+    for (name, sealed) in cases {
+        assert!(open(PASSPHRASE, &sealed).is_err(), "{name}");
+    }
+}
+
+#[test]
+fn unfinished_files_are_refused() {
+    let mut partial = Vec::new();
+    let mut sealer = FileSealer::new(&mut partial, PASSPHRASE).unwrap();
+    sealer.write_all(&plaintext(CHUNK + 100)).unwrap();
+    sealer.flush().unwrap();
+    // Dropped without `finish`: the full chunk went out, the last didn't.
+    drop(sealer);
+    assert_eq!(partial.len(), HEADER + CHUNK + TAG);
+    assert!(open(PASSPHRASE, &partial).is_err());
+}
@@ -0,0 +1,36 @@
+// ─── Capture worklet ───────────────────────────────────────────────────────────
+// Cuts the microphone (downmixed to mono by the node) into frames of
+// `processorOptions.frame` samples and posts each to the encoder on the main
+// thread (see mic.rs). Loaded from a Blob by `Microphone.record`.
+
+class VoiceCapture extends AudioWorkletProcessor {
+  constructor(options) {
+    super();
+    this.frame = options.processorOptions.frame;
+    this.buf = new Float32Array(this.frame);
+    this.fill = 0;
+  }
+
+  process(inputs) {
+    const input = inputs[0];
+    if (input.length === 0) {
+      return true;
+    }
+    const samples = input[0];
+    let at = 0;
+    while (at < samples.length) {
+      const n = Math.min(this.frame - this.fill, samples.length - at);
+      this.buf.set(samples.subarray(at, at + n), this.fill);
+      this.fill += n;
+      at += n;
+      if (this.fill === this.frame) {
+        this.port.postMessage(this.buf, [this.buf.buffer]);
+        this.buf = new Float32Array(this.frame);
+        this.fill = 0;
+      }
+    }
+    return true;
+  }
+}
+
+registerProcessor("voice-capture", VoiceCapture);
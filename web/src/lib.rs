@@ -0,0 +1,22 @@
+// The browser client: the parts of a call that run in a page, built to wasm
+// with wasm-pack (see README.md).
+// ────────────────────────────────────────────────────────────────────────────────
+//   • Playout never touches the main thread: Opus is decoded with WebCodecs
+//     straight into a wait‑free ring in a SharedArrayBuffer, which an
+//     AudioWorkletProcessor plays from (see `ring`, `player`).
+//   • The microphone is asked for up front, and what stands in the way
+//     (denied, missing, busy, insecure page) comes back as a status, along
+//     with the rates and constraints it supports, before capture starts;
+//     capture encodes to Opus with WebCodecs (see `mic`).
+//   • Joins the same signaling rooms as native clients, and calls the peer
+//     it is paired with through that peer's WebRTC endpoint (see `room`).
+
+mod mic;
+mod player;
+mod ring;
+mod room;
+
+pub use mic::{MicState, MicStatus, Microphone, Recorder};
+pub use player::{Player, PlayoutStats};
+pub use ring::PlayoutRing;
+pub use room::{Call, Room};
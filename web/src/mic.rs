@@ -0,0 +1,408 @@
+// ─── Microphone ────────────────────────────────────────────────────────────────
+// Permission first, capture second. `Microphone.probe` says where the
+// microphone stands without prompting; `Microphone.request` asks for it and
+// reads what the device offers; `record` only starts on a granted microphone.
+// Every failure comes back as a `MicStatus` (a rejected promise for
+// `record`), never as an exception from deep inside Web Audio:
+//
+//   granted      the page may capture
+//   prompt       asking will prompt (or the browser won't say)
+//   denied       the user or a policy refused
+//   unavailable  no audio input, or none that fits
+//   busy         another application holds the device
+//   insecure     not a secure context (https or localhost)
+//   unsupported  no getUserMedia, Web Audio worklets or WebCodecs
+//
+// Capture runs the microphone through a 48 kHz AudioContext and a worklet
+// that cuts it into frames (`capture.js`), and encodes them to Opus with
+// WebCodecs for the caller.
+
+use crate::player::{add_module, SAMPLE_RATE};
+use js_sys::{Array, Float32Array, Function, Object, Reflect, Uint8Array};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    AudioContext, AudioContextOptions, AudioData, AudioDataInit, AudioEncoder, AudioEncoderConfig,
+    AudioEncoderInit, AudioSampleFormat, AudioWorkletNode, AudioWorkletNodeOptions,
+    ChannelCountMode, DomException, EncodedAudioChunk, MediaDeviceInfo, MediaDeviceKind,
+    MediaDevices, MediaStream, MediaStreamAudioSourceNode, MediaStreamConstraints,
+    MediaStreamTrack, MessageEvent, PermissionState, PermissionStatus,
+};
+
+const CAPTURE_WORKLET: &str = include_str!("capture.js");
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MicState {
+    Granted,
+    Prompt,
+    Denied,
+    Unavailable,
+    Busy,
+    Insecure,
+    Unsupported,
+}
+
+/// Where the microphone stands, and what it offers.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Clone, Debug)]
+pub struct MicStatus {
+    pub state: MicState,
+    /// The browser's explanation, when there is one.
+    pub detail: Option<String>,
+    /// Audio inputs the page can see.
+    pub inputs: u32,
+    /// Constraints the browser understands (`echoCancellation`,
+    /// `sampleRate`, …).
+    pub constraints: Vec<String>,
+    /// Lowest and highest rate the device offers, and the one it runs at;
+    /// 0 until granted, or where the browser doesn't say.
+    pub min_rate: u32,
+    pub max_rate: u32,
+    pub rate: u32,
+    /// Most channels the device offers (0 where unknown).
+    pub max_channels: u32,
+}
+
+impl MicStatus {
+    fn new(state: MicState, detail: Option<String>) -> Self {
+        Self {
+            state,
+            detail,
+            inputs: 0,
+            constraints: Vec::new(),
+            min_rate: 0,
+            max_rate: 0,
+            rate: 0,
+            max_channels: 0,
+        }
+    }
+
+    /// What a failed browser call means for the microphone.
+    fn from_error(e: &JsValue) -> Self {
+        let Some(e) = e.dyn_ref::<DomException>() else {
+            return Self::new(MicState::Unsupported, e.as_string());
+        };
+        let state = match e.name().as_str() {
+            "NotAllowedError" => MicState::Denied,
+            "NotFoundError" | "OverconstrainedError" => MicState::Unavailable,
+            "NotReadableError" | "AbortError" => MicState::Busy,
+            "SecurityError" => MicState::Insecure,
+            _ => MicState::Unsupported,
+        };
+        Self::new(state, Some(e.message()))
+    }
+}
+
+#[wasm_bindgen]
+pub struct Microphone {
+    stream: Option<MediaStream>,
+    status: MicStatus,
+}
+
+#[wasm_bindgen]
+impl Microphone {
+    /// Where the microphone stands, without prompting.
+    pub async fn probe() -> MicStatus {
+        let devices = match media_devices() {
+            Ok(devices) => devices,
+            Err(status) => return status,
+        };
+        let mut status = MicStatus::new(permission().await, None);
+        describe(&devices, &mut status).await;
+        if status.inputs == 0 && status.state != MicState::Denied {
+            status.state = MicState::Unavailable;
+        }
+        status
+    }
+
+    /// Asks for the microphone, prompting if need be. Never fails: the
+    /// outcome is in `status`.
+    pub async fn request() -> Microphone {
+        let devices = match media_devices() {
+            Ok(devices) => devices,
+            Err(status) => {
+                return Microphone {
+                    stream: None,
+                    status,
+                }
+            }
+        };
+        let constraints = MediaStreamConstraints::new();
+        let audio = Object::new();
+        for key in ["echoCancellation", "noiseSuppression", "autoGainControl"] {
+            let _ = Reflect::set(&audio, &key.into(), &true.into());
+        }
+        constraints.set_audio(&audio);
+        let stream = match devices.get_user_media_with_constraints(&constraints) {
+            Ok(promise) => JsFuture::from(promise).await,
+            Err(e) => Err(e),
+        };
+        let (stream, mut status) = match stream {
+            Ok(stream) => (
+                Some(stream.unchecked_into::<MediaStream>()),
+                MicStatus::new(MicState::Granted, None),
+            ),
+            Err(e) => (None, MicStatus::from_error(&e)),
+        };
+        describe(&devices, &mut status).await;
+        if let Some(track) = stream.as_ref().and_then(first_track) {
+            capabilities(&track, &mut status);
+        }
+        Microphone { stream, status }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn status(&self) -> MicStatus {
+        self.status.clone()
+    }
+
+    /// The granted microphone, for a `Room` to send.
+    #[wasm_bindgen(getter)]
+    pub fn stream(&self) -> Option<MediaStream> {
+        self.stream.clone()
+    }
+
+    /// Starts capturing: each `frame_ms` of the microphone goes to
+    /// `on_packet` as a mono Opus packet (a Uint8Array) at about `bitrate`
+    /// bits a second. Rejects with the `MicStatus` if it can't.
+    pub async fn record(
+        self,
+        frame_ms: u32,
+        bitrate: u32,
+        on_packet: Function,
+    ) -> Result<Recorder, MicStatus> {
+        let Some(stream) = self
+            .stream
+            .filter(|_| self.status.state == MicState::Granted)
+        else {
+            return Err(self.status);
+        };
+        match Recorder::start(&stream, frame_ms, bitrate, on_packet).await {
+            Ok(recorder) => Ok(recorder),
+            Err(e) => {
+                stop(&stream);
+                Err(MicStatus::from_error(&e))
+            }
+        }
+    }
+
+    /// Lets go of the microphone without recording.
+    pub fn release(self) {
+        if let Some(stream) = &self.stream {
+            stop(stream);
+        }
+    }
+}
+
+/// A running capture. `stop` ends it and releases the microphone.
+#[wasm_bindgen]
+pub struct Recorder {
+    stream: MediaStream,
+    context: AudioContext,
+    source: MediaStreamAudioSourceNode,
+    node: AudioWorkletNode,
+    encoder: AudioEncoder,
+    _frames: Closure<dyn FnMut(MessageEvent)>,
+    _output: Closure<dyn FnMut(EncodedAudioChunk)>,
+    _error: Closure<dyn FnMut(JsValue)>,
+}
+
+impl Recorder {
+    async fn start(
+        stream: &MediaStream,
+        frame_ms: u32,
+        bitrate: u32,
+        on_packet: Function,
+    ) -> Result<Recorder, JsValue> {
+        let frame = frame_ms * SAMPLE_RATE / 1000;
+        let output =
+            Closure::<dyn FnMut(EncodedAudioChunk)>::new(move |chunk: EncodedAudioChunk| {
+                let packet = Uint8Array::new_with_length(chunk.byte_length());
+                if chunk.copy_to_with_buffer_source(&packet).is_ok() {
+                    let _ = on_packet.call1(&JsValue::NULL, &packet);
+                }
+            });
+        let error = Closure::<dyn FnMut(JsValue)>::new(|e: JsValue| {
+            web_sys::console::warn_2(&"voice-chat: encoder:".into(), &e);
+        });
+        let encoder = AudioEncoder::new(&AudioEncoderInit::new(
+            error.as_ref().unchecked_ref(),
+            output.as_ref().unchecked_ref(),
+        ))?;
+        let config = AudioEncoderConfig::new("opus", 1, SAMPLE_RATE);
+        config.set_bitrate(bitrate);
+        let opus = Object::new();
+        Reflect::set(&opus, &"frameDuration".into(), &(frame_ms * 1000).into())?;
+        Reflect::set(&config, &"opus".into(), &opus)?;
+        encoder.configure(&config)?;
+
+        let options = AudioContextOptions::new();
+        options.set_sample_rate(SAMPLE_RATE as f32);
+        options.set_latency_hint(&"interactive".into());
+        let context = AudioContext::new_with_context_options(&options)?;
+        add_module(&context, CAPTURE_WORKLET).await?;
+        let processor = Object::new();
+        Reflect::set(&processor, &"frame".into(), &frame.into())?;
+        let node_options = AudioWorkletNodeOptions::new();
+        node_options.set_number_of_inputs(1);
+        node_options.set_number_of_outputs(0);
+        node_options.set_channel_count(1);
+        node_options.set_channel_count_mode(ChannelCountMode::Explicit);
+        node_options.set_processor_options(Some(&processor));
+        let node = AudioWorkletNode::new_with_options(&context, "voice-capture", &node_options)?;
+
+        let sink = encoder.clone();
+        let mut clock = 0.0;
+        let frames = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+            let pcm: Float32Array = event.data().unchecked_into();
+            let init = AudioDataInit::new(
+                &pcm,
+                AudioSampleFormat::F32Planar,
+                1,
+                pcm.length(),
+                SAMPLE_RATE as f32,
+                0,
+            );
+            init.set_timestamp_f64(clock);
+            clock += pcm.length() as f64 * 1e6 / SAMPLE_RATE as f64;
+            if let Ok(data) = AudioData::new(&init) {
+                let _ = sink.encode(&data);
+                data.close();
+            }
+        });
+        node.port()?
+            .set_onmessage(Some(frames.as_ref().unchecked_ref()));
+        let source = context.create_media_stream_source(stream)?;
+        source.connect_with_audio_node(&node)?;
+        JsFuture::from(context.resume()?).await?;
+        Ok(Recorder {
+            stream: stream.clone(),
+            context,
+            source,
+            node,
+            encoder,
+            _frames: frames,
+            _output: output,
+            _error: error,
+        })
+    }
+}
+
+#[wasm_bindgen]
+impl Recorder {
+    pub async fn stop(self) -> Result<(), JsValue> {
+        self.source.disconnect()?;
+        self.node.port()?.set_onmessage(None);
+        stop(&self.stream);
+        let _ = JsFuture::from(self.encoder.flush()).await;
+        self.encoder.close()?;
+        JsFuture::from(self.context.close()?).await?;
+        Ok(())
+    }
+}
+
+/// The page's MediaDevices, or why there are none.
+fn media_devices() -> Result<MediaDevices, MicStatus> {
+    let Some(window) = web_sys::window() else {
+        return Err(MicStatus::new(
+            MicState::Unsupported,
+            Some("no window".into()),
+        ));
+    };
+    if !window.is_secure_context() {
+        return Err(MicStatus::new(MicState::Insecure, None));
+    }
+    let devices = Reflect::get(&window.navigator(), &"mediaDevices".into())
+        .ok()
+        .filter(|d| !d.is_undefined());
+    match devices {
+        Some(devices) => Ok(devices.unchecked_into()),
+        None => Err(MicStatus::new(
+            MicState::Unsupported,
+            Some("no navigator.mediaDevices".into()),
+        )),
+    }
+}
+
+/// The microphone permission as the Permissions API has it; `Prompt` where
+/// the browser doesn't answer for microphones.
+async fn permission() -> MicState {
+    let Some(permissions) = web_sys::window().and_then(|w| w.navigator().permissions().ok()) else {
+        return MicState::Prompt;
+    };
+    let query = Object::new();
+    let _ = Reflect::set(&query, &"name".into(), &"microphone".into());
+    let status = match permissions.query(&query) {
+        Ok(promise) => JsFuture::from(promise).await,
+        Err(e) => Err(e),
+    };
+    match status.map(|s| s.unchecked_into::<PermissionStatus>().state()) {
+        Ok(PermissionState::Granted) => MicState::Granted,
+        Ok(PermissionState::Denied) => MicState::Denied,
+        _ => MicState::Prompt,
+    }
+}
+
+/// Counts the inputs and lists the constraints the browser understands.
+async fn describe(devices: &MediaDevices, status: &mut MicStatus) {
+    if let Ok(promise) = devices.enumerate_devices() {
+        if let Ok(list) = JsFuture::from(promise).await {
+            status.inputs = Array::from(&list)
+                .iter()
+                .filter(|d| {
+                    d.unchecked_ref::<MediaDeviceInfo>().kind() == MediaDeviceKind::Audioinput
+                })
+                .count() as u32;
+        }
+    }
+    let supported: Object = devices.get_supported_constraints().into();
+    status.constraints = Object::keys(&supported)
+        .iter()
+        .filter(|key| Reflect::get(&supported, key).is_ok_and(|v| v.is_truthy()))
+        .filter_map(|key| key.as_string())
+        .collect();
+}
+
+/// The rates and channels the granted track offers. `getCapabilities` is
+/// missing in some browsers, so it is looked up rather than called blind.
+fn capabilities(track: &MediaStreamTrack, status: &mut MicStatus) {
+    let number = |of: &JsValue, key: &str| {
+        Reflect::get(of, &key.into())
+            .ok()
+            .and_then(|v| v.as_f64())
+            .map_or(0, |v| v as u32)
+    };
+    if let Some(caps) = call(track, "getCapabilities") {
+        if let Ok(rate) = Reflect::get(&caps, &"sampleRate".into()) {
+            status.min_rate = number(&rate, "min");
+            status.max_rate = number(&rate, "max");
+        }
+        if let Ok(channels) = Reflect::get(&caps, &"channelCount".into()) {
+            status.max_channels = number(&channels, "max");
+        }
+    }
+    if let Some(settings) = call(track, "getSettings") {
+        status.rate = number(&settings, "sampleRate");
+    }
+}
+
+/// `target.name()`, or `None` where the browser doesn't have it.
+fn call(target: &JsValue, name: &str) -> Option<JsValue> {
+    let method: Function = Reflect::get(target, &name.into()).ok()?.dyn_into().ok()?;
+    method.call0(target).ok()
+}
+
+fn first_track(stream: &MediaStream) -> Option<MediaStreamTrack> {
+    stream
+        .get_audio_tracks()
+        .iter()
+        .next()
+        .map(|t| t.unchecked_into())
+}
+
+fn stop(stream: &MediaStream) {
+    for track in stream.get_audio_tracks().iter() {
+        track.unchecked_into::<MediaStreamTrack>().stop();
+    }
+}
@@ -0,0 +1,165 @@
+// ─── Player ────────────────────────────────────────────────────────────────────
+// Decodes the call's Opus with WebCodecs and plays it through the playout
+// ring and worklet. The decoder's output goes straight into the ring, so the
+// only main‑thread work per packet is handing it to the decoder.
+//
+// The page must be cross‑origin isolated for SharedArrayBuffer:
+//
+//   Cross-Origin-Opener-Policy: same-origin
+//   Cross-Origin-Embedder-Policy: require-corp
+
+use crate::ring::PlayoutRing;
+use js_sys::{Array, Float32Array, Object, Reflect, Uint8Array};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    AudioContext, AudioContextOptions, AudioData, AudioDataCopyToOptions, AudioDecoder,
+    AudioDecoderConfig, AudioDecoderInit, AudioSampleFormat, AudioWorkletNode,
+    AudioWorkletNodeOptions, Blob, BlobPropertyBag, EncodedAudioChunk, EncodedAudioChunkInit,
+    EncodedAudioChunkType, Url,
+};
+
+pub(crate) const SAMPLE_RATE: u32 = 48_000;
+/// Two seconds of audio.
+const RING_CAPACITY: u32 = 2 * SAMPLE_RATE;
+const PLAYOUT_WORKLET: &str = include_str!("playout.js");
+
+/// What the player has been through since it started.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug)]
+pub struct PlayoutStats {
+    /// Audio queued in the ring.
+    pub queued_ms: f64,
+    /// Render quanta that ran dry.
+    pub underruns: u32,
+    /// Samples that arrived to a full ring.
+    pub dropped: u32,
+}
+
+#[wasm_bindgen]
+pub struct Player {
+    context: AudioContext,
+    node: AudioWorkletNode,
+    ring: PlayoutRing,
+    decoder: AudioDecoder,
+    _output: Closure<dyn FnMut(AudioData)>,
+    _error: Closure<dyn FnMut(JsValue)>,
+    /// Microseconds of audio handed to the decoder.
+    clock: f64,
+}
+
+#[wasm_bindgen]
+impl Player {
+    /// Opens a 48 kHz AudioContext playing from a new ring, starting once
+    /// `target_ms` of audio is queued. Call it from a user gesture, or the
+    /// browser keeps the context suspended.
+    pub async fn start(target_ms: u32) -> Result<Player, JsValue> {
+        if !isolated() {
+            return Err(JsValue::from_str(
+                "the page isn't cross-origin isolated (COOP/COEP headers), \
+                 so there's no SharedArrayBuffer for the playout ring",
+            ));
+        }
+        let options = AudioContextOptions::new();
+        options.set_sample_rate(SAMPLE_RATE as f32);
+        options.set_latency_hint(&"interactive".into());
+        let context = AudioContext::new_with_context_options(&options)?;
+        add_module(&context, PLAYOUT_WORKLET).await?;
+
+        let ring = PlayoutRing::new(RING_CAPACITY, target_ms * SAMPLE_RATE / 1000);
+        let processor = Object::new();
+        Reflect::set(&processor, &"buffer".into(), &ring.buffer())?;
+        let node_options = AudioWorkletNodeOptions::new();
+        node_options.set_number_of_inputs(0);
+        node_options.set_number_of_outputs(1);
+        node_options.set_output_channel_count(&Array::of1(&1.into()));
+        node_options.set_processor_options(Some(&processor));
+        let node = AudioWorkletNode::new_with_options(&context, "voice-playout", &node_options)?;
+        node.connect_with_audio_node(&context.destination())?;
+
+        let sink = ring.clone();
+        let output = Closure::<dyn FnMut(AudioData)>::new(move |data: AudioData| {
+            let pcm = Float32Array::new_with_length(data.number_of_frames());
+            let copy = AudioDataCopyToOptions::new(0);
+            copy.set_format(AudioSampleFormat::F32Planar);
+            let copied = data.copy_to_with_buffer_source(&pcm, &copy);
+            data.close();
+            if copied.is_ok() {
+                sink.push_array(&pcm);
+            }
+        });
+        let error = Closure::<dyn FnMut(JsValue)>::new(|e: JsValue| {
+            web_sys::console::warn_2(&"voice-chat: decoder:".into(), &e);
+        });
+        let decoder = AudioDecoder::new(&AudioDecoderInit::new(
+            error.as_ref().unchecked_ref(),
+            output.as_ref().unchecked_ref(),
+        ))?;
+        decoder.configure(&AudioDecoderConfig::new("opus", 1, SAMPLE_RATE))?;
+        JsFuture::from(context.resume()?).await?;
+        Ok(Player {
+            context,
+            node,
+            ring,
+            decoder,
+            _output: output,
+            _error: error,
+            clock: 0.0,
+        })
+    }
+
+    /// Decodes one Opus packet of `frame_ms` into the ring.
+    pub fn push_opus(&mut self, packet: &[u8], frame_ms: u32) -> Result<(), JsValue> {
+        let data = Uint8Array::from(packet);
+        let init = EncodedAudioChunkInit::new(&data, 0, EncodedAudioChunkType::Key);
+        init.set_timestamp_f64(self.clock);
+        init.set_duration(frame_ms * 1000);
+        self.decoder.decode(&EncodedAudioChunk::new(&init)?)?;
+        self.clock += frame_ms as f64 * 1000.0;
+        Ok(())
+    }
+
+    /// Or mono 48 kHz PCM, straight into the ring. Returns how much fit.
+    pub fn push_pcm(&self, pcm: &[f32]) -> u32 {
+        self.ring.push(pcm)
+    }
+
+    /// How much to queue before playing (and after running dry).
+    pub fn set_target_ms(&self, ms: u32) {
+        self.ring.set_target(ms * SAMPLE_RATE / 1000);
+    }
+
+    pub fn stats(&self) -> PlayoutStats {
+        PlayoutStats {
+            queued_ms: self.ring.queued() as f64 * 1000.0 / SAMPLE_RATE as f64,
+            underruns: self.ring.underruns(),
+            dropped: self.ring.dropped(),
+        }
+    }
+
+    /// Stops playing and releases the audio device.
+    pub async fn close(self) -> Result<(), JsValue> {
+        self.decoder.close()?;
+        self.node.disconnect()?;
+        JsFuture::from(self.context.close()?).await?;
+        Ok(())
+    }
+}
+
+/// Whether SharedArrayBuffer may be shared with a worklet.
+pub(crate) fn isolated() -> bool {
+    Reflect::get(&js_sys::global(), &"crossOriginIsolated".into())
+        .map(|v| v.is_truthy())
+        .unwrap_or(false)
+}
+
+/// Loads worklet `source` into `context` from a Blob URL.
+pub(crate) async fn add_module(context: &AudioContext, source: &str) -> Result<(), JsValue> {
+    let kind = BlobPropertyBag::new();
+    kind.set_type("text/javascript");
+    let blob = Blob::new_with_str_sequence_and_options(&Array::of1(&source.into()), &kind)?;
+    let url = Url::create_object_url_with_blob(&blob)?;
+    let loaded = JsFuture::from(context.audio_worklet()?.add_module(&url)?).await;
+    Url::revoke_object_url(&url)?;
+    loaded.map(|_| ())
+}
@@ -0,0 +1,57 @@
+// ─── Playout worklet ───────────────────────────────────────────────────────────
+// The render side of `PlayoutRing` (see ring.rs for the layout): plays the
+// samples the wasm side queued, on the audio rendering thread. Loaded from
+// a Blob by `Player.start`, so pages don't serve it themselves.
+
+const WRITE = 0;
+const READ = 1;
+const UNDERRUNS = 2;
+const TARGET = 3;
+const HEADER = 8;
+
+class VoicePlayout extends AudioWorkletProcessor {
+  constructor(options) {
+    super();
+    const buffer = options.processorOptions.buffer;
+    this.header = new Int32Array(buffer, 0, HEADER);
+    this.samples = new Float32Array(buffer, HEADER * 4);
+    this.playing = false;
+  }
+
+  process(inputs, outputs) {
+    const out = outputs[0];
+    const block = out[0].length;
+    const capacity = this.samples.length;
+    const write = Atomics.load(this.header, WRITE);
+    let read = Atomics.load(this.header, READ);
+    let queued = (write + capacity - read) % capacity;
+    const target = Atomics.load(this.header, TARGET);
+    if (!this.playing && queued < target) {
+      return true;
+    }
+    this.playing = true;
+    // Fallen behind (a stall, or the sender's clock running fast): skip to
+    // the newest `target` samples.
+    if (queued > 2 * target + block) {
+      read = (write + capacity - target) % capacity;
+      queued = target;
+    }
+    const n = Math.min(block, queued);
+    for (let i = 0; i < n; i++) {
+      out[0][i] = this.samples[(read + i) % capacity];
+    }
+    if (n < block) {
+      // Run dry: play silence and wait for the target again.
+      out[0].fill(0, n);
+      Atomics.add(this.header, UNDERRUNS, 1);
+      this.playing = false;
+    }
+    for (let c = 1; c < out.length; c++) {
+      out[c].set(out[0]);
+    }
+    Atomics.store(this.header, READ, (read + n) % capacity);
+    return true;
+  }
+}
+
+registerProcessor("voice-playout", VoicePlayout);
@@ -0,0 +1,136 @@
+// ─── Playout ring ──────────────────────────────────────────────────────────────
+// A wait‑free single‑producer, single‑consumer ring of mono 48 kHz samples in
+// a SharedArrayBuffer. The wasm side (network and decode) writes it; the
+// playout worklet (`playout.js`) reads it on the audio rendering thread, so
+// the main thread never schedules audio and a busy page can't make it skip.
+//
+// Layout, shared with `playout.js` – an Int32 header, then the samples:
+//
+//   [0] WRITE      next slot the producer fills   (producer only)
+//   [1] READ       next slot the worklet plays    (worklet only)
+//   [2] UNDERRUNS  render quanta that ran dry     (worklet only)
+//   [3] TARGET     samples to queue before playing (producer only)
+//   [4] DROPPED    samples that found the ring full (producer only)
+//
+// Each index has one writer and is published with an Atomics store after
+// the samples it covers, so neither side ever waits on the other. One slot
+// stays empty to tell a full ring from an empty one. The worklet starts
+// (and restarts after running dry) once TARGET samples are queued, and drops
+// the oldest when more than twice that piles up, which bounds the latency.
+
+use js_sys::{Atomics, Float32Array, Int32Array, SharedArrayBuffer};
+use wasm_bindgen::prelude::*;
+
+const WRITE: u32 = 0;
+const READ: u32 = 1;
+const UNDERRUNS: u32 = 2;
+const TARGET: u32 = 3;
+const DROPPED: u32 = 4;
+/// Header slots, keeping the samples 32‑byte aligned.
+const HEADER: u32 = 8;
+
+/// The producer's handle on the ring. Clones share it.
+#[wasm_bindgen]
+#[derive(Clone)]
+pub struct PlayoutRing {
+    buffer: SharedArrayBuffer,
+    header: Int32Array,
+    samples: Float32Array,
+    capacity: u32,
+}
+
+#[wasm_bindgen]
+impl PlayoutRing {
+    /// A ring holding up to `capacity` samples that starts playing once
+    /// `target` are queued.
+    #[wasm_bindgen(constructor)]
+    pub fn new(capacity: u32, target: u32) -> PlayoutRing {
+        let capacity = capacity.max(2);
+        let buffer = SharedArrayBuffer::new(4 * (HEADER + capacity));
+        let header = Int32Array::new_with_byte_offset_and_length(&buffer, 0, HEADER);
+        let samples = Float32Array::new_with_byte_offset_and_length(&buffer, 4 * HEADER, capacity);
+        let ring = PlayoutRing {
+            buffer,
+            header,
+            samples,
+            capacity,
+        };
+        ring.set_target(target);
+        ring
+    }
+
+    /// What to hand the worklet (`processorOptions.buffer`).
+    #[wasm_bindgen(getter)]
+    pub fn buffer(&self) -> SharedArrayBuffer {
+        self.buffer.clone()
+    }
+
+    /// Samples to queue before playing, at most half the ring.
+    pub fn set_target(&self, target: u32) {
+        let target = target.min(self.capacity / 2);
+        self.store(TARGET, target);
+    }
+
+    /// Writes what fits of `pcm`; the rest is counted as dropped.
+    pub fn push(&self, pcm: &[f32]) -> u32 {
+        self.write(pcm.len() as u32, |dst, from, len| {
+            let from = from as usize;
+            dst.copy_from(&pcm[from..from + len as usize]);
+        })
+    }
+
+    /// Samples waiting to be played.
+    #[wasm_bindgen(getter)]
+    pub fn queued(&self) -> u32 {
+        let write = self.load(WRITE);
+        let read = self.load(READ);
+        (write + self.capacity - read) % self.capacity
+    }
+
+    /// Render quanta (128 samples) the worklet had to fill with silence.
+    #[wasm_bindgen(getter)]
+    pub fn underruns(&self) -> u32 {
+        self.load(UNDERRUNS)
+    }
+
+    /// Samples that arrived to a full ring.
+    #[wasm_bindgen(getter)]
+    pub fn dropped(&self) -> u32 {
+        self.load(DROPPED)
+    }
+}
+
+impl PlayoutRing {
+    /// As `push`, from a JS array (a decoder's output, say).
+    pub(crate) fn push_array(&self, pcm: &Float32Array) -> u32 {
+        self.write(pcm.length(), |dst, from, len| {
+            dst.set(&pcm.subarray(from, from + len), 0);
+        })
+    }
+
+    /// Hands `copy` the free stretches of the ring for `len` samples (two
+    /// when they wrap), then publishes them. Returns how many fit.
+    fn write(&self, len: u32, copy: impl Fn(Float32Array, u32, u32)) -> u32 {
+        let write = self.load(WRITE);
+        let free = self.capacity - 1 - self.queued();
+        let n = len.min(free);
+        let first = n.min(self.capacity - write);
+        copy(self.samples.subarray(write, write + first), 0, first);
+        if n > first {
+            copy(self.samples.subarray(0, n - first), first, n - first);
+        }
+        self.store(WRITE, (write + n) % self.capacity);
+        if n < len {
+            let _ = Atomics::add(&self.header, DROPPED, (len - n) as i32);
+        }
+        n
+    }
+
+    fn load(&self, slot: u32) -> u32 {
+        Atomics::load(&self.header, slot).unwrap_or(0) as u32
+    }
+
+    fn store(&self, slot: u32, value: u32) {
+        let _ = Atomics::store(&self.header, slot, value as i32);
+    }
+}
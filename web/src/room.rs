@@ -0,0 +1,723 @@
+// ─── Room ──────────────────────────────────────────────────────────────────────
+// Joins the same signaling rooms as native clients, with the same messages
+// (see `signaling` in the native crate), over the room's WebSocket where the
+// server has one (see Socket below). Otherwise over HTTP as native clients
+// do: POST our join to `<server>/join/<room>`, poll it until the server pairs
+// us, publish to `<server>/presence/<room>` and follow the roster streamed
+// from `<server>/roster/<room>`, all with the room token as a bearer token.
+//
+// A page can't send to the UDP candidates in a native join, so ours says
+// `"webrtc": true` and leaves them empty, and the call itself goes through
+// the peer's WebRTC endpoint: we POST an SDP offer to the `webrtc_url` in its
+// join and get the answer back (see `browser` in the native crate). The
+// peer must run with `--webrtc-url`; rooms with a passphrase are out of
+// reach, since a browser can't prove it.
+//
+// The signaling server and the endpoint must allow the page's origin (CORS).
+
+use js_sys::{Array, Function, Map, Object, Promise, Reflect, Uint8Array, JSON};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use web_sys::{
+    AbortController, AbortSignal, MediaStream, MediaStreamTrack, MessageEvent, ReadableStream,
+    ReadableStreamDefaultReader, RequestInit, Response, RtcConfiguration, RtcIceGatheringState,
+    RtcPeerConnection, RtcRtpReceiver, RtcRtpTransceiverDirection, RtcRtpTransceiverInit,
+    RtcSdpType, RtcSessionDescriptionInit, Url, WebSocket,
+};
+
+const POLL_INTERVAL_MS: i32 = 1000;
+const ROSTER_RETRY_MAX_MS: i32 = 30_000;
+/// The native client's default (`browser::DEFAULT_ICE_SERVERS`).
+const DEFAULT_ICE_SERVER: &str = "stun:stun.l.google.com:19302";
+
+/// A room to join; set what it needs, then `join`.
+#[wasm_bindgen(getter_with_clone)]
+pub struct Room {
+    join_url: String,
+    presence_url: String,
+    roster_url: String,
+    socket_url: String,
+    /// Given a ws(s):// server, so there's no HTTP to fall back to.
+    socket_only: bool,
+    room: String,
+    /// The room token, sent to the signaling server and the peer's
+    /// endpoint.
+    pub token: Option<String>,
+    /// Shown to the other members of the room.
+    pub nickname: Option<String>,
+    /// STUN or TURN servers for ICE (defaults to a public STUN server).
+    pub ice_servers: Vec<String>,
+    /// Sent to the peer; without it we only listen.
+    pub microphone: Option<MediaStream>,
+    /// Called with the other members (`{ id, nickname, metadata, muted }`)
+    /// whenever the roster changes, on servers that stream it.
+    pub on_roster: Option<Function>,
+}
+
+#[wasm_bindgen]
+impl Room {
+    #[wasm_bindgen(constructor)]
+    pub fn new(server: &str, room: &str) -> Result<Room, JsValue> {
+        let base =
+            Url::new(server).map_err(|_| JsValue::from_str("invalid signaling server URL"))?;
+        let (http, socket) = match base.protocol().as_str() {
+            "https:" | "wss:" => ("https:", "wss:"),
+            "http:" | "ws:" => ("http:", "ws:"),
+            other => {
+                return Err(JsValue::from_str(&format!(
+                    "browsers only join rooms over http(s) or ws(s), not {other}"
+                )))
+            }
+        };
+        let socket_only = base.protocol().starts_with("ws");
+        let socket_base = Url::new(&base.href())?;
+        socket_base.set_protocol(socket);
+        base.set_protocol(http);
+        Ok(Room {
+            join_url: room_url(&base, "join", room)?,
+            presence_url: room_url(&base, "presence", room)?,
+            roster_url: room_url(&base, "roster", room)?,
+            socket_url: room_url(&socket_base, "ws", room)?,
+            socket_only,
+            room: room.to_owned(),
+            token: None,
+            nickname: None,
+            ice_servers: Vec::new(),
+            microphone: None,
+            on_roster: None,
+        })
+    }
+
+    /// Joins, waits for the server to pair us and calls the peer it paired
+    /// us with.
+    pub async fn join(self) -> Result<Call, JsValue> {
+        if self.token.is_some() && !secure(&self.join_url) {
+            return Err(JsValue::from_str(
+                "refusing to send the room token over plain HTTP; use https:// or wss://",
+            ));
+        }
+        let http = Http {
+            token: self.token.clone(),
+            signal: None,
+        };
+        let member_id = format!(
+            "{:08x}{:08x}",
+            (js_sys::Math::random() * 4294967296.0) as u32,
+            (js_sys::Math::random() * 4294967296.0) as u32
+        );
+        let join = Object::new();
+        for (key, value) in [
+            ("reflexive_addr", JsValue::from_str("")),
+            ("lan_addr", "".into()),
+            ("pub_key", "".into()),
+            ("member_id", member_id.as_str().into()),
+            ("webrtc", true.into()),
+        ] {
+            Reflect::set(&join, &key.into(), &value)?;
+        }
+        if let Some(nickname) = &self.nickname {
+            Reflect::set(&join, &"nickname".into(), &nickname.into())?;
+        }
+
+        let (peer, socket) = match Socket::open(&self.socket_url).await {
+            Ok(ws) => {
+                let (socket, peer) = self.join_socket(ws, &join, &member_id).await?;
+                (peer, Some(socket))
+            }
+            Err(_) if !self.socket_only => (self.join_http(&http, &join).await?, None),
+            Err(_) => {
+                return Err(JsValue::from_str(&format!(
+                    "couldn't open {}",
+                    self.socket_url
+                )))
+            }
+        };
+        let Some(endpoint) = Reflect::get(&peer, &"webrtc_url".into())?.as_string() else {
+            return Err(JsValue::from_str(&format!(
+                "the peer in room {} doesn't take browsers (it needs --webrtc-url)",
+                self.room
+            )));
+        };
+        if self.token.is_some() && !secure(&endpoint) {
+            return Err(JsValue::from_str(&format!(
+                "refusing to send the room token to {endpoint} over plain HTTP"
+            )));
+        }
+
+        let connection = self.connect(&http, &endpoint).await?;
+        let remote = MediaStream::new()?;
+        for receiver in connection.get_receivers().iter() {
+            remote.add_track(&receiver.unchecked_into::<RtcRtpReceiver>().track());
+        }
+
+        let signaling = match socket {
+            Some(socket) => Signaling::Socket(socket),
+            None => {
+                let abort = AbortController::new()?;
+                let http = Rc::new(Http {
+                    token: self.token,
+                    signal: Some(abort.signal()),
+                });
+                let closed = Rc::new(Cell::new(false));
+                if let Some(on_roster) = self.on_roster {
+                    spawn_local(watch_roster(
+                        http.clone(),
+                        self.roster_url,
+                        member_id.clone(),
+                        on_roster,
+                        closed.clone(),
+                    ));
+                }
+                Signaling::Http {
+                    http,
+                    abort,
+                    closed,
+                    presence_url: self.presence_url,
+                }
+            }
+        };
+        let call = Call {
+            connection,
+            remote,
+            microphone: self.microphone,
+            signaling,
+            member_id,
+            nickname: self.nickname,
+        };
+        call.publish(false);
+        Ok(call)
+    }
+}
+
+impl Room {
+    /// POSTs our join and polls until the server pairs us.
+    async fn join_http(&self, http: &Http, join: &Object) -> Result<JsValue, JsValue> {
+        let body = JSON::stringify(join)?;
+        self.check(
+            http.send(
+                "POST",
+                &self.join_url,
+                Some(("application/json", &body.into())),
+            )
+            .await?,
+        )?;
+        loop {
+            let resp = self.check(http.send("GET", &self.join_url, None).await?)?;
+            let peer = JsFuture::from(resp.json()?).await?;
+            if peer.is_object() {
+                return Ok(peer);
+            }
+            sleep(POLL_INTERVAL_MS).await;
+        }
+    }
+
+    /// Sends our join down the room's socket and waits for the server to
+    /// pair us; roster events from then on go to `on_roster`.
+    async fn join_socket(
+        &self,
+        ws: WebSocket,
+        join: &Object,
+        me: &str,
+    ) -> Result<(Socket, JsValue), JsValue> {
+        let settle: Rc<RefCell<Option<(Function, Function)>>> = Rc::default();
+        let paired = Promise::new(&mut |resolve, reject| {
+            *settle.borrow_mut() = Some((resolve, reject));
+        });
+
+        let (pending, room, me) = (settle.clone(), self.room.clone(), me.to_owned());
+        let on_roster = self.on_roster.clone();
+        let members = Map::new();
+        let on_message = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+            let Some(message) = event.data().as_string() else {
+                return;
+            };
+            let Ok(message) = JSON::parse(&message) else {
+                return;
+            };
+            let field = |name: &str| Reflect::get(&message, &name.into()).unwrap_or_default();
+            match field("type").as_string().unwrap_or_default().as_str() {
+                "peer" => {
+                    if let Some((resolve, _)) = pending.borrow_mut().take() {
+                        let _ = resolve.call1(&JsValue::NULL, &field("peer"));
+                    }
+                }
+                "error" => {
+                    let status = field("status").as_f64().unwrap_or_default() as u16;
+                    let error = match field("message").as_string() {
+                        Some(message) if !matches!(status, 401 | 403) => {
+                            JsValue::from_str(&format!("signaling server: {message}"))
+                        }
+                        _ => refusal(&room, status),
+                    };
+                    match pending.borrow_mut().take() {
+                        Some((_, reject)) => drop(reject.call1(&JsValue::NULL, &error)),
+                        None => web_sys::console::warn_2(&"voice-chat: room:".into(), &error),
+                    }
+                }
+                event => {
+                    let Some(on_roster) = &on_roster else {
+                        return;
+                    };
+                    if apply(&members, event, &field("data"), &me) {
+                        let _ = on_roster.call1(&JsValue::NULL, &Array::from(&members.values()));
+                    }
+                }
+            }
+        });
+        let pending = settle.clone();
+        let on_close = Closure::<dyn FnMut(JsValue)>::new(move |_| {
+            if let Some((_, reject)) = pending.borrow_mut().take() {
+                let _ = reject.call1(
+                    &JsValue::NULL,
+                    &"the signaling server closed the room's socket".into(),
+                );
+            }
+        });
+        ws.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+        ws.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+        let socket = Socket {
+            ws,
+            _on_message: on_message,
+            _on_close: on_close,
+        };
+
+        let hello = Object::new();
+        Reflect::set(&hello, &"type".into(), &"join".into())?;
+        if let Some(token) = &self.token {
+            Reflect::set(&hello, &"token".into(), &token.into())?;
+        }
+        Reflect::set(&hello, &"join".into(), join)?;
+        socket.send(&hello)?;
+        let peer = JsFuture::from(paired).await?;
+        Ok((socket, peer))
+    }
+
+    /// Offers the call to the peer's WebRTC endpoint and applies its
+    /// answer. The endpoint doesn't trickle, so neither do we: the offer
+    /// goes once every candidate is in it.
+    async fn connect(&self, http: &Http, endpoint: &str) -> Result<RtcPeerConnection, JsValue> {
+        let servers = Array::new();
+        let urls = match self.ice_servers.is_empty() {
+            true => vec![DEFAULT_ICE_SERVER.to_owned()],
+            false => self.ice_servers.clone(),
+        };
+        for url in urls {
+            let server = Object::new();
+            Reflect::set(&server, &"urls".into(), &url.into())?;
+            servers.push(&server);
+        }
+        let config = RtcConfiguration::new();
+        config.set_ice_servers(&servers);
+        let connection = RtcPeerConnection::new_with_configuration(&config)?;
+
+        let track = self.microphone.as_ref().and_then(|stream| {
+            stream
+                .get_audio_tracks()
+                .iter()
+                .next()
+                .map(|t| t.unchecked_into::<MediaStreamTrack>())
+        });
+        let init = RtcRtpTransceiverInit::new();
+        match track {
+            Some(track) => {
+                init.set_direction(RtcRtpTransceiverDirection::Sendrecv);
+                connection.add_transceiver_with_media_stream_track_and_init(&track, &init);
+            }
+            None => {
+                init.set_direction(RtcRtpTransceiverDirection::Recvonly);
+                connection.add_transceiver_with_str_and_init("audio", &init);
+            }
+        }
+
+        let offer = JsFuture::from(connection.create_offer()).await?;
+        JsFuture::from(connection.set_local_description(offer.unchecked_ref())).await?;
+        while connection.ice_gathering_state() != RtcIceGatheringState::Complete {
+            let changed = Promise::new(&mut |resolve, _| {
+                connection.set_onicegatheringstatechange(Some(&resolve));
+            });
+            JsFuture::from(changed).await?;
+        }
+        connection.set_onicegatheringstatechange(None);
+        let offer = connection
+            .local_description()
+            .ok_or_else(|| JsValue::from_str("no local description"))?
+            .sdp();
+
+        let resp = http
+            .send("POST", endpoint, Some(("application/sdp", &offer.into())))
+            .await;
+        let answer = match resp {
+            Ok(resp) if resp.status() == 201 => JsFuture::from(resp.text()?).await?,
+            Ok(resp) => {
+                connection.close();
+                return Err(JsValue::from_str(&match resp.status() {
+                    401 => "the peer's WebRTC endpoint wants a different token".to_owned(),
+                    503 => "the peer takes no more browsers".to_owned(),
+                    status => format!("the peer's WebRTC endpoint answered {status}"),
+                }));
+            }
+            Err(e) => {
+                connection.close();
+                return Err(e);
+            }
+        };
+        let description = RtcSessionDescriptionInit::new(RtcSdpType::Answer);
+        description.set_sdp(&answer.as_string().unwrap_or_default());
+        JsFuture::from(connection.set_remote_description(&description)).await?;
+        Ok(connection)
+    }
+
+    fn check(&self, resp: Response) -> Result<Response, JsValue> {
+        match resp.ok() {
+            true => Ok(resp),
+            false => Err(refusal(&self.room, resp.status())),
+        }
+    }
+}
+
+fn refusal(room: &str, status: u16) -> JsValue {
+    JsValue::from_str(&match status {
+        401 | 403 => format!("signaling server rejected our credentials for room {room}"),
+        status => format!("signaling server answered {status}"),
+    })
+}
+
+/// A call with the peer the room paired us with. `leave` ends it.
+#[wasm_bindgen]
+pub struct Call {
+    connection: RtcPeerConnection,
+    remote: MediaStream,
+    microphone: Option<MediaStream>,
+    signaling: Signaling,
+    member_id: String,
+    nickname: Option<String>,
+}
+
+/// How the call stays in the room.
+enum Signaling {
+    Http {
+        http: Rc<Http>,
+        abort: AbortController,
+        /// Stops the roster stream.
+        closed: Rc<Cell<bool>>,
+        presence_url: String,
+    },
+    Socket(Socket),
+}
+
+#[wasm_bindgen]
+impl Call {
+    /// What the peer sends; play it with an `<audio>` element's
+    /// `srcObject`.
+    #[wasm_bindgen(getter)]
+    pub fn remote(&self) -> MediaStream {
+        self.remote.clone()
+    }
+
+    /// Stops or resumes sending the microphone, and tells the room.
+    pub fn set_muted(&self, muted: bool) {
+        if let Some(stream) = &self.microphone {
+            for track in stream.get_audio_tracks().iter() {
+                track
+                    .unchecked_into::<MediaStreamTrack>()
+                    .set_enabled(!muted);
+            }
+        }
+        self.publish(muted);
+    }
+
+    /// Hangs up. The microphone stays the caller's to release.
+    pub fn leave(self) {
+        if let Signaling::Http { abort, closed, .. } = &self.signaling {
+            closed.set(true);
+            abort.abort();
+        }
+        self.connection.close();
+    }
+}
+
+impl Call {
+    /// Our nickname and mute state for the roster; HTTP servers without
+    /// presence answer 404, which is fine.
+    fn publish(&self, muted: bool) {
+        let presence = Object::new();
+        let _ = Reflect::set(&presence, &"id".into(), &self.member_id.as_str().into());
+        let nickname = self
+            .nickname
+            .as_deref()
+            .map_or(JsValue::NULL, JsValue::from);
+        let _ = Reflect::set(&presence, &"nickname".into(), &nickname);
+        let _ = Reflect::set(&presence, &"muted".into(), &muted.into());
+        let (http, url) = match &self.signaling {
+            Signaling::Http {
+                http, presence_url, ..
+            } => (http.clone(), presence_url.clone()),
+            Signaling::Socket(socket) => {
+                let message = Object::new();
+                let _ = Reflect::set(&message, &"type".into(), &"presence".into());
+                let _ = Reflect::set(&message, &"presence".into(), &presence);
+                if let Err(e) = socket.send(&message) {
+                    web_sys::console::debug_2(&"voice-chat: presence:".into(), &e);
+                }
+                return;
+            }
+        };
+        let Ok(body) = JSON::stringify(&presence) else {
+            return;
+        };
+        spawn_local(async move {
+            let body = JsValue::from(body);
+            if let Err(e) = http
+                .send("POST", &url, Some(("application/json", &body)))
+                .await
+            {
+                web_sys::console::debug_2(&"voice-chat: presence:".into(), &e);
+            }
+        });
+    }
+}
+
+struct Http {
+    token: Option<String>,
+    /// Aborts whatever is in flight when the call ends.
+    signal: Option<AbortSignal>,
+}
+
+impl Http {
+    async fn send(
+        &self,
+        method: &str,
+        url: &str,
+        body: Option<(&str, &JsValue)>,
+    ) -> Result<Response, JsValue> {
+        self.request(method, url, body, None).await
+    }
+
+    async fn request(
+        &self,
+        method: &str,
+        url: &str,
+        body: Option<(&str, &JsValue)>,
+        accept: Option<&str>,
+    ) -> Result<Response, JsValue> {
+        let window = web_sys::window().ok_or_else(|| JsValue::from_str("no window"))?;
+        let headers = Object::new();
+        if let Some(token) = &self.token {
+            Reflect::set(
+                &headers,
+                &"Authorization".into(),
+                &format!("Bearer {token}").into(),
+            )?;
+        }
+        let init = RequestInit::new();
+        init.set_method(method);
+        if let Some((kind, body)) = body {
+            Reflect::set(&headers, &"Content-Type".into(), &kind.into())?;
+            init.set_body(body);
+        }
+        if let Some(accept) = accept {
+            Reflect::set(&headers, &"Accept".into(), &accept.into())?;
+        }
+        init.set_headers(&headers);
+        init.set_signal(self.signal.as_ref());
+        let resp = JsFuture::from(window.fetch_with_str_and_init(url, &init)).await?;
+        Ok(resp.unchecked_into())
+    }
+}
+
+// ─── Socket ────────────────────────────────────────────────────────────────────
+// Servers that support it also serve each room as a WebSocket at
+// `<server>/ws/<room>` (wss:// for an https:// server), carrying the HTTP
+// protocol's JSON in text messages tagged by `type`:
+//
+//   → {"type": "join", "token": …, "join": {…}}    first, instead of the POST
+//   ← {"type": "peer", "peer": {…}}                once the server pairs us
+//   → {"type": "presence", "presence": {…}}        instead of the presence POST
+//   ← {"type": "roster" | "join" | "update" | "leave", "data": …}
+//   ← {"type": "error", "status": 401, "message": …}, then the server closes
+//
+// Pages can't set headers on a WebSocket, so the token rides in the join
+// rather than in the URL, where it would end up in access logs. A server
+// that won't upgrade gets the HTTP protocol instead, unless it was given as
+// ws(s)://. The server drops us from the roster when the socket closes.
+
+struct Socket {
+    ws: WebSocket,
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+    _on_close: Closure<dyn FnMut(JsValue)>,
+}
+
+impl Socket {
+    /// Fails if the server doesn't upgrade.
+    async fn open(url: &str) -> Result<WebSocket, JsValue> {
+        let ws = WebSocket::new(url)?;
+        let opened = Promise::new(&mut |resolve, reject| {
+            ws.set_onopen(Some(&resolve));
+            ws.set_onerror(Some(&reject));
+        });
+        let opened = JsFuture::from(opened).await;
+        ws.set_onopen(None);
+        ws.set_onerror(None);
+        opened.map(|_| ws)
+    }
+
+    fn send(&self, message: &Object) -> Result<(), JsValue> {
+        self.ws
+            .send_with_str(&String::from(JSON::stringify(message)?))
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        self.ws.set_onmessage(None);
+        self.ws.set_onclose(None);
+        let _ = self.ws.close();
+    }
+}
+
+// ─── Roster ────────────────────────────────────────────────────────────────────
+// The same server‑sent events the native client reads: `roster` with every
+// member, `join` / `update` / `leave` with one. EventSource can't send the
+// token, so the stream is read from `fetch`.
+
+enum Stream {
+    /// The server closed it; reconnect.
+    Ended,
+    Unsupported,
+}
+
+async fn watch_roster(
+    http: Rc<Http>,
+    url: String,
+    me: String,
+    on_roster: Function,
+    closed: Rc<Cell<bool>>,
+) {
+    let members = Map::new();
+    let mut retry = 1000;
+    while !closed.get() {
+        match stream_roster(&http, &url, &me, &members, &on_roster).await {
+            Ok(Stream::Unsupported) => return,
+            Ok(Stream::Ended) => retry = 1000,
+            Err(_) if closed.get() => return,
+            Err(e) => web_sys::console::warn_2(&"voice-chat: roster stream:".into(), &e),
+        }
+        sleep(retry).await;
+        retry = (retry * 2).min(ROSTER_RETRY_MAX_MS);
+    }
+}
+
+async fn stream_roster(
+    http: &Http,
+    url: &str,
+    me: &str,
+    members: &Map,
+    on_roster: &Function,
+) -> Result<Stream, JsValue> {
+    let resp = http
+        .request("GET", url, None, Some("text/event-stream"))
+        .await?;
+    if resp.status() == 404 {
+        return Ok(Stream::Unsupported);
+    }
+    if !resp.ok() {
+        return Err(JsValue::from_str(&format!(
+            "signaling server answered {}",
+            resp.status()
+        )));
+    }
+    let body: ReadableStream = resp.body().ok_or_else(|| JsValue::from_str("no body"))?;
+    let reader = ReadableStreamDefaultReader::new(&body)?;
+    let mut buf = Vec::new();
+    let (mut name, mut data) = (String::new(), String::new());
+    loop {
+        let chunk = JsFuture::from(reader.read()).await?;
+        if Reflect::get(&chunk, &"done".into())?.is_truthy() {
+            return Ok(Stream::Ended);
+        }
+        let value: Uint8Array = Reflect::get(&chunk, &"value".into())?.unchecked_into();
+        buf.extend_from_slice(&value.to_vec());
+        while let Some(end) = buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = buf.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line.is_empty() {
+                let (name, data) = (std::mem::take(&mut name), std::mem::take(&mut data));
+                if JSON::parse(&data).is_ok_and(|data| apply(members, &name, &data, me)) {
+                    let _ = on_roster.call1(&JsValue::NULL, &Array::from(&members.values()));
+                }
+            } else if let Some(value) = line.strip_prefix("event:") {
+                name = value.trim_start().to_owned();
+            } else if let Some(value) = line.strip_prefix("data:") {
+                if !data.is_empty() {
+                    data.push('\n');
+                }
+                data.push_str(value.strip_prefix(' ').unwrap_or(value));
+            }
+        }
+    }
+}
+
+/// Applies one event to `members`; false for events that aren't about
+/// the roster.
+fn apply(members: &Map, event: &str, parsed: &JsValue, me: &str) -> bool {
+    let id = |member: &JsValue| Reflect::get(member, &"id".into()).unwrap_or_default();
+    match event {
+        "roster" => {
+            members.clear();
+            for member in Array::from(parsed).iter() {
+                members.set(&id(&member), &member);
+            }
+        }
+        "join" | "update" => {
+            members.set(&id(parsed), parsed);
+        }
+        "leave" => {
+            members.delete(&id(parsed));
+        }
+        _ => return false,
+    }
+    members.delete(&me.into());
+    true
+}
+
+/// `<base>/<kind>/<room>`, with the room one percent‑encoded path segment
+/// whatever characters it holds.
+fn room_url(base: &Url, kind: &str, room: &str) -> Result<String, JsValue> {
+    if matches!(room, "" | "." | "..") {
+        return Err(JsValue::from_str(&format!("invalid room name {room:?}")));
+    }
+    let url = Url::new(&base.href())?;
+    url.set_search("");
+    url.set_hash("");
+    let path = url.pathname();
+    let room: String = js_sys::encode_uri_component(room).into();
+    url.set_pathname(&format!("{}/{kind}/{room}", path.trim_end_matches('/')));
+    Ok(url.href())
+}
+
+/// HTTPS or WSS, or plain HTTP to this machine.
+fn secure(url: &str) -> bool {
+    let Ok(url) = Url::new(url) else {
+        return false;
+    };
+    let host = url.hostname();
+    matches!(url.protocol().as_str(), "https:" | "wss:")
+        || host == "localhost"
+        || host == "[::1]"
+        || host.starts_with("127.")
+}
+
+async fn sleep(ms: i32) {
+    let done = Promise::new(&mut |resolve, _| {
+        let set = web_sys::window().map(|window| {
+            window.set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, ms)
+        });
+        if !matches!(set, Some(Ok(_))) {
+            let _ = resolve.call0(&JsValue::NULL);
+        }
+    });
+    let _ = JsFuture::from(done).await;
+}